@@ -293,33 +293,12 @@ impl Drop for Connection {
     }
 }
 
-#[allow(clippy::arc_with_non_send_sync)]
 #[pyfunction]
 pub fn connect(path: &str) -> Result<Connection> {
-    #[inline(always)]
-    fn open_or(
-        io: Arc<dyn limbo_core::IO>,
-        path: &str,
-    ) -> std::result::Result<Arc<limbo_core::Database>, PyErr> {
-        limbo_core::Database::open_file(io, path, false).map_err(|e| {
-            PyErr::new::<DatabaseError, _>(format!("Failed to open database: {:?}", e))
-        })
-    }
-
-    match path {
-        ":memory:" => {
-            let io: Arc<dyn limbo_core::IO> = Arc::new(limbo_core::MemoryIO::new());
-            let db = open_or(io.clone(), path)?;
-            let conn: Rc<limbo_core::Connection> = db.connect().unwrap();
-            Ok(Connection { conn, io })
-        }
-        path => {
-            let io: Arc<dyn limbo_core::IO> = Arc::new(limbo_core::PlatformIO::new()?);
-            let db = open_or(io.clone(), path)?;
-            let conn: Rc<limbo_core::Connection> = db.connect().unwrap();
-            Ok(Connection { conn, io })
-        }
-    }
+    let (io, db) = limbo_core::Database::open_uri(path, false)
+        .map_err(|e| PyErr::new::<DatabaseError, _>(format!("Failed to open database: {:?}", e)))?;
+    let conn: Rc<limbo_core::Connection> = db.connect().unwrap();
+    Ok(Connection { conn, io })
 }
 
 fn row_to_py(py: Python, row: &limbo_core::Row) -> Result<PyObject> {
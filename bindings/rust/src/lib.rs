@@ -1,6 +1,7 @@
 pub mod params;
 pub mod value;
 
+pub use limbo_core::CArray;
 pub use value::Value;
 
 pub use params::params_from_iter;
@@ -41,18 +42,8 @@ impl Builder {
 
     #[allow(unused_variables, clippy::arc_with_non_send_sync)]
     pub async fn build(self) -> Result<Database> {
-        match self.path.as_str() {
-            ":memory:" => {
-                let io: Arc<dyn limbo_core::IO> = Arc::new(limbo_core::MemoryIO::new());
-                let db = limbo_core::Database::open_file(io, self.path.as_str(), false)?;
-                Ok(Database { inner: db })
-            }
-            path => {
-                let io: Arc<dyn limbo_core::IO> = Arc::new(limbo_core::PlatformIO::new()?);
-                let db = limbo_core::Database::open_file(io, path, false)?;
-                Ok(Database { inner: db })
-            }
-        }
+        let (_io, db) = limbo_core::Database::open_uri(self.path.as_str(), false)?;
+        Ok(Database { inner: db })
     }
 }
 
@@ -214,6 +214,14 @@ impl From<Vec<u8>> for Value {
     }
 }
 
+/// An array for `carray()`, e.g. `params![CArray::from_integers(&ids)]` for
+/// `WHERE id IN carray(?1)`.
+impl From<limbo_core::CArray> for Value {
+    fn from(array: limbo_core::CArray) -> Value {
+        Value::Blob(array.into_blob())
+    }
+}
+
 impl From<bool> for Value {
     fn from(value: bool) -> Value {
         Value::Integer(value as i64)
@@ -263,6 +263,11 @@ impl limbo_core::File for File {
     fn size(&self) -> Result<u64> {
         Ok(self.vfs.size(self.fd))
     }
+
+    fn truncate(&self, len: usize) -> Result<()> {
+        self.vfs.truncate(self.fd, len);
+        Ok(())
+    }
 }
 
 pub struct PlatformIO {
@@ -367,8 +372,8 @@ impl limbo_core::DatabaseStorage for DatabaseFile {
         Ok(())
     }
 
-    fn sync(&self, _c: limbo_core::Completion) -> Result<()> {
-        todo!()
+    fn sync(&self, c: limbo_core::Completion) -> Result<()> {
+        self.file.sync(c)
     }
 }
 
@@ -399,6 +404,9 @@ extern "C" {
 
     #[wasm_bindgen(method)]
     fn sync(this: &VFS, fd: i32);
+
+    #[wasm_bindgen(method)]
+    fn truncate(this: &VFS, fd: i32, len: usize);
 }
 
 #[cfg(feature = "nodejs")]
@@ -425,6 +433,9 @@ extern "C" {
 
     #[wasm_bindgen(method)]
     fn sync(this: &VFS, fd: i32);
+
+    #[wasm_bindgen(method)]
+    fn truncate(this: &VFS, fd: i32, len: usize);
 }
 
 #[wasm_bindgen(start)]
@@ -28,6 +28,10 @@ class VFS {
   sync(fd) {
     fs.fsyncSync(fd);
   }
+
+  truncate(fd, len) {
+    fs.ftruncateSync(fd, len);
+  }
 }
 
 module.exports = { VFS };
@@ -0,0 +1,223 @@
+// IndexedDB fallback for browsers or pages where OPFS sync access handles are
+// not available. IndexedDB only has an async API, so every file is loaded into
+// memory when the VFS starts and `sync` writes the blocks changed since the
+// previous sync in a single transaction. This keeps the synchronous VFS api in
+// `lib.rs` unchanged, at the cost of holding the databases in memory, so it is
+// meant for small databases.
+const DB_NAME = "limbo";
+const BLOCK_SIZE = 4096;
+
+class VFS {
+  constructor(dbName = DB_NAME) {
+    this.files = new Map();
+    this.handles = new Map();
+    this.nextFd = 1;
+    // Syncs whose transaction is still running, or failed and wasn't reported
+    // by `flush` yet.
+    this.pending = new Set();
+
+    this.isReady = false;
+    this.ready = openDatabase(dbName).then(async (db) => {
+      this.db = db;
+      await this.load();
+      this.isReady = true;
+    });
+  }
+
+  async load() {
+    const tx = this.db.transaction(["files", "blocks"], "readonly");
+    const [files, blocks] = await Promise.all([
+      request(tx.objectStore("files").getAll()),
+      request(tx.objectStore("blocks").getAll()),
+    ]);
+    for (const { path, size } of files) {
+      this.files.set(path, newFile(size));
+    }
+    for (const { path, index, data } of blocks) {
+      this.files.get(path)?.blocks.set(index, new Uint8Array(data));
+    }
+    log("idb loaded files: ", [...this.files.keys()]);
+  }
+
+  open(path) {
+    if (!this.files.has(path)) {
+      const file = newFile(0);
+      file.sizeChanged = true;
+      this.files.set(path, file);
+    }
+    const fd = this.nextFd++;
+    this.handles.set(fd, path);
+    return fd;
+  }
+
+  close(fd) {
+    this.sync(fd);
+    return this.handles.delete(fd);
+  }
+
+  pread(fd, buffer, offset) {
+    const file = this.file(fd);
+    const end = Math.min(offset + buffer.byteLength, file.size);
+    let pos = offset;
+    while (pos < end) {
+      const index = Math.floor(pos / BLOCK_SIZE);
+      const start = pos % BLOCK_SIZE;
+      const len = Math.min(BLOCK_SIZE - start, end - pos);
+      const block = file.blocks.get(index);
+      if (block) {
+        buffer.set(block.subarray(start, start + len), pos - offset);
+      } else {
+        // Never written, e.g. skipped over by a write past the end of the file.
+        buffer.fill(0, pos - offset, pos - offset + len);
+      }
+      pos += len;
+    }
+    return Math.max(end - offset, 0);
+  }
+
+  pwrite(fd, buffer, offset) {
+    const file = this.file(fd);
+    const end = offset + buffer.byteLength;
+    let pos = offset;
+    while (pos < end) {
+      const index = Math.floor(pos / BLOCK_SIZE);
+      const start = pos % BLOCK_SIZE;
+      const len = Math.min(BLOCK_SIZE - start, end - pos);
+      let block = file.blocks.get(index);
+      if (!block) {
+        block = new Uint8Array(BLOCK_SIZE);
+        file.blocks.set(index, block);
+      }
+      block.set(buffer.subarray(pos - offset, pos - offset + len), start);
+      file.dirty.add(index);
+      file.removed.delete(index);
+      pos += len;
+    }
+    if (end > file.size) {
+      file.size = end;
+      file.sizeChanged = true;
+    }
+    return buffer.byteLength;
+  }
+
+  size(fd) {
+    return BigInt(this.file(fd).size);
+  }
+
+  truncate(fd, len) {
+    const file = this.file(fd);
+    for (const index of file.blocks.keys()) {
+      if (index * BLOCK_SIZE >= len) {
+        file.blocks.delete(index);
+        file.dirty.delete(index);
+        file.removed.add(index);
+      }
+    }
+    // Zero the tail of the last block, so that extending the file again reads
+    // back zeroes.
+    const last = file.blocks.get(Math.floor(len / BLOCK_SIZE));
+    if (last && len % BLOCK_SIZE !== 0) {
+      last.fill(0, len % BLOCK_SIZE);
+      file.dirty.add(Math.floor(len / BLOCK_SIZE));
+    }
+    file.size = len;
+    file.sizeChanged = true;
+  }
+
+  sync(fd) {
+    const path = this.handles.get(fd);
+    const file = this.file(fd);
+    if (file.dirty.size === 0 && file.removed.size === 0 && !file.sizeChanged)
+      return;
+
+    const tx = this.db.transaction(["files", "blocks"], "readwrite");
+    tx.objectStore("files").put({ path, size: file.size });
+    const blocks = tx.objectStore("blocks");
+    for (const index of file.dirty) {
+      // Copy the block, later writes must not change what is being stored.
+      blocks.put({ path, index, data: file.blocks.get(index).slice() });
+    }
+    for (const index of file.removed) {
+      blocks.delete([path, index]);
+    }
+    file.dirty.clear();
+    file.removed.clear();
+    file.sizeChanged = false;
+
+    const done = new Promise((resolve, reject) => {
+      tx.oncomplete = () => resolve();
+      tx.onerror = () => reject(tx.error);
+      tx.onabort = () => reject(tx.error);
+    });
+    this.pending.add(done);
+    done.then(
+      () => this.pending.delete(done),
+      (e) => error("idb sync failed: ", e),
+    );
+  }
+
+  // Resolves once everything synced so far has been written to IndexedDB, or
+  // rejects with the error of the first sync that failed.
+  async flush() {
+    const pending = [...this.pending];
+    const results = await Promise.allSettled(pending);
+    for (const done of pending) this.pending.delete(done);
+    const failed = results.find((result) => result.status === "rejected");
+    if (failed) throw failed.reason;
+  }
+
+  file(fd) {
+    const file = this.files.get(this.handles.get(fd));
+    if (!file) throw new Error(`Invalid file descriptor: ${fd}`);
+    return file;
+  }
+}
+
+function newFile(size) {
+  return {
+    size,
+    blocks: new Map(),
+    dirty: new Set(),
+    removed: new Set(),
+    sizeChanged: false,
+  };
+}
+
+function openDatabase(name) {
+  const req = indexedDB.open(name, 1);
+  req.onupgradeneeded = () => {
+    const db = req.result;
+    db.createObjectStore("files", { keyPath: "path" });
+    db.createObjectStore("blocks", { keyPath: ["path", "index"] });
+  };
+  return request(req);
+}
+
+function request(req) {
+  return new Promise((resolve, reject) => {
+    req.onsuccess = () => resolve(req.result);
+    req.onerror = () => reject(req.error);
+  });
+}
+
+// logLevel:
+//
+// 0 = no logging output
+// 1 = only errors
+// 2 = warnings and errors
+// 3 = debug, warnings, and errors
+const logLevel = 1;
+
+const loggers = {
+  0: console.error.bind(console),
+  1: console.warn.bind(console),
+  2: console.log.bind(console),
+};
+const logImpl = (level, ...args) => {
+  if (logLevel > level) loggers[level]("IndexedDB VFS:", ...args);
+};
+const log = (...args) => logImpl(2, ...args);
+const warn = (...args) => logImpl(1, ...args);
+const error = (...args) => logImpl(0, ...args);
+
+export { VFS };
@@ -1,11 +1,24 @@
-import { VFS } from "./opfs.js";
+import { VFS as OpfsVFS } from "./opfs.js";
+import { VFS as IndexedDbVFS } from "./idb.js";
 import init, { Database } from "../dist/index.js";
 
 let db = null;
 let currentStmt = null;
 
+// The OPFS VFS needs sync access handles, and a SharedArrayBuffer to wait for
+// the proxy worker that owns them, which requires a cross-origin isolated page.
+// Everywhere else the databases are kept in IndexedDB. The backend can also be
+// picked with a `vfs=opfs` or `vfs=indexeddb` query parameter on the worker URL.
+function useOpfs() {
+  const requested = new URL(self.location.href).searchParams.get("vfs");
+  if (requested) return requested === "opfs";
+  return typeof FileSystemSyncAccessHandle !== "undefined" &&
+    typeof navigator.storage?.getDirectory === "function" &&
+    self.crossOriginIsolated;
+}
+
 async function initVFS() {
-  const vfs = new VFS();
+  const vfs = useOpfs() ? new OpfsVFS() : new IndexedDbVFS();
   await vfs.ready;
   self.vfs = vfs;
   return vfs;
@@ -19,7 +32,7 @@ async function initAll() {
 initAll().then(() => {
   self.postMessage({ type: "ready" });
 
-  self.onmessage = (e) => {
+  self.onmessage = async (e) => {
     try {
       switch (e.data.op) {
         case "createDb": {
@@ -30,6 +43,8 @@ initAll().then(() => {
         case "exec": {
           log(e.data.sql);
           db.exec(e.data.sql);
+          // Report success only once the changes are durable.
+          await self.vfs.flush?.();
           self.postMessage({ type: "success", op: "exec" });
           break;
         }
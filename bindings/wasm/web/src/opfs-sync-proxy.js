@@ -47,6 +47,8 @@ function handleCommand(msg) {
       return handleSize(msg.fd);
     case "sync":
       return handleSync(msg.fd);
+    case "truncate":
+      return handleTruncate(msg.fd, msg.len);
   }
 }
 
@@ -103,6 +105,12 @@ function handleSync(fd) {
   return { success: true };
 }
 
+function handleTruncate(fd, len) {
+  const handle = handles.get(fd);
+  handle.truncate(len);
+  return { success: true };
+}
+
 function sendResult(result) {
   if (result?.fd) {
     statusView.setInt32(4, result.fd, true);
@@ -1,3 +1,5 @@
+// Forwards to the VFS the worker installed as `self.vfs`, backed by either OPFS
+// or IndexedDB (see `limbo-worker.js`).
 export class VFS {
   constructor() {
     return self.vfs;
@@ -26,4 +28,8 @@ export class VFS {
   sync(fd) {
     return self.vfs.sync(fd);
   }
+
+  truncate(fd, len) {
+    return self.vfs.truncate(fd, len);
+  }
 }
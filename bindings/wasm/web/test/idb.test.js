@@ -0,0 +1,71 @@
+import { afterAll, beforeAll, beforeEach, expect, test } from "vitest";
+import { setupTestEnvironment, teardownTestEnvironment } from "./helpers.js";
+
+let testEnv;
+
+beforeAll(async () => {
+  testEnv = await setupTestEnvironment(5175);
+});
+
+beforeEach(async () => {
+  const { page } = testEnv;
+  await page.goto("http://localhost:5175/limbo-test.html");
+});
+
+afterAll(async () => {
+  await teardownTestEnvironment(testEnv);
+});
+
+test("database persists in IndexedDB", async () => {
+  const { page } = testEnv;
+  const result = await page.evaluate(async () => {
+    const startWorker = async () => {
+      const worker = new Worker("./src/limbo-worker.js?vfs=indexeddb", {
+        type: "module",
+      });
+      const waitForMessage = (type, op) =>
+        new Promise((resolve, reject) => {
+          const handler = (e) => {
+            if (e.data.type === type && (!op || e.data.op === op)) {
+              worker.removeEventListener("message", handler);
+              resolve(e.data);
+            } else if (e.data.type === "error") {
+              worker.removeEventListener("message", handler);
+              reject(e.data.error);
+            }
+          };
+          worker.addEventListener("message", handler);
+        });
+      await waitForMessage("ready");
+      worker.postMessage({ op: "createDb", path: "idb-test.db" });
+      await waitForMessage("success", "createDb");
+      return { worker, waitForMessage };
+    };
+
+    try {
+      let { worker, waitForMessage } = await startWorker();
+      worker.postMessage({
+        op: "exec",
+        sql: "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);",
+      });
+      await waitForMessage("success", "exec");
+      worker.postMessage({
+        op: "exec",
+        sql: "INSERT INTO users VALUES (1, 'Alice');",
+      });
+      await waitForMessage("success", "exec");
+      worker.terminate();
+
+      ({ worker, waitForMessage } = await startWorker());
+      worker.postMessage({ op: "prepare", sql: "SELECT * FROM users;" });
+      const results = await waitForMessage("result");
+      worker.terminate();
+      return results;
+    } catch (error) {
+      return { error: error.message ?? error };
+    }
+  });
+
+  if (result.error) throw new Error(`Test failed: ${result.error}`);
+  expect(result.result).toEqual([[1, "Alice"]]);
+});
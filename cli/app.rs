@@ -1,15 +1,26 @@
 use crate::{
-    commands::{args::EchoMode, import::ImportFile, Command, CommandParser},
+    commands::{
+        archive::{Archive, ArchiveArgs},
+        args::{ChangesMode, EchoMode, ExportArgs, ScanStatsMode, TraceArgs},
+        import::ImportFile,
+        Command, CommandParser,
+    },
     helper::LimboHelper,
-    input::{get_io, get_writer, DbLocation, OutputMode, Settings},
+    input::{get_io, get_writer, open_database, DbLocation, OutputMode, Settings},
     opcodes_dictionary::OPCODE_DESCRIPTIONS,
+    server::ServeArgs,
 };
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use comfy_table::{Attribute, Cell, CellAlignment, Color, ContentArrangement, Row, Table};
-use limbo_core::{Database, LimboError, OwnedValue, Statement, StepResult};
+use limbo_core::{
+    CheckpointStatus, Database, ExportFormat, LimboError, OwnedValue, Statement, StepResult,
+    TraceEvent,
+};
 
 use clap::Parser;
 use rustyline::{history::DefaultHistory, Editor};
 use std::{
+    cell::RefCell,
     fmt,
     io::{self, Write},
     path::PathBuf,
@@ -18,12 +29,16 @@ use std::{
         atomic::{AtomicUsize, Ordering},
         Arc,
     },
+    time::Duration,
 };
 
 #[derive(Parser)]
 #[command(name = "limbo")]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct Opts {
+    #[command(subcommand)]
+    pub command: Option<Mode>,
     #[clap(index = 1, help = "SQLite database file", default_value = ":memory:")]
     pub database: Option<PathBuf>,
     #[clap(index = 2, help = "Optional SQL command to execute")]
@@ -44,14 +59,23 @@ pub struct Opts {
     #[clap(
         short = 'v',
         long,
-        help = "Select VFS. options are io_uring (if feature enabled), memory, and syscall"
+        help = "Select VFS. options are io_uring (if feature enabled), memory, syscall, http and object"
     )]
     pub vfs: Option<String>,
     #[clap(long, help = "Enable experimental MVCC feature")]
     pub experimental_mvcc: bool,
 }
 
+#[derive(clap::Subcommand)]
+pub enum Mode {
+    /// Serve a database over HTTP using the Hrana protocol
+    Serve(ServeArgs),
+}
+
 const PROMPT: &str = "limbo> ";
+const PROMPT_TX: &str = "limbo (tx)> ";
+
+type TraceWriter = Rc<RefCell<Box<dyn Write>>>;
 
 pub struct Limbo<'a> {
     pub prompt: String,
@@ -61,6 +85,7 @@ pub struct Limbo<'a> {
     pub interrupt_count: Arc<AtomicUsize>,
     input_buff: String,
     opts: Settings,
+    trace: Option<(TraceWriter, bool)>,
     pub rl: &'a mut Editor<LimboHelper, DefaultHistory>,
 }
 
@@ -92,32 +117,15 @@ macro_rules! query_internal {
 static COLORS: &[Color] = &[Color::Green, Color::Black, Color::Grey];
 
 impl<'a> Limbo<'a> {
-    pub fn new(rl: &'a mut rustyline::Editor<LimboHelper, DefaultHistory>) -> anyhow::Result<Self> {
-        let opts = Opts::parse();
+    pub fn new(
+        rl: &'a mut rustyline::Editor<LimboHelper, DefaultHistory>,
+        opts: Opts,
+    ) -> anyhow::Result<Self> {
         let db_file = opts
             .database
             .as_ref()
             .map_or(":memory:".to_string(), |p| p.to_string_lossy().to_string());
-        let (io, db) = if let Some(ref vfs) = opts.vfs {
-            Database::open_new(&db_file, vfs)?
-        } else {
-            let io = {
-                match db_file.as_str() {
-                    ":memory:" => get_io(
-                        DbLocation::Memory,
-                        opts.vfs.as_ref().map_or("", |s| s.as_str()),
-                    )?,
-                    _path => get_io(
-                        DbLocation::Path,
-                        opts.vfs.as_ref().map_or("", |s| s.as_str()),
-                    )?,
-                }
-            };
-            (
-                io.clone(),
-                Database::open_file(io.clone(), &db_file, opts.experimental_mvcc)?,
-            )
-        };
+        let (io, db) = open_database(&db_file, opts.vfs.as_deref(), opts.experimental_mvcc)?;
         let conn = db.connect()?;
         let h = LimboHelper::new(conn.clone(), io.clone());
         rl.set_helper(Some(h));
@@ -138,6 +146,7 @@ impl<'a> Limbo<'a> {
             interrupt_count,
             input_buff: String::new(),
             opts: Settings::from(&opts),
+            trace: None,
             rl,
         };
 
@@ -183,43 +192,66 @@ impl<'a> Limbo<'a> {
     }
 
     fn dump_table(&mut self, name: &str) -> Result<(), LimboError> {
-        let query = format!("pragma table_info={}", name);
+        let table = quote_ident(name);
+        let query = format!("pragma table_info({})", table);
         let mut cols = vec![];
-        let mut value_types = vec![];
+        let mut integer_pk = vec![];
         query_internal!(
             self,
             query,
             |row: &limbo_core::Row| -> Result<(), LimboError> {
                 let name: &str = row.get::<&str>(1)?;
+                let ty: &str = row.get::<&str>(2)?;
+                if row.get::<i64>(5)? != 0 {
+                    integer_pk.push(ty.eq_ignore_ascii_case("integer"));
+                }
                 cols.push(name.to_string());
-                let value_type: &str = row.get::<&str>(2)?;
-                value_types.push(value_type.to_string());
                 Ok(())
             }
         )?;
-        // FIXME: sqlite has logic to check rowid and optionally preserve
-        // it, but it requires pragma index_list, and it seems to be relevant
-        // only for indexes.
-        let cols_str = cols.join(", ");
-        let select = format!("select {} from {}", cols_str, name);
+        // Rows are written with their rowid unless a column is an alias for it, so that the
+        // restored table keeps them, like SQLite's --preserve-rowids. A rowid whose every
+        // name is taken by a column can't be selected, like in SQLite.
+        let rowid = if integer_pk == [true] {
+            None
+        } else {
+            ["rowid", "_rowid_", "oid"]
+                .into_iter()
+                .find(|alias| !cols.iter().any(|col| col.eq_ignore_ascii_case(alias)))
+        };
+        let (select, insert) = match rowid {
+            Some(rowid) => {
+                let cols_str = cols
+                    .iter()
+                    .map(|col| quote_ident(col))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                (
+                    format!("select {}, * from {}", rowid, table),
+                    format!("INSERT INTO {}({},{})", table, rowid, cols_str),
+                )
+            }
+            None => (
+                format!("select * from {}", table),
+                format!("INSERT INTO {}", table),
+            ),
+        };
         query_internal!(
             self,
             select,
             |row: &limbo_core::Row| -> Result<(), LimboError> {
+                // Like SQLite, values are written as literals of their own type rather than
+                // the column's, which may hold values of any type.
                 let values = row
                     .get_values()
-                    .zip(value_types.iter())
-                    .map(|(value, value_type)| {
-                        // If the type affinity is TEXT, replace each single
-                        // quotation mark with two single quotation marks, and
-                        // wrap it with single quotation marks.
-                        if value_type.contains("CHAR")
-                            || value_type.contains("CLOB")
-                            || value_type.contains("TEXT")
-                        {
-                            format!("'{}'", value.to_string().replace("'", "''"))
-                        } else if value_type.contains("BLOB") {
-                            let blob = value.to_blob().unwrap_or(&[]);
+                    .map(|value| match value {
+                        OwnedValue::Null => "NULL".to_string(),
+                        OwnedValue::Integer(i) => i.to_string(),
+                        OwnedValue::Float(f) => float_literal(*f),
+                        OwnedValue::Text(text) => {
+                            format!("'{}'", text.as_str().replace("'", "''"))
+                        }
+                        OwnedValue::Blob(blob) => {
                             let hex_string: String =
                                 blob.iter().fold(String::new(), |mut output, b| {
                                     let _ =
@@ -227,13 +259,11 @@ impl<'a> Limbo<'a> {
                                     output
                                 });
                             format!("X'{}'", hex_string)
-                        } else {
-                            value.to_string()
                         }
                     })
                     .collect::<Vec<_>>()
                     .join(",");
-                self.write_fmt(format_args!("INSERT INTO {} VALUES({});", name, values))?;
+                self.write_fmt(format_args!("{} VALUES({});", insert, values))?;
                 Ok(())
             }
         )?;
@@ -243,6 +273,21 @@ impl<'a> Limbo<'a> {
     fn dump_database(&mut self) -> anyhow::Result<()> {
         self.writeln("PRAGMA foreign_keys=OFF;")?;
         self.writeln("BEGIN TRANSACTION;")?;
+        // The header fields applications identify and version their databases by, which
+        // SQLite's .dump leaves out.
+        for pragma in ["user_version", "application_id"] {
+            query_internal!(
+                self,
+                format!("PRAGMA {pragma}"),
+                |row: &limbo_core::Row| -> Result<(), LimboError> {
+                    let value = row.get::<i64>(0)?;
+                    if value != 0 {
+                        self.write_fmt(format_args!("PRAGMA {pragma}={value};"))?;
+                    }
+                    Ok(())
+                }
+            )?;
+        }
         // FIXME: At this point, SQLite executes the following:
         // sqlite3_exec(p->db, "SAVEPOINT dump; PRAGMA writable_schema=ON", 0, 0, 0);
         // we don't have those yet, so don't.
@@ -259,7 +304,17 @@ impl<'a> Limbo<'a> {
             |row: &limbo_core::Row| -> Result<(), LimboError> {
                 let sql: &str = row.get::<&str>(2)?;
                 let name: &str = row.get::<&str>(0)?;
-                self.write_fmt(format_args!("{};", sql))?;
+                // SQLite creates its own tables, so their rows are restored into the ones
+                // that exist once the others are created, replacing what they have.
+                if name == "sqlite_sequence" {
+                    self.writeln("DELETE FROM sqlite_sequence;")?;
+                } else if name == "sqlite_stat1" {
+                    self.writeln("ANALYZE sqlite_schema;")?;
+                } else if name.starts_with("sqlite_") {
+                    return Ok(());
+                } else {
+                    self.write_fmt(format_args!("{};", sql))?;
+                }
                 self.dump_table(name)
             }
         );
@@ -280,6 +335,83 @@ impl<'a> Limbo<'a> {
         Ok(())
     }
 
+    /// Writes the rows as objects keyed by column name, in an array like SQLite's json mode or
+    /// one per line in ndjson mode. NULL is null and blobs are base64 strings.
+    fn print_json_rows(&mut self, rows: &mut Statement) -> anyhow::Result<()> {
+        let ndjson = self.opts.output_mode == OutputMode::Ndjson;
+        let keys = (0..rows.num_columns())
+            .map(|i| serde_json::to_string(rows.get_column_name(i).as_str()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut count = 0;
+        let mut error = None;
+        loop {
+            if self.interrupt_count.load(Ordering::SeqCst) > 0 {
+                error = Some("Query interrupted.".to_string());
+                break;
+            }
+            match rows.step() {
+                Ok(StepResult::Row) => {
+                    let row = rows.row().unwrap();
+                    let mut line = String::new();
+                    if !ndjson {
+                        line.push_str(if count == 0 { "[" } else { ",\n" });
+                    }
+                    line.push('{');
+                    for (i, (key, value)) in keys.iter().zip(row.get_values()).enumerate() {
+                        if i > 0 {
+                            line.push(',');
+                        }
+                        line.push_str(key);
+                        line.push(':');
+                        match value {
+                            OwnedValue::Null => line.push_str("null"),
+                            // Like SQLite's JSON functions, which write infinities as numbers
+                            // too large to parse.
+                            OwnedValue::Float(f) if f.is_infinite() => {
+                                line.push_str(if *f < 0.0 { "-9.0e999" } else { "9.0e999" })
+                            }
+                            OwnedValue::Integer(_) | OwnedValue::Float(_) => {
+                                line.push_str(&value.to_string())
+                            }
+                            OwnedValue::Text(text) => {
+                                line.push_str(&serde_json::to_string(text.as_str())?)
+                            }
+                            OwnedValue::Blob(blob) => {
+                                line.push_str(&serde_json::to_string(&BASE64.encode(blob))?)
+                            }
+                        }
+                    }
+                    line.push('}');
+                    if ndjson {
+                        line.push('\n');
+                    }
+                    self.writer.write_all(line.as_bytes())?;
+                    count += 1;
+                }
+                Ok(StepResult::IO) => {
+                    self.io.run_once()?;
+                }
+                Ok(StepResult::Interrupt) | Ok(StepResult::Done) => break,
+                Ok(StepResult::Busy) => {
+                    error = Some("database is busy".to_string());
+                    break;
+                }
+                Err(err) => {
+                    error = Some(err.to_string());
+                    break;
+                }
+            }
+        }
+        // The array is closed before an error, so the rows before it still parse.
+        if !ndjson && count > 0 {
+            self.writer.write_all(b"]\n")?;
+        }
+        if let Some(error) = error {
+            let _ = self.writeln(error);
+        }
+        Ok(())
+    }
+
     fn display_in_memory(&mut self) -> io::Result<()> {
         if self.opts.db_file == ":memory:" {
             self.writeln("Connected to a transient in-memory database.")?;
@@ -294,14 +426,26 @@ impl<'a> Limbo<'a> {
     }
 
     pub fn reset_input(&mut self) {
-        self.prompt = PROMPT.to_string();
+        self.prompt = match self.conn.get_auto_commit() {
+            true => PROMPT.to_string(),
+            false => PROMPT_TX.to_string(),
+        };
         self.input_buff.clear();
     }
 
     pub fn close_conn(&mut self) -> Result<(), LimboError> {
+        self.warn_open_transaction();
         self.conn.close()
     }
 
+    fn warn_open_transaction(&self) {
+        if !self.conn.get_auto_commit() {
+            eprintln!(
+                "Warning: exiting with an open transaction, uncommitted changes will be lost."
+            );
+        }
+    }
+
     fn toggle_echo(&mut self, arg: EchoMode) {
         match arg {
             EchoMode::On => self.opts.echo = true,
@@ -309,10 +453,26 @@ impl<'a> Limbo<'a> {
         }
     }
 
+    fn toggle_changes(&mut self, arg: ChangesMode) {
+        match arg {
+            ChangesMode::On => self.opts.changes = true,
+            ChangesMode::Off => self.opts.changes = false,
+        }
+    }
+
+    fn toggle_scanstats(&mut self, arg: ScanStatsMode) {
+        match arg {
+            ScanStatsMode::On => self.opts.scanstats = true,
+            ScanStatsMode::Off => self.opts.scanstats = false,
+        }
+    }
+
     fn open_db(&mut self, path: &str, vfs_name: Option<&str>) -> anyhow::Result<()> {
         self.conn.close()?;
         let (io, db) = if let Some(vfs_name) = vfs_name {
             self.conn.open_new(path, vfs_name)?
+        } else if path.starts_with("file:") {
+            Database::open_uri(path, false)?
         } else {
             let io = {
                 match path {
@@ -325,9 +485,69 @@ impl<'a> Limbo<'a> {
         self.io = io;
         self.conn = db.connect()?;
         self.opts.db_file = path.to_string();
+        self.install_trace();
+        Ok(())
+    }
+
+    fn handle_archive(&mut self, args: ArchiveArgs) -> anyhow::Result<()> {
+        let Some(path) = args.file.clone() else {
+            return Archive::new(self.conn.clone(), self.io.clone(), &mut self.writer).run(args);
+        };
+        let io = get_io(DbLocation::Path, &self.opts.io.to_string())?;
+        let conn = Database::open_file(io.clone(), &path, false)?.connect()?;
+        let result = Archive::new(conn.clone(), io, &mut self.writer).run(args);
+        conn.close()?;
+        result
+    }
+
+    fn export_table(&mut self, args: ExportArgs) -> anyhow::Result<()> {
+        let format = if args.jsonl {
+            ExportFormat::Jsonl
+        } else {
+            ExportFormat::Csv
+        };
+        let mut file = std::fs::File::create(&args.file)?;
+        let rows = self.conn.export_table(&args.table, format, &mut file)?;
+        if args.verbose {
+            self.writeln(format!("Exported {} rows to {}", rows, args.file))?;
+        }
+        Ok(())
+    }
+
+    fn set_trace(&mut self, args: TraceArgs) -> io::Result<()> {
+        let writer: Box<dyn Write> = match args.target.as_str() {
+            "off" => {
+                self.trace = None;
+                self.conn.trace(false, None);
+                return Ok(());
+            }
+            "stdout" => Box::new(io::stdout()),
+            "stderr" => Box::new(io::stderr()),
+            path => Box::new(std::fs::File::create(path)?),
+        };
+        self.trace = Some((Rc::new(RefCell::new(writer)), args.opcodes));
+        self.install_trace();
         Ok(())
     }
 
+    fn install_trace(&self) {
+        let Some((writer, opcodes)) = &self.trace else {
+            return;
+        };
+        let writer = writer.clone();
+        self.conn.trace(
+            *opcodes,
+            Some(Box::new(move |event| {
+                let mut writer = writer.borrow_mut();
+                let _ = match event {
+                    TraceEvent::Stmt(sql) => writeln!(writer, "{}", sql),
+                    TraceEvent::Insn(insn) => writeln!(writer, "  {}", insn),
+                    TraceEvent::Registers(registers) => writeln!(writer, "        {}", registers),
+                };
+            })),
+        );
+    }
+
     fn set_output_file(&mut self, path: &str) -> Result<(), String> {
         if path.is_empty() || path.trim().eq_ignore_ascii_case("stdout") {
             self.set_output_stdout();
@@ -337,7 +557,9 @@ impl<'a> Limbo<'a> {
             Ok(file) => {
                 self.writer = Box::new(file);
                 self.opts.is_stdout = false;
-                self.opts.output_mode = OutputMode::List;
+                if self.opts.output_mode == OutputMode::Pretty {
+                    self.opts.output_mode = OutputMode::List;
+                }
                 self.opts.output_filename = path.to_string();
                 Ok(())
             }
@@ -472,6 +694,7 @@ impl<'a> Limbo<'a> {
             }
             Ok(cmd) => match cmd.command {
                 Command::Exit(args) => {
+                    self.warn_open_transaction();
                     std::process::exit(args.code);
                 }
                 Command::Quit => {
@@ -480,8 +703,9 @@ impl<'a> Limbo<'a> {
                     std::process::exit(0)
                 }
                 Command::Open(args) => {
-                    if self.open_db(&args.path, args.vfs_name.as_deref()).is_err() {
-                        let _ = self.writeln("Error: Unable to open database file.");
+                    let vfs_name = args.vfs.as_deref().or(args.vfs_name.as_deref());
+                    if let Err(e) = self.open_db(&args.path, vfs_name) {
+                        let _ = self.writeln(format!("Error: Unable to open database file: {}", e));
                     }
                 }
                 Command::Schema(args) => {
@@ -527,6 +751,12 @@ impl<'a> Limbo<'a> {
                 Command::Echo(args) => {
                     self.toggle_echo(args.mode);
                 }
+                Command::Changes(args) => {
+                    self.toggle_changes(args.mode);
+                }
+                Command::ScanStats(args) => {
+                    self.toggle_scanstats(args.mode);
+                }
                 Command::Cwd(args) => {
                     let _ = std::env::set_current_dir(args.directory);
                 }
@@ -538,6 +768,16 @@ impl<'a> Limbo<'a> {
                         ImportFile::new(self.conn.clone(), self.io.clone(), &mut self.writer);
                     import_file.import(args)
                 }
+                Command::Export(args) => {
+                    if let Err(e) = self.export_table(args) {
+                        let _ = self.write_fmt(format_args!("Error: {}", e));
+                    }
+                }
+                Command::Archive(args) => {
+                    if let Err(e) = self.handle_archive(args) {
+                        let _ = self.writeln(e.to_string());
+                    }
+                }
                 Command::LoadExtension(args) => {
                     #[cfg(not(target_family = "wasm"))]
                     if let Err(e) = self.handle_load_extension(&args.path) {
@@ -555,6 +795,17 @@ impl<'a> Limbo<'a> {
                         let _ = self.writeln(v);
                     });
                 }
+                Command::Rebuild(args) => {
+                    let create_sql = args.create_sql.join(" ");
+                    if let Err(e) = self.conn.alter_table_rebuild(&args.table, &create_sql) {
+                        let _ = self.write_fmt(format_args!("Error: {}", e));
+                    }
+                }
+                Command::Trace(args) => {
+                    if let Err(e) = self.set_trace(args) {
+                        let _ = self.write_fmt(format_args!("Error: {}", e));
+                    }
+                }
             },
         }
     }
@@ -564,6 +815,9 @@ impl<'a> Limbo<'a> {
         sql: &str,
         mut output: Result<Option<Statement>, LimboError>,
     ) -> anyhow::Result<()> {
+        if let Ok(Some(ref mut rows)) = output {
+            rows.set_profiling(self.opts.scanstats);
+        }
         match output {
             Ok(Some(ref mut rows)) => match self.opts.output_mode {
                 OutputMode::List => loop {
@@ -680,6 +934,7 @@ impl<'a> Limbo<'a> {
                         let _ = self.write_fmt(format_args!("{}", table));
                     }
                 }
+                OutputMode::Json | OutputMode::Ndjson => self.print_json_rows(rows)?,
             },
             Ok(None) => {}
             Err(err) => {
@@ -690,11 +945,50 @@ impl<'a> Limbo<'a> {
                 anyhow::bail!("We have to throw here, even if we printed error");
             }
         }
+        if let Ok(Some(ref rows)) = output {
+            // Statements without result columns are the ones that can write.
+            if self.opts.changes && rows.num_columns() == 0 {
+                let _ = self.write_fmt(format_args!(
+                    "changes: {}   total_changes: {}",
+                    self.conn.changes(),
+                    self.conn.total_changes()
+                ));
+            }
+            if self.opts.scanstats {
+                self.print_profile(rows);
+            }
+        }
         // for now let's cache flush always
-        self.conn.cacheflush()?;
+        while let CheckpointStatus::IO = self.conn.cacheflush()? {
+            self.io.run_once()?;
+        }
         Ok(())
     }
 
+    /// Prints the instructions that ran, the most expensive first.
+    fn print_profile(&mut self, rows: &Statement) {
+        let mut profile = rows.profile();
+        profile.retain(|insn| insn.executions > 0);
+        profile.sort_by(|a, b| b.elapsed.cmp(&a.elapsed));
+        let total: Duration = profile.iter().map(|insn| insn.elapsed).sum();
+        let _ = self.writeln("     calls        time       %  instruction");
+        for insn in profile {
+            let percent = if total.is_zero() {
+                0.0
+            } else {
+                insn.elapsed.as_secs_f64() * 100.0 / total.as_secs_f64()
+            };
+            let _ = self.write_fmt(format_args!(
+                "{:>10}  {:>8.3}ms  {:>5.1}%  {}",
+                insn.executions,
+                insn.elapsed.as_secs_f64() * 1000.0,
+                percent,
+                insn.insn
+            ));
+        }
+        let _ = self.write_fmt(format_args!("Total: {:.3}ms", total.as_secs_f64() * 1000.0));
+    }
+
     fn display_schema(&mut self, table: Option<&str>) -> anyhow::Result<()> {
         let sql = match table {
         Some(table_name) => format!(
@@ -824,3 +1118,26 @@ impl<'a> Limbo<'a> {
         self.reset_input();
     }
 }
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Writes a float as a literal that SQLite reads back as the same REAL: the shortest digits
+/// that round-trip, with an exponent when there would be no decimal point, so that whole
+/// numbers aren't read back as integers. Infinities are written as numbers too large to
+/// parse, and NaN, which SQLite stores as NULL, as NULL.
+fn float_literal(f: f64) -> String {
+    if f.is_nan() {
+        return "NULL".to_string();
+    }
+    if f.is_infinite() {
+        return if f < 0.0 { "-1e999" } else { "1e999" }.to_string();
+    }
+    let plain = f.to_string();
+    if plain.contains('.') && plain.len() <= 20 {
+        plain
+    } else {
+        format!("{:e}", f)
+    }
+}
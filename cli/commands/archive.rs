@@ -0,0 +1,366 @@
+use anyhow::anyhow;
+use clap::{ArgGroup, Args};
+use clap_complete::{ArgValueCompleter, PathCompleter};
+use limbo_core::{Connection, OwnedValue, Statement, StepResult};
+use std::{
+    fs,
+    io::Write,
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const SQLAR_SCHEMA: &str =
+    "CREATE TABLE sqlar(name TEXT PRIMARY KEY, mode INT, mtime INT, sz INT, data BLOB)";
+
+/// File type bits of `mode`, as stored by `stat(2)`.
+const S_IFMT: i64 = 0o170000;
+const S_IFDIR: i64 = 0o040000;
+const S_IFREG: i64 = 0o100000;
+const S_IFLNK: i64 = 0o120000;
+
+#[derive(Debug, Clone, Args)]
+#[command(group(ArgGroup::new("action").required(true).args(["create", "list", "extract"])))]
+pub struct ArchiveArgs {
+    /// Create a new archive containing FILES
+    #[arg(short = 'c', long)]
+    create: bool,
+    /// List the files in the archive
+    #[arg(short = 't', long)]
+    list: bool,
+    /// Extract files from the archive
+    #[arg(short = 'x', long)]
+    extract: bool,
+    /// Use archive FILE instead of the current database
+    #[arg(short = 'f', long, add = ArgValueCompleter::new(PathCompleter::file()))]
+    pub file: Option<String>,
+    /// Change to directory DIR before reading or writing files
+    #[arg(short = 'C', long, add = ArgValueCompleter::new(PathCompleter::dir()))]
+    directory: Option<PathBuf>,
+    /// Print each file as it is processed
+    #[arg(short, long, default_value = "false")]
+    verbose: bool,
+    /// Files to add, or to restrict listing and extraction to
+    #[arg(add = ArgValueCompleter::new(PathCompleter::any()))]
+    files: Vec<String>,
+}
+
+pub struct Archive<'a> {
+    conn: Rc<Connection>,
+    io: Arc<dyn limbo_core::IO>,
+    writer: &'a mut dyn Write,
+}
+
+impl<'a> Archive<'a> {
+    pub fn new(
+        conn: Rc<Connection>,
+        io: Arc<dyn limbo_core::IO>,
+        writer: &'a mut dyn Write,
+    ) -> Self {
+        Self { conn, io, writer }
+    }
+
+    pub fn run(&mut self, args: ArchiveArgs) -> anyhow::Result<()> {
+        let dir = args.directory.clone().unwrap_or_else(|| PathBuf::from("."));
+        if args.create {
+            self.create(&args, &dir)
+        } else if args.list {
+            self.list(&args)
+        } else {
+            self.extract(&args, &dir)
+        }
+    }
+
+    fn create(&mut self, args: &ArchiveArgs, dir: &Path) -> anyhow::Result<()> {
+        self.execute("BEGIN")?;
+        // The previous archive is only replaced once every file made it into the new one.
+        if let Err(e) = self.add_files(args, dir) {
+            self.conn.rollback()?;
+            return Err(e);
+        }
+        self.execute("COMMIT")
+    }
+
+    fn add_files(&mut self, args: &ArchiveArgs, dir: &Path) -> anyhow::Result<()> {
+        self.execute("DROP TABLE IF EXISTS sqlar")?;
+        self.execute(SQLAR_SCHEMA)?;
+        let mut insert = self.conn.prepare(
+            "INSERT INTO sqlar(name, mode, mtime, sz, data) VALUES (?, ?, ?, ?, sqlar_compress(?))",
+        )?;
+        for name in &args.files {
+            let name = name.trim_end_matches('/');
+            self.add_path(&mut insert, dir, name, args.verbose)?;
+        }
+        Ok(())
+    }
+
+    fn add_path(
+        &mut self,
+        insert: &mut Statement,
+        dir: &Path,
+        name: &str,
+        verbose: bool,
+    ) -> anyhow::Result<()> {
+        let path = dir.join(name);
+        // Symbolic links are stored as links, like SQLite does, rather than followed into
+        // directories above or outside of the ones archived.
+        let metadata =
+            fs::symlink_metadata(&path).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs() as i64);
+        let (data, sz) = if metadata.is_dir() {
+            (OwnedValue::Null, 0)
+        } else if metadata.is_symlink() {
+            let target = fs::read_link(&path)?.to_string_lossy().into_owned();
+            let sz = target.len() as i64;
+            (OwnedValue::from_blob(target.into_bytes()), sz)
+        } else {
+            let data = fs::read(&path)?;
+            let sz = data.len() as i64;
+            (OwnedValue::from_blob(data), sz)
+        };
+        insert.reset();
+        insert.bind_at(1.try_into()?, OwnedValue::build_text(name));
+        insert.bind_at(2.try_into()?, OwnedValue::Integer(file_mode(&metadata)));
+        insert.bind_at(3.try_into()?, OwnedValue::Integer(mtime));
+        insert.bind_at(4.try_into()?, OwnedValue::Integer(sz));
+        insert.bind_at(5.try_into()?, data);
+        self.step_to_end(insert)?;
+        if verbose {
+            let _ = writeln!(self.writer, "{}", name);
+        }
+        if metadata.is_dir() {
+            let mut entries = fs::read_dir(&path)?
+                .map(|entry| entry.map(|e| e.file_name().to_string_lossy().to_string()))
+                .collect::<Result<Vec<_>, _>>()?;
+            entries.sort();
+            for entry in entries {
+                self.add_path(insert, dir, &format!("{}/{}", name, entry), verbose)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn list(&mut self, args: &ArchiveArgs) -> anyhow::Result<()> {
+        let rows = self.select("SELECT name, mode, sz, mtime FROM sqlar ORDER BY name")?;
+        for row in rows.iter().filter(|row| selected(&args.files, &row[0])) {
+            if args.verbose {
+                let int = |value: &OwnedValue| match value {
+                    OwnedValue::Integer(i) => *i,
+                    _ => 0,
+                };
+                let _ = writeln!(
+                    self.writer,
+                    "{} {:>10}  {}  {}",
+                    mode_string(int(&row[1])),
+                    int(&row[2]),
+                    format_mtime(int(&row[3])),
+                    row[0]
+                );
+            } else {
+                let _ = writeln!(self.writer, "{}", row[0]);
+            }
+        }
+        Ok(())
+    }
+
+    fn extract(&mut self, args: &ArchiveArgs, dir: &Path) -> anyhow::Result<()> {
+        let rows = self.select(
+            "SELECT name, mode, mtime, sqlar_uncompress(data, sz) FROM sqlar ORDER BY name",
+        )?;
+        // Links are made last, so that no file of the archive is written through one.
+        let mut links = Vec::new();
+        for row in rows.iter().filter(|row| selected(&args.files, &row[0])) {
+            let name = row[0].to_string();
+            let relative = Path::new(&name);
+            if relative
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+            {
+                let _ = writeln!(self.writer, "skipping unsafe path: {}", name);
+                continue;
+            }
+            let path = dir.join(relative);
+            let mode = match row[1] {
+                OwnedValue::Integer(mode) => mode,
+                _ => S_IFREG | 0o644,
+            };
+            if mode & S_IFMT == S_IFDIR {
+                fs::create_dir_all(&path)?;
+            } else {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let data = match &row[3] {
+                    OwnedValue::Blob(data) => data.as_slice(),
+                    OwnedValue::Text(text) => text.as_str().as_bytes(),
+                    _ => &[],
+                };
+                if mode & S_IFMT == S_IFLNK {
+                    let target = String::from_utf8_lossy(data).into_owned();
+                    links.push((path, target, name));
+                    continue;
+                }
+                let file = fs::File::create(&path)?;
+                (&file).write_all(data)?;
+                if let OwnedValue::Integer(mtime) = row[2] {
+                    let _ = file
+                        .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(mtime as u64));
+                }
+            }
+            set_permissions(&path, mode)?;
+            if args.verbose {
+                let _ = writeln!(self.writer, "{}", name);
+            }
+        }
+        for (path, target, name) in links {
+            if path.symlink_metadata().is_ok() {
+                fs::remove_file(&path)?;
+            }
+            symlink(&target, &path)?;
+            if args.verbose {
+                let _ = writeln!(self.writer, "{}", name);
+            }
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, sql: &str) -> anyhow::Result<()> {
+        if let Some(mut stmt) = self.conn.query(sql)? {
+            self.step_to_end(&mut stmt)?;
+        }
+        Ok(())
+    }
+
+    fn select(&mut self, sql: &str) -> anyhow::Result<Vec<Vec<OwnedValue>>> {
+        let mut rows = Vec::new();
+        let Some(mut stmt) = self.conn.query(sql)? else {
+            return Ok(rows);
+        };
+        loop {
+            match stmt.step()? {
+                StepResult::Row => {
+                    let row = stmt.row().unwrap();
+                    rows.push(row.get_values().cloned().collect());
+                }
+                StepResult::IO => self.io.run_once()?,
+                StepResult::Interrupt | StepResult::Done => break,
+                StepResult::Busy => return Err(anyhow!("database is busy")),
+            }
+        }
+        Ok(rows)
+    }
+
+    fn step_to_end(&mut self, stmt: &mut Statement) -> anyhow::Result<()> {
+        loop {
+            match stmt.step()? {
+                StepResult::IO => self.io.run_once()?,
+                StepResult::Row => {}
+                StepResult::Interrupt | StepResult::Done => return Ok(()),
+                StepResult::Busy => return Err(anyhow!("database is busy")),
+            }
+        }
+    }
+}
+
+/// Returns whether an archive entry is one of `files`, or lives under one of them.
+/// An empty list selects every entry.
+fn selected(files: &[String], name: &OwnedValue) -> bool {
+    let name = name.to_string();
+    files.is_empty()
+        || files.iter().any(|file| {
+            let file = file.trim_end_matches('/');
+            name == file
+                || name
+                    .strip_prefix(file)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> i64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.mode() as i64
+}
+
+#[cfg(not(unix))]
+fn file_mode(metadata: &fs::Metadata) -> i64 {
+    if metadata.is_symlink() {
+        S_IFLNK | 0o777
+    } else if metadata.is_dir() {
+        S_IFDIR | 0o755
+    } else if metadata.permissions().readonly() {
+        S_IFREG | 0o444
+    } else {
+        S_IFREG | 0o644
+    }
+}
+
+#[cfg(unix)]
+fn set_permissions(path: &Path, mode: i64) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode((mode & 0o777) as u32))
+}
+
+#[cfg(not(unix))]
+fn set_permissions(_path: &Path, _mode: i64) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(target: &str, path: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(not(unix))]
+fn symlink(_target: &str, path: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("{}: symbolic links can't be extracted here", path.display()),
+    ))
+}
+
+/// Formats `mode` the way `ls -l` does, e.g. `-rw-r--r--`.
+fn mode_string(mode: i64) -> String {
+    let mut s = String::with_capacity(10);
+    s.push(match mode & S_IFMT {
+        S_IFDIR => 'd',
+        S_IFLNK => 'l',
+        _ => '-',
+    });
+    for shift in [6, 3, 0] {
+        let bits = (mode >> shift) & 0o7;
+        s.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        s.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+    s
+}
+
+/// Formats seconds since the Unix epoch as `YYYY-MM-DD HH:MM:SS` (UTC).
+fn format_mtime(secs: i64) -> String {
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // Civil-from-days conversion, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
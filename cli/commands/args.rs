@@ -19,6 +19,9 @@ pub struct OpenArgs {
     // Currently not possible to pass arbitrary
     /// Name of VFS
     pub vfs_name: Option<String>,
+    /// Name of VFS, as in `.open --vfs NAME PATH`
+    #[arg(long = "vfs", conflicts_with = "vfs_name")]
+    pub vfs: Option<String>,
 }
 
 #[derive(Debug, Clone, Args)]
@@ -95,6 +98,30 @@ pub enum EchoMode {
     Off,
 }
 
+#[derive(Debug, Clone, Args)]
+pub struct ChangesArgs {
+    #[arg(value_enum)]
+    pub mode: ChangesMode,
+}
+
+#[derive(Debug, ValueEnum, Clone)]
+pub enum ChangesMode {
+    On,
+    Off,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ScanStatsArgs {
+    #[arg(value_enum)]
+    pub mode: ScanStatsMode,
+}
+
+#[derive(Debug, ValueEnum, Clone)]
+pub enum ScanStatsMode {
+    On,
+    Off,
+}
+
 #[derive(Debug, Clone, Args)]
 pub struct TablesArgs {
     pub pattern: Option<String>,
@@ -106,3 +133,40 @@ pub struct LoadExtensionArgs {
     #[arg(add = ArgValueCompleter::new(PathCompleter::file()))]
     pub path: String,
 }
+
+#[derive(Debug, Clone, Args)]
+pub struct TraceArgs {
+    /// Also trace every VDBE instruction that is executed
+    #[arg(long, default_value_t = false)]
+    pub opcodes: bool,
+    /// File to write the trace to, or one of stdout, stderr and off
+    #[arg(add = ArgValueCompleter::new(PathCompleter::file()))]
+    pub target: String,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ExportArgs {
+    /// Write CSV with a header line of the column names (the default)
+    #[arg(long, conflicts_with = "jsonl")]
+    pub csv: bool,
+    /// Write a JSON object per line
+    #[arg(long)]
+    pub jsonl: bool,
+    /// "Verbose" - print the number of rows written
+    #[arg(short, default_value = "false")]
+    pub verbose: bool,
+    /// Table to export
+    pub table: String,
+    /// File to write the rows to
+    #[arg(add = ArgValueCompleter::new(PathCompleter::file()))]
+    pub file: String,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct RebuildArgs {
+    /// Table to rebuild
+    pub table: String,
+    /// CREATE TABLE statement with the new definition of the table
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+    pub create_sql: Vec<String>,
+}
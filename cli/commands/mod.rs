@@ -1,9 +1,12 @@
+pub mod archive;
 pub mod args;
 pub mod import;
 
+use archive::ArchiveArgs;
 use args::{
-    CwdArgs, EchoArgs, ExitArgs, LoadExtensionArgs, NullValueArgs, OpcodesArgs, OpenArgs,
-    OutputModeArgs, SchemaArgs, SetOutputArgs, TablesArgs,
+    ChangesArgs, CwdArgs, EchoArgs, ExitArgs, ExportArgs, LoadExtensionArgs, NullValueArgs,
+    OpcodesArgs, OpenArgs, OutputModeArgs, RebuildArgs, ScanStatsArgs, SchemaArgs, SetOutputArgs,
+    TablesArgs, TraceArgs,
 };
 use clap::Parser;
 use import::ImportArgs;
@@ -59,11 +62,23 @@ pub enum Command {
     /// Toggle 'echo' mode to repeat commands before execution
     #[command(display_name = ".echo")]
     Echo(EchoArgs),
+    /// Toggle printing the number of rows changed by each statement
+    #[command(name = "changes", display_name = ".changes")]
+    Changes(ChangesArgs),
+    /// Toggle printing how often each instruction ran and how long it took
+    #[command(name = "scanstats", display_name = ".scanstats")]
+    ScanStats(ScanStatsArgs),
     /// Display tables
     Tables(TablesArgs),
     /// Import data from FILE into TABLE
     #[command(name = "import", display_name = ".import")]
     Import(ImportArgs),
+    /// Export the rows of TABLE to FILE as CSV or JSON lines
+    #[command(name = "export", display_name = ".export")]
+    Export(ExportArgs),
+    /// Create, list or extract an SQL archive (sqlar)
+    #[command(name = "archive", display_name = ".archive", alias = "ar")]
+    Archive(ArchiveArgs),
     /// Loads an extension library
     #[command(name = "load", display_name = ".load")]
     LoadExtension(LoadExtensionArgs),
@@ -72,6 +87,12 @@ pub enum Command {
     /// List vfs modules available
     #[command(name = "vfslist", display_name = ".vfslist")]
     ListVfs,
+    /// Change the definition of TABLE by rebuilding it, keeping its rows and indexes
+    #[command(name = "rebuild", display_name = ".rebuild")]
+    Rebuild(RebuildArgs),
+    /// Log executed SQL statements to FILE, or turn tracing off
+    #[command(name = "trace", display_name = ".trace")]
+    Trace(TraceArgs),
 }
 
 const _HELP_TEMPLATE: &str = "{before-help}{name}
@@ -62,6 +62,10 @@ impl Default for Io {
 pub enum OutputMode {
     List,
     Pretty,
+    /// An array of objects keyed by column name.
+    Json,
+    /// An object per line, written as the rows come.
+    Ndjson,
 }
 
 impl std::fmt::Display for OutputMode {
@@ -79,6 +83,8 @@ pub struct Settings {
     pub null_value: String,
     pub output_mode: OutputMode,
     pub echo: bool,
+    pub changes: bool,
+    pub scanstats: bool,
     pub is_stdout: bool,
     pub io: Io,
 }
@@ -89,6 +95,8 @@ impl From<&Opts> for Settings {
             null_value: String::new(),
             output_mode: opts.output_mode,
             echo: false,
+            changes: false,
+            scanstats: false,
             is_stdout: opts.output.is_empty(),
             output_filename: opts.output.clone(),
             db_file: opts
@@ -111,7 +119,7 @@ impl std::fmt::Display for Settings {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Settings:\nOutput mode: {}\nDB: {}\nOutput: {}\nNull value: {}\nCWD: {}\nEcho: {}",
+            "Settings:\nOutput mode: {}\nDB: {}\nOutput: {}\nNull value: {}\nCWD: {}\nEcho: {}\nChanges: {}\nScanstats: {}",
             self.output_mode,
             self.db_file,
             match self.is_stdout {
@@ -123,6 +131,14 @@ impl std::fmt::Display for Settings {
             match self.echo {
                 true => "on",
                 false => "off",
+            },
+            match self.changes {
+                true => "on",
+                false => "off",
+            },
+            match self.scanstats {
+                true => "on",
+                false => "off",
             }
         )
     }
@@ -168,6 +184,19 @@ pub fn get_io(db_location: DbLocation, io_choice: &str) -> anyhow::Result<Arc<dy
     })
 }
 
+/// Opens `db_file`, which may be a `file:` URI, with the requested VFS or the
+/// IO backend that suits the location (in-memory or on-disk).
+pub fn open_database(
+    db_file: &str,
+    vfs: Option<&str>,
+    enable_mvcc: bool,
+) -> anyhow::Result<(Arc<dyn limbo_core::IO>, Arc<limbo_core::Database>)> {
+    Ok(match vfs {
+        Some(vfs) => limbo_core::Database::open_new(db_file, vfs)?,
+        None => limbo_core::Database::open_uri(db_file, enable_mvcc)?,
+    })
+}
+
 pub const BEFORE_HELP_MSG: &str = r#"
 
 Limbo SQL Shell Help
@@ -213,8 +242,26 @@ pub const AFTER_HELP_MSG: &str = r#"Usage Examples:
    .load /target/debug/liblimbo_regexp
 
 13. To list all available VFS:
-   .listvfs
+   .vfslist
+
+14. To log every executed statement to 'trace.log':
+   .trace trace.log
+
+15. To print the number of rows changed after each write:
+   .changes on
+
+16. To see which instructions a slow query spends its time in:
+   .scanstats on
+
+17. To pack the 'docs' directory into the SQL archive 'docs.db' and list it:
+   .archive --create --file docs.db docs
+   .archive --list --verbose --file docs.db
+
+18. To open 'data.db' with the VFS 'testvfs' registered by a loaded extension:
+   .open --vfs testvfs data.db
+   .open file:data.db?vfs=testvfs
 
 Note:
 - All SQL commands must end with a semicolon (;).
+- The prompt changes to 'limbo (tx)> ' while a transaction is open.
 - Special commands start with a dot (.) and are not required to end with a semicolon."#;
@@ -4,7 +4,10 @@ mod commands;
 mod helper;
 mod input;
 mod opcodes_dictionary;
+mod server;
 
+use app::{Mode, Opts};
+use clap::Parser;
 use rustyline::{error::ReadlineError, Config, Editor};
 use std::sync::atomic::Ordering;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
@@ -16,7 +19,7 @@ fn rustyline_config() -> Config {
 }
 
 fn main() -> anyhow::Result<()> {
-    let mut rl = Editor::with_config(rustyline_config())?;
+    let mut opts = Opts::parse();
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::fmt::layer()
@@ -25,7 +28,11 @@ fn main() -> anyhow::Result<()> {
         )
         .with(EnvFilter::from_default_env())
         .init();
-    let mut app = app::Limbo::new(&mut rl)?;
+    if let Some(Mode::Serve(args)) = opts.command.take() {
+        return server::serve(args);
+    }
+    let mut rl = Editor::with_config(rustyline_config())?;
+    let mut app = app::Limbo::new(&mut rl, opts)?;
     let home = dirs::home_dir().expect("Could not determine home directory");
     let history_file = home.join(".limbo_history");
     if history_file.exists() {
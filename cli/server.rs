@@ -0,0 +1,947 @@
+//! `limbo serve`: exposes a database over HTTP using the Hrana protocol
+//! (see <https://github.com/tursodatabase/libsql/blob/main/docs/HRANA_3_SPEC.md>).
+//!
+//! Connections are not `Send`, so requests are served one at a time on the
+//! thread that owns the database. Every Hrana stream borrows a connection from
+//! a pool, and the baton returned to the client keeps that connection reserved
+//! until the stream is closed or sits idle for longer than the stream timeout.
+//! Clients get a limited time to send their whole request, so that one that
+//! stalls or trickles it in can't hold up the others.
+
+use crate::input::open_database;
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+use clap::Args;
+use limbo_core::error::{
+    SQLITE_BUSY, SQLITE_CONSTRAINT, SQLITE_CORRUPT, SQLITE_IOERR, SQLITE_LOCKED, SQLITE_NOTADB,
+    SQLITE_RANGE,
+};
+use limbo_core::{Connection, Database, LimboError, OwnedValue, StepResult};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    num::NonZero,
+    path::PathBuf,
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Requests with a larger body are rejected before it is read.
+const MAX_BODY_SIZE: usize = 64 * 1024 * 1024;
+
+/// Requests whose request line and headers are larger are rejected.
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+
+/// Hrana encodes blobs as base64 without padding, but accepts either form.
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new()
+        .with_encode_padding(false)
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+#[derive(Debug, Clone, Args)]
+pub struct ServeArgs {
+    #[clap(help = "SQLite database file", default_value = ":memory:")]
+    database: PathBuf,
+    #[clap(
+        short,
+        long,
+        help = "Address to listen on",
+        default_value = "127.0.0.1:8080"
+    )]
+    listen: String,
+    #[clap(
+        long,
+        help = "Maximum number of database connections",
+        default_value_t = 16
+    )]
+    max_connections: usize,
+    #[clap(
+        long,
+        help = "Seconds after which an idle stream is closed",
+        default_value_t = 10
+    )]
+    stream_timeout: u64,
+    #[clap(
+        long,
+        help = "Seconds a client may take to send a whole request, or to accept each write of the response, before it is disconnected",
+        default_value_t = 10
+    )]
+    request_timeout: u64,
+    #[clap(
+        short = 'v',
+        long,
+        help = "Select VFS. options are io_uring (if feature enabled), memory, syscall, http and object"
+    )]
+    vfs: Option<String>,
+    #[clap(long, help = "Enable experimental MVCC feature")]
+    experimental_mvcc: bool,
+}
+
+pub fn serve(args: ServeArgs) -> anyhow::Result<()> {
+    let db_file = args.database.to_string_lossy().to_string();
+    let (_io, db) = open_database(&db_file, args.vfs.as_deref(), args.experimental_mvcc)?;
+    let listener = TcpListener::bind(&args.listen)?;
+    eprintln!(
+        "Limbo v{} serving {} on http://{}",
+        env!("CARGO_PKG_VERSION"),
+        db_file,
+        listener.local_addr()?
+    );
+    let mut server = Server {
+        pool: Pool::new(
+            db,
+            args.max_connections,
+            Duration::from_secs(args.stream_timeout),
+        ),
+        request_timeout: Duration::from_secs(args.request_timeout),
+    };
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = server.handle(stream) {
+                    tracing::debug!("error while serving request: {}", e);
+                }
+            }
+            Err(e) => tracing::warn!("failed to accept connection: {}", e),
+        }
+    }
+    Ok(())
+}
+
+struct Server {
+    pool: Pool,
+    request_timeout: Duration,
+}
+
+impl Server {
+    fn handle(&mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_write_timeout(Some(self.request_timeout))?;
+        let mut reader = BufReader::new(DeadlineReader {
+            stream: stream.try_clone()?,
+            deadline: Instant::now() + self.request_timeout,
+        });
+        let mut stream = stream;
+        let request = match read_request(&mut reader) {
+            Ok(request) => request,
+            Err(e) => {
+                let body = serde_json::to_vec(&Error::new(e.to_string(), "HTTP_BAD_REQUEST"))?;
+                write_response(&mut stream, "400 Bad Request", "application/json", &body)?;
+                // Closing with input left unread resets the connection, which can discard the
+                // response before the client reads it, so the rest is read first, for no longer
+                // than the request could take.
+                stream.shutdown(Shutdown::Write)?;
+                let _ = io::copy(&mut reader.take(MAX_BODY_SIZE as u64), &mut io::sink());
+                return Ok(());
+            }
+        };
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/health" | "/v2" | "/v3") => {
+                write_response(&mut stream, "200 OK", "text/plain", b"")
+            }
+            ("GET", "/version") => write_response(
+                &mut stream,
+                "200 OK",
+                "text/plain",
+                env!("CARGO_PKG_VERSION").as_bytes(),
+            ),
+            ("POST", "/v2/pipeline" | "/v3/pipeline") => {
+                let result = parse_body(&request.body).and_then(|body| self.pipeline(body));
+                match result {
+                    Ok(response) => {
+                        let body = serde_json::to_vec(&response)?;
+                        write_response(&mut stream, "200 OK", "application/json", &body)
+                    }
+                    Err(e) => {
+                        let body = serde_json::to_vec(&e)?;
+                        write_response(&mut stream, "400 Bad Request", "application/json", &body)
+                    }
+                }
+            }
+            ("POST", "/v3/cursor") => match parse_body(&request.body) {
+                Ok(body) => self.cursor(body, stream),
+                Err(e) => {
+                    let body = serde_json::to_vec(&e)?;
+                    write_response(&mut stream, "400 Bad Request", "application/json", &body)
+                }
+            },
+            _ => write_response(&mut stream, "404 Not Found", "text/plain", b"not found"),
+        }
+    }
+
+    /// Runs every request of a pipeline on the stream's connection.
+    fn pipeline(&mut self, body: PipelineReqBody) -> Result<PipelineRespBody, Error> {
+        let conn = self.pool.acquire(body.baton.as_deref())?;
+        let mut closed = false;
+        let mut results = Vec::with_capacity(body.requests.len());
+        for request in body.requests {
+            if closed {
+                results.push(StreamResult::Error {
+                    error: Error::new("stream is closed", "STREAM_CLOSED"),
+                });
+                continue;
+            }
+            let response = match request {
+                StreamRequest::Close => {
+                    closed = true;
+                    Ok(StreamResponse::Close)
+                }
+                StreamRequest::Execute { stmt } => {
+                    let mut sink = StmtResult::default();
+                    execute_stmt(&conn, &stmt, &mut sink)
+                        .expect("collecting results cannot fail")
+                        .map(|end| {
+                            sink.affected_row_count = end.affected_row_count;
+                            sink.last_insert_rowid = end.last_insert_rowid;
+                            StreamResponse::Execute { result: sink }
+                        })
+                }
+                StreamRequest::Batch { batch } => {
+                    let mut sink = BatchResult::default();
+                    run_batch(&conn, &batch, &mut sink).expect("collecting results cannot fail");
+                    Ok(StreamResponse::Batch { result: sink })
+                }
+                StreamRequest::Sequence { sql } => {
+                    run_sequence(&conn, &sql).map(|_| StreamResponse::Sequence)
+                }
+                StreamRequest::GetAutocommit => Ok(StreamResponse::GetAutocommit {
+                    is_autocommit: conn.get_auto_commit(),
+                }),
+                StreamRequest::Unsupported => Err(Error::new(
+                    "request type is not supported",
+                    "PROTOCOL_ERROR",
+                )),
+            };
+            results.push(match response {
+                Ok(response) => StreamResult::Ok { response },
+                Err(error) => StreamResult::Error { error },
+            });
+        }
+        let baton = if closed {
+            self.pool.release(conn);
+            None
+        } else {
+            Some(self.pool.keep(conn))
+        };
+        Ok(PipelineRespBody {
+            baton,
+            base_url: None,
+            results,
+        })
+    }
+
+    /// Runs a batch and streams its results back as newline-delimited JSON,
+    /// one entry per row, so large results never have to be buffered.
+    fn cursor(&mut self, body: CursorReqBody, mut stream: TcpStream) -> io::Result<()> {
+        let conn = match self.pool.acquire(body.baton.as_deref()) {
+            Ok(conn) => conn,
+            Err(e) => {
+                let body = serde_json::to_vec(&e)?;
+                return write_response(&mut stream, "400 Bad Request", "application/json", &body);
+            }
+        };
+        let baton = self.pool.new_baton();
+        let header = CursorRespBody {
+            baton: Some(baton.clone()),
+            base_url: None,
+        };
+        let mut writer = CursorWriter::new(&mut stream)?;
+        let result = writer
+            .send(&header)
+            .and_then(|_| run_batch(&conn, &body.batch, &mut writer))
+            .and_then(|_| writer.finish());
+        self.pool.insert(baton, conn);
+        result
+    }
+}
+
+/// Receives the columns and rows of a statement as it is being stepped.
+trait RowSink {
+    fn columns(&mut self, cols: Vec<Col>) -> io::Result<()>;
+    fn row(&mut self, row: Vec<Value>) -> io::Result<()>;
+}
+
+/// Receives the outcome of each step of a batch.
+trait BatchSink: RowSink {
+    fn step_begin(&mut self, step: usize) -> io::Result<()>;
+    fn step_end(&mut self, step: usize, result: Result<StmtEnd, Error>) -> io::Result<()>;
+    fn step_skipped(&mut self, step: usize) -> io::Result<()>;
+}
+
+struct StmtEnd {
+    affected_row_count: u64,
+    last_insert_rowid: Option<String>,
+}
+
+/// Executes `stmt`, passing its rows to `sink`. The outer error is only
+/// returned when the sink fails; SQL errors are reported in the inner result.
+fn execute_stmt(
+    conn: &Rc<Connection>,
+    stmt: &Stmt,
+    sink: &mut impl RowSink,
+) -> io::Result<Result<StmtEnd, Error>> {
+    let mut statement = match conn.prepare(&stmt.sql) {
+        Ok(statement) => statement,
+        Err(e) => return Ok(Err(e.into())),
+    };
+    for (i, arg) in stmt.args.iter().enumerate() {
+        let value = match arg.to_owned_value() {
+            Ok(value) => value,
+            Err(e) => return Ok(Err(e)),
+        };
+        statement.bind_at(NonZero::new(i + 1).unwrap(), value);
+    }
+    for arg in &stmt.named_args {
+        let index = ["", ":", "@", "$"].iter().find_map(|prefix| {
+            statement
+                .parameters()
+                .index(format!("{prefix}{}", arg.name))
+        });
+        let Some(index) = index else {
+            return Ok(Err(Error::new(
+                format!("no parameter named {}", arg.name),
+                "ARGS_INVALID",
+            )));
+        };
+        match arg.value.to_owned_value() {
+            Ok(value) => statement.bind_at(index, value),
+            Err(e) => return Ok(Err(e)),
+        }
+    }
+    let cols = (0..statement.num_columns())
+        .map(|i| Col {
+            name: Some(statement.get_column_name(i).to_string()),
+            decltype: None,
+        })
+        .collect();
+    sink.columns(cols)?;
+    let total_changes = conn.total_changes();
+    loop {
+        let step = match statement.step() {
+            Ok(step) => step,
+            Err(e) => return Ok(Err(e.into())),
+        };
+        match step {
+            StepResult::Row => {
+                if stmt.want_rows {
+                    let row = statement.row().unwrap();
+                    sink.row(row.get_values().map(Value::from).collect())?;
+                }
+            }
+            StepResult::IO => {
+                if let Err(e) = statement.run_once() {
+                    return Ok(Err(e.into()));
+                }
+            }
+            StepResult::Interrupt | StepResult::Done => break,
+            StepResult::Busy => return Ok(Err(Error::new("database is busy", "SQLITE_BUSY"))),
+        }
+    }
+    Ok(Ok(StmtEnd {
+        affected_row_count: (conn.total_changes() - total_changes) as u64,
+        last_insert_rowid: Some(conn.last_insert_rowid().to_string()),
+    }))
+}
+
+fn run_batch(conn: &Rc<Connection>, batch: &Batch, sink: &mut impl BatchSink) -> io::Result<()> {
+    // `Some(true)` for steps that succeeded, `Some(false)` for steps that failed
+    // and `None` for steps whose condition did not hold.
+    let mut outcomes: Vec<Option<bool>> = Vec::with_capacity(batch.steps.len());
+    for (i, step) in batch.steps.iter().enumerate() {
+        let run = step
+            .condition
+            .as_ref()
+            .is_none_or(|cond| cond.eval(&outcomes, conn));
+        if !run {
+            outcomes.push(None);
+            sink.step_skipped(i)?;
+            continue;
+        }
+        sink.step_begin(i)?;
+        let result = execute_stmt(conn, &step.stmt, sink)?;
+        outcomes.push(Some(result.is_ok()));
+        sink.step_end(i, result)?;
+    }
+    Ok(())
+}
+
+fn run_sequence(conn: &Rc<Connection>, sql: &str) -> Result<(), Error> {
+    for statement in conn.query_runner(sql.as_bytes()) {
+        let Some(mut statement) = statement? else {
+            continue;
+        };
+        loop {
+            match statement.step()? {
+                StepResult::IO => statement.run_once()?,
+                StepResult::Row => {}
+                StepResult::Interrupt | StepResult::Done => break,
+                StepResult::Busy => return Err(Error::new("database is busy", "SQLITE_BUSY")),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Connections that are not attached to a stream, plus the streams that are
+/// currently open, keyed by baton.
+struct Pool {
+    db: Arc<Database>,
+    idle: Vec<Rc<Connection>>,
+    streams: HashMap<String, (Rc<Connection>, Instant)>,
+    max_connections: usize,
+    stream_timeout: Duration,
+    next_baton: u64,
+    random: RandomState,
+}
+
+impl Pool {
+    fn new(db: Arc<Database>, max_connections: usize, stream_timeout: Duration) -> Self {
+        Self {
+            db,
+            idle: Vec::new(),
+            streams: HashMap::new(),
+            max_connections,
+            stream_timeout,
+            next_baton: 0,
+            random: RandomState::new(),
+        }
+    }
+
+    /// Returns the connection of the stream identified by `baton`, or a fresh
+    /// connection for a new stream.
+    fn acquire(&mut self, baton: Option<&str>) -> Result<Rc<Connection>, Error> {
+        self.expire_streams();
+        if let Some(baton) = baton {
+            return self
+                .streams
+                .remove(baton)
+                .map(|(conn, _)| conn)
+                .ok_or_else(|| {
+                    Error::new("stream has expired or does not exist", "STREAM_EXPIRED")
+                });
+        }
+        if let Some(conn) = self.idle.pop() {
+            return Ok(conn);
+        }
+        if self.streams.len() >= self.max_connections {
+            return Err(Error::new("too many open streams", "STREAM_LIMIT"));
+        }
+        Ok(self.db.connect()?)
+    }
+
+    /// Returns the connection of a closed stream to the pool. A connection
+    /// whose transaction was left open is rolled back, or dropped if that fails.
+    fn release(&mut self, conn: Rc<Connection>) {
+        if !conn.get_auto_commit() {
+            if let Err(e) = conn.rollback() {
+                tracing::warn!("failed to roll back abandoned transaction: {}", e);
+                return;
+            }
+        }
+        self.idle.push(conn);
+    }
+
+    /// Keeps `conn` reserved for its stream and returns the stream's new baton.
+    fn keep(&mut self, conn: Rc<Connection>) -> String {
+        let baton = self.new_baton();
+        self.insert(baton.clone(), conn);
+        baton
+    }
+
+    fn insert(&mut self, baton: String, conn: Rc<Connection>) {
+        self.streams.insert(baton, (conn, Instant::now()));
+    }
+
+    fn new_baton(&mut self) -> String {
+        self.next_baton += 1;
+        let mut hasher = self.random.build_hasher();
+        hasher.write_u64(self.next_baton);
+        format!("{:016x}{:016x}", self.next_baton, hasher.finish())
+    }
+
+    fn expire_streams(&mut self) {
+        let timeout = self.stream_timeout;
+        let expired: Vec<String> = self
+            .streams
+            .iter()
+            .filter(|(_, (_, last_used))| last_used.elapsed() > timeout)
+            .map(|(baton, _)| baton.clone())
+            .collect();
+        for baton in expired {
+            if let Some((conn, _)) = self.streams.remove(&baton) {
+                self.release(conn);
+            }
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+fn read_request(reader: &mut impl BufRead) -> io::Result<Request> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let mut header_size = 0;
+    let mut read_line = |line: &mut String| {
+        let limit = MAX_HEADER_SIZE - header_size;
+        let n = Read::take(&mut *reader, limit as u64 + 1).read_line(line)?;
+        if n > limit {
+            return Err(invalid("request headers are too large"));
+        }
+        header_size += n;
+        Ok(n)
+    };
+    let mut line = String::new();
+    read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or_else(|| invalid("missing method"))?;
+    let path = parts.next().ok_or_else(|| invalid("missing path"))?;
+    let (method, path) = (method.to_string(), path.to_string());
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        if read_line(&mut line)? == 0 {
+            break;
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid("invalid Content-Length"))?;
+            }
+        }
+    }
+    if content_length > MAX_BODY_SIZE {
+        return Err(invalid("request body is too large"));
+    }
+    // Grown as the body arrives, so a client announcing a large body doesn't get the
+    // memory for it before sending it.
+    let mut body = Vec::new();
+    reader.take(content_length as u64).read_to_end(&mut body)?;
+    if body.len() < content_length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "request body is shorter than its Content-Length",
+        ));
+    }
+    Ok(Request { method, path, body })
+}
+
+/// Reads a request from a client until a deadline, which bounds the time it takes to send
+/// all of it rather than each read.
+struct DeadlineReader {
+    stream: TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "request was not received in time",
+            ));
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+fn parse_body<'a, T: Deserialize<'a>>(body: &'a [u8]) -> Result<T, Error> {
+    serde_json::from_slice(body).map_err(|e| Error::new(e.to_string(), "PROTOCOL_ERROR"))
+}
+
+fn write_response(
+    stream: &mut impl Write,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+/// Writes a cursor response using chunked transfer encoding, one chunk per
+/// JSON entry.
+struct CursorWriter<W: Write> {
+    inner: W,
+    /// Index of the batch step currently being executed.
+    step: usize,
+}
+
+impl<W: Write> CursorWriter<W> {
+    fn new(mut inner: W) -> io::Result<Self> {
+        write!(
+            inner,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n"
+        )?;
+        Ok(Self { inner, step: 0 })
+    }
+
+    fn send(&mut self, entry: &impl Serialize) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        write!(self.inner, "{:x}\r\n", line.len())?;
+        self.inner.write_all(&line)?;
+        self.inner.write_all(b"\r\n")
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> RowSink for CursorWriter<W> {
+    fn columns(&mut self, cols: Vec<Col>) -> io::Result<()> {
+        self.send(&CursorEntry::StepBegin {
+            step: self.step,
+            cols,
+        })
+    }
+
+    fn row(&mut self, row: Vec<Value>) -> io::Result<()> {
+        self.send(&CursorEntry::Row { row })
+    }
+}
+
+impl<W: Write> BatchSink for CursorWriter<W> {
+    fn step_begin(&mut self, step: usize) -> io::Result<()> {
+        self.step = step;
+        Ok(())
+    }
+
+    fn step_end(&mut self, step: usize, result: Result<StmtEnd, Error>) -> io::Result<()> {
+        match result {
+            Ok(end) => self.send(&CursorEntry::StepEnd {
+                affected_row_count: end.affected_row_count,
+                last_insert_rowid: end.last_insert_rowid,
+            }),
+            Err(error) => self.send(&CursorEntry::StepError { step, error }),
+        }
+    }
+
+    fn step_skipped(&mut self, _step: usize) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl RowSink for StmtResult {
+    fn columns(&mut self, cols: Vec<Col>) -> io::Result<()> {
+        self.cols = cols;
+        Ok(())
+    }
+
+    fn row(&mut self, row: Vec<Value>) -> io::Result<()> {
+        self.rows.push(row);
+        Ok(())
+    }
+}
+
+impl RowSink for BatchResult {
+    fn columns(&mut self, cols: Vec<Col>) -> io::Result<()> {
+        self.current.columns(cols)
+    }
+
+    fn row(&mut self, row: Vec<Value>) -> io::Result<()> {
+        self.current.row(row)
+    }
+}
+
+impl BatchSink for BatchResult {
+    fn step_begin(&mut self, _step: usize) -> io::Result<()> {
+        self.current = StmtResult::default();
+        Ok(())
+    }
+
+    fn step_end(&mut self, _step: usize, result: Result<StmtEnd, Error>) -> io::Result<()> {
+        let mut current = std::mem::take(&mut self.current);
+        match result {
+            Ok(end) => {
+                current.affected_row_count = end.affected_row_count;
+                current.last_insert_rowid = end.last_insert_rowid;
+                self.step_results.push(Some(current));
+                self.step_errors.push(None);
+            }
+            Err(error) => {
+                self.step_results.push(None);
+                self.step_errors.push(Some(error));
+            }
+        }
+        Ok(())
+    }
+
+    fn step_skipped(&mut self, _step: usize) -> io::Result<()> {
+        self.step_results.push(None);
+        self.step_errors.push(None);
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct PipelineReqBody {
+    baton: Option<String>,
+    requests: Vec<StreamRequest>,
+}
+
+#[derive(Serialize)]
+struct PipelineRespBody {
+    baton: Option<String>,
+    base_url: Option<String>,
+    results: Vec<StreamResult>,
+}
+
+#[derive(Deserialize)]
+struct CursorReqBody {
+    baton: Option<String>,
+    batch: Batch,
+}
+
+#[derive(Serialize)]
+struct CursorRespBody {
+    baton: Option<String>,
+    base_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamRequest {
+    Close,
+    Execute {
+        stmt: Stmt,
+    },
+    Batch {
+        batch: Batch,
+    },
+    Sequence {
+        sql: String,
+    },
+    GetAutocommit,
+    #[serde(other)]
+    Unsupported,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamResult {
+    Ok { response: StreamResponse },
+    Error { error: Error },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamResponse {
+    Close,
+    Execute { result: StmtResult },
+    Batch { result: BatchResult },
+    Sequence,
+    GetAutocommit { is_autocommit: bool },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum CursorEntry {
+    StepBegin {
+        step: usize,
+        cols: Vec<Col>,
+    },
+    StepEnd {
+        affected_row_count: u64,
+        last_insert_rowid: Option<String>,
+    },
+    StepError {
+        step: usize,
+        error: Error,
+    },
+    Row {
+        row: Vec<Value>,
+    },
+}
+
+#[derive(Deserialize)]
+struct Stmt {
+    sql: String,
+    #[serde(default)]
+    args: Vec<Value>,
+    #[serde(default)]
+    named_args: Vec<NamedArg>,
+    #[serde(default = "want_rows_default")]
+    want_rows: bool,
+}
+
+fn want_rows_default() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+struct NamedArg {
+    name: String,
+    value: Value,
+}
+
+#[derive(Deserialize)]
+struct Batch {
+    steps: Vec<BatchStep>,
+}
+
+#[derive(Deserialize)]
+struct BatchStep {
+    condition: Option<BatchCond>,
+    stmt: Stmt,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BatchCond {
+    Ok { step: usize },
+    Error { step: usize },
+    Not { cond: Box<BatchCond> },
+    And { conds: Vec<BatchCond> },
+    Or { conds: Vec<BatchCond> },
+    IsAutocommit,
+}
+
+impl BatchCond {
+    fn eval(&self, outcomes: &[Option<bool>], conn: &Connection) -> bool {
+        match self {
+            BatchCond::Ok { step } => outcomes.get(*step) == Some(&Some(true)),
+            BatchCond::Error { step } => outcomes.get(*step) == Some(&Some(false)),
+            BatchCond::Not { cond } => !cond.eval(outcomes, conn),
+            BatchCond::And { conds } => conds.iter().all(|c| c.eval(outcomes, conn)),
+            BatchCond::Or { conds } => conds.iter().any(|c| c.eval(outcomes, conn)),
+            BatchCond::IsAutocommit => conn.get_auto_commit(),
+        }
+    }
+}
+
+#[derive(Serialize, Default)]
+struct StmtResult {
+    cols: Vec<Col>,
+    rows: Vec<Vec<Value>>,
+    affected_row_count: u64,
+    last_insert_rowid: Option<String>,
+}
+
+#[derive(Serialize, Default)]
+struct BatchResult {
+    step_results: Vec<Option<StmtResult>>,
+    step_errors: Vec<Option<Error>>,
+    #[serde(skip)]
+    current: StmtResult,
+}
+
+#[derive(Serialize)]
+struct Col {
+    name: Option<String>,
+    decltype: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Value {
+    Null,
+    /// Integers are sent as strings so that JSON parsers don't lose precision.
+    Integer {
+        value: String,
+    },
+    Float {
+        value: f64,
+    },
+    Text {
+        value: String,
+    },
+    Blob {
+        base64: String,
+    },
+}
+
+impl Value {
+    fn to_owned_value(&self) -> Result<OwnedValue, Error> {
+        let invalid = |msg: String| Error::new(msg, "ARGS_INVALID");
+        Ok(match self {
+            Value::Null => OwnedValue::Null,
+            Value::Integer { value } => OwnedValue::Integer(
+                value
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid integer: {value}")))?,
+            ),
+            Value::Float { value } => OwnedValue::Float(*value),
+            Value::Text { value } => OwnedValue::build_text(value),
+            Value::Blob { base64 } => OwnedValue::from_blob(
+                BASE64
+                    .decode(base64)
+                    .map_err(|e| invalid(format!("invalid base64 blob: {e}")))?,
+            ),
+        })
+    }
+}
+
+impl From<&OwnedValue> for Value {
+    fn from(value: &OwnedValue) -> Self {
+        match value {
+            OwnedValue::Null => Value::Null,
+            OwnedValue::Integer(i) => Value::Integer {
+                value: i.to_string(),
+            },
+            OwnedValue::Float(f) => Value::Float { value: *f },
+            OwnedValue::Text(text) => Value::Text {
+                value: text.as_str().to_string(),
+            },
+            OwnedValue::Blob(blob) => Value::Blob {
+                base64: BASE64.encode(blob),
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Error {
+    message: String,
+    code: String,
+}
+
+impl Error {
+    fn new(message: impl Into<String>, code: &str) -> Self {
+        Self {
+            message: message.into(),
+            code: code.to_string(),
+        }
+    }
+}
+
+impl From<LimboError> for Error {
+    fn from(e: LimboError) -> Self {
+        let code = match e.code() {
+            SQLITE_BUSY => "SQLITE_BUSY",
+            SQLITE_LOCKED => "SQLITE_LOCKED",
+            SQLITE_IOERR => "SQLITE_IOERR",
+            SQLITE_CONSTRAINT => "SQLITE_CONSTRAINT",
+            SQLITE_CORRUPT => "SQLITE_CORRUPT",
+            SQLITE_NOTADB => "SQLITE_NOTADB",
+            SQLITE_RANGE => "SQLITE_RANGE",
+            _ => "SQLITE_ERROR",
+        };
+        Error::new(e.to_string(), code)
+    }
+}
@@ -180,9 +180,54 @@ fn bench_execute_select_1(criterion: &mut Criterion) {
     group.finish();
 }
 
+fn bench_execute_sorted_rows(criterion: &mut Criterion) {
+    #[allow(clippy::arc_with_non_send_sync)]
+    let io = Arc::new(PlatformIO::new().unwrap());
+    let db = Database::open_file(io.clone(), "../testing/testing.db", false).unwrap();
+    let limbo_conn = db.connect().unwrap();
+
+    let query = "SELECT first_name, last_name FROM users ORDER BY last_name LIMIT 10";
+    let mut group = criterion.benchmark_group(format!("Execute `{}`", query));
+
+    group.bench_function("limbo_execute_sorted_rows", |b| {
+        let mut stmt = limbo_conn.prepare(query).unwrap();
+        let io = io.clone();
+        let run = move |stmt: &mut limbo_core::Statement| {
+            loop {
+                match stmt.step().unwrap() {
+                    limbo_core::StepResult::Row => {
+                        black_box(stmt.row());
+                    }
+                    limbo_core::StepResult::IO => {
+                        let _ = io.run_once();
+                    }
+                    limbo_core::StepResult::Done => {
+                        break;
+                    }
+                    limbo_core::StepResult::Interrupt | limbo_core::StepResult::Busy => {
+                        unreachable!();
+                    }
+                }
+            }
+            stmt.reset();
+        };
+        run(&mut stmt);
+        let warm = stmt.allocation_stats();
+        b.iter(|| run(&mut stmt));
+        // Once warm, the records of an execution fit in the buffers of the previous ones.
+        let stats = stmt.allocation_stats();
+        assert_eq!(
+            stats.allocations, warm.allocations,
+            "records allocated after warming up: {stats:?}"
+        );
+    });
+
+    group.finish();
+}
+
 criterion_group! {
     name = benches;
     config = Criterion::default().with_profiler(PProfProfiler::new(100, Output::Flamegraph(None)));
-    targets = bench_prepare_query, bench_execute_select_1, bench_execute_select_rows
+    targets = bench_prepare_query, bench_execute_select_1, bench_execute_select_rows, bench_execute_sorted_rows
 }
 criterion_main!(benches);
@@ -0,0 +1,716 @@
+//! Change data capture: the row changes of every committed write transaction, handed to
+//! subscribers in commit order.
+//!
+//! Changes are captured while the VDBE writes rows to table b-trees, where the table and the
+//! row being replaced are still at hand, instead of being reconstructed from WAL frames later.
+//! They are buffered per connection and only published once the transaction's commit frame is
+//! in the WAL, so subscribers never see rows of failed statements or uncommitted transactions.
+//!
+//! The other direction, applying changes captured elsewhere, identifies rows by their rowid, so
+//! it only works on tables with an `INTEGER PRIMARY KEY`, the one case where the rowid is part of
+//! the row rather than an accident of the database it was written to. Changesets of SQLite's
+//! session extension, which identify rows by their primary key, are applied the same way.
+//!
+//! Each change set has a [CommitToken], which a writer can hand to its readers so they can wait
+//! for a replica to catch up with its writes before reading from it. A replica commits the
+//! changes it applies under the token of their change set and keeps the last one in the
+//! `limbo_replication` table, so it knows how far it got once reopened.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use fallible_streaming_iterator::FallibleStreamingIterator;
+use parking_lot::{Condvar, Mutex};
+
+use crate::schema::BTreeTable;
+use crate::storage::sqlite3_ondisk::read_varint;
+use crate::types::{ImmutableRecord, OwnedValue};
+use crate::{Connection, LimboError, Result, Statement};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOp {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// One row written by a committed transaction. `old` is set for updates and deletes, `new`
+/// for inserts and updates. Values are in the table's column order, with the rowid standing in
+/// for an `INTEGER PRIMARY KEY` column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowChange {
+    pub table: String,
+    pub op: ChangeOp,
+    pub rowid: i64,
+    pub old: Option<Vec<OwnedValue>>,
+    pub new: Option<Vec<OwnedValue>>,
+}
+
+/// The changes of one committed transaction, in the order they were made.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeSet {
+    pub token: CommitToken,
+    pub changes: Vec<RowChange>,
+}
+
+/// Identifies a committed transaction of a database and the ones before it. Tokens of later
+/// commits are greater, also across restarts: a token is the time of the commit in microseconds
+/// since the Unix epoch, or one more than the previous token if the clock went back.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CommitToken(pub u64);
+
+/// The name of the table keeping the token of the last change set a replica applied.
+pub const REPLICATION_TABLE_NAME: &str = "limbo_replication";
+
+/// Receives a [ChangeSet] for each transaction committed after subscribing. Dropping it ends
+/// the subscription.
+pub type ChangeReceiver = Receiver<Arc<ChangeSet>>;
+
+#[derive(Default)]
+pub(crate) struct ChangeSubscribers {
+    senders: Mutex<Vec<Sender<Arc<ChangeSet>>>>,
+    /// Number of senders, so writers can skip capturing without taking the lock.
+    count: AtomicUsize,
+    /// The token of the newest commit, made or applied.
+    last_token: Mutex<CommitToken>,
+    token_advanced: Condvar,
+}
+
+impl ChangeSubscribers {
+    pub(crate) fn subscribe(&self) -> ChangeReceiver {
+        let (sender, receiver) = mpsc::channel();
+        let mut senders = self.senders.lock();
+        senders.push(sender);
+        self.count.store(senders.len(), Ordering::Release);
+        receiver
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.count.load(Ordering::Acquire) == 0
+    }
+
+    /// Publishes the changes of a commit under the next token, or under `replicated` for
+    /// changes applied from another database. `now` is the time in microseconds.
+    pub(crate) fn publish(
+        &self,
+        changes: Vec<RowChange>,
+        replicated: Option<CommitToken>,
+        now: u64,
+    ) -> Arc<ChangeSet> {
+        // Tokens are handed out under the lock, so subscribers receive them in order.
+        let mut senders = self.senders.lock();
+        let token = replicated.unwrap_or_else(|| {
+            let last = self.last_token.lock().0;
+            CommitToken(now.max(last + 1))
+        });
+        self.advance(token);
+        let change_set = Arc::new(ChangeSet { token, changes });
+        senders.retain(|sender| sender.send(change_set.clone()).is_ok());
+        self.count.store(senders.len(), Ordering::Release);
+        change_set
+    }
+
+    pub(crate) fn advance(&self, token: CommitToken) {
+        let mut last = self.last_token.lock();
+        if token > *last {
+            *last = token;
+            self.token_advanced.notify_all();
+        }
+    }
+
+    /// Waits until the commit of `token` is made or applied, returning whether it was.
+    pub(crate) fn wait_for(&self, token: CommitToken, timeout: Duration) -> bool {
+        let deadline = Instant::now().checked_add(timeout);
+        let mut last = self.last_token.lock();
+        while *last < token {
+            match deadline {
+                Some(deadline) => {
+                    if self
+                        .token_advanced
+                        .wait_until(&mut last, deadline)
+                        .timed_out()
+                    {
+                        return *last >= token;
+                    }
+                }
+                None => self.token_advanced.wait(&mut last),
+            }
+        }
+        true
+    }
+}
+
+/// Picks up where a replica got to before it was closed.
+pub(crate) fn load(conn: &Rc<Connection>) -> Result<()> {
+    if conn
+        .schema
+        .read()
+        .get_btree_table(REPLICATION_TABLE_NAME)
+        .is_none()
+    {
+        return Ok(());
+    }
+    let mut stmt = conn.prepare(format!("SELECT token FROM {REPLICATION_TABLE_NAME}"))?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        if let Some(OwnedValue::Integer(token)) = row.get_values().next() {
+            conn._db
+                .change_subscribers
+                .advance(CommitToken(*token as u64));
+        }
+    }
+    Ok(())
+}
+
+/// The captured changes of a connection's open transaction.
+#[derive(Default)]
+pub(crate) struct ChangeBuffer {
+    changes: Vec<RowChange>,
+    /// Number of changes made before the current statement, which stay when it fails.
+    statement_start: usize,
+}
+
+impl ChangeBuffer {
+    pub(crate) fn push(&mut self, change: RowChange) {
+        self.changes.push(change);
+    }
+
+    pub(crate) fn begin_statement(&mut self) {
+        self.statement_start = self.changes.len();
+    }
+
+    /// Keeps the changes of the statement that completed when a later statement fails,
+    /// including one that doesn't begin a statement because it writes nothing itself.
+    pub(crate) fn end_statement(&mut self) {
+        self.statement_start = self.changes.len();
+    }
+
+    pub(crate) fn rollback_statement(&mut self) {
+        self.changes.truncate(self.statement_start);
+    }
+
+    /// Number of changes captured so far, to [Self::truncate] to when a savepoint is rolled
+    /// back.
+    pub(crate) fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.changes.truncate(len);
+        self.statement_start = self.statement_start.min(len);
+    }
+
+    pub(crate) fn take(&mut self) -> Vec<RowChange> {
+        self.statement_start = 0;
+        std::mem::take(&mut self.changes)
+    }
+
+    /// Puts back `changes` taken with [Self::take], before the ones captured since.
+    pub(crate) fn put_back(&mut self, mut changes: Vec<RowChange>) {
+        changes.append(&mut self.changes);
+        self.changes = changes;
+        self.statement_start = self.changes.len();
+    }
+}
+
+/// Whether rows written to `table` are reported. Transient tables, SQLite's own tables, such as
+/// the schema table, and the token of a replica are not.
+pub(crate) fn captures_table(table: &BTreeTable) -> bool {
+    !table.ephemeral && !table.name.starts_with("sqlite_") && table.name != REPLICATION_TABLE_NAME
+}
+
+/// Decodes a table row, filling in the rowid alias column that the record stores as NULL.
+pub(crate) fn row_values(
+    table: &BTreeTable,
+    rowid: i64,
+    record: &ImmutableRecord,
+) -> Vec<OwnedValue> {
+    table
+        .columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            if column.is_rowid_alias {
+                OwnedValue::Integer(rowid)
+            } else {
+                record
+                    .get_value_opt(i)
+                    .map_or(OwnedValue::Null, |value| value.to_owned())
+            }
+        })
+        .collect()
+}
+
+/// What to do with a change that doesn't fit the database it is applied to: an insert of a row
+/// that exists, or an update or delete of a row that is missing or differs from the change's
+/// old values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Fail, undoing the changes applied before.
+    Abort,
+    /// Skip the change.
+    Ignore,
+    /// Let the change win: an insert or update overwrites the row and a delete removes it.
+    /// Changes to missing rows are skipped, there being nothing to replace.
+    Replace,
+}
+
+/// A change to apply, with `None` for the values a changeset leaves out.
+pub(crate) struct Change {
+    table: String,
+    op: ChangeOp,
+    rowid: i64,
+    /// The column identifying the row in the changeset, which must be the rowid alias.
+    key_column: Option<usize>,
+    old: Option<Vec<Option<OwnedValue>>>,
+    new: Option<Vec<Option<OwnedValue>>>,
+}
+
+impl From<&RowChange> for Change {
+    fn from(change: &RowChange) -> Self {
+        let values = |values: &Option<Vec<OwnedValue>>| {
+            values
+                .as_ref()
+                .map(|values| values.iter().cloned().map(Some).collect())
+        };
+        Self {
+            table: change.table.clone(),
+            op: change.op,
+            rowid: change.rowid,
+            key_column: None,
+            old: values(&change.old),
+            new: values(&change.new),
+        }
+    }
+}
+
+/// Applies `changes` in one transaction, which is rolled back if any of them fails. The
+/// transaction is committed under `token`, if the changes have one.
+pub(crate) fn apply_changes(
+    conn: &Rc<Connection>,
+    changes: impl IntoIterator<Item = Change>,
+    policy: ConflictPolicy,
+    token: Option<CommitToken>,
+) -> Result<()> {
+    if !conn.get_auto_commit() {
+        return Err(LimboError::TxError(
+            "cannot apply changes within a transaction".to_string(),
+        ));
+    }
+    // Replicated changes apply to rows the row filter may hide.
+    let _suspended = conn.suspend_row_filter();
+    if token.is_some()
+        && conn
+            .schema
+            .read()
+            .get_btree_table(REPLICATION_TABLE_NAME)
+            .is_none()
+    {
+        conn.execute(format!(
+            "CREATE TABLE IF NOT EXISTS {REPLICATION_TABLE_NAME}(token INTEGER)"
+        ))?;
+    }
+    conn.execute("BEGIN IMMEDIATE")?;
+    let header = conn.pager.db_header.lock().clone();
+    let mut applier = Applier {
+        conn,
+        policy,
+        statements: HashMap::new(),
+    };
+    let result = changes
+        .into_iter()
+        .try_for_each(|change| applier.apply(&change))
+        .and_then(|_| match token {
+            Some(token) => applier.record_token(token),
+            None => Ok(()),
+        });
+    drop(applier);
+    conn.replicated_token.set(token);
+    let result = result.and_then(|_| conn.execute("COMMIT"));
+    conn.replicated_token.set(None);
+    match result {
+        Ok(()) => {
+            // The changes may all have been skipped, leaving nothing to publish.
+            if let Some(token) = token {
+                conn._db.change_subscribers.advance(token);
+            }
+            Ok(())
+        }
+        Err(err) => {
+            conn.rollback_write_tx(header)?;
+            Err(err)
+        }
+    }
+}
+
+struct Applier<'a> {
+    conn: &'a Rc<Connection>,
+    policy: ConflictPolicy,
+    /// Prepared statements by SQL, as a batch tends to repeat the same few.
+    statements: HashMap<String, Statement>,
+}
+
+impl Applier<'_> {
+    fn apply(&mut self, change: &Change) -> Result<()> {
+        let table = self
+            .conn
+            .schema
+            .try_read()
+            .ok_or(LimboError::SchemaLocked)?
+            .get_btree_table(&change.table)
+            .ok_or_else(|| {
+                LimboError::InvalidArgument(format!("no such table: {}", change.table))
+            })?;
+        let key_column = table
+            .columns
+            .iter()
+            .position(|column| column.is_rowid_alias)
+            .filter(|key_column| change.key_column.map_or(true, |c| c == *key_column))
+            .ok_or_else(|| {
+                LimboError::InvalidArgument(format!(
+                    "cannot apply changes to {}: rows must be identified by an INTEGER PRIMARY KEY",
+                    table.name
+                ))
+            })?;
+        for values in [&change.old, &change.new].into_iter().flatten() {
+            if values.len() != table.columns.len() {
+                return Err(LimboError::InvalidArgument(format!(
+                    "change to {} has {} columns but the table has {}",
+                    table.name,
+                    values.len(),
+                    table.columns.len()
+                )));
+            }
+        }
+        let columns: Vec<String> = table
+            .columns
+            .iter()
+            .map(|column| quote_ident(column.name.as_deref().unwrap_or_default()))
+            .collect();
+        let table_name = quote_ident(&table.name);
+        let key = &columns[key_column];
+
+        let current = self.run(
+            format!(
+                "SELECT {} FROM {table_name} WHERE {key} = ?1",
+                columns.join(", ")
+            ),
+            vec![OwnedValue::Integer(change.rowid)],
+        )?;
+        let exists = match (change.op, current) {
+            (ChangeOp::Insert, None) => false,
+            (ChangeOp::Update | ChangeOp::Delete, Some(current))
+                if change
+                    .old
+                    .as_ref()
+                    .map_or(true, |old| same_row(old, &current)) =>
+            {
+                true
+            }
+            (ChangeOp::Update | ChangeOp::Delete, None)
+                if self.policy == ConflictPolicy::Replace =>
+            {
+                return Ok(());
+            }
+            (_, current) => match self.policy {
+                ConflictPolicy::Abort => {
+                    return Err(LimboError::Constraint(format!(
+                        "change conflicts with {} row {} of {}",
+                        if current.is_some() {
+                            "existing"
+                        } else {
+                            "missing"
+                        },
+                        change.rowid,
+                        table.name
+                    )))
+                }
+                ConflictPolicy::Ignore => return Ok(()),
+                ConflictPolicy::Replace => true,
+            },
+        };
+
+        let new = change.new.as_deref().unwrap_or_default();
+        if change.op == ChangeOp::Delete {
+            self.run(
+                format!("DELETE FROM {table_name} WHERE {key} = ?1"),
+                vec![OwnedValue::Integer(change.rowid)],
+            )?;
+        } else if exists {
+            // Only the columns the change has values for are set; the key stays as it is.
+            // Parameters are numbered, as UPDATE doesn't number them in the order they appear.
+            let mut params = vec![OwnedValue::Integer(change.rowid)];
+            let mut assignments = Vec::new();
+            for (i, value) in new.iter().enumerate() {
+                if let (Some(value), false) = (value, i == key_column) {
+                    params.push(value.clone());
+                    assignments.push(format!("{} = ?{}", columns[i], params.len()));
+                }
+            }
+            if assignments.is_empty() {
+                return Ok(());
+            }
+            self.run(
+                format!(
+                    "UPDATE {table_name} SET {} WHERE {key} = ?1",
+                    assignments.join(", ")
+                ),
+                params,
+            )?;
+        } else {
+            let params = new
+                .iter()
+                .enumerate()
+                .map(|(i, value)| match value {
+                    _ if i == key_column => OwnedValue::Integer(change.rowid),
+                    Some(value) => value.clone(),
+                    None => OwnedValue::Null,
+                })
+                .collect();
+            self.run(
+                format!(
+                    "INSERT INTO {table_name} ({}) VALUES ({})",
+                    columns.join(", "),
+                    (1..=columns.len())
+                        .map(|i| format!("?{i}"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                params,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn record_token(&mut self, token: CommitToken) -> Result<()> {
+        self.run(format!("DELETE FROM {REPLICATION_TABLE_NAME}"), Vec::new())?;
+        self.run(
+            format!("INSERT INTO {REPLICATION_TABLE_NAME} VALUES (?1)"),
+            vec![OwnedValue::Integer(token.0 as i64)],
+        )?;
+        Ok(())
+    }
+
+    /// Runs `sql` to completion, returning its first row.
+    fn run(&mut self, sql: String, params: Vec<OwnedValue>) -> Result<Option<Vec<OwnedValue>>> {
+        let stmt = match self.statements.entry(sql) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                let stmt = self.conn.prepare(entry.key())?;
+                entry.insert(stmt)
+            }
+        };
+        let mut rows = stmt.query(params)?;
+        let first = rows
+            .next()?
+            .map(|row| row.get_values().cloned().collect::<Vec<_>>());
+        while rows.next()?.is_some() {}
+        Ok(first)
+    }
+}
+
+/// Whether `current` has the values of `old`, leaving out the ones `old` doesn't have.
+fn same_row(old: &[Option<OwnedValue>], current: &[OwnedValue]) -> bool {
+    old.iter()
+        .zip(current)
+        .all(|(old, current)| old.as_ref().map_or(true, |old| old == current))
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+// Tags of the changeset format of SQLite's session extension, see
+// https://www.sqlite.org/sessionintro.html.
+const CHANGESET_TABLE: u8 = b'T';
+const CHANGESET_PATCHSET_TABLE: u8 = b'P';
+const CHANGESET_INSERT: u8 = 18;
+const CHANGESET_DELETE: u8 = 9;
+const CHANGESET_UPDATE: u8 = 23;
+
+/// Decodes a changeset produced by `sqlite3session_changeset()`.
+pub(crate) fn parse_changeset(mut buf: &[u8]) -> Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    // The table the following changes belong to, with the number of its columns and the
+    // primary key column.
+    let mut table: Option<(String, usize, Option<usize>)> = None;
+    while let Some((&tag, rest)) = buf.split_first() {
+        buf = rest;
+        let op = match tag {
+            CHANGESET_TABLE => {
+                let (column_count, n) = read_varint(buf)?;
+                let column_count = column_count as usize;
+                buf = &buf[n..];
+                let primary_key = take(&mut buf, column_count)?;
+                let mut key_columns = (0..column_count).filter(|i| primary_key[*i] != 0);
+                let key_column = match (key_columns.next(), key_columns.next()) {
+                    (Some(key_column), None) => Some(key_column),
+                    _ => None,
+                };
+                let name_len = buf
+                    .iter()
+                    .position(|b| *b == 0)
+                    .ok_or_else(|| changeset_error("unterminated table name"))?;
+                let name = String::from_utf8_lossy(&buf[..name_len]).into_owned();
+                buf = &buf[name_len + 1..];
+                table = Some((name, column_count, key_column));
+                continue;
+            }
+            CHANGESET_PATCHSET_TABLE => {
+                return Err(LimboError::InvalidArgument(
+                    "patchsets are not supported".to_string(),
+                ))
+            }
+            CHANGESET_INSERT => ChangeOp::Insert,
+            CHANGESET_DELETE => ChangeOp::Delete,
+            CHANGESET_UPDATE => ChangeOp::Update,
+            _ => return Err(changeset_error(&format!("unknown change type {tag}"))),
+        };
+        let (name, column_count, key_column) = table
+            .as_ref()
+            .ok_or_else(|| changeset_error("change before a table"))?;
+        // The flag telling whether the change was made by a trigger doesn't matter here.
+        take(&mut buf, 1)?;
+        let old = match op {
+            ChangeOp::Insert => None,
+            _ => Some(read_changeset_values(&mut buf, *column_count)?),
+        };
+        let new = match op {
+            ChangeOp::Delete => None,
+            _ => Some(read_changeset_values(&mut buf, *column_count)?),
+        };
+        let Some(key_column) = key_column else {
+            return Err(LimboError::InvalidArgument(format!(
+                "cannot apply changes to {name}: rows must be identified by an INTEGER PRIMARY KEY"
+            )));
+        };
+        // Updates and deletes have the key among their old values, inserts among their new ones.
+        let rowid = match [&old, &new]
+            .into_iter()
+            .flatten()
+            .find_map(|values| values[*key_column].as_ref())
+        {
+            Some(OwnedValue::Integer(rowid)) => *rowid,
+            _ => {
+                return Err(LimboError::InvalidArgument(format!(
+                    "change to {name} has no integer primary key"
+                )))
+            }
+        };
+        changes.push(Change {
+            table: name.clone(),
+            op,
+            rowid,
+            key_column: Some(*key_column),
+            old,
+            new,
+        });
+    }
+    Ok(changes)
+}
+
+fn read_changeset_values(buf: &mut &[u8], count: usize) -> Result<Vec<Option<OwnedValue>>> {
+    (0..count)
+        .map(|_| {
+            let tag = take(buf, 1)?[0];
+            Ok(match tag {
+                0 => None,
+                1 => Some(OwnedValue::Integer(i64::from_be_bytes(
+                    take(buf, 8)?.try_into().unwrap(),
+                ))),
+                2 => Some(OwnedValue::Float(f64::from_be_bytes(
+                    take(buf, 8)?.try_into().unwrap(),
+                ))),
+                3 | 4 => {
+                    let (len, n) = read_varint(buf)?;
+                    *buf = &buf[n..];
+                    let data = take(buf, len as usize)?;
+                    Some(if tag == 3 {
+                        OwnedValue::build_text(&String::from_utf8_lossy(data))
+                    } else {
+                        OwnedValue::from_blob(data.to_vec())
+                    })
+                }
+                5 => Some(OwnedValue::Null),
+                _ => return Err(changeset_error(&format!("unknown value type {tag}"))),
+            })
+        })
+        .collect()
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if buf.len() < len {
+        return Err(changeset_error("truncated"));
+    }
+    let (taken, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(taken)
+}
+
+fn changeset_error(message: &str) -> LimboError {
+    LimboError::InvalidArgument(format!("malformed changeset: {message}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Database, MemoryIO, IO};
+
+    #[test]
+    fn test_apply_session_changeset() {
+        let io: Arc<dyn IO> = Arc::new(MemoryIO::new());
+        let db = Database::open_file(io, ":memory:", false).unwrap();
+        let conn = db.connect().unwrap();
+        conn.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, x)")
+            .unwrap();
+        conn.execute("INSERT INTO t VALUES (2, 'b')").unwrap();
+
+        // Table t with two columns, the first being the primary key.
+        let mut changeset = vec![CHANGESET_TABLE, 2, 1, 0, b't', 0];
+        // INSERT (1, 'a')
+        changeset.extend([CHANGESET_INSERT, 0, 1]);
+        changeset.extend(1i64.to_be_bytes());
+        changeset.extend([3, 1, b'a']);
+        // UPDATE t SET x = 2.5 WHERE id = 2, leaving out the new key.
+        changeset.extend([CHANGESET_UPDATE, 0, 1]);
+        changeset.extend(2i64.to_be_bytes());
+        changeset.extend([3, 1, b'b', 0, 2]);
+        changeset.extend(2.5f64.to_be_bytes());
+        conn.apply_changeset(&changeset, ConflictPolicy::Abort)
+            .unwrap();
+
+        let mut stmt = conn.prepare("SELECT id, x FROM t").unwrap();
+        let mut rows = stmt.query([]).unwrap();
+        let mut values = Vec::new();
+        while let Some(row) = rows.next().unwrap() {
+            values.extend(row.get_values().cloned());
+        }
+        assert_eq!(
+            values,
+            vec![
+                OwnedValue::Integer(1),
+                OwnedValue::build_text("a"),
+                OwnedValue::Integer(2),
+                OwnedValue::Float(2.5),
+            ]
+        );
+
+        // DELETE of a row whose old values differ.
+        let mut changeset = vec![CHANGESET_TABLE, 2, 1, 0, b't', 0, CHANGESET_DELETE, 0, 1];
+        changeset.extend(1i64.to_be_bytes());
+        changeset.extend([5]);
+        assert!(conn
+            .apply_changeset(&changeset, ConflictPolicy::Abort)
+            .is_err());
+        assert!(conn
+            .apply_changeset(&changeset[..10], ConflictPolicy::Abort)
+            .is_err());
+        conn.apply_changeset(&changeset, ConflictPolicy::Replace)
+            .unwrap();
+        assert_eq!(stmt.query([]).unwrap().count().unwrap(), 1);
+    }
+}
@@ -0,0 +1,93 @@
+//! Checkpoints taken off the write path, see [crate::Database::defer_checkpoints].
+//!
+//! While checkpoints are deferred, commits no longer checkpoint when the WAL reaches its
+//! threshold, so the I/O of copying frames into the database file doesn't add to their latency.
+//! The owner of the database calls [crate::Database::checkpoint_if_needed] instead, from its own
+//! thread or event loop, whenever it has time for it. The database's IO must not be used from two
+//! threads at once, so there is no thread of our own doing this.
+
+use std::sync::Arc;
+
+use crate::storage::wal::{CheckpointMode, CheckpointResult};
+use crate::{Database, Result};
+
+/// When [crate::Database::checkpoint_if_needed] checkpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeferredCheckpoint {
+    /// Frames in the WAL from which it is checkpointed. These checkpoints are passive, so they
+    /// never wait for readers or writers.
+    pub min_frames: u64,
+    /// Frames in the WAL from which the checkpoint also truncates the WAL file, if no reader
+    /// needs its frames anymore. 0 to never truncate it.
+    pub truncate_frames: u64,
+}
+
+impl Default for DeferredCheckpoint {
+    fn default() -> Self {
+        Self {
+            min_frames: 1000,
+            truncate_frames: 0,
+        }
+    }
+}
+
+pub(crate) fn checkpoint_if_needed(
+    db: &Arc<Database>,
+    config: &DeferredCheckpoint,
+) -> Result<Option<CheckpointResult>> {
+    let frames = db.wal_frames();
+    if frames == 0 || frames < config.min_frames || db.retains_history() {
+        return Ok(None);
+    }
+    let mode = if config.truncate_frames > 0 && frames >= config.truncate_frames {
+        CheckpointMode::Truncate
+    } else {
+        CheckpointMode::Passive
+    };
+    let conn = db.connect()?;
+    let result = conn.checkpoint_with_mode(mode)?;
+    tracing::debug!(
+        "deferred checkpoint: {} of {} frames, busy={}",
+        result.num_checkpointed_frames,
+        result.num_wal_frames,
+        result.busy
+    );
+    Ok(Some(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemoryIO, IO};
+
+    #[test]
+    fn test_deferred_checkpoint() {
+        let io: Arc<dyn IO> = Arc::new(MemoryIO::new());
+        let db = Database::open_file(io, ":memory:", false).unwrap();
+        db.defer_checkpoints(DeferredCheckpoint::default());
+        let conn = db.connect().unwrap();
+        conn.execute("CREATE TABLE t (x)").unwrap();
+        for i in 0..1500 {
+            conn.execute(format!("INSERT INTO t VALUES ({i})")).unwrap();
+        }
+        // Commits left the WAL past the threshold they would have checkpointed at.
+        assert!(db.wal_frames() > 1000);
+
+        db.defer_checkpoints(DeferredCheckpoint {
+            min_frames: 1,
+            truncate_frames: 1,
+        });
+        assert!(db.checkpoint_if_needed().unwrap().is_some());
+        assert_eq!(db.wal_frames(), 0);
+        assert!(db.checkpoint_if_needed().unwrap().is_none());
+        db.stop_deferring_checkpoints();
+        assert!(db.checkpoint_if_needed().unwrap().is_none());
+
+        let mut stmt = conn.prepare("SELECT count(*) FROM t").unwrap();
+        let mut rows = stmt.query([]).unwrap();
+        let row = crate::FallibleStreamingIterator::next(&mut rows)
+            .unwrap()
+            .unwrap();
+        assert_eq!(row.get::<i64>(0).unwrap(), 1500);
+    }
+}
@@ -1,3 +1,4 @@
+use std::fmt;
 use std::num::NonZero;
 
 use thiserror::Error;
@@ -23,11 +24,22 @@ pub enum LimboError {
     TxError(String),
     #[error("I/O error: {0}")]
     IOError(#[from] std::io::Error),
+    #[error("I/O error: {op} failed: {source}")]
+    FileIOError {
+        op: FileOperation,
+        source: std::io::Error,
+    },
     #[cfg(all(target_os = "linux", feature = "io_uring"))]
     #[error("I/O error: {0}")]
     UringIOError(String),
     #[error("Locking error: {0}")]
     LockingError(String),
+    #[error("database is locked")]
+    Busy,
+    /// Another connection committed since the read transaction being upgraded to a write
+    /// transaction started, so it would write over changes it didn't see.
+    #[error("database is locked")]
+    BusySnapshot,
     #[cfg(target_family = "unix")]
     #[error("I/O error: {0}")]
     RustixIOError(#[from] rustix::io::Errno),
@@ -47,6 +59,10 @@ pub enum LimboError {
     InvalidFormatter(String),
     #[error("Runtime error: {0}")]
     Constraint(String),
+    /// A constraint of the schema failed, `code` being the extended result code of its kind,
+    /// e.g. [SQLITE_CONSTRAINT_UNIQUE].
+    #[error("Runtime error: {message} ({})", .code & 0xff)]
+    ConstraintViolation { code: usize, message: String },
     #[error("Extension error: {0}")]
     ExtensionError(String),
     #[error("Unbound parameter at index {0}")]
@@ -55,6 +71,9 @@ pub enum LimboError {
     IntegerOverflow,
     #[error("Schema is locked for write")]
     SchemaLocked,
+    /// The statement went over one of its [crate::ExecutionLimits] and was aborted.
+    #[error("Query aborted: {0} limit exceeded")]
+    LimitExceeded(ExecutionLimit),
 }
 
 #[macro_export]
@@ -78,11 +97,160 @@ macro_rules! bail_constraint_error {
     };
 }
 
+impl LimboError {
+    pub(crate) fn file_io(op: FileOperation, err: impl Into<std::io::Error>) -> Self {
+        LimboError::FileIOError {
+            op,
+            source: err.into(),
+        }
+    }
+
+    /// The primary result code SQLite reports for the error, e.g. [SQLITE_CONSTRAINT].
+    pub fn code(&self) -> usize {
+        self.extended_code() & 0xff
+    }
+
+    /// The extended result code SQLite reports for the error, e.g. [SQLITE_CONSTRAINT_UNIQUE].
+    /// Errors without an extended code of their own report their primary code.
+    pub fn extended_code(&self) -> usize {
+        match self {
+            LimboError::Corrupt(_) => SQLITE_CORRUPT,
+            LimboError::NotADB => SQLITE_NOTADB,
+            LimboError::InternalError(_) => SQLITE_INTERNAL,
+            LimboError::IOError(_) => SQLITE_IOERR,
+            #[cfg(all(target_os = "linux", feature = "io_uring"))]
+            LimboError::UringIOError(_) => SQLITE_IOERR,
+            #[cfg(target_family = "unix")]
+            LimboError::RustixIOError(_) => SQLITE_IOERR,
+            LimboError::FileIOError { op, .. } => op.extended_code(),
+            LimboError::LockingError(_) => SQLITE_IOERR_LOCK,
+            LimboError::Busy => SQLITE_BUSY,
+            LimboError::BusySnapshot => SQLITE_BUSY_SNAPSHOT,
+            LimboError::SchemaLocked => SQLITE_LOCKED_SHAREDCACHE,
+            LimboError::ConstraintViolation { code, .. } => *code,
+            LimboError::Unbound(_) => SQLITE_RANGE,
+            LimboError::LimitExceeded(_) => SQLITE_INTERRUPT,
+            _ => SQLITE_ERROR,
+        }
+    }
+}
+
+/// An error the way SQLite reports it, for drivers that map errors like they do for SQLite.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqliteError {
+    /// The extended result code, its low byte being the primary one.
+    pub extended_code: usize,
+    /// The message sqlite3_errmsg() returns for the error.
+    pub message: String,
+}
+
+impl SqliteError {
+    pub fn code(&self) -> usize {
+        self.extended_code & 0xff
+    }
+}
+
+impl From<&LimboError> for SqliteError {
+    fn from(err: &LimboError) -> Self {
+        let message = match err {
+            LimboError::ConstraintViolation { message, .. } => message.clone(),
+            _ => err.to_string(),
+        };
+        SqliteError {
+            extended_code: err.extended_code(),
+            message,
+        }
+    }
+}
+
+/// The file operation an I/O error happened in, which decides its extended result code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileOperation {
+    Read,
+    Write,
+    Sync,
+    Truncate,
+}
+
+impl FileOperation {
+    fn extended_code(self) -> usize {
+        match self {
+            FileOperation::Read => SQLITE_IOERR_READ,
+            FileOperation::Write => SQLITE_IOERR_WRITE,
+            FileOperation::Sync => SQLITE_IOERR_FSYNC,
+            FileOperation::Truncate => SQLITE_IOERR_TRUNCATE,
+        }
+    }
+}
+
+impl fmt::Display for FileOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FileOperation::Read => "read",
+            FileOperation::Write => "write",
+            FileOperation::Sync => "fsync",
+            FileOperation::Truncate => "truncate",
+        })
+    }
+}
+
+/// The budget of [crate::ExecutionLimits] a statement went over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionLimit {
+    Rows,
+    Duration,
+    Steps,
+}
+
+impl fmt::Display for ExecutionLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ExecutionLimit::Rows => "row",
+            ExecutionLimit::Duration => "time",
+            ExecutionLimit::Steps => "step",
+        })
+    }
+}
+
 impl From<limbo_ext::ResultCode> for LimboError {
     fn from(err: limbo_ext::ResultCode) -> Self {
         LimboError::ExtensionError(err.to_string())
     }
 }
 
+// Primary result codes, see https://www.sqlite.org/rescode.html
+pub const SQLITE_ERROR: usize = 1;
+pub const SQLITE_INTERNAL: usize = 2;
+pub const SQLITE_PERM: usize = 3;
+pub const SQLITE_ABORT: usize = 4;
+pub const SQLITE_BUSY: usize = 5;
+pub const SQLITE_LOCKED: usize = 6;
+pub const SQLITE_NOMEM: usize = 7;
+pub const SQLITE_READONLY: usize = 8;
+pub const SQLITE_INTERRUPT: usize = 9;
+pub const SQLITE_IOERR: usize = 10;
+pub const SQLITE_CORRUPT: usize = 11;
+pub const SQLITE_NOTFOUND: usize = 12;
+pub const SQLITE_FULL: usize = 13;
+pub const SQLITE_CANTOPEN: usize = 14;
+pub const SQLITE_PROTOCOL: usize = 15;
+pub const SQLITE_SCHEMA: usize = 17;
+pub const SQLITE_TOOBIG: usize = 18;
 pub const SQLITE_CONSTRAINT: usize = 19;
+pub const SQLITE_MISMATCH: usize = 20;
+pub const SQLITE_MISUSE: usize = 21;
+pub const SQLITE_RANGE: usize = 25;
+pub const SQLITE_NOTADB: usize = 26;
+
+// Extended result codes, which keep the primary code in their low byte.
+pub const SQLITE_IOERR_READ: usize = SQLITE_IOERR | (1 << 8);
+pub const SQLITE_IOERR_WRITE: usize = SQLITE_IOERR | (3 << 8);
+pub const SQLITE_IOERR_FSYNC: usize = SQLITE_IOERR | (4 << 8);
+pub const SQLITE_IOERR_TRUNCATE: usize = SQLITE_IOERR | (6 << 8);
+pub const SQLITE_IOERR_LOCK: usize = SQLITE_IOERR | (15 << 8);
+pub const SQLITE_LOCKED_SHAREDCACHE: usize = SQLITE_LOCKED | (1 << 8);
+pub const SQLITE_BUSY_SNAPSHOT: usize = SQLITE_BUSY | (2 << 8);
+pub const SQLITE_CONSTRAINT_CHECK: usize = SQLITE_CONSTRAINT | (1 << 8);
+pub const SQLITE_CONSTRAINT_NOTNULL: usize = SQLITE_CONSTRAINT | (5 << 8);
 pub const SQLITE_CONSTRAINT_PRIMARYKEY: usize = SQLITE_CONSTRAINT | (6 << 8);
+pub const SQLITE_CONSTRAINT_UNIQUE: usize = SQLITE_CONSTRAINT | (8 << 8);
@@ -0,0 +1,160 @@
+//! Writing every row of a table to a file in bulk, e.g. to dump millions of rows for another
+//! tool. Rows are written as they are read, through a buffered writer, without formatting
+//! them into a table first.
+
+use std::io::{BufWriter, Write};
+use std::rc::Rc;
+
+use fallible_streaming_iterator::FallibleStreamingIterator;
+
+use crate::{Connection, OwnedValue, Result};
+
+/// The format of [Connection::export_table].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// CSV as in RFC 4180, with a header line of the column names. NULL is an empty field and
+    /// fields are quoted only when they contain a comma, a double quote or a line break.
+    Csv,
+    /// A JSON object per line, keyed by column name. Blobs are hex strings.
+    Jsonl,
+}
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Writes the rows of `table` to `writer` in `format`, and returns how many were written.
+pub(crate) fn export_table(
+    conn: &Rc<Connection>,
+    table: &str,
+    format: ExportFormat,
+    writer: &mut dyn Write,
+) -> Result<u64> {
+    let mut stmt = conn.prepare(format!("SELECT * FROM \"{}\"", table.replace('"', "\"\"")))?;
+    let columns: Vec<String> = (0..stmt.num_columns())
+        .map(|i| stmt.get_column_name(i).to_string())
+        .collect();
+    let mut out = BufWriter::with_capacity(BUFFER_SIZE, writer);
+    // JSON keys don't change from row to row.
+    let keys: Vec<Vec<u8>> = columns
+        .iter()
+        .map(|column| {
+            let mut key = vec![];
+            write_json_string(&mut key, column.as_bytes())?;
+            key.push(b':');
+            Ok(key)
+        })
+        .collect::<std::io::Result<_>>()?;
+    if format == ExportFormat::Csv {
+        for (i, column) in columns.iter().enumerate() {
+            if i > 0 {
+                out.write_all(b",")?;
+            }
+            write_csv_field(&mut out, column.as_bytes())?;
+        }
+        out.write_all(b"\r\n")?;
+    }
+    let mut count = 0;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        match format {
+            ExportFormat::Csv => {
+                for (i, value) in row.get_values().enumerate() {
+                    if i > 0 {
+                        out.write_all(b",")?;
+                    }
+                    write_csv_value(&mut out, value)?;
+                }
+                out.write_all(b"\r\n")?;
+            }
+            ExportFormat::Jsonl => {
+                out.write_all(b"{")?;
+                for (i, (key, value)) in keys.iter().zip(row.get_values()).enumerate() {
+                    if i > 0 {
+                        out.write_all(b",")?;
+                    }
+                    out.write_all(key)?;
+                    write_json_value(&mut out, value)?;
+                }
+                out.write_all(b"}\n")?;
+            }
+        }
+        count += 1;
+    }
+    out.flush()?;
+    Ok(count)
+}
+
+fn write_csv_value(out: &mut impl Write, value: &OwnedValue) -> std::io::Result<()> {
+    match value {
+        OwnedValue::Null => Ok(()),
+        OwnedValue::Integer(i) => write!(out, "{}", i),
+        OwnedValue::Float(_) => write!(out, "{}", value),
+        OwnedValue::Text(text) => write_csv_field(out, text.as_str().as_bytes()),
+        OwnedValue::Blob(blob) => write_csv_field(out, blob),
+    }
+}
+
+fn write_csv_field(out: &mut impl Write, field: &[u8]) -> std::io::Result<()> {
+    if !field
+        .iter()
+        .any(|b| matches!(b, b',' | b'"' | b'\r' | b'\n'))
+    {
+        return out.write_all(field);
+    }
+    out.write_all(b"\"")?;
+    for (i, part) in field.split(|b| *b == b'"').enumerate() {
+        if i > 0 {
+            out.write_all(b"\"\"")?;
+        }
+        out.write_all(part)?;
+    }
+    out.write_all(b"\"")
+}
+
+fn write_json_value(out: &mut impl Write, value: &OwnedValue) -> std::io::Result<()> {
+    match value {
+        OwnedValue::Null => out.write_all(b"null"),
+        OwnedValue::Integer(i) => write!(out, "{}", i),
+        // Like SQLite's JSON functions, which write infinities as numbers too large to parse.
+        OwnedValue::Float(f) if f.is_infinite() => {
+            out.write_all(if *f < 0.0 { "-9.0e999" } else { "9.0e999" }.as_bytes())
+        }
+        OwnedValue::Float(f) if f.is_nan() => out.write_all(b"null"),
+        OwnedValue::Float(_) => write!(out, "{}", value),
+        OwnedValue::Text(text) => write_json_string(out, text.as_str().as_bytes()),
+        OwnedValue::Blob(blob) => {
+            out.write_all(b"\"")?;
+            for b in blob.iter() {
+                write!(out, "{:02x}", b)?;
+            }
+            out.write_all(b"\"")
+        }
+    }
+}
+
+fn write_json_string(out: &mut impl Write, s: &[u8]) -> std::io::Result<()> {
+    out.write_all(b"\"")?;
+    let mut start = 0;
+    for (i, b) in s.iter().enumerate() {
+        let escaped: &[u8] = match b {
+            b'"' => b"\\\"",
+            b'\\' => b"\\\\",
+            b'\n' => b"\\n",
+            b'\r' => b"\\r",
+            b'\t' => b"\\t",
+            0x08 => b"\\b",
+            0x0c => b"\\f",
+            0x00..=0x1f => {
+                out.write_all(&s[start..i])?;
+                write!(out, "\\u{:04x}", b)?;
+                start = i + 1;
+                continue;
+            }
+            _ => continue,
+        };
+        out.write_all(&s[start..i])?;
+        out.write_all(escaped)?;
+        start = i + 1;
+    }
+    out.write_all(&s[start..])?;
+    out.write_all(b"\"")
+}
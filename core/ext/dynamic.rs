@@ -1,9 +1,14 @@
 use crate::{
-    ext::{register_aggregate_function, register_scalar_function, register_vtab_module},
+    ext::{
+        register_aggregate_function, register_scalar_function, register_vtab_module, CAPABILITIES,
+    },
     Connection, LimboError,
 };
 use libloading::{Library, Symbol};
-use limbo_ext::{ExtensionApi, ExtensionApiRef, ExtensionEntryPoint, ResultCode, VfsImpl};
+use limbo_ext::{
+    ExtensionApi, ExtensionApiRef, ExtensionEntryPoint, ExtensionInfoFn, ResultCode, VfsImpl,
+    EXTENSION_API_VERSION,
+};
 use std::{
     ffi::{c_char, CString},
     sync::{Arc, Mutex, OnceLock},
@@ -32,9 +37,24 @@ impl Connection {
     pub fn load_extension<P: AsRef<std::ffi::OsStr>>(&self, path: P) -> crate::Result<()> {
         use limbo_ext::ExtensionApiRef;
 
+        if std::path::Path::new(path.as_ref())
+            .extension()
+            .is_some_and(|ext| ext == "wasm")
+        {
+            #[cfg(feature = "wasm_ext")]
+            return self.load_wasm_extension(
+                std::fs::read(path.as_ref())
+                    .map_err(|e| LimboError::ExtensionError(e.to_string()))?,
+            );
+            #[cfg(not(feature = "wasm_ext"))]
+            return Err(LimboError::ExtensionError(
+                "loading WebAssembly extensions requires the wasm_ext feature".to_string(),
+            ));
+        }
         let api = Box::new(self.build_limbo_ext());
         let lib =
             unsafe { Library::new(path).map_err(|e| LimboError::ExtensionError(e.to_string()))? };
+        check_extension_info(&lib)?;
         let entry: Symbol<ExtensionEntryPoint> = unsafe {
             lib.get(b"register_extension")
                 .map_err(|e| LimboError::ExtensionError(e.to_string()))?
@@ -57,6 +77,31 @@ impl Connection {
     }
 }
 
+/// Makes sure the library was built against the extension API of this build and only needs
+/// what it supports, before handing it an [ExtensionApi] it might read incorrectly.
+fn check_extension_info(lib: &Library) -> crate::Result<()> {
+    let info: Symbol<ExtensionInfoFn> = unsafe { lib.get(b"extension_info") }.map_err(|_| {
+        LimboError::ExtensionError(
+            "not a Limbo extension, or built against a version of limbo_ext older than this build supports"
+                .to_string(),
+        )
+    })?;
+    let info = unsafe { info() };
+    if info.api_version != EXTENSION_API_VERSION {
+        return Err(LimboError::ExtensionError(format!(
+            "extension was built against extension API version {}, but this build of Limbo supports version {}",
+            info.api_version, EXTENSION_API_VERSION
+        )));
+    }
+    let missing = info.capabilities.difference(CAPABILITIES);
+    if !missing.is_empty() {
+        return Err(LimboError::ExtensionError(format!(
+            "extension registers {missing}, which this build of Limbo does not support"
+        )));
+    }
+    Ok(())
+}
+
 #[allow(clippy::arc_with_non_send_sync)]
 pub(crate) unsafe extern "C" fn register_vfs(
     name: *const c_char,
@@ -87,6 +132,8 @@ pub fn add_builtin_vfs_extensions(
     let mut vfslist: Vec<*const VfsImpl> = Vec::new();
     let mut api = match api {
         None => ExtensionApi {
+            version: EXTENSION_API_VERSION,
+            capabilities: CAPABILITIES,
             ctx: std::ptr::null_mut(),
             register_scalar_function,
             register_aggregate_function,
@@ -139,6 +186,14 @@ fn register_static_vfs_modules(_api: &mut ExtensionApi) {
     unsafe {
         limbo_ext_tests::register_extension_static(_api);
     }
+    #[cfg(feature = "httpvfs")]
+    unsafe {
+        limbo_httpvfs::register_extension_static(_api);
+    }
+    #[cfg(feature = "objectvfs")]
+    unsafe {
+        limbo_objectvfs::register_extension_static(_api);
+    }
 }
 
 pub fn add_vfs_module(name: String, vfs: Arc<VfsMod>) {
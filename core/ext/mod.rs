@@ -1,12 +1,15 @@
 #[cfg(feature = "fs")]
 mod dynamic;
-#[cfg(all(target_os = "linux", feature = "io_uring"))]
-use crate::UringIO;
-use crate::{function::ExternalFunc, Connection, Database, LimboError, IO};
+#[cfg(feature = "wasm_ext")]
+mod wasm;
+use crate::{function::ExternalFunc, Connection, Database, IO};
 #[cfg(feature = "fs")]
-pub use dynamic::{add_builtin_vfs_extensions, add_vfs_module, list_vfs_modules, VfsMod};
+pub use dynamic::{
+    add_builtin_vfs_extensions, add_vfs_module, get_vfs_modules, list_vfs_modules, VfsMod,
+};
 use limbo_ext::{
-    ExtensionApi, InitAggFunction, ResultCode, ScalarFunction, VTabKind, VTabModuleImpl,
+    Capabilities, ExtensionApi, InitAggFunction, ResultCode, ScalarFunction, VTabKind,
+    VTabModuleImpl, EXTENSION_API_VERSION,
 };
 pub use limbo_ext::{FinalizeFunction, StepFunction, Value as ExtValue, ValueType as ExtValueType};
 use std::{
@@ -14,8 +17,23 @@ use std::{
     rc::Rc,
     sync::Arc,
 };
+#[cfg(feature = "wasm_ext")]
+pub use wasm::WasmScalar;
 type ExternAggFunc = (InitAggFunction, StepFunction, FinalizeFunction);
 
+/// What extensions can register with this build. VFS modules need the `fs` feature, as
+/// [ExtensionApi] only has its VFS interface with it.
+pub(crate) const CAPABILITIES: Capabilities = {
+    let capabilities = Capabilities::SCALARS
+        .union(Capabilities::AGGREGATES)
+        .union(Capabilities::VTABS);
+    if cfg!(feature = "fs") {
+        capabilities.union(Capabilities::VFS)
+    } else {
+        capabilities
+    }
+};
+
 #[derive(Clone)]
 pub struct VTabImpl {
     pub module_kind: VTabKind,
@@ -89,24 +107,7 @@ impl Database {
         path: &str,
         vfs: &str,
     ) -> crate::Result<(Arc<dyn IO>, Arc<Database>)> {
-        use crate::{MemoryIO, PlatformIO};
-        use dynamic::get_vfs_modules;
-
-        let io: Arc<dyn IO> = match vfs {
-            "memory" => Arc::new(MemoryIO::new()),
-            "syscall" => Arc::new(PlatformIO::new()?),
-            #[cfg(all(target_os = "linux", feature = "io_uring"))]
-            "io_uring" => Arc::new(UringIO::new()?),
-            other => match get_vfs_modules().iter().find(|v| v.0 == vfs) {
-                Some((_, vfs)) => vfs.clone(),
-                None => {
-                    return Err(LimboError::InvalidArgument(format!(
-                        "no such VFS: {}",
-                        other
-                    )));
-                }
-            },
-        };
+        let io = Self::vfs_io(vfs)?;
         let db = Self::open_file(io.clone(), path, false)?;
         Ok((io, db))
     }
@@ -118,6 +119,7 @@ impl Connection {
             name.to_string(),
             Rc::new(ExternalFunc::new_scalar(name.to_string(), func)),
         );
+        self.clear_plan_cache();
         ResultCode::OK
     }
 
@@ -131,6 +133,7 @@ impl Connection {
             name.to_string(),
             Rc::new(ExternalFunc::new_aggregate(name.to_string(), args, func)),
         );
+        self.clear_plan_cache();
         ResultCode::OK
     }
 
@@ -154,6 +157,8 @@ impl Connection {
 
     pub fn build_limbo_ext(&self) -> ExtensionApi {
         ExtensionApi {
+            version: EXTENSION_API_VERSION,
+            capabilities: CAPABILITIES,
             ctx: self as *const _ as *mut c_void,
             register_scalar_function,
             register_aggregate_function,
@@ -202,6 +207,30 @@ impl Connection {
         if unsafe { !limbo_completion::register_extension_static(&mut ext_api).is_ok() } {
             return Err("Failed to register completion extension".to_string());
         }
+        #[cfg(feature = "sqlar")]
+        if unsafe { !limbo_sqlar::register_extension_static(&mut ext_api).is_ok() } {
+            return Err("Failed to register sqlar extension".to_string());
+        }
+        #[cfg(feature = "parquet")]
+        if unsafe { !limbo_parquet::register_extension_static(&mut ext_api).is_ok() } {
+            return Err("Failed to register parquet extension".to_string());
+        }
+        #[cfg(feature = "geopoly")]
+        if unsafe { !limbo_geopoly::register_extension_static(&mut ext_api).is_ok() } {
+            return Err("Failed to register geopoly extension".to_string());
+        }
+        #[cfg(feature = "decimal")]
+        if unsafe { !limbo_decimal::register_extension_static(&mut ext_api).is_ok() } {
+            return Err("Failed to register decimal extension".to_string());
+        }
+        #[cfg(feature = "compress")]
+        if unsafe { !limbo_compress::register_extension_static(&mut ext_api).is_ok() } {
+            return Err("Failed to register compress extension".to_string());
+        }
+        #[cfg(feature = "carray")]
+        if unsafe { !limbo_carray::register_extension_static(&mut ext_api).is_ok() } {
+            return Err("Failed to register carray extension".to_string());
+        }
         #[cfg(feature = "fs")]
         {
             let vfslist = add_builtin_vfs_extensions(Some(ext_api)).map_err(|e| e.to_string())?;
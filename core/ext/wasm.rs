@@ -0,0 +1,360 @@
+//! Extensions compiled to WebAssembly, run by wasmtime in a sandbox. A module can only reach
+//! the host through the `limbo` imports below, gets no WASI, and is limited in memory and in
+//! fuel per call, so an untrusted extension can neither touch the process nor hang a query.
+//!
+//! A module exports its `memory` and:
+//! - `limbo_init() -> i32`, called once when it is loaded, returning 0 on success. It
+//!   registers functions with the `limbo.register_scalar(name_ptr, name_len, export_ptr,
+//!   export_len) -> i32` import, naming the SQL function and the export implementing it.
+//! - `limbo_alloc(len: i32) -> i32`, returning a buffer for the host to write the arguments
+//!   of a call to, and optionally `limbo_free(ptr: i32, len: i32)` to release it afterwards.
+//! - The scalar functions, `(args_ptr: i32, args_len: i32) -> i64`. The arguments are encoded
+//!   one after another, and the result is a single encoded value in the module's memory,
+//!   returned as its address in the high and its length in the low 32 bits.
+//!
+//! A value is encoded as a tag byte and its payload: 0 is NULL; 1 an integer and 2 a float,
+//! as 8 little-endian bytes; 3 a text and 4 a blob, as a 4-byte little-endian length and the
+//! bytes. A function fails by returning 5, an error message encoded like a text.
+use crate::{
+    function::{ExtFunc, ExternalFunc},
+    types::OwnedValue,
+    Connection, LimboError, Result,
+};
+use std::{
+    cell::RefCell,
+    fmt::{self, Debug},
+    rc::Rc,
+    sync::OnceLock,
+};
+use wasmtime::{
+    Caller, Config, Engine, Extern, Instance, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder, TypedFunc,
+};
+
+/// The most memory a module can grow to.
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+/// The fuel, roughly the number of instructions, each call into a module can use.
+const FUEL_PER_CALL: u64 = 100_000_000;
+
+const NULL: u8 = 0;
+const INTEGER: u8 = 1;
+const FLOAT: u8 = 2;
+const TEXT: u8 = 3;
+const BLOB: u8 = 4;
+const ERROR: u8 = 5;
+
+fn engine() -> &'static Engine {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    ENGINE.get_or_init(|| {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        Engine::new(&config).expect("wasmtime configuration is valid")
+    })
+}
+
+fn wasm_error(context: &str, e: impl fmt::Display) -> LimboError {
+    LimboError::ExtensionError(format!("{context}: {e}"))
+}
+
+struct HostState {
+    limits: StoreLimits,
+    /// The functions registered by `limbo_init`, as SQL name and export name.
+    scalars: Vec<(String, String)>,
+}
+
+/// An instantiated module, shared by the functions it registered.
+struct WasmModule {
+    store: RefCell<Store<HostState>>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    free: Option<TypedFunc<(i32, i32), ()>>,
+}
+
+/// A scalar function implemented by a WebAssembly extension.
+pub struct WasmScalar {
+    name: String,
+    module: Rc<WasmModule>,
+    func: TypedFunc<(i32, i32), i64>,
+}
+
+impl Debug for WasmScalar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "WasmScalar({})", self.name)
+    }
+}
+
+impl WasmScalar {
+    pub fn call(&self, args: &[OwnedValue]) -> Result<OwnedValue> {
+        let context = format!("wasm function {} failed", self.name);
+        let module = &self.module;
+        let mut store = module.store.borrow_mut();
+        let mut encoded = Vec::new();
+        for arg in args {
+            encode_value(&mut encoded, arg);
+        }
+        let len = i32::try_from(encoded.len()).map_err(|e| wasm_error(&context, e))?;
+        store
+            .set_fuel(FUEL_PER_CALL)
+            .map_err(|e| wasm_error(&context, e))?;
+        let ptr = module
+            .alloc
+            .call(&mut *store, len)
+            .map_err(|e| wasm_error(&context, e))?;
+        module
+            .memory
+            .write(&mut *store, ptr as u32 as usize, &encoded)
+            .map_err(|e| wasm_error(&context, e))?;
+        let result = self.func.call(&mut *store, (ptr, len));
+        if let Some(free) = &module.free {
+            free.call(&mut *store, (ptr, len))
+                .map_err(|e| wasm_error(&context, e))?;
+        }
+        let result = result.map_err(|e| wasm_error(&context, e))? as u64;
+        let (ptr, len) = ((result >> 32) as usize, (result & 0xffff_ffff) as usize);
+        let data = module
+            .memory
+            .data(&*store)
+            .get(ptr..ptr + len)
+            .ok_or_else(|| wasm_error(&context, "result is out of bounds"))?;
+        decode_value(data)
+            .ok_or_else(|| wasm_error(&context, "result is not a valid value"))?
+            .map_err(LimboError::ExtensionError)
+    }
+}
+
+fn encode_value(buf: &mut Vec<u8>, value: &OwnedValue) {
+    match value {
+        OwnedValue::Null => buf.push(NULL),
+        OwnedValue::Integer(i) => {
+            buf.push(INTEGER);
+            buf.extend_from_slice(&i.to_le_bytes());
+        }
+        OwnedValue::Float(f) => {
+            buf.push(FLOAT);
+            buf.extend_from_slice(&f.to_le_bytes());
+        }
+        OwnedValue::Text(text) => {
+            buf.push(TEXT);
+            buf.extend_from_slice(&(text.as_str().len() as u32).to_le_bytes());
+            buf.extend_from_slice(text.as_str().as_bytes());
+        }
+        OwnedValue::Blob(blob) => {
+            buf.push(BLOB);
+            buf.extend_from_slice(&(blob.len() as u32).to_le_bytes());
+            buf.extend_from_slice(blob);
+        }
+    }
+}
+
+/// Decodes a result, `Err` with the message if the function failed and `None` if the data
+/// isn't a valid value.
+fn decode_value(data: &[u8]) -> Option<std::result::Result<OwnedValue, String>> {
+    let (&tag, payload) = data.split_first()?;
+    let bytes = || -> Option<&[u8]> {
+        let (len, rest) = payload.split_first_chunk::<4>()?;
+        let len = u32::from_le_bytes(*len) as usize;
+        (rest.len() == len).then_some(rest)
+    };
+    let value = match tag {
+        NULL if payload.is_empty() => OwnedValue::Null,
+        INTEGER => OwnedValue::Integer(i64::from_le_bytes(payload.try_into().ok()?)),
+        FLOAT => OwnedValue::Float(f64::from_le_bytes(payload.try_into().ok()?)),
+        TEXT => OwnedValue::build_text(std::str::from_utf8(bytes()?).ok()?),
+        BLOB => OwnedValue::Blob(bytes()?.to_vec()),
+        ERROR => return Some(Err(String::from_utf8_lossy(bytes()?).into_owned())),
+        _ => return None,
+    };
+    Some(Ok(value))
+}
+
+fn read_string(memory: &[u8], ptr: i32, len: i32) -> Option<String> {
+    let start = ptr as u32 as usize;
+    let bytes = memory.get(start..start.checked_add(len as u32 as usize)?)?;
+    String::from_utf8(bytes.to_vec()).ok()
+}
+
+fn register_scalar(
+    mut caller: Caller<'_, HostState>,
+    name_ptr: i32,
+    name_len: i32,
+    export_ptr: i32,
+    export_len: i32,
+) -> i32 {
+    let Some(memory) = caller.get_export("memory").and_then(Extern::into_memory) else {
+        return 1;
+    };
+    let data = memory.data(&caller);
+    let (Some(name), Some(export)) = (
+        read_string(data, name_ptr, name_len),
+        read_string(data, export_ptr, export_len),
+    ) else {
+        return 1;
+    };
+    caller.data_mut().scalars.push((name, export));
+    0
+}
+
+impl Connection {
+    /// Loads an extension compiled to WebAssembly, from the binary or text format of the
+    /// module. See the [module docs](self) for what the module has to export.
+    pub fn load_wasm_extension(&self, wasm: impl AsRef<[u8]>) -> Result<()> {
+        let context = "unable to load wasm extension";
+        let engine = engine();
+        let module = Module::new(engine, wasm).map_err(|e| wasm_error(context, e))?;
+        let mut store = Store::new(
+            engine,
+            HostState {
+                limits: StoreLimitsBuilder::new()
+                    .memory_size(MEMORY_LIMIT)
+                    .instances(1)
+                    .build(),
+                scalars: Vec::new(),
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+        store
+            .set_fuel(FUEL_PER_CALL)
+            .map_err(|e| wasm_error(context, e))?;
+        let mut linker = Linker::new(engine);
+        linker
+            .func_wrap("limbo", "register_scalar", register_scalar)
+            .map_err(|e| wasm_error(context, e))?;
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .map_err(|e| wasm_error(context, e))?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| wasm_error(context, "module does not export its memory"))?;
+        let init = typed_func::<(), i32>(&instance, &mut store, "limbo_init")?;
+        let alloc = typed_func::<i32, i32>(&instance, &mut store, "limbo_alloc")?;
+        let free = typed_func::<(i32, i32), ()>(&instance, &mut store, "limbo_free").ok();
+        let code = init
+            .call(&mut store, ())
+            .map_err(|e| wasm_error(context, e))?;
+        if code != 0 {
+            return Err(wasm_error(context, format!("limbo_init returned {code}")));
+        }
+        let scalars = std::mem::take(&mut store.data_mut().scalars);
+        let funcs = scalars
+            .into_iter()
+            .map(|(name, export)| {
+                let func = typed_func::<(i32, i32), i64>(&instance, &mut store, &export)?;
+                Ok((name, func))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let module = Rc::new(WasmModule {
+            store: RefCell::new(store),
+            memory,
+            alloc,
+            free,
+        });
+        for (name, func) in funcs {
+            let scalar = WasmScalar {
+                name: name.clone(),
+                module: module.clone(),
+                func,
+            };
+            self.syms.borrow_mut().functions.insert(
+                name.clone(),
+                Rc::new(ExternalFunc {
+                    name,
+                    func: ExtFunc::Wasm(Rc::new(scalar)),
+                }),
+            );
+        }
+        self.clear_plan_cache();
+        Ok(())
+    }
+}
+
+fn typed_func<Params, Results>(
+    instance: &Instance,
+    store: &mut Store<HostState>,
+    name: &str,
+) -> Result<TypedFunc<Params, Results>>
+where
+    Params: wasmtime::WasmParams,
+    Results: wasmtime::WasmResults,
+{
+    instance
+        .get_typed_func(store, name)
+        .map_err(|e| wasm_error(&format!("invalid export {name}"), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{types::OwnedValue, Connection, Database, MemoryIO};
+    use fallible_streaming_iterator::FallibleStreamingIterator;
+    use std::{rc::Rc, sync::Arc};
+
+    /// Exports `add_one`, which adds one to an integer, and `spin`, which never returns.
+    const MODULE: &str = r#"
+        (module
+          (import "limbo" "register_scalar" (func $register (param i32 i32 i32 i32) (result i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "add_one")
+          (data (i32.const 16) "spin")
+          (func (export "limbo_init") (result i32)
+            (drop (call $register (i32.const 0) (i32.const 7) (i32.const 0) (i32.const 7)))
+            (call $register (i32.const 16) (i32.const 4) (i32.const 16) (i32.const 4)))
+          (func (export "limbo_alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "add_one") (param $ptr i32) (param $len i32) (result i64)
+            (if (i32.ne (i32.load8_u (local.get $ptr)) (i32.const 1))
+              (then (i32.store8 (i32.const 2048) (i32.const 0))
+                    (return (i64.const 0x0000080000000001))))
+            (i32.store8 (i32.const 2048) (i32.const 1))
+            (i64.store (i32.const 2049)
+              (i64.add (i64.load (i32.add (local.get $ptr) (i32.const 1))) (i64.const 1)))
+            (i64.const 0x0000080000000009))
+          (func (export "spin") (param i32 i32) (result i64)
+            (loop $forever (br $forever))
+            (i64.const 0)))
+    "#;
+
+    fn query_one(conn: &Rc<Connection>, sql: &str) -> crate::Result<OwnedValue> {
+        let mut stmt = conn.prepare(sql)?;
+        let mut rows = stmt.query([])?;
+        let row = rows.next()?.expect("a row");
+        Ok(row.get_value(0).clone())
+    }
+
+    #[test]
+    fn test_wasm_scalar() {
+        let io = Arc::new(MemoryIO::new());
+        let db = Database::open_file(io, ":memory:", false).unwrap();
+        let conn = db.connect().unwrap();
+        conn.load_wasm_extension(MODULE).unwrap();
+        assert_eq!(
+            query_one(&conn, "SELECT add_one(41)").unwrap(),
+            OwnedValue::Integer(42)
+        );
+        assert_eq!(
+            query_one(&conn, "SELECT add_one('x')").unwrap(),
+            OwnedValue::Null
+        );
+        let err = query_one(&conn, "SELECT spin()").unwrap_err();
+        assert!(
+            err.to_string().contains("wasm function spin failed"),
+            "{err}"
+        );
+        // The module is still usable after a trap.
+        assert_eq!(
+            query_one(&conn, "SELECT add_one(1)").unwrap(),
+            OwnedValue::Integer(2)
+        );
+    }
+
+    #[test]
+    fn test_wasm_imports_are_restricted() {
+        let io = Arc::new(MemoryIO::new());
+        let db = Database::open_file(io, ":memory:", false).unwrap();
+        let conn = db.connect().unwrap();
+        let wasi = r#"(module (import "wasi_snapshot_preview1" "fd_write"
+            (func (param i32 i32 i32 i32) (result i32))))"#;
+        let err = conn.load_wasm_extension(wasi).unwrap_err();
+        assert!(
+            err.to_string().contains("unable to load wasm extension"),
+            "{err}"
+        );
+    }
+}
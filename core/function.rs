@@ -19,6 +19,8 @@ pub enum ExtFunc {
         step: StepFunction,
         finalize: FinalizeFunction,
     },
+    #[cfg(feature = "wasm_ext")]
+    Wasm(Rc<crate::ext::WasmScalar>),
 }
 
 impl ExtFunc {
@@ -136,6 +138,7 @@ impl Display for JsonFunc {
     }
 }
 
+#[cfg(feature = "vector")]
 #[derive(Debug, Clone)]
 pub enum VectorFunc {
     Vector,
@@ -143,8 +146,10 @@ pub enum VectorFunc {
     Vector64,
     VectorExtract,
     VectorDistanceCos,
+    VectorDistanceL2,
 }
 
+#[cfg(feature = "vector")]
 impl Display for VectorFunc {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let str = match self {
@@ -153,6 +158,7 @@ impl Display for VectorFunc {
             Self::Vector64 => "vector64".to_string(),
             Self::VectorExtract => "vector_extract".to_string(),
             Self::VectorDistanceCos => "vector_distance_cos".to_string(),
+            Self::VectorDistanceL2 => "vector_distance_l2".to_string(),
         };
         write!(f, "{}", str)
     }
@@ -293,6 +299,22 @@ pub enum ScalarFunc {
     StrfTime,
     Printf,
     Likely,
+    Unlikely,
+    Likelihood,
+}
+
+impl ScalarFunc {
+    /// Whether the function always returns the same result for the same arguments.
+    pub fn is_deterministic(&self) -> bool {
+        !matches!(
+            self,
+            Self::Random
+                | Self::RandomBlob
+                | Self::Changes
+                | Self::TotalChanges
+                | Self::LastInsertRowid
+        )
+    }
 }
 
 impl Display for ScalarFunc {
@@ -348,6 +370,8 @@ impl Display for ScalarFunc {
             Self::StrfTime => "strftime".to_string(),
             Self::Printf => "printf".to_string(),
             Self::Likely => "likely".to_string(),
+            Self::Unlikely => "unlikely".to_string(),
+            Self::Likelihood => "likelihood".to_string(),
         };
         write!(f, "{}", str)
     }
@@ -465,11 +489,203 @@ impl Display for MathFunc {
     }
 }
 
+/// A built-in function, as `PRAGMA function_list` reports it.
+#[derive(Debug, Clone, Copy)]
+pub struct BuiltinFunction {
+    pub name: &'static str,
+    pub aggregate: bool,
+    /// Number of arguments the function takes, -1 if it takes any number of them. Functions
+    /// that take a few different numbers of arguments have one entry for each.
+    pub narg: i32,
+}
+
+impl BuiltinFunction {
+    const fn scalar(name: &'static str, narg: i32) -> Self {
+        Self {
+            name,
+            aggregate: false,
+            narg,
+        }
+    }
+
+    const fn aggregate(name: &'static str, narg: i32) -> Self {
+        Self {
+            name,
+            aggregate: true,
+            narg,
+        }
+    }
+}
+
+const BUILTIN_FUNCTIONS: &[BuiltinFunction] = &[
+    BuiltinFunction::aggregate("avg", 1),
+    BuiltinFunction::aggregate("count", 0),
+    BuiltinFunction::aggregate("count", 1),
+    BuiltinFunction::aggregate("group_concat", 1),
+    BuiltinFunction::aggregate("group_concat", 2),
+    BuiltinFunction::aggregate("max", 1),
+    BuiltinFunction::aggregate("min", 1),
+    BuiltinFunction::aggregate("string_agg", 2),
+    BuiltinFunction::aggregate("sum", 1),
+    BuiltinFunction::aggregate("total", 1),
+    BuiltinFunction::scalar("char", -1),
+    BuiltinFunction::scalar("coalesce", -1),
+    BuiltinFunction::scalar("concat", -1),
+    BuiltinFunction::scalar("concat_ws", -1),
+    BuiltinFunction::scalar("changes", 0),
+    BuiltinFunction::scalar("total_changes", 0),
+    BuiltinFunction::scalar("glob", 2),
+    BuiltinFunction::scalar("ifnull", 2),
+    BuiltinFunction::scalar("iif", -1),
+    BuiltinFunction::scalar("if", -1),
+    BuiltinFunction::scalar("instr", 2),
+    BuiltinFunction::scalar("like", 2),
+    BuiltinFunction::scalar("like", 3),
+    BuiltinFunction::scalar("abs", 1),
+    BuiltinFunction::scalar("upper", 1),
+    BuiltinFunction::scalar("lower", 1),
+    BuiltinFunction::scalar("random", 0),
+    BuiltinFunction::scalar("randomblob", 1),
+    BuiltinFunction::scalar("trim", 1),
+    BuiltinFunction::scalar("trim", 2),
+    BuiltinFunction::scalar("ltrim", 1),
+    BuiltinFunction::scalar("ltrim", 2),
+    BuiltinFunction::scalar("rtrim", 1),
+    BuiltinFunction::scalar("rtrim", 2),
+    BuiltinFunction::scalar("round", 1),
+    BuiltinFunction::scalar("round", 2),
+    BuiltinFunction::scalar("length", 1),
+    BuiltinFunction::scalar("octet_length", 1),
+    BuiltinFunction::scalar("sign", 1),
+    BuiltinFunction::scalar("substr", 2),
+    BuiltinFunction::scalar("substr", 3),
+    BuiltinFunction::scalar("substring", 2),
+    BuiltinFunction::scalar("substring", 3),
+    BuiltinFunction::scalar("date", -1),
+    BuiltinFunction::scalar("time", -1),
+    BuiltinFunction::scalar("datetime", -1),
+    BuiltinFunction::scalar("typeof", 1),
+    BuiltinFunction::scalar("last_insert_rowid", 0),
+    BuiltinFunction::scalar("unicode", 1),
+    BuiltinFunction::scalar("quote", 1),
+    BuiltinFunction::scalar("sqlite_version", 0),
+    BuiltinFunction::scalar("sqlite_source_id", 0),
+    BuiltinFunction::scalar("replace", 3),
+    BuiltinFunction::scalar("likely", 1),
+    BuiltinFunction::scalar("unlikely", 1),
+    BuiltinFunction::scalar("likelihood", 2),
+    BuiltinFunction::scalar("unixepoch", -1),
+    BuiltinFunction::scalar("julianday", -1),
+    BuiltinFunction::scalar("hex", 1),
+    BuiltinFunction::scalar("unhex", 1),
+    BuiltinFunction::scalar("unhex", 2),
+    BuiltinFunction::scalar("zeroblob", 1),
+    BuiltinFunction::scalar("soundex", 1),
+    BuiltinFunction::scalar("strftime", -1),
+    BuiltinFunction::scalar("printf", -1),
+    BuiltinFunction::scalar("max", -1),
+    BuiltinFunction::scalar("min", -1),
+    BuiltinFunction::scalar("nullif", 2),
+    BuiltinFunction::scalar("acos", 1),
+    BuiltinFunction::scalar("acosh", 1),
+    BuiltinFunction::scalar("asin", 1),
+    BuiltinFunction::scalar("asinh", 1),
+    BuiltinFunction::scalar("atan", 1),
+    BuiltinFunction::scalar("atan2", 2),
+    BuiltinFunction::scalar("atanh", 1),
+    BuiltinFunction::scalar("ceil", 1),
+    BuiltinFunction::scalar("ceiling", 1),
+    BuiltinFunction::scalar("cos", 1),
+    BuiltinFunction::scalar("cosh", 1),
+    BuiltinFunction::scalar("degrees", 1),
+    BuiltinFunction::scalar("exp", 1),
+    BuiltinFunction::scalar("floor", 1),
+    BuiltinFunction::scalar("ln", 1),
+    BuiltinFunction::scalar("log", 1),
+    BuiltinFunction::scalar("log", 2),
+    BuiltinFunction::scalar("log10", 1),
+    BuiltinFunction::scalar("log2", 1),
+    BuiltinFunction::scalar("mod", 2),
+    BuiltinFunction::scalar("pi", 0),
+    BuiltinFunction::scalar("pow", 2),
+    BuiltinFunction::scalar("power", 2),
+    BuiltinFunction::scalar("radians", 1),
+    BuiltinFunction::scalar("sin", 1),
+    BuiltinFunction::scalar("sinh", 1),
+    BuiltinFunction::scalar("sqrt", 1),
+    BuiltinFunction::scalar("tan", 1),
+    BuiltinFunction::scalar("tanh", 1),
+    BuiltinFunction::scalar("trunc", 1),
+];
+
+#[cfg(feature = "vector")]
+const VECTOR_FUNCTIONS: &[BuiltinFunction] = &[
+    BuiltinFunction::scalar("vector", 1),
+    BuiltinFunction::scalar("vector32", 1),
+    BuiltinFunction::scalar("vector64", 1),
+    BuiltinFunction::scalar("vector_extract", 1),
+    BuiltinFunction::scalar("vector_distance_cos", 2),
+    BuiltinFunction::scalar("vector_distance_l2", 2),
+];
+
+#[cfg(feature = "json")]
+const JSON_FUNCTIONS: &[BuiltinFunction] = &[
+    BuiltinFunction::aggregate("json_group_array", 1),
+    BuiltinFunction::aggregate("jsonb_group_array", 1),
+    BuiltinFunction::aggregate("json_group_object", 2),
+    BuiltinFunction::aggregate("jsonb_group_object", 2),
+    BuiltinFunction::scalar("json", 1),
+    BuiltinFunction::scalar("jsonb", 1),
+    BuiltinFunction::scalar("json_array", -1),
+    BuiltinFunction::scalar("jsonb_array", -1),
+    BuiltinFunction::scalar("json_array_length", 1),
+    BuiltinFunction::scalar("json_array_length", 2),
+    BuiltinFunction::scalar("json_error_position", 1),
+    BuiltinFunction::scalar("json_extract", -1),
+    BuiltinFunction::scalar("jsonb_extract", -1),
+    BuiltinFunction::scalar("json_insert", -1),
+    BuiltinFunction::scalar("jsonb_insert", -1),
+    BuiltinFunction::scalar("json_object", -1),
+    BuiltinFunction::scalar("jsonb_object", -1),
+    BuiltinFunction::scalar("json_patch", 2),
+    BuiltinFunction::scalar("json_pretty", 1),
+    BuiltinFunction::scalar("json_pretty", 2),
+    BuiltinFunction::scalar("json_quote", 1),
+    BuiltinFunction::scalar("json_remove", -1),
+    BuiltinFunction::scalar("jsonb_remove", -1),
+    BuiltinFunction::scalar("json_replace", -1),
+    BuiltinFunction::scalar("jsonb_replace", -1),
+    BuiltinFunction::scalar("json_set", -1),
+    BuiltinFunction::scalar("jsonb_set", -1),
+    BuiltinFunction::scalar("json_type", 1),
+    BuiltinFunction::scalar("json_type", 2),
+    BuiltinFunction::scalar("json_valid", 1),
+];
+
+#[cfg(feature = "fs")]
+const FS_FUNCTIONS: &[BuiltinFunction] = &[
+    BuiltinFunction::scalar("load_extension", 1),
+    BuiltinFunction::scalar("load_extension", 2),
+];
+
+/// The functions [Func::resolve_function] knows about, in no particular order.
+pub fn builtin_functions() -> impl Iterator<Item = &'static BuiltinFunction> {
+    let functions = BUILTIN_FUNCTIONS.iter();
+    #[cfg(feature = "json")]
+    let functions = functions.chain(JSON_FUNCTIONS.iter());
+    #[cfg(feature = "vector")]
+    let functions = functions.chain(VECTOR_FUNCTIONS.iter());
+    #[cfg(feature = "fs")]
+    let functions = functions.chain(FS_FUNCTIONS.iter());
+    functions
+}
+
 #[derive(Debug)]
 pub enum Func {
     Agg(AggFunc),
     Scalar(ScalarFunc),
     Math(MathFunc),
+    #[cfg(feature = "vector")]
     Vector(VectorFunc),
     #[cfg(feature = "json")]
     Json(JsonFunc),
@@ -482,6 +698,7 @@ impl Display for Func {
             Self::Agg(agg_func) => write!(f, "{}", agg_func.to_string()),
             Self::Scalar(scalar_func) => write!(f, "{}", scalar_func),
             Self::Math(math_func) => write!(f, "{}", math_func),
+            #[cfg(feature = "vector")]
             Self::Vector(vector_func) => write!(f, "{}", vector_func),
             #[cfg(feature = "json")]
             Self::Json(json_func) => write!(f, "{}", json_func),
@@ -497,6 +714,15 @@ pub struct FuncCtx {
 }
 
 impl Func {
+    /// Whether the function always returns the same result for the same arguments. Functions
+    /// of extensions are assumed to be.
+    pub fn is_deterministic(&self) -> bool {
+        match self {
+            Self::Scalar(scalar_func) => scalar_func.is_deterministic(),
+            _ => true,
+        }
+    }
+
     pub fn resolve_function(name: &str, arg_count: usize) -> Result<Self, LimboError> {
         match name {
             "avg" => {
@@ -571,7 +797,7 @@ impl Func {
             "total_changes" => Ok(Self::Scalar(ScalarFunc::TotalChanges)),
             "glob" => Ok(Self::Scalar(ScalarFunc::Glob)),
             "ifnull" => Ok(Self::Scalar(ScalarFunc::IfNull)),
-            "iif" => Ok(Self::Scalar(ScalarFunc::Iif)),
+            "iif" | "if" => Ok(Self::Scalar(ScalarFunc::Iif)),
             "instr" => Ok(Self::Scalar(ScalarFunc::Instr)),
             "like" => Ok(Self::Scalar(ScalarFunc::Like)),
             "abs" => Ok(Self::Scalar(ScalarFunc::Abs)),
@@ -599,6 +825,8 @@ impl Func {
             "sqlite_source_id" => Ok(Self::Scalar(ScalarFunc::SqliteSourceId)),
             "replace" => Ok(Self::Scalar(ScalarFunc::Replace)),
             "likely" => Ok(Self::Scalar(ScalarFunc::Likely)),
+            "unlikely" => Ok(Self::Scalar(ScalarFunc::Unlikely)),
+            "likelihood" => Ok(Self::Scalar(ScalarFunc::Likelihood)),
             #[cfg(feature = "json")]
             "json" => Ok(Self::Json(JsonFunc::Json)),
             #[cfg(feature = "json")]
@@ -684,11 +912,18 @@ impl Func {
             "load_extension" => Ok(Self::Scalar(ScalarFunc::LoadExtension)),
             "strftime" => Ok(Self::Scalar(ScalarFunc::StrfTime)),
             "printf" => Ok(Self::Scalar(ScalarFunc::Printf)),
+            #[cfg(feature = "vector")]
             "vector" => Ok(Self::Vector(VectorFunc::Vector)),
+            #[cfg(feature = "vector")]
             "vector32" => Ok(Self::Vector(VectorFunc::Vector32)),
+            #[cfg(feature = "vector")]
             "vector64" => Ok(Self::Vector(VectorFunc::Vector64)),
+            #[cfg(feature = "vector")]
             "vector_extract" => Ok(Self::Vector(VectorFunc::VectorExtract)),
+            #[cfg(feature = "vector")]
             "vector_distance_cos" => Ok(Self::Vector(VectorFunc::VectorDistanceCos)),
+            #[cfg(feature = "vector")]
+            "vector_distance_l2" => Ok(Self::Vector(VectorFunc::VectorDistanceL2)),
             _ => crate::bail_parse_error!("no such function: {}", name),
         }
     }
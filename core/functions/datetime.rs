@@ -1,3 +1,4 @@
+use crate::io::clock::Instant;
 use crate::LimboError::InvalidModifier;
 use crate::Result;
 use crate::{types::OwnedValue, vdbe::Register};
@@ -5,24 +6,37 @@ use chrono::{
     DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone, Timelike, Utc,
 };
 
-/// Execution of date/time/datetime functions
+/// Execution of date/time/datetime functions. `now` is the time 'now' stands for, taken from
+/// the clock of the connection's IO so that it can be controlled, e.g. by the simulator.
 #[inline(always)]
-pub fn exec_date(values: &[Register]) -> OwnedValue {
-    exec_datetime(values, DateTimeOutput::Date)
+pub fn exec_date(values: &[Register], now: NaiveDateTime) -> OwnedValue {
+    exec_datetime(values, DateTimeOutput::Date, now)
 }
 
 #[inline(always)]
-pub fn exec_time(values: &[Register]) -> OwnedValue {
-    exec_datetime(values, DateTimeOutput::Time)
+pub fn exec_time(values: &[Register], now: NaiveDateTime) -> OwnedValue {
+    exec_datetime(values, DateTimeOutput::Time, now)
 }
 
 #[inline(always)]
-pub fn exec_datetime_full(values: &[Register]) -> OwnedValue {
-    exec_datetime(values, DateTimeOutput::DateTime)
+pub fn exec_datetime_full(values: &[Register], now: NaiveDateTime) -> OwnedValue {
+    exec_datetime(values, DateTimeOutput::DateTime, now)
 }
 
+/// unixepoch(): an INTEGER number of seconds since 1970-01-01, or a REAL with 'subsec'.
 #[inline(always)]
-pub fn exec_strftime(values: &[Register]) -> OwnedValue {
+pub fn exec_unixepoch(values: &[Register], now: NaiveDateTime) -> OwnedValue {
+    exec_datetime(values, DateTimeOutput::UnixEpoch, now)
+}
+
+/// julianday(): the REAL number of days since noon in Greenwich on November 24, 4714 B.C.
+#[inline(always)]
+pub fn exec_julianday(values: &[Register], now: NaiveDateTime) -> OwnedValue {
+    exec_datetime(values, DateTimeOutput::JulianDay, now)
+}
+
+#[inline(always)]
+pub fn exec_strftime(values: &[Register], now: NaiveDateTime) -> OwnedValue {
     if values.is_empty() {
         return OwnedValue::Null;
     }
@@ -37,7 +51,14 @@ pub fn exec_strftime(values: &[Register]) -> OwnedValue {
         return OwnedValue::Null;
     };
 
-    exec_datetime(&values[1..], DateTimeOutput::StrfTime(format_str))
+    exec_datetime(&values[1..], DateTimeOutput::StrfTime(format_str), now)
+}
+
+/// Converts a reading of an IO's clock to the time 'now' stands for.
+pub fn instant_to_date_time(instant: Instant) -> NaiveDateTime {
+    DateTime::from_timestamp(instant.secs, instant.micros * 1000)
+        .expect("clock reading out of range")
+        .naive_utc()
 }
 
 enum DateTimeOutput {
@@ -46,28 +67,38 @@ enum DateTimeOutput {
     DateTime,
     // Holds the format string
     StrfTime(String),
+    UnixEpoch,
+    JulianDay,
 }
 
-fn exec_datetime(values: &[Register], output_type: DateTimeOutput) -> OwnedValue {
-    if values.is_empty() {
-        let now = parse_naive_date_time(&OwnedValue::build_text("now")).unwrap();
-
-        let formatted_str = match output_type {
-            DateTimeOutput::DateTime => now.format("%Y-%m-%d %H:%M:%S").to_string(),
-            DateTimeOutput::Time => now.format("%H:%M:%S").to_string(),
-            DateTimeOutput::Date => now.format("%Y-%m-%d").to_string(),
-            DateTimeOutput::StrfTime(ref format_str) => strftime_format(&now, format_str),
-        };
+impl DateTimeOutput {
+    /// The result for an invalid time value or modifier: SQLite returns NULL from every
+    /// date/time function here, but the text functions have always returned '' in limbo.
+    fn invalid(&self) -> OwnedValue {
+        match self {
+            DateTimeOutput::UnixEpoch | DateTimeOutput::JulianDay => OwnedValue::Null,
+            _ => OwnedValue::build_text(""),
+        }
+    }
+}
 
-        // Parse here
-        return OwnedValue::build_text(&formatted_str);
+fn exec_datetime(
+    values: &[Register],
+    output_type: DateTimeOutput,
+    now: NaiveDateTime,
+) -> OwnedValue {
+    if values.is_empty() {
+        return output_dt(now, output_type, false);
     }
-    if let Some(mut dt) = parse_naive_date_time(values[0].get_owned_value()) {
+    if let Some(mut dt) = parse_unixepoch_time_value(values) {
+        // a numeric time value followed by the 'unixepoch' modifier
+        modify_dt(&mut dt, &values[2..], output_type)
+    } else if let Some(mut dt) = parse_naive_date_time(values[0].get_owned_value(), now) {
         // if successful, treat subsequent entries as modifiers
         modify_dt(&mut dt, &values[1..], output_type)
     } else {
         // if the first argument is NOT a valid date/time, treat the entire set of values as modifiers.
-        let mut dt = chrono::Local::now().to_utc().naive_utc();
+        let mut dt = now;
         modify_dt(&mut dt, values, output_type)
     }
 }
@@ -82,17 +113,31 @@ fn modify_dt(dt: &mut NaiveDateTime, mods: &[Register], output_type: DateTimeOut
             match apply_modifier(dt, text_rc.as_str()) {
                 Ok(true) => subsec_requested = true,
                 Ok(false) => {}
-                Err(_) => return OwnedValue::build_text(""),
+                Err(_) => return output_type.invalid(),
             }
         } else {
-            return OwnedValue::build_text("");
+            return output_type.invalid();
         }
     }
     if is_leap_second(dt) || *dt > get_max_datetime_exclusive() {
-        return OwnedValue::build_text("");
+        return output_type.invalid();
+    }
+    output_dt(*dt, output_type, subsec_requested)
+}
+
+fn output_dt(dt: NaiveDateTime, output_type: DateTimeOutput, subsec: bool) -> OwnedValue {
+    match output_type {
+        DateTimeOutput::UnixEpoch => {
+            let utc = dt.and_utc();
+            if subsec {
+                OwnedValue::Float(utc.timestamp_millis() as f64 / 1000.0)
+            } else {
+                OwnedValue::Integer(utc.timestamp())
+            }
+        }
+        DateTimeOutput::JulianDay => OwnedValue::Float(to_julian_day(&dt)),
+        _ => OwnedValue::build_text(&format_dt(dt, output_type, subsec)),
     }
-    let formatted = format_dt(*dt, output_type, subsec_requested);
-    OwnedValue::build_text(&formatted)
 }
 
 fn format_dt(dt: NaiveDateTime, output_type: DateTimeOutput, subsec: bool) -> String {
@@ -113,6 +158,7 @@ fn format_dt(dt: NaiveDateTime, output_type: DateTimeOutput, subsec: bool) -> St
             }
         }
         DateTimeOutput::StrfTime(format_str) => strftime_format(&dt, &format_str),
+        DateTimeOutput::UnixEpoch | DateTimeOutput::JulianDay => unreachable!(),
     }
 }
 
@@ -325,14 +371,12 @@ fn last_day_in_month(year: i32, month: u32) -> u32 {
     28
 }
 
-pub fn exec_julianday(time_value: &OwnedValue) -> Result<String> {
-    let dt = parse_naive_date_time(time_value);
-    match dt {
-        // if we did something heinous like: parse::<f64>().unwrap().to_string()
-        // that would solve the precision issue, but dear lord...
-        Some(dt) => Ok(format!("{:.1$}", to_julian_day_exact(&dt), 8)),
-        None => Ok(String::new()),
-    }
+/// The julian day of `dt` the way SQLite computes it: from the time in whole milliseconds, so
+/// that e.g. julianday() of a unixepoch() value round trips.
+fn to_julian_day(dt: &NaiveDateTime) -> f64 {
+    // milliseconds between the julian day epoch and 1970-01-01
+    const UNIX_EPOCH_JD_MILLIS: i64 = 210_866_760_000_000;
+    (dt.and_utc().timestamp_millis() + UNIX_EPOCH_JD_MILLIS) as f64 / 86_400_000.0
 }
 
 fn to_julian_day_exact(dt: &NaiveDateTime) -> f64 {
@@ -362,31 +406,38 @@ fn to_julian_day_exact(dt: &NaiveDateTime) -> f64 {
     jd_days + jd_fraction
 }
 
-pub fn exec_unixepoch(time_value: &OwnedValue) -> Result<String> {
-    let dt = parse_naive_date_time(time_value);
-    match dt {
-        Some(dt) => Ok(get_unixepoch_from_naive_datetime(dt)),
-        None => Ok(String::new()),
+/// Interprets `values[0]` as seconds since 1970-01-01 if it is a number followed by the
+/// 'unixepoch' modifier.
+fn parse_unixepoch_time_value(values: &[Register]) -> Option<NaiveDateTime> {
+    let modifier = values.get(1)?.get_owned_value();
+    if !matches!(modifier, OwnedValue::Text(t) if t.as_str().trim().eq_ignore_ascii_case("unixepoch"))
+    {
+        return None;
     }
-}
-
-fn get_unixepoch_from_naive_datetime(value: NaiveDateTime) -> String {
-    if is_leap_second(&value) {
-        return String::new();
+    let seconds = match values[0].get_owned_value() {
+        OwnedValue::Integer(i) => *i as f64,
+        OwnedValue::Float(f) => *f,
+        OwnedValue::Text(t) => t.as_str().trim().parse::<f64>().ok()?,
+        _ => return None,
+    };
+    let millis = (seconds * 1000.0).round();
+    if !millis.is_finite() {
+        return None;
     }
-    value.and_utc().timestamp().to_string()
+    DateTime::from_timestamp_millis(millis as i64).map(|dt| dt.naive_utc())
 }
 
-fn parse_naive_date_time(time_value: &OwnedValue) -> Option<NaiveDateTime> {
+/// Reads a time value like the date/time functions do, None if it isn't one.
+pub fn parse_naive_date_time(time_value: &OwnedValue, now: NaiveDateTime) -> Option<NaiveDateTime> {
     match time_value {
-        OwnedValue::Text(s) => get_date_time_from_time_value_string(s.as_str()),
+        OwnedValue::Text(s) => get_date_time_from_time_value_string(s.as_str(), now),
         OwnedValue::Integer(i) => get_date_time_from_time_value_integer(*i),
         OwnedValue::Float(f) => get_date_time_from_time_value_float(*f),
         _ => None,
     }
 }
 
-fn get_date_time_from_time_value_string(value: &str) -> Option<NaiveDateTime> {
+fn get_date_time_from_time_value_string(value: &str, now: NaiveDateTime) -> Option<NaiveDateTime> {
     // Time-value formats:
     // 1-7. YYYY-MM-DD[THH:MM[:SS[.SSS]]]
     // 8-10. HH:MM[:SS[.SSS]]
@@ -397,7 +448,7 @@ fn get_date_time_from_time_value_string(value: &str) -> Option<NaiveDateTime> {
 
     // Check for 'now'
     if value.trim().eq_ignore_ascii_case("now") {
-        return Some(chrono::Local::now().to_utc().naive_utc());
+        return Some(now);
     }
 
     // Check for Julian day number (integer or float)
@@ -660,9 +711,14 @@ fn parse_modifier(modifier: &str) -> Result<Modifier> {
 mod tests {
     use super::*;
 
+    fn now() -> NaiveDateTime {
+        Utc::now().naive_utc()
+    }
+
     #[test]
     fn test_valid_get_date_from_time_value() {
-        let now = chrono::Local::now().to_utc().format("%Y-%m-%d").to_string();
+        let now = now();
+        let today = now.format("%Y-%m-%d").to_string();
 
         let prev_date_str = "2024-07-20";
         let test_date_str = "2024-07-21";
@@ -798,14 +854,14 @@ mod tests {
             (OwnedValue::build_text("01:30:45.123+05:00"), "1999-12-31"),
             (OwnedValue::build_text("22:30:45.123Z"), "2000-01-01"),
             // Test Format 11: 'now'
-            (OwnedValue::build_text("now"), &now),
+            (OwnedValue::build_text("now"), &today),
             // Format 12: DDDDDDDDDD (Julian date as float or integer)
             (OwnedValue::Float(2460512.5), test_date_str),
             (OwnedValue::Integer(2460513), test_date_str),
         ];
 
         for (input, expected) in test_cases {
-            let result = exec_date(&[Register::OwnedValue(input.clone())]);
+            let result = exec_date(&[Register::OwnedValue(input.clone())], now);
             assert_eq!(
                 result,
                 OwnedValue::build_text(expected),
@@ -846,7 +902,7 @@ mod tests {
         ];
 
         for case in invalid_cases.iter() {
-            let result = exec_date(&[Register::OwnedValue(case.clone())]);
+            let result = exec_date(&[Register::OwnedValue(case.clone())], now());
             match result {
                 OwnedValue::Text(ref result_str) if result_str.value.is_empty() => (),
                 _ => panic!(
@@ -959,7 +1015,7 @@ mod tests {
         ];
 
         for (input, expected) in test_cases {
-            let result = exec_time(&[Register::OwnedValue(input)]);
+            let result = exec_time(&[Register::OwnedValue(input)], now());
             if let OwnedValue::Text(result_str) = result {
                 assert_eq!(result_str.as_str(), expected);
             } else {
@@ -999,7 +1055,7 @@ mod tests {
         ];
 
         for case in invalid_cases {
-            let result = exec_time(&[Register::OwnedValue(case.clone())]);
+            let result = exec_time(&[Register::OwnedValue(case.clone())], now());
             match result {
                 OwnedValue::Text(ref result_str) if result_str.value.is_empty() => (),
                 _ => panic!(
@@ -1319,6 +1375,7 @@ mod tests {
         let result = exec_datetime(
             &[text("2023-06-15 12:30:45"), text("-1 day")],
             DateTimeOutput::DateTime,
+            now(),
         );
         assert_eq!(result, *text(&expected).get_owned_value());
     }
@@ -1334,6 +1391,7 @@ mod tests {
                 text("+3 hours"),
             ],
             DateTimeOutput::DateTime,
+            now(),
         );
         assert_eq!(result, *text(&expected).get_owned_value());
     }
@@ -1344,6 +1402,7 @@ mod tests {
         let result = exec_datetime(
             &[text("2023-06-15 12:30:45"), text("subsec")],
             DateTimeOutput::Time,
+            now(),
         );
         let result = NaiveTime::parse_from_str(&result.to_string(), "%H:%M:%S%.3f").unwrap();
         assert_eq!(time.time(), result);
@@ -1361,6 +1420,7 @@ mod tests {
                 text("-1 day"),
             ],
             DateTimeOutput::DateTime,
+            now(),
         );
         assert_eq!(result, *text(&expected).get_owned_value());
     }
@@ -1380,6 +1440,7 @@ mod tests {
                 text("+1 day"),
             ],
             DateTimeOutput::DateTime,
+            now(),
         );
         assert_eq!(result, *text(&expected).get_owned_value());
     }
@@ -1400,6 +1461,7 @@ mod tests {
                 text("+5 hours"),
             ],
             DateTimeOutput::DateTime,
+            now(),
         );
         assert_eq!(result, *text(&expected).get_owned_value());
     }
@@ -1410,6 +1472,7 @@ mod tests {
         let result_local = exec_datetime(
             &[text("2023-06-15 12:30:45"), text("localtime")],
             DateTimeOutput::DateTime,
+            now(),
         );
         assert_eq!(
             result_local,
@@ -1442,6 +1505,7 @@ mod tests {
                 text("subsec"),
             ],
             DateTimeOutput::DateTime,
+            now(),
         );
         let result =
             NaiveDateTime::parse_from_str(&result.to_string(), "%Y-%m-%d %H:%M:%S%.3f").unwrap();
@@ -1456,7 +1520,11 @@ mod tests {
             .and_hms_opt(23, 59, 59)
             .unwrap();
         let expected = format(max);
-        let result = exec_datetime(&[text("9999-12-31 23:59:59")], DateTimeOutput::DateTime);
+        let result = exec_datetime(
+            &[text("9999-12-31 23:59:59")],
+            DateTimeOutput::DateTime,
+            now(),
+        );
         assert_eq!(result, *text(&expected).get_owned_value());
     }
 
@@ -1468,7 +1536,11 @@ mod tests {
             .and_hms_nano_opt(23, 59, 59, 1_500_000_000)
             .unwrap();
         let expected = String::new(); // SQLite ignores leap seconds
-        let result = exec_datetime(&[text(&leap_second.to_string())], DateTimeOutput::DateTime);
+        let result = exec_datetime(
+            &[text(&leap_second.to_string())],
+            DateTimeOutput::DateTime,
+            now(),
+        );
         assert_eq!(result, *text(&expected).get_owned_value());
     }
 
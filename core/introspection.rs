@@ -0,0 +1,152 @@
+//! Typed view of a connection's schema, for tools that would otherwise have to parse the SQL
+//! text stored in sqlite_schema. The results are built from the schema the connection plans
+//! with, so they reflect every table and index it can use, see [crate::Connection::tables].
+
+use crate::schema::{ForeignKey, Index, Schema, Table};
+use crate::util::normalize_ident;
+use crate::{LimboError, Result};
+use limbo_sqlite3_parser::ast::SortOrder;
+
+/// A table of the schema, like a row of `PRAGMA table_list`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableInfo {
+    pub name: String,
+    /// Root page of the table's b-tree, 0 for virtual tables.
+    pub root_page: usize,
+    /// False for `WITHOUT ROWID` tables, and for virtual tables.
+    pub has_rowid: bool,
+    pub is_virtual: bool,
+}
+
+/// A column of a table, like a row of `PRAGMA table_info`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnInfo {
+    pub name: String,
+    /// The type as declared, empty if the column has none.
+    pub declared_type: String,
+    pub not_null: bool,
+    /// The `DEFAULT` expression as SQL text.
+    pub default_value: Option<String>,
+    /// Position of the column in the primary key starting at 1, 0 if it isn't part of it.
+    pub primary_key: usize,
+    /// Whether the column is an `INTEGER PRIMARY KEY` and so the rowid of the table.
+    pub is_rowid_alias: bool,
+}
+
+/// An index of a table, with what `PRAGMA index_list` and `PRAGMA index_info` report about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexInfo {
+    pub name: String,
+    pub table_name: String,
+    pub root_page: usize,
+    pub unique: bool,
+    /// Created for a `PRIMARY KEY` or `UNIQUE` constraint rather than by `CREATE INDEX`.
+    pub automatic: bool,
+    pub columns: Vec<IndexColumnInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexColumnInfo {
+    pub name: String,
+    pub descending: bool,
+}
+
+/// Every table of `schema` ordered by name, including sqlite_schema itself.
+pub(crate) fn tables(schema: &Schema) -> Vec<TableInfo> {
+    let mut tables: Vec<TableInfo> = schema
+        .tables
+        .values()
+        .filter_map(|table| match table.as_ref() {
+            Table::BTree(table) => Some(TableInfo {
+                name: table.name.clone(),
+                root_page: table.root_page,
+                has_rowid: table.has_rowid,
+                is_virtual: false,
+            }),
+            Table::Virtual(table) => Some(TableInfo {
+                name: table.name.clone(),
+                root_page: 0,
+                has_rowid: false,
+                is_virtual: true,
+            }),
+            Table::Pseudo(_) => None,
+        })
+        .collect();
+    tables.sort_by(|a, b| a.name.cmp(&b.name));
+    tables
+}
+
+pub(crate) fn columns(schema: &Schema, table_name: &str) -> Result<Vec<ColumnInfo>> {
+    let table = get_table(schema, table_name)?;
+    let primary_key_column_names = table
+        .btree()
+        .map(|table| table.primary_key_column_names.clone())
+        .unwrap_or_default();
+    Ok(table
+        .columns()
+        .iter()
+        .map(|column| {
+            let name = column.name.clone().unwrap_or_default();
+            let primary_key = if column.primary_key {
+                primary_key_column_names
+                    .iter()
+                    .position(|pk| normalize_ident(pk) == name)
+                    .map_or(1, |position| position + 1)
+            } else {
+                0
+            };
+            ColumnInfo {
+                name,
+                declared_type: column.ty_str.clone(),
+                not_null: column.notnull,
+                default_value: column.default.as_ref().map(|expr| expr.to_string()),
+                primary_key,
+                is_rowid_alias: column.is_rowid_alias,
+            }
+        })
+        .collect())
+}
+
+/// The indexes of a table ordered by name.
+pub(crate) fn indexes(schema: &Schema, table_name: &str) -> Result<Vec<IndexInfo>> {
+    let table = get_table(schema, table_name)?;
+    let mut indexes: Vec<IndexInfo> = schema
+        .get_indices(table.get_name())
+        .iter()
+        .map(|index| index_info(index))
+        .collect();
+    indexes.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(indexes)
+}
+
+pub(crate) fn foreign_keys(schema: &Schema, table_name: &str) -> Result<Vec<ForeignKey>> {
+    let table = get_table(schema, table_name)?;
+    Ok(table
+        .btree()
+        .map(|table| table.foreign_keys.clone())
+        .unwrap_or_default())
+}
+
+fn get_table(schema: &Schema, table_name: &str) -> Result<std::sync::Arc<Table>> {
+    schema
+        .get_table(table_name)
+        .ok_or_else(|| LimboError::InvalidArgument(format!("no such table: {}", table_name)))
+}
+
+fn index_info(index: &Index) -> IndexInfo {
+    IndexInfo {
+        name: index.name.clone(),
+        table_name: index.table_name.clone(),
+        root_page: index.root_page,
+        unique: index.unique,
+        automatic: index.name.starts_with("sqlite_autoindex_"),
+        columns: index
+            .columns
+            .iter()
+            .map(|column| IndexColumnInfo {
+                name: column.name.clone(),
+                descending: column.order == SortOrder::Desc,
+            })
+            .collect(),
+    }
+}
@@ -4,6 +4,13 @@ pub struct Instant {
     pub micros: u32,
 }
 
+impl Instant {
+    /// Microseconds from `earlier` to this instant.
+    pub fn micros_since(&self, earlier: Instant) -> i64 {
+        (self.secs - earlier.secs) * 1_000_000 + self.micros as i64 - earlier.micros as i64
+    }
+}
+
 pub trait Clock {
     fn now(&self) -> Instant;
 }
@@ -1,5 +1,55 @@
 pub const ENV_DISABLE_FILE_LOCK: &str = "LIMBO_DISABLE_FILE_LOCK";
 
+/// A path in the system temp directory that no file has yet, for [crate::IO::open_temp_file].
+#[cfg(target_family = "unix")]
+pub fn unused_temp_path(io: &dyn crate::IO) -> std::path::PathBuf {
+    loop {
+        let name = format!(
+            "limbo-{}-{:016x}",
+            std::process::id(),
+            io.generate_random_number() as u64
+        );
+        let path = std::env::temp_dir().join(name);
+        if !path.exists() {
+            return path;
+        }
+    }
+}
+
+/// What a file opened by one of the unix backends guarantees, see [super::DeviceCharacteristics].
+///
+/// Local file systems are assumed to not damage data outside of a torn write, as SQLite does.
+/// On Linux the sector size is the alignment direct IO requires, which is the logical block size
+/// of the device, and F2FS reports its atomic writes.
+#[cfg(target_family = "unix")]
+pub fn device_characteristics(file: &std::fs::File) -> super::DeviceCharacteristics {
+    let mut characteristics = super::DeviceCharacteristics {
+        powersafe_overwrite: true,
+        ..Default::default()
+    };
+    if let Ok(flags) = rustix::fs::fcntl_getfl(file) {
+        // O_SYNC includes the O_DSYNC bit.
+        characteristics.sync_on_write = flags.bits() & libc::O_DSYNC as u32 != 0;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        use rustix::fs::{AtFlags, StatxFlags};
+
+        /// `F2FS_SUPER_MAGIC` from linux/magic.h.
+        const F2FS_SUPER_MAGIC: u32 = 0xF2F52010;
+
+        if let Ok(statx) = rustix::fs::statx(file, "", AtFlags::EMPTY_PATH, StatxFlags::DIOALIGN) {
+            if statx.stx_mask & StatxFlags::DIOALIGN.bits() != 0 && statx.stx_dio_offset_align > 0 {
+                characteristics.sector_size = statx.stx_dio_offset_align as usize;
+            }
+        }
+        if let Ok(statfs) = rustix::fs::fstatfs(file) {
+            characteristics.batch_atomic_write = statfs.f_type as u32 == F2FS_SUPER_MAGIC;
+        }
+    }
+    characteristics
+}
+
 #[cfg(test)]
 pub mod tests {
     use crate::{Result, IO};
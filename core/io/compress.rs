@@ -0,0 +1,722 @@
+//! A shim that stacks on top of another [IO] and stores the database file with
+//! compressed pages and per-page checksums, for read-mostly databases where
+//! the size on disk matters.
+//!
+//! The database file is split into fixed-size blocks that are stored as
+//! variable-sized extents, located through an index. A write stores the new
+//! version of its blocks in free space, and a sync writes a new index and then
+//! points the header at it, so the file on disk always holds a consistent
+//! version. Extents replaced since the previous sync are reused only after the
+//! next one. The WAL and journal are passed through unchanged.
+use super::clock::Instant;
+use super::{
+    Buffer, Clock, Completion, DeviceCharacteristics, File, OpenFlags, ReadCompletion,
+    SyncCompletion, WriteCompletion, IO,
+};
+use crate::{LimboError, Result};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap},
+    rc::Rc,
+    sync::Arc,
+};
+use tracing::debug;
+
+const MAGIC: &[u8; 16] = b"limbo compressed";
+const HEADER_SIZE: usize = 512;
+const BLOCK_SIZE: usize = 4096;
+const INDEX_ENTRY_SIZE: usize = 24;
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Zstd,
+    Lz4,
+}
+
+impl Compression {
+    fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Zstd),
+            2 => Ok(Self::Lz4),
+            _ => Err(LimboError::Corrupt(format!(
+                "unknown compression {}",
+                value
+            ))),
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Zstd => 1,
+            Self::Lz4 => 2,
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL)
+                .map_err(|e| LimboError::InternalError(format!("zstd: {}", e))),
+            Self::Lz4 => Ok(lz4_flex::block::compress(data)),
+        }
+    }
+
+    fn decompress(self, data: &[u8], size: usize) -> Result<Vec<u8>> {
+        let decompressed = match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Zstd => zstd::bulk::decompress(data, size).map_err(|e| e.to_string()),
+            Self::Lz4 => lz4_flex::block::decompress(data, size).map_err(|e| e.to_string()),
+        };
+        match decompressed {
+            Ok(block) if block.len() == size => Ok(block),
+            Ok(_) => Err(LimboError::Corrupt("short compressed block".to_string())),
+            Err(e) => Err(LimboError::Corrupt(format!("compressed block: {}", e))),
+        }
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = LimboError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Self::None),
+            "zstd" => Ok(Self::Zstd),
+            "lz4" => Ok(Self::Lz4),
+            _ => Err(LimboError::InvalidArgument(format!(
+                "unknown compression '{}', expected zstd, lz4 or none",
+                s
+            ))),
+        }
+    }
+}
+
+/// How new database files are stored. Existing files keep the options they
+/// were created with.
+#[derive(Debug, Clone, Copy)]
+pub struct CompressionOptions {
+    pub compression: Compression,
+    pub checksum: bool,
+}
+
+pub struct CompressedIO {
+    inner: Arc<dyn IO>,
+    options: CompressionOptions,
+}
+
+impl CompressedIO {
+    pub fn new(inner: Arc<dyn IO>, options: CompressionOptions) -> Self {
+        debug!("Using compression shim {:?}", options);
+        Self { inner, options }
+    }
+}
+
+impl Clock for CompressedIO {
+    fn now(&self) -> Instant {
+        self.inner.now()
+    }
+}
+
+impl IO for CompressedIO {
+    fn open_file(&self, path: &str, flags: OpenFlags, direct: bool) -> Result<Arc<dyn File>> {
+        if path.ends_with("-wal") || path.ends_with("-journal") {
+            return self.inner.open_file(path, flags, direct);
+        }
+        // Blocks are stored at arbitrary offsets, which direct I/O does not allow.
+        let file = self.inner.open_file(path, flags, false)?;
+        Ok(Arc::new(CompressedFile::open(
+            self.inner.clone(),
+            file,
+            self.options,
+        )?))
+    }
+
+    fn open_temp_file(&self) -> Result<Arc<dyn File>> {
+        self.inner.open_temp_file()
+    }
+
+    fn run_once(&self) -> Result<()> {
+        self.inner.run_once()
+    }
+
+    fn generate_random_number(&self) -> i64 {
+        self.inner.generate_random_number()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Extent {
+    offset: u64,
+    len: u32,
+    /// CRC32 of the uncompressed block, or 0 without checksums.
+    checksum: u32,
+}
+
+struct State {
+    compression: Compression,
+    checksum: bool,
+    /// Logical size of the file.
+    size: u64,
+    blocks: HashMap<u64, Extent>,
+    /// Extent holding the index the header points at.
+    index: Option<(u64, u32)>,
+    /// Free ranges below `end`, by offset.
+    free: BTreeMap<u64, u64>,
+    /// Everything from here on is free.
+    end: u64,
+    /// Extents that the index on disk still refers to, freed by the next sync.
+    pending_free: Vec<(u64, u64)>,
+    dirty: bool,
+}
+
+impl State {
+    fn allocate(&mut self, len: u64) -> u64 {
+        let found = self
+            .free
+            .iter()
+            .find(|(_, free_len)| **free_len >= len)
+            .map(|(offset, free_len)| (*offset, *free_len));
+        match found {
+            Some((offset, free_len)) => {
+                self.free.remove(&offset);
+                if free_len > len {
+                    self.free.insert(offset + len, free_len - len);
+                }
+                offset
+            }
+            None => {
+                let offset = self.end;
+                self.end += len;
+                offset
+            }
+        }
+    }
+
+    fn release(&mut self, mut offset: u64, mut len: u64) {
+        if let Some((&prev, &prev_len)) = self.free.range(..offset).next_back() {
+            if prev + prev_len == offset {
+                self.free.remove(&prev);
+                offset = prev;
+                len += prev_len;
+            }
+        }
+        if let Some(next_len) = self.free.remove(&(offset + len)) {
+            len += next_len;
+        }
+        if offset + len == self.end {
+            self.end = offset;
+        } else {
+            self.free.insert(offset, len);
+        }
+    }
+
+    fn encode_index(&self) -> Vec<u8> {
+        let mut index = Vec::with_capacity(12 + self.blocks.len() * INDEX_ENTRY_SIZE);
+        index.extend_from_slice(&self.size.to_be_bytes());
+        index.extend_from_slice(&(self.blocks.len() as u32).to_be_bytes());
+        for (block, extent) in &self.blocks {
+            index.extend_from_slice(&block.to_be_bytes());
+            index.extend_from_slice(&extent.offset.to_be_bytes());
+            index.extend_from_slice(&extent.len.to_be_bytes());
+            index.extend_from_slice(&extent.checksum.to_be_bytes());
+        }
+        index
+    }
+
+    fn decode_index(&mut self, index: &[u8]) -> Result<()> {
+        let corrupt = || LimboError::Corrupt("truncated compression index".to_string());
+        let size = u64::from_be_bytes(index.get(0..8).ok_or_else(corrupt)?.try_into().unwrap());
+        let count = u32::from_be_bytes(index.get(8..12).ok_or_else(corrupt)?.try_into().unwrap());
+        let entries = index.get(12..).ok_or_else(corrupt)?;
+        if entries.len() != count as usize * INDEX_ENTRY_SIZE {
+            return Err(corrupt());
+        }
+        self.size = size;
+        for entry in entries.chunks_exact(INDEX_ENTRY_SIZE) {
+            let block = u64::from_be_bytes(entry[0..8].try_into().unwrap());
+            let extent = Extent {
+                offset: u64::from_be_bytes(entry[8..16].try_into().unwrap()),
+                len: u32::from_be_bytes(entry[16..20].try_into().unwrap()),
+                checksum: u32::from_be_bytes(entry[20..24].try_into().unwrap()),
+            };
+            self.blocks.insert(block, extent);
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the free space as everything between the header and the end of
+    /// the last extent that is not used by a block or the index.
+    fn rebuild_free_space(&mut self) {
+        let mut used: Vec<(u64, u64)> = self
+            .blocks
+            .values()
+            .map(|extent| (extent.offset, extent.len as u64))
+            .chain(self.index.map(|(offset, len)| (offset, len as u64)))
+            .collect();
+        used.sort_unstable();
+        let mut pos = HEADER_SIZE as u64;
+        for (offset, len) in used {
+            if offset > pos {
+                self.free.insert(pos, offset - pos);
+            }
+            pos = pos.max(offset + len);
+        }
+        self.end = pos;
+    }
+
+    fn encode_header(&self) -> Vec<u8> {
+        let (index_offset, index_len) = self.index.unwrap_or((0, 0));
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(MAGIC);
+        header.push(self.compression.to_u8());
+        header.push(self.checksum as u8);
+        header.extend_from_slice(&[0, 0]);
+        header.extend_from_slice(&(BLOCK_SIZE as u32).to_be_bytes());
+        header.extend_from_slice(&index_offset.to_be_bytes());
+        header.extend_from_slice(&index_len.to_be_bytes());
+        header
+    }
+}
+
+/// A database file stored through [CompressedIO].
+pub struct CompressedFile {
+    io: Arc<dyn IO>,
+    inner: Arc<dyn File>,
+    state: RefCell<State>,
+}
+
+unsafe impl Send for CompressedFile {}
+unsafe impl Sync for CompressedFile {}
+
+impl CompressedFile {
+    fn open(io: Arc<dyn IO>, inner: Arc<dyn File>, options: CompressionOptions) -> Result<Self> {
+        let file = Self {
+            io,
+            inner,
+            state: RefCell::new(State {
+                compression: options.compression,
+                checksum: options.checksum,
+                size: 0,
+                blocks: HashMap::new(),
+                index: None,
+                free: BTreeMap::new(),
+                end: HEADER_SIZE as u64,
+                pending_free: Vec::new(),
+                dirty: false,
+            }),
+        };
+        if file.inner.size()? == 0 {
+            file.state.borrow_mut().dirty = true;
+            return Ok(file);
+        }
+        let header = file.read_at(0, HEADER_SIZE)?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(LimboError::InvalidArgument(
+                "not a compressed database file".to_string(),
+            ));
+        }
+        let header_checksum = u32::from_be_bytes(header[40..44].try_into().unwrap());
+        if crc32fast::hash(&header[..40]) != header_checksum {
+            return Err(LimboError::Corrupt(
+                "compression header checksum mismatch".to_string(),
+            ));
+        }
+        let block_size = u32::from_be_bytes(header[20..24].try_into().unwrap());
+        if block_size as usize != BLOCK_SIZE {
+            return Err(LimboError::Corrupt(format!(
+                "unsupported compression block size {}",
+                block_size
+            )));
+        }
+        let index_offset = u64::from_be_bytes(header[24..32].try_into().unwrap());
+        let index_len = u32::from_be_bytes(header[32..36].try_into().unwrap());
+        let index_checksum = u32::from_be_bytes(header[36..40].try_into().unwrap());
+        {
+            let mut state = file.state.borrow_mut();
+            state.compression = Compression::from_u8(header[16])?;
+            state.checksum = header[17] != 0;
+            if index_len > 0 {
+                let index = file.read_at(index_offset, index_len as usize)?;
+                if crc32fast::hash(&index) != index_checksum {
+                    return Err(LimboError::Corrupt(
+                        "compression index checksum mismatch".to_string(),
+                    ));
+                }
+                state.decode_index(&index)?;
+                state.index = Some((index_offset, index_len));
+            }
+            state.rebuild_free_space();
+        }
+        Ok(file)
+    }
+
+    /// Waits for a completion of the inner file by driving its IO.
+    fn wait(&self, done: &Cell<bool>) -> Result<()> {
+        while !done.get() {
+            self.io.run_once()?;
+        }
+        Ok(())
+    }
+
+    fn read_at(&self, pos: u64, len: usize) -> Result<Vec<u8>> {
+        let drop_fn = Rc::new(|_| {});
+        #[allow(clippy::arc_with_non_send_sync)]
+        let buf = Arc::new(RefCell::new(Buffer::allocate(len, drop_fn)));
+        let done = Rc::new(Cell::new(false));
+        let c = {
+            let done = done.clone();
+            Completion::Read(ReadCompletion::new(
+                buf.clone(),
+                Box::new(move |_| done.set(true)),
+            ))
+        };
+        self.inner.pread(pos as usize, c)?;
+        self.wait(&done)?;
+        let data = buf.borrow().as_slice().to_vec();
+        Ok(data)
+    }
+
+    fn write_at(&self, pos: u64, data: Vec<u8>) -> Result<()> {
+        let drop_fn = Rc::new(|_| {});
+        #[allow(clippy::arc_with_non_send_sync)]
+        let buf = Arc::new(RefCell::new(Buffer::new(std::pin::Pin::new(data), drop_fn)));
+        let done = Rc::new(Cell::new(false));
+        let c = {
+            let done = done.clone();
+            Completion::Write(WriteCompletion::new(Box::new(move |_| done.set(true))))
+        };
+        self.inner.pwrite(pos as usize, buf, c)?;
+        self.wait(&done)
+    }
+
+    fn sync_inner(&self) -> Result<()> {
+        let done = Rc::new(Cell::new(false));
+        let c = {
+            let done = done.clone();
+            Completion::Sync(SyncCompletion::new(Box::new(move |_| done.set(true))))
+        };
+        self.inner.sync(c)?;
+        self.wait(&done)
+    }
+
+    /// Returns the contents of `block`, which reads as zeroes if it was never written.
+    fn read_block(&self, state: &State, block: u64) -> Result<Vec<u8>> {
+        let Some(extent) = state.blocks.get(&block) else {
+            return Ok(vec![0; BLOCK_SIZE]);
+        };
+        let stored = self.read_at(extent.offset, extent.len as usize)?;
+        // Blocks that do not shrink when compressed are stored as they are.
+        let data = if stored.len() == BLOCK_SIZE {
+            stored
+        } else {
+            state.compression.decompress(&stored, BLOCK_SIZE)?
+        };
+        if state.checksum && crc32fast::hash(&data) != extent.checksum {
+            return Err(LimboError::Corrupt(format!(
+                "checksum mismatch in block {}",
+                block
+            )));
+        }
+        Ok(data)
+    }
+
+    fn write_block(&self, state: &mut State, block: u64, data: &[u8]) -> Result<()> {
+        let checksum = if state.checksum {
+            crc32fast::hash(data)
+        } else {
+            0
+        };
+        let mut stored = state.compression.compress(data)?;
+        if stored.len() >= BLOCK_SIZE {
+            stored = data.to_vec();
+        }
+        let len = stored.len() as u32;
+        let offset = state.allocate(len as u64);
+        self.write_at(offset, stored)?;
+        let extent = Extent {
+            offset,
+            len,
+            checksum,
+        };
+        if let Some(old) = state.blocks.insert(block, extent) {
+            state.pending_free.push((old.offset, old.len as u64));
+        }
+        state.dirty = true;
+        Ok(())
+    }
+
+    /// Writes a new index, then points the header at it.
+    fn commit(&self) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        if !state.dirty {
+            return self.sync_inner();
+        }
+        let index = state.encode_index();
+        let index_len = index.len() as u32;
+        let index_checksum = crc32fast::hash(&index);
+        let index_offset = state.allocate(index_len as u64);
+        self.write_at(index_offset, index)?;
+        self.sync_inner()?;
+
+        let old_index = state.index.replace((index_offset, index_len));
+        let mut header = state.encode_header();
+        header.extend_from_slice(&index_checksum.to_be_bytes());
+        header.extend_from_slice(&crc32fast::hash(&header).to_be_bytes());
+        header.resize(HEADER_SIZE, 0);
+        self.write_at(0, header)?;
+        self.sync_inner()?;
+
+        if let Some((offset, len)) = old_index {
+            state.release(offset, len as u64);
+        }
+        for (offset, len) in std::mem::take(&mut state.pending_free) {
+            state.release(offset, len);
+        }
+        state.dirty = false;
+        Ok(())
+    }
+}
+
+impl File for CompressedFile {
+    fn lock_file(&self, exclusive: bool) -> Result<()> {
+        self.inner.lock_file(exclusive)
+    }
+
+    fn unlock_file(&self) -> Result<()> {
+        self.inner.unlock_file()
+    }
+
+    fn pread(&self, pos: usize, c: Completion) -> Result<()> {
+        {
+            let state = self.state.borrow();
+            let r = c.as_read();
+            let mut buf = r.buf_mut();
+            let buf = buf.as_mut_slice();
+            let end = pos + buf.len();
+            let mut offset = pos;
+            while offset < end {
+                let block = (offset / BLOCK_SIZE) as u64;
+                let start = offset % BLOCK_SIZE;
+                let len = (BLOCK_SIZE - start).min(end - offset);
+                let dst = &mut buf[offset - pos..offset - pos + len];
+                if (offset as u64) < state.size {
+                    dst.copy_from_slice(&self.read_block(&state, block)?[start..start + len]);
+                } else {
+                    dst.fill(0);
+                }
+                offset += len;
+            }
+        }
+        c.complete(0);
+        Ok(())
+    }
+
+    fn pwrite(&self, pos: usize, buffer: Arc<RefCell<Buffer>>, c: Completion) -> Result<()> {
+        let written = {
+            let mut state = self.state.borrow_mut();
+            let buf = buffer.borrow();
+            let buf = buf.as_slice();
+            let end = pos + buf.len();
+            let mut offset = pos;
+            while offset < end {
+                let block = (offset / BLOCK_SIZE) as u64;
+                let start = offset % BLOCK_SIZE;
+                let len = (BLOCK_SIZE - start).min(end - offset);
+                let src = &buf[offset - pos..offset - pos + len];
+                if len == BLOCK_SIZE {
+                    self.write_block(&mut state, block, src)?;
+                } else {
+                    let mut data = self.read_block(&state, block)?;
+                    data[start..start + len].copy_from_slice(src);
+                    self.write_block(&mut state, block, &data)?;
+                }
+                offset += len;
+            }
+            state.size = state.size.max(end as u64);
+            buf.len()
+        };
+        c.complete(written as i32);
+        Ok(())
+    }
+
+    fn sync(&self, c: Completion) -> Result<()> {
+        self.commit()?;
+        c.complete(0);
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.state.borrow().size)
+    }
+
+    fn truncate(&self, len: usize) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        let first_dropped = len.div_ceil(BLOCK_SIZE) as u64;
+        let dropped: Vec<u64> = state
+            .blocks
+            .keys()
+            .copied()
+            .filter(|block| *block >= first_dropped)
+            .collect();
+        for block in dropped {
+            let extent = state.blocks.remove(&block).unwrap();
+            state.pending_free.push((extent.offset, extent.len as u64));
+        }
+        // Zero the tail of the last block, so that extending the file again reads back zeroes.
+        if len % BLOCK_SIZE != 0 && state.blocks.contains_key(&((len / BLOCK_SIZE) as u64)) {
+            let block = (len / BLOCK_SIZE) as u64;
+            let mut data = self.read_block(&state, block)?;
+            data[len % BLOCK_SIZE..].fill(0);
+            self.write_block(&mut state, block, &data)?;
+        }
+        state.size = len as u64;
+        state.dirty = true;
+        Ok(())
+    }
+
+    fn device_characteristics(&self) -> DeviceCharacteristics {
+        // Writes only become durable with the index written by sync, and rewrite whole blocks.
+        let inner = self.inner.device_characteristics();
+        DeviceCharacteristics {
+            sector_size: inner.sector_size.max(BLOCK_SIZE),
+            sync_on_write: false,
+            ..inner
+        }
+    }
+}
+
+impl Drop for CompressedFile {
+    fn drop(&mut self) {
+        // Writes that were never synced, like the first page of a new database,
+        // would otherwise be lost.
+        if self.state.borrow().dirty {
+            if let Err(e) = self.commit() {
+                tracing::error!("failed to commit compressed file: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::{Database, StepResult, UnixIO};
+
+    // Uses `UnixIO` rather than `PlatformIO`, whose io_uring variant completes
+    // reads asynchronously, which the btree does not handle yet.
+    fn open(path: &std::path::Path, compression: Compression) -> Arc<Database> {
+        let options = CompressionOptions {
+            compression,
+            checksum: true,
+        };
+        let io = Arc::new(CompressedIO::new(Arc::new(UnixIO::new().unwrap()), options));
+        Database::open_file(io, path.to_str().unwrap(), false).unwrap()
+    }
+
+    fn query_count(conn: &std::rc::Rc<crate::Connection>, sql: &str) -> i64 {
+        let mut stmt = conn.prepare(sql).unwrap();
+        loop {
+            match stmt.step().unwrap() {
+                StepResult::Row => {
+                    let row = stmt.row().unwrap();
+                    return match row.get_values().next().unwrap() {
+                        crate::OwnedValue::Integer(i) => *i,
+                        other => panic!("unexpected value {:?}", other),
+                    };
+                }
+                StepResult::IO => stmt.run_once().unwrap(),
+                other => panic!("unexpected step result {:?}", other),
+            }
+        }
+    }
+
+    fn fill(conn: &std::rc::Rc<crate::Connection>) {
+        conn.execute("CREATE TABLE t (x TEXT)").unwrap();
+        for i in 0..200 {
+            conn.execute(format!(
+                "INSERT INTO t VALUES ('{}')",
+                format!("row {} ", i).repeat(50)
+            ))
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_compressed_database_roundtrip() {
+        for compression in ["zstd", "lz4", "none"] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("test.db");
+            let compression: Compression = compression.parse().unwrap();
+            {
+                let db = open(&path, compression);
+                let conn = db.connect().unwrap();
+                fill(&conn);
+                conn.close().unwrap();
+            }
+            let db = open(&path, compression);
+            let conn = db.connect().unwrap();
+            assert_eq!(query_count(&conn, "SELECT count(*) FROM t"), 200);
+            if compression != Compression::None {
+                let size = std::fs::metadata(&path).unwrap().len();
+                let logical = query_count(&conn, "PRAGMA page_count") * 4096;
+                assert!(
+                    (size as i64) < logical / 2,
+                    "{:?} stored {} bytes for {} bytes of pages",
+                    compression,
+                    size,
+                    logical
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_checksum_detects_corruption() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.db");
+        {
+            let db = open(&path, Compression::None);
+            let conn = db.connect().unwrap();
+            fill(&conn);
+            conn.close().unwrap();
+        }
+        // Flip a byte in the stored copy of the last page, a leaf of `t`.
+        let offset = {
+            let io: Arc<dyn IO> = Arc::new(UnixIO::new().unwrap());
+            let inner = io
+                .open_file(path.to_str().unwrap(), OpenFlags::None, false)
+                .unwrap();
+            let options = CompressionOptions {
+                compression: Compression::None,
+                checksum: true,
+            };
+            let file = CompressedFile::open(io, inner, options).unwrap();
+            let state = file.state.borrow();
+            let last = state.blocks.keys().max().unwrap();
+            state.blocks[last].offset
+        };
+        let mut data = std::fs::read(&path).unwrap();
+        data[offset as usize + 100] ^= 0xff;
+        std::fs::write(&path, data).unwrap();
+
+        let db = open(&path, Compression::None);
+        let conn = db.connect().unwrap();
+        let mut stmt = conn.prepare("SELECT count(*) FROM t").unwrap();
+        let result = loop {
+            match stmt.step() {
+                Ok(StepResult::IO) => stmt.run_once().unwrap(),
+                Ok(StepResult::Row) => continue,
+                other => break other,
+            }
+        };
+        assert!(
+            matches!(result, Err(crate::LimboError::Corrupt(_))),
+            "{:?}",
+            result.map(|_| ())
+        );
+    }
+}
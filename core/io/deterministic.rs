@@ -0,0 +1,147 @@
+//! A shim that stacks on top of another [IO] and replaces its clock and its source of
+//! randomness with ones the embedder controls, so that runs of a workload are reproducible.
+//! Everything in the database that reads the time or draws random numbers goes through the
+//! [IO], e.g. `datetime('now')`, `random()`, WAL salts and new rowids once they run out.
+//! `random()` and `randomblob()` can also be seeded for a single connection with
+//! [crate::Connection::seed_random].
+use super::clock::Instant;
+use super::{Clock, File, OpenFlags, IO};
+use crate::Result;
+use parking_lot::Mutex;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{sync::Arc, time::Duration};
+
+pub struct DeterministicIO {
+    inner: Arc<dyn IO>,
+    now: Mutex<Instant>,
+    rng: Mutex<StdRng>,
+}
+
+impl DeterministicIO {
+    /// The clock starts at `start` and only moves with [Self::set_time] and [Self::advance].
+    /// The same `seed` gives the same sequence of random numbers.
+    pub fn new(inner: Arc<dyn IO>, seed: u64, start: Instant) -> Self {
+        Self {
+            inner,
+            now: Mutex::new(start),
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+
+    pub fn set_time(&self, now: Instant) {
+        *self.now.lock() = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock();
+        let micros = now.micros as u128 + duration.as_micros();
+        now.secs += (micros / 1_000_000) as i64;
+        now.micros = (micros % 1_000_000) as u32;
+    }
+}
+
+impl Clock for DeterministicIO {
+    fn now(&self) -> Instant {
+        *self.now.lock()
+    }
+}
+
+impl IO for DeterministicIO {
+    fn open_file(&self, path: &str, flags: OpenFlags, direct: bool) -> Result<Arc<dyn File>> {
+        self.inner.open_file(path, flags, direct)
+    }
+
+    fn open_temp_file(&self) -> Result<Arc<dyn File>> {
+        self.inner.open_temp_file()
+    }
+
+    fn run_once(&self) -> Result<()> {
+        self.inner.run_once()
+    }
+
+    fn generate_random_number(&self) -> i64 {
+        self.rng.lock().gen()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Database, FallibleStreamingIterator, MemoryIO, OwnedValue};
+
+    fn query(io: Arc<DeterministicIO>, sql: &str) -> Vec<OwnedValue> {
+        let db = Database::open_file(io, ":memory:", false).unwrap();
+        let conn = db.connect().unwrap();
+        let mut stmt = conn.prepare(sql).unwrap();
+        let mut rows = stmt.query([]).unwrap();
+        let mut values = Vec::new();
+        while let Some(row) = rows.next().unwrap() {
+            values.extend(row.get_values().cloned());
+        }
+        values
+    }
+
+    #[test]
+    fn test_deterministic_io() {
+        let start = Instant {
+            secs: 1_700_000_000,
+            micros: 900_000,
+        };
+        let io = DeterministicIO::new(Arc::new(MemoryIO::new()), 42, start);
+        let other = DeterministicIO::new(Arc::new(MemoryIO::new()), 42, start);
+        for _ in 0..10 {
+            assert_eq!(io.generate_random_number(), other.generate_random_number());
+        }
+        assert_eq!(io.now(), start);
+        io.advance(Duration::from_millis(1_500));
+        assert_eq!(
+            io.now(),
+            Instant {
+                secs: 1_700_000_002,
+                micros: 400_000,
+            }
+        );
+    }
+
+    #[test]
+    fn test_deterministic_io_functions() {
+        let start = Instant {
+            secs: 1_704_067_200,
+            micros: 0,
+        };
+        let sql = "SELECT datetime('now'), unixepoch(), random(), hex(randomblob(12))";
+        let io = Arc::new(DeterministicIO::new(Arc::new(MemoryIO::new()), 7, start));
+        let values = query(io.clone(), sql);
+        assert_eq!(values[0], OwnedValue::build_text("2024-01-01 00:00:00"));
+        assert_eq!(values[1], OwnedValue::Integer(1_704_067_200));
+        let other = Arc::new(DeterministicIO::new(Arc::new(MemoryIO::new()), 7, start));
+        assert_eq!(query(other, sql), values);
+
+        io.advance(Duration::from_secs(90));
+        assert_eq!(
+            query(io, "SELECT time('now')"),
+            vec![OwnedValue::build_text("00:01:30")]
+        );
+    }
+
+    #[test]
+    fn test_seed_random() {
+        let db = Database::open_file(Arc::new(MemoryIO::new()), ":memory:", false).unwrap();
+        let conn = db.connect().unwrap();
+        let draw = || {
+            let mut stmt = conn.prepare("SELECT random(), randomblob(10)").unwrap();
+            let mut rows = stmt.query([]).unwrap();
+            let row = rows.next().unwrap().unwrap();
+            row.get_values().cloned().collect::<Vec<_>>()
+        };
+        conn.seed_random(Some(11));
+        let first = draw();
+        assert_ne!(draw(), first);
+        conn.seed_random(Some(11));
+        assert_eq!(draw(), first);
+        conn.seed_random(Some(12));
+        assert_ne!(draw(), first);
+        conn.seed_random(None);
+        assert_ne!(draw(), first);
+    }
+}
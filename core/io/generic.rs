@@ -1,3 +1,4 @@
+use crate::error::FileOperation;
 use crate::{Clock, Completion, File, Instant, LimboError, OpenFlags, Result, IO};
 use std::cell::RefCell;
 use std::io::{Read, Seek, Write};
@@ -78,7 +79,8 @@ impl File for GenericFile {
             };
             let mut buf = r.buf_mut();
             let buf = buf.as_mut_slice();
-            file.read_exact(buf)?;
+            file.read_exact(buf)
+                .map_err(|err| LimboError::file_io(FileOperation::Read, err))?;
         }
         c.complete(0);
         Ok(())
@@ -89,14 +91,16 @@ impl File for GenericFile {
         file.seek(std::io::SeekFrom::Start(pos as u64))?;
         let buf = buffer.borrow();
         let buf = buf.as_slice();
-        file.write_all(buf)?;
+        file.write_all(buf)
+            .map_err(|err| LimboError::file_io(FileOperation::Write, err))?;
         c.complete(buf.len() as i32);
         Ok(())
     }
 
     fn sync(&self, c: Completion) -> Result<()> {
         let mut file = self.file.borrow_mut();
-        file.sync_all().map_err(|err| LimboError::IOError(err))?;
+        file.sync_all()
+            .map_err(|err| LimboError::file_io(FileOperation::Sync, err))?;
         c.complete(0);
         Ok(())
     }
@@ -105,6 +109,13 @@ impl File for GenericFile {
         let file = self.file.borrow();
         Ok(file.metadata().unwrap().len())
     }
+
+    fn truncate(&self, len: usize) -> Result<()> {
+        let file = self.file.borrow();
+        file.set_len(len as u64)
+            .map_err(|err| LimboError::file_io(FileOperation::Truncate, err))?;
+        Ok(())
+    }
 }
 
 impl Drop for GenericFile {
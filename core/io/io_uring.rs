@@ -1,10 +1,10 @@
-use super::{common, Completion, File, OpenFlags, WriteCompletion, IO};
+use super::lock::{FileLock, LockLevel};
+use super::{common, Completion, DeviceCharacteristics, File, OpenFlags, WriteCompletion, IO};
 use crate::{LimboError, Result};
-use rustix::fs::{self, FlockOperation, OFlags};
+use rustix::fs::{self, OFlags};
 use rustix::io_uring::iovec;
 use std::cell::RefCell;
 use std::fmt;
-use std::io::ErrorKind;
 use std::os::fd::AsFd;
 use std::os::unix::io::AsRawFd;
 use std::rc::Rc;
@@ -155,7 +155,9 @@ impl IO for UringIO {
         }
         let uring_file = Arc::new(UringFile {
             io: self.inner.clone(),
+            device_characteristics: common::device_characteristics(&file),
             file,
+            lock: FileLock::new(),
         });
         if std::env::var(common::ENV_DISABLE_FILE_LOCK).is_err() {
             uring_file.lock_file(true)?;
@@ -163,6 +165,14 @@ impl IO for UringIO {
         Ok(uring_file)
     }
 
+    fn open_temp_file(&self) -> Result<Arc<dyn File>> {
+        let path = common::unused_temp_path(self);
+        let file = self.open_file(&path.to_string_lossy(), OpenFlags::Create, false)?;
+        // Unlinked right away, so the file goes away with its last descriptor.
+        std::fs::remove_file(&path)?;
+        Ok(file)
+    }
+
     fn run_once(&self) -> Result<()> {
         trace!("run_once()");
         let mut inner = self.inner.borrow_mut();
@@ -212,6 +222,8 @@ impl Clock for UringIO {
 pub struct UringFile {
     io: Rc<RefCell<InnerUringIO>>,
     file: std::fs::File,
+    device_characteristics: DeviceCharacteristics,
+    lock: FileLock,
 }
 
 unsafe impl Send for UringFile {}
@@ -219,40 +231,16 @@ unsafe impl Sync for UringFile {}
 
 impl File for UringFile {
     fn lock_file(&self, exclusive: bool) -> Result<()> {
-        let fd = self.file.as_fd();
-        // F_SETLK is a non-blocking lock. The lock will be released when the file is closed
-        // or the process exits or after an explicit unlock.
-        fs::fcntl_lock(
-            fd,
-            if exclusive {
-                FlockOperation::NonBlockingLockExclusive
-            } else {
-                FlockOperation::NonBlockingLockShared
-            },
-        )
-        .map_err(|e| {
-            let io_error = std::io::Error::from(e);
-            let message = match io_error.kind() {
-                ErrorKind::WouldBlock => {
-                    "Failed locking file. File is locked by another process".to_string()
-                }
-                _ => format!("Failed locking file, {}", io_error),
-            };
-            LimboError::LockingError(message)
-        })?;
-
-        Ok(())
+        let level = if exclusive {
+            LockLevel::Exclusive
+        } else {
+            LockLevel::Shared
+        };
+        self.lock.lock(&self.file, level)
     }
 
     fn unlock_file(&self) -> Result<()> {
-        let fd = self.file.as_fd();
-        fs::fcntl_lock(fd, FlockOperation::NonBlockingUnlock).map_err(|e| {
-            LimboError::LockingError(format!(
-                "Failed to release file lock: {}",
-                std::io::Error::from(e)
-            ))
-        })?;
-        Ok(())
+        self.lock.unlock(&self.file, LockLevel::None)
     }
 
     fn pread(&self, pos: usize, c: Completion) -> Result<()> {
@@ -311,6 +299,15 @@ impl File for UringFile {
     fn size(&self) -> Result<u64> {
         Ok(self.file.metadata()?.len())
     }
+
+    fn truncate(&self, len: usize) -> Result<()> {
+        self.file.set_len(len as u64)?;
+        Ok(())
+    }
+
+    fn device_characteristics(&self) -> DeviceCharacteristics {
+        self.device_characteristics
+    }
 }
 
 impl Drop for UringFile {
@@ -0,0 +1,330 @@
+//! SQLite's locking protocol for database files, so that Limbo and SQLite processes opening the
+//! same file see each other's locks.
+//!
+//! The locks are byte-range locks on bytes past the 1 GiB mark, which SQLite never uses for
+//! data. A connection moves up through the lock levels of [LockLevel] and gets
+//! [LimboError::Busy] when another process holds a conflicting lock.
+//! More info: https://www.sqlite.org/lockingv3.html
+use crate::{LimboError, Result};
+use std::cell::Cell;
+
+/// Taken for writing by a connection about to become exclusive, which keeps new readers out.
+pub const PENDING_BYTE: u64 = 0x4000_0000;
+/// Taken for writing by the one connection that plans to write.
+pub const RESERVED_BYTE: u64 = PENDING_BYTE + 1;
+/// Start of the range readers take for reading and an exclusive connection takes for writing.
+pub const SHARED_FIRST: u64 = PENDING_BYTE + 2;
+pub const SHARED_SIZE: u64 = 510;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockLevel {
+    None,
+    /// The file can be read.
+    Shared,
+    /// The file will be written, other connections can still read it.
+    Reserved,
+    /// Waiting for the readers to leave before becoming exclusive.
+    Pending,
+    /// The file can be written.
+    Exclusive,
+}
+
+/// Non-blocking byte-range locks of the platform, owned by the process.
+pub(crate) trait RangeLock {
+    /// Returns false when another process holds a conflicting lock.
+    fn lock_range(&self, start: u64, len: u64, exclusive: bool) -> Result<bool>;
+    fn unlock_range(&self, start: u64, len: u64) -> Result<()>;
+}
+
+/// Lock level held on a file.
+#[derive(Debug)]
+pub(crate) struct FileLock {
+    level: Cell<LockLevel>,
+}
+
+impl FileLock {
+    pub fn new() -> Self {
+        Self {
+            level: Cell::new(LockLevel::None),
+        }
+    }
+
+    pub fn level(&self) -> LockLevel {
+        self.level.get()
+    }
+
+    /// Moves up to `level`, going through the levels in between. On [LimboError::Busy] the
+    /// levels that could be taken are kept, like in SQLite.
+    pub fn lock(&self, file: &impl RangeLock, level: LockLevel) -> Result<()> {
+        assert_ne!(
+            level,
+            LockLevel::Pending,
+            "pending is only taken on the way to exclusive"
+        );
+        if self.level() >= level {
+            return Ok(());
+        }
+        if self.level() == LockLevel::None {
+            // Readers can't come in while someone waits for exclusive access.
+            if !file.lock_range(PENDING_BYTE, 1, false)? {
+                return Err(LimboError::Busy);
+            }
+            let locked = file.lock_range(SHARED_FIRST, SHARED_SIZE, false);
+            file.unlock_range(PENDING_BYTE, 1)?;
+            if !locked? {
+                return Err(LimboError::Busy);
+            }
+            self.level.set(LockLevel::Shared);
+        }
+        if level >= LockLevel::Reserved && self.level() == LockLevel::Shared {
+            if !file.lock_range(RESERVED_BYTE, 1, true)? {
+                return Err(LimboError::Busy);
+            }
+            self.level.set(LockLevel::Reserved);
+        }
+        if level == LockLevel::Exclusive {
+            if self.level() < LockLevel::Pending {
+                if !file.lock_range(PENDING_BYTE, 1, true)? {
+                    return Err(LimboError::Busy);
+                }
+                self.level.set(LockLevel::Pending);
+            }
+            // Not every platform can upgrade a lock in place. Holding the pending byte, no new
+            // reader can take the range in between.
+            file.unlock_range(SHARED_FIRST, SHARED_SIZE)?;
+            if !file.lock_range(SHARED_FIRST, SHARED_SIZE, true)? {
+                file.lock_range(SHARED_FIRST, SHARED_SIZE, false)?;
+                return Err(LimboError::Busy);
+            }
+            self.level.set(LockLevel::Exclusive);
+        }
+        Ok(())
+    }
+
+    /// Moves down to `level`, which is either [LockLevel::Shared] or [LockLevel::None].
+    pub fn unlock(&self, file: &impl RangeLock, level: LockLevel) -> Result<()> {
+        assert!(level <= LockLevel::Shared);
+        let current = self.level();
+        if current <= level {
+            return Ok(());
+        }
+        if current == LockLevel::Exclusive || level == LockLevel::None {
+            file.unlock_range(SHARED_FIRST, SHARED_SIZE)?;
+        }
+        if current == LockLevel::Exclusive && level == LockLevel::Shared {
+            file.lock_range(SHARED_FIRST, SHARED_SIZE, false)?;
+        }
+        if current >= LockLevel::Pending {
+            file.unlock_range(PENDING_BYTE, 1)?;
+        }
+        if current >= LockLevel::Reserved {
+            file.unlock_range(RESERVED_BYTE, 1)?;
+        }
+        self.level.set(level);
+        Ok(())
+    }
+}
+
+#[cfg(target_family = "unix")]
+impl RangeLock for std::fs::File {
+    fn lock_range(&self, start: u64, len: u64, exclusive: bool) -> Result<bool> {
+        let lock_type = if exclusive {
+            libc::F_WRLCK
+        } else {
+            libc::F_RDLCK
+        };
+        match fcntl_setlk(self, lock_type, start, len) {
+            Ok(()) => Ok(true),
+            Err(e) if matches!(e.raw_os_error(), Some(libc::EAGAIN | libc::EACCES)) => Ok(false),
+            Err(e) => Err(LimboError::LockingError(format!(
+                "Failed locking file, {}",
+                e
+            ))),
+        }
+    }
+
+    fn unlock_range(&self, start: u64, len: u64) -> Result<()> {
+        fcntl_setlk(self, libc::F_UNLCK, start, len)
+            .map_err(|e| LimboError::LockingError(format!("Failed to release file lock: {}", e)))
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn fcntl_setlk(
+    file: &std::fs::File,
+    lock_type: libc::c_int,
+    start: u64,
+    len: u64,
+) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let mut flock: libc::flock = unsafe { std::mem::zeroed() };
+    flock.l_type = lock_type as _;
+    flock.l_whence = libc::SEEK_SET as _;
+    flock.l_start = start as _;
+    flock.l_len = len as _;
+    // F_SETLK doesn't wait, the lock is released when the process closes the file.
+    let res = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETLK, &flock) };
+    if res == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+impl RangeLock for std::fs::File {
+    fn lock_range(&self, start: u64, len: u64, exclusive: bool) -> Result<bool> {
+        use std::os::windows::io::AsRawHandle;
+        use windows_sys::Win32::Foundation::ERROR_LOCK_VIOLATION;
+        use windows_sys::Win32::Storage::FileSystem::{
+            LockFileEx, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY,
+        };
+        use windows_sys::Win32::System::IO::OVERLAPPED;
+
+        let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+        overlapped.Anonymous.Anonymous.Offset = start as u32;
+        overlapped.Anonymous.Anonymous.OffsetHigh = (start >> 32) as u32;
+        let mut flags = LOCKFILE_FAIL_IMMEDIATELY;
+        if exclusive {
+            flags |= LOCKFILE_EXCLUSIVE_LOCK;
+        }
+        let ok = unsafe {
+            LockFileEx(
+                self.as_raw_handle() as _,
+                flags,
+                0,
+                len as u32,
+                (len >> 32) as u32,
+                &mut overlapped,
+            )
+        };
+        if ok != 0 {
+            return Ok(true);
+        }
+        let e = std::io::Error::last_os_error();
+        if e.raw_os_error() == Some(ERROR_LOCK_VIOLATION as i32) {
+            return Ok(false);
+        }
+        Err(LimboError::LockingError(format!(
+            "Failed locking file, {}",
+            e
+        )))
+    }
+
+    fn unlock_range(&self, start: u64, len: u64) -> Result<()> {
+        use std::os::windows::io::AsRawHandle;
+        use windows_sys::Win32::Storage::FileSystem::UnlockFileEx;
+        use windows_sys::Win32::System::IO::OVERLAPPED;
+
+        let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+        overlapped.Anonymous.Anonymous.Offset = start as u32;
+        overlapped.Anonymous.Anonymous.OffsetHigh = (start >> 32) as u32;
+        let ok = unsafe {
+            UnlockFileEx(
+                self.as_raw_handle() as _,
+                0,
+                len as u32,
+                (len >> 32) as u32,
+                &mut overlapped,
+            )
+        };
+        if ok == 0 {
+            return Err(LimboError::LockingError(format!(
+                "Failed to release file lock: {}",
+                std::io::Error::last_os_error()
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Locks of several owners on one file, standing in for processes.
+    #[derive(Default)]
+    struct LockTable {
+        locks: RefCell<Vec<(usize, u64, u64, bool)>>,
+    }
+
+    struct Owner {
+        id: usize,
+        table: Rc<LockTable>,
+    }
+
+    impl RangeLock for Owner {
+        fn lock_range(&self, start: u64, len: u64, exclusive: bool) -> Result<bool> {
+            let mut locks = self.table.locks.borrow_mut();
+            let conflict = locks.iter().any(|&(id, s, l, x)| {
+                id != self.id && s < start + len && start < s + l && (x || exclusive)
+            });
+            if !conflict {
+                locks.push((self.id, start, len, exclusive));
+            }
+            Ok(!conflict)
+        }
+
+        fn unlock_range(&self, start: u64, len: u64) -> Result<()> {
+            let mut locks = self.table.locks.borrow_mut();
+            let idx = locks
+                .iter()
+                .position(|&(id, s, l, _)| id == self.id && s == start && l == len)
+                .expect("unlocking a range that isn't locked");
+            locks.remove(idx);
+            Ok(())
+        }
+    }
+
+    fn owners() -> (Owner, Owner) {
+        let table = Rc::new(LockTable::default());
+        let a = Owner {
+            id: 1,
+            table: table.clone(),
+        };
+        let b = Owner { id: 2, table };
+        (a, b)
+    }
+
+    #[test]
+    fn test_readers_share_the_file() {
+        let (a, b) = owners();
+        let (lock_a, lock_b) = (FileLock::new(), FileLock::new());
+        lock_a.lock(&a, LockLevel::Shared).unwrap();
+        lock_b.lock(&b, LockLevel::Reserved).unwrap();
+        // Only one connection can plan to write.
+        assert!(matches!(
+            lock_a.lock(&a, LockLevel::Reserved),
+            Err(LimboError::Busy)
+        ));
+        assert_eq!(lock_a.level(), LockLevel::Shared);
+        // The reader keeps the writer from becoming exclusive and the writer keeps new readers
+        // out while it waits.
+        assert!(matches!(
+            lock_b.lock(&b, LockLevel::Exclusive),
+            Err(LimboError::Busy)
+        ));
+        assert_eq!(lock_b.level(), LockLevel::Pending);
+        let c = Owner {
+            id: 3,
+            table: a.table.clone(),
+        };
+        assert!(matches!(
+            FileLock::new().lock(&c, LockLevel::Shared),
+            Err(LimboError::Busy)
+        ));
+        lock_a.unlock(&a, LockLevel::None).unwrap();
+        lock_b.lock(&b, LockLevel::Exclusive).unwrap();
+        assert!(matches!(
+            lock_a.lock(&a, LockLevel::Shared),
+            Err(LimboError::Busy)
+        ));
+        lock_b.unlock(&b, LockLevel::Shared).unwrap();
+        lock_a.lock(&a, LockLevel::Shared).unwrap();
+        lock_b.unlock(&b, LockLevel::None).unwrap();
+        lock_a.unlock(&a, LockLevel::None).unwrap();
+        assert!(a.table.locks.borrow().is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use super::{Buffer, Clock, Completion, File, OpenFlags, IO};
+use super::{Buffer, Clock, Completion, DeviceCharacteristics, File, OpenFlags, IO};
 use crate::Result;
 
 use crate::io::clock::Instant;
@@ -161,6 +161,29 @@ impl File for MemoryFile {
     fn size(&self) -> Result<u64> {
         Ok(self.size.get() as u64)
     }
+
+    fn truncate(&self, len: usize) -> Result<()> {
+        let pages = unsafe { &mut *self.pages.get() };
+        // Drop the pages past the new end and zero the tail of the last one, so
+        // that extending the file again reads back zeroes.
+        pages.split_off(&len.div_ceil(PAGE_SIZE));
+        if len % PAGE_SIZE != 0 {
+            if let Some(page) = pages.get_mut(&(len / PAGE_SIZE)) {
+                page[len % PAGE_SIZE..].fill(0);
+            }
+        }
+        self.size.set(len);
+        Ok(())
+    }
+
+    fn device_characteristics(&self) -> DeviceCharacteristics {
+        // Nothing is lost on power loss that wouldn't be lost anyway.
+        DeviceCharacteristics {
+            powersafe_overwrite: true,
+            sync_on_write: true,
+            ..Default::default()
+        }
+    }
 }
 
 impl Drop for MemoryFile {
@@ -17,6 +17,43 @@ pub trait File: Send + Sync {
     fn pwrite(&self, pos: usize, buffer: Arc<RefCell<Buffer>>, c: Completion) -> Result<()>;
     fn sync(&self, c: Completion) -> Result<()>;
     fn size(&self) -> Result<u64>;
+    /// Shrinks or extends the file to `len` bytes.
+    fn truncate(&self, len: usize) -> Result<()>;
+
+    /// What the storage under the file guarantees about writes, see [DeviceCharacteristics].
+    fn device_characteristics(&self) -> DeviceCharacteristics {
+        DeviceCharacteristics::default()
+    }
+}
+
+/// How writes to a file behave on power loss, like SQLite's `xSectorSize` and
+/// `xDeviceCharacteristics`. The WAL uses it to decide how much it has to do to keep committed
+/// frames intact. The default assumes nothing beyond what a sync guarantees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceCharacteristics {
+    /// The unit the device writes in. A write that is torn by a power loss may damage the whole
+    /// sectors it touches, including bytes of them it didn't write.
+    pub sector_size: usize,
+    /// Writes never damage bytes outside of the range written, even when torn.
+    pub powersafe_overwrite: bool,
+    /// The file system can write a batch of pages atomically, like F2FS atomic writes.
+    pub batch_atomic_write: bool,
+    /// Writes are durable once they complete, as with `O_DSYNC`, so syncing is not needed.
+    pub sync_on_write: bool,
+}
+
+/// The sector size assumed when the device doesn't report one, the same as SQLite's.
+pub const DEFAULT_SECTOR_SIZE: usize = 4096;
+
+impl Default for DeviceCharacteristics {
+    fn default() -> Self {
+        Self {
+            sector_size: DEFAULT_SECTOR_SIZE,
+            powersafe_overwrite: false,
+            batch_atomic_write: false,
+            sync_on_write: false,
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -37,6 +74,12 @@ impl OpenFlags {
 pub trait IO: Clock + Send + Sync {
     fn open_file(&self, path: &str, flags: OpenFlags, direct: bool) -> Result<Arc<dyn File>>;
 
+    /// Opens an anonymous file for data that doesn't outlive the connection, like transient
+    /// b-trees spilled out of memory. Backends without a file system keep it in memory.
+    fn open_temp_file(&self) -> Result<Arc<dyn File>> {
+        memory::MemoryIO::new().open_file("", OpenFlags::Create, false)
+    }
+
     fn run_once(&self) -> Result<()>;
 
     fn generate_random_number(&self) -> i64;
@@ -210,10 +253,18 @@ cfg_block! {
     }
 }
 
+#[cfg(feature = "compression")]
+mod compress;
+mod deterministic;
 mod memory;
 #[cfg(feature = "fs")]
 mod vfs;
+#[cfg(feature = "compression")]
+pub use compress::{CompressedIO, Compression, CompressionOptions};
+pub use deterministic::DeterministicIO;
 pub use memory::MemoryIO;
 pub mod clock;
 mod common;
+#[cfg(any(target_family = "unix", target_os = "windows"))]
+mod lock;
 pub use clock::Clock;
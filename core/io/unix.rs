@@ -1,12 +1,14 @@
-use crate::error::LimboError;
+use crate::error::FileOperation;
 use crate::io::common;
-use crate::Result;
+use crate::io::lock::{FileLock, LockLevel};
+use crate::{LimboError, Result};
 
-use super::{Completion, File, OpenFlags, IO};
+use super::{Completion, DeviceCharacteristics, File, OpenFlags, IO};
+use crate::io::clock::{Clock, Instant};
 use polling::{Event, Events, Poller};
 use rustix::{
     fd::{AsFd, AsRawFd},
-    fs::{self, FlockOperation, OFlags, OpenOptionsExt},
+    fs::{self, OFlags, OpenOptionsExt},
     io::Errno,
 };
 use std::{
@@ -14,11 +16,10 @@ use std::{
     mem::MaybeUninit,
 };
 use std::{
-    io::{ErrorKind, Read, Seek, Write},
+    io::{Read, Seek, Write},
     sync::Arc,
 };
 use tracing::{debug, trace};
-use crate::io::clock::{Clock, Instant};
 
 struct OwnedCallbacks(UnsafeCell<Callbacks>);
 // We assume we locking on IO level is done by user.
@@ -204,11 +205,14 @@ impl IO for UnixIO {
             .create(matches!(flags, OpenFlags::Create))
             .open(path)?;
 
+        let device_characteristics = common::device_characteristics(&file);
         #[allow(clippy::arc_with_non_send_sync)]
         let unix_file = Arc::new(UnixFile {
             file: Arc::new(RefCell::new(file)),
+            device_characteristics,
             poller: BorrowedPollHandler(self.poller.as_mut().into()),
             callbacks: BorrowedCallbacks(self.callbacks.as_mut().into()),
+            lock: FileLock::new(),
         });
         if std::env::var(common::ENV_DISABLE_FILE_LOCK).is_err() {
             unix_file.lock_file(true)?;
@@ -216,6 +220,14 @@ impl IO for UnixIO {
         Ok(unix_file)
     }
 
+    fn open_temp_file(&self) -> Result<Arc<dyn File>> {
+        let path = common::unused_temp_path(self);
+        let file = self.open_file(&path.to_string_lossy(), OpenFlags::Create, false)?;
+        // Unlinked right away, so the file goes away with its last descriptor.
+        std::fs::remove_file(&path)?;
+        Ok(file)
+    }
+
     fn run_once(&self) -> Result<()> {
         if self.callbacks.is_empty() {
             return Ok(());
@@ -246,7 +258,13 @@ impl IO for UnixIO {
                         CompletionCallback::Read(_, ref c, _) => c.complete(0),
                         CompletionCallback::Write(_, ref c, _, _) => c.complete(n as i32),
                     },
-                    Err(e) => return Err(e.into()),
+                    Err(e) => {
+                        let op = match cf {
+                            CompletionCallback::Read(..) => FileOperation::Read,
+                            CompletionCallback::Write(..) => FileOperation::Write,
+                        };
+                        return Err(LimboError::file_io(op, e));
+                    }
                 }
             }
         }
@@ -273,50 +291,28 @@ enum CompletionCallback {
 pub struct UnixFile<'io> {
     #[allow(clippy::arc_with_non_send_sync)]
     file: Arc<RefCell<std::fs::File>>,
+    device_characteristics: DeviceCharacteristics,
     poller: BorrowedPollHandler<'io>,
     callbacks: BorrowedCallbacks<'io>,
+    lock: FileLock,
 }
 unsafe impl Send for UnixFile<'_> {}
 unsafe impl Sync for UnixFile<'_> {}
 
 impl File for UnixFile<'_> {
     fn lock_file(&self, exclusive: bool) -> Result<()> {
-        let fd = self.file.borrow();
-        let fd = fd.as_fd();
-        // F_SETLK is a non-blocking lock. The lock will be released when the file is closed
-        // or the process exits or after an explicit unlock.
-        fs::fcntl_lock(
-            fd,
-            if exclusive {
-                FlockOperation::NonBlockingLockExclusive
-            } else {
-                FlockOperation::NonBlockingLockShared
-            },
-        )
-        .map_err(|e| {
-            let io_error = std::io::Error::from(e);
-            let message = match io_error.kind() {
-                ErrorKind::WouldBlock => {
-                    "Failed locking file. File is locked by another process".to_string()
-                }
-                _ => format!("Failed locking file, {}", io_error),
-            };
-            LimboError::LockingError(message)
-        })?;
-
-        Ok(())
+        let file = self.file.borrow();
+        let level = if exclusive {
+            LockLevel::Exclusive
+        } else {
+            LockLevel::Shared
+        };
+        self.lock.lock(&*file, level)
     }
 
     fn unlock_file(&self) -> Result<()> {
-        let fd = self.file.borrow();
-        let fd = fd.as_fd();
-        fs::fcntl_lock(fd, FlockOperation::NonBlockingUnlock).map_err(|e| {
-            LimboError::LockingError(format!(
-                "Failed to release file lock: {}",
-                std::io::Error::from(e)
-            ))
-        })?;
-        Ok(())
+        let file = self.file.borrow();
+        self.lock.unlock(&*file, LockLevel::None)
     }
 
     fn pread(&self, pos: usize, c: Completion) -> Result<()> {
@@ -347,7 +343,7 @@ impl File for UnixFile<'_> {
                 }
                 Ok(())
             }
-            Err(e) => Err(e.into()),
+            Err(e) => Err(LimboError::file_io(FileOperation::Read, e)),
         }
     }
 
@@ -376,7 +372,7 @@ impl File for UnixFile<'_> {
                 );
                 Ok(())
             }
-            Err(e) => Err(e.into()),
+            Err(e) => Err(LimboError::file_io(FileOperation::Write, e)),
         }
     }
 
@@ -389,7 +385,7 @@ impl File for UnixFile<'_> {
                 c.complete(0);
                 Ok(())
             }
-            Err(e) => Err(e.into()),
+            Err(e) => Err(LimboError::file_io(FileOperation::Sync, e)),
         }
     }
 
@@ -397,6 +393,17 @@ impl File for UnixFile<'_> {
         let file = self.file.borrow();
         Ok(file.metadata()?.len())
     }
+
+    fn truncate(&self, len: usize) -> Result<()> {
+        let file = self.file.borrow();
+        file.set_len(len as u64)
+            .map_err(|err| LimboError::file_io(FileOperation::Truncate, err))?;
+        Ok(())
+    }
+
+    fn device_characteristics(&self) -> DeviceCharacteristics {
+        self.device_characteristics
+    }
 }
 
 impl Drop for UnixFile<'_> {
@@ -152,6 +152,15 @@ impl File for VfsFileImpl {
             Ok(result as u64)
         }
     }
+
+    fn truncate(&self, len: usize) -> Result<()> {
+        let vfs = unsafe { &*self.vfs };
+        let result = unsafe { (vfs.truncate)(self.file, len as i64) };
+        if !result.is_ok() {
+            return Err(LimboError::ExtensionError(result.to_string()));
+        }
+        Ok(())
+    }
 }
 
 impl Drop for VfsMod {
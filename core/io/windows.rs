@@ -1,3 +1,6 @@
+use super::common;
+use super::lock::{FileLock, LockLevel};
+use crate::error::FileOperation;
 use crate::{Clock, Completion, File, Instant, LimboError, OpenFlags, Result, IO};
 use std::cell::RefCell;
 use std::io::{Read, Seek, Write};
@@ -24,9 +27,14 @@ impl IO for WindowsIO {
             .write(true)
             .create(matches!(flags, OpenFlags::Create))
             .open(path)?;
-        Ok(Arc::new(WindowsFile {
+        let windows_file = Arc::new(WindowsFile {
             file: RefCell::new(file),
-        }))
+            lock: FileLock::new(),
+        });
+        if std::env::var(common::ENV_DISABLE_FILE_LOCK).is_err() {
+            windows_file.lock_file(true)?;
+        }
+        Ok(windows_file)
     }
 
     fn run_once(&self) -> Result<()> {
@@ -52,6 +60,7 @@ impl Clock for WindowsIO {
 
 pub struct WindowsFile {
     file: RefCell<std::fs::File>,
+    lock: FileLock,
 }
 
 unsafe impl Send for WindowsFile {}
@@ -59,11 +68,18 @@ unsafe impl Sync for WindowsFile {}
 
 impl File for WindowsFile {
     fn lock_file(&self, exclusive: bool) -> Result<()> {
-        unimplemented!()
+        let file = self.file.borrow();
+        let level = if exclusive {
+            LockLevel::Exclusive
+        } else {
+            LockLevel::Shared
+        };
+        self.lock.lock(&*file, level)
     }
 
     fn unlock_file(&self) -> Result<()> {
-        unimplemented!()
+        let file = self.file.borrow();
+        self.lock.unlock(&*file, LockLevel::None)
     }
 
     fn pread(&self, pos: usize, c: Completion) -> Result<()> {
@@ -73,7 +89,8 @@ impl File for WindowsFile {
             let r = c.as_read();
             let mut buf = r.buf_mut();
             let buf = buf.as_mut_slice();
-            file.read_exact(buf)?;
+            file.read_exact(buf)
+                .map_err(|err| LimboError::file_io(FileOperation::Read, err))?;
         }
         c.complete(0);
         Ok(())
@@ -84,14 +101,16 @@ impl File for WindowsFile {
         file.seek(std::io::SeekFrom::Start(pos as u64))?;
         let buf = buffer.borrow();
         let buf = buf.as_slice();
-        file.write_all(buf)?;
+        file.write_all(buf)
+            .map_err(|err| LimboError::file_io(FileOperation::Write, err))?;
         c.complete(buffer.borrow().len() as i32);
         Ok(())
     }
 
     fn sync(&self, c: Completion) -> Result<()> {
         let file = self.file.borrow_mut();
-        file.sync_all().map_err(LimboError::IOError)?;
+        file.sync_all()
+            .map_err(|err| LimboError::file_io(FileOperation::Sync, err))?;
         c.complete(0);
         Ok(())
     }
@@ -100,4 +119,11 @@ impl File for WindowsFile {
         let file = self.file.borrow();
         Ok(file.metadata().unwrap().len())
     }
+
+    fn truncate(&self, len: usize) -> Result<()> {
+        let file = self.file.borrow();
+        file.set_len(len as u64)
+            .map_err(|err| LimboError::file_io(FileOperation::Truncate, err))?;
+        Ok(())
+    }
 }
@@ -0,0 +1,214 @@
+//! Reading and writing the b-trees of a database directly, without SQL.
+//!
+//! A [KvTransaction] is a transaction on a connection, in which [KvCursor]s get, put, delete
+//! and scan the entries of table b-trees by their integer key, like SQLite's intkey b-tree
+//! layer. Values are records, so the b-trees of tables can be read and written as well as ones
+//! created with [KvTransaction::create_btree]. Nothing else is maintained: writing to the
+//! b-tree of a table doesn't update its indexes, and b-trees created here aren't in the schema,
+//! so `PRAGMA integrity_check` reports their pages as unused.
+
+use std::rc::Rc;
+
+use crate::storage::btree::{BTreeCursor, BTreeKey};
+use crate::storage::sqlite3_ondisk::DatabaseHeader;
+use crate::types::{CursorResult, ImmutableRecord, OwnedValue, SeekKey, SeekOp};
+use crate::vdbe::Register;
+use crate::{Connection, LimboError, Result};
+
+pub struct KvTransaction {
+    conn: Rc<Connection>,
+    /// The database header when a write transaction began, to put back on rollback.
+    header: Option<DatabaseHeader>,
+    ended: bool,
+}
+
+impl KvTransaction {
+    pub(crate) fn begin(conn: &Rc<Connection>, write: bool) -> Result<Self> {
+        if conn._db.mv_store.is_some() {
+            return Err(LimboError::InternalError(
+                "b-trees can't be accessed directly with MVCC".to_string(),
+            ));
+        }
+        if !conn.get_auto_commit() {
+            return Err(LimboError::TxError(
+                "cannot start a transaction within a transaction".to_string(),
+            ));
+        }
+        let header = if write {
+            conn.execute("BEGIN IMMEDIATE")?;
+            Some(conn.pager.db_header.lock().clone())
+        } else {
+            conn.begin_read_tx()?;
+            None
+        };
+        Ok(Self {
+            conn: conn.clone(),
+            header,
+            ended: false,
+        })
+    }
+
+    /// Opens a cursor on the table b-tree with its root at `root_page`.
+    pub fn cursor(&self, root_page: usize) -> KvCursor<'_> {
+        KvCursor {
+            tx: self,
+            cursor: BTreeCursor::new(None, self.conn.pager.clone(), root_page),
+        }
+    }
+
+    /// Creates an empty table b-tree and returns its root page.
+    pub fn create_btree(&self) -> Result<usize> {
+        self.check_writable()?;
+        Ok(self.conn.pager.btree_create(1) as usize)
+    }
+
+    /// Frees the pages of the b-tree with its root at `root_page`.
+    pub fn destroy_btree(&self, root_page: usize) -> Result<()> {
+        self.check_writable()?;
+        let mut cursor = BTreeCursor::new(None, self.conn.pager.clone(), root_page);
+        self.wait(|| cursor.btree_destroy())
+    }
+
+    /// Commits the writes made in the transaction. Dropping the transaction without committing
+    /// it rolls them back.
+    pub fn commit(mut self) -> Result<()> {
+        self.ended = true;
+        if self.header.is_none() {
+            return self.conn.end_read_tx();
+        }
+        let result = self.conn.execute("COMMIT");
+        if result.is_err() {
+            self.rollback()?;
+        }
+        result
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        self.ended = true;
+        match self.header.take() {
+            Some(header) => self.conn.rollback_write_tx(header),
+            None => self.conn.end_read_tx(),
+        }
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.header.is_none() {
+            return Err(LimboError::TxError(
+                "cannot write in a read transaction".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn wait<T>(&self, mut op: impl FnMut() -> Result<CursorResult<T>>) -> Result<T> {
+        loop {
+            match op()? {
+                CursorResult::Ok(value) => return Ok(value),
+                CursorResult::IO => self.conn.pager.io.run_once()?,
+            }
+        }
+    }
+}
+
+impl Drop for KvTransaction {
+    fn drop(&mut self) {
+        if !self.ended {
+            let _ = self.rollback();
+        }
+    }
+}
+
+/// A cursor on a table b-tree, see [KvTransaction::cursor].
+pub struct KvCursor<'a> {
+    tx: &'a KvTransaction,
+    cursor: BTreeCursor,
+}
+
+impl KvCursor<'_> {
+    /// The value of the entry with `key`, if there is one.
+    pub fn get(&mut self, key: i64) -> Result<Option<Vec<OwnedValue>>> {
+        let cursor = &mut self.cursor;
+        if !self
+            .tx
+            .wait(|| cursor.seek(SeekKey::TableRowId(key as u64), SeekOp::EQ))?
+        {
+            return Ok(None);
+        }
+        Ok(self.current().map(|(_, value)| value))
+    }
+
+    /// Sets the value of the entry with `key`, adding it if there is none.
+    pub fn put(&mut self, key: i64, value: &[OwnedValue]) -> Result<()> {
+        self.tx.check_writable()?;
+        let registers = value
+            .iter()
+            .cloned()
+            .map(Register::OwnedValue)
+            .collect::<Vec<_>>();
+        let record = ImmutableRecord::from_registers(&registers);
+        // Like inserts of statements, seek to the key first: an entry already there is
+        // overwritten in place, which takes the key from the cursor's position.
+        let cursor = &mut self.cursor;
+        self.tx
+            .wait(|| cursor.seek(SeekKey::TableRowId(key as u64), SeekOp::EQ))?;
+        self.tx
+            .wait(|| cursor.insert(&BTreeKey::new_table_rowid(key as u64, Some(&record)), true))
+    }
+
+    /// Deletes the entry with `key`, returning whether there was one.
+    pub fn delete(&mut self, key: i64) -> Result<bool> {
+        self.tx.check_writable()?;
+        let cursor = &mut self.cursor;
+        if !self
+            .tx
+            .wait(|| cursor.seek(SeekKey::TableRowId(key as u64), SeekOp::EQ))?
+        {
+            return Ok(false);
+        }
+        self.tx.wait(|| cursor.delete())?;
+        Ok(true)
+    }
+
+    /// Moves to the first entry, for [Self::next_entry] to return the entries from there on.
+    pub fn rewind(&mut self) -> Result<()> {
+        let cursor = &mut self.cursor;
+        self.tx.wait(|| cursor.rewind())
+    }
+
+    /// Moves to the first entry with a key greater than or equal to `key`, for
+    /// [Self::next_entry] to return the entries from there on.
+    pub fn seek(&mut self, key: i64) -> Result<()> {
+        let cursor = &mut self.cursor;
+        self.tx
+            .wait(|| cursor.seek(SeekKey::TableRowId(key as u64), SeekOp::GE))?;
+        Ok(())
+    }
+
+    /// Returns the key and value of the entry the cursor is on and moves to the next one, in
+    /// the order of keys, or returns `None` at the end of the b-tree. Writes through the cursor
+    /// move it, so it has to be positioned again with [Self::rewind] or [Self::seek] after
+    /// them.
+    pub fn next_entry(&mut self) -> Result<Option<(i64, Vec<OwnedValue>)>> {
+        let Some(entry) = self.current() else {
+            return Ok(None);
+        };
+        let cursor = &mut self.cursor;
+        self.tx.wait(|| cursor.next())?;
+        Ok(Some(entry))
+    }
+
+    fn current(&self) -> Option<(i64, Vec<OwnedValue>)> {
+        if self.cursor.is_empty() {
+            return None;
+        }
+        let key = self.cursor.rowid().ok().flatten()?;
+        let record = self.cursor.record();
+        let value = record
+            .as_ref()?
+            .get_values()
+            .iter()
+            .map(|value| value.to_owned())
+            .collect();
+        Some((key as i64, value))
+    }
+}
@@ -1,51 +1,96 @@
-mod error;
+pub mod cdc;
+mod checkpointer;
+pub mod error;
+mod export;
 mod ext;
 mod fast_lock;
 mod function;
 mod functions;
 mod info;
+mod introspection;
 mod io;
 #[cfg(feature = "json")]
 mod json;
+pub mod kv;
+mod matview;
+mod memory_limit;
 pub mod mvcc;
+mod paging;
 mod parameters;
+mod plan_cache;
+pub mod pool;
 mod pseudo;
+mod rebuild;
 pub mod result;
+mod row_filter;
 mod schema;
+pub mod snapshot;
 mod storage;
+mod transaction;
 mod translate;
 pub mod types;
 #[allow(dead_code)]
 mod util;
 mod vdbe;
+#[cfg(feature = "vector")]
 mod vector;
 
 #[cfg(not(target_family = "wasm"))]
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
-use crate::{fast_lock::SpinLock, translate::optimizer::optimize_plan};
-pub use error::LimboError;
+use crate::{
+    fast_lock::SpinLock,
+    translate::optimizer::{optimize_plan, use_automatic_indexes},
+};
+use cdc::{
+    ChangeBuffer, ChangeReceiver, ChangeSet, ChangeSubscribers, CommitToken, ConflictPolicy,
+};
+pub use checkpointer::DeferredCheckpoint;
+pub use error::{ExecutionLimit, LimboError, SqliteError};
+pub use export::ExportFormat;
 use fallible_iterator::FallibleIterator;
+pub use fallible_streaming_iterator::FallibleStreamingIterator;
+pub use introspection::{ColumnInfo, IndexColumnInfo, IndexInfo, TableInfo};
 pub use io::clock::{Clock, Instant};
 #[cfg(all(feature = "fs", target_family = "unix"))]
 pub use io::UnixIO;
 #[cfg(all(feature = "fs", target_os = "linux", feature = "io_uring"))]
 pub use io::UringIO;
-pub use io::{Buffer, Completion, File, MemoryIO, OpenFlags, PlatformIO, WriteCompletion, IO};
-use limbo_ext::{ResultCode, VTabKind, VTabModuleImpl};
+pub use io::{
+    Buffer, Completion, DeterministicIO, DeviceCharacteristics, File, MemoryIO, OpenFlags,
+    PlatformIO, WriteCompletion, IO,
+};
+#[cfg(feature = "compression")]
+pub use io::{CompressedIO, Compression, CompressionOptions};
+pub use kv::{KvCursor, KvTransaction};
+#[cfg(feature = "carray")]
+pub use limbo_carray::CArray;
+use limbo_ext::{ConstraintInfo, ResultCode, VTabKind, VTabModuleImpl};
+pub use limbo_macros::FromRow;
 use limbo_sqlite3_parser::{ast, ast::Cmd, lexer::sql::Parser};
+use memory_limit::MemoryLimit;
+pub use memory_limit::{MemoryPressure, MemoryPressureCallback};
+pub use paging::{PagingOptions, ResultPage};
 use parking_lot::RwLock;
+pub use plan_cache::PlanCacheStats;
+use plan_cache::{PlanCache, DEFAULT_PLAN_CACHE_CAPACITY};
+pub use pool::{ConnectionInit, ConnectionPool, PooledConnection, PooledWriter, WriteRequest};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+pub use row_filter::{RowFilter, RowFilterHook};
 use schema::{Column, Schema};
+pub use schema::{ForeignKey, ForeignKeyAction};
+pub use snapshot::Snapshot;
 use std::{
     borrow::Cow,
     cell::{Cell, RefCell, UnsafeCell},
     collections::HashMap,
     io::Write,
+    marker::PhantomData,
     num::NonZero,
     ops::Deref,
     rc::Rc,
-    sync::{Arc, OnceLock},
+    sync::{Arc, Mutex, OnceLock},
 };
 use storage::btree::btree_init_page;
 #[cfg(feature = "fs")]
@@ -54,22 +99,41 @@ pub use storage::{
     buffer_pool::BufferPool,
     database::DatabaseStorage,
     pager::PageRef,
-    pager::{Page, Pager},
-    wal::{CheckpointMode, CheckpointResult, CheckpointStatus, Wal, WalFile, WalFileShared},
+    pager::{CorruptPage, Page, Pager, TempStore},
+    wal::{
+        CheckpointMode, CheckpointResult, CheckpointStatus, LockingMode, SyncMode, Wal,
+        WalBackpressure, WalBackpressureStats, WalCommit, WalFile, WalFileShared,
+    },
 };
 use storage::{
     page_cache::DumbLruPageCache,
     pager::allocate_page,
     sqlite3_ondisk::{DatabaseHeader, DATABASE_HEADER_SIZE},
 };
+use transaction::TxSnapshot;
+pub use transaction::{Savepoint, Transaction};
 use translate::select::prepare_select_plan;
 pub use types::OwnedValue;
 pub use types::RefValue;
 use util::{columns_from_create_table_body, parse_schema_rows};
-use vdbe::{builder::QueryMode, VTabOpaqueCursor};
+pub use vdbe::{
+    arena::AllocationStats, ExecutionLimits, FromRow, FromValueRow, InsnProfile, TableStats,
+};
+use vdbe::{
+    builder::{CursorType, QueryMode},
+    insn::Insn,
+    VTabOpaqueCursor,
+};
 pub type Result<T, E = LimboError> = std::result::Result<T, E>;
 pub static DATABASE_VERSION: OnceLock<String> = OnceLock::new();
 
+/// In-memory databases opened with `cache=shared`, keyed by name. The entries are weak so
+/// that, like in SQLite, a shared database is freed once its last handle is dropped.
+#[cfg(feature = "fs")]
+static SHARED_MEMORY_DATABASES: OnceLock<
+    std::sync::Mutex<HashMap<String, std::sync::Weak<Database>>>,
+> = OnceLock::new();
+
 #[derive(Clone, PartialEq, Eq)]
 enum TransactionState {
     Write,
@@ -93,6 +157,13 @@ pub struct Database {
     // create DB connections.
     shared_page_cache: Arc<RwLock<DumbLruPageCache>>,
     shared_wal: Arc<UnsafeCell<WalFileShared>>,
+    change_subscribers: ChangeSubscribers,
+    /// Whether connections check pages before trusting them, see [Database::open_untrusted].
+    untrusted: bool,
+    memory_limit: Arc<MemoryLimit>,
+    deferred_checkpoint: Mutex<Option<DeferredCheckpoint>>,
+    /// The key signing the tokens of [Connection::next_page].
+    page_token_key: std::collections::hash_map::RandomState,
 }
 
 unsafe impl Send for Database {}
@@ -101,28 +172,72 @@ unsafe impl Sync for Database {}
 impl Database {
     #[cfg(feature = "fs")]
     pub fn open_file(io: Arc<dyn IO>, path: &str, enable_mvcc: bool) -> Result<Arc<Database>> {
+        Self::open_file_with(io, path, enable_mvcc, false)
+    }
+
+    /// Opens a database file that may have been crafted to break the reader, e.g. one
+    /// received from a user or produced by a fuzzer. The header and every b-tree page are
+    /// checked against the invariants of the file format before they are used, and a file
+    /// that breaks them fails with [LimboError::Corrupt] instead of crashing the process.
+    #[cfg(feature = "fs")]
+    pub fn open_untrusted(io: Arc<dyn IO>, path: &str) -> Result<Arc<Database>> {
+        Self::open_file_with(io, path, false, true)
+    }
+
+    #[cfg(feature = "fs")]
+    fn open_file_with(
+        io: Arc<dyn IO>,
+        path: &str,
+        enable_mvcc: bool,
+        untrusted: bool,
+    ) -> Result<Arc<Database>> {
         use storage::wal::WalFileShared;
 
         let file = io.open_file(path, OpenFlags::Create, true)?;
         maybe_init_database_file(&file, &io)?;
-        let db_file = Arc::new(DatabaseFile::new(file));
+        let db_file = Arc::new(DatabaseFile::new(file.clone()));
         let wal_path = format!("{}-wal", path);
         let db_header = Pager::begin_open(db_file.clone())?;
         io.run_once()?;
+        if untrusted {
+            let header = db_header.lock();
+            storage::sqlite3_ondisk::check_database_header(&header)?;
+            let file_pages = file.size()? / header.page_size as u64;
+            if header.database_size as u64 > file_pages {
+                return Err(LimboError::Corrupt(format!(
+                    "database size of {} pages exceeds the {} pages of the file",
+                    header.database_size, file_pages
+                )));
+            }
+        }
         let page_size = db_header.lock().page_size;
         let wal_shared = WalFileShared::open_shared(&io, wal_path.as_str(), page_size)?;
-        Self::open(io, db_file, wal_shared, enable_mvcc)
+        Self::open_with(io, db_file, wal_shared, enable_mvcc, untrusted)
     }
 
-    #[allow(clippy::arc_with_non_send_sync)]
     pub fn open(
         io: Arc<dyn IO>,
         db_file: Arc<dyn DatabaseStorage>,
         shared_wal: Arc<UnsafeCell<WalFileShared>>,
         enable_mvcc: bool,
+    ) -> Result<Arc<Database>> {
+        Self::open_with(io, db_file, shared_wal, enable_mvcc, false)
+    }
+
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn open_with(
+        io: Arc<dyn IO>,
+        db_file: Arc<dyn DatabaseStorage>,
+        shared_wal: Arc<UnsafeCell<WalFileShared>>,
+        enable_mvcc: bool,
+        untrusted: bool,
     ) -> Result<Arc<Database>> {
         let db_header = Pager::begin_open(db_file.clone())?;
         io.run_once()?;
+        // Commits recovered from the WAL may have changed the header since the last checkpoint.
+        if unsafe { &*shared_wal.get() }.begin_read_database_header(db_header.clone())? {
+            io.run_once()?;
+        }
         DATABASE_VERSION.get_or_init(|| {
             let version = db_header.lock().version_number;
             version.to_string()
@@ -135,8 +250,11 @@ impl Database {
         } else {
             None
         };
-        let shared_page_cache = Arc::new(RwLock::new(DumbLruPageCache::new(10)));
         let page_size = db_header.lock().page_size;
+        let memory_limit = Arc::new(MemoryLimit::default());
+        let mut page_cache = DumbLruPageCache::new(10);
+        page_cache.set_memory_limit(memory_limit.clone(), page_size as usize);
+        let shared_page_cache = Arc::new(RwLock::new(page_cache));
         let header = db_header;
         let schema = Arc::new(RwLock::new(Schema::new()));
         let db = Database {
@@ -148,6 +266,11 @@ impl Database {
             db_file,
             io: io.clone(),
             page_size,
+            change_subscribers: ChangeSubscribers::default(),
+            untrusted,
+            memory_limit,
+            deferred_checkpoint: Mutex::new(None),
+            page_token_key: std::collections::hash_map::RandomState::new(),
         };
         let db = Arc::new(db);
         {
@@ -159,10 +282,129 @@ impl Database {
                 .expect("lock on schema should succeed first try");
             let syms = conn.syms.borrow();
             parse_schema_rows(rows, &mut schema, io, syms.deref(), None)?;
+            drop(schema);
+            drop(syms);
+            matview::load(&conn)?;
+            #[cfg(feature = "vector")]
+            vector::index::load(&conn)?;
+            cdc::load(&conn)?;
         }
         Ok(db)
     }
 
+    /// Takes a snapshot of the database to back up its files from, see [Snapshot].
+    pub fn snapshot(self: &Arc<Database>) -> Result<Snapshot> {
+        Snapshot::new(self)
+    }
+
+    /// Writes a consistent copy of the database to a new database file at `path`, with the
+    /// frames committed to the WAL so far backfilled into it, while writes go on. The database
+    /// file and its WAL are left as they are, see [Snapshot::checkpoint_into].
+    pub fn checkpoint_into(self: &Arc<Database>, path: &str) -> Result<()> {
+        self.snapshot()?.checkpoint_into(path)
+    }
+
+    /// Subscribes to the row changes of every transaction committed from now on, by any
+    /// connection to this database.
+    pub fn subscribe_changes(&self) -> ChangeReceiver {
+        self.change_subscribers.subscribe()
+    }
+
+    /// Sets a soft limit in bytes on the memory used for cached pages and for the sorters and
+    /// transient b-trees of statements, like `sqlite3_soft_heap_limit64`. Statements don't fail
+    /// when it is reached: pages are evicted from the cache and transient data is spilled to
+    /// temp files instead, see [Database::on_memory_pressure]. 0 removes the limit. Returns the
+    /// previous limit.
+    pub fn set_memory_limit(&self, bytes: usize) -> usize {
+        let previous = self.memory_limit.set_limit(bytes);
+        self.shared_page_cache.write().release_memory(0);
+        previous
+    }
+
+    pub fn memory_limit(&self) -> usize {
+        self.memory_limit.limit()
+    }
+
+    /// Bytes currently counted against the memory limit.
+    pub fn memory_used(&self) -> usize {
+        self.memory_limit.used()
+    }
+
+    /// Registers a callback that is invoked with a [MemoryPressure] whenever the memory limit
+    /// makes the database give up memory it would otherwise keep. Passing `None` removes the
+    /// current callback.
+    pub fn on_memory_pressure(&self, callback: Option<MemoryPressureCallback>) {
+        self.memory_limit.set_callback(callback);
+    }
+
+    /// Delays write transactions while the WAL has at least `backpressure.max_frames` frames,
+    /// for up to `backpressure.max_delay` each. A delayed write transaction checkpoints what it
+    /// can and steps with [StepResult::IO] until readers that keep the WAL from restarting are
+    /// done, instead of making the WAL grow further. Applies to every connection.
+    pub fn set_wal_backpressure(&self, backpressure: WalBackpressure) {
+        unsafe { &*self.shared_wal.get() }.set_backpressure(backpressure);
+    }
+
+    pub fn wal_backpressure(&self) -> WalBackpressure {
+        unsafe { &*self.shared_wal.get() }.backpressure()
+    }
+
+    /// How many write transactions WAL backpressure delayed, and for how long.
+    pub fn wal_backpressure_stats(&self) -> WalBackpressureStats {
+        unsafe { &*self.shared_wal.get() }.backpressure_stats()
+    }
+
+    /// Stops commits from checkpointing when the WAL reaches its threshold, so they don't wait
+    /// for the I/O of checkpoints. The WAL is then checkpointed as `config` says by
+    /// [Database::checkpoint_if_needed], which the owner of the database calls from the thread
+    /// it uses the database from.
+    pub fn defer_checkpoints(&self, config: DeferredCheckpoint) {
+        *self.deferred_checkpoint.lock().unwrap() = Some(config);
+        unsafe { &*self.shared_wal.get() }.set_defer_checkpoints(true);
+    }
+
+    /// Makes commits checkpoint again, see [Database::defer_checkpoints].
+    pub fn stop_deferring_checkpoints(&self) {
+        self.deferred_checkpoint.lock().unwrap().take();
+        unsafe { &*self.shared_wal.get() }.set_defer_checkpoints(false);
+    }
+
+    /// Checkpoints the WAL if checkpoints are deferred and it has reached the thresholds given
+    /// to [Database::defer_checkpoints]. Returns `None` when nothing was checkpointed.
+    pub fn checkpoint_if_needed(self: &Arc<Database>) -> Result<Option<CheckpointResult>> {
+        let Some(config) = *self.deferred_checkpoint.lock().unwrap() else {
+            return Ok(None);
+        };
+        checkpointer::checkpoint_if_needed(self, &config)
+    }
+
+    /// Keeps the commits in the WAL readable with `SELECT ... AS OF <marker>` by no longer
+    /// checkpointing when commits reach the checkpoint threshold or in
+    /// [Database::checkpoint_if_needed]. The WAL then grows until a checkpoint is asked for, e.g. with
+    /// `PRAGMA wal_checkpoint`, which drops the history it backfills. Time travel isn't
+    /// supported with MVCC.
+    pub fn set_retain_history(&self, retain: bool) {
+        unsafe { &*self.shared_wal.get() }.set_retain_history(retain);
+    }
+
+    pub fn retains_history(&self) -> bool {
+        unsafe { &*self.shared_wal.get() }.retains_history()
+    }
+
+    /// The commits that can be read as of, oldest first, see [Database::set_retain_history].
+    pub fn history(&self) -> Vec<WalCommit> {
+        unsafe { &*self.shared_wal.get() }.history()
+    }
+
+    pub(crate) fn wal_frames(&self) -> u64 {
+        unsafe { &*self.shared_wal.get() }.max_frame()
+    }
+
+    /// Creates a pool of up to `max_connections` connections to this database.
+    pub fn pool(self: &Arc<Database>, max_connections: usize) -> Rc<ConnectionPool> {
+        ConnectionPool::new(self.clone(), max_connections)
+    }
+
     pub fn connect(self: &Arc<Database>) -> Result<Rc<Connection>> {
         let buffer_pool = Rc::new(BufferPool::new(self.page_size as usize));
 
@@ -180,6 +422,8 @@ impl Database {
             self.shared_page_cache.clone(),
             buffer_pool,
         )?);
+        pager.set_untrusted(self.untrusted);
+        pager.set_memory_limit(self.memory_limit.clone());
         let conn = Rc::new(Connection {
             _db: self.clone(),
             pager: pager.clone(),
@@ -192,6 +436,21 @@ impl Database {
             last_change: Cell::new(0),
             syms: RefCell::new(SymbolTable::new()),
             total_changes: Cell::new(0),
+            tracer: RefCell::new(None),
+            row_filter: RefCell::new(None),
+            metadata: RefCell::new(HashMap::new()),
+            table_writes: RefCell::new(HashMap::new()),
+            table_stats: RefCell::new(HashMap::new()),
+            automatic_index: Cell::new(cfg!(feature = "fs")),
+            vdbe_trace: Cell::new(false),
+            rng: RefCell::new(None),
+            temp_store: Cell::new(TempStore::Default),
+            plan_cache: RefCell::new(PlanCache::new(DEFAULT_PLAN_CACHE_CAPACITY)),
+            changes: RefCell::new(ChangeBuffer::default()),
+            writing_materialized_views: Cell::new(false),
+            last_commit_token: Cell::new(None),
+            replicated_token: Cell::new(None),
+            tx_snapshot: RefCell::new(None),
         });
         if let Err(e) = conn.register_builtins() {
             return Err(LimboError::ExtensionError(e));
@@ -204,25 +463,171 @@ impl Database {
     #[cfg(feature = "fs")]
     #[allow(clippy::arc_with_non_send_sync)]
     pub fn open_new(path: &str, vfs: &str) -> Result<(Arc<dyn IO>, Arc<Database>)> {
-        let vfsmods = crate::ext::add_builtin_vfs_extensions(None)?;
-        let io: Arc<dyn IO> = match vfsmods.iter().find(|v| v.0 == vfs).map(|v| v.1.clone()) {
-            Some(vfs) => vfs,
-            None => match vfs.trim() {
-                "memory" => Arc::new(MemoryIO::new()),
-                "syscall" => Arc::new(PlatformIO::new()?),
-                #[cfg(all(target_os = "linux", feature = "io_uring"))]
-                "io_uring" => Arc::new(UringIO::new()?),
-                other => {
-                    return Err(LimboError::InvalidArgument(format!(
-                        "no such VFS: {}",
-                        other
-                    )));
-                }
-            },
-        };
+        let io = Self::vfs_io(vfs)?;
         let db = Self::open_file(io.clone(), path, false)?;
         Ok((io, db))
     }
+
+    /// Open a database from a filename that may be a SQLite URI (`file:...`).
+    ///
+    /// In-memory databases (`:memory:`, `file::memory:` or `mode=memory`) are private to the
+    /// returned handle, unless `cache=shared` is given: then every open of the same name in
+    /// this process returns the same database for as long as a handle to it is alive.
+    #[cfg(feature = "fs")]
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub fn open_uri(uri: &str, enable_mvcc: bool) -> Result<(Arc<dyn IO>, Arc<Database>)> {
+        let opts = util::parse_sqlite_uri(uri)?;
+        if opts.mode != util::OpenMode::Memory && opts.path != ":memory:" {
+            let io = match &opts.vfs {
+                Some(vfs) => Self::vfs_io(vfs)?,
+                None => Arc::new(PlatformIO::new()?),
+            };
+            let io = Self::compression_io(io, &opts)?;
+            let db = Self::open_file(io.clone(), &opts.path, enable_mvcc)?;
+            return Ok((io, db));
+        }
+        if opts.cache != util::CacheMode::Shared {
+            let io: Arc<dyn IO> = Arc::new(MemoryIO::new());
+            let db = Self::open_file(io.clone(), &opts.path, enable_mvcc)?;
+            return Ok((io, db));
+        }
+        let mut shared = SHARED_MEMORY_DATABASES
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap();
+        shared.retain(|_, db| db.strong_count() > 0);
+        if let Some(db) = shared.get(&opts.path).and_then(std::sync::Weak::upgrade) {
+            return Ok((db.io.clone(), db));
+        }
+        let io: Arc<dyn IO> = Arc::new(MemoryIO::new());
+        let db = Self::open_file(io.clone(), &opts.path, enable_mvcc)?;
+        shared.insert(opts.path, Arc::downgrade(&db));
+        Ok((io, db))
+    }
+
+    /// Stacks the compression shim on `io` if the URI asked for compression or checksums.
+    #[cfg(feature = "fs")]
+    fn compression_io(io: Arc<dyn IO>, opts: &util::OpenOptions) -> Result<Arc<dyn IO>> {
+        if opts.compress.is_none() && !opts.checksum {
+            return Ok(io);
+        }
+        #[cfg(feature = "compression")]
+        {
+            let compression = match &opts.compress {
+                Some(compression) => compression.parse()?,
+                None => Compression::None,
+            };
+            let options = CompressionOptions {
+                compression,
+                checksum: opts.checksum,
+            };
+            Ok(Arc::new(CompressedIO::new(io, options)))
+        }
+        #[cfg(not(feature = "compression"))]
+        Err(LimboError::InvalidArgument(
+            "compress and checksum require the compression feature".to_string(),
+        ))
+    }
+
+    /// The IO of the VFS called `vfs`: one registered by an extension loaded at runtime, one
+    /// built in, or one of core's own IO backends.
+    #[cfg(feature = "fs")]
+    fn vfs_io(vfs: &str) -> Result<Arc<dyn IO>> {
+        if let Some((_, io)) = crate::ext::get_vfs_modules()
+            .into_iter()
+            .find(|(name, _)| name == vfs)
+        {
+            return Ok(io);
+        }
+        let vfsmods = crate::ext::add_builtin_vfs_extensions(None)?;
+        Ok(
+            match vfsmods.iter().find(|v| v.0 == vfs).map(|v| v.1.clone()) {
+                Some(vfs) => vfs,
+                None => match vfs.trim() {
+                    "memory" => Arc::new(MemoryIO::new()),
+                    "syscall" => Arc::new(PlatformIO::new()?),
+                    #[cfg(all(target_os = "linux", feature = "io_uring"))]
+                    "io_uring" => Arc::new(UringIO::new()?),
+                    other => {
+                        return Err(LimboError::InvalidArgument(format!(
+                            "no such VFS: {}",
+                            other
+                        )));
+                    }
+                },
+            },
+        )
+    }
+}
+
+/// Opens a pager on a private database, for b-trees that only live as long as a statement.
+/// With [TempStore::Memory] the database is in memory and every page stays in the page cache.
+/// Otherwise it is an anonymous temp file of `io`, which dirty pages are spilled to once there
+/// are more than `cache_size` of them.
+#[cfg(feature = "fs")]
+pub(crate) fn open_ephemeral_pager(
+    io: &Arc<dyn IO>,
+    temp_store: TempStore,
+    cache_size: usize,
+    memory_limit: Option<Arc<MemoryLimit>>,
+) -> Result<Rc<Pager>> {
+    use storage::wal::WalFileShared;
+
+    // The WAL is never written, so it stays in memory either way.
+    let memory_io: Arc<dyn IO> = Arc::new(MemoryIO::new());
+    let (file, io) = match temp_store {
+        TempStore::Memory => (
+            memory_io.open_file(":memory:", OpenFlags::Create, false)?,
+            memory_io.clone(),
+        ),
+        TempStore::Default | TempStore::File => (io.open_temp_file()?, io.clone()),
+    };
+    maybe_init_database_file(&file, &io)?;
+    let db_file = Arc::new(DatabaseFile::new(file));
+    let db_header = Pager::begin_open(db_file.clone())?;
+    io.run_once()?;
+    let page_size = db_header.lock().page_size;
+    let shared_wal = WalFileShared::open_shared(&memory_io, ":memory:-wal", page_size)?;
+    let buffer_pool = Rc::new(BufferPool::new(page_size as usize));
+    let wal = Rc::new(RefCell::new(WalFile::new(
+        memory_io,
+        page_size as usize,
+        shared_wal,
+        buffer_pool.clone(),
+    )));
+    let mut page_cache = DumbLruPageCache::new(cache_size);
+    if let Some(memory_limit) = &memory_limit {
+        page_cache.set_memory_limit(memory_limit.clone(), page_size as usize);
+    }
+    let pager = Pager::finish_open(
+        db_header,
+        db_file,
+        wal,
+        io,
+        Arc::new(RwLock::new(page_cache)),
+        buffer_pool,
+    )?;
+    if temp_store != TempStore::Memory {
+        pager.set_spill_threshold(cache_size);
+        if let Some(memory_limit) = memory_limit {
+            pager.set_memory_limit(memory_limit);
+        }
+    }
+    pager.begin_read_tx()?;
+    pager.begin_write_tx()?;
+    Ok(Rc::new(pager))
+}
+
+#[cfg(not(feature = "fs"))]
+pub(crate) fn open_ephemeral_pager(
+    _io: &Arc<dyn IO>,
+    _temp_store: TempStore,
+    _cache_size: usize,
+    _memory_limit: Option<Arc<MemoryLimit>>,
+) -> Result<Rc<Pager>> {
+    Err(LimboError::InternalError(
+        "ephemeral b-trees need the fs feature".to_string(),
+    ))
 }
 
 pub fn maybe_init_database_file(file: &Arc<dyn File>, io: &Arc<dyn IO>) -> Result<()> {
@@ -285,11 +690,48 @@ pub struct Connection {
     last_change: Cell<i64>,
     total_changes: Cell<i64>,
     syms: RefCell<SymbolTable>,
+    tracer: RefCell<Option<Rc<Tracer>>>,
+    row_filter: RefCell<Option<Rc<RowFilterHook>>>,
+    /// Values embedders attach to the connection, see [Connection::set_metadata].
+    metadata: RefCell<HashMap<String, OwnedValue>>,
+    /// Rows written per table since it was last analyzed, which PRAGMA optimize uses to find
+    /// tables with stale statistics.
+    table_writes: RefCell<HashMap<String, u64>>,
+    /// Access counters of every table used since the connection was opened.
+    table_stats: RefCell<HashMap<String, Rc<Cell<TableStats>>>>,
+    automatic_index: Cell<bool>,
+    vdbe_trace: Cell<bool>,
+    /// The source of random() and randomblob() once seeded, see [Connection::seed_random].
+    rng: RefCell<Option<StdRng>>,
+    temp_store: Cell<TempStore>,
+    plan_cache: RefCell<PlanCache>,
+    /// Row changes of the open write transaction, published to subscribers once it commits.
+    changes: RefCell<ChangeBuffer>,
+    /// Set while the connection maintains materialized views, which other statements can't
+    /// write.
+    writing_materialized_views: Cell<bool>,
+    last_commit_token: Cell<Option<CommitToken>>,
+    /// The token of the changes being applied from another database, which their commit takes.
+    replicated_token: Cell<Option<CommitToken>>,
+    /// What [Connection::rollback] puts back, taken when the explicit transaction began to write.
+    tx_snapshot: RefCell<Option<TxSnapshot>>,
 }
 
 impl Connection {
     pub fn prepare(self: &Rc<Connection>, sql: impl AsRef<str>) -> Result<Statement> {
-        let sql = sql.as_ref();
+        match self.prepare_with_tail(sql.as_ref())? {
+            (Some(stmt), _) => Ok(stmt),
+            (None, _) => todo!(),
+        }
+    }
+
+    /// Prepares the first statement of `sql`, also returning the offset in `sql` where it ends
+    /// and the next one may start. There is no statement if `sql` holds only whitespace or
+    /// comments.
+    pub fn prepare_with_tail(
+        self: &Rc<Connection>,
+        sql: &str,
+    ) -> Result<(Option<Statement>, usize)> {
         tracing::trace!("Preparing: {}", sql);
         let mut parser = Parser::new(sql.as_bytes());
         let cmd = parser.next()?;
@@ -297,29 +739,16 @@ impl Connection {
         if let Some(cmd) = cmd {
             match cmd {
                 Cmd::Stmt(stmt) => {
-                    let program = Rc::new(translate::translate(
-                        self.schema
-                            .try_read()
-                            .ok_or(LimboError::SchemaLocked)?
-                            .deref(),
-                        stmt,
-                        self.header.clone(),
-                        self.pager.clone(),
-                        Rc::downgrade(self),
-                        &syms,
-                        QueryMode::Normal,
-                    )?);
-                    Ok(Statement::new(
-                        program,
-                        self._db.mv_store.clone(),
-                        self.pager.clone(),
-                    ))
+                    let program = self.translate_stmt(stmt, &sql[..parser.offset()], &syms)?;
+                    let stmt =
+                        Statement::new(program, self._db.mv_store.clone(), self.pager.clone());
+                    Ok((Some(stmt), parser.offset()))
                 }
                 Cmd::Explain(_stmt) => todo!(),
                 Cmd::ExplainQueryPlan(_stmt) => todo!(),
             }
         } else {
-            todo!()
+            Ok((None, sql.len()))
         }
     }
 
@@ -329,27 +758,35 @@ impl Connection {
         let mut parser = Parser::new(sql.as_bytes());
         let cmd = parser.next()?;
         match cmd {
-            Some(cmd) => self.run_cmd(cmd),
+            Some(cmd) => self.run_cmd(cmd, &sql[..parser.offset()]),
             None => Ok(None),
         }
     }
 
-    pub(crate) fn run_cmd(self: &Rc<Connection>, cmd: Cmd) -> Result<Option<Statement>> {
+    pub(crate) fn run_cmd(self: &Rc<Connection>, cmd: Cmd, sql: &str) -> Result<Option<Statement>> {
         let syms = self.syms.borrow();
         match cmd {
-            Cmd::Stmt(ref stmt) | Cmd::Explain(ref stmt) => {
-                let program = translate::translate(
+            Cmd::Stmt(stmt) => {
+                let program = self.translate_stmt(stmt, sql, &syms)?;
+                let stmt = Statement::new(program, self._db.mv_store.clone(), self.pager.clone());
+                Ok(Some(stmt))
+            }
+            Cmd::Explain(ref stmt) => {
+                let mut stmt = stmt.clone();
+                self.apply_row_filter(&mut stmt)?;
+                let mut program = translate::translate(
                     self.schema
                         .try_read()
                         .ok_or(LimboError::SchemaLocked)?
                         .deref(),
-                    stmt.clone(),
+                    stmt,
                     self.header.clone(),
                     self.pager.clone(),
                     Rc::downgrade(self),
                     &syms,
                     cmd.into(),
                 )?;
+                program.sql = sql.trim().to_string();
                 let stmt = Statement::new(
                     program.into(),
                     self._db.mv_store.clone(),
@@ -357,9 +794,10 @@ impl Connection {
                 );
                 Ok(Some(stmt))
             }
-            Cmd::ExplainQueryPlan(stmt) => {
+            Cmd::ExplainQueryPlan(mut stmt) => {
+                self.apply_row_filter(&mut stmt)?;
                 match stmt {
-                    ast::Stmt::Select(select) => {
+                    ast::Stmt::Select(select) | ast::Stmt::SelectAsOf { select, .. } => {
                         let mut plan = prepare_select_plan(
                             self.schema
                                 .try_read()
@@ -376,6 +814,9 @@ impl Connection {
                                 .ok_or(LimboError::SchemaLocked)?
                                 .deref(),
                         )?;
+                        if self.automatic_index() {
+                            use_automatic_indexes(&mut plan)?;
+                        }
                         let _ = std::io::stdout().write_all(plan.to_string().as_bytes());
                     }
                     _ => todo!(),
@@ -385,6 +826,47 @@ impl Connection {
         }
     }
 
+    /// Translates `stmt`, reusing the program of an earlier prepare of the same statement if
+    /// nothing it was planned against changed since.
+    fn translate_stmt(
+        self: &Rc<Connection>,
+        mut stmt: ast::Stmt,
+        sql: &str,
+        syms: &SymbolTable,
+    ) -> Result<Rc<vdbe::Program>> {
+        // Before the cache lookup, so that programs are cached with the filters they apply.
+        self.apply_row_filter(&mut stmt)?;
+        let schema = self.schema.try_read().ok_or(LimboError::SchemaLocked)?;
+        if !self.writing_materialized_views.get() {
+            matview::check_write(&schema, &stmt)?;
+            #[cfg(feature = "vector")]
+            vector::index::check_write(&schema, &stmt)?;
+        }
+        let key = self.plan_cache.borrow().key(&stmt);
+        if let Some(key) = &key {
+            if let Some(program) = self.plan_cache.borrow_mut().get(key, &schema) {
+                return Ok(program);
+            }
+        }
+        let mut program = translate::translate(
+            schema.deref(),
+            stmt,
+            self.header.clone(),
+            self.pager.clone(),
+            Rc::downgrade(self),
+            syms,
+            QueryMode::Normal,
+        )?;
+        program.sql = sql.trim().to_string();
+        let program = Rc::new(program);
+        if let Some(key) = key {
+            self.plan_cache
+                .borrow_mut()
+                .insert(key, program.clone(), &schema);
+        }
+        Ok(program)
+    }
+
     pub fn query_runner<'a>(self: &'a Rc<Connection>, sql: &'a [u8]) -> QueryRunner<'a> {
         QueryRunner::new(self, sql)
     }
@@ -398,7 +880,8 @@ impl Connection {
         let syms = self.syms.borrow();
         if let Some(cmd) = cmd {
             match cmd {
-                Cmd::Explain(stmt) => {
+                Cmd::Explain(mut stmt) => {
+                    self.apply_row_filter(&mut stmt)?;
                     let program = translate::translate(
                         self.schema
                             .try_read()
@@ -415,18 +898,7 @@ impl Connection {
                 }
                 Cmd::ExplainQueryPlan(_stmt) => todo!(),
                 Cmd::Stmt(stmt) => {
-                    let program = translate::translate(
-                        self.schema
-                            .try_read()
-                            .ok_or(LimboError::SchemaLocked)?
-                            .deref(),
-                        stmt,
-                        self.header.clone(),
-                        self.pager.clone(),
-                        Rc::downgrade(self),
-                        &syms,
-                        QueryMode::Normal,
-                    )?;
+                    let program = self.translate_stmt(stmt, &sql[..parser.offset()], &syms)?;
 
                     let mut state =
                         vdbe::ProgramState::new(program.max_registers, program.cursor_ref.len());
@@ -447,6 +919,27 @@ impl Connection {
         Ok(())
     }
 
+    /// Runs all statements of `sql` in order, e.g. a migration script, discarding the rows
+    /// they return. The script can begin and commit transactions: if a statement fails, the
+    /// statements after it don't run and the transaction the script left open is rolled back,
+    /// unless the connection was already in a transaction when the script started.
+    pub fn execute_batch(self: &Rc<Connection>, sql: impl AsRef<str>) -> Result<()> {
+        let in_transaction = !self.get_auto_commit();
+        let result = self
+            .query_runner(sql.as_ref().as_bytes())
+            .try_for_each(|stmt| {
+                if let Some(mut stmt) = stmt? {
+                    let mut rows = stmt.query([])?;
+                    while rows.next()?.is_some() {}
+                }
+                Ok(())
+            });
+        if result.is_err() && !in_transaction && !self.get_auto_commit() {
+            self.rollback()?;
+        }
+        result
+    }
+
     pub fn cacheflush(&self) -> Result<CheckpointStatus> {
         self.pager.cacheflush()
     }
@@ -457,7 +950,11 @@ impl Connection {
     }
 
     pub fn checkpoint(&self) -> Result<CheckpointResult> {
-        let checkpoint_result = self.pager.clear_page_cache();
+        self.checkpoint_with_mode(CheckpointMode::Passive)
+    }
+
+    pub fn checkpoint_with_mode(&self, mode: CheckpointMode) -> Result<CheckpointResult> {
+        let checkpoint_result = self.pager.wal_checkpoint(mode);
         Ok(checkpoint_result)
     }
 
@@ -480,16 +977,180 @@ impl Connection {
         self.last_insert_rowid.get()
     }
 
-    fn update_last_rowid(&self, rowid: u64) {
+    /// The token of the last transaction committed by this connection that wrote rows, to wait
+    /// for with [Self::wait_for_commit] on a replica.
+    pub fn last_commit_token(&self) -> Option<CommitToken> {
+        self.last_commit_token.get()
+    }
+
+    /// Blocks until this database has made or applied the commit of `token`, so the statements
+    /// run afterwards see its rows, e.g. to read the writes of a client from a replica. Fails
+    /// with [LimboError::Busy] if that doesn't happen within `timeout`.
+    pub fn wait_for_commit(&self, token: CommitToken, timeout: std::time::Duration) -> Result<()> {
+        if !self.get_auto_commit() {
+            return Err(LimboError::TxError(
+                "cannot wait for a commit within a transaction".to_string(),
+            ));
+        }
+        if self._db.change_subscribers.wait_for(token, timeout) {
+            Ok(())
+        } else {
+            Err(LimboError::Busy)
+        }
+    }
+
+    pub(crate) fn update_last_rowid(&self, rowid: u64) {
         self.last_insert_rowid.set(rowid);
     }
 
+    /// Whether anyone subscribed to row changes, so that writes need to capture them.
+    pub(crate) fn capturing_changes(&self) -> bool {
+        !self._db.change_subscribers.is_empty()
+    }
+
+    pub(crate) fn record_table_write(&self, table_name: &str) {
+        let mut table_writes = self.table_writes.borrow_mut();
+        match table_writes.get_mut(table_name) {
+            Some(writes) => *writes += 1,
+            None => {
+                table_writes.insert(table_name.to_string(), 1);
+            }
+        }
+    }
+
+    pub(crate) fn reset_table_writes(&self, table_name: &str) {
+        self.table_writes.borrow_mut().remove(table_name);
+    }
+
+    pub(crate) fn table_writes(&self, table_name: &str) -> u64 {
+        self.table_writes
+            .borrow()
+            .get(table_name)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// How the statements of this connection accessed each table since it was opened, sorted
+    /// by table name. Also reported by `PRAGMA limbo_table_stats`.
+    pub fn table_stats(&self) -> Vec<(String, TableStats)> {
+        let mut stats: Vec<_> = self
+            .table_stats
+            .borrow()
+            .iter()
+            .map(|(name, stats)| (name.clone(), stats.get()))
+            .collect();
+        stats.sort_by(|a, b| a.0.cmp(&b.0));
+        stats
+    }
+
+    pub(crate) fn table_stats_counter(&self, table_name: &str) -> Rc<Cell<TableStats>> {
+        self.table_stats
+            .borrow_mut()
+            .entry(table_name.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Whether the planner may build transient indexes for joins, see PRAGMA automatic_index.
+    pub fn automatic_index(&self) -> bool {
+        self.automatic_index.get()
+    }
+
+    pub fn set_automatic_index(&self, enabled: bool) {
+        self.automatic_index.set(enabled);
+        self.clear_plan_cache();
+    }
+
+    /// Sets how many prepared programs are kept for reuse, 0 disables the plan cache.
+    pub fn set_plan_cache_capacity(&self, capacity: usize) {
+        self.plan_cache.borrow_mut().set_capacity(capacity);
+    }
+
+    /// Drops every cached program, so the next prepare of each statement plans it again.
+    pub fn clear_plan_cache(&self) {
+        self.plan_cache.borrow_mut().clear();
+    }
+
+    pub fn plan_cache_stats(&self) -> PlanCacheStats {
+        self.plan_cache.borrow().stats()
+    }
+
+    /// The tables of the database ordered by name, including sqlite_schema.
+    pub fn tables(&self) -> Result<Vec<TableInfo>> {
+        let schema = self.schema.try_read().ok_or(LimboError::SchemaLocked)?;
+        Ok(introspection::tables(&schema))
+    }
+
+    /// The columns of `table` in the order they were declared.
+    pub fn columns(&self, table: &str) -> Result<Vec<ColumnInfo>> {
+        let schema = self.schema.try_read().ok_or(LimboError::SchemaLocked)?;
+        introspection::columns(&schema, table)
+    }
+
+    /// The indexes of `table` ordered by name, including those built for its constraints.
+    pub fn indexes(&self, table: &str) -> Result<Vec<IndexInfo>> {
+        let schema = self.schema.try_read().ok_or(LimboError::SchemaLocked)?;
+        introspection::indexes(&schema, table)
+    }
+
+    /// The foreign keys `table` declares, in the order they were declared.
+    pub fn foreign_keys(&self, table: &str) -> Result<Vec<ForeignKey>> {
+        let schema = self.schema.try_read().ok_or(LimboError::SchemaLocked)?;
+        introspection::foreign_keys(&schema, table)
+    }
+
+    /// Whether statements report the registers each instruction changes, see PRAGMA vdbe_trace.
+    pub fn vdbe_trace(&self) -> bool {
+        self.vdbe_trace.get()
+    }
+
+    pub fn set_vdbe_trace(&self, enabled: bool) {
+        self.vdbe_trace.set(enabled);
+    }
+
+    /// Makes random() and randomblob() of this connection return the same sequence for the same
+    /// `seed`, e.g. to replay a simulation. `None` goes back to the randomness of the [IO].
+    pub fn seed_random(&self, seed: Option<u64>) {
+        *self.rng.borrow_mut() = seed.map(StdRng::seed_from_u64);
+    }
+
+    pub(crate) fn random_number(&self) -> i64 {
+        match self.rng.borrow_mut().as_mut() {
+            Some(rng) => rng.gen(),
+            None => self.pager.io.generate_random_number(),
+        }
+    }
+
+    /// Where transient b-trees of statements keep their pages, see PRAGMA temp_store.
+    pub fn temp_store(&self) -> TempStore {
+        self.temp_store.get()
+    }
+
+    pub fn set_temp_store(&self, temp_store: TempStore) {
+        self.temp_store.set(temp_store);
+    }
+
+    pub fn salvage_mode(&self) -> bool {
+        self.pager.salvage_mode()
+    }
+
+    /// Makes table and index scans skip corrupt pages rather than fail, to read what is left
+    /// of a damaged database. The pages a statement skipped are in
+    /// [Statement::corrupt_pages].
+    pub fn set_salvage_mode(&self, enabled: bool) {
+        self.pager.set_salvage_mode(enabled);
+    }
+
     pub fn set_changes(&self, nchange: i64) {
         self.last_change.set(nchange);
         let prev_total_changes = self.total_changes.get();
         self.total_changes.set(prev_total_changes + nchange);
     }
 
+    pub fn changes(&self) -> i64 {
+        self.last_change.get()
+    }
+
     pub fn total_changes(&self) -> i64 {
         self.total_changes.get()
     }
@@ -519,6 +1180,225 @@ impl Connection {
     pub fn get_auto_commit(&self) -> bool {
         *self.auto_commit.borrow()
     }
+
+    /// Applies row changes captured from another database in one transaction, resolving the
+    /// ones that don't fit this database according to `policy`. If any of them fails, none is
+    /// applied. The transaction is committed under the token of `changes`, see
+    /// [Self::wait_for_commit].
+    pub fn apply_changes(
+        self: &Rc<Connection>,
+        changes: &ChangeSet,
+        policy: ConflictPolicy,
+    ) -> Result<()> {
+        cdc::apply_changes(
+            self,
+            changes.changes.iter().map(Into::into),
+            policy,
+            Some(changes.token),
+        )
+    }
+
+    /// Replaces the definition of `table` with `create_sql` and copies its rows over, for the
+    /// changes ALTER TABLE can't make. See [rebuild] for what happens to its indexes, triggers
+    /// and foreign keys.
+    pub fn alter_table_rebuild(self: &Rc<Connection>, table: &str, create_sql: &str) -> Result<()> {
+        rebuild::alter_table_rebuild(self, table, create_sql)
+    }
+
+    /// Writes every row of `table` to `writer` in `format`, and returns how many were written.
+    /// Rows are streamed through a buffer, so this is the fast way to dump a large table.
+    pub fn export_table(
+        self: &Rc<Connection>,
+        table: &str,
+        format: ExportFormat,
+        writer: &mut dyn std::io::Write,
+    ) -> Result<u64> {
+        export::export_table(self, table, format, writer)
+    }
+
+    /// Runs the SELECT statement `sql` and returns the first page of its rows, with a token to
+    /// fetch the next one with [Self::next_page] if there are more. Nothing is kept open
+    /// between pages, see [paging] for what that means for the rows of later pages.
+    pub fn query_page(
+        self: &Rc<Connection>,
+        sql: &str,
+        params: impl IntoIterator<Item = OwnedValue>,
+        options: &PagingOptions,
+    ) -> Result<ResultPage> {
+        paging::query_page(self, sql, params.into_iter().collect(), options)
+    }
+
+    /// Returns the page of rows following the one `token` was returned with, by
+    /// [Self::query_page] or a previous call. Fails if the token has expired or wasn't issued
+    /// by this database.
+    pub fn next_page(
+        self: &Rc<Connection>,
+        token: &str,
+        options: &PagingOptions,
+    ) -> Result<ResultPage> {
+        paging::next_page(self, token, options)
+    }
+
+    /// Like [Self::apply_changes], for a changeset of SQLite's session extension.
+    pub fn apply_changeset(
+        self: &Rc<Connection>,
+        changeset: &[u8],
+        policy: ConflictPolicy,
+    ) -> Result<()> {
+        cdc::apply_changes(self, cdc::parse_changeset(changeset)?, policy, None)
+    }
+
+    /// Starts a write transaction that is rolled back unless it is committed, see
+    /// [Transaction].
+    pub fn transaction(self: &Rc<Connection>) -> Result<Transaction> {
+        Transaction::begin(self)
+    }
+
+    /// Starts a read transaction in which b-trees are read directly, see [KvTransaction].
+    pub fn begin_kv_read(self: &Rc<Connection>) -> Result<KvTransaction> {
+        KvTransaction::begin(self, false)
+    }
+
+    /// Starts a write transaction in which b-trees are read and written directly, see
+    /// [KvTransaction].
+    pub fn begin_kv_write(self: &Rc<Connection>) -> Result<KvTransaction> {
+        KvTransaction::begin(self, true)
+    }
+
+    /// Starts a read transaction that lasts until [Self::end_read_tx], across statements.
+    pub(crate) fn begin_read_tx(&self) -> Result<()> {
+        if !self.get_auto_commit() {
+            return Err(LimboError::TxError(
+                "cannot start a transaction within a transaction".to_string(),
+            ));
+        }
+        if let result::LimboResult::Busy = self.pager.begin_read_tx()? {
+            return Err(LimboError::Busy);
+        }
+        self.transaction_state.replace(TransactionState::Read);
+        self.auto_commit.replace(false);
+        Ok(())
+    }
+
+    pub(crate) fn end_read_tx(&self) -> Result<()> {
+        self.auto_commit.replace(true);
+        if self.transaction_state.replace(TransactionState::None) == TransactionState::Read {
+            self.pager.end_read_tx()?;
+        }
+        Ok(())
+    }
+
+    /// Rolls back the transaction left open by `BEGIN`, putting back the pages, the database
+    /// header, the schema and the captured row changes it began with, e.g. for a pool taking
+    /// back a connection whose user went away in the middle of a transaction. Does nothing
+    /// outside of a transaction.
+    pub fn rollback(&self) -> Result<()> {
+        let snapshot = self.tx_snapshot.take();
+        let writing = *self.transaction_state.borrow() == TransactionState::Write;
+        match snapshot {
+            Some(snapshot) if writing => snapshot.restore(self),
+            // Nothing was written, so the header is the one the transaction began with.
+            _ => self.rollback_write_tx(self.pager.db_header.lock().clone()),
+        }
+    }
+
+    /// Takes what [Connection::rollback] puts back, when an explicit transaction begins to
+    /// write, or an implicit one that maintaining tables may have to roll back.
+    pub(crate) fn snapshot_write_tx(&self) {
+        self.tx_snapshot.replace(Some(TxSnapshot::capture(self)));
+    }
+
+    /// Ends the open write transaction without committing it, putting back `header`, the
+    /// database header it started with.
+    pub(crate) fn rollback_write_tx(&self, header: DatabaseHeader) -> Result<()> {
+        self.changes.borrow_mut().take();
+        self.auto_commit.replace(true);
+        match self.transaction_state.replace(TransactionState::None) {
+            TransactionState::Write => {
+                self.pager.rollback_write_tx()?;
+                *self.pager.db_header.lock() = header;
+                Ok(())
+            }
+            TransactionState::Read => self.pager.end_read_tx(),
+            TransactionState::None => Ok(()),
+        }
+    }
+
+    /// Registers a callback that is invoked with a [TraceEvent] for every statement this
+    /// connection executes and, if `insns` is set, for every VDBE instruction as well.
+    /// Passing `None` removes the current callback.
+    pub fn trace(&self, insns: bool, callback: Option<TraceCallback>) {
+        *self.tracer.borrow_mut() = callback.map(|callback| Rc::new(Tracer { callback, insns }));
+    }
+
+    /// Registers a hook that is asked for a [RowFilter] for every table a statement reads,
+    /// updates or deletes from, whose predicate is added to the statement before it is
+    /// planned. This restricts the rows of all queries on the connection, e.g. to those with
+    /// the `tenant_id` of the current user, without changing their SQL. The hook runs when
+    /// statements are prepared, so those prepared earlier keep the filters they had. Passing
+    /// `None` removes the current hook.
+    pub fn set_row_filter(&self, hook: Option<RowFilterHook>) {
+        *self.row_filter.borrow_mut() = hook.map(Rc::new);
+    }
+
+    /// Attaches `value` to the connection under `key`, returning the value it replaces.
+    /// Embedders use this to keep state that belongs with a connection, like the user a
+    /// pooled connection serves. The values are never seen by SQL.
+    pub fn set_metadata(&self, key: impl Into<String>, value: OwnedValue) -> Option<OwnedValue> {
+        self.metadata.borrow_mut().insert(key.into(), value)
+    }
+
+    pub fn metadata(&self, key: &str) -> Option<OwnedValue> {
+        self.metadata.borrow().get(key).cloned()
+    }
+
+    pub fn remove_metadata(&self, key: &str) -> Option<OwnedValue> {
+        self.metadata.borrow_mut().remove(key)
+    }
+
+    fn apply_row_filter(&self, stmt: &mut ast::Stmt) -> Result<()> {
+        let hook = self.row_filter.borrow().clone();
+        match hook {
+            Some(hook) => row_filter::apply(stmt, &hook),
+            None => Ok(()),
+        }
+    }
+
+    /// Lifts the row filter until the returned guard is dropped, for the statements the
+    /// connection runs internally.
+    pub(crate) fn suspend_row_filter(&self) -> row_filter::Suspended<'_> {
+        row_filter::Suspended::new(&self.row_filter)
+    }
+
+    /// Lets the connection write materialized views until the returned guard is dropped.
+    pub(crate) fn write_materialized_views(&self) -> matview::Writing<'_> {
+        matview::Writing::new(&self.writing_materialized_views)
+    }
+
+    pub(crate) fn writing_materialized_views(&self) -> bool {
+        self.writing_materialized_views.get()
+    }
+}
+
+/// An event reported to the callback registered with [Connection::trace].
+#[derive(Debug, Clone, Copy)]
+pub enum TraceEvent<'a> {
+    /// A statement started executing. Carries its SQL text with the bound
+    /// parameters expanded into literals.
+    Stmt(&'a str),
+    /// A VDBE instruction is about to execute. Carries the instruction formatted
+    /// as an `EXPLAIN` row.
+    Insn(&'a str),
+    /// A VDBE instruction executed with `PRAGMA vdbe_trace` on. Carries the registers it
+    /// changed, as in `r[1]=42 r[2]='abc'`.
+    Registers(&'a str),
+}
+
+pub type TraceCallback = Box<dyn Fn(TraceEvent)>;
+
+pub(crate) struct Tracer {
+    pub callback: TraceCallback,
+    pub insns: bool,
 }
 
 pub struct Statement {
@@ -526,6 +1406,8 @@ pub struct Statement {
     state: vdbe::ProgramState,
     mv_store: Option<Rc<MvStore>>,
     pager: Rc<Pager>,
+    /// Pages skipped in salvage mode since the statement was last reset.
+    corrupt_pages: Vec<CorruptPage>,
 }
 
 impl Statement {
@@ -540,6 +1422,7 @@ impl Statement {
             state,
             mv_store,
             pager,
+            corrupt_pages: Vec::new(),
         }
     }
 
@@ -552,14 +1435,46 @@ impl Statement {
     }
 
     pub fn step(&mut self) -> Result<StepResult> {
-        self.program
-            .step(&mut self.state, self.mv_store.clone(), self.pager.clone())
+        let result = self
+            .program
+            .step(&mut self.state, self.mv_store.clone(), self.pager.clone());
+        if self.pager.salvage_mode() {
+            for page in self.pager.take_corrupt_pages() {
+                // Scans repeated within the statement, e.g. in joins, come across the same pages.
+                if !self.corrupt_pages.contains(&page) {
+                    self.corrupt_pages.push(page);
+                }
+            }
+        }
+        result
+    }
+
+    /// Corrupt pages the statement skipped, with the rows below them, since it was last reset.
+    /// Only scans in salvage mode skip pages, see [Connection::set_salvage_mode].
+    pub fn corrupt_pages(&self) -> &[CorruptPage] {
+        &self.corrupt_pages
     }
 
     pub fn run_once(&self) -> Result<()> {
         self.pager.io.run_once()
     }
 
+    /// Steps to the next row, waiting for any I/O on the way. Returns false once the statement
+    /// is done.
+    fn step_blocking(&mut self) -> Result<bool> {
+        loop {
+            match self.step()? {
+                StepResult::IO => self.run_once()?,
+                StepResult::Row => return Ok(true),
+                StepResult::Done => return Ok(false),
+                StepResult::Interrupt => {
+                    return Err(LimboError::InternalError("statement interrupted".into()))
+                }
+                StepResult::Busy => return Err(LimboError::Busy),
+            }
+        }
+    }
+
     pub fn num_columns(&self) -> usize {
         self.program.result_columns.len()
     }
@@ -576,6 +1491,24 @@ impl Statement {
         &self.program.parameters
     }
 
+    /// The SQL text the statement was prepared from.
+    pub fn sql(&self) -> &str {
+        &self.program.sql
+    }
+
+    /// The SQL text with every bound parameter replaced by the literal of its value, as the
+    /// statement would run now. Unbound parameters are left as written.
+    pub fn expanded_sql(&self) -> String {
+        parameters::expand_sql(&self.program.sql, |index| self.state.get_parameter(index))
+    }
+
+    /// The SQL text with literals and parameters replaced by `?` and whitespace collapsed, so
+    /// statements that differ only in their values share it. Suited to grouping statements
+    /// by query in logs and metrics.
+    pub fn normalized_sql(&self) -> String {
+        parameters::normalize_sql(&self.program.sql)
+    }
+
     pub fn parameters_count(&self) -> usize {
         self.program.parameters.count()
     }
@@ -586,12 +1519,134 @@ impl Statement {
 
     pub fn reset(&mut self) {
         self.state.reset();
+        self.corrupt_pages.clear();
+    }
+
+    /// Sets the budgets of each execution of the statement, which fails with
+    /// [LimboError::LimitExceeded] once it goes over one. The counts start over when the
+    /// statement is reset.
+    pub fn set_limits(&mut self, limits: ExecutionLimits) {
+        self.state.set_limits(limits);
+    }
+
+    /// Reads the pages that looking up `rowids` in the tables this statement seeks by rowid
+    /// needs, all at once rather than one after the other as executions of the statement
+    /// would. Meant for running a point lookup for many keys over storage with high latency.
+    /// It only warms the page cache: nothing is prefetched when the database is locked.
+    pub fn prefetch(&self, rowids: &[i64]) -> Result<()> {
+        let mut root_pages = Vec::new();
+        for (insn, _) in &self.program.insns {
+            let cursor_id = match insn {
+                Insn::SeekRowid { cursor_id, .. } => *cursor_id,
+                Insn::NotExists { cursor, .. } => *cursor,
+                _ => continue,
+            };
+            if let Some((_, CursorType::BTreeTable(table))) = self.program.cursor_ref.get(cursor_id)
+            {
+                if !root_pages.contains(&table.root_page) {
+                    root_pages.push(table.root_page);
+                }
+            }
+        }
+        if root_pages.is_empty() || rowids.is_empty() {
+            return Ok(());
+        }
+        let Some(conn) = self.program.connection.upgrade() else {
+            return Ok(());
+        };
+        // Outside of a transaction, the pages are read from the snapshot the next one sees.
+        let in_transaction = !matches!(*conn.transaction_state.borrow(), TransactionState::None);
+        if !in_transaction {
+            if let result::LimboResult::Busy = self.pager.begin_read_tx()? {
+                return Ok(());
+            }
+        }
+        let rowids = rowids.iter().map(|rowid| *rowid as u64).collect::<Vec<_>>();
+        let result = root_pages.into_iter().try_for_each(|root_page| {
+            storage::btree::prefetch_table_rows(&self.pager, root_page, &rowids)
+        });
+        if !in_transaction {
+            self.pager.end_read_tx()?;
+        }
+        result
+    }
+
+    /// Enables or disables counting how often each instruction runs and how long it takes.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.state.set_profiling(enabled);
+    }
+
+    /// The statistics of every instruction since profiling was enabled or the statement was
+    /// last reset, in program order. Empty while profiling is disabled.
+    pub fn profile(&self) -> Vec<InsnProfile> {
+        self.program.profile(&self.state)
+    }
+
+    /// How often the records built by the statement needed a new buffer, over all its
+    /// executions. Once a statement ran, executing it again should mostly reuse buffers.
+    pub fn allocation_stats(&self) -> AllocationStats {
+        self.state.allocation_stats()
     }
 
     pub fn row(&self) -> Option<&Row> {
         self.state.result_row.as_ref()
     }
 
+    /// Resets the statement, binds `params` to its parameters in order and returns its rows.
+    /// Stepping the rows waits for I/O, so they can be read synchronously:
+    ///
+    /// ```ignore
+    /// for row in stmt.query([OwnedValue::Integer(1)])? {
+    ///     println!("{:?}", row?);
+    /// }
+    /// ```
+    pub fn query(&mut self, params: impl IntoIterator<Item = OwnedValue>) -> Result<Rows<'_>> {
+        self.reset();
+        for (i, value) in params.into_iter().enumerate() {
+            let index = NonZero::new(i + 1).unwrap();
+            if index.get() > self.parameters_count() {
+                return Err(LimboError::InvalidArgument(format!(
+                    "statement has {} parameters but more were given",
+                    self.parameters_count()
+                )));
+            }
+            self.bind_at(index, value);
+        }
+        Ok(Rows {
+            stmt: self,
+            has_row: false,
+            done: false,
+        })
+    }
+
+    /// The current row as a map from column name to value, for emitting rows as JSON objects
+    /// and the like.
+    #[cfg(feature = "serde")]
+    pub fn row_map(&self) -> Option<RowMap<'_>> {
+        self.row().map(|row| RowMap { stmt: self, row })
+    }
+
+    /// Runs the statement, mapping each result row into a `T`. The columns `T` reads are looked
+    /// up by name before the first step, so a missing column fails here rather than mid-query.
+    pub fn query_as<T: FromRow>(&mut self) -> Result<QueryAs<'_, T>> {
+        let columns = T::COLUMNS
+            .iter()
+            .map(|name| {
+                (0..self.num_columns())
+                    .find(|&i| self.get_column_name(i).eq_ignore_ascii_case(name))
+                    .ok_or_else(|| {
+                        LimboError::InvalidArgument(format!("query has no column named {name}"))
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(QueryAs {
+            stmt: self,
+            columns,
+            done: false,
+            _marker: PhantomData,
+        })
+    }
+
     pub fn explain(&self) -> String {
         self.program.explain()
     }
@@ -656,24 +1711,93 @@ impl VirtualTable {
         ))
     }
 
+    /// Creates the virtual table `tbl_name` of a `CREATE VIRTUAL TABLE` statement. Its arguments
+    /// are passed as text to the module, both for its schema and at the start of each scan.
+    pub(crate) fn create(
+        tbl_name: &str,
+        module_name: &str,
+        args: Vec<String>,
+        syms: &SymbolTable,
+    ) -> Result<Rc<Self>> {
+        let exprs = args
+            .iter()
+            .map(|arg| {
+                ast::Expr::Literal(ast::Literal::String(format!(
+                    "'{}'",
+                    arg.replace('\'', "''")
+                )))
+            })
+            .collect();
+        let args = args.into_iter().map(limbo_ext::Value::from_text).collect();
+        Self::from_args(
+            Some(tbl_name),
+            module_name,
+            args,
+            syms,
+            VTabKind::VirtualTable,
+            Some(exprs),
+        )
+    }
+
+    /// Rebuilds the virtual table `tbl_name` from the `CREATE VIRTUAL TABLE` statement stored in
+    /// the schema, if its module is registered.
+    pub(crate) fn from_sql(tbl_name: &str, sql: &str, syms: &SymbolTable) -> Result<Rc<Self>> {
+        let mut parser = Parser::new(sql.as_bytes());
+        let Some(ast::Cmd::Stmt(ast::Stmt::CreateVirtualTable(vtab))) = parser.next()? else {
+            return Err(LimboError::ParseError(format!(
+                "invalid schema for virtual table: {}",
+                tbl_name
+            )));
+        };
+        if !syms.vtab_modules.contains_key(&vtab.module_name.0) {
+            return Err(LimboError::ParseError(format!(
+                "no such module for virtual table: {}",
+                tbl_name
+            )));
+        }
+        Self::create(
+            tbl_name,
+            &vtab.module_name.0,
+            vtab.args.unwrap_or_default(),
+            syms,
+        )
+    }
+
     pub fn open(&self) -> crate::Result<VTabOpaqueCursor> {
         let cursor = unsafe { (self.implementation.open)(self.implementation.ctx) };
         VTabOpaqueCursor::new(cursor)
     }
 
+    /// Chooses the constraints of the WHERE clause whose values are passed to [VirtualTable::filter].
+    pub fn best_index(&self, constraints: &[ConstraintInfo]) -> limbo_ext::IndexInfo {
+        self.implementation.best_index(constraints)
+    }
+
     pub fn filter(
         &self,
         cursor: &VTabOpaqueCursor,
         arg_count: usize,
         args: Vec<OwnedValue>,
+        idx_str: Option<&str>,
+        idx_num: i32,
     ) -> Result<bool> {
         let mut filter_args = Vec::with_capacity(arg_count);
         for i in 0..arg_count {
             let ownedvalue_arg = args.get(i).unwrap();
             filter_args.push(ownedvalue_arg.to_ffi());
         }
+        let idx_str = idx_str
+            .map(std::ffi::CString::new)
+            .transpose()
+            .map_err(|e| LimboError::ExtensionError(e.to_string()))?;
         let rc = unsafe {
-            (self.implementation.filter)(cursor.as_ptr(), arg_count as i32, filter_args.as_ptr())
+            (self.implementation.filter)(
+                cursor.as_ptr(),
+                arg_count as i32,
+                filter_args.as_ptr(),
+                idx_str.as_ref().map_or(std::ptr::null(), |s| s.as_ptr()),
+                idx_num,
+            )
         };
         for arg in filter_args {
             unsafe {
@@ -742,8 +1866,9 @@ impl std::fmt::Debug for SymbolTable {
 }
 
 fn is_shared_library(path: &std::path::Path) -> bool {
-    path.extension()
-        .map_or(false, |ext| ext == "so" || ext == "dylib" || ext == "dll")
+    path.extension().map_or(false, |ext| {
+        ext == "so" || ext == "dylib" || ext == "dll" || ext == "wasm"
+    })
 }
 
 pub fn resolve_ext_path(extpath: &str) -> Result<std::path::PathBuf> {
@@ -786,9 +1911,142 @@ impl SymbolTable {
     }
 }
 
+/// A row paired with its statement's column names, which serializes as a map.
+#[cfg(feature = "serde")]
+pub struct RowMap<'a> {
+    stmt: &'a Statement,
+    row: &'a Row,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for RowMap<'_> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(Some(self.row.len()))?;
+        for (i, value) in self.row.get_values().enumerate() {
+            map.serialize_entry(self.stmt.get_column_name(i).as_str(), value)?;
+        }
+        map.end()
+    }
+}
+
+/// Iterator over the rows of [`Statement::query_as`], which waits for I/O as it steps.
+pub struct QueryAs<'a, T> {
+    stmt: &'a mut Statement,
+    columns: Vec<usize>,
+    done: bool,
+    _marker: PhantomData<T>,
+}
+
+impl<T: FromRow> Iterator for QueryAs<'_, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.stmt.step_blocking() {
+            Ok(true) => Some(T::from_row(self.stmt.row().unwrap(), &self.columns)),
+            Ok(false) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// The rows of [`Statement::query`], read in place as a streaming iterator or copied out
+/// through [`IntoIterator`].
+pub struct Rows<'a> {
+    stmt: &'a mut Statement,
+    has_row: bool,
+    done: bool,
+}
+
+impl FallibleStreamingIterator for Rows<'_> {
+    type Item = Row;
+    type Error = LimboError;
+
+    fn advance(&mut self) -> Result<()> {
+        self.has_row = false;
+        if self.done {
+            return Ok(());
+        }
+        match self.stmt.step_blocking() {
+            Ok(has_row) => {
+                self.has_row = has_row;
+                self.done = !has_row;
+                Ok(())
+            }
+            Err(err) => {
+                self.done = true;
+                Err(err)
+            }
+        }
+    }
+
+    fn get(&self) -> Option<&Row> {
+        if self.has_row {
+            self.stmt.row()
+        } else {
+            None
+        }
+    }
+}
+
+impl Rows<'_> {
+    /// Reads up to `size` rows, for sending results on in batches. Fewer are returned only
+    /// once the statement is done.
+    pub fn next_chunk(&mut self, size: usize) -> Result<Vec<Vec<OwnedValue>>> {
+        let mut chunk = Vec::with_capacity(size);
+        while chunk.len() < size {
+            match self.next()? {
+                Some(row) => chunk.push(row.get_values().cloned().collect()),
+                None => break,
+            }
+        }
+        Ok(chunk)
+    }
+}
+
+impl<'a> IntoIterator for Rows<'a> {
+    type Item = Result<Vec<OwnedValue>>;
+    type IntoIter = OwnedRows<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        OwnedRows { rows: self }
+    }
+}
+
+/// Iterator over copies of the values of each row of [`Rows`].
+pub struct OwnedRows<'a> {
+    rows: Rows<'a>,
+}
+
+impl Iterator for OwnedRows<'_> {
+    type Item = Result<Vec<OwnedValue>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.rows.next() {
+            Ok(Some(row)) => Some(Ok(row.get_values().cloned().collect())),
+            Ok(None) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
 pub struct QueryRunner<'a> {
     parser: Parser<'a>,
     conn: &'a Rc<Connection>,
+    statements: &'a [u8],
 }
 
 impl<'a> QueryRunner<'a> {
@@ -796,6 +2054,7 @@ impl<'a> QueryRunner<'a> {
         Self {
             parser: Parser::new(statements),
             conn,
+            statements,
         }
     }
 }
@@ -804,8 +2063,12 @@ impl Iterator for QueryRunner<'_> {
     type Item = Result<Option<Statement>>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        let start = self.parser.offset();
         match self.parser.next() {
-            Ok(Some(cmd)) => Some(self.conn.run_cmd(cmd)),
+            Ok(Some(cmd)) => {
+                let sql = String::from_utf8_lossy(&self.statements[start..self.parser.offset()]);
+                Some(self.conn.run_cmd(cmd, &sql))
+            }
             Ok(None) => None,
             Err(err) => {
                 self.parser.finalize();
@@ -0,0 +1,810 @@
+//! Materialized views: tables holding the result of a query, kept up to date as the table the
+//! query reads is written.
+//!
+//! `CREATE MATERIALIZED VIEW v AS SELECT ...` creates an ordinary table `v` with a column per
+//! result column and fills it with the result, so the view is read like any other table, also
+//! by SQLite. Definitions are kept in the `limbo_materialized_views` table.
+//!
+//! A view reading a single table is maintained from the row changes captured for change data
+//! capture (see [crate::cdc]): when a transaction writing the table commits, the rows of the
+//! view that the changed rows contribute to are computed again, in the transaction itself right
+//! before its commit, so the view commits along with the table or not at all.
+//! - Without aggregates, a view has a row for every row of the table passing the WHERE clause,
+//!   under the same rowid, so each changed row is deleted from the view and selected again.
+//! - With aggregates, a view has a row per group. The groups have to be given by columns of the
+//!   table that the view also selects, so that the groups of the old and new values of each
+//!   changed row can be deleted and computed again.
+//!
+//! Other views, e.g. ones joining tables, using subqueries, DISTINCT or LIMIT, or reading another
+//! materialized view, are only brought up to date by `REFRESH MATERIALIZED VIEW`, which computes
+//! them anew. That is also the way to repair a view after its table was written by another
+//! program, such as SQLite, which doesn't know about the view. When maintaining a view fails, the
+//! transaction is rolled back and the statement committing it fails.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use fallible_iterator::FallibleIterator;
+use fallible_streaming_iterator::FallibleStreamingIterator;
+use limbo_sqlite3_parser::ast;
+use limbo_sqlite3_parser::lexer::sql::Parser;
+
+use crate::cdc::RowChange;
+use crate::function::Func;
+use crate::row_filter::add_predicate;
+use crate::schema::Schema;
+use crate::translate::optimizer::for_each_expr_in;
+use crate::types::OwnedValue;
+use crate::util::normalize_ident;
+use crate::{Connection, LimboError, Result, Statement};
+
+/// The table with the name and `CREATE MATERIALIZED VIEW` statement of every view.
+pub const CATALOG_TABLE_NAME: &str = "limbo_materialized_views";
+
+/// A materialized view statement, run by [crate::vdbe::insn::Insn::MaterializedView].
+#[derive(Debug, Clone)]
+pub enum ViewStatement {
+    /// `CREATE MATERIALIZED VIEW`, with the SQL of the statement.
+    Create(String),
+    /// `REFRESH MATERIALIZED VIEW`, with the name of the view.
+    Refresh(String),
+    /// `DROP MATERIALIZED VIEW`.
+    Drop { name: String, if_exists: bool },
+}
+
+impl ViewStatement {
+    pub fn from_stmt(stmt: ast::Stmt) -> Self {
+        match stmt {
+            ast::Stmt::RefreshMaterializedView { view_name } => {
+                Self::Refresh(normalize_ident(&view_name.name.0))
+            }
+            ast::Stmt::DropMaterializedView {
+                if_exists,
+                view_name,
+            } => Self::Drop {
+                name: normalize_ident(&view_name.name.0),
+                if_exists,
+            },
+            stmt => {
+                let sql = ast::Cmd::Stmt(stmt).to_string();
+                Self::Create(sql.trim_end_matches(';').to_string())
+            }
+        }
+    }
+}
+
+pub(crate) fn run(conn: &Rc<Connection>, statement: &ViewStatement) -> Result<()> {
+    match statement {
+        ViewStatement::Create(sql) => create(conn, sql),
+        ViewStatement::Refresh(name) => refresh(conn, name),
+        ViewStatement::Drop { name, if_exists } => drop_view(conn, name, *if_exists),
+    }
+}
+
+pub struct MaterializedView {
+    /// Normalized name of the view and of the table holding its rows.
+    pub name: String,
+    /// The `CREATE MATERIALIZED VIEW` statement.
+    pub sql: String,
+    columns: Vec<String>,
+    select: ast::Select,
+    maintenance: Maintenance,
+}
+
+/// How a view is brought up to date with the changes of the table it reads.
+enum Maintenance {
+    /// A row of the view per row of `source`, under the same rowid.
+    Rows { source: Source },
+    /// A row of the view per group of rows of `source`. No keys means a single group.
+    Groups { source: Source, keys: Vec<GroupKey> },
+    /// Only by refreshing it.
+    Refresh,
+}
+
+struct Source {
+    /// Normalized table name.
+    table: String,
+    /// The name the table has in the query, its alias if it has one.
+    qualifier: ast::Name,
+}
+
+/// A column of the source table the rows of a view are grouped by, and the column of the view
+/// holding its value.
+struct GroupKey {
+    source_column: usize,
+    name: ast::Name,
+    view_column: String,
+}
+
+impl MaterializedView {
+    fn new(
+        name: String,
+        sql: String,
+        select: ast::Select,
+        columns: Vec<String>,
+        schema: &Schema,
+    ) -> Self {
+        let maintenance = Maintenance::analyze(&select, &columns, schema);
+        Self {
+            name,
+            sql,
+            columns,
+            select,
+            maintenance,
+        }
+    }
+
+    /// The table the view is maintained from, if it is maintained incrementally.
+    pub fn source(&self) -> Option<&str> {
+        match &self.maintenance {
+            Maintenance::Rows { source } | Maintenance::Groups { source, .. } => {
+                Some(&source.table)
+            }
+            Maintenance::Refresh => None,
+        }
+    }
+
+    /// Computes the rows of the view and inserts them.
+    fn fill(&self, statements: &mut Statements) -> Result<()> {
+        let with_rowid = matches!(self.maintenance, Maintenance::Rows { .. });
+        let rows = statements.query(&self.select_sql(Vec::new(), with_rowid), Vec::new())?;
+        self.insert(statements, rows, with_rowid)
+    }
+
+    fn insert(
+        &self,
+        statements: &mut Statements,
+        rows: Vec<Vec<OwnedValue>>,
+        with_rowid: bool,
+    ) -> Result<()> {
+        let mut columns: Vec<String> = self.columns.iter().map(|c| quote_ident(c)).collect();
+        if with_rowid {
+            columns.insert(0, "rowid".to_string());
+        }
+        let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("?{i}")).collect();
+        let sql = format!(
+            "INSERT INTO {}({}) VALUES ({})",
+            quote_ident(&self.name),
+            columns.join(", "),
+            placeholders.join(", ")
+        );
+        for row in rows {
+            statements.query(&sql, row)?;
+        }
+        Ok(())
+    }
+
+    /// Brings the view up to date with `changes` to its source table.
+    fn apply(&self, statements: &mut Statements, changes: &[&RowChange]) -> Result<()> {
+        match &self.maintenance {
+            Maintenance::Rows { source } => {
+                let mut rowids: Vec<i64> = changes.iter().map(|change| change.rowid).collect();
+                rowids.sort_unstable();
+                rowids.dedup();
+                let delete = format!("DELETE FROM {} WHERE rowid = ?1", quote_ident(&self.name));
+                let select = self.select_sql(
+                    vec![compare_to_param(
+                        qualified(&source.qualifier, ast::Name("rowid".to_string())),
+                        ast::Operator::Equals,
+                        1,
+                    )],
+                    true,
+                );
+                for rowid in rowids {
+                    statements.query(&delete, vec![OwnedValue::Integer(rowid)])?;
+                    let rows = statements.query(&select, vec![OwnedValue::Integer(rowid)])?;
+                    self.insert(statements, rows, true)?;
+                }
+                Ok(())
+            }
+            Maintenance::Groups { keys, .. } if keys.is_empty() => {
+                statements.query(
+                    &format!("DELETE FROM {}", quote_ident(&self.name)),
+                    Vec::new(),
+                )?;
+                self.fill(statements)
+            }
+            Maintenance::Groups { source, keys } => {
+                let mut groups: Vec<Vec<OwnedValue>> = Vec::new();
+                for change in changes {
+                    for values in [&change.old, &change.new].into_iter().flatten() {
+                        let group: Vec<OwnedValue> = keys
+                            .iter()
+                            .map(|key| {
+                                values
+                                    .get(key.source_column)
+                                    .cloned()
+                                    .unwrap_or(OwnedValue::Null)
+                            })
+                            .collect();
+                        if !groups.contains(&group) {
+                            groups.push(group);
+                        }
+                    }
+                }
+                let matches: Vec<String> = keys
+                    .iter()
+                    .enumerate()
+                    .map(|(i, key)| format!("{} IS ?{}", quote_ident(&key.view_column), i + 1))
+                    .collect();
+                let delete = format!(
+                    "DELETE FROM {} WHERE {}",
+                    quote_ident(&self.name),
+                    matches.join(" AND ")
+                );
+                let predicates = keys
+                    .iter()
+                    .enumerate()
+                    .map(|(i, key)| {
+                        compare_to_param(
+                            qualified(&source.qualifier, key.name.clone()),
+                            ast::Operator::Is,
+                            i + 1,
+                        )
+                    })
+                    .collect();
+                let select = self.select_sql(predicates, false);
+                for group in groups {
+                    statements.query(&delete, group.clone())?;
+                    let rows = statements.query(&select, group)?;
+                    self.insert(statements, rows, false)?;
+                }
+                Ok(())
+            }
+            Maintenance::Refresh => Ok(()),
+        }
+    }
+
+    /// The query of the view restricted by `predicates`, selecting the rowid of the source row
+    /// first if `with_rowid` is set.
+    fn select_sql(&self, predicates: Vec<ast::Expr>, with_rowid: bool) -> String {
+        let mut select = self.select.clone();
+        if let ast::OneSelect::Select(inner) = select.body.select.as_mut() {
+            for predicate in predicates {
+                add_predicate(&mut inner.where_clause, predicate);
+            }
+            if let (true, Maintenance::Rows { source }) = (with_rowid, &self.maintenance) {
+                let rowid = qualified(&source.qualifier, ast::Name("rowid".to_string()));
+                inner
+                    .columns
+                    .insert(0, ast::ResultColumn::Expr(rowid, None));
+            }
+        }
+        ast::Cmd::Stmt(ast::Stmt::Select(Box::new(select))).to_string()
+    }
+}
+
+impl Maintenance {
+    fn analyze(select: &ast::Select, columns: &[String], schema: &Schema) -> Self {
+        Self::try_analyze(select, columns, schema).unwrap_or(Maintenance::Refresh)
+    }
+
+    fn try_analyze(select: &ast::Select, columns: &[String], schema: &Schema) -> Option<Self> {
+        if select.with.is_some()
+            || select.order_by.is_some()
+            || select.limit.is_some()
+            || select.body.compounds.is_some()
+        {
+            return None;
+        }
+        let ast::OneSelect::Select(inner) = select.body.select.as_ref() else {
+            return None;
+        };
+        if inner.distinctness.is_some() || inner.window_clause.is_some() {
+            return None;
+        }
+        let from = inner.from.as_ref()?;
+        if from.joins.as_ref().is_some_and(|joins| !joins.is_empty()) {
+            return None;
+        }
+        let ast::SelectTable::Table(table_name, alias, _) = from.select.as_deref()? else {
+            return None;
+        };
+        let table = schema.get_btree_table(&table_name.name.0)?;
+        // Views aren't maintained from the changes made while maintaining other views.
+        if schema
+            .materialized_views
+            .contains_key(&normalize_ident(&table.name))
+        {
+            return None;
+        }
+        let qualifier = match alias {
+            Some(ast::As::As(alias) | ast::As::Elided(alias)) => alias.clone(),
+            None => table_name.name.clone(),
+        };
+
+        // Subqueries read other tables, whose changes wouldn't reach the view.
+        let mut exprs: Vec<ast::Expr> = inner
+            .columns
+            .iter()
+            .filter_map(|column| match column {
+                ast::ResultColumn::Expr(expr, _) => Some(expr.clone()),
+                _ => None,
+            })
+            .chain(inner.where_clause.clone())
+            .collect();
+        if let Some(group_by) = &inner.group_by {
+            exprs.extend(group_by.exprs.iter().cloned());
+            exprs.extend(group_by.having.as_deref().cloned());
+        }
+        let mut reads_other_tables = false;
+        let mut aggregates = false;
+        for expr in exprs.iter_mut() {
+            for_each_expr_in(expr, &mut |expr| {
+                match expr {
+                    ast::Expr::Subquery(_)
+                    | ast::Expr::Exists(_)
+                    | ast::Expr::InSelect { .. }
+                    | ast::Expr::InTable { .. } => reads_other_tables = true,
+                    ast::Expr::FunctionCall { name, args, .. } => {
+                        let arg_count = args.as_ref().map_or(0, |args| args.len());
+                        aggregates |= is_aggregate(&name.0, arg_count);
+                    }
+                    ast::Expr::FunctionCallStar { name, .. } => {
+                        aggregates |= is_aggregate(&name.0, 0);
+                    }
+                    _ => {}
+                }
+                true
+            });
+        }
+        if reads_other_tables {
+            return None;
+        }
+        let source = Source {
+            table: normalize_ident(&table.name),
+            qualifier,
+        };
+        let Some(group_by) = &inner.group_by else {
+            return Some(if aggregates {
+                Maintenance::Groups {
+                    source,
+                    keys: Vec::new(),
+                }
+            } else {
+                Maintenance::Rows { source }
+            });
+        };
+        let keys = group_by
+            .exprs
+            .iter()
+            .map(|expr| {
+                let name = column_name(expr, &source.qualifier)?;
+                let (source_column, _) = table.get_column(&name.0)?;
+                let view_column =
+                    inner
+                        .columns
+                        .iter()
+                        .zip(columns)
+                        .find_map(|(result, view_column)| {
+                            let ast::ResultColumn::Expr(expr, _) = result else {
+                                return None;
+                            };
+                            column_name(expr, &source.qualifier)
+                                .filter(|result| {
+                                    normalize_ident(&result.0) == normalize_ident(&name.0)
+                                })
+                                .map(|_| view_column.clone())
+                        })?;
+                Some(GroupKey {
+                    source_column,
+                    name,
+                    view_column,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Maintenance::Groups { source, keys })
+    }
+}
+
+fn is_aggregate(name: &str, arg_count: usize) -> bool {
+    matches!(Func::resolve_function(name, arg_count), Ok(Func::Agg(_)))
+}
+
+/// The column `expr` refers to, if it is a bare column of the table named `qualifier`.
+fn column_name(expr: &ast::Expr, qualifier: &ast::Name) -> Option<ast::Name> {
+    match expr {
+        ast::Expr::Id(id) => Some(ast::Name(id.0.clone())),
+        ast::Expr::Qualified(table, column)
+            if normalize_ident(&table.0) == normalize_ident(&qualifier.0) =>
+        {
+            Some(column.clone())
+        }
+        _ => None,
+    }
+}
+
+fn qualified(qualifier: &ast::Name, column: ast::Name) -> ast::Expr {
+    ast::Expr::Qualified(qualifier.clone(), column)
+}
+
+/// `lhs op ?param`.
+fn compare_to_param(lhs: ast::Expr, op: ast::Operator, param: usize) -> ast::Expr {
+    ast::Expr::Binary(
+        Box::new(lhs),
+        op,
+        Box::new(ast::Expr::Variable(param.to_string())),
+    )
+}
+
+fn create(conn: &Rc<Connection>, sql: &str) -> Result<()> {
+    let mut parser = Parser::new(sql.as_bytes());
+    let Some(ast::Cmd::Stmt(ast::Stmt::CreateMaterializedView {
+        if_not_exists,
+        view_name,
+        columns,
+        select,
+    })) = parser.next()?
+    else {
+        return Err(LimboError::InternalError(format!(
+            "not a materialized view definition: {sql}"
+        )));
+    };
+    let name = normalize_ident(&view_name.name.0);
+    if !conn.get_auto_commit() {
+        return Err(LimboError::TxError(
+            "cannot create a materialized view within a transaction".to_string(),
+        ));
+    }
+    {
+        let schema = conn.schema.read();
+        if schema.get_table(&name).is_some() {
+            if if_not_exists && schema.materialized_views.contains_key(&name) {
+                return Ok(());
+            }
+            return Err(LimboError::ParseError(format!(
+                "Table {name} already exists"
+            )));
+        }
+    }
+    let _suspended = conn.suspend_row_filter();
+    let columns = match columns {
+        Some(columns) => columns
+            .iter()
+            .map(|column| normalize_ident(&column.col_name.0))
+            .collect(),
+        None => {
+            let query = ast::Cmd::Stmt(ast::Stmt::Select(select.clone())).to_string();
+            let stmt = conn.prepare(query)?;
+            unique_names((0..stmt.num_columns()).map(|i| stmt.get_column_name(i).to_string()))
+        }
+    };
+    let view = MaterializedView::new(name, sql.to_string(), *select, columns, &conn.schema.read());
+    in_transaction(conn, |statements| {
+        statements.execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {CATALOG_TABLE_NAME}(name TEXT, sql TEXT)"
+        ))?;
+        let columns: Vec<String> = view.columns.iter().map(|c| quote_ident(c)).collect();
+        statements.execute(&format!(
+            "CREATE TABLE {}({})",
+            quote_ident(&view.name),
+            columns.join(", ")
+        ))?;
+        statements.query(
+            &format!("INSERT INTO {CATALOG_TABLE_NAME} VALUES (?1, ?2)"),
+            vec![
+                OwnedValue::build_text(&view.name),
+                OwnedValue::build_text(&view.sql),
+            ],
+        )?;
+        view.fill(statements)
+    })?;
+    conn.schema.write().add_materialized_view(Arc::new(view));
+    Ok(())
+}
+
+fn refresh(conn: &Rc<Connection>, name: &str) -> Result<()> {
+    if !conn.get_auto_commit() {
+        return Err(LimboError::TxError(
+            "cannot refresh a materialized view within a transaction".to_string(),
+        ));
+    }
+    let view = conn
+        .schema
+        .read()
+        .get_materialized_view(name)
+        .ok_or_else(|| LimboError::ParseError(format!("no such materialized view: {name}")))?;
+    let _suspended = conn.suspend_row_filter();
+    in_transaction(conn, |statements| {
+        statements.query(
+            &format!("DELETE FROM {}", quote_ident(&view.name)),
+            Vec::new(),
+        )?;
+        view.fill(statements)
+    })
+}
+
+fn drop_view(conn: &Rc<Connection>, name: &str, if_exists: bool) -> Result<()> {
+    if !conn.get_auto_commit() {
+        return Err(LimboError::TxError(
+            "cannot drop a materialized view within a transaction".to_string(),
+        ));
+    }
+    let Some(view) = conn.schema.read().get_materialized_view(name) else {
+        if if_exists {
+            return Ok(());
+        }
+        return Err(LimboError::ParseError(format!(
+            "no such materialized view: {name}"
+        )));
+    };
+    check_not_read(&conn.schema.read(), &view.name)?;
+    let _suspended = conn.suspend_row_filter();
+    in_transaction(conn, |statements| {
+        statements.execute(&format!("DROP TABLE {}", quote_ident(&view.name)))?;
+        statements.query(
+            &format!("DELETE FROM {CATALOG_TABLE_NAME} WHERE name = ?1"),
+            vec![OwnedValue::build_text(&view.name)],
+        )?;
+        Ok(())
+    })?;
+    conn.schema.write().remove_materialized_view(&view.name);
+    Ok(())
+}
+
+/// Brings the views maintained from the tables written by the transaction about to commit up to
+/// date with its `changes`.
+pub(crate) fn maintain(conn: &Rc<Connection>, changes: &[RowChange]) -> Result<()> {
+    let views: Vec<Arc<MaterializedView>> = {
+        let schema = conn.schema.read();
+        if schema.materialized_views.is_empty() {
+            return Ok(());
+        }
+        schema
+            .materialized_views
+            .values()
+            .filter(|view| {
+                view.source().is_some_and(|source| {
+                    changes
+                        .iter()
+                        .any(|change| normalize_ident(&change.table) == source)
+                })
+            })
+            .cloned()
+            .collect()
+    };
+    if views.is_empty() {
+        return Ok(());
+    }
+    in_committing_transaction(conn, |statements| {
+        for view in &views {
+            let source = view.source();
+            let changes: Vec<&RowChange> = changes
+                .iter()
+                .filter(|change| Some(normalize_ident(&change.table).as_str()) == source)
+                .collect();
+            view.apply(statements, &changes)?;
+        }
+        Ok(())
+    })
+}
+
+/// Loads the views of a database that was just opened.
+pub(crate) fn load(conn: &Rc<Connection>) -> Result<()> {
+    if conn
+        .schema
+        .read()
+        .get_btree_table(CATALOG_TABLE_NAME)
+        .is_none()
+    {
+        return Ok(());
+    }
+    let mut statements = Statements::new(conn);
+    let rows = statements.query(
+        &format!("SELECT name, sql FROM {CATALOG_TABLE_NAME}"),
+        Vec::new(),
+    )?;
+    for row in rows {
+        let [OwnedValue::Text(name), OwnedValue::Text(sql)] = row.as_slice() else {
+            continue;
+        };
+        let mut parser = Parser::new(sql.as_str().as_bytes());
+        let Some(ast::Cmd::Stmt(ast::Stmt::CreateMaterializedView { select, .. })) =
+            parser.next()?
+        else {
+            continue;
+        };
+        let mut schema = conn.schema.write();
+        let Some(table) = schema.get_btree_table(name.as_str()) else {
+            continue;
+        };
+        let columns = table
+            .columns
+            .iter()
+            .map(|column| column.name.clone().unwrap_or_default())
+            .collect();
+        let view = MaterializedView::new(
+            name.as_str().to_string(),
+            sql.as_str().to_string(),
+            *select,
+            columns,
+            &schema,
+        );
+        schema.add_materialized_view(Arc::new(view));
+    }
+    Ok(())
+}
+
+/// Fails if `stmt` writes a materialized view, which only the statements maintaining it may do.
+pub(crate) fn check_write(schema: &Schema, stmt: &ast::Stmt) -> Result<()> {
+    if schema.materialized_views.is_empty() {
+        return Ok(());
+    }
+    let name = match stmt {
+        ast::Stmt::Insert(insert) => &insert.tbl_name.name,
+        ast::Stmt::Update(update) => &update.tbl_name.name,
+        ast::Stmt::Delete(delete) => &delete.tbl_name.name,
+        ast::Stmt::DropTable { tbl_name, .. } => {
+            let name = normalize_ident(&tbl_name.name.0);
+            if schema.materialized_views.contains_key(&name) {
+                return Err(LimboError::ParseError(format!(
+                    "use DROP MATERIALIZED VIEW to drop materialized view {name}"
+                )));
+            }
+            return check_not_read(schema, &name);
+        }
+        _ => return Ok(()),
+    };
+    let name = normalize_ident(&name.0);
+    if schema.materialized_views.contains_key(&name) {
+        return Err(LimboError::ParseError(format!(
+            "cannot modify materialized view {name}"
+        )));
+    }
+    Ok(())
+}
+
+/// Fails if a view is maintained from the table `name`, which therefore can't be dropped.
+fn check_not_read(schema: &Schema, name: &str) -> Result<()> {
+    match schema
+        .materialized_views
+        .values()
+        .find(|view| view.source() == Some(name))
+    {
+        Some(view) => Err(LimboError::ParseError(format!(
+            "cannot drop {name}: materialized view {} reads it",
+            view.name
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Lets the connection write materialized views until dropped.
+pub(crate) struct Writing<'a> {
+    flag: &'a Cell<bool>,
+    was_writing: bool,
+}
+
+impl<'a> Writing<'a> {
+    pub fn new(flag: &'a Cell<bool>) -> Self {
+        Self {
+            flag,
+            was_writing: flag.replace(true),
+        }
+    }
+}
+
+impl Drop for Writing<'_> {
+    fn drop(&mut self) {
+        self.flag.set(self.was_writing);
+    }
+}
+
+/// Runs `f` in a write transaction of its own, undoing its changes if it fails.
+pub(crate) fn in_transaction(
+    conn: &Rc<Connection>,
+    f: impl FnOnce(&mut Statements) -> Result<()>,
+) -> Result<()> {
+    let _writing = conn.write_materialized_views();
+    conn.execute("BEGIN IMMEDIATE")?;
+    let header = conn.pager.db_header.lock().clone();
+    let (tables, indexes) = {
+        let schema = conn.schema.read();
+        (schema.tables.clone(), schema.indexes.clone())
+    };
+    let result = {
+        let mut statements = Statements::new(conn);
+        f(&mut statements)
+    };
+    match result.and_then(|_| conn.execute("COMMIT")) {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            conn.rollback_write_tx(header)?;
+            // The statements that ran changed the schema as they went.
+            let mut schema = conn.schema.write();
+            schema.tables = tables;
+            schema.indexes = indexes;
+            schema.schema_version += 1;
+            Err(err)
+        }
+    }
+}
+
+/// Runs `f` in the write transaction about to commit, before it commits. The statements `f` runs
+/// don't commit it, can write materialized views, and leave the connection's last insert rowid
+/// alone. If they fail, the changes of the one that failed are undone.
+pub(crate) fn in_committing_transaction<T>(
+    conn: &Rc<Connection>,
+    f: impl FnOnce(&mut Statements) -> Result<T>,
+) -> Result<T> {
+    let _writing = conn.write_materialized_views();
+    let _suspended = conn.suspend_row_filter();
+    let auto_commit = conn.auto_commit.replace(false);
+    let last_insert_rowid = conn.last_insert_rowid();
+    let result = {
+        let mut statements = Statements::new(conn);
+        f(&mut statements)
+    };
+    conn.auto_commit.replace(auto_commit);
+    conn.update_last_rowid(last_insert_rowid);
+    result
+}
+
+/// Prepared statements, reused while maintaining a view.
+pub(crate) struct Statements<'a> {
+    conn: &'a Rc<Connection>,
+    statements: HashMap<String, Statement>,
+}
+
+impl<'a> Statements<'a> {
+    pub fn new(conn: &'a Rc<Connection>) -> Self {
+        Self {
+            conn,
+            statements: HashMap::new(),
+        }
+    }
+
+    pub fn execute(&mut self, sql: &str) -> Result<()> {
+        self.conn.execute(sql)
+    }
+
+    pub fn query(&mut self, sql: &str, params: Vec<OwnedValue>) -> Result<Vec<Vec<OwnedValue>>> {
+        if !self.statements.contains_key(sql) {
+            let stmt = self.conn.prepare(sql)?;
+            self.statements.insert(sql.to_string(), stmt);
+        }
+        let stmt = self
+            .statements
+            .get_mut(sql)
+            .expect("statement was prepared");
+        let mut rows = stmt.query(params)?;
+        let mut values = Vec::new();
+        while let Some(row) = rows.next()? {
+            values.push(row.get_values().cloned().collect());
+        }
+        Ok(values)
+    }
+}
+
+/// Makes column names unique the way `CREATE TABLE ... AS SELECT` does, by appending `:N` to
+/// repeated ones.
+fn unique_names(names: impl Iterator<Item = String>) -> Vec<String> {
+    let mut unique: Vec<String> = Vec::new();
+    for name in names {
+        let mut candidate = name.clone();
+        let mut n = 1;
+        while unique
+            .iter()
+            .any(|other| other.eq_ignore_ascii_case(&candidate))
+        {
+            candidate = format!("{name}:{n}");
+            n += 1;
+        }
+        unique.push(candidate);
+    }
+    unique
+}
+
+/// Quotes `name` if it isn't a plain identifier. Plain names are left alone because quoted table
+/// names end up quoted in the schema table.
+pub(crate) fn quote_ident(name: &str) -> String {
+    let plain = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if plain {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+}
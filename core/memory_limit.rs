@@ -0,0 +1,97 @@
+//! A soft limit on the memory a database uses for cached pages and for the transient data of
+//! statements, like SQLite's `sqlite3_soft_heap_limit64`. Going over the limit never fails a
+//! statement: page caches evict pages they could otherwise keep, while sorters and transient
+//! b-trees move their data to temp files and report it to the callback set with
+//! [crate::Database::on_memory_pressure].
+
+use parking_lot::RwLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Memory the limit made a part of the database give up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryPressure {
+    /// A page cache evicted pages it had room for.
+    PageCache { pages: usize },
+    /// A sorter wrote the rows it held to a temp file.
+    Sorter { bytes: usize },
+    /// A transient b-tree wrote its dirty pages to its temp file.
+    TransientStorage { pages: usize },
+}
+
+pub type MemoryPressureCallback = Box<dyn Fn(MemoryPressure) + Send + Sync>;
+
+/// The limit along with the memory counted against it, shared by the page caches, pagers and
+/// sorters of a database.
+#[derive(Default)]
+pub(crate) struct MemoryLimit {
+    /// In bytes, 0 if there is no limit.
+    limit: AtomicUsize,
+    used: AtomicUsize,
+    callback: RwLock<Option<MemoryPressureCallback>>,
+}
+
+impl MemoryLimit {
+    pub(crate) fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Returns the previous limit.
+    pub(crate) fn set_limit(&self, bytes: usize) -> usize {
+        self.limit.swap(bytes, Ordering::Relaxed)
+    }
+
+    pub(crate) fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn allocate(&self, bytes: usize) {
+        self.used.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn free(&self, bytes: usize) {
+        let previous = self.used.fetch_sub(bytes, Ordering::Relaxed);
+        debug_assert!(previous >= bytes, "freed more memory than was allocated");
+    }
+
+    /// Whether allocating `bytes` more would go over the limit.
+    pub(crate) fn would_exceed(&self, bytes: usize) -> bool {
+        let limit = self.limit();
+        limit != 0 && self.used() + bytes > limit
+    }
+
+    pub(crate) fn is_exceeded(&self) -> bool {
+        self.would_exceed(0)
+    }
+
+    pub(crate) fn set_callback(&self, callback: Option<MemoryPressureCallback>) {
+        *self.callback.write() = callback;
+    }
+
+    pub(crate) fn report(&self, pressure: MemoryPressure) {
+        tracing::debug!("memory_pressure({:?}, used={})", pressure, self.used());
+        if let Some(callback) = self.callback.read().as_ref() {
+            callback(pressure);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_limit() {
+        let memory = MemoryLimit::default();
+        memory.allocate(100);
+        assert!(!memory.would_exceed(1_000_000), "no limit by default");
+        assert_eq!(memory.set_limit(150), 0);
+        assert!(!memory.would_exceed(50));
+        assert!(memory.would_exceed(51));
+        memory.allocate(100);
+        assert!(memory.is_exceeded());
+        memory.free(100);
+        assert!(!memory.is_exceeded());
+        assert_eq!(memory.set_limit(0), 150);
+        assert_eq!(memory.used(), 100);
+    }
+}
@@ -0,0 +1,284 @@
+//! Fetching the rows of a query a page at a time, across requests that don't keep a statement
+//! open between them, e.g. for a server or a binding that hands out pages to its clients.
+//!
+//! The state of the query is kept in the token returned with each page: its SQL, parameters
+//! and the number of rows already returned. The next page runs the query again and skips those
+//! rows, so it sees the rows committed since the previous page, and rows can be skipped or
+//! returned twice if the table changed in between. Tokens are signed with a key of the
+//! [crate::Database], so only the database that issued a token accepts it, and only until it
+//! expires.
+
+use std::hash::{BuildHasher, Hasher};
+use std::rc::Rc;
+use std::time::Duration;
+
+use fallible_iterator::FallibleIterator;
+use fallible_streaming_iterator::FallibleStreamingIterator;
+use limbo_sqlite3_parser::ast;
+use limbo_sqlite3_parser::lexer::sql::Parser;
+
+use crate::{Connection, LimboError, OwnedValue, Result};
+
+/// How [Connection::query_page] and [Connection::next_page] split the rows into pages.
+#[derive(Debug, Clone, Copy)]
+pub struct PagingOptions {
+    /// The maximum number of rows of a page.
+    pub page_size: usize,
+    /// How long the token of the next page stays valid.
+    pub expiry: Duration,
+}
+
+impl Default for PagingOptions {
+    fn default() -> Self {
+        Self {
+            page_size: 100,
+            expiry: Duration::from_secs(300),
+        }
+    }
+}
+
+/// A page of the rows of a query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultPage {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<OwnedValue>>,
+    /// The token to fetch the next page with, if there are more rows.
+    pub next: Option<String>,
+}
+
+/// The query of a token and where its next page starts.
+#[derive(Debug, Clone, PartialEq)]
+struct PageCursor {
+    sql: String,
+    params: Vec<OwnedValue>,
+    /// The number of rows returned by the previous pages.
+    position: u64,
+    /// Microseconds since the Unix epoch after which the token is rejected.
+    expires_at: i64,
+}
+
+const TOKEN_VERSION: u8 = 1;
+
+pub(crate) fn query_page(
+    conn: &Rc<Connection>,
+    sql: &str,
+    params: Vec<OwnedValue>,
+    options: &PagingOptions,
+) -> Result<ResultPage> {
+    let mut parser = Parser::new(sql.as_bytes());
+    if !matches!(parser.next()?, Some(ast::Cmd::Stmt(ast::Stmt::Select(_)))) {
+        return Err(LimboError::InvalidArgument(
+            "only SELECT statements can be paged".to_string(),
+        ));
+    }
+    fetch_page(
+        conn,
+        PageCursor {
+            sql: sql.to_string(),
+            params,
+            position: 0,
+            expires_at: 0,
+        },
+        options,
+    )
+}
+
+pub(crate) fn next_page(
+    conn: &Rc<Connection>,
+    token: &str,
+    options: &PagingOptions,
+) -> Result<ResultPage> {
+    let cursor = PageCursor::decode(token, &conn._db.page_token_key)?;
+    if now_micros(conn) > cursor.expires_at {
+        return Err(LimboError::InvalidArgument(
+            "page token has expired".to_string(),
+        ));
+    }
+    fetch_page(conn, cursor, options)
+}
+
+fn fetch_page(
+    conn: &Rc<Connection>,
+    mut cursor: PageCursor,
+    options: &PagingOptions,
+) -> Result<ResultPage> {
+    let mut stmt = conn.prepare(&cursor.sql)?;
+    let columns = (0..stmt.num_columns())
+        .map(|i| stmt.get_column_name(i).to_string())
+        .collect();
+    let mut rows = stmt.query(cursor.params.iter().cloned())?;
+    for _ in 0..cursor.position {
+        if rows.next()?.is_none() {
+            break;
+        }
+    }
+    let mut page = Vec::with_capacity(options.page_size.min(1024));
+    while page.len() < options.page_size {
+        let Some(row) = rows.next()? else {
+            break;
+        };
+        page.push(row.get_values().cloned().collect());
+    }
+    // A full page may be followed by more rows.
+    let more = page.len() == options.page_size && rows.next()?.is_some();
+    let next = if more {
+        cursor.position += page.len() as u64;
+        let expiry = i64::try_from(options.expiry.as_micros()).unwrap_or(i64::MAX);
+        cursor.expires_at = now_micros(conn).saturating_add(expiry);
+        Some(cursor.encode(&conn._db.page_token_key))
+    } else {
+        None
+    };
+    Ok(ResultPage {
+        columns,
+        rows: page,
+        next,
+    })
+}
+
+fn now_micros(conn: &Connection) -> i64 {
+    let now = conn._db.io.now();
+    now.secs * 1_000_000 + now.micros as i64
+}
+
+impl PageCursor {
+    fn encode(&self, key: &impl BuildHasher) -> String {
+        let mut buf = vec![TOKEN_VERSION];
+        buf.extend_from_slice(&self.expires_at.to_be_bytes());
+        buf.extend_from_slice(&self.position.to_be_bytes());
+        write_bytes(&mut buf, self.sql.as_bytes());
+        buf.extend_from_slice(&(self.params.len() as u32).to_be_bytes());
+        for param in &self.params {
+            match param {
+                OwnedValue::Null => buf.push(0),
+                OwnedValue::Integer(i) => {
+                    buf.push(1);
+                    buf.extend_from_slice(&i.to_be_bytes());
+                }
+                OwnedValue::Float(f) => {
+                    buf.push(2);
+                    buf.extend_from_slice(&f.to_be_bytes());
+                }
+                OwnedValue::Text(t) => {
+                    buf.push(3);
+                    write_bytes(&mut buf, t.as_str().as_bytes());
+                }
+                OwnedValue::Blob(b) => {
+                    buf.push(4);
+                    write_bytes(&mut buf, b);
+                }
+            }
+        }
+        let mac = sign(key, &buf);
+        buf.extend_from_slice(&mac.to_be_bytes());
+        hex::encode(buf)
+    }
+
+    fn decode(token: &str, key: &impl BuildHasher) -> Result<Self> {
+        let invalid = || LimboError::InvalidArgument("invalid page token".to_string());
+        let buf = hex::decode(token).map_err(|_| invalid())?;
+        let (data, mac) = buf
+            .split_at_checked(buf.len().wrapping_sub(8))
+            .ok_or_else(invalid)?;
+        if sign(key, data).to_be_bytes() != mac {
+            return Err(invalid());
+        }
+        let mut reader = TokenReader { data };
+        if reader.take(1)? != [TOKEN_VERSION] {
+            return Err(invalid());
+        }
+        let expires_at = i64::from_be_bytes(reader.take_array()?);
+        let position = u64::from_be_bytes(reader.take_array()?);
+        let sql = String::from_utf8(reader.take_bytes()?.to_vec()).map_err(|_| invalid())?;
+        let count = u32::from_be_bytes(reader.take_array()?);
+        let params = (0..count)
+            .map(|_| {
+                Ok(match reader.take(1)?[0] {
+                    0 => OwnedValue::Null,
+                    1 => OwnedValue::Integer(i64::from_be_bytes(reader.take_array()?)),
+                    2 => OwnedValue::Float(f64::from_be_bytes(reader.take_array()?)),
+                    3 => OwnedValue::build_text(
+                        std::str::from_utf8(reader.take_bytes()?).map_err(|_| invalid())?,
+                    ),
+                    4 => OwnedValue::Blob(reader.take_bytes()?.to_vec()),
+                    _ => return Err(invalid()),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            sql,
+            params,
+            position,
+            expires_at,
+        })
+    }
+}
+
+fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+/// A keyed hash of the token, which can't be forged without the key of the database.
+fn sign(key: &impl BuildHasher, data: &[u8]) -> u64 {
+    let mut hasher = key.build_hasher();
+    hasher.write(data);
+    hasher.finish()
+}
+
+struct TokenReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> TokenReader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let (taken, rest) = self
+            .data
+            .split_at_checked(n)
+            .ok_or_else(|| LimboError::InvalidArgument("invalid page token".to_string()))?;
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn take_bytes(&mut self) -> Result<&'a [u8]> {
+        let len = u32::from_be_bytes(self.take_array()?);
+        self.take(len as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::RandomState;
+
+    #[test]
+    fn test_token_round_trip() {
+        let key = RandomState::new();
+        let cursor = PageCursor {
+            sql: "SELECT * FROM t WHERE a > ? AND b = ?".to_string(),
+            params: vec![
+                OwnedValue::Integer(-3),
+                OwnedValue::build_text("x"),
+                OwnedValue::Float(1.5),
+                OwnedValue::Blob(vec![0, 255]),
+                OwnedValue::Null,
+            ],
+            position: 200,
+            expires_at: 1_700_000_000_000_000,
+        };
+        let token = cursor.encode(&key);
+        assert_eq!(PageCursor::decode(&token, &key).unwrap(), cursor);
+
+        // Another database, a tampered token or garbage are rejected.
+        assert!(PageCursor::decode(&token, &RandomState::new()).is_err());
+        let mut tampered = hex::decode(&token).unwrap();
+        tampered[10] ^= 1;
+        assert!(PageCursor::decode(&hex::encode(tampered), &key).is_err());
+        assert!(PageCursor::decode("zz", &key).is_err());
+        assert!(PageCursor::decode("", &key).is_err());
+    }
+}
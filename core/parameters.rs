@@ -1,5 +1,12 @@
 use std::num::NonZero;
 
+use limbo_sqlite3_parser::lexer::{
+    sql::{TokenType, Tokenizer},
+    Scanner,
+};
+
+use crate::OwnedValue;
+
 #[derive(Clone, Debug)]
 pub enum Parameter {
     Anonymous(NonZero<usize>),
@@ -105,7 +112,7 @@ impl Parameters {
             index => {
                 // SAFETY: Guaranteed from parser that the index is bigger than 0.
                 let index: NonZero<usize> = index.parse().unwrap();
-                if index > self.index {
+                if index >= self.index {
                     self.index = index.checked_add(1).unwrap();
                 }
                 self.list.push(Parameter::Indexed(index));
@@ -115,3 +122,89 @@ impl Parameters {
         }
     }
 }
+
+/// Returns `sql` with every parameter replaced by the SQL literal of its bound
+/// value. Parameters are numbered the same way translation numbers them and
+/// unbound ones are left as written.
+pub fn expand_sql<'a>(
+    sql: &str,
+    lookup: impl Fn(NonZero<usize>) -> Option<&'a OwnedValue>,
+) -> String {
+    let input = sql.as_bytes();
+    let mut scanner = Scanner::new(Tokenizer::new());
+    let mut params = Parameters::new();
+    let mut expanded = String::with_capacity(sql.len());
+    let mut copied = 0;
+    loop {
+        match scanner.scan(input) {
+            Ok((start, Some((name, TokenType::TK_VARIABLE)), end)) => {
+                let index = params.push(String::from_utf8_lossy(name));
+                if let Some(value) = lookup(index) {
+                    expanded.push_str(&sql[copied..start]);
+                    push_literal(&mut expanded, value);
+                    copied = end;
+                }
+            }
+            Ok((_, Some(_), _)) => {}
+            Ok((_, None, _)) | Err(_) => break,
+        }
+    }
+    expanded.push_str(&sql[copied..]);
+    expanded
+}
+
+/// Returns `sql` with every literal and parameter replaced by `?`, and the whitespace and
+/// comments between tokens collapsed to a single space. Statements that differ only in their
+/// values normalize to the same text, which makes it a fingerprint of the query.
+pub fn normalize_sql(sql: &str) -> String {
+    let input = sql.as_bytes();
+    let mut scanner = Scanner::new(Tokenizer::new());
+    let mut normalized = String::with_capacity(sql.len());
+    let mut copied = 0;
+    loop {
+        match scanner.scan(input) {
+            Ok((start, Some((_, token_type)), end)) => {
+                if start > copied && !normalized.is_empty() {
+                    normalized.push(' ');
+                }
+                match token_type {
+                    TokenType::TK_INTEGER
+                    | TokenType::TK_FLOAT
+                    | TokenType::TK_STRING
+                    | TokenType::TK_BLOB
+                    | TokenType::TK_VARIABLE => normalized.push('?'),
+                    _ => normalized.push_str(&sql[start..end]),
+                }
+                copied = end;
+            }
+            Ok((_, None, _)) => break,
+            Err(_) => {
+                // Text the tokenizer rejects is kept as written.
+                let rest = sql[copied..].trim();
+                if !rest.is_empty() && !normalized.is_empty() {
+                    normalized.push(' ');
+                }
+                normalized.push_str(rest);
+                break;
+            }
+        }
+    }
+    normalized
+}
+
+pub(crate) fn push_literal(out: &mut String, value: &OwnedValue) {
+    match value {
+        OwnedValue::Null => out.push_str("NULL"),
+        OwnedValue::Integer(_) | OwnedValue::Float(_) => out.push_str(&value.to_string()),
+        OwnedValue::Text(text) => {
+            out.push('\'');
+            out.push_str(&text.as_str().replace('\'', "''"));
+            out.push('\'');
+        }
+        OwnedValue::Blob(blob) => {
+            out.push_str("X'");
+            out.push_str(&hex::encode_upper(blob));
+            out.push('\'');
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Per-connection cache of prepared programs, so preparing the same statement again skips
+//! planning.
+//!
+//! Entries are keyed by the statement as rendered from its syntax tree, which makes
+//! differences in whitespace, comments and keyword case irrelevant. Each entry remembers the
+//! schema and statistics versions it was planned against and is replanned once either moved
+//! on, i.e. after DDL or ANALYZE on any connection of the database. Changes that only affect
+//! this connection's planning, like `PRAGMA automatic_index` or new functions, clear the cache.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::rc::Rc;
+
+use limbo_sqlite3_parser::ast::{self, fmt::ToTokens};
+
+use crate::schema::Schema;
+use crate::vdbe::Program;
+
+/// The number of programs a connection keeps by default.
+pub const DEFAULT_PLAN_CACHE_CAPACITY: usize = 64;
+
+/// Counters of a connection's plan cache, see [crate::Connection::plan_cache_stats].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlanCacheStats {
+    /// Prepares that reused a cached program.
+    pub hits: u64,
+    /// Prepares of cacheable statements that had to plan.
+    pub misses: u64,
+    /// The number of cached programs.
+    pub entries: usize,
+}
+
+struct StmtFormatter<'a> {
+    stmt: &'a ast::Stmt,
+}
+
+impl Display for StmtFormatter<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.stmt.to_fmt(f)
+    }
+}
+
+struct Entry {
+    program: Rc<Program>,
+    schema_version: u64,
+    stats_version: u64,
+    last_used: u64,
+}
+
+pub(crate) struct PlanCache {
+    entries: HashMap<String, Entry>,
+    capacity: usize,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl PlanCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            capacity,
+            tick: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// The key of `stmt` if its program can be reused, which is only the case for statements
+    /// whose translation has no side effects.
+    pub fn key(&self, stmt: &ast::Stmt) -> Option<String> {
+        if self.capacity == 0 {
+            return None;
+        }
+        match stmt {
+            ast::Stmt::Select(_)
+            | ast::Stmt::Insert(_)
+            | ast::Stmt::Update(_)
+            | ast::Stmt::Delete(_) => Some(StmtFormatter { stmt }.to_string()),
+            _ => None,
+        }
+    }
+
+    pub fn get(&mut self, key: &str, schema: &Schema) -> Option<Rc<Program>> {
+        self.tick += 1;
+        let Some(entry) = self.entries.get_mut(key) else {
+            self.misses += 1;
+            return None;
+        };
+        if entry.schema_version != schema.schema_version
+            || entry.stats_version != schema.stats_version
+        {
+            self.entries.remove(key);
+            self.misses += 1;
+            return None;
+        }
+        entry.last_used = self.tick;
+        self.hits += 1;
+        Some(entry.program.clone())
+    }
+
+    pub fn insert(&mut self, key: String, program: Rc<Program>, schema: &Schema) {
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            self.evict_least_recently_used();
+        }
+        self.entries.insert(
+            key,
+            Entry {
+                program,
+                schema_version: schema.schema_version,
+                stats_version: schema.stats_version,
+                last_used: self.tick,
+            },
+        );
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.evict_least_recently_used();
+        }
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let least_recently_used = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = least_recently_used {
+            self.entries.remove(&key);
+        }
+    }
+
+    pub fn stats(&self) -> PlanCacheStats {
+        PlanCacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len(),
+        }
+    }
+}
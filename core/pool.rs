@@ -0,0 +1,337 @@
+//! A pool of connections to one database.
+//!
+//! Connections aren't `Send`, so a pool belongs to the thread that created it, which is the
+//! usual shape for an event loop serving many requests. Readers share the idle connections,
+//! while writes go through a single writer connection that is handed out in the order it was
+//! asked for, so a busy stream of writers can't starve one that has been waiting longer.
+//! Every connection the pool opens first runs the pool's init steps, so pragmas, functions
+//! and attached databases are set up in one place.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::ops::Deref;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::{Connection, Database, FallibleStreamingIterator, LimboError, Result};
+
+/// A step run on every connection the pool opens, before it is handed out.
+pub type ConnectionInit = Box<dyn Fn(&Rc<Connection>) -> Result<()>>;
+
+pub struct ConnectionPool {
+    db: Arc<Database>,
+    max_connections: usize,
+    /// Connections handed out or idle, including the writer.
+    open: Cell<usize>,
+    idle: RefCell<Vec<Rc<Connection>>>,
+    writer: RefCell<Option<Rc<Connection>>>,
+    writer_busy: Cell<bool>,
+    /// Tickets of the pending [WriteRequest]s, oldest first.
+    write_queue: RefCell<VecDeque<u64>>,
+    next_ticket: Cell<u64>,
+    init: RefCell<Vec<ConnectionInit>>,
+}
+
+impl ConnectionPool {
+    pub(crate) fn new(db: Arc<Database>, max_connections: usize) -> Rc<Self> {
+        assert!(max_connections > 0, "a pool needs at least one connection");
+        Rc::new(Self {
+            db,
+            max_connections,
+            open: Cell::new(0),
+            idle: RefCell::new(Vec::new()),
+            writer: RefCell::new(None),
+            writer_busy: Cell::new(false),
+            write_queue: RefCell::new(VecDeque::new()),
+            next_ticket: Cell::new(0),
+            init: RefCell::new(Vec::new()),
+        })
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    /// Number of idle read connections ready for reuse.
+    pub fn idle_connections(&self) -> usize {
+        self.idle.borrow().len()
+    }
+
+    /// Takes an idle connection, opening a new one while the pool is below its limit.
+    /// Returns [LimboError::Busy] when every connection is in use.
+    pub fn get(self: &Rc<Self>) -> Result<PooledConnection> {
+        let conn = match self.idle.borrow_mut().pop() {
+            Some(conn) => conn,
+            None => self.open_connection()?,
+        };
+        Ok(PooledConnection {
+            pool: self.clone(),
+            conn: Some(conn),
+        })
+    }
+
+    /// Adds a step run on every connection the pool opens from now on, after the steps added
+    /// before it. Connections that are already open aren't touched, so steps are best added
+    /// right after creating the pool. A connection whose steps fail is closed, and the error
+    /// is returned to whoever asked for it.
+    pub fn on_connect(&self, init: impl Fn(&Rc<Connection>) -> Result<()> + 'static) {
+        self.init.borrow_mut().push(Box::new(init));
+    }
+
+    /// Like [ConnectionPool::on_connect], running the statements of `sql` in order, e.g.
+    /// `PRAGMA cache_size = 4000; ATTACH 'users.db' AS users;`. Their rows are discarded.
+    pub fn on_connect_sql(&self, sql: impl Into<String>) {
+        let sql = sql.into();
+        self.on_connect(move |conn| {
+            for stmt in conn.query_runner(sql.as_bytes()) {
+                if let Some(mut stmt) = stmt? {
+                    let mut rows = stmt.query([])?;
+                    while rows.next()?.is_some() {}
+                }
+            }
+            Ok(())
+        });
+    }
+
+    /// Queues up for the writer connection. The request is granted by
+    /// [WriteRequest::try_acquire] once every earlier request was served.
+    pub fn writer(self: &Rc<Self>) -> WriteRequest {
+        let ticket = self.next_ticket.get();
+        self.next_ticket.set(ticket + 1);
+        self.write_queue.borrow_mut().push_back(ticket);
+        WriteRequest {
+            pool: self.clone(),
+            ticket,
+        }
+    }
+
+    fn open_connection(&self) -> Result<Rc<Connection>> {
+        if self.open.get() >= self.max_connections {
+            return Err(LimboError::Busy);
+        }
+        let conn = self.db.connect()?;
+        for init in self.init.borrow().iter() {
+            init(&conn)?;
+        }
+        self.open.set(self.open.get() + 1);
+        Ok(conn)
+    }
+
+    /// Takes a connection back, returning it if it can be reused. One left inside a
+    /// transaction is rolled back first, as the connections share the schema and the pages
+    /// it changed, and is closed if that fails.
+    fn release(&self, conn: Rc<Connection>) -> Option<Rc<Connection>> {
+        if !conn.get_auto_commit() {
+            if let Err(e) = conn.rollback() {
+                tracing::warn!("failed to roll back abandoned transaction: {}", e);
+                self.open.set(self.open.get() - 1);
+                return None;
+            }
+        }
+        Some(conn)
+    }
+}
+
+/// A read connection borrowed from a [ConnectionPool], returned to it on drop.
+pub struct PooledConnection {
+    pool: Rc<ConnectionPool>,
+    conn: Option<Rc<Connection>>,
+}
+
+impl Deref for PooledConnection {
+    type Target = Rc<Connection>;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take().and_then(|conn| self.pool.release(conn)) {
+            self.pool.idle.borrow_mut().push(conn);
+        }
+    }
+}
+
+/// A place in the queue for the writer connection. Dropping it gives up the place.
+pub struct WriteRequest {
+    pool: Rc<ConnectionPool>,
+    ticket: u64,
+}
+
+impl WriteRequest {
+    /// Returns the writer connection if it is free and this is the oldest pending request.
+    /// Fails with [LimboError::Busy] if the writer has to be opened but the pool is full.
+    pub fn try_acquire(&self) -> Result<Option<PooledWriter>> {
+        let pool = &self.pool;
+        if pool.writer_busy.get() || pool.write_queue.borrow().front() != Some(&self.ticket) {
+            return Ok(None);
+        }
+        let existing = pool.writer.borrow_mut().take();
+        let conn = match existing {
+            Some(conn) => conn,
+            None => pool.open_connection()?,
+        };
+        pool.write_queue.borrow_mut().pop_front();
+        pool.writer_busy.set(true);
+        Ok(Some(PooledWriter {
+            pool: pool.clone(),
+            conn: Some(conn),
+        }))
+    }
+}
+
+impl Drop for WriteRequest {
+    fn drop(&mut self) {
+        let mut queue = self.pool.write_queue.borrow_mut();
+        if let Some(pos) = queue.iter().position(|ticket| *ticket == self.ticket) {
+            queue.remove(pos);
+        }
+    }
+}
+
+/// The pool's writer connection, passed on to the next [WriteRequest] on drop.
+pub struct PooledWriter {
+    pool: Rc<ConnectionPool>,
+    conn: Option<Rc<Connection>>,
+}
+
+impl Deref for PooledWriter {
+    type Target = Rc<Connection>;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledWriter {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            *self.pool.writer.borrow_mut() = self.pool.release(conn);
+        }
+        self.pool.writer_busy.set(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemoryIO, OwnedValue, IO};
+
+    fn pool(max_connections: usize) -> Rc<ConnectionPool> {
+        let io: Arc<dyn IO> = Arc::new(MemoryIO::new());
+        let db = Database::open_file(io, ":memory:", false).unwrap();
+        db.pool(max_connections)
+    }
+
+    #[test]
+    fn test_pool_reuses_read_connections() {
+        let pool = pool(2);
+        let first = pool.get().unwrap();
+        let first_ptr = Rc::as_ptr(&first);
+        let second = pool.get().unwrap();
+        assert!(matches!(pool.get(), Err(LimboError::Busy)));
+
+        drop(first);
+        assert_eq!(pool.idle_connections(), 1);
+        let again = pool.get().unwrap();
+        assert_eq!(Rc::as_ptr(&again), first_ptr);
+        drop(second);
+        drop(again);
+        assert_eq!(pool.idle_connections(), 2);
+    }
+
+    #[test]
+    fn test_pool_serves_writers_in_order() {
+        let pool = pool(3);
+        let first = pool.writer();
+        let second = pool.writer();
+        let third = pool.writer();
+
+        // Later requests wait for earlier ones, even while the writer is free.
+        assert!(second.try_acquire().unwrap().is_none());
+        let writer = first.try_acquire().unwrap().unwrap();
+        assert!(second.try_acquire().unwrap().is_none());
+        drop(writer);
+        drop(first);
+
+        // A request that gives up its place doesn't hold up the ones behind it.
+        drop(second);
+        let writer = third.try_acquire().unwrap().unwrap();
+        writer.execute("CREATE TABLE t (x)").unwrap();
+        drop(writer);
+
+        // The writer connection is kept for the next request rather than reopened.
+        let reader = pool.get().unwrap();
+        let request = pool.writer();
+        let writer = request.try_acquire().unwrap().unwrap();
+        assert!(!Rc::ptr_eq(&reader, &writer));
+        assert_eq!(pool.open.get(), 2);
+    }
+
+    #[test]
+    fn test_pool_rolls_back_abandoned_transactions() {
+        let pool = pool(2);
+        let count = |conn: &Rc<Connection>, table: &str| {
+            let mut stmt = conn
+                .prepare(format!("SELECT count(*) FROM {table}"))
+                .unwrap();
+            let mut rows = stmt.query([]).unwrap();
+            let row = rows.next().unwrap().unwrap();
+            row.get::<i64>(0).unwrap()
+        };
+        let conn = pool.get().unwrap();
+        conn.execute("CREATE TABLE t (x)").unwrap();
+        conn.execute("BEGIN").unwrap();
+        conn.execute("CREATE TABLE zz (a)").unwrap();
+        conn.execute("INSERT INTO zz VALUES (1)").unwrap();
+        conn.execute("INSERT INTO t VALUES (42)").unwrap();
+        drop(conn);
+        assert_eq!(pool.idle_connections(), 1);
+
+        let conn = pool.get().unwrap();
+        assert!(conn.get_auto_commit());
+        assert!(conn.execute("SELECT * FROM zz").is_err());
+        assert_eq!(count(&conn, "t"), 0);
+
+        // The same goes for the writer.
+        let request = pool.writer();
+        let writer = request.try_acquire().unwrap().unwrap();
+        writer.execute("BEGIN").unwrap();
+        writer.execute("INSERT INTO t VALUES (43)").unwrap();
+        drop(writer);
+        assert_eq!(count(&conn, "t"), 0);
+
+        conn.execute("CREATE TABLE zz (a)").unwrap();
+        conn.execute("INSERT INTO t VALUES (1)").unwrap();
+        assert_eq!(count(&conn, "t"), 1);
+        let mut stmt = conn.prepare("PRAGMA integrity_check").unwrap();
+        let mut rows = stmt.query([]).unwrap();
+        let row = rows.next().unwrap().unwrap();
+        assert_eq!(row.get::<String>(0).unwrap(), "ok");
+    }
+
+    #[test]
+    fn test_pool_initializes_new_connections() {
+        let pool = pool(2);
+        pool.on_connect_sql("PRAGMA automatic_index = 0; CREATE TABLE IF NOT EXISTS t (x);");
+        pool.on_connect(|conn| {
+            conn.set_metadata("tenant", OwnedValue::Integer(7));
+            Ok(())
+        });
+
+        let conn = pool.get().unwrap();
+        let mut stmt = conn.prepare("PRAGMA automatic_index").unwrap();
+        let mut rows = stmt.query([]).unwrap();
+        let row = rows.next().unwrap().unwrap();
+        assert_eq!(row.get_values().next(), Some(&OwnedValue::Integer(0)));
+        assert_eq!(conn.metadata("tenant"), Some(OwnedValue::Integer(7)));
+        assert_eq!(conn.metadata("user"), None);
+
+        // A connection whose init fails isn't handed out and doesn't count against the limit.
+        pool.on_connect(|_| Err(LimboError::InternalError("init failed".into())));
+        assert!(matches!(pool.get(), Err(LimboError::InternalError(_))));
+        assert_eq!(pool.open.get(), 1);
+    }
+}
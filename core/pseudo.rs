@@ -13,7 +13,12 @@ impl PseudoCursor {
         self.current.as_ref()
     }
 
-    pub fn insert(&mut self, record: ImmutableRecord) {
-        self.current = Some(record);
+    pub fn into_record(self) -> Option<ImmutableRecord> {
+        self.current
+    }
+
+    /// Replaces the current record, returning the previous one.
+    pub fn insert(&mut self, record: ImmutableRecord) -> Option<ImmutableRecord> {
+        self.current.replace(record)
     }
 }
@@ -0,0 +1,219 @@
+//! Changing the definition of a table by rebuilding it, for the edits ALTER TABLE can't make,
+//! e.g. dropping a column or changing its type or constraints. This is the procedure SQLite
+//! documents for such changes: create a table with the new definition, copy the rows over,
+//! drop the old table and give the new one its name, all in one transaction.
+//!
+//! There is no `ALTER TABLE ... RENAME` to give the new table the old name, so the rows are
+//! copied twice: into a scratch table with the new definition, which also checks them against
+//! it, and from there into the table created under the original name.
+
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use fallible_iterator::FallibleIterator;
+use fallible_streaming_iterator::FallibleStreamingIterator;
+use limbo_sqlite3_parser::ast::{self, Cmd, Name, Stmt};
+use limbo_sqlite3_parser::lexer::sql::Parser;
+
+use crate::introspection::{self, IndexInfo};
+use crate::schema::BTreeTable;
+use crate::util::normalize_ident;
+use crate::{Connection, LimboError, Result};
+
+/// Replaces the definition of `table_name` with `create_sql`, a `CREATE TABLE` statement for a
+/// table of the same name, keeping the rows. The columns of the new definition that the old
+/// one has too are copied, the others get their default value.
+///
+/// Indexes created with `CREATE INDEX` are created again if their columns are still there,
+/// triggers stay, as dropping a table keeps them, and foreign keys are the ones of the new
+/// definition. Other tables keep referring to the table by name. If any step fails, e.g. a row
+/// doesn't satisfy a constraint of the new definition, nothing is changed.
+pub(crate) fn alter_table_rebuild(
+    conn: &Rc<Connection>,
+    table_name: &str,
+    create_sql: &str,
+) -> Result<()> {
+    if !conn.get_auto_commit() {
+        return Err(LimboError::TxError(
+            "cannot rebuild a table within a transaction".to_string(),
+        ));
+    }
+    let plan = RebuildPlan::new(conn, table_name, create_sql)?;
+    // The copy has to see all rows, whatever the application may read.
+    let _suspended = conn.suspend_row_filter();
+    conn.execute("BEGIN IMMEDIATE")?;
+    let header = conn.pager.db_header.lock().clone();
+    let (tables, indexes) = {
+        let schema = conn.schema.read();
+        (schema.tables.clone(), schema.indexes.clone())
+    };
+    match plan.run(conn).and_then(|_| conn.execute("COMMIT")) {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            conn.rollback_write_tx(header)?;
+            // The statements that ran changed the schema as they went.
+            let mut schema = conn.schema.write();
+            schema.tables = tables;
+            schema.indexes = indexes;
+            schema.schema_version += 1;
+            Err(err)
+        }
+    }
+}
+
+struct RebuildPlan {
+    table_name: String,
+    create_sql: String,
+    /// The new definition under the name of the scratch table.
+    scratch_sql: String,
+    scratch_name: String,
+    /// Columns of the old table that the new one keeps.
+    kept_columns: Vec<String>,
+    /// Every column of the new table.
+    columns: Vec<String>,
+    indexes: Vec<IndexInfo>,
+}
+
+impl RebuildPlan {
+    fn new(conn: &Rc<Connection>, table_name: &str, create_sql: &str) -> Result<Self> {
+        let schema = conn.schema.try_read().ok_or(LimboError::SchemaLocked)?;
+        let old = schema
+            .get_btree_table(table_name)
+            .filter(|table| table.name != "sqlite_schema")
+            .ok_or_else(|| LimboError::InvalidArgument(format!("no such table: {table_name}")))?;
+
+        let mut parser = Parser::new(create_sql.as_bytes());
+        let Some(Cmd::Stmt(Stmt::CreateTable {
+            temporary: false,
+            if_not_exists,
+            tbl_name,
+            body,
+        })) = parser.next()?
+        else {
+            return Err(LimboError::InvalidArgument(format!(
+                "expected a CREATE TABLE statement for {}",
+                old.name
+            )));
+        };
+        if normalize_ident(&tbl_name.name.0) != old.name {
+            return Err(LimboError::InvalidArgument(format!(
+                "the new definition is for {}, not {}",
+                tbl_name.name.0, old.name
+            )));
+        }
+        let new = BTreeTable::from_sql(create_sql, 0)?;
+        for foreign_key in &new.foreign_keys {
+            if foreign_key.parent_table != old.name
+                && schema.get_table(&foreign_key.parent_table).is_none()
+            {
+                return Err(LimboError::InvalidArgument(format!(
+                    "foreign key of {} refers to missing table {}",
+                    old.name, foreign_key.parent_table
+                )));
+            }
+        }
+
+        let columns: Vec<String> = new.columns.iter().filter_map(|c| c.name.clone()).collect();
+        let kept_columns: Vec<String> = columns
+            .iter()
+            .filter(|name| old.get_column(name).is_some())
+            .cloned()
+            .collect();
+        if kept_columns.is_empty() {
+            return Err(LimboError::InvalidArgument(format!(
+                "the new definition of {} keeps none of its columns",
+                old.name
+            )));
+        }
+        let new_columns: HashSet<&String> = columns.iter().collect();
+        let indexes = introspection::indexes(&schema, &old.name)?
+            .into_iter()
+            .filter(|index| {
+                !index.automatic
+                    && index
+                        .columns
+                        .iter()
+                        .all(|column| new_columns.contains(&column.name))
+            })
+            .collect();
+
+        let mut scratch_name = format!("{}_rebuild", old.name);
+        while schema.get_table(&scratch_name).is_some() {
+            scratch_name.push('_');
+        }
+        let scratch = Stmt::CreateTable {
+            temporary: false,
+            if_not_exists,
+            tbl_name: ast::QualifiedName::single(Name(scratch_name.clone())),
+            body,
+        };
+        Ok(Self {
+            table_name: old.name.clone(),
+            create_sql: create_sql.to_string(),
+            scratch_sql: Cmd::Stmt(scratch).to_string(),
+            scratch_name,
+            kept_columns,
+            columns,
+            indexes,
+        })
+    }
+
+    fn run(&self, conn: &Rc<Connection>) -> Result<()> {
+        conn.execute(&self.scratch_sql)?;
+        copy_rows(
+            conn,
+            &self.table_name,
+            &self.scratch_name,
+            &self.kept_columns,
+        )?;
+        conn.execute(format!("DROP TABLE {}", self.table_name))?;
+        conn.execute(&self.create_sql)?;
+        copy_rows(conn, &self.scratch_name, &self.table_name, &self.columns)?;
+        conn.execute(format!("DROP TABLE {}", self.scratch_name))?;
+        // Indexes are built from the rows of the table, so they come last.
+        for index in &self.indexes {
+            let columns: Vec<String> = index
+                .columns
+                .iter()
+                .map(|column| {
+                    if column.descending {
+                        format!("{} DESC", column.name)
+                    } else {
+                        column.name.clone()
+                    }
+                })
+                .collect();
+            conn.execute(format!(
+                "CREATE {}INDEX {} ON {} ({})",
+                if index.unique { "UNIQUE " } else { "" },
+                index.name,
+                self.table_name,
+                columns.join(", ")
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+/// Copies `columns` of every row of `from` into `to`.
+fn copy_rows(conn: &Rc<Connection>, from: &str, to: &str, columns: &[String]) -> Result<()> {
+    let params: Vec<String> = (1..=columns.len()).map(|i| format!("?{i}")).collect();
+    let columns: Vec<String> = columns.iter().map(|name| quote_ident(name)).collect();
+    let columns = columns.join(", ");
+    let mut select = conn.prepare(format!("SELECT {columns} FROM {}", quote_ident(from)))?;
+    let mut insert = conn.prepare(format!(
+        "INSERT INTO {} ({columns}) VALUES ({})",
+        quote_ident(to),
+        params.join(", ")
+    ))?;
+    let mut rows = select.query([])?;
+    while let Some(row) = rows.next()? {
+        let mut inserted = insert.query(row.get_values().cloned())?;
+        while inserted.next()?.is_some() {}
+    }
+    Ok(())
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
@@ -0,0 +1,408 @@
+//! Row filters: predicates an embedder attaches to tables to limit the rows a connection can
+//! see and change, e.g. to those of one tenant, without the application's SQL knowing about
+//! them. Statements are rewritten before planning, so the filters apply to every query on the
+//! connection, see [crate::Connection::set_row_filter].
+//!
+//! The filter of a table is ANDed to the WHERE clause of each SELECT, UPDATE or DELETE reading
+//! it, or to the ON clause when the table is on the nullable side of an outer join, so that its
+//! hidden rows behave as missing. Where neither is possible, for NATURAL or USING outer joins
+//! and RIGHT joins, the table is replaced by a filtered subquery. Subqueries, CTEs and the
+//! SELECT of an INSERT are filtered the same way; the rows an INSERT adds are not checked.
+use std::{cell::RefCell, rc::Rc};
+
+use fallible_iterator::FallibleIterator;
+use limbo_sqlite3_parser::{
+    ast::{self, Cmd},
+    lexer::sql::Parser,
+};
+
+use crate::{
+    parameters::expand_sql, translate::optimizer::for_each_expr_in, types::OwnedValue,
+    util::normalize_ident, LimboError, Result,
+};
+
+/// A condition over the columns of a table that its rows must satisfy to be visible.
+#[derive(Debug, Clone)]
+pub struct RowFilter {
+    /// The predicate with its parameters expanded, as SQL.
+    sql: String,
+    predicate: ast::Expr,
+}
+
+/// Called with the name of every table a statement reads, updates or deletes from, returning
+/// the filter for its rows if it has one.
+pub type RowFilterHook = Box<dyn Fn(&str) -> Option<RowFilter>>;
+
+impl RowFilter {
+    /// A filter on `predicate`, an SQL expression over the columns of the table like
+    /// `tenant_id = ?`. Its parameters are bound to `params` in order, as literals.
+    pub fn new(predicate: &str, params: &[OwnedValue]) -> Result<Self> {
+        let invalid = || LimboError::InvalidArgument(format!("invalid row filter: {predicate}"));
+        let sql = expand_sql(predicate, |index| params.get(index.get() - 1));
+        let query = format!("SELECT 1 WHERE {sql}");
+        let mut parser = Parser::new(query.as_bytes());
+        let Some(Cmd::Stmt(ast::Stmt::Select(select))) = parser.next()? else {
+            return Err(invalid());
+        };
+        if parser.next()?.is_some()
+            || select.with.is_some()
+            || select.body.compounds.is_some()
+            || select.order_by.is_some()
+            || select.limit.is_some()
+        {
+            return Err(invalid());
+        }
+        let ast::OneSelect::Select(inner) = *select.body.select else {
+            return Err(invalid());
+        };
+        if inner.group_by.is_some() || inner.window_clause.is_some() {
+            return Err(invalid());
+        }
+        let Some(mut predicate) = inner.where_clause else {
+            return Err(invalid());
+        };
+        let mut unbound = false;
+        for_each_expr_in(&mut predicate, &mut |expr| {
+            unbound |= matches!(expr, ast::Expr::Variable(_));
+            true
+        });
+        if unbound {
+            return Err(LimboError::InvalidArgument(format!(
+                "row filter {predicate} has more parameters than values"
+            )));
+        }
+        Ok(Self { sql, predicate })
+    }
+
+    /// The predicate with the columns qualified by `table`, the name or alias of the table
+    /// in the statement.
+    fn qualified(&self, table: &ast::Name) -> ast::Expr {
+        let mut predicate = self.predicate.clone();
+        for_each_expr_in(&mut predicate, &mut |expr| {
+            if let ast::Expr::Id(id) = expr {
+                *expr = ast::Expr::Qualified(table.clone(), ast::Name(id.0.clone()));
+            }
+            true
+        });
+        predicate
+    }
+
+    /// `SELECT * FROM table WHERE predicate`, to stand in for `table`.
+    fn subquery(&self, table: &ast::QualifiedName) -> Result<Box<ast::Select>> {
+        let name = match &table.db_name {
+            Some(db) => format!("{}.{}", db.0, table.name.0),
+            None => table.name.0.clone(),
+        };
+        let query = format!("SELECT * FROM {name} WHERE {}", self.sql);
+        match Parser::new(query.as_bytes()).next()? {
+            Some(Cmd::Stmt(ast::Stmt::Select(select))) => Ok(select),
+            _ => unreachable!("a filtered table is a valid select"),
+        }
+    }
+}
+
+/// Adds the filters `hook` returns to every table `stmt` reads, updates or deletes from.
+pub(crate) fn apply(stmt: &mut ast::Stmt, hook: &RowFilterHook) -> Result<()> {
+    let mut rewriter = Rewriter {
+        hook,
+        ctes: Vec::new(),
+    };
+    match stmt {
+        ast::Stmt::Select(select) | ast::Stmt::SelectAsOf { select, .. } => rewriter.select(select),
+        ast::Stmt::Insert(insert) => {
+            rewriter.with(&mut insert.with)?;
+            match &mut insert.body {
+                ast::InsertBody::Select(select, _) => rewriter.select(select),
+                ast::InsertBody::DefaultValues => Ok(()),
+            }
+        }
+        ast::Stmt::Update(update) => {
+            rewriter.with(&mut update.with)?;
+            for set in update.sets.iter_mut() {
+                rewriter.expr(&mut set.expr)?;
+            }
+            let mut where_clause = update.where_clause.take().map(|expr| *expr);
+            if let Some(expr) = &mut where_clause {
+                rewriter.expr(expr)?;
+            }
+            if let Some(from) = &mut update.from {
+                rewriter.tables(from, &mut where_clause, false)?;
+            }
+            if let Some(filter) = rewriter.filter(&update.tbl_name) {
+                let table = update
+                    .tbl_name
+                    .alias
+                    .as_ref()
+                    .unwrap_or(&update.tbl_name.name);
+                add_predicate(&mut where_clause, filter.qualified(table));
+            }
+            update.where_clause = where_clause.map(Box::new);
+            Ok(())
+        }
+        ast::Stmt::Delete(delete) => {
+            rewriter.with(&mut delete.with)?;
+            let mut where_clause = delete.where_clause.take().map(|expr| *expr);
+            if let Some(expr) = &mut where_clause {
+                rewriter.expr(expr)?;
+            }
+            if let Some(filter) = rewriter.filter(&delete.tbl_name) {
+                let table = delete
+                    .tbl_name
+                    .alias
+                    .as_ref()
+                    .unwrap_or(&delete.tbl_name.name);
+                add_predicate(&mut where_clause, filter.qualified(table));
+            }
+            delete.where_clause = where_clause.map(Box::new);
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+struct Rewriter<'a> {
+    hook: &'a RowFilterHook,
+    /// The CTEs in scope, which are not filtered themselves.
+    ctes: Vec<String>,
+}
+
+impl Rewriter<'_> {
+    fn filter(&self, table: &ast::QualifiedName) -> Option<RowFilter> {
+        let name = normalize_ident(&table.name.0);
+        if name.starts_with("sqlite_") || (table.db_name.is_none() && self.ctes.contains(&name)) {
+            return None;
+        }
+        (self.hook)(&name)
+    }
+
+    fn with(&mut self, with: &mut Option<ast::With>) -> Result<()> {
+        let Some(with) = with else {
+            return Ok(());
+        };
+        for cte in with.ctes.iter_mut() {
+            let name = normalize_ident(&cte.tbl_name.0);
+            if with.recursive {
+                self.ctes.push(name);
+                self.select(&mut cte.select)?;
+            } else {
+                self.select(&mut cte.select)?;
+                self.ctes.push(name);
+            }
+        }
+        Ok(())
+    }
+
+    fn select(&mut self, select: &mut ast::Select) -> Result<()> {
+        let scope = self.ctes.len();
+        self.with(&mut select.with)?;
+        self.one_select(&mut select.body.select)?;
+        for compound in select.body.compounds.iter_mut().flatten() {
+            self.one_select(&mut compound.select)?;
+        }
+        for sorted in select.order_by.iter_mut().flatten() {
+            self.expr(&mut sorted.expr)?;
+        }
+        if let Some(limit) = &mut select.limit {
+            self.expr(&mut limit.expr)?;
+            if let Some(offset) = &mut limit.offset {
+                self.expr(offset)?;
+            }
+        }
+        self.ctes.truncate(scope);
+        Ok(())
+    }
+
+    fn one_select(&mut self, select: &mut ast::OneSelect) -> Result<()> {
+        match select {
+            ast::OneSelect::Select(inner) => {
+                let ast::SelectInner {
+                    columns,
+                    from,
+                    where_clause,
+                    group_by,
+                    ..
+                } = &mut **inner;
+                for column in columns.iter_mut() {
+                    if let ast::ResultColumn::Expr(expr, _) = column {
+                        self.expr(expr)?;
+                    }
+                }
+                if let Some(expr) = where_clause {
+                    self.expr(expr)?;
+                }
+                if let Some(group_by) = group_by {
+                    for expr in group_by.exprs.iter_mut() {
+                        self.expr(expr)?;
+                    }
+                    if let Some(having) = &mut group_by.having {
+                        self.expr(having)?;
+                    }
+                }
+                if let Some(from) = from {
+                    self.tables(from, where_clause, false)?;
+                }
+                Ok(())
+            }
+            ast::OneSelect::Values(rows) => {
+                for expr in rows.iter_mut().flatten() {
+                    self.expr(expr)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Filters the tables of `from`, adding predicates to `where_clause` or the ON clauses of
+    /// its joins, or replacing all of them by filtered subqueries if `wrap` is set.
+    fn tables(
+        &mut self,
+        from: &mut ast::FromClause,
+        where_clause: &mut Option<ast::Expr>,
+        wrap: bool,
+    ) -> Result<()> {
+        let wrap = wrap
+            || from
+                .joins
+                .iter()
+                .flatten()
+                .any(|join| join_type(&join.operator).contains(ast::JoinType::RIGHT));
+        if let Some(table) = &mut from.select {
+            if let Some(predicate) = self.select_table(table, wrap)? {
+                add_predicate(where_clause, predicate);
+            }
+        }
+        for join in from.joins.iter_mut().flatten() {
+            if let Some(ast::JoinConstraint::On(expr)) = &mut join.constraint {
+                self.expr(expr)?;
+            }
+            let join_type = join_type(&join.operator);
+            let outer = join_type.contains(ast::JoinType::LEFT);
+            let on_clause = match &mut join.constraint {
+                Some(ast::JoinConstraint::On(expr)) => Some(Some(expr)),
+                None if !join_type.contains(ast::JoinType::NATURAL) => Some(None),
+                _ => None,
+            };
+            let wrap = wrap || (outer && on_clause.is_none());
+            let Some(predicate) = self.select_table(&mut join.table, wrap)? else {
+                continue;
+            };
+            match on_clause {
+                Some(Some(expr)) if outer => {
+                    let on = std::mem::replace(expr, ast::Expr::Literal(ast::Literal::Null));
+                    *expr = conjoin(on, predicate);
+                }
+                Some(None) if outer => join.constraint = Some(ast::JoinConstraint::On(predicate)),
+                _ => add_predicate(where_clause, predicate),
+            }
+        }
+        Ok(())
+    }
+
+    /// Filters a table of a FROM clause, returning the predicate to add to the statement for
+    /// it, unless `wrap` is set and the table is replaced by a filtered subquery instead.
+    fn select_table(
+        &mut self,
+        table: &mut ast::SelectTable,
+        wrap: bool,
+    ) -> Result<Option<ast::Expr>> {
+        match table {
+            ast::SelectTable::Table(name, alias, _) => {
+                let Some(filter) = self.filter(name) else {
+                    return Ok(None);
+                };
+                let qualifier = match alias {
+                    Some(ast::As::As(alias) | ast::As::Elided(alias)) => alias.clone(),
+                    None => name.name.clone(),
+                };
+                if !wrap {
+                    return Ok(Some(filter.qualified(&qualifier)));
+                }
+                let subquery = filter.subquery(name)?;
+                *table = ast::SelectTable::Select(subquery, Some(ast::As::As(qualifier)));
+                Ok(None)
+            }
+            ast::SelectTable::TableCall(_, args, _) => {
+                for arg in args.iter_mut().flatten() {
+                    self.expr(arg)?;
+                }
+                Ok(None)
+            }
+            ast::SelectTable::Select(select, _) => {
+                self.select(select)?;
+                Ok(None)
+            }
+            ast::SelectTable::Sub(from, _) => {
+                // The tables of a parenthesized join may end up on either side of an outer
+                // join, so they are filtered on their own.
+                self.tables(from, &mut None, true)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Filters the subqueries of `expr`.
+    fn expr(&mut self, expr: &mut ast::Expr) -> Result<()> {
+        let mut result = Ok(());
+        for_each_expr_in(expr, &mut |expr| {
+            if result.is_err() {
+                return false;
+            }
+            if let ast::Expr::Subquery(select)
+            | ast::Expr::Exists(select)
+            | ast::Expr::InSelect { rhs: select, .. } = expr
+            {
+                result = self.select(select);
+            }
+            true
+        });
+        result
+    }
+}
+
+fn join_type(operator: &ast::JoinOperator) -> ast::JoinType {
+    match operator {
+        ast::JoinOperator::TypedJoin(Some(join_type)) => *join_type,
+        ast::JoinOperator::Comma | ast::JoinOperator::TypedJoin(None) => ast::JoinType::INNER,
+    }
+}
+
+/// `lhs AND rhs`, with operands that are ORs parenthesized so the expression reads the same
+/// when formatted.
+fn conjoin(lhs: ast::Expr, rhs: ast::Expr) -> ast::Expr {
+    let operand = |expr: ast::Expr| match expr {
+        ast::Expr::Binary(_, ast::Operator::Or, _) => ast::Expr::parenthesized(expr),
+        _ => expr,
+    };
+    ast::Expr::Binary(
+        Box::new(operand(lhs)),
+        ast::Operator::And,
+        Box::new(operand(rhs)),
+    )
+}
+
+pub(crate) fn add_predicate(where_clause: &mut Option<ast::Expr>, predicate: ast::Expr) {
+    *where_clause = Some(match where_clause.take() {
+        Some(expr) => conjoin(expr, predicate),
+        None => predicate,
+    });
+}
+
+/// Takes the row filter hook of a connection away until dropped, for the statements the
+/// connection runs on its own behalf, like copying a table while rebuilding it.
+pub(crate) struct Suspended<'a> {
+    slot: &'a RefCell<Option<Rc<RowFilterHook>>>,
+    hook: Option<Rc<RowFilterHook>>,
+}
+
+impl<'a> Suspended<'a> {
+    pub fn new(slot: &'a RefCell<Option<Rc<RowFilterHook>>>) -> Self {
+        let hook = slot.borrow_mut().take();
+        Self { slot, hook }
+    }
+}
+
+impl Drop for Suspended<'_> {
+    fn drop(&mut self) {
+        *self.slot.borrow_mut() = self.hook.take();
+    }
+}
@@ -1,8 +1,13 @@
+use crate::matview::MaterializedView;
+#[cfg(feature = "vector")]
+use crate::vector::index::VectorIndex;
 use crate::VirtualTable;
 use crate::{util::normalize_ident, Result};
 use core::fmt;
 use fallible_iterator::FallibleIterator;
-use limbo_sqlite3_parser::ast::{Expr, Literal, SortOrder, TableOptions};
+use limbo_sqlite3_parser::ast::{
+    Expr, ForeignKeyClause, Literal, RefAct, RefArg, SortOrder, TableOptions,
+};
 use limbo_sqlite3_parser::{
     ast::{Cmd, CreateTableBody, QualifiedName, ResultColumn, Stmt},
     lexer::sql::Parser,
@@ -16,6 +21,15 @@ pub struct Schema {
     pub tables: HashMap<String, Arc<Table>>,
     // table_name to list of indexes for the table
     pub indexes: HashMap<String, Vec<Arc<Index>>>,
+    /// Bumped whenever a table or index is added or removed, so cached plans know to replan.
+    pub schema_version: u64,
+    /// Bumped whenever ANALYZE collects new statistics.
+    pub stats_version: u64,
+    /// Materialized views by name, whose rows are in the table of the same name.
+    pub materialized_views: HashMap<String, Arc<MaterializedView>>,
+    /// Vector indexes by name, whose data is in their shadow tables.
+    #[cfg(feature = "vector")]
+    pub vector_indexes: HashMap<String, Arc<VectorIndex>>,
 }
 
 impl Schema {
@@ -27,10 +41,22 @@ impl Schema {
             "sqlite_schema".to_string(),
             Arc::new(Table::BTree(sqlite_schema_table().into())),
         );
-        Self { tables, indexes }
+        Self {
+            tables,
+            indexes,
+            schema_version: 0,
+            stats_version: 0,
+            materialized_views: HashMap::new(),
+            #[cfg(feature = "vector")]
+            vector_indexes: HashMap::new(),
+        }
     }
 
     pub fn is_unique_idx_name(&self, name: &str) -> bool {
+        #[cfg(feature = "vector")]
+        if self.vector_indexes.contains_key(name) {
+            return false;
+        }
         !self
             .indexes
             .iter()
@@ -40,11 +66,13 @@ impl Schema {
     pub fn add_btree_table(&mut self, table: Rc<BTreeTable>) {
         let name = normalize_ident(&table.name);
         self.tables.insert(name, Table::BTree(table).into());
+        self.schema_version += 1;
     }
 
     pub fn add_virtual_table(&mut self, table: Rc<VirtualTable>) {
         let name = normalize_ident(&table.name);
         self.tables.insert(name, Table::Virtual(table).into());
+        self.schema_version += 1;
     }
 
     pub fn get_table(&self, name: &str) -> Option<Arc<Table>> {
@@ -55,6 +83,7 @@ impl Schema {
     pub fn remove_table(&mut self, table_name: &str) {
         let name = normalize_ident(table_name);
         self.tables.remove(&name);
+        self.schema_version += 1;
     }
 
     pub fn get_btree_table(&self, name: &str) -> Option<Rc<BTreeTable>> {
@@ -71,7 +100,8 @@ impl Schema {
         self.indexes
             .entry(table_name)
             .or_default()
-            .push(index.clone())
+            .push(index.clone());
+        self.schema_version += 1;
     }
 
     pub fn get_indices(&self, table_name: &str) -> &[Arc<Index>] {
@@ -81,9 +111,79 @@ impl Schema {
             .map_or_else(|| &[] as &[Arc<Index>], |v| v.as_slice())
     }
 
+    pub fn add_materialized_view(&mut self, view: Arc<MaterializedView>) {
+        self.materialized_views.insert(view.name.clone(), view);
+    }
+
+    pub fn get_materialized_view(&self, name: &str) -> Option<Arc<MaterializedView>> {
+        self.materialized_views.get(&normalize_ident(name)).cloned()
+    }
+
+    pub fn remove_materialized_view(&mut self, name: &str) {
+        self.materialized_views.remove(&normalize_ident(name));
+    }
+
+    /// Whether a materialized view is maintained from the changes of `table_name`.
+    pub fn has_materialized_views_on(&self, table_name: &str) -> bool {
+        let name = normalize_ident(table_name);
+        self.materialized_views
+            .values()
+            .any(|view| view.source() == Some(name.as_str()))
+    }
+
+    #[cfg(feature = "vector")]
+    pub fn add_vector_index(&mut self, index: Arc<VectorIndex>) {
+        self.vector_indexes.insert(index.name.clone(), index);
+        self.schema_version += 1;
+    }
+
+    #[cfg(feature = "vector")]
+    pub fn get_vector_index(&self, name: &str) -> Option<Arc<VectorIndex>> {
+        self.vector_indexes.get(&normalize_ident(name)).cloned()
+    }
+
+    #[cfg(feature = "vector")]
+    pub fn remove_vector_index(&mut self, name: &str) {
+        self.vector_indexes.remove(&normalize_ident(name));
+        self.schema_version += 1;
+    }
+
+    /// The vector indexes on the table `table_name`.
+    #[cfg(feature = "vector")]
+    pub fn vector_indexes_on(&self, table_name: &str) -> Vec<Arc<VectorIndex>> {
+        let name = normalize_ident(table_name);
+        self.vector_indexes
+            .values()
+            .filter(|index| index.table_name == name)
+            .cloned()
+            .collect()
+    }
+
+    /// Whether tables are maintained from the changes of others, so that transactions writing
+    /// them have to be able to roll back after the maintenance fails, see
+    /// [crate::Connection::rollback].
+    pub fn has_maintained_tables(&self) -> bool {
+        #[cfg(feature = "vector")]
+        if !self.vector_indexes.is_empty() {
+            return true;
+        }
+        !self.materialized_views.is_empty()
+    }
+
+    /// Whether materialized views or vector indexes are maintained from the changes of
+    /// `table_name`.
+    pub fn is_maintained_from(&self, table_name: &str) -> bool {
+        #[cfg(feature = "vector")]
+        if !self.vector_indexes_on(table_name).is_empty() {
+            return true;
+        }
+        self.has_materialized_views_on(table_name)
+    }
+
     pub fn remove_indices_for_table(&mut self, table_name: &str) {
         let name = normalize_ident(table_name);
         self.indexes.remove(&name);
+        self.schema_version += 1;
     }
 }
 
@@ -161,9 +261,26 @@ pub struct BTreeTable {
     pub primary_key_column_names: Vec<String>,
     pub columns: Vec<Column>,
     pub has_rowid: bool,
+    pub foreign_keys: Vec<ForeignKey>,
+    /// Built for the duration of a single statement, see [BTreeTable::ephemeral].
+    /// Its b-tree lives in memory and is not part of the schema.
+    pub ephemeral: bool,
 }
 
 impl BTreeTable {
+    /// A transient table holding the rows of a materialized subquery.
+    pub fn ephemeral(name: &str, columns: Vec<Column>) -> BTreeTable {
+        BTreeTable {
+            root_page: 0,
+            name: name.to_string(),
+            primary_key_column_names: vec![],
+            columns,
+            has_rowid: true,
+            foreign_keys: vec![],
+            ephemeral: true,
+        }
+    }
+
     pub fn get_rowid_alias_column(&self) -> Option<(usize, &Column)> {
         if self.primary_key_column_names.len() == 1 {
             let (idx, col) = self.get_column(&self.primary_key_column_names[0]).unwrap();
@@ -195,7 +312,7 @@ impl BTreeTable {
             Some(Cmd::Stmt(Stmt::CreateTable { tbl_name, body, .. })) => {
                 create_table(tbl_name, *body, root_page)
             }
-            _ => todo!("Expected CREATE TABLE statement"),
+            _ => crate::bail_parse_error!("Expected CREATE TABLE statement"),
         }
     }
 
@@ -216,6 +333,67 @@ impl BTreeTable {
     }
 }
 
+/// A `REFERENCES` clause of a column or a `FOREIGN KEY` constraint of a table. The schema only
+/// records them, they are not enforced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForeignKey {
+    /// Columns of the child table, in the order of `parent_columns`.
+    pub columns: Vec<String>,
+    pub parent_table: String,
+    /// Referenced columns of the parent table, empty if the clause refers to its primary key.
+    pub parent_columns: Vec<String>,
+    pub on_delete: ForeignKeyAction,
+    pub on_update: ForeignKeyAction,
+}
+
+/// What happens to child rows when their parent row is deleted or its key updated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ForeignKeyAction {
+    #[default]
+    NoAction,
+    Restrict,
+    SetNull,
+    SetDefault,
+    Cascade,
+}
+
+impl ForeignKey {
+    fn from_clause(columns: Vec<String>, clause: &ForeignKeyClause) -> Self {
+        let mut foreign_key = ForeignKey {
+            columns,
+            parent_table: normalize_ident(&clause.tbl_name.0),
+            parent_columns: clause
+                .columns
+                .iter()
+                .flatten()
+                .map(|column| normalize_ident(&column.col_name.0))
+                .collect(),
+            on_delete: ForeignKeyAction::NoAction,
+            on_update: ForeignKeyAction::NoAction,
+        };
+        for arg in &clause.args {
+            match arg {
+                RefArg::OnDelete(action) => foreign_key.on_delete = (*action).into(),
+                RefArg::OnUpdate(action) => foreign_key.on_update = (*action).into(),
+                RefArg::OnInsert(_) | RefArg::Match(_) => {}
+            }
+        }
+        foreign_key
+    }
+}
+
+impl From<RefAct> for ForeignKeyAction {
+    fn from(action: RefAct) -> Self {
+        match action {
+            RefAct::NoAction => Self::NoAction,
+            RefAct::Restrict => Self::Restrict,
+            RefAct::SetNull => Self::SetNull,
+            RefAct::SetDefault => Self::SetDefault,
+            RefAct::Cascade => Self::Cascade,
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct PseudoTable {
     pub columns: Vec<Column>,
@@ -261,6 +439,9 @@ fn create_table(
     trace!("Creating table {}", table_name);
     let mut has_rowid = true;
     let mut primary_key_column_names = vec![];
+    let mut foreign_keys = vec![];
+    // Table constraints come after the columns, but are looked at first for the primary key.
+    let mut constraint_foreign_keys = vec![];
     let mut cols = vec![];
     match body {
         CreateTableBody::ColumnsAndConstraints {
@@ -270,21 +451,36 @@ fn create_table(
         } => {
             if let Some(constraints) = constraints {
                 for c in constraints {
-                    if let limbo_sqlite3_parser::ast::TableConstraint::PrimaryKey {
-                        columns, ..
-                    } = c.constraint
-                    {
-                        for column in columns {
-                            primary_key_column_names.push(match column.expr {
-                                Expr::Id(id) => normalize_ident(&id.0),
-                                Expr::Literal(Literal::String(value)) => {
-                                    value.trim_matches('\'').to_owned()
-                                }
-                                _ => {
-                                    todo!("Unsupported primary key expression");
-                                }
-                            });
+                    match c.constraint {
+                        limbo_sqlite3_parser::ast::TableConstraint::PrimaryKey {
+                            columns, ..
+                        } => {
+                            for column in columns {
+                                primary_key_column_names.push(match column.expr {
+                                    Expr::Id(id) => normalize_ident(&id.0),
+                                    Expr::Literal(Literal::String(value)) => {
+                                        value.trim_matches('\'').to_owned()
+                                    }
+                                    _ => {
+                                        crate::bail_parse_error!(
+                                            "Unsupported primary key expression"
+                                        );
+                                    }
+                                });
+                            }
                         }
+                        limbo_sqlite3_parser::ast::TableConstraint::ForeignKey {
+                            columns,
+                            clause,
+                            ..
+                        } => {
+                            let columns = columns
+                                .iter()
+                                .map(|column| normalize_ident(&column.col_name.0))
+                                .collect();
+                            constraint_foreign_keys.push(ForeignKey::from_clause(columns, &clause));
+                        }
+                        _ => {}
                     }
                 }
             }
@@ -351,6 +547,14 @@ fn create_table(
                         limbo_sqlite3_parser::ast::ColumnConstraint::Default(expr) => {
                             default = Some(expr.clone())
                         }
+                        limbo_sqlite3_parser::ast::ColumnConstraint::ForeignKey {
+                            clause, ..
+                        } => {
+                            foreign_keys.push(ForeignKey::from_clause(
+                                vec![normalize_ident(&name)],
+                                clause,
+                            ));
+                        }
                         _ => {}
                     }
                 }
@@ -371,11 +575,14 @@ fn create_table(
                     default,
                 });
             }
+            foreign_keys.append(&mut constraint_foreign_keys);
             if options.contains(TableOptions::WITHOUT_ROWID) {
                 has_rowid = false;
             }
         }
-        CreateTableBody::AsSelect(_) => todo!(),
+        CreateTableBody::AsSelect(_) => {
+            crate::bail_parse_error!("CREATE TABLE AS SELECT is not supported")
+        }
     };
     // flip is_rowid_alias back to false if the table has multiple primary keys
     // or if the table has no rowid
@@ -390,6 +597,8 @@ fn create_table(
         has_rowid,
         primary_key_column_names,
         columns: cols,
+        foreign_keys,
+        ephemeral: false,
     })
 }
 
@@ -425,7 +634,7 @@ pub struct Column {
 
 impl Column {
     pub fn affinity(&self) -> Affinity {
-        affinity(&self.ty_str)
+        affinity(&self.ty_str.to_uppercase())
     }
 }
 
@@ -531,6 +740,18 @@ impl Affinity {
             Affinity::Numeric => SQLITE_AFF_NUMERIC,
         }
     }
+
+    /// The inverse of [Affinity::aff_mask]. Unknown characters map to [Affinity::Blob], which
+    /// leaves values untouched.
+    pub fn from_char(c: char) -> Self {
+        match c {
+            SQLITE_AFF_INTEGER => Affinity::Integer,
+            SQLITE_AFF_TEXT => Affinity::Text,
+            SQLITE_AFF_NUMERIC => Affinity::Numeric,
+            SQLITE_AFF_REAL => Affinity::Real,
+            _ => Affinity::Blob,
+        }
+    }
 }
 
 impl fmt::Display for Type {
@@ -552,6 +773,8 @@ pub fn sqlite_schema_table() -> BTreeTable {
         root_page: 1,
         name: "sqlite_schema".to_string(),
         has_rowid: true,
+        foreign_keys: vec![],
+        ephemeral: false,
         primary_key_column_names: vec![],
         columns: vec![
             Column {
@@ -611,6 +834,9 @@ pub struct Index {
     pub root_page: usize,
     pub columns: Vec<IndexColumn>,
     pub unique: bool,
+    /// Built by the query planner for the duration of a single statement, see
+    /// [Index::automatic]. Its b-tree lives in memory and is not part of the schema.
+    pub ephemeral: bool,
 }
 
 #[allow(dead_code)]
@@ -646,9 +872,10 @@ impl Index {
                     root_page,
                     columns: index_columns,
                     unique,
+                    ephemeral: false,
                 })
             }
-            _ => todo!("Expected create index statement"),
+            _ => crate::bail_parse_error!("Expected create index statement"),
         }
     }
 
@@ -687,8 +914,25 @@ impl Index {
             root_page,
             columns: index_columns,
             unique: true, // Primary key indexes are always unique
+            ephemeral: false,
         })
     }
+
+    /// A transient index on one column of a table, used to look up the rows of an inner join
+    /// table instead of scanning it for every row of the outer tables.
+    pub fn automatic(table: &BTreeTable, table_identifier: &str, column_name: &str) -> Index {
+        Index {
+            name: format!("automatic_index_{}_{}", table_identifier, column_name),
+            table_name: table.name.clone(),
+            root_page: 0,
+            columns: vec![IndexColumn {
+                name: column_name.to_string(),
+                order: SortOrder::Asc,
+            }],
+            unique: false,
+            ephemeral: true,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -915,6 +1159,33 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    pub fn test_foreign_keys() -> Result<()> {
+        let sql = r#"CREATE TABLE t1 (a REFERENCES "P" (Id) ON DELETE SET DEFAULT, b, c,
+            FOREIGN KEY (b, c) REFERENCES q ON UPDATE RESTRICT);"#;
+        let table = BTreeTable::from_sql(sql, 0)?;
+        assert_eq!(
+            table.foreign_keys,
+            vec![
+                ForeignKey {
+                    columns: vec!["a".to_string()],
+                    parent_table: "p".to_string(),
+                    parent_columns: vec!["id".to_string()],
+                    on_delete: ForeignKeyAction::SetDefault,
+                    on_update: ForeignKeyAction::NoAction,
+                },
+                ForeignKey {
+                    columns: vec!["b".to_string(), "c".to_string()],
+                    parent_table: "q".to_string(),
+                    parent_columns: vec![],
+                    on_delete: ForeignKeyAction::NoAction,
+                    on_update: ForeignKeyAction::Restrict,
+                },
+            ]
+        );
+        Ok(())
+    }
+
     #[test]
     pub fn test_col_notnull() -> Result<()> {
         let sql = r#"CREATE TABLE t1 (a INTEGER NOT NULL);"#;
@@ -1046,6 +1317,8 @@ mod tests {
             root_page: 0,
             name: "t1".to_string(),
             has_rowid: true,
+            foreign_keys: vec![],
+            ephemeral: false,
             primary_key_column_names: vec!["nonexistent".to_string()],
             columns: vec![Column {
                 name: Some("a".to_string()),
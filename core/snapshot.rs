@@ -0,0 +1,128 @@
+//! Consistent file-level backups of a database that is being written to.
+//!
+//! A [Snapshot] is a read transaction kept open on a connection of its own. While it is held,
+//! checkpoints only backfill the frames it sees and the WAL isn't restarted. A copy of the
+//! database file with the first [Snapshot::wal_size] bytes of the WAL is then a consistent
+//! database, even if pages are backfilled while it is copied: every page a checkpoint can write
+//! has its latest version in that part of the WAL, which opening the copy replays.
+//!
+//! [Snapshot::checkpoint_into] makes the copy without the WAL instead: it writes every page of
+//! the snapshot to a new file, taking the version in the WAL where there is one, as a checkpoint
+//! would write them to the database file.
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::io::{Buffer, Completion, File, OpenFlags, SyncCompletion, WriteCompletion};
+use crate::storage::sqlite3_ondisk::{WAL_FRAME_HEADER_SIZE, WAL_HEADER_SIZE};
+use crate::{Connection, Database, LimboError, Result, IO};
+
+/// Number of pages [Snapshot::checkpoint_into] writes at once.
+const COPY_BATCH_PAGES: usize = 256;
+
+pub struct Snapshot {
+    conn: Rc<Connection>,
+    wal_frame: u64,
+    page_size: u64,
+}
+
+impl Snapshot {
+    pub(crate) fn new(db: &Arc<Database>) -> Result<Self> {
+        let conn = db.connect()?;
+        conn.begin_read_tx()?;
+        let wal_frame = conn.pager.wal_max_frame();
+        let page_size = conn.pager.db_header.lock().page_size as u64;
+        Ok(Self {
+            conn,
+            wal_frame,
+            page_size,
+        })
+    }
+
+    /// A connection reading the database as of the snapshot. Writing through it or ending its
+    /// transaction isn't supported.
+    pub fn connection(&self) -> &Rc<Connection> {
+        &self.conn
+    }
+
+    /// The last WAL frame of the snapshot, 0 if it is all in the database file.
+    pub fn wal_frame(&self) -> u64 {
+        self.wal_frame
+    }
+
+    /// Number of bytes at the start of the WAL file (the database path with `-wal` appended)
+    /// that belong to the snapshot.
+    pub fn wal_size(&self) -> u64 {
+        if self.wal_frame == 0 {
+            return 0;
+        }
+        WAL_HEADER_SIZE as u64 + self.wal_frame * (WAL_FRAME_HEADER_SIZE as u64 + self.page_size)
+    }
+
+    /// Writes the database as of the snapshot to a new database file at `path`, with the WAL
+    /// frames of the snapshot backfilled into it. The copy is complete without a WAL. Fails if
+    /// `path` exists and isn't empty.
+    pub fn checkpoint_into(&self, path: &str) -> Result<()> {
+        let pager = &self.conn.pager;
+        let io = &*self.conn._db.io;
+        // The size in the header of the snapshot, which may be behind the database's.
+        let page_count = {
+            let page = pager.read_page_sync(1)?;
+            let contents = page.get().contents.as_ref().unwrap();
+            contents.read_u32_no_offset(28) as usize
+        };
+        let file = io.open_file(path, OpenFlags::Create, false)?;
+        if file.size()? > 0 {
+            return Err(LimboError::InvalidArgument(format!(
+                "output file already exists: {path}"
+            )));
+        }
+        let page_size = self.page_size as usize;
+        let batch_size = COPY_BATCH_PAGES * page_size;
+        let mut batch = Vec::with_capacity(batch_size);
+        let mut batch_start = 0;
+        for page_idx in 1..=page_count {
+            let page = pager.read_page_sync(page_idx)?;
+            batch.extend_from_slice(&page.get().contents.as_ref().unwrap().as_slice());
+            if batch.len() == batch_size || page_idx == page_count {
+                let data = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
+                write_at(io, &file, batch_start, data)?;
+                batch_start = page_idx * page_size;
+            }
+        }
+        let done = Rc::new(Cell::new(false));
+        let c = {
+            let done = done.clone();
+            Completion::Sync(SyncCompletion::new(Box::new(move |_| done.set(true))))
+        };
+        file.sync(c)?;
+        wait(io, &done)
+    }
+}
+
+fn write_at(io: &dyn IO, file: &Arc<dyn File>, pos: usize, data: Vec<u8>) -> Result<()> {
+    let drop_fn = Rc::new(|_| {});
+    #[allow(clippy::arc_with_non_send_sync)]
+    let buf = Arc::new(RefCell::new(Buffer::new(std::pin::Pin::new(data), drop_fn)));
+    let done = Rc::new(Cell::new(false));
+    let c = {
+        let done = done.clone();
+        Completion::Write(WriteCompletion::new(Box::new(move |_| done.set(true))))
+    };
+    file.pwrite(pos, buf, c)?;
+    wait(io, &done)
+}
+
+fn wait(io: &dyn IO, done: &Cell<bool>) -> Result<()> {
+    while !done.get() {
+        io.run_once()?;
+    }
+    Ok(())
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let _ = self.conn.end_read_tx();
+    }
+}
@@ -15,11 +15,12 @@ use std::cell::{Cell, Ref, RefCell};
 use std::cmp::Ordering;
 use std::pin::Pin;
 use std::rc::Rc;
+use std::sync::Arc;
 
 use super::pager::PageRef;
 use super::sqlite3_ondisk::{
-    read_record, write_varint_to_vec, IndexInteriorCell, IndexLeafCell, OverflowCell,
-    DATABASE_HEADER_SIZE,
+    payload_overflows, read_record, write_varint_to_vec, IndexInteriorCell, IndexLeafCell,
+    OverflowCell, DATABASE_HEADER_SIZE,
 };
 
 /*
@@ -58,6 +59,10 @@ const PAGE_HEADER_OFFSET_RIGHTMOST_PTR: usize = 8;
 /// assumed that the database is corrupt.
 pub const BTCURSOR_MAX_DEPTH: usize = 20;
 
+/// Number of sibling pages a forward scan starts reading ahead of time when it descends
+/// into a child of an interior page, so that their I/O overlaps with processing the current one.
+const PREFETCH_CHILD_PAGES: usize = 4;
+
 /// Evaluate a Result<CursorResult<T>>, if IO return IO.
 macro_rules! return_if_io {
     ($expr:expr) => {
@@ -87,6 +92,7 @@ macro_rules! debug_validate_cells {
     };
 }
 /// Check if the page is unlocked, if not return IO. If the page is not locked but not loaded, then try to load it.
+/// Once loaded, the page is checked if the pager is in untrusted mode.
 macro_rules! return_if_locked_maybe_load {
     ($pager:expr, $expr:expr) => {{
         if $expr.is_locked() {
@@ -96,6 +102,7 @@ macro_rules! return_if_locked_maybe_load {
             $pager.load_page($expr.clone())?;
             return Ok(CursorResult::IO);
         }
+        check_untrusted_page(&$pager, &$expr)?;
     }};
 }
 
@@ -224,10 +231,10 @@ impl BTreeKey<'_> {
 
 #[derive(Clone)]
 struct BalanceInfo {
-    /// Old pages being balanced.
+    /// Old pages being balanced, pinned until the balance is done.
     pages_to_balance: Vec<PageRef>,
-    /// Bookkeeping of the rightmost pointer so the PAGE_HEADER_OFFSET_RIGHTMOST_PTR can be updated.
-    rightmost_pointer: *mut u8,
+    /// Position in the parent page of the pointer to the rightmost sibling, so it can be updated.
+    rightmost_pointer: usize,
     /// Divider cells of old pages
     divider_cells: Vec<Vec<u8>>,
     /// Number of siblings being used to balance
@@ -260,6 +267,8 @@ enum CursorState {
     Write(WriteInfo),
     Destroy(DestroyInfo),
     Delete(DeleteInfo),
+    /// The number of entries counted so far by [BTreeCursor::count].
+    Count(u64),
 }
 
 impl CursorState {
@@ -356,7 +365,12 @@ struct PageStack {
 }
 
 struct CellArray {
-    cells: Vec<&'static mut [u8]>, // TODO(pere): make this with references
+    /// Copies of the cells, as the pages they come from are rewritten while they are placed.
+    cells: Vec<Vec<u8>>,
+    /// Where each cell was before balancing, as the buffer of its page (see [page_buffer_id])
+    /// and the offset in it, so that a page can free the cells that move off it. Overflow and
+    /// divider cells weren't in a page.
+    cell_origins: Vec<Option<(usize, usize)>>,
 
     number_of_cells_per_page: Vec<u16>, // number of cells in each page
 }
@@ -395,6 +409,7 @@ impl BTreeCursor {
         }
         let page = self.pager.read_page(self.root_page)?;
         return_if_locked!(page);
+        check_untrusted_page(&self.pager, &page)?;
 
         let cell_count = page.get().contents.as_ref().unwrap().cell_count();
         Ok(CursorResult::Ok(cell_count == 0))
@@ -416,6 +431,8 @@ impl BTreeCursor {
                         break;
                     }
                     if self.stack.has_parent() {
+                        // Index interior cells are visited on the way up, after their right subtree.
+                        self.going_upwards = true;
                         self.stack.pop();
                     } else {
                         // moved to begin of btree
@@ -437,9 +454,25 @@ impl BTreeCursor {
                 self.pager.load_page(page.clone())?;
                 return Ok(CursorResult::IO);
             }
+            check_untrusted_page(&self.pager, &page)?;
             let contents = page.get().contents.as_ref().unwrap();
 
             let cell_count = contents.cell_count();
+            if cell_idx == i32::MAX as usize {
+                // We just descended into this page from the right of its parent's cell,
+                // so its rightmost subtree comes first.
+                if let Some(right_most_pointer) = contents.rightmost_pointer() {
+                    self.stack.set_cell_index(cell_count as i32 + 1);
+                    let mem_page = self.pager.read_page(right_most_pointer as usize)?;
+                    self.stack.push(mem_page);
+                    self.stack.set_cell_index(i32::MAX);
+                    continue;
+                }
+            }
+            if cell_count == 0 {
+                self.stack.set_cell_index(-1);
+                continue;
+            }
             let cell_idx = if cell_idx >= cell_count {
                 self.stack.set_cell_index(cell_count as i32 - 1);
                 cell_count - 1
@@ -472,18 +505,76 @@ impl BTreeCursor {
                     payload_size,
                 }) => {
                     if let Some(next_page) = first_overflow_page {
-                        return_if_io!(self.process_overflow_read(_payload, next_page, payload_size))
+                        return_if_io!(self.process_overflow_read(
+                            &_payload.as_slice(),
+                            next_page,
+                            payload_size
+                        ))
                     } else {
                         crate::storage::sqlite3_ondisk::read_record(
-                            _payload,
+                            &_payload.as_slice(),
                             self.get_immutable_record_or_create().as_mut().unwrap(),
                         )?
                     };
                     self.stack.retreat();
                     return Ok(CursorResult::Ok(Some(_rowid)));
                 }
-                BTreeCell::IndexInteriorCell(_) => todo!(),
-                BTreeCell::IndexLeafCell(_) => todo!(),
+                BTreeCell::IndexInteriorCell(IndexInteriorCell {
+                    payload,
+                    left_child_page,
+                    first_overflow_page,
+                    payload_size,
+                }) => {
+                    if !self.going_upwards {
+                        let mem_page = self.pager.read_page(left_child_page as usize)?;
+                        self.stack.push(mem_page);
+                        self.stack.set_cell_index(i32::MAX);
+                        continue;
+                    }
+                    if let Some(next_page) = first_overflow_page {
+                        return_if_io!(self.process_overflow_read(
+                            &payload.as_slice(),
+                            next_page,
+                            payload_size
+                        ))
+                    } else {
+                        crate::storage::sqlite3_ondisk::read_record(
+                            &payload.as_slice(),
+                            self.get_immutable_record_or_create().as_mut().unwrap(),
+                        )?
+                    };
+                    // The cell stays current so that its left subtree is visited next.
+                    self.going_upwards = false;
+                    let rowid = match self.get_immutable_record().as_ref().unwrap().last_value() {
+                        Some(RefValue::Integer(rowid)) => *rowid as u64,
+                        _ => unreachable!("index cells should have an integer rowid"),
+                    };
+                    return Ok(CursorResult::Ok(Some(rowid)));
+                }
+                BTreeCell::IndexLeafCell(IndexLeafCell {
+                    payload,
+                    first_overflow_page,
+                    payload_size,
+                }) => {
+                    if let Some(next_page) = first_overflow_page {
+                        return_if_io!(self.process_overflow_read(
+                            &payload.as_slice(),
+                            next_page,
+                            payload_size
+                        ))
+                    } else {
+                        crate::storage::sqlite3_ondisk::read_record(
+                            &payload.as_slice(),
+                            self.get_immutable_record_or_create().as_mut().unwrap(),
+                        )?
+                    };
+                    self.stack.retreat();
+                    let rowid = match self.get_immutable_record().as_ref().unwrap().last_value() {
+                        Some(RefValue::Integer(rowid)) => *rowid as u64,
+                        _ => unreachable!("index cells should have an integer rowid"),
+                    };
+                    return Ok(CursorResult::Ok(Some(rowid)));
+                }
             }
         }
     }
@@ -492,13 +583,30 @@ impl BTreeCursor {
     /// that calls this function should be reentrant.
     fn process_overflow_read(
         &mut self,
-        payload: &'static [u8],
+        payload: &[u8],
         start_next_page: u32,
         payload_size: u64,
     ) -> Result<CursorResult<()>> {
         let res = match &mut self.state {
             CursorState::None => {
                 tracing::debug!("start reading overflow page payload_size={}", payload_size);
+                if (payload_size as usize) < payload.len() {
+                    return_corrupt!(format!(
+                        "overflowing cell payload size {} is smaller than its local part",
+                        payload_size
+                    ));
+                }
+                // Every overflow page holds part of the payload, so a payload that would need
+                // more overflow pages than the database has can't be right.
+                let overflow_pages =
+                    (payload_size as usize - payload.len()).div_ceil(self.pager.usable_space() - 4);
+                if overflow_pages > self.pager.db_header.lock().database_size as usize {
+                    return_corrupt!(format!(
+                        "payload size {} is larger than the database",
+                        payload_size
+                    ));
+                }
+                Self::check_overflow_page_number(&self.pager, start_next_page)?;
                 let page = self.pager.read_page(start_next_page as usize)?;
                 self.state = CursorState::Read(ReadPayloadOverflow::ProcessPage {
                     payload: payload.to_vec(),
@@ -521,20 +629,22 @@ impl BTreeCursor {
                 let contents = page.get_contents();
                 // The first four bytes of each overflow page are a big-endian integer which is the page number of the next page in the chain, or zero for the final page in the chain.
                 let next = contents.read_u32_no_offset(0);
-                let buf = contents.as_ptr();
                 let usable_space = self.pager.usable_space();
                 let to_read = (*remaining_to_read).min(usable_space - 4);
-                payload.extend_from_slice(&buf[4..4 + to_read]);
+                payload.extend_from_slice(&contents.as_slice()[4..4 + to_read]);
                 *remaining_to_read -= to_read;
                 if *remaining_to_read == 0 || next == 0 {
-                    assert!(
-                        *remaining_to_read == 0 && next == 0,
-                        "we can't have more pages to read while also have read everything"
-                    );
+                    if *remaining_to_read != 0 || next != 0 {
+                        return_corrupt!(format!(
+                            "overflow chain of page {} does not match the payload size",
+                            next_page
+                        ));
+                    }
                     let mut payload_swap = Vec::new();
                     std::mem::swap(payload, &mut payload_swap);
                     CursorResult::Ok(payload_swap)
                 } else {
+                    Self::check_overflow_page_number(&self.pager, next)?;
                     let new_page = self.pager.read_page(next as usize)?;
                     *page = new_page;
                     *next_page = next;
@@ -559,6 +669,19 @@ impl BTreeCursor {
         }
     }
 
+    /// Overflow pointers come straight from the page, so make sure they point inside the file
+    /// before following them.
+    fn check_overflow_page_number(pager: &Pager, page_number: u32) -> Result<()> {
+        let database_size = pager.db_header.lock().database_size;
+        if page_number < 2 || page_number > database_size {
+            return_corrupt!(format!(
+                "overflow page {} out of range 2..={}",
+                page_number, database_size
+            ));
+        }
+        Ok(())
+    }
+
     /// Move the cursor to the next record and return it.
     /// Used in forwards iteration, which is the default.
     fn get_next_record(
@@ -595,6 +718,22 @@ impl BTreeCursor {
 
             let contents = mem_page.contents.as_ref().unwrap();
 
+            // A page is checked before anything is read from it. Index interior pages are
+            // checked again when coming back up from their first child, which is harmless.
+            if cell_idx == 0 && self.pager.salvage_mode() {
+                let database_size = self.pager.db_header.lock().database_size as usize;
+                if let Err(reason) = check_page(contents, self.usable_space(), database_size) {
+                    self.pager.quarantine_page(mem_page.id, reason);
+                    if !self.stack.has_parent() {
+                        return Ok(CursorResult::Ok(None));
+                    }
+                    self.going_upwards = true;
+                    self.stack.pop();
+                    continue;
+                }
+            }
+            check_untrusted_page(&self.pager, &mem_page_rc)?;
+
             if cell_idx == contents.cell_count() {
                 // do rightmost
                 let has_parent = self.stack.has_parent();
@@ -645,7 +784,10 @@ impl BTreeCursor {
                 }) => {
                     assert!(predicate.is_none());
                     self.stack.advance();
+                    // Reading the child may evict this page, so the siblings are looked up first.
+                    let siblings = self.children_to_prefetch(contents, cell_idx + 1)?;
                     let mem_page = self.pager.read_page(*_left_child_page as usize)?;
+                    self.prefetch_pages(siblings)?;
                     self.stack.push(mem_page);
                     continue;
                 }
@@ -658,13 +800,13 @@ impl BTreeCursor {
                     assert!(predicate.is_none());
                     if let Some(next_page) = first_overflow_page {
                         return_if_io!(self.process_overflow_read(
-                            _payload,
+                            &_payload.as_slice(),
                             *next_page,
                             *payload_size
                         ))
                     } else {
                         crate::storage::sqlite3_ondisk::read_record(
-                            _payload,
+                            &_payload.as_slice(),
                             self.get_immutable_record_or_create().as_mut().unwrap(),
                         )?
                     };
@@ -678,19 +820,22 @@ impl BTreeCursor {
                     payload_size,
                 }) => {
                     if !self.going_upwards {
+                        // Reading the child may evict this page, so the siblings are looked up first.
+                        let siblings = self.children_to_prefetch(contents, cell_idx + 1)?;
                         let mem_page = self.pager.read_page(*left_child_page as usize)?;
+                        self.prefetch_pages(siblings)?;
                         self.stack.push(mem_page);
                         continue;
                     }
                     if let Some(next_page) = first_overflow_page {
                         return_if_io!(self.process_overflow_read(
-                            payload,
+                            &payload.as_slice(),
                             *next_page,
                             *payload_size
                         ))
                     } else {
                         crate::storage::sqlite3_ondisk::read_record(
-                            payload,
+                            &payload.as_slice(),
                             self.get_immutable_record_or_create().as_mut().unwrap(),
                         )?
                     };
@@ -718,6 +863,9 @@ impl BTreeCursor {
                         SeekOp::GT => order.is_gt(),
                         SeekOp::GE => order.is_ge(),
                         SeekOp::EQ => order.is_eq(),
+                        SeekOp::LE | SeekOp::LT => {
+                            unreachable!("backwards seeks do not iterate forwards")
+                        }
                     };
                     if found {
                         let rowid = match self.get_immutable_record().as_ref().unwrap().last_value()
@@ -737,13 +885,13 @@ impl BTreeCursor {
                 }) => {
                     if let Some(next_page) = first_overflow_page {
                         return_if_io!(self.process_overflow_read(
-                            payload,
+                            &payload.as_slice(),
                             *next_page,
                             *payload_size
                         ))
                     } else {
                         crate::storage::sqlite3_ondisk::read_record(
-                            payload,
+                            &payload.as_slice(),
                             self.get_immutable_record_or_create().as_mut().unwrap(),
                         )?
                     };
@@ -769,6 +917,9 @@ impl BTreeCursor {
                         SeekOp::GT => order.is_lt(),
                         SeekOp::GE => order.is_le(),
                         SeekOp::EQ => order.is_le(),
+                        SeekOp::LE | SeekOp::LT => {
+                            unreachable!("backwards seeks do not iterate forwards")
+                        }
                     };
                     if found {
                         let rowid = match self.get_immutable_record().as_ref().unwrap().last_value()
@@ -792,9 +943,14 @@ impl BTreeCursor {
     fn do_seek(&mut self, key: SeekKey<'_>, op: SeekOp) -> Result<CursorResult<Option<u64>>> {
         return_if_io!(self.move_to(key.clone(), op.clone()));
 
+        if op.is_backwards() {
+            return self.do_seek_backwards(key, op);
+        }
+
         {
             let page = self.stack.top();
             return_if_locked!(page);
+            check_untrusted_page(&self.pager, &page)?;
 
             let contents = page.get().contents.as_ref().unwrap();
 
@@ -825,17 +981,20 @@ impl BTreeCursor {
                             SeekOp::GT => *cell_rowid > rowid_key,
                             SeekOp::GE => *cell_rowid >= rowid_key,
                             SeekOp::EQ => *cell_rowid == rowid_key,
+                            SeekOp::LE | SeekOp::LT => {
+                                unreachable!("handled by do_seek_backwards()")
+                            }
                         };
                         if found {
                             if let Some(next_page) = first_overflow_page {
                                 return_if_io!(self.process_overflow_read(
-                                    payload,
+                                    &payload.as_slice(),
                                     *next_page,
                                     *payload_size
                                 ))
                             } else {
                                 crate::storage::sqlite3_ondisk::read_record(
-                                    payload,
+                                    &payload.as_slice(),
                                     self.get_immutable_record_or_create().as_mut().unwrap(),
                                 )?
                             };
@@ -855,13 +1014,13 @@ impl BTreeCursor {
                         };
                         if let Some(next_page) = first_overflow_page {
                             return_if_io!(self.process_overflow_read(
-                                payload,
+                                &payload.as_slice(),
                                 *next_page,
                                 *payload_size
                             ))
                         } else {
                             crate::storage::sqlite3_ondisk::read_record(
-                                payload,
+                                &payload.as_slice(),
                                 self.get_immutable_record_or_create().as_mut().unwrap(),
                             )?
                         };
@@ -873,6 +1032,9 @@ impl BTreeCursor {
                             SeekOp::GT => order.is_gt(),
                             SeekOp::GE => order.is_ge(),
                             SeekOp::EQ => order.is_eq(),
+                            SeekOp::LE | SeekOp::LT => {
+                                unreachable!("handled by do_seek_backwards()")
+                            }
                         };
                         self.stack.advance();
                         if found {
@@ -913,6 +1075,248 @@ impl BTreeCursor {
         Ok(CursorResult::Ok(None))
     }
 
+    /// Second half of do_seek() for SeekOp::LT and SeekOp::LE: the cursor has already been moved to the leaf page
+    /// that may contain the key, and we look for the last record in it that satisfies the comparison.
+    /// The record itself is read by get_prev_record(), which also takes care of walking back up the tree
+    /// (e.g. to an index interior cell, or to the previous leaf) when no cell in this leaf matches.
+    fn do_seek_backwards(
+        &mut self,
+        key: SeekKey<'_>,
+        op: SeekOp,
+    ) -> Result<CursorResult<Option<u64>>> {
+        let page = self.stack.top();
+        return_if_locked!(page);
+        check_untrusted_page(&self.pager, &page)?;
+
+        let contents = page.get().contents.as_ref().unwrap();
+        let mut cell_idx = contents.cell_count();
+        while cell_idx > 0 {
+            let cell = contents.cell_get(
+                cell_idx - 1,
+                payload_overflow_threshold_max(contents.page_type(), self.usable_space() as u16),
+                payload_overflow_threshold_min(contents.page_type(), self.usable_space() as u16),
+                self.usable_space(),
+            )?;
+            let found = match &cell {
+                BTreeCell::TableLeafCell(TableLeafCell {
+                    _rowid: cell_rowid, ..
+                }) => {
+                    let SeekKey::TableRowId(rowid_key) = key else {
+                        unreachable!("table seek key should be a rowid");
+                    };
+                    match op {
+                        SeekOp::LT => *cell_rowid < rowid_key,
+                        _ => *cell_rowid <= rowid_key,
+                    }
+                }
+                BTreeCell::IndexLeafCell(IndexLeafCell {
+                    payload,
+                    first_overflow_page,
+                    payload_size,
+                }) => {
+                    let SeekKey::IndexKey(index_key) = key else {
+                        unreachable!("index seek key should be a record");
+                    };
+                    if let Some(next_page) = first_overflow_page {
+                        return_if_io!(self.process_overflow_read(
+                            &payload.as_slice(),
+                            *next_page,
+                            *payload_size
+                        ))
+                    } else {
+                        crate::storage::sqlite3_ondisk::read_record(
+                            &payload.as_slice(),
+                            self.get_immutable_record_or_create().as_mut().unwrap(),
+                        )?
+                    };
+                    let record = self.get_immutable_record();
+                    let values = record.as_ref().unwrap().get_values();
+                    let prefix_len = index_key.len().min(values.len());
+                    let order = compare_immutable(&values[..prefix_len], index_key.get_values());
+                    match op {
+                        SeekOp::LT => order.is_lt(),
+                        _ => order.is_le(),
+                    }
+                }
+                cell_type => {
+                    unreachable!("unexpected cell type: {:?}", cell_type);
+                }
+            };
+            if found {
+                break;
+            }
+            cell_idx -= 1;
+        }
+
+        // Point the cursor at the matching cell (or before the first cell if none matched)
+        // and let get_prev_record() read it and retreat past it.
+        self.stack.set_cell_index(cell_idx as i32 - 1);
+        self.going_upwards = false;
+        self.get_prev_record()
+    }
+
+    /// Checks whether the page stack still holds the path that a descent from the root would take to
+    /// the leaf page on top of it, i.e. every parent points to the next page through the cell its saved
+    /// index refers to, following move_to()'s convention of advancing past table interior cells but not
+    /// index interior cells. Balancing relies on these indices, so the stack can only be reused when they match.
+    /// Returns whether the leaf is the rightmost one in the b-tree, or None if the stack can't be reused.
+    fn reusable_leaf_path(&self) -> Result<Option<bool>> {
+        if self.stack.is_empty() || self.stack.page_at(0).get().id != self.root_page {
+            return Ok(None);
+        }
+        let depth = self.stack.current();
+        let mut is_rightmost = true;
+        for level in 0..=depth {
+            let page = self.stack.page_at(level);
+            if page.is_locked() || !page.is_loaded() {
+                return Ok(None);
+            }
+            let contents = page.get_contents();
+            if level == depth {
+                return Ok(contents.is_leaf().then_some(is_rightmost));
+            }
+            let cell_count = contents.cell_count() as i32;
+            let cell_idx = self.stack.cell_index_at(level);
+            let child = if cell_idx == cell_count + 1 {
+                contents.rightmost_pointer()
+            } else {
+                is_rightmost = false;
+                let cell_idx = match contents.page_type() {
+                    PageType::TableInterior => cell_idx - 1,
+                    PageType::IndexInterior => cell_idx,
+                    _ => return Ok(None),
+                };
+                if cell_idx < 0 || cell_idx >= cell_count {
+                    return Ok(None);
+                }
+                match contents.cell_get(
+                    cell_idx as usize,
+                    payload_overflow_threshold_max(
+                        contents.page_type(),
+                        self.usable_space() as u16,
+                    ),
+                    payload_overflow_threshold_min(
+                        contents.page_type(),
+                        self.usable_space() as u16,
+                    ),
+                    self.usable_space(),
+                )? {
+                    BTreeCell::TableInteriorCell(cell) => Some(cell._left_child_page),
+                    BTreeCell::IndexInteriorCell(cell) => Some(cell.left_child_page),
+                    _ => None,
+                }
+            };
+            if child != Some(self.stack.page_at(level + 1).get().id as u32) {
+                return Ok(None);
+            }
+        }
+        unreachable!("the loop returns when it reaches the top of the stack")
+    }
+
+    /// Whether the leaf page on top of the stack is the one move_to() would descend to for an EQ or GE seek
+    /// of `key`, similar to the shortcuts sqlite3BtreeTableMoveto() takes for a cursor that is at the last row
+    /// or just before the key. This is the case when the key falls between the first and last cells of the leaf,
+    /// or after the last cell of the rightmost leaf.
+    fn leaf_covers_key(&self, key: &SeekKey<'_>) -> Result<bool> {
+        let Some(is_rightmost) = self.reusable_leaf_path()? else {
+            return Ok(false);
+        };
+        let page = self.stack.top();
+        let contents = page.get_contents();
+        let cell_count = contents.cell_count();
+        if cell_count == 0 {
+            return Ok(false);
+        }
+        let first = self.compare_key_with_leaf_cell(contents, 0, key)?;
+        let last = self.compare_key_with_leaf_cell(contents, cell_count - 1, key)?;
+        // Index keys can be equal to entries at the end of the previous leaf, which move_to() would descend to.
+        let after_first = |first: Ordering| match key {
+            SeekKey::TableRowId(_) => first.is_ge(),
+            SeekKey::IndexKey(_) => first.is_gt(),
+        };
+        Ok(match (first, last) {
+            (Some(first), Some(last)) => after_first(first) && (last.is_le() || is_rightmost),
+            _ => false,
+        })
+    }
+
+    /// Compares a seek key with the key of a leaf cell, the same way move_to() compares it with interior cells.
+    /// Returns None for index cells that spill to overflow pages, which would need I/O to be read.
+    fn compare_key_with_leaf_cell(
+        &self,
+        contents: &PageContent,
+        cell_idx: usize,
+        key: &SeekKey<'_>,
+    ) -> Result<Option<Ordering>> {
+        let cell = contents.cell_get(
+            cell_idx,
+            payload_overflow_threshold_max(contents.page_type(), self.usable_space() as u16),
+            payload_overflow_threshold_min(contents.page_type(), self.usable_space() as u16),
+            self.usable_space(),
+        )?;
+        match (cell, key) {
+            (BTreeCell::TableLeafCell(cell), SeekKey::TableRowId(rowid)) => {
+                Ok(Some(rowid.cmp(&cell._rowid)))
+            }
+            (
+                BTreeCell::IndexLeafCell(IndexLeafCell {
+                    payload,
+                    first_overflow_page: None,
+                    ..
+                }),
+                SeekKey::IndexKey(index_key),
+            ) => {
+                read_record(
+                    &payload.as_slice(),
+                    self.get_immutable_record_or_create().as_mut().unwrap(),
+                )?;
+                Ok(Some(compare_immutable(
+                    index_key.get_values(),
+                    self.get_immutable_record().as_ref().unwrap().get_values(),
+                )))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The child pages of an interior page that a forward scan visits after the child it is
+    /// descending into, beginning with the left child of cell `first_cell_idx` and ending with
+    /// the rightmost pointer.
+    fn children_to_prefetch(
+        &self,
+        contents: &PageContent,
+        first_cell_idx: usize,
+    ) -> Result<Vec<u32>> {
+        let last_cell_idx = contents
+            .cell_count()
+            .min(first_cell_idx + PREFETCH_CHILD_PAGES);
+        let mut children = Vec::with_capacity(PREFETCH_CHILD_PAGES);
+        for cell_idx in first_cell_idx..last_cell_idx {
+            match contents.cell_get(
+                cell_idx,
+                payload_overflow_threshold_max(contents.page_type(), self.usable_space() as u16),
+                payload_overflow_threshold_min(contents.page_type(), self.usable_space() as u16),
+                self.usable_space(),
+            )? {
+                BTreeCell::TableInteriorCell(cell) => children.push(cell._left_child_page),
+                BTreeCell::IndexInteriorCell(cell) => children.push(cell.left_child_page),
+                _ => return Ok(Vec::new()),
+            }
+        }
+        if children.len() < PREFETCH_CHILD_PAGES {
+            children.extend(contents.rightmost_pointer());
+        }
+        Ok(children)
+    }
+
+    /// Starts reading `pages` ahead of time, see [Self::children_to_prefetch].
+    fn prefetch_pages(&self, pages: Vec<u32>) -> Result<()> {
+        for page in pages {
+            self.pager.prefetch_page(page as usize)?;
+        }
+        Ok(())
+    }
+
     /// Move the cursor to the root page of the btree.
     fn move_to_root(&mut self) {
         tracing::trace!("move_to_root({})", self.root_page);
@@ -923,6 +1327,14 @@ impl BTreeCursor {
 
     /// Move the cursor to the rightmost record in the btree.
     fn move_to_rightmost(&mut self) -> Result<CursorResult<()>> {
+        // Appending rows keeps the cursor on the rightmost leaf, so there is no need to go back to the root.
+        if self.reusable_leaf_path()? == Some(true) {
+            let cell_count = self.stack.top().get_contents().cell_count();
+            if cell_count > 0 {
+                self.stack.set_cell_index(cell_count as i32 - 1);
+                return Ok(CursorResult::Ok(()));
+            }
+        }
         self.move_to_root();
 
         loop {
@@ -930,6 +1342,7 @@ impl BTreeCursor {
             let page_idx = mem_page.get().id;
             let page = self.pager.read_page(page_idx)?;
             return_if_locked!(page);
+            check_untrusted_page(&self.pager, &page)?;
             let contents = page.get().contents.as_ref().unwrap();
             if contents.is_leaf() {
                 if contents.cell_count() > 0 {
@@ -979,11 +1392,19 @@ impl BTreeCursor {
         // 5. We scan the leaf cells in the leaf page until we find the cell whose rowid is equal to the rowid we are looking for.
         //    This cell contains the actual data we are looking for.
         // 6. If we find the cell, we return the record. Otherwise, we return an empty result.
+        //
+        // Sorted inserts and near-sequential seeks usually land on the leaf page the cursor is already on,
+        // in which case we skip the descent altogether.
+        if matches!(cmp, SeekOp::EQ | SeekOp::GE) && self.leaf_covers_key(&key)? {
+            self.stack.set_cell_index(0);
+            return Ok(CursorResult::Ok(()));
+        }
         self.move_to_root();
 
         loop {
             let page = self.stack.top();
             return_if_locked!(page);
+            check_untrusted_page(&self.pager, &page)?;
 
             let contents = page.get().contents.as_ref().unwrap();
             if contents.is_leaf() {
@@ -1015,8 +1436,15 @@ impl BTreeCursor {
                             SeekOp::GT => rowid_key < *_rowid,
                             SeekOp::GE => rowid_key <= *_rowid,
                             SeekOp::EQ => rowid_key <= *_rowid,
+                            // The left subtree holds rowids <= the divider, so the last rowid
+                            // below or at the key can only be further right when key > divider.
+                            SeekOp::LE | SeekOp::LT => rowid_key <= *_rowid,
                         };
-                        self.stack.advance();
+                        // When iterating backwards, the cell index of an interior page points at the
+                        // cell whose left subtree we are in (see get_prev_record()).
+                        if !(target_leaf_page_is_in_left_subtree && cmp.is_backwards()) {
+                            self.stack.advance();
+                        }
                         if target_leaf_page_is_in_left_subtree {
                             let mem_page = self.pager.read_page(*_left_child_page as usize)?;
                             self.stack.push(mem_page);
@@ -1045,13 +1473,13 @@ impl BTreeCursor {
                         };
                         if let Some(next_page) = first_overflow_page {
                             return_if_io!(self.process_overflow_read(
-                                payload,
+                                &payload.as_slice(),
                                 *next_page,
                                 *payload_size
                             ))
                         } else {
                             crate::storage::sqlite3_ondisk::read_record(
-                                payload,
+                                &payload.as_slice(),
                                 self.get_immutable_record_or_create().as_mut().unwrap(),
                             )?
                         };
@@ -1063,6 +1491,22 @@ impl BTreeCursor {
                             SeekOp::GT => order.is_lt(),
                             SeekOp::GE => order.is_le(),
                             SeekOp::EQ => order.is_le(),
+                            SeekOp::LE | SeekOp::LT => {
+                                // Backwards seeks compare only the columns present in the key,
+                                // so that every record equal to the key prefix is kept on the left side for LT
+                                // and on the right side for LE.
+                                let record = self.get_immutable_record();
+                                let values = record.as_ref().unwrap().get_values();
+                                let prefix_len = index_key.len().min(values.len());
+                                let order = compare_immutable(
+                                    index_key.get_values(),
+                                    &values[..prefix_len],
+                                );
+                                match cmp {
+                                    SeekOp::LT => order.is_le(),
+                                    _ => order.is_lt(),
+                                }
+                            }
                         };
                         if target_leaf_page_is_in_the_left_subtree {
                             // we don't advance in case of index tree internal nodes because we will visit this node going up
@@ -1126,7 +1570,7 @@ impl BTreeCursor {
                         return_if_locked!(page);
 
                         page.set_dirty();
-                        self.pager.add_dirty(page.get().id);
+                        self.pager.add_dirty(&page);
 
                         let page = page.get().contents.as_mut().unwrap();
                         assert!(matches!(
@@ -1161,7 +1605,7 @@ impl BTreeCursor {
                         }
                       BTreeCell::IndexLeafCell(idx_leaf) => {
                         read_record(
-                            idx_leaf.payload,
+                            &idx_leaf.payload.as_slice(),
                             self.get_immutable_record_or_create().as_mut().unwrap(),
                         )
                         .expect("failed to read record");
@@ -1264,12 +1708,28 @@ impl BTreeCursor {
                     let current_page = self.stack.top();
                     {
                         // check if we don't need to balance
-                        // don't continue if there are no overflow cells
+                        // don't continue if there are no overflow cells and the page is at least
+                        // a third full, like SQLite does.
                         let page = current_page.get().contents.as_mut().unwrap();
+                        let underfull =
+                            compute_free_space(page, self.usable_space() as u16)? as usize * 3
+                                > self.usable_space() * 2;
                         if page.overflow_cells.is_empty() {
-                            let write_info = self.state.mut_write_info().unwrap();
-                            write_info.state = WriteState::Finish;
-                            return Ok(CursorResult::Ok(()));
+                            if !self.stack.has_parent() {
+                                // An interior root that was left with a single child after
+                                // merging takes over the content of that child.
+                                if page.cell_count() == 0 && !page.is_leaf() {
+                                    return_if_io!(self.balance_shallower());
+                                }
+                                let write_info = self.state.mut_write_info().unwrap();
+                                write_info.state = WriteState::Finish;
+                                return Ok(CursorResult::Ok(()));
+                            }
+                            if !underfull {
+                                let write_info = self.state.mut_write_info().unwrap();
+                                write_info.state = WriteState::Finish;
+                                return Ok(CursorResult::Ok(()));
+                            }
                         }
                     }
 
@@ -1280,7 +1740,15 @@ impl BTreeCursor {
                     let write_info = self.state.mut_write_info().unwrap();
                     write_info.state = WriteState::BalanceNonRoot;
                     self.stack.pop();
-                    self.stack.retreat();
+                    // Moving down an index interior page doesn't advance past the cell whose
+                    // left child was taken, only past the last cell for the rightmost child.
+                    let parent = self.stack.top();
+                    let parent_contents = parent.get_contents();
+                    if parent_contents.page_type() == PageType::TableInterior
+                        || self.stack.current_cell_index() > parent_contents.cell_count() as i32
+                    {
+                        self.stack.retreat();
+                    }
                     return_if_io!(self.balance_non_root());
                 }
                 WriteState::BalanceNonRoot | WriteState::BalanceNonRootWaitLoadPages => {
@@ -1310,7 +1778,7 @@ impl BTreeCursor {
                 }
                 return_if_locked_maybe_load!(self.pager, parent_page);
                 parent_page.set_dirty();
-                self.pager.add_dirty(parent_page.get().id);
+                self.pager.add_dirty(&parent_page);
                 let parent_contents = parent_page.get().contents.as_ref().unwrap();
                 let page_to_balance_idx = self.stack.current_cell_index() as usize;
 
@@ -1363,7 +1831,8 @@ impl BTreeCursor {
                     == parent_contents.cell_count();
                 // Get the right page pointer that we will need to update later
                 let right_pointer = if last_sibling_is_right_pointer {
-                    parent_contents.rightmost_pointer_raw().unwrap()
+                    assert!(!parent_contents.is_leaf());
+                    parent_contents.offset + PAGE_HEADER_OFFSET_RIGHTMOST_PTR
                 } else {
                     let (start_of_cell, _) = parent_contents.cell_get_raw_region(
                         first_cell_divider + sibling_pointer,
@@ -1376,18 +1845,18 @@ impl BTreeCursor {
                             self.usable_space() as u16,
                         ),
                         self.usable_space(),
-                    );
-                    let buf = parent_contents.as_ptr().as_mut_ptr();
-                    unsafe { buf.add(start_of_cell) }
+                    )?;
+                    start_of_cell
                 };
 
                 // load sibling pages
                 // start loading right page first
-                let mut pgno: u32 = unsafe { right_pointer.cast::<u32>().read().swap_bytes() };
+                let mut pgno: u32 = parent_contents.read_u32_no_offset(right_pointer);
                 let current_sibling = sibling_pointer;
                 for i in (0..=current_sibling).rev() {
                     let page = self.pager.read_page(pgno as usize)?;
-                    debug_validate_cells!(&page.get_contents(), self.usable_space() as u16);
+                    // Keep the siblings in the cache while the others load.
+                    page.pin();
                     pages_to_balance.push(page);
                     assert_eq!(
                         parent_contents.overflow_cells.len(),
@@ -1424,15 +1893,6 @@ impl BTreeCursor {
                 // Reverse in order to keep the right order
                 pages_to_balance.reverse();
 
-                #[cfg(debug_assertions)]
-                {
-                    let page_type_of_siblings = pages_to_balance[0].get_contents().page_type();
-                    for page in &pages_to_balance {
-                        let contents = page.get_contents();
-                        debug_validate_cells!(&contents, self.usable_space() as u16);
-                        assert_eq!(contents.page_type(), page_type_of_siblings);
-                    }
-                }
                 self.state
                     .write_info()
                     .unwrap()
@@ -1460,6 +1920,16 @@ impl BTreeCursor {
                 if !all_loaded {
                     return Ok(CursorResult::IO);
                 }
+                #[cfg(debug_assertions)]
+                {
+                    let page_type_of_siblings =
+                        balance_info.pages_to_balance[0].get_contents().page_type();
+                    for page in &balance_info.pages_to_balance {
+                        let contents = page.get_contents();
+                        debug_validate_cells!(&contents, self.usable_space() as u16);
+                        assert_eq!(contents.page_type(), page_type_of_siblings);
+                    }
+                }
                 // Now do real balancing
                 let parent_page = self.stack.top();
                 let parent_contents = parent_page.get_contents();
@@ -1475,7 +1945,7 @@ impl BTreeCursor {
                     let sibling_page = &balance_info.pages_to_balance[i];
                     let sibling_contents = sibling_page.get_contents();
                     sibling_page.set_dirty();
-                    self.pager.add_dirty(sibling_page.get().id);
+                    self.pager.add_dirty(sibling_page);
                     max_cells += sibling_contents.cell_count();
                     max_cells += sibling_contents.overflow_cells.len();
                     if i == 0 {
@@ -1495,13 +1965,13 @@ impl BTreeCursor {
                             self.usable_space() as u16,
                         ),
                         self.usable_space(),
-                    );
-                    let buf = parent_contents.as_ptr();
-                    let cell_buf = &buf[cell_start..cell_start + cell_len];
+                    )?;
                     max_cells += 1;
 
                     // TODO(pere): make this reference and not copy
-                    balance_info.divider_cells.push(cell_buf.to_vec());
+                    balance_info.divider_cells.push(
+                        parent_contents.as_slice()[cell_start..cell_start + cell_len].to_vec(),
+                    );
                     tracing::trace!(
                         "dropping divider cell from parent cell_idx={} count={}",
                         cell_idx,
@@ -1519,6 +1989,7 @@ impl BTreeCursor {
 
                 let mut cell_array = CellArray {
                     cells: Vec::with_capacity(max_cells),
+                    cell_origins: Vec::with_capacity(max_cells),
                     number_of_cells_per_page: Vec::new(),
                 };
                 let cells_capacity_start = cell_array.cells.capacity();
@@ -1545,11 +2016,14 @@ impl BTreeCursor {
                                 self.usable_space() as u16,
                             ),
                             self.usable_space(),
-                        );
-                        let buf = old_page_contents.as_ptr();
-                        let cell_buf = &mut buf[cell_start..cell_start + cell_len];
-                        // TODO(pere): make this reference and not copy
-                        cell_array.cells.push(to_static_buf(cell_buf));
+                        )?;
+                        let buf = old_page_contents.as_slice();
+                        cell_array
+                            .cells
+                            .push(buf[cell_start..cell_start + cell_len].to_vec());
+                        cell_array
+                            .cell_origins
+                            .push(Some((page_buffer_id(old_page_contents), cell_start)));
                     }
                     // Insert overflow cells into correct place
                     let offset = total_cells_inserted;
@@ -1558,10 +2032,12 @@ impl BTreeCursor {
                         "todo: check this works for more than one overflow cell"
                     );
                     for overflow_cell in old_page_contents.overflow_cells.iter_mut() {
-                        cell_array.cells.insert(
-                            offset + overflow_cell.index,
-                            to_static_buf(&mut Pin::as_mut(&mut overflow_cell.payload)),
-                        );
+                        cell_array
+                            .cells
+                            .insert(offset + overflow_cell.index, overflow_cell.payload.to_vec());
+                        cell_array
+                            .cell_origins
+                            .insert(offset + overflow_cell.index, None);
                     }
 
                     count_cells_in_old_pages.push(cell_array.cells.len() as u16);
@@ -1573,10 +2049,20 @@ impl BTreeCursor {
                         // If we are a index page or a interior table page we need to take the divider cell too.
                         // But we don't need the last divider as it will remain the same.
                         let divider_cell = &mut balance_info.divider_cells[i];
-                        // TODO(pere): in case of old pages are leaf pages, so index leaf page, we need to strip page pointers
-                        // from divider cells in index interior pages (parent) because those should not be included.
+                        // Divider cells of index interior pages start with a left child pointer
+                        // that an index leaf cell doesn't have.
+                        let divider_cell = if leaf {
+                            &mut divider_cell[4..]
+                        } else {
+                            // The divider moves down next to the cells of this page, so its left
+                            // child becomes the subtree this page had as its rightmost pointer.
+                            let right_pointer = old_page_contents.rightmost_pointer().unwrap();
+                            divider_cell[0..4].copy_from_slice(&right_pointer.to_be_bytes());
+                            divider_cell.as_mut_slice()
+                        };
                         cells_inserted += 1;
-                        cell_array.cells.push(to_static_buf(divider_cell.as_mut()));
+                        cell_array.cells.push(divider_cell.to_vec());
+                        cell_array.cell_origins.push(None);
                     }
                     total_cells_inserted += cells_inserted;
                 }
@@ -1608,13 +2094,13 @@ impl BTreeCursor {
                         .push(count_cells_in_old_pages[i]);
                     let page = &balance_info.pages_to_balance[i];
                     let page_contents = page.get_contents();
-                    let free_space = compute_free_space(page_contents, self.usable_space() as u16);
+                    let free_space = compute_free_space(page_contents, self.usable_space() as u16)?;
 
-                    new_page_sizes.push(usable_space as u16 - free_space);
+                    new_page_sizes.push(usable_space as i64 - free_space as i64);
                     for overflow in &page_contents.overflow_cells {
                         let size = new_page_sizes.last_mut().unwrap();
                         // 2 to account of pointer
-                        *size += 2 + overflow.payload.len() as u16;
+                        *size += 2 + overflow.payload.len() as i64;
                     }
                 }
 
@@ -1623,7 +2109,7 @@ impl BTreeCursor {
                 let mut i = 0;
                 while i < sibling_count_new {
                     // First try to move cells to the right if they do not fit
-                    while new_page_sizes[i] > usable_space as u16 {
+                    while new_page_sizes[i] > usable_space as i64 {
                         let needs_new_page = i + 1 >= sibling_count_new;
                         if needs_new_page {
                             sibling_count_new += 1;
@@ -1637,7 +2123,7 @@ impl BTreeCursor {
                             );
                         }
                         let size_of_cell_to_remove_from_left =
-                            2 + cell_array.cells[cell_array.cell_count(i) - 1].len() as u16;
+                            2 + cell_array.cells[cell_array.cell_count(i) - 1].len() as i64;
                         new_page_sizes[i] -= size_of_cell_to_remove_from_left;
                         let size_of_cell_to_move_right = if !leaf_data {
                             if cell_array.number_of_cells_per_page[i]
@@ -1645,7 +2131,7 @@ impl BTreeCursor {
                             {
                                 // This means we move to the right page the divider cell and we
                                 // promote left cell to divider
-                                2 + cell_array.cells[cell_array.cell_count(i)].len() as u16
+                                2 + cell_array.cells[cell_array.cell_count(i)].len() as i64
                             } else {
                                 0
                             }
@@ -1659,9 +2145,9 @@ impl BTreeCursor {
                     // Now try to take from the right if we didn't have enough
                     while cell_array.number_of_cells_per_page[i] < cell_array.cells.len() as u16 {
                         let size_of_cell_to_remove_from_right =
-                            2 + cell_array.cells[cell_array.cell_count(i)].len() as u16;
+                            2 + cell_array.cells[cell_array.cell_count(i)].len() as i64;
                         let can_take = new_page_sizes[i] + size_of_cell_to_remove_from_right
-                            > usable_space as u16;
+                            > usable_space as i64;
                         if can_take {
                             break;
                         }
@@ -1672,7 +2158,7 @@ impl BTreeCursor {
                             if cell_array.number_of_cells_per_page[i]
                                 < cell_array.cells.len() as u16
                             {
-                                2 + cell_array.cells[cell_array.cell_count(i)].len() as u16
+                                2 + cell_array.cells[cell_array.cell_count(i)].len() as i64
                             } else {
                                 0
                             }
@@ -1718,8 +2204,8 @@ impl BTreeCursor {
                     // the same we add to right (we don't add divider to right).
                     let mut cell_right = cell_left + 1 - leaf_data as u16;
                     loop {
-                        let cell_left_size = cell_array.cell_size(cell_left as usize);
-                        let cell_right_size = cell_array.cell_size(cell_right as usize);
+                        let cell_left_size = cell_array.cell_size(cell_left as usize) as i64;
+                        let cell_right_size = cell_array.cell_size(cell_right as usize) as i64;
                         // TODO: add assert nMaxCells
 
                         let pointer_size = if i == sibling_count_new - 1 { 0 } else { 2 };
@@ -1781,10 +2267,7 @@ impl BTreeCursor {
 
                 // Write right pointer in parent page to point to new rightmost page
                 let right_page_id = pages_to_balance_new.last().unwrap().get().id as u32;
-                let rightmost_pointer = balance_info.rightmost_pointer;
-                let rightmost_pointer =
-                    unsafe { std::slice::from_raw_parts_mut(rightmost_pointer, 4) };
-                rightmost_pointer[0..4].copy_from_slice(&right_page_id.to_be_bytes());
+                parent_contents.write_u32_no_offset(balance_info.rightmost_pointer, right_page_id);
 
                 // Ensure right-child pointer of the right-most new sibling pge points to the page
                 // that was originally on that place.
@@ -1830,10 +2313,9 @@ impl BTreeCursor {
                         write_varint_to_vec(rowid, &mut new_divider_cell);
                     } else {
                         // Leaf index
+                        new_divider_cell.extend_from_slice(&(page.get().id as u32).to_be_bytes());
                         new_divider_cell.extend_from_slice(divider_cell);
                     }
-                    // FIXME: defragment shouldn't be needed
-                    defragment_page(parent_contents, self.usable_space() as u16);
                     insert_into_cell(
                         parent_contents,
                         &new_divider_cell,
@@ -1894,21 +2376,90 @@ impl BTreeCursor {
                         done[page_idx] = true;
                     }
                 }
-                // TODO: balance root
-                // TODO: free pages
+                // Siblings that are no longer needed after the cells were merged into fewer pages.
+                for page in balance_info.pages_to_balance.iter().skip(sibling_count_new) {
+                    let page_id = page.get().id;
+                    tracing::debug!("balance_non_root(free_page={})", page_id);
+                    self.pager.free_page(Some(page.clone()), page_id)?;
+                }
                 (WriteState::BalanceStart, Ok(CursorResult::Ok(())))
             }
             WriteState::Finish => todo!(),
         };
         if matches!(next_write_state, WriteState::BalanceStart) {
             // reset balance state
-            let _ = self.state.mut_write_info().unwrap().balance_info.take();
+            let balance_info = self.state.mut_write_info().unwrap().balance_info.take();
+            if let Some(balance_info) = balance_info {
+                balance_info
+                    .pages_to_balance
+                    .iter()
+                    .for_each(|page| page.unpin());
+            }
         }
         let write_info = self.state.mut_write_info().unwrap();
         write_info.state = next_write_state;
         result
     }
 
+    /// Decrease the depth of the tree when its root is an interior page without cells,
+    /// by moving the cells of the only child into the root and freeing the child.
+    /// When the root is page 1, the child has to leave room for the database header.
+    fn balance_shallower(&mut self) -> Result<CursorResult<()>> {
+        let root = self.stack.top();
+        let root_contents = root.get_contents();
+        let child_id = root_contents.rightmost_pointer().unwrap() as usize;
+        let child = self.pager.read_page(child_id)?;
+        return_if_locked_maybe_load!(self.pager, child);
+        let child_contents = child.get_contents();
+        let offset = if root.get().id == 1 {
+            DATABASE_HEADER_SIZE
+        } else {
+            0
+        };
+        if (compute_free_space(child_contents, self.usable_space() as u16)? as usize) < offset {
+            return Ok(CursorResult::Ok(()));
+        }
+        tracing::debug!(
+            "balance_shallower(root={}, child={})",
+            root.get().id,
+            child_id
+        );
+        let mut cells = Vec::with_capacity(child_contents.cell_count());
+        for cell_idx in 0..child_contents.cell_count() {
+            let (cell_start, cell_len) = child_contents.cell_get_raw_region(
+                cell_idx,
+                payload_overflow_threshold_max(
+                    child_contents.page_type(),
+                    self.usable_space() as u16,
+                ),
+                payload_overflow_threshold_min(
+                    child_contents.page_type(),
+                    self.usable_space() as u16,
+                ),
+                self.usable_space(),
+            )?;
+            cells.push(child_contents.as_slice()[cell_start..cell_start + cell_len].to_vec());
+        }
+        let child_page_type = child_contents.page_type();
+        let child_rightmost_pointer = child_contents.rightmost_pointer();
+
+        root.set_dirty();
+        self.pager.add_dirty(&root);
+        btree_init_page(&root, child_page_type, offset, self.usable_space() as u16);
+        let root_contents = root.get_contents();
+        if let Some(pointer) = child_rightmost_pointer {
+            root_contents.write_u32(PAGE_HEADER_OFFSET_RIGHTMOST_PTR, pointer);
+        }
+        for (cell_idx, cell) in cells.iter().enumerate() {
+            insert_into_cell(root_contents, cell, cell_idx, self.usable_space() as u16)?;
+        }
+        debug_validate_cells!(root_contents, self.usable_space() as u16);
+        self.pager.free_page(Some(child), child_id)?;
+        self.stack.clear();
+        self.stack.push(root);
+        Ok(CursorResult::Ok(()))
+    }
+
     /// Balance the root page.
     /// This is done when the root page overflows, and we need to create a new root page.
     /// See e.g. https://en.wikipedia.org/wiki/B-tree
@@ -1934,27 +2485,30 @@ impl BTreeCursor {
             root.get_contents().page_type()
         );
 
-        self.pager.add_dirty(root.get().id);
-        self.pager.add_dirty(child.get().id);
+        self.pager.add_dirty(&root);
+        self.pager.add_dirty(&child);
 
-        let root_buf = root_contents.as_ptr();
         let child_contents = child.get_contents();
-        let child_buf = child_contents.as_ptr();
         let (root_pointer_start, root_pointer_len) =
             root_contents.cell_pointer_array_offset_and_size();
-        let (child_pointer_start, _) = child.get_contents().cell_pointer_array_offset_and_size();
+        let (child_pointer_start, _) = child_contents.cell_pointer_array_offset_and_size();
 
         let top = root_contents.cell_content_area() as usize;
+        let header_size = root_contents.header_size();
 
         // 1. Modify child
-        // Copy pointers
-        child_buf[child_pointer_start..child_pointer_start + root_pointer_len]
-            .copy_from_slice(&root_buf[root_pointer_start..root_pointer_start + root_pointer_len]);
-        // Copy cell contents
-        child_buf[top..].copy_from_slice(&root_buf[top..]);
-        // Copy header
-        child_buf[0..root_contents.header_size()]
-            .copy_from_slice(&root_buf[offset..offset + root_contents.header_size()]);
+        {
+            let root_buf = root_contents.as_slice();
+            let mut child_buf = child_contents.as_mut_slice();
+            // Copy pointers
+            child_buf[child_pointer_start..child_pointer_start + root_pointer_len].copy_from_slice(
+                &root_buf[root_pointer_start..root_pointer_start + root_pointer_len],
+            );
+            // Copy cell contents
+            child_buf[top..].copy_from_slice(&root_buf[top..]);
+            // Copy header
+            child_buf[0..header_size].copy_from_slice(&root_buf[offset..offset + header_size]);
+        }
         // Copy overflow cells
         child_contents.overflow_cells = root_contents.overflow_cells.clone();
 
@@ -2016,7 +2570,7 @@ impl BTreeCursor {
                     // TODO: implement efficient comparison of records
                     // e.g. https://github.com/sqlite/sqlite/blob/master/src/vdbeaux.c#L4719
                     read_record(
-                        payload,
+                        &payload.as_slice(),
                         self.get_immutable_record_or_create().as_mut().unwrap(),
                     )
                     .expect("failed to read record");
@@ -2045,6 +2599,7 @@ impl BTreeCursor {
             let page_id = mem_page.get().id;
             let page = self.pager.read_page(page_id)?;
             return_if_locked!(page);
+            check_untrusted_page(&self.pager, &page)?;
 
             let contents = page.get().contents.as_ref().unwrap();
             if contents.is_leaf() {
@@ -2084,6 +2639,21 @@ impl BTreeCursor {
         self.root_page
     }
 
+    /// Makes the cursor read rows into `record` instead of allocating a record of its own.
+    pub fn set_record_buffer(&mut self, record: ImmutableRecord) {
+        *self.reusable_immutable_record.get_mut() = Some(record);
+    }
+
+    /// Takes the record the cursor reads rows into, to reuse its buffers elsewhere.
+    pub fn take_record_buffer(&mut self) -> Option<ImmutableRecord> {
+        self.reusable_immutable_record.get_mut().take()
+    }
+
+    /// Opens another cursor on the same b-tree, positioned nowhere.
+    pub fn open_dup(&self) -> Self {
+        BTreeCursor::new(self.mv_cursor.clone(), self.pager.clone(), self.root_page)
+    }
+
     pub fn rewind(&mut self) -> Result<CursorResult<()>> {
         if self.mv_cursor.is_some() {
             let rowid = return_if_io!(self.get_next_record(None));
@@ -2139,8 +2709,81 @@ impl BTreeCursor {
         Ok(self.rowid.get())
     }
 
-    pub fn seek(&mut self, key: SeekKey<'_>, op: SeekOp) -> Result<CursorResult<bool>> {
+    /// Counts the entries of the btree without reading them, like SQLite's sqlite3BtreeCount():
+    /// every cell of a leaf page is an entry, and so is every cell of an interior page of an
+    /// index. Only page headers and the child pointers of interior cells are read.
+    pub fn count(&mut self) -> Result<CursorResult<u64>> {
+        if self.mv_cursor.is_some() {
+            let mut count = 0;
+            return_if_io!(self.rewind());
+            while !self.is_empty() {
+                count += 1;
+                return_if_io!(self.next());
+            }
+            return Ok(CursorResult::Ok(count));
+        }
+        if !matches!(self.state, CursorState::Count(_)) {
+            self.move_to_root();
+            self.state = CursorState::Count(0);
+        }
+        // Every iteration enters a page that has not been counted yet.
+        loop {
+            let page = self.stack.top();
+            return_if_locked_maybe_load!(self.pager, page);
+            let contents = page.get_contents();
+            if let CursorState::Count(count) = &mut self.state {
+                if contents.is_leaf() || contents.page_type() == PageType::IndexInterior {
+                    *count += contents.cell_count() as u64;
+                }
+            }
+            if contents.is_leaf() {
+                // Go up to the first page that still has a child to visit.
+                loop {
+                    if !self.stack.has_parent() {
+                        let CursorState::Count(count) =
+                            std::mem::replace(&mut self.state, CursorState::None)
+                        else {
+                            unreachable!("count state was replaced during count");
+                        };
+                        return Ok(CursorResult::Ok(count));
+                    }
+                    self.stack.pop();
+                    let parent_cell_count = self.stack.top().get_contents().cell_count();
+                    if self.stack.current_cell_index() < parent_cell_count as i32 {
+                        break;
+                    }
+                }
+                self.stack.advance();
+            }
+            let page = self.stack.top();
+            let contents = page.get_contents();
+            let cell_idx = self.stack.current_cell_index() as usize;
+            let child_page = if cell_idx == contents.cell_count() {
+                contents.rightmost_pointer().unwrap()
+            } else {
+                let (cell_start, _) = contents.cell_get_raw_region(
+                    cell_idx,
+                    payload_overflow_threshold_max(
+                        contents.page_type(),
+                        self.usable_space() as u16,
+                    ),
+                    payload_overflow_threshold_min(
+                        contents.page_type(),
+                        self.usable_space() as u16,
+                    ),
+                    self.usable_space(),
+                )?;
+                contents.read_u32_no_offset(cell_start)
+            };
+            let child_page = self.pager.read_page(child_page as usize)?;
+            self.stack.push(child_page);
+        }
+    }
+
+    pub fn seek(&mut self, key: SeekKey<'_>, op: SeekOp) -> Result<CursorResult<bool>> {
         assert!(self.mv_cursor.is_none());
+        // Seeking moves the cursor off the NULL row of an unmatched LEFT JOIN row.
+        self.null_flag = false;
         let rowid = return_if_io!(self.do_seek(key, op));
         self.rowid.replace(rowid);
         self.empty_record.replace(rowid.is_none());
@@ -2167,7 +2810,9 @@ impl BTreeCursor {
                 None => todo!("Support mvcc inserts with index btrees"),
             },
             None => {
-                if !moved_before {
+                // A write that returned IO mid-way resumes where it left off; seeking again
+                // would reset the page stack under the pending balance.
+                if !moved_before && !matches!(self.state, CursorState::Write(_)) {
                     match key {
                         BTreeKey::IndexKey(_) => {
                             return_if_io!(self
@@ -2183,6 +2828,8 @@ impl BTreeCursor {
                     let int_key = key.to_rowid();
                     self.rowid.replace(Some(int_key));
                 }
+                // The insert is complete, so transient b-trees can write out their pages.
+                self.pager.spill_if_needed()?;
             }
         };
         Ok(CursorResult::Ok(()))
@@ -2349,7 +2996,7 @@ impl BTreeCursor {
                     )?;
 
                     parent_page.set_dirty();
-                    self.pager.add_dirty(parent_page.get().id);
+                    self.pager.add_dirty(&parent_page);
 
                     let parent_contents = parent_page.get().contents.as_mut().unwrap();
 
@@ -2387,7 +3034,7 @@ impl BTreeCursor {
                     }
 
                     page.set_dirty();
-                    self.pager.add_dirty(page.get().id);
+                    self.pager.add_dirty(&page);
 
                     let contents = page.get().contents.as_mut().unwrap();
                     drop_cell(contents, cell_idx, self.usable_space() as u16)?;
@@ -2406,7 +3053,7 @@ impl BTreeCursor {
                     }
 
                     let contents = page.get().contents.as_ref().unwrap();
-                    let free_space = compute_free_space(contents, self.usable_space() as u16);
+                    let free_space = compute_free_space(contents, self.usable_space() as u16)?;
                     let needs_balancing = free_space as usize * 3 > self.usable_space() * 2;
 
                     let target_rowid = self.rowid.get().unwrap();
@@ -2473,6 +3120,37 @@ impl BTreeCursor {
                 DeleteState::SeekAfterBalancing { target_rowid } => {
                     return_if_io!(self.move_to(SeekKey::TableRowId(target_rowid), SeekOp::EQ));
 
+                    // Point at the cell that took the place of the deleted one, like
+                    // StackRetreat does, so that the next call to next() doesn't revisit
+                    // the rows before it that balancing moved into this page.
+                    let page = self.stack.top();
+                    return_if_locked_maybe_load!(self.pager, page);
+                    let contents = page.get().contents.as_ref().unwrap();
+                    let mut cell_idx = 0;
+                    while cell_idx < contents.cell_count() {
+                        let cell = contents.cell_get(
+                            cell_idx,
+                            payload_overflow_threshold_max(
+                                contents.page_type(),
+                                self.usable_space() as u16,
+                            ),
+                            payload_overflow_threshold_min(
+                                contents.page_type(),
+                                self.usable_space() as u16,
+                            ),
+                            self.usable_space(),
+                        )?;
+                        match cell {
+                            BTreeCell::TableLeafCell(TableLeafCell { _rowid, .. })
+                                if _rowid < target_rowid =>
+                            {
+                                cell_idx += 1
+                            }
+                            _ => break,
+                        }
+                    }
+                    self.stack.set_cell_index(cell_idx as i32);
+
                     let delete_info = self.state.mut_delete_info().unwrap();
                     delete_info.state = DeleteState::Finish;
                     delete_info.balance_write_info = None;
@@ -2851,7 +3529,7 @@ impl BTreeCursor {
                 payload_overflow_threshold_max(page_type, self.usable_space() as u16),
                 payload_overflow_threshold_min(page_type, self.usable_space() as u16),
                 self.usable_space(),
-            )
+            )?
         };
 
         // if it all fits in local space and old_local_size is enough, do an in-place overwrite
@@ -2902,8 +3580,8 @@ impl BTreeCursor {
     ) -> Result<CursorResult<()>> {
         return_if_locked!(page_ref);
         page_ref.set_dirty();
-        self.pager.add_dirty(page_ref.get().id);
-        let buf = page_ref.get().contents.as_mut().unwrap().as_ptr();
+        self.pager.add_dirty(&page_ref);
+        let mut buf = page_ref.get_contents().as_mut_slice();
 
         // if new_payload doesn't have enough data, we fill with zeros
         let n_data = new_payload.len().saturating_sub(src_offset);
@@ -2964,7 +3642,10 @@ impl PageStack {
             current < BTCURSOR_MAX_DEPTH as i32,
             "corrupted database, stack is bigger than expected"
         );
-        self.stack.borrow_mut()[current as usize] = Some(page);
+        page.pin();
+        if let Some(previous) = self.stack.borrow_mut()[current as usize].replace(page) {
+            previous.unpin();
+        }
         self.cell_indices.borrow_mut()[current as usize] = 0;
     }
 
@@ -2974,7 +3655,9 @@ impl PageStack {
         let current = self.current_page.get();
         tracing::trace!("pagestack::pop(current={})", current);
         self.cell_indices.borrow_mut()[current as usize] = 0;
-        self.stack.borrow_mut()[current as usize] = None;
+        if let Some(page) = self.stack.borrow_mut()[current as usize].take() {
+            page.unpin();
+        }
         self.decrement_current();
     }
 
@@ -3034,11 +3717,38 @@ impl PageStack {
         self.current_page.get() > 0
     }
 
+    fn is_empty(&self) -> bool {
+        self.current_page.get() < 0
+    }
+
+    /// Page at the given depth of the stack, where the root page is at depth 0.
+    fn page_at(&self, depth: usize) -> PageRef {
+        self.stack.borrow()[depth].as_ref().unwrap().clone()
+    }
+
+    /// Cell index saved for the page at the given depth of the stack.
+    fn cell_index_at(&self, depth: usize) -> i32 {
+        self.cell_indices.borrow()[depth]
+    }
+
     fn clear(&self) {
+        for page in self.stack.borrow_mut().iter_mut() {
+            if let Some(page) = page.take() {
+                page.unpin();
+            }
+        }
         self.current_page.set(-1);
     }
 }
 
+/// Pages on the stack are pinned, so that the page cache keeps the path to the cursor's position
+/// in memory while the cursor reads other pages.
+impl Drop for PageStack {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
 impl CellArray {
     pub fn cell_size(&self, cell_idx: usize) -> u16 {
         self.cells[cell_idx].len() as u16
@@ -3075,7 +3785,7 @@ fn find_free_cell(page_ref: &PageContent, usable_space: u16, amount: usize) -> R
                     return Ok(0);
                 }
                 // Delete the slot from freelist and update the page's fragment count.
-                page_ref.write_u16(prev_pc, next);
+                page_ref.write_u16_no_offset(prev_pc, next);
                 let frag = page_ref.num_frag_free_bytes() + new_size as u8;
                 page_ref.write_u8(PAGE_HEADER_OFFSET_FRAGMENTED_BYTES_COUNT, frag);
                 return Ok(pc);
@@ -3084,7 +3794,7 @@ fn find_free_cell(page_ref: &PageContent, usable_space: u16, amount: usize) -> R
             } else {
                 // Requested amount fits inside the current free slot so we reduce its size
                 // to account for newly allocated space.
-                page_ref.write_u16(pc + 2, new_size as u16);
+                page_ref.write_u16_no_offset(pc + 2, new_size as u16);
                 return Ok(pc + new_size);
             }
         }
@@ -3121,8 +3831,9 @@ pub fn btree_init_page(page: &PageRef, page_type: PageType, offset: usize, usabl
     contents.write_u32(PAGE_HEADER_OFFSET_RIGHTMOST_PTR, 0);
 }
 
-fn to_static_buf(buf: &mut [u8]) -> &'static mut [u8] {
-    unsafe { std::mem::transmute::<&mut [u8], &'static mut [u8]>(buf) }
+/// Identifies the buffer of a page, to tell whether a cell was copied from it.
+fn page_buffer_id(page: &PageContent) -> usize {
+    Arc::as_ptr(&page.buffer) as usize
 }
 
 fn edit_page(
@@ -3153,9 +3864,8 @@ fn edit_page(
             usable_space,
         )?;
         // shift pointers left
-        let buf = page.as_ptr();
         let (start, _) = page.cell_pointer_array_offset_and_size();
-        buf.copy_within(
+        page.as_mut_slice().copy_within(
             start + (number_to_shift * 2)..start + (count_cells * 2),
             start,
         );
@@ -3176,7 +3886,7 @@ fn edit_page(
         debug_validate_cells!(page, usable_space);
     }
     // TODO: make page_free_array defragment, for now I'm lazy so this will work for now.
-    defragment_page(page, usable_space);
+    defragment_page(page, usable_space)?;
     // TODO: add to start
     if start_new_cells < start_old_cells {
         let count = number_new_cells.min(start_old_cells - start_new_cells);
@@ -3227,24 +3937,27 @@ fn page_free_array(
     usable_space: u16,
 ) -> Result<usize> {
     tracing::debug!("page_free_array {}..{}", first, first + count);
-    let buf = &mut page.as_ptr()[page.offset..usable_space as usize];
-    let buf_range = buf.as_ptr_range();
+    let buffer_id = page_buffer_id(page);
     let mut number_of_cells_removed = 0;
     // TODO: implement fancy smart free block coalescing procedure instead of dumb free to
     // then defragment
     for i in first..first + count {
-        let cell = &cell_array.cells[i];
-        let cell_pointer = cell.as_ptr_range();
         // check if not overflow cell
-        if cell_pointer.start >= buf_range.start && cell_pointer.start < buf_range.end {
+        let Some((cell_buffer_id, cell_start)) = cell_array.cell_origins[i] else {
+            continue;
+        };
+        if cell_buffer_id == buffer_id
+            && cell_start >= page.offset
+            && cell_start < usable_space as usize
+        {
+            let len = cell_array.cells[i].len();
             assert!(
-                cell_pointer.end >= buf_range.start && cell_pointer.end <= buf_range.end,
+                cell_start + len <= usable_space as usize,
                 "whole cell should be inside the page"
             );
             // TODO: remove pointer too
-            let offset = (cell_pointer.start as usize - buf_range.start as usize) as u16;
-            let len = (cell_pointer.end as usize - cell_pointer.start as usize) as u16;
-            free_cell_range(page, offset, len, usable_space)?;
+            let offset = (cell_start - page.offset) as u16;
+            free_cell_range(page, offset, len as u16, usable_space)?;
             page.write_u16(PAGE_HEADER_OFFSET_CELL_COUNT, page.cell_count() as u16 - 1);
             number_of_cells_removed += 1;
         }
@@ -3270,7 +3983,7 @@ fn page_insert_array(
     );
     for i in first..first + count {
         debug_validate_cells!(page, usable_space);
-        insert_into_cell(page, cell_array.cells[i], start_insert, usable_space)?;
+        insert_into_cell(page, &cell_array.cells[i], start_insert, usable_space)?;
         debug_validate_cells!(page, usable_space);
         start_insert += 1;
     }
@@ -3377,22 +4090,86 @@ fn free_cell_range(
     Ok(())
 }
 
+/// Defragment a page, allowing at most `max_frag` fragmented bytes to be left behind.
+/// Like SQLite's defragmentPage, a page with at most two freeblocks is compacted by only
+/// moving the cells in front of them, otherwise every cell is packed to the end of the page.
+fn defragment_page_fast(page: &PageContent, usable_space: u16, max_frag: u8) -> Result<()> {
+    if page.num_frag_free_bytes() > max_frag {
+        return defragment_page(page, usable_space);
+    }
+    let first_freeblock = page.first_freeblock();
+    if first_freeblock == 0 {
+        return defragment_page(page, usable_space);
+    }
+    let last_cell = usable_space - 4;
+    if first_freeblock > last_cell {
+        return_corrupt!("First freeblock beyond usable space");
+    }
+    let second_freeblock = page.read_u16_no_offset(first_freeblock as usize);
+    if second_freeblock > last_cell {
+        return_corrupt!("Second freeblock beyond usable space");
+    }
+    if second_freeblock != 0 && page.read_u16_no_offset(second_freeblock as usize) != 0 {
+        // More than two freeblocks, the fast path doesn't apply.
+        return defragment_page(page, usable_space);
+    }
+    debug_validate_cells!(page, usable_space);
+    tracing::debug!("defragment_page_fast");
+    let free_space = compute_free_space(page, usable_space)?;
+    let top = page.cell_content_area();
+    if top >= first_freeblock {
+        return_corrupt!("Freeblock before the cell content area");
+    }
+    let mut size = page.read_u16_no_offset(first_freeblock as usize + 2);
+    let mut second_size = 0;
+    if second_freeblock != 0 {
+        if first_freeblock + size > second_freeblock {
+            return_corrupt!("Overlapping freeblocks");
+        }
+        second_size = page.read_u16_no_offset(second_freeblock as usize + 2);
+        if second_freeblock + second_size > usable_space {
+            return_corrupt!("Freeblock extends beyond usable space");
+        }
+        // Close the gap of the second freeblock by moving the cells between both freeblocks.
+        page.as_mut_slice().copy_within(
+            (first_freeblock + size) as usize..second_freeblock as usize,
+            (first_freeblock + size + second_size) as usize,
+        );
+        size += second_size;
+    } else if first_freeblock + size > usable_space {
+        return_corrupt!("Freeblock extends beyond usable space");
+    }
+    let cbrk = top + size;
+    page.as_mut_slice()
+        .copy_within(top as usize..first_freeblock as usize, cbrk as usize);
+
+    let (cell_offset, _) = page.cell_pointer_array_offset_and_size();
+    for i in 0..page.cell_count() {
+        let cell_idx = cell_offset + (i * 2);
+        let pc = page.read_u16_no_offset(cell_idx);
+        if pc < first_freeblock {
+            page.write_u16_no_offset(cell_idx, pc + size);
+        } else if pc < second_freeblock {
+            page.write_u16_no_offset(cell_idx, pc + second_size);
+        }
+    }
+    finish_defragment(page, cbrk, free_space, usable_space)
+}
+
 /// Defragment a page. This means packing all the cells to the end of the page.
-fn defragment_page(page: &PageContent, usable_space: u16) {
+fn defragment_page(page: &PageContent, usable_space: u16) -> Result<()> {
     debug_validate_cells!(page, usable_space);
     tracing::debug!("defragment_page");
+    let free_space = compute_free_space(page, usable_space)?;
     let cloned_page = page.clone();
     // TODO(pere): usable space should include offset probably
     let mut cbrk = usable_space;
 
-    // TODO: implement fast algorithm
-
     let last_cell = usable_space - 4;
-    let first_cell = cloned_page.unallocated_region_start() as u16;
+    let cell_content_area = cloned_page.cell_content_area();
 
     if cloned_page.cell_count() > 0 {
-        let read_buf = cloned_page.as_ptr();
-        let write_buf = page.as_ptr();
+        let read_buf = cloned_page.as_slice();
 
         for i in 0..cloned_page.cell_count() {
             let (cell_offset, _) = page.cell_pointer_array_offset_and_size();
@@ -3400,43 +4177,51 @@ fn defragment_page(page: &PageContent, usable_space: u16) {
 
             let pc = cloned_page.read_u16_no_offset(cell_idx);
             if pc > last_cell {
-                unimplemented!("corrupted page");
+                return_corrupt!("Cell pointer beyond usable space");
             }
 
-            assert!(pc <= last_cell);
-
             let (_, size) = cloned_page.cell_get_raw_region(
                 i,
                 payload_overflow_threshold_max(page.page_type(), usable_space),
                 payload_overflow_threshold_min(page.page_type(), usable_space),
                 usable_space as usize,
-            );
+            )?;
             let size = size as u16;
-            cbrk -= size;
-            if cbrk < first_cell || pc + size > usable_space {
-                todo!("corrupt");
+            if cbrk < cell_content_area + size || pc + size > usable_space {
+                return_corrupt!("Cell extends beyond the cell content area");
             }
-            assert!(cbrk + size <= usable_space && cbrk >= first_cell);
+            cbrk -= size;
             // set new pointer
             page.write_u16_no_offset(cell_idx, cbrk);
             // copy payload
-            write_buf[cbrk as usize..cbrk as usize + size as usize]
+            page.as_mut_slice()[cbrk as usize..cbrk as usize + size as usize]
                 .copy_from_slice(&read_buf[pc as usize..pc as usize + size as usize]);
         }
     }
 
-    // assert!( nfree >= 0 );
-    // if( data[hdr+7]+cbrk-iCellFirst!=pPage->nFree ){
-    //   return SQLITE_CORRUPT_PAGE(pPage);
-    // }
-    assert!(cbrk >= first_cell);
+    page.write_u8(PAGE_HEADER_OFFSET_FRAGMENTED_BYTES_COUNT, 0);
+    finish_defragment(page, cbrk, free_space, usable_space)
+}
 
+/// Point the header at the new start of the cell content area, which now follows the
+/// unallocated region without any freeblock in between.
+fn finish_defragment(
+    page: &PageContent,
+    cbrk: u16,
+    free_space: u16,
+    usable_space: u16,
+) -> Result<()> {
+    let first_cell = page.unallocated_region_start() as u16;
+    if cbrk < first_cell || page.num_frag_free_bytes() as u16 + cbrk - first_cell != free_space {
+        return_corrupt!("Free space changed while defragmenting the page");
+    }
     // set new first byte of cell content
     page.write_u16(PAGE_HEADER_OFFSET_CELL_CONTENT_AREA, cbrk);
     // set free block to 0, unused spaced can be retrieved from gap between cell pointer end and content start
     page.write_u16(PAGE_HEADER_OFFSET_FIRST_FREEBLOCK, 0);
-    page.write_u8(PAGE_HEADER_OFFSET_FRAGMENTED_BYTES_COUNT, 0);
+    page.as_mut_slice()[first_cell as usize..cbrk as usize].fill(0);
     debug_validate_cells!(page, usable_space);
+    Ok(())
 }
 
 #[cfg(debug_assertions)]
@@ -3444,14 +4229,16 @@ fn defragment_page(page: &PageContent, usable_space: u16) {
 fn debug_validate_cells_core(page: &PageContent, usable_space: u16) {
     for i in 0..page.cell_count() {
         // println!("Debug function: i={}", i);
-        let (offset, size) = page.cell_get_raw_region(
-            i,
-            payload_overflow_threshold_max(page.page_type(), usable_space),
-            payload_overflow_threshold_min(page.page_type(), usable_space),
-            usable_space as usize,
-        );
+        let (offset, size) = page
+            .cell_get_raw_region(
+                i,
+                payload_overflow_threshold_max(page.page_type(), usable_space),
+                payload_overflow_threshold_min(page.page_type(), usable_space),
+                usable_space as usize,
+            )
+            .unwrap();
         if page.is_leaf() {
-            assert!(page.as_ptr()[offset] != 0);
+            assert!(page.as_slice()[offset] != 0);
         }
         assert!(size >= 4, "cell size should be at least 4 bytes idx={}", i);
     }
@@ -3474,7 +4261,7 @@ fn insert_into_cell(
         cell_idx,
         page.cell_count()
     );
-    let free = compute_free_space(page, usable_space);
+    let free = compute_free_space(page, usable_space)?;
     const CELL_POINTER_SIZE_BYTES: usize = 2;
     let enough_space = payload.len() + CELL_POINTER_SIZE_BYTES <= free as usize;
     if !enough_space {
@@ -3492,17 +4279,17 @@ fn insert_into_cell(
         cell_idx,
         new_cell_data_pointer
     );
-    let buf = page.as_ptr();
+    //  memmove(pIns+2, pIns, 2*(pPage->nCell - i));
+    let (cell_pointer_array_start, _) = page.cell_pointer_array_offset_and_size();
+    let cell_pointer_cur_idx = cell_pointer_array_start + (CELL_POINTER_SIZE_BYTES * cell_idx);
+    let n_cells_forward = page.cell_count() - cell_idx;
+    let mut buf = page.as_mut_slice();
 
     // copy data
     buf[new_cell_data_pointer as usize..new_cell_data_pointer as usize + payload.len()]
         .copy_from_slice(payload);
-    //  memmove(pIns+2, pIns, 2*(pPage->nCell - i));
-    let (cell_pointer_array_start, _) = page.cell_pointer_array_offset_and_size();
-    let cell_pointer_cur_idx = cell_pointer_array_start + (CELL_POINTER_SIZE_BYTES * cell_idx);
 
     // move existing pointers forward by CELL_POINTER_SIZE_BYTES...
-    let n_cells_forward = page.cell_count() - cell_idx;
     let n_bytes_forward = CELL_POINTER_SIZE_BYTES * n_cells_forward;
     if n_bytes_forward > 0 {
         buf.copy_within(
@@ -3510,6 +4297,7 @@ fn insert_into_cell(
             cell_pointer_cur_idx + CELL_POINTER_SIZE_BYTES,
         );
     }
+    drop(buf);
     // ...and insert new cell pointer at the current index
     page.write_u16_no_offset(cell_pointer_cur_idx, new_cell_data_pointer);
 
@@ -3523,7 +4311,7 @@ fn insert_into_cell(
 /// Free blocks can be zero, meaning the "real free space" that can be used to allocate is expected to be between first cell byte
 /// and end of cell pointer area.
 #[allow(unused_assignments)]
-fn compute_free_space(page: &PageContent, usable_space: u16) -> u16 {
+fn compute_free_space(page: &PageContent, usable_space: u16) -> Result<u16> {
     // TODO(pere): maybe free space is not calculated correctly with offset
 
     // Usable space, not the same as free space, simply means:
@@ -3564,13 +4352,15 @@ fn compute_free_space(page: &PageContent, usable_space: u16) -> u16 {
         if cur_freeblock_ptr < cell_content_area_start as usize {
             // Freeblocks exist in the cell content area e.g. after deletions
             // They should never exist in the unused area of the page.
-            todo!("corrupted page");
+            return_corrupt!("Free block before content area");
         }
 
         let mut next = 0;
         let mut size = 0;
         loop {
-            // TODO: check corruption icellast
+            if cur_freeblock_ptr + 4 > usable_space {
+                return_corrupt!("Free block header extends beyond page");
+            }
             next = page.read_u16_no_offset(cur_freeblock_ptr) as usize; // first 2 bytes in freeblock = next freeblock pointer
             size = page.read_u16_no_offset(cur_freeblock_ptr + 2) as usize; // next 2 bytes in freeblock = size of current freeblock
             free_space_bytes += size;
@@ -3583,27 +4373,23 @@ fn compute_free_space(page: &PageContent, usable_space: u16) -> u16 {
         }
 
         // Next should always be 0 (NULL) at this point since we have reached the end of the freeblocks linked list
-        assert!(
-            next == 0,
-            "corrupted page: freeblocks list not in ascending order"
-        );
-
-        assert!(
-            cur_freeblock_ptr + size <= usable_space,
-            "corrupted page: last freeblock extends last page end"
-        );
+        if next != 0 {
+            return_corrupt!("corrupted page: freeblocks list not in ascending order");
+        }
+        if cur_freeblock_ptr + size > usable_space {
+            return_corrupt!("corrupted page: last freeblock extends last page end");
+        }
     }
 
-    assert!(
-        free_space_bytes <= usable_space,
-        "corrupted page: free space is greater than usable space"
-    );
+    if free_space_bytes > usable_space || free_space_bytes < first_cell {
+        return_corrupt!("corrupted page: free space is greater than usable space");
+    }
 
     // if( nFree>usableSize || nFree<iCellFirst ){
     //   return SQLITE_CORRUPT_PAGE(pPage);
     // }
 
-    free_space_bytes as u16 - first_cell as u16
+    Ok(free_space_bytes as u16 - first_cell as u16)
 }
 
 /// Allocate space for a cell on a page.
@@ -3614,6 +4400,10 @@ fn allocate_cell_space(page_ref: &PageContent, amount: u16, usable_space: u16) -
     let gap = cell_offset + 2 * page_ref.cell_count();
     let mut top = page_ref.cell_content_area() as usize;
 
+    if gap > top {
+        return_corrupt!("Cell pointer array overlaps the cell content area");
+    }
+
     // there are free blocks and enough space
     if page_ref.first_freeblock() != 0 && gap + 2 <= top {
         // find slot
@@ -3625,9 +4415,14 @@ fn allocate_cell_space(page_ref: &PageContent, amount: u16, usable_space: u16) -
     }
 
     if gap + 2 + amount > top {
-        // defragment
-        defragment_page(page_ref, usable_space);
+        // Defragment, leaving at most as many fragmented bytes as we can spare for this cell.
+        let free_space = compute_free_space(page_ref, usable_space)? as usize;
+        let max_frag = free_space.saturating_sub(2 + amount).min(4) as u8;
+        defragment_page_fast(page_ref, usable_space, max_frag)?;
         top = page_ref.read_u16(PAGE_HEADER_OFFSET_CELL_CONTENT_AREA) as usize;
+        if gap + 2 + amount > top {
+            return_corrupt!("Not enough space for the cell after defragmenting the page");
+        }
     }
 
     top -= amount;
@@ -3652,8 +4447,7 @@ fn fill_cell_payload(
         page_type,
         PageType::TableLeaf | PageType::IndexLeaf
     ));
-    // TODO: make record raw from start, having to serialize is not good
-    let record_buf = record.get_payload().to_vec();
+    let record_buf = record.get_payload();
 
     // fill in header
     if matches!(page_type, PageType::TableLeaf) {
@@ -3672,7 +4466,7 @@ fn fill_cell_payload(
     );
     if record_buf.len() <= payload_overflow_threshold_max {
         // enough allowed space to fit inside a btree page
-        cell_payload.extend_from_slice(record_buf.as_slice());
+        cell_payload.extend_from_slice(record_buf);
         return;
     }
 
@@ -3687,42 +4481,31 @@ fn fill_cell_payload(
 
     // cell_size must be equal to first value of space_left as this will be the bytes copied to non-overflow page.
     let cell_size = space_left + cell_payload.len() + 4; // 4 is the number of bytes of pointer to first overflow page
-    let mut to_copy_buffer = record_buf.as_slice();
-
     let prev_size = cell_payload.len();
     cell_payload.resize(prev_size + space_left + 4, 0);
-    let mut pointer = unsafe { cell_payload.as_mut_ptr().add(prev_size) };
-    let mut pointer_to_next = unsafe { cell_payload.as_mut_ptr().add(prev_size + space_left) };
-    let mut overflow_pages = Vec::new();
-
-    loop {
-        let to_copy = space_left.min(to_copy_buffer.len());
-        unsafe { std::ptr::copy(to_copy_buffer.as_ptr(), pointer, to_copy) };
-
-        let left = to_copy_buffer.len() - to_copy;
-        if left == 0 {
-            break;
-        }
+    let to_copy = space_left.min(record_buf.len());
+    cell_payload[prev_size..prev_size + to_copy].copy_from_slice(&record_buf[..to_copy]);
+    let mut to_copy_buffer = &record_buf[to_copy..];
 
+    // The overflow page that points to the next one, none while that is still the cell.
+    let mut previous_page: Option<PageRef> = None;
+    while !to_copy_buffer.is_empty() {
         // we still have bytes to add, we will need to allocate new overflow page
         let overflow_page = allocate_overflow_page(pager.clone());
-        overflow_pages.push(overflow_page.clone());
-        {
-            let id = overflow_page.get().id as u32;
-            let contents = overflow_page.get().contents.as_mut().unwrap();
-
-            // TODO: take into account offset here?
-            let buf = contents.as_ptr();
-            let as_bytes = id.to_be_bytes();
-            // update pointer to new overflow page
-            unsafe { std::ptr::copy(as_bytes.as_ptr(), pointer_to_next, 4) };
-
-            pointer = unsafe { buf.as_mut_ptr().add(4) };
-            pointer_to_next = buf.as_mut_ptr();
-            space_left = usable_space as usize - 4;
+        let id = overflow_page.get().id as u32;
+        // update pointer to new overflow page
+        match &previous_page {
+            None => cell_payload[prev_size + space_left..prev_size + space_left + 4]
+                .copy_from_slice(&id.to_be_bytes()),
+            Some(previous_page) => previous_page.get_contents().write_u32_no_offset(0, id),
         }
 
+        // TODO: take into account offset here?
+        let to_copy = (usable_space as usize - 4).min(to_copy_buffer.len());
+        overflow_page.get_contents().as_mut_slice()[4..4 + to_copy]
+            .copy_from_slice(&to_copy_buffer[..to_copy]);
         to_copy_buffer = &to_copy_buffer[to_copy..];
+        previous_page = Some(overflow_page);
     }
 
     assert_eq!(cell_size, cell_payload.len());
@@ -3735,9 +4518,7 @@ fn allocate_overflow_page(pager: Rc<Pager>) -> PageRef {
     tracing::debug!("allocate_overflow_page(id={})", page.get().id);
 
     // setup overflow page
-    let contents = page.get().contents.as_mut().unwrap();
-    let buf = contents.as_ptr();
-    buf.fill(0);
+    page.get_contents().as_mut_slice().fill(0);
 
     page
 }
@@ -3752,7 +4533,7 @@ fn allocate_overflow_page(pager: Rc<Pager>) -> PageRef {
 /// - Give a minimum fanout of 4 for index b-trees
 /// - Ensure enough payload is on the b-tree page that the record header can usually be accessed
 ///   without consulting an overflow page
-fn payload_overflow_threshold_max(page_type: PageType, usable_space: u16) -> usize {
+pub(crate) fn payload_overflow_threshold_max(page_type: PageType, usable_space: u16) -> usize {
     match page_type {
         PageType::IndexInterior | PageType::IndexLeaf => {
             ((usable_space as usize - 12) * 64 / 255) - 23 // Index page formula
@@ -3772,11 +4553,221 @@ fn payload_overflow_threshold_max(page_type: PageType, usable_space: u16) -> usi
 /// - Otherwise: store M bytes on page
 ///
 /// The remaining bytes are stored on overflow pages in both cases.
-fn payload_overflow_threshold_min(_page_type: PageType, usable_space: u16) -> usize {
+pub(crate) fn payload_overflow_threshold_min(_page_type: PageType, usable_space: u16) -> usize {
     // Same formula for all page types
     ((usable_space as usize - 12) * 32 / 255) - 23
 }
 
+/// Checks that the cells of a b-tree page can be read without going past its end and that the
+/// pages it points to exist, for scans in salvage mode and integrity checks. Returns what is
+/// wrong with it otherwise.
+/// Overflow pages aren't followed, so records spilling into them are only checked on use.
+pub(crate) fn check_page(
+    page: &PageContent,
+    usable_space: usize,
+    database_size: usize,
+) -> std::result::Result<(), String> {
+    let Some(page_type) = page.maybe_page_type() else {
+        return Err(format!("invalid page type {}", page.read_u8(0)));
+    };
+    let buf = page.as_slice();
+    let buf = &buf[..usable_space];
+    // Reads a varint that may be cut off by the end of the page.
+    let varint_at = |pos: usize| -> Option<(u64, usize)> {
+        let available = buf.len().saturating_sub(pos).min(9);
+        let mut bytes = [0; 9];
+        bytes[..available].copy_from_slice(&buf[pos..pos + available]);
+        read_varint(&bytes).ok().filter(|(_, n)| *n <= available)
+    };
+    let check_child = |child: u32| {
+        if child < 2 || child as usize > database_size {
+            return Err(format!("child page {} out of range", child));
+        }
+        Ok(())
+    };
+    let cell_count = page.cell_count();
+    let cells_start = page.offset + page.header_size() + cell_count * 2;
+    if cells_start > buf.len() {
+        return Err(format!("{} cells don't fit", cell_count));
+    }
+    if let Some(rightmost) = page.rightmost_pointer() {
+        check_child(rightmost)?;
+    }
+    let max_local = payload_overflow_threshold_max(page_type, usable_space as u16);
+    let min_local = payload_overflow_threshold_min(page_type, usable_space as u16);
+    for idx in 0..cell_count {
+        let mut pos = page.read_u16(page.header_size() + idx * 2) as usize;
+        if pos < cells_start || pos >= buf.len() {
+            return Err(format!("cell {} starts outside of the cell area", idx));
+        }
+        let truncated = || format!("cell {} runs past the end of the page", idx);
+        if matches!(page_type, PageType::TableInterior | PageType::IndexInterior) {
+            if pos + 4 > buf.len() {
+                return Err(truncated());
+            }
+            check_child(read_u32(buf, pos))?;
+            pos += 4;
+        }
+        let (payload_size, n) = varint_at(pos).ok_or_else(truncated)?;
+        pos += n;
+        match page_type {
+            PageType::TableInterior => continue,
+            PageType::TableLeaf => pos += varint_at(pos).ok_or_else(truncated)?.1,
+            PageType::IndexInterior | PageType::IndexLeaf => {}
+        }
+        let payload_size = payload_size as usize;
+        let (overflows, local_size) =
+            payload_overflows(payload_size, max_local, min_local, usable_space);
+        if !overflows {
+            if pos + payload_size > buf.len() {
+                return Err(truncated());
+            }
+            check_record_header(&buf[pos..pos + payload_size])
+                .map_err(|reason| format!("cell {}: {}", idx, reason))?;
+        } else if pos + local_size > buf.len() {
+            return Err(truncated());
+        } else {
+            let first_overflow_page = read_u32(buf, pos + local_size - 4);
+            if first_overflow_page < 2 || first_overflow_page as usize > database_size {
+                return Err(format!("cell {}: overflow page out of range", idx));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks a b-tree page the first time it is used in untrusted mode, see
+/// [Pager::set_untrusted]. On top of [check_page], the cells and freeblocks must lie in the
+/// cell content area so that later writes to the page can't be misled by it either.
+/// Reads the pages on the paths from the root of a table btree to the leaves that hold
+/// `rowids`, so that seeking to them afterwards finds every page in the page cache. The pages
+/// of each level are read at the same time, so looking up N rows waits for as many reads as
+/// the btree is deep rather than N times that.
+pub(crate) fn prefetch_table_rows(pager: &Pager, root_page: usize, rowids: &[u64]) -> Result<()> {
+    let mut rowids = rowids.to_vec();
+    rowids.sort_unstable();
+    rowids.dedup();
+    let usable_space = pager.usable_space();
+    let mut level = vec![(root_page, rowids)];
+    while !level.is_empty() {
+        let pages = level
+            .into_iter()
+            .map(|(page_idx, rowids)| Ok((pager.read_page(page_idx)?, rowids)))
+            .collect::<Result<Vec<_>>>()?;
+        while pages.iter().any(|(page, _)| page.is_locked()) {
+            pager.io.run_once()?;
+        }
+        let mut next_level = Vec::new();
+        for (page, rowids) in pages {
+            if page.is_error() {
+                continue;
+            }
+            check_untrusted_page(pager, &page)?;
+            let contents = page.get().contents.as_ref().unwrap();
+            if contents.page_type() != PageType::TableInterior {
+                continue;
+            }
+            // Rowids up to and including the one of a cell are in its left child, the rest
+            // are in the rightmost child.
+            let mut rowids = rowids.as_slice();
+            for cell_idx in 0..contents.cell_count() {
+                if rowids.is_empty() {
+                    break;
+                }
+                let BTreeCell::TableInteriorCell(cell) = contents.cell_get(
+                    cell_idx,
+                    payload_overflow_threshold_max(contents.page_type(), usable_space as u16),
+                    payload_overflow_threshold_min(contents.page_type(), usable_space as u16),
+                    usable_space,
+                )?
+                else {
+                    unreachable!("interior table pages only have interior table cells");
+                };
+                let in_left_child = rowids.partition_point(|rowid| *rowid <= cell._rowid);
+                if in_left_child > 0 {
+                    let (left, rest) = rowids.split_at(in_left_child);
+                    next_level.push((cell._left_child_page as usize, left.to_vec()));
+                    rowids = rest;
+                }
+            }
+            if let (false, Some(rightmost)) = (rowids.is_empty(), contents.rightmost_pointer()) {
+                next_level.push((rightmost as usize, rowids.to_vec()));
+            }
+        }
+        level = next_level;
+    }
+    Ok(())
+}
+
+fn check_untrusted_page(pager: &Pager, page: &PageRef) -> Result<()> {
+    if !pager.untrusted() || page.is_checked() {
+        return Ok(());
+    }
+    let Some(contents) = page.get().contents.as_ref() else {
+        return Ok(());
+    };
+    let usable_space = pager.usable_space();
+    let database_size = pager.db_header.lock().database_size as usize;
+    check_page(contents, usable_space, database_size)
+        .and_then(|_| check_page_layout(contents, usable_space))
+        .map_err(|reason| LimboError::Corrupt(format!("page {}: {}", page.get().id, reason)))?;
+    page.set_checked();
+    Ok(())
+}
+
+/// Checks that the cell content area starts after the cell pointer array, that every cell
+/// starts inside it and that the freeblock list is well formed.
+pub(crate) fn check_page_layout(
+    page: &PageContent,
+    usable_space: usize,
+) -> std::result::Result<(), String> {
+    let cells_start = page.offset + page.header_size() + page.cell_count() * 2;
+    let content_area = match page.cell_content_area() {
+        0 => 65536,
+        start => start as usize,
+    };
+    if content_area < cells_start || content_area > usable_space {
+        return Err(format!("cell content area starts at {}", content_area));
+    }
+    for idx in 0..page.cell_count() {
+        if (page.read_u16(page.header_size() + idx * 2) as usize) < content_area {
+            return Err(format!("cell {} starts before the cell content area", idx));
+        }
+    }
+    match compute_free_space(page, usable_space as u16) {
+        Ok(_) => Ok(()),
+        Err(LimboError::Corrupt(reason)) => Err(reason),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Checks that the column types of a record add up to its size.
+fn check_record_header(payload: &[u8]) -> std::result::Result<(), String> {
+    let invalid = || "invalid record header".to_string();
+    let (header_size, mut pos) = read_varint(payload).map_err(|_| invalid())?;
+    let header_size = header_size as usize;
+    if header_size < pos || header_size > payload.len() {
+        return Err(invalid());
+    }
+    let mut body_size = 0;
+    while pos < header_size {
+        let (serial_type, n) = read_varint(&payload[pos..header_size]).map_err(|_| invalid())?;
+        pos += n;
+        body_size += match serial_type {
+            0 | 8 | 9 => 0,
+            1..=4 => serial_type as usize,
+            5 => 6,
+            6 | 7 => 8,
+            10 | 11 => return Err(format!("invalid serial type {}", serial_type)),
+            _ => (serial_type as usize - 12) / 2,
+        };
+    }
+    if pos != header_size || header_size + body_size != payload.len() {
+        return Err(invalid());
+    }
+    Ok(())
+}
+
 /// Drop a cell from a page.
 /// This is done by freeing the range of bytes that the cell occupies.
 fn drop_cell(page: &mut PageContent, cell_idx: usize, usable_space: u16) -> Result<()> {
@@ -3786,7 +4777,7 @@ fn drop_cell(page: &mut PageContent, cell_idx: usize, usable_space: u16) -> Resu
         payload_overflow_threshold_max(page.page_type(), usable_space),
         payload_overflow_threshold_min(page.page_type(), usable_space),
         usable_space as usize,
-    );
+    )?;
     free_cell_range(page, cell_start as u16, cell_len as u16, usable_space)?;
     if page.cell_count() > 1 {
         shift_pointers_left(page, cell_idx);
@@ -3805,12 +4796,12 @@ fn drop_cell(page: &mut PageContent, cell_idx: usize, usable_space: u16) -> Resu
 /// the empty space that's not needed
 fn shift_pointers_left(page: &mut PageContent, cell_idx: usize) {
     assert!(page.cell_count() > 0);
-    let buf = page.as_ptr();
     let (start, _) = page.cell_pointer_array_offset_and_size();
     let start = start + (cell_idx * 2) + 2;
     let right_cells = page.cell_count() - cell_idx - 1;
     let amount_to_shift = right_cells * 2;
-    buf.copy_within(start..start + amount_to_shift, start - 2);
+    page.as_mut_slice()
+        .copy_within(start..start + amount_to_shift, start - 2);
 }
 
 #[cfg(test)]
@@ -3827,13 +4818,13 @@ mod tests {
     use crate::storage::page_cache::DumbLruPageCache;
     use crate::storage::sqlite3_ondisk;
     use crate::storage::sqlite3_ondisk::DatabaseHeader;
+    use crate::storage::wal::CheckpointStatus;
     use crate::types::Text;
     use crate::vdbe::Register;
     use crate::Connection;
     use crate::{BufferPool, DatabaseStorage, WalFile, WalFileShared, WriteCompletion};
     use std::cell::RefCell;
     use std::collections::HashSet;
-    use std::mem::transmute;
     use std::ops::Deref;
     use std::panic;
     use std::rc::Rc;
@@ -3849,7 +4840,7 @@ mod tests {
                 payload_overflow_threshold_min,
             },
             pager::PageRef,
-            sqlite3_ondisk::{BTreeCell, PageContent, PageType},
+            sqlite3_ondisk::{BTreeCell, PageContent, PageSlice, PageType},
         },
         types::OwnedValue,
         Database, Page, Pager, PlatformIO,
@@ -3858,12 +4849,23 @@ mod tests {
     use super::{btree_init_page, defragment_page, drop_cell, insert_into_cell};
 
     #[allow(clippy::arc_with_non_send_sync)]
+    fn page_slice(bytes: &[u8]) -> PageSlice {
+        let buffer = Buffer::new(BufferData::new(bytes.to_vec()), Rc::new(|_| {}));
+        PageSlice::new(Arc::new(RefCell::new(buffer)), 0, bytes.len())
+    }
+
     fn get_page(id: usize) -> PageRef {
+        get_page_at_offset(id, 0)
+    }
+
+    /// A table leaf page whose b-tree header is at `offset`, like on page 1.
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn get_page_at_offset(id: usize, offset: usize) -> PageRef {
         let page = Arc::new(Page::new(id));
 
         let drop_fn = Rc::new(|_| {});
         let inner = PageContent {
-            offset: 0,
+            offset,
             buffer: Arc::new(RefCell::new(Buffer::new(
                 BufferData::new(vec![0; 4096]),
                 drop_fn,
@@ -3872,7 +4874,7 @@ mod tests {
         };
         page.get().contents.replace(inner);
 
-        btree_init_page(&page, PageType::TableLeaf, 0, 4096);
+        btree_init_page(&page, PageType::TableLeaf, offset, 4096);
         page
     }
 
@@ -3893,14 +4895,17 @@ mod tests {
     }
 
     fn ensure_cell(page: &mut PageContent, cell_idx: usize, payload: &Vec<u8>) {
-        let cell = page.cell_get_raw_region(
-            cell_idx,
-            payload_overflow_threshold_max(page.page_type(), 4096),
-            payload_overflow_threshold_min(page.page_type(), 4096),
-            4096,
-        );
+        let cell = page
+            .cell_get_raw_region(
+                cell_idx,
+                payload_overflow_threshold_max(page.page_type(), 4096),
+                payload_overflow_threshold_min(page.page_type(), 4096),
+                4096,
+            )
+            .unwrap();
         tracing::trace!("cell idx={} start={} len={}", cell_idx, cell.0, cell.1);
-        let buf = &page.as_ptr()[cell.0..cell.0 + cell.1];
+        let buf = page.as_slice();
+        let buf = &buf[cell.0..cell.0 + cell.1];
         assert_eq!(buf.len(), payload.len());
         assert_eq!(buf, payload);
     }
@@ -3936,7 +4941,7 @@ mod tests {
             ImmutableRecord::from_registers(&[Register::OwnedValue(OwnedValue::Integer(1))]);
         let payload = add_record(1, 0, page, record, &conn);
         assert_eq!(page.cell_count(), 1);
-        let free = compute_free_space(page, 4096);
+        let free = compute_free_space(page, 4096).unwrap();
         assert_eq!(free, 4096 - payload.len() as u16 - 2 - header_size);
 
         let cell_idx = 0;
@@ -3966,7 +4971,7 @@ mod tests {
             )]);
             let payload = add_record(i, i, page, record, &conn);
             assert_eq!(page.cell_count(), i + 1);
-            let free = compute_free_space(page, usable_space);
+            let free = compute_free_space(page, usable_space).unwrap();
             total_size += payload.len() as u16 + 2;
             assert_eq!(free, 4096 - total_size - header_size);
             cells.push(Cell { pos: i, payload });
@@ -4333,7 +5338,7 @@ mod tests {
             )]);
             let payload = add_record(i, i, page, record, &conn);
             assert_eq!(page.cell_count(), i + 1);
-            let free = compute_free_space(page, usable_space);
+            let free = compute_free_space(page, usable_space).unwrap();
             total_size += payload.len() as u16 + 2;
             assert_eq!(free, 4096 - total_size - header_size);
             cells.push(Cell { pos: i, payload });
@@ -4391,6 +5396,110 @@ mod tests {
         btree_insert_fuzz_run(64, 32, |rng| (rng.next_u32() % 32 * 1024) as usize);
     }
 
+    #[test]
+    pub fn btree_index_insert_split() {
+        let (pager, _) = empty_btree();
+        let index_page = pager.allocate_page().unwrap();
+        btree_init_page(&index_page, PageType::IndexLeaf, 0, 4096);
+        let root_page = index_page.get().id;
+        let mut cursor = BTreeCursor::new(None, pager.clone(), root_page);
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut keys = (0..2000).collect::<Vec<i64>>();
+        for i in (1..keys.len()).rev() {
+            keys.swap(i, (rng.next_u64() % (i as u64 + 1)) as usize);
+        }
+        for key in keys.iter() {
+            let record = ImmutableRecord::from_registers(&[
+                Register::OwnedValue(OwnedValue::build_text(&format!("name{:05}", key))),
+                Register::OwnedValue(OwnedValue::Integer(*key)),
+            ]);
+            run_until_done(
+                || cursor.insert(&BTreeKey::new_index_key(&record), false),
+                pager.deref(),
+            )
+            .unwrap();
+        }
+        run_until_done(|| cursor.rewind(), pager.deref()).unwrap();
+        for expected in 0..2000 {
+            assert!(!cursor.is_empty(), "index ended before key {}", expected);
+            {
+                let record = cursor.record();
+                let values = record.as_ref().unwrap().get_values();
+                assert_eq!(values[1], RefValue::Integer(expected));
+            }
+            run_until_done(|| cursor.next(), pager.deref()).unwrap();
+        }
+        assert!(cursor.is_empty());
+    }
+
+    #[test]
+    pub fn btree_index_insert_sequential() {
+        let (pager, _) = empty_btree();
+        let index_page = pager.allocate_page().unwrap();
+        btree_init_page(&index_page, PageType::IndexLeaf, 0, 4096);
+        let root_page = index_page.get().id;
+        let mut cursor = BTreeCursor::new(None, pager.clone(), root_page);
+        for key in 0..5000 {
+            let record = ImmutableRecord::from_registers(&[
+                Register::OwnedValue(OwnedValue::build_text(&format!("name{:05}", key))),
+                Register::OwnedValue(OwnedValue::Integer(key)),
+            ]);
+            run_until_done(
+                || cursor.insert(&BTreeKey::new_index_key(&record), false),
+                pager.deref(),
+            )
+            .unwrap();
+        }
+        run_until_done(|| cursor.rewind(), pager.deref()).unwrap();
+        for expected in 0..5000 {
+            assert!(!cursor.is_empty(), "index ended before key {}", expected);
+            {
+                let record = cursor.record();
+                let values = record.as_ref().unwrap().get_values();
+                assert_eq!(values[1], RefValue::Integer(expected));
+            }
+            run_until_done(|| cursor.next(), pager.deref()).unwrap();
+        }
+        assert!(cursor.is_empty());
+    }
+
+    #[test]
+    pub fn btree_index_iterate_backwards() {
+        let (pager, _) = empty_btree();
+        let index_page = pager.allocate_page().unwrap();
+        btree_init_page(&index_page, PageType::IndexLeaf, 0, 4096);
+        let root_page = index_page.get().id;
+        let mut cursor = BTreeCursor::new(None, pager.clone(), root_page);
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut keys = (0..2000).collect::<Vec<i64>>();
+        for i in (1..keys.len()).rev() {
+            keys.swap(i, (rng.next_u64() % (i as u64 + 1)) as usize);
+        }
+        let padding = "x".repeat(20);
+        for key in keys.iter() {
+            let record = ImmutableRecord::from_registers(&[
+                Register::OwnedValue(OwnedValue::build_text(&format!("{:05}{}", key, padding))),
+                Register::OwnedValue(OwnedValue::Integer(*key)),
+            ]);
+            run_until_done(
+                || cursor.insert(&BTreeKey::new_index_key(&record), false),
+                pager.deref(),
+            )
+            .unwrap();
+        }
+        run_until_done(|| cursor.last(), pager.deref()).unwrap();
+        for expected in (0..2000).rev() {
+            assert!(!cursor.is_empty(), "index ended before key {}", expected);
+            {
+                let record = cursor.record();
+                let values = record.as_ref().unwrap().get_values();
+                assert_eq!(values[1], RefValue::Integer(expected));
+            }
+            run_until_done(|| cursor.prev(), pager.deref()).unwrap();
+        }
+        assert!(cursor.is_empty());
+    }
+
     #[allow(clippy::arc_with_non_send_sync)]
     fn setup_test_env(database_size: u32) -> (Rc<Pager>, Arc<SpinLock<DatabaseHeader>>) {
         let page_size = 512;
@@ -4492,8 +5601,7 @@ mod tests {
                 };
                 contents.write_u32(0, next_page); // Write pointer to next overflow page
 
-                let buf = contents.as_ptr();
-                buf[4..].fill(b'A');
+                contents.as_mut_slice()[4..].fill(b'A');
             }
 
             current_page += 1;
@@ -4503,7 +5611,7 @@ mod tests {
         // Create leaf cell pointing to start of overflow chain
         let leaf_cell = BTreeCell::TableLeafCell(TableLeafCell {
             _rowid: 1,
-            _payload: unsafe { transmute::<&[u8], &'static [u8]>(large_payload.as_slice()) },
+            _payload: page_slice(&large_payload),
             first_overflow_page: Some(2), // Point to first overflow page
             payload_size: large_payload.len() as u64,
         });
@@ -4559,7 +5667,7 @@ mod tests {
         // Create leaf cell with no overflow pages
         let leaf_cell = BTreeCell::TableLeafCell(TableLeafCell {
             _rowid: 1,
-            _payload: unsafe { transmute::<&[u8], &'static [u8]>(small_payload.as_slice()) },
+            _payload: page_slice(&small_payload),
             first_overflow_page: None,
             payload_size: small_payload.len() as u64,
         });
@@ -4693,7 +5801,7 @@ mod tests {
             )]);
             let payload = add_record(i, i, page, record, &conn);
             assert_eq!(page.cell_count(), i + 1);
-            let free = compute_free_space(page, usable_space);
+            let free = compute_free_space(page, usable_space).unwrap();
             total_size += payload.len() as u16 + 2;
             assert_eq!(free, 4096 - total_size - header_size);
             cells.push(Cell { pos: i, payload });
@@ -4709,7 +5817,7 @@ mod tests {
             ensure_cell(page, i, &cell.payload);
         }
 
-        defragment_page(page, usable_space);
+        defragment_page(page, usable_space).unwrap();
 
         for (i, cell) in cells.iter().enumerate() {
             ensure_cell(page, i, &cell.payload);
@@ -4735,7 +5843,7 @@ mod tests {
             )]);
             let payload = add_record(i, i, page, record, &conn);
             assert_eq!(page.cell_count(), i + 1);
-            let free = compute_free_space(page, usable_space);
+            let free = compute_free_space(page, usable_space).unwrap();
             total_size += payload.len() as u16 + 2;
             assert_eq!(free, 4096 - total_size - header_size);
             cells.push(Cell { pos: i, payload });
@@ -4756,7 +5864,7 @@ mod tests {
             ensure_cell(page, i, &cell.payload);
         }
 
-        defragment_page(page, usable_space);
+        defragment_page(page, usable_space).unwrap();
 
         for (i, cell) in cells.iter().enumerate() {
             ensure_cell(page, i, &cell.payload);
@@ -4787,7 +5895,7 @@ mod tests {
                 0 => {
                     // allow appends with extra place to insert
                     let cell_idx = rng.next_u64() as usize % (page.cell_count() + 1);
-                    let free = compute_free_space(page, usable_space);
+                    let free = compute_free_space(page, usable_space).unwrap();
                     let record = ImmutableRecord::from_registers(&[Register::OwnedValue(
                         OwnedValue::Integer(i as i64),
                     )]);
@@ -4814,22 +5922,24 @@ mod tests {
                         continue;
                     }
                     let cell_idx = rng.next_u64() as usize % page.cell_count();
-                    let (_, len) = page.cell_get_raw_region(
-                        cell_idx,
-                        payload_overflow_threshold_max(page.page_type(), 4096),
-                        payload_overflow_threshold_min(page.page_type(), 4096),
-                        usable_space as usize,
-                    );
+                    let (_, len) = page
+                        .cell_get_raw_region(
+                            cell_idx,
+                            payload_overflow_threshold_max(page.page_type(), 4096),
+                            payload_overflow_threshold_min(page.page_type(), 4096),
+                            usable_space as usize,
+                        )
+                        .unwrap();
                     drop_cell(page, cell_idx, usable_space).unwrap();
                     total_size -= len as u16 + 2;
                     cells.remove(cell_idx);
                 }
                 2 => {
-                    defragment_page(page, usable_space);
+                    defragment_page(page, usable_space).unwrap();
                 }
                 _ => unreachable!(),
             }
-            let free = compute_free_space(page, usable_space);
+            let free = compute_free_space(page, usable_space).unwrap();
             assert_eq!(free, 4096 - total_size - header_size);
         }
     }
@@ -4858,7 +5968,7 @@ mod tests {
                     0 => {
                         // allow appends with extra place to insert
                         let cell_idx = rng.next_u64() as usize % (page.cell_count() + 1);
-                        let free = compute_free_space(page, usable_space);
+                        let free = compute_free_space(page, usable_space).unwrap();
                         let record = ImmutableRecord::from_registers(&[Register::OwnedValue(
                             OwnedValue::Integer(i as i64),
                         )]);
@@ -4885,22 +5995,24 @@ mod tests {
                             continue;
                         }
                         let cell_idx = rng.next_u64() as usize % page.cell_count();
-                        let (_, len) = page.cell_get_raw_region(
-                            cell_idx,
-                            payload_overflow_threshold_max(page.page_type(), 4096),
-                            payload_overflow_threshold_min(page.page_type(), 4096),
-                            usable_space as usize,
-                        );
+                        let (_, len) = page
+                            .cell_get_raw_region(
+                                cell_idx,
+                                payload_overflow_threshold_max(page.page_type(), 4096),
+                                payload_overflow_threshold_min(page.page_type(), 4096),
+                                usable_space as usize,
+                            )
+                            .unwrap();
                         drop_cell(page, cell_idx, usable_space).unwrap();
                         total_size -= len as u16 + 2;
                         cells.remove(cell_idx);
                     }
                     2 => {
-                        defragment_page(page, usable_space);
+                        defragment_page(page, usable_space).unwrap();
                     }
                     _ => unreachable!(),
                 }
-                let free = compute_free_space(page, usable_space);
+                let free = compute_free_space(page, usable_space).unwrap();
                 assert_eq!(free, 4096 - total_size - header_size);
             }
         }
@@ -4918,7 +6030,7 @@ mod tests {
         let record =
             ImmutableRecord::from_registers(&[Register::OwnedValue(OwnedValue::Integer(0))]);
         let payload = add_record(0, 0, page, record, &conn);
-        let free = compute_free_space(page, usable_space);
+        let free = compute_free_space(page, usable_space).unwrap();
         assert_eq!(free, 4096 - payload.len() as u16 - 2 - header_size);
     }
 
@@ -4936,18 +6048,175 @@ mod tests {
         let payload = add_record(0, 0, page, record, &conn);
 
         assert_eq!(page.cell_count(), 1);
-        defragment_page(page, usable_space);
+        defragment_page(page, usable_space).unwrap();
         assert_eq!(page.cell_count(), 1);
-        let (start, len) = page.cell_get_raw_region(
-            0,
-            payload_overflow_threshold_max(page.page_type(), 4096),
-            payload_overflow_threshold_min(page.page_type(), 4096),
-            usable_space as usize,
-        );
-        let buf = page.as_ptr();
+        let (start, len) = page
+            .cell_get_raw_region(
+                0,
+                payload_overflow_threshold_max(page.page_type(), 4096),
+                payload_overflow_threshold_min(page.page_type(), 4096),
+                usable_space as usize,
+            )
+            .unwrap();
+        let buf = page.as_slice();
         assert_eq!(&payload, &buf[start..start + len]);
     }
 
+    fn count_freeblocks(page: &PageContent) -> usize {
+        let mut count = 0;
+        let mut pc = page.first_freeblock() as usize;
+        while pc != 0 {
+            count += 1;
+            pc = page.read_u16_no_offset(pc) as usize;
+        }
+        count
+    }
+
+    #[test]
+    pub fn test_free_cell_range_coalesces_freeblocks() {
+        let db = get_database();
+        let conn = db.connect().unwrap();
+
+        let page = get_page(2);
+        let page = page.get_contents();
+        let usable_space = 4096;
+
+        let mut payloads = Vec::new();
+        for i in 0..5 {
+            let record = ImmutableRecord::from_registers(&[Register::OwnedValue(
+                OwnedValue::Integer(i as i64),
+            )]);
+            payloads.push(add_record(i, i, page, record, &conn));
+        }
+        let free = compute_free_space(page, usable_space).unwrap();
+
+        // Cells are allocated from the end of the page, so cells 1, 2 and 3 are adjacent.
+        drop_cell(page, 2, usable_space).unwrap();
+        assert_eq!(count_freeblocks(page), 1);
+        drop_cell(page, 2, usable_space).unwrap();
+        assert_eq!(count_freeblocks(page), 1);
+        drop_cell(page, 1, usable_space).unwrap();
+        assert_eq!(count_freeblocks(page), 1);
+        let freed: usize = payloads[1..4].iter().map(|p| p.len() + 2).sum();
+        assert_eq!(
+            compute_free_space(page, usable_space).unwrap() as usize,
+            free as usize + freed
+        );
+
+        ensure_cell(page, 0, &payloads[0]);
+        ensure_cell(page, 1, &payloads[4]);
+    }
+
+    #[test]
+    pub fn test_free_cell_reuse_on_first_page() {
+        let db = get_database();
+        let conn = db.connect().unwrap();
+
+        // Freeblock offsets are from the start of the page, also on page 1 where the b-tree
+        // page starts after the database header.
+        let page = get_page_at_offset(1, DATABASE_HEADER_SIZE);
+        let page = page.get_contents();
+        let usable_space = 4096;
+
+        let record = |text: &str| {
+            ImmutableRecord::from_registers(&[Register::OwnedValue(OwnedValue::build_text(text))])
+        };
+        let first = add_record(1, 0, page, record("first"), &conn);
+        add_record(2, 1, page, record(&"x".repeat(100)), &conn);
+        let last = add_record(3, 2, page, record("last"), &conn);
+        drop_cell(page, 1, usable_space).unwrap();
+        assert_eq!(count_freeblocks(page), 1);
+
+        // The first cell only takes part of the freeblock, the second one takes what is left.
+        let small = add_record(4, 1, page, record(&"y".repeat(40)), &conn);
+        assert_eq!(count_freeblocks(page), 1);
+        let rest = page.read_u16_no_offset(page.first_freeblock() as usize + 2) as usize;
+        let filler = add_record(5, 2, page, record(&"z".repeat(rest - 4)), &conn);
+        assert_eq!(count_freeblocks(page), 0);
+
+        ensure_cell(page, 0, &first);
+        ensure_cell(page, 1, &small);
+        ensure_cell(page, 2, &filler);
+        ensure_cell(page, 3, &last);
+    }
+
+    #[test]
+    pub fn test_defragment_fast_path() {
+        let db = get_database();
+        let conn = db.connect().unwrap();
+
+        let page = get_page(2);
+        let page = page.get_contents();
+        let usable_space = 4096;
+
+        let mut payloads = Vec::new();
+        for i in 0..6 {
+            let record = ImmutableRecord::from_registers(&[Register::OwnedValue(
+                OwnedValue::Integer(i as i64),
+            )]);
+            payloads.push(add_record(i, i, page, record, &conn));
+        }
+        drop_cell(page, 4, usable_space).unwrap();
+        drop_cell(page, 1, usable_space).unwrap();
+        payloads.remove(4);
+        payloads.remove(1);
+        assert_eq!(count_freeblocks(page), 2);
+        let free = compute_free_space(page, usable_space).unwrap();
+
+        defragment_page_fast(page, usable_space, 0).unwrap();
+        assert_eq!(count_freeblocks(page), 0);
+        assert_eq!(compute_free_space(page, usable_space).unwrap(), free);
+        for (i, payload) in payloads.iter().enumerate() {
+            ensure_cell(page, i, payload);
+        }
+    }
+
+    #[test]
+    pub fn test_fragmented_page_keeps_accepting_inserts() {
+        let db = get_database();
+        let conn = db.connect().unwrap();
+
+        let page = get_page(2);
+        let page = page.get_contents();
+        let usable_space = 4096;
+
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let mut cells: Vec<Vec<u8>> = Vec::new();
+        for i in 0..5000 {
+            if rng.next_u64() % 2 == 0 && !cells.is_empty() {
+                let cell_idx = rng.next_u64() as usize % cells.len();
+                drop_cell(page, cell_idx, usable_space).unwrap();
+                cells.remove(cell_idx);
+                continue;
+            }
+            // Cells of varying sizes leave freeblocks and fragments that new cells don't fill.
+            let len = 1 + (rng.next_u64() % 300) as usize;
+            let record = ImmutableRecord::from_registers(&[Register::OwnedValue(
+                OwnedValue::Text(Text::new(&"a".repeat(len))),
+            )]);
+            let mut payload: Vec<u8> = Vec::new();
+            fill_cell_payload(
+                page.page_type(),
+                Some(i),
+                &mut payload,
+                &record,
+                4096,
+                conn.pager.clone(),
+            );
+            if (compute_free_space(page, usable_space).unwrap() as usize) < payload.len() + 2 {
+                continue;
+            }
+            let cell_idx = rng.next_u64() as usize % (cells.len() + 1);
+            insert_into_cell(page, &payload, cell_idx, usable_space).unwrap();
+            assert!(page.overflow_cells.is_empty());
+            cells.insert(cell_idx, payload);
+        }
+        assert_eq!(page.cell_count(), cells.len());
+        for (i, payload) in cells.iter().enumerate() {
+            ensure_cell(page, i, payload);
+        }
+    }
+
     #[test]
     pub fn test_insert_drop_insert() {
         let db = get_database();
@@ -4972,16 +6241,47 @@ mod tests {
         let payload = add_record(0, 0, page, record, &conn);
         assert_eq!(page.cell_count(), 1);
 
-        let (start, len) = page.cell_get_raw_region(
-            0,
-            payload_overflow_threshold_max(page.page_type(), 4096),
-            payload_overflow_threshold_min(page.page_type(), 4096),
-            usable_space as usize,
-        );
-        let buf = page.as_ptr();
+        let (start, len) = page
+            .cell_get_raw_region(
+                0,
+                payload_overflow_threshold_max(page.page_type(), 4096),
+                payload_overflow_threshold_min(page.page_type(), 4096),
+                usable_space as usize,
+            )
+            .unwrap();
+        let buf = page.as_slice();
         assert_eq!(&payload, &buf[start..start + len]);
     }
 
+    #[test]
+    pub fn test_cell_payload_outlives_page() {
+        let db = get_database();
+        let conn = db.connect().unwrap();
+
+        let page = get_page(2);
+        let record = ImmutableRecord::from_registers(&[
+            Register::OwnedValue(OwnedValue::Integer(0)),
+            Register::OwnedValue(OwnedValue::Text(Text::new("aaaaaaaa"))),
+        ]);
+        let _ = add_record(0, 0, page.get_contents(), record.clone(), &conn);
+        let cell = page
+            .get_contents()
+            .cell_get(
+                0,
+                payload_overflow_threshold_max(PageType::TableLeaf, 4096),
+                payload_overflow_threshold_min(PageType::TableLeaf, 4096),
+                4096,
+            )
+            .unwrap();
+
+        // Evicting the page drops its contents, the cell keeps its buffer.
+        page.get().contents.take();
+        let BTreeCell::TableLeafCell(cell) = cell else {
+            panic!("expected a table leaf cell");
+        };
+        assert_eq!(&*cell._payload.as_slice(), record.get_payload());
+    }
+
     #[test]
     pub fn test_insert_drop_insert_multiple() {
         let db = get_database();
@@ -5007,13 +6307,15 @@ mod tests {
             let payload = add_record(0, 0, page, record, &conn);
             assert_eq!(page.cell_count(), 1);
 
-            let (start, len) = page.cell_get_raw_region(
-                0,
-                payload_overflow_threshold_max(page.page_type(), 4096),
-                payload_overflow_threshold_min(page.page_type(), 4096),
-                usable_space as usize,
-            );
-            let buf = page.as_ptr();
+            let (start, len) = page
+                .cell_get_raw_region(
+                    0,
+                    payload_overflow_threshold_max(page.page_type(), 4096),
+                    payload_overflow_threshold_min(page.page_type(), 4096),
+                    usable_space as usize,
+                )
+                .unwrap();
+            let buf = page.as_slice();
             assert_eq!(&payload, &buf[start..start + len]);
         }
     }
@@ -5061,7 +6363,7 @@ mod tests {
         let _ = add_record(0, 0, page, record, &conn);
         drop_cell(page, 0, usable_space).unwrap();
 
-        defragment_page(page, usable_space);
+        defragment_page(page, usable_space).unwrap();
 
         let record =
             ImmutableRecord::from_registers(&[Register::OwnedValue(OwnedValue::Integer(0))]);
@@ -5090,7 +6392,7 @@ mod tests {
             drop_cell(page, pos, usable_space).unwrap();
         };
         let defragment = |page| {
-            defragment_page(page, usable_space);
+            defragment_page(page, usable_space).unwrap();
         };
         defragment(page.get_contents());
         defragment(page.get_contents());
@@ -5111,7 +6413,7 @@ mod tests {
         insert(0, page.get_contents());
         drop(3, page.get_contents());
         drop(2, page.get_contents());
-        compute_free_space(page.get_contents(), usable_space);
+        compute_free_space(page.get_contents(), usable_space).unwrap();
     }
 
     #[test]
@@ -5130,7 +6432,7 @@ mod tests {
             drop_cell(page, pos, usable_space).unwrap();
         };
         let defragment = |page| {
-            defragment_page(page, usable_space);
+            defragment_page(page, usable_space).unwrap();
         };
         let record =
             ImmutableRecord::from_registers(&[Register::OwnedValue(OwnedValue::Integer(0))]);
@@ -5150,7 +6452,7 @@ mod tests {
         insert(0, page.get_contents());
         drop(2, page.get_contents());
         drop(0, page.get_contents());
-        let free = compute_free_space(page.get_contents(), usable_space);
+        let free = compute_free_space(page.get_contents(), usable_space).unwrap();
         let total_size = payload.len() + 2;
         assert_eq!(
             free,
@@ -5221,7 +6523,7 @@ mod tests {
             conn.pager.clone(),
         );
         insert_into_cell(page.get_contents(), &payload, 0, 4096).unwrap();
-        let free = compute_free_space(page.get_contents(), usable_space);
+        let free = compute_free_space(page.get_contents(), usable_space).unwrap();
         let total_size = payload.len() + 2;
         assert_eq!(
             free,
@@ -5230,6 +6532,279 @@ mod tests {
         dbg!(free);
     }
 
+    fn count_btree_pages(pager: &Rc<Pager>, page_idx: usize) -> usize {
+        let page = pager.read_page(page_idx).unwrap();
+        let contents = page.get_contents();
+        let mut children = Vec::new();
+        for cell_idx in 0..contents.cell_count() {
+            if let BTreeCell::TableInteriorCell(TableInteriorCell {
+                _left_child_page, ..
+            }) = contents
+                .cell_get(
+                    cell_idx,
+                    payload_overflow_threshold_max(contents.page_type(), 4096),
+                    payload_overflow_threshold_min(contents.page_type(), 4096),
+                    pager.usable_space(),
+                )
+                .unwrap()
+            {
+                children.push(_left_child_page as usize);
+            }
+        }
+        children.extend(contents.rightmost_pointer().map(|p| p as usize));
+        1 + children
+            .into_iter()
+            .map(|child| count_btree_pages(pager, child))
+            .sum::<usize>()
+    }
+
+    fn insert_rows(pager: &Rc<Pager>, root_page: usize, rows: std::ops::RangeInclusive<u64>) {
+        let value = ImmutableRecord::from_registers(&[Register::OwnedValue(OwnedValue::Text(
+            Text::new("hello world"),
+        ))]);
+        for i in rows {
+            let mut cursor = BTreeCursor::new(None, pager.clone(), root_page);
+            run_until_done(
+                || cursor.move_to(SeekKey::TableRowId(i), SeekOp::EQ),
+                pager.deref(),
+            )
+            .unwrap();
+            run_until_done(
+                || cursor.insert(&BTreeKey::new_table_rowid(i, Some(&value)), true),
+                pager.deref(),
+            )
+            .unwrap();
+        }
+    }
+
+    fn delete_row(pager: &Rc<Pager>, root_page: usize, rowid: u64) {
+        let mut cursor = BTreeCursor::new(None, pager.clone(), root_page);
+        let found = run_until_done(
+            || cursor.seek(SeekKey::TableRowId(rowid), SeekOp::EQ),
+            pager.deref(),
+        )
+        .unwrap();
+        assert!(found, "row {} not found", rowid);
+        run_until_done(|| cursor.delete(), pager.deref()).unwrap();
+    }
+
+    #[test]
+    pub fn test_delete_while_iterating() {
+        // Deleting rows while scanning the table, as DELETE does, must visit every row once
+        // even when deletions merge pages and move rows into the page of the cursor.
+        let (pager, root_page) = empty_btree();
+        let value = ImmutableRecord::from_registers(&[Register::OwnedValue(OwnedValue::Text(
+            Text::new(&"x".repeat(100)),
+        ))]);
+        for i in 1..=5000u64 {
+            let mut cursor = BTreeCursor::new(None, pager.clone(), root_page);
+            run_until_done(
+                || cursor.move_to(SeekKey::TableRowId(i), SeekOp::EQ),
+                pager.deref(),
+            )
+            .unwrap();
+            run_until_done(
+                || cursor.insert(&BTreeKey::new_table_rowid(i, Some(&value)), true),
+                pager.deref(),
+            )
+            .unwrap();
+        }
+
+        let mut cursor = BTreeCursor::new(None, pager.clone(), root_page);
+        run_until_done(|| cursor.rewind(), pager.deref()).unwrap();
+        let mut visited = Vec::new();
+        while !cursor.is_empty() {
+            let rowid = cursor.rowid().unwrap().unwrap();
+            visited.push(rowid);
+            if rowid % 3 != 0 || rowid > 4000 {
+                run_until_done(|| cursor.delete(), pager.deref()).unwrap();
+            }
+            run_until_done(|| cursor.next(), pager.deref()).unwrap();
+        }
+        assert_eq!(visited, (1..=5000).collect::<Vec<_>>());
+
+        let (_, valid) = validate_btree(pager.clone(), root_page);
+        assert!(valid, "invalid b-tree after deleting");
+        let mut cursor = BTreeCursor::new(None, pager.clone(), root_page);
+        run_until_done(|| cursor.rewind(), pager.deref()).unwrap();
+        for expected in (3..=4000).step_by(3) {
+            assert_eq!(cursor.rowid().unwrap(), Some(expected));
+            run_until_done(|| cursor.next(), pager.deref()).unwrap();
+        }
+        assert!(cursor.is_empty());
+    }
+
+    #[test]
+    pub fn test_append_stays_on_rightmost_leaf() {
+        // Inserting like NewRowid does, through a single cursor, only descends from the root after a split.
+        let (pager, root_page) = empty_btree();
+        let value = ImmutableRecord::from_registers(&[Register::OwnedValue(OwnedValue::Text(
+            Text::new("hello world"),
+        ))]);
+        let mut cursor = BTreeCursor::new(None, pager.clone(), root_page);
+        for i in 1..=10000u64 {
+            run_until_done(|| cursor.seek_to_last(), pager.deref()).unwrap();
+            assert_eq!(cursor.rowid().unwrap().unwrap_or(0) + 1, i);
+            run_until_done(
+                || cursor.insert(&BTreeKey::new_table_rowid(i, Some(&value)), true),
+                pager.deref(),
+            )
+            .unwrap();
+        }
+        let (_, valid) = validate_btree(pager.clone(), root_page);
+        assert!(valid, "invalid b-tree after appending");
+        run_until_done(|| cursor.seek_to_last(), pager.deref()).unwrap();
+        assert_eq!(cursor.reusable_leaf_path().unwrap(), Some(true));
+
+        // Near-sequential seeks find the same rows whether or not they reuse the current leaf.
+        for i in (1..=10000u64).step_by(7) {
+            let found = run_until_done(
+                || cursor.seek(SeekKey::TableRowId(i), SeekOp::EQ),
+                pager.deref(),
+            )
+            .unwrap();
+            assert!(found, "row {} not found", i);
+            assert_eq!(cursor.rowid().unwrap(), Some(i));
+        }
+        let found = run_until_done(
+            || cursor.seek(SeekKey::TableRowId(10001), SeekOp::EQ),
+            pager.deref(),
+        )
+        .unwrap();
+        assert!(!found);
+    }
+
+    #[test]
+    pub fn test_scan_with_prefetch_after_cache_clear() {
+        // Read-ahead pages come from disk and compete for a small cache with the pages being scanned.
+        let (pager, root_page) = empty_btree();
+        let value = ImmutableRecord::from_registers(&[Register::OwnedValue(OwnedValue::Text(
+            Text::new(&"x".repeat(100)),
+        ))]);
+        let mut cursor = BTreeCursor::new(None, pager.clone(), root_page);
+        for i in 1..=3000u64 {
+            run_until_done(
+                || cursor.insert(&BTreeKey::new_table_rowid(i, Some(&value)), false),
+                pager.deref(),
+            )
+            .unwrap();
+        }
+        loop {
+            match pager.cacheflush().unwrap() {
+                CheckpointStatus::Done(_) => break,
+                CheckpointStatus::IO => pager.io.run_once().unwrap(),
+            }
+        }
+        pager.clear_page_cache();
+
+        let mut cursor = BTreeCursor::new(None, pager.clone(), root_page);
+        run_until_done(|| cursor.rewind(), pager.deref()).unwrap();
+        for expected in 1..=3000u64 {
+            assert!(!cursor.is_empty(), "table ended before row {}", expected);
+            assert_eq!(cursor.rowid().unwrap(), Some(expected));
+            run_until_done(|| cursor.next(), pager.deref()).unwrap();
+        }
+        assert!(cursor.is_empty());
+    }
+
+    #[test]
+    pub fn test_seek_backwards() {
+        let (pager, root_page) = empty_btree();
+        for i in (2..=20000u64).step_by(2) {
+            insert_rows(&pager, root_page, i..=i);
+        }
+
+        for key in [1u64, 2, 3, 1000, 1001, 7777, 19999, 20000, 20001, 30000] {
+            for op in [SeekOp::LT, SeekOp::LE] {
+                let expected = match op {
+                    SeekOp::LT => (1..key.min(20001)).rev().find(|i| i % 2 == 0),
+                    _ => (1..=key.min(20000)).rev().find(|i| i % 2 == 0),
+                };
+                let mut cursor = BTreeCursor::new(None, pager.clone(), root_page);
+                let found = run_until_done(
+                    || cursor.seek(SeekKey::TableRowId(key), op.clone()),
+                    pager.deref(),
+                )
+                .unwrap();
+                assert_eq!(found, expected.is_some(), "seek {:?} {}", op, key);
+                let Some(expected) = expected else {
+                    continue;
+                };
+                // Iterating backwards from the seek position visits every smaller row.
+                for rowid in (2..=expected).rev().step_by(2).take(300) {
+                    assert_eq!(
+                        cursor.rowid().unwrap(),
+                        Some(rowid),
+                        "seek {:?} {}",
+                        op,
+                        key
+                    );
+                    run_until_done(|| cursor.prev(), pager.deref()).unwrap();
+                }
+                if expected <= 600 {
+                    assert!(cursor.is_empty());
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_delete_merges_pages() {
+        let (pager, root_page) = empty_btree();
+        insert_rows(&pager, root_page, 1..=10000);
+        let pages_before = count_btree_pages(&pager, root_page);
+
+        // Keep one row in fifty, which fits in a small fraction of the pages.
+        for i in 1..=10000u64 {
+            if i % 50 != 0 {
+                delete_row(&pager, root_page, i);
+            }
+        }
+
+        let (_, valid) = validate_btree(pager.clone(), root_page);
+        assert!(valid, "invalid b-tree after deleting");
+        let pages_after = count_btree_pages(&pager, root_page);
+        assert!(
+            pages_after * 10 < pages_before,
+            "pages were not merged: {} before, {} after",
+            pages_before,
+            pages_after
+        );
+        assert_eq!(
+            pager.db_header.lock().freelist_pages as usize,
+            pages_before - pages_after
+        );
+
+        let mut cursor = BTreeCursor::new(None, pager.clone(), root_page);
+        run_until_done(|| cursor.rewind(), pager.deref()).unwrap();
+        for expected in (50..=10000).step_by(50) {
+            assert_eq!(cursor.rowid().unwrap(), Some(expected));
+            run_until_done(|| cursor.next(), pager.deref()).unwrap();
+        }
+        assert!(cursor.is_empty());
+    }
+
+    #[test]
+    pub fn test_delete_all_rows_shrinks_to_root() {
+        let (pager, root_page) = empty_btree();
+        insert_rows(&pager, root_page, 1..=5000);
+        assert!(count_btree_pages(&pager, root_page) > 1);
+
+        for i in (1..=5000u64).rev() {
+            delete_row(&pager, root_page, i);
+        }
+
+        let root = pager.read_page(root_page).unwrap();
+        let contents = root.get_contents();
+        assert_eq!(contents.page_type(), PageType::TableLeaf);
+        assert_eq!(contents.cell_count(), 0);
+
+        // The emptied tree can be filled again.
+        insert_rows(&pager, root_page, 1..=2000);
+        let (_, valid) = validate_btree(pager.clone(), root_page);
+        assert!(valid, "invalid b-tree after inserting again");
+    }
+
     #[test]
     pub fn test_delete_balancing() {
         // What does this test do:
@@ -34,11 +34,18 @@ impl DatabaseStorage for DatabaseFile {
     fn read_page(&self, page_idx: usize, c: Completion) -> Result<()> {
         let r = c.as_read();
         let size = r.buf().len();
-        assert!(page_idx > 0);
         if !(512..=65536).contains(&size) || size & (size - 1) != 0 {
             return Err(LimboError::NotADB);
         }
-        let pos = (page_idx - 1) * size;
+        let Some(pos) = page_idx
+            .checked_sub(1)
+            .and_then(|idx| idx.checked_mul(size))
+        else {
+            return Err(LimboError::Corrupt(format!(
+                "invalid page number {}",
+                page_idx
+            )));
+        };
         self.file.pread(pos, c)?;
         Ok(())
     }
@@ -0,0 +1,527 @@
+//! Consistency checks of the database file, for `PRAGMA integrity_check`, `PRAGMA quick_check`
+//! and `PRAGMA index_check`.
+//!
+//! The b-trees are walked page by page, checking that every page is well formed, that the keys
+//! of table b-trees are in order, that all leaves are at the same depth and that overflow
+//! chains have the length their payload needs. A check of the whole database also walks the
+//! freelist and reports pages that nothing refers to. Unless the check is a quick one, the
+//! entries of each index are compared against the rows of its table, both for rows missing from
+//! the index and for entries that no row has.
+
+use std::cmp::Ordering;
+
+use crate::storage::btree::{
+    check_page, check_page_layout, payload_overflow_threshold_max, payload_overflow_threshold_min,
+};
+use crate::storage::pager::{PageRef, Pager};
+use crate::storage::sqlite3_ondisk::{read_record, BTreeCell, PageType};
+use crate::types::{ImmutableRecord, OwnedValue};
+use crate::Result;
+
+/// An index whose entries are compared against the rows of its table.
+#[derive(Debug, Clone)]
+pub struct IndexCheck {
+    pub name: String,
+    pub root_page: usize,
+    pub table_root_page: usize,
+    /// The table column of each index column, `None` for the rowid.
+    pub columns: Vec<Option<usize>>,
+    pub unique: bool,
+}
+
+/// What to check, see [check_integrity].
+#[derive(Debug, Clone)]
+pub struct IntegrityCheck {
+    /// Root pages of the b-trees to walk.
+    pub roots: Vec<usize>,
+    /// Indexes to compare against their tables. Their b-trees and the ones of their tables
+    /// must be in `roots`.
+    pub indexes: Vec<IndexCheck>,
+    /// Whether `roots` covers every b-tree, so that the freelist is checked too and pages
+    /// that no b-tree refers to can be reported.
+    pub whole_database: bool,
+    /// The check stops once it has found this many problems.
+    pub max_errors: usize,
+}
+
+/// A row of a table b-tree or an entry of an index b-tree.
+struct Entry {
+    rowid: i64,
+    values: Vec<OwnedValue>,
+}
+
+/// Runs the check and returns a message for each problem found.
+pub fn check_integrity(pager: &Pager, check: &IntegrityCheck) -> Result<Vec<String>> {
+    let database_size = pager.db_header.lock().database_size as usize;
+    let mut checker = Checker {
+        pager,
+        usable_space: pager.usable_space(),
+        database_size,
+        referenced: vec![false; database_size + 1],
+        errors: Vec::new(),
+        max_errors: check.max_errors,
+    };
+    let mut entries: Vec<(usize, Vec<Entry>)> = Vec::new();
+    for &root in &check.roots {
+        let collect = check
+            .indexes
+            .iter()
+            .any(|index| index.root_page == root || index.table_root_page == root);
+        let tree_entries = checker.check_tree(root, collect)?;
+        if collect {
+            entries.push((root, tree_entries));
+        }
+    }
+    if check.whole_database {
+        checker.check_freelist()?;
+        checker.check_unreferenced();
+    }
+    for index in &check.indexes {
+        let find = |root: usize| entries.iter().find(|(r, _)| *r == root).map(|(_, e)| e);
+        if let (Some(rows), Some(index_entries)) =
+            (find(index.table_root_page), find(index.root_page))
+        {
+            checker.check_index(index, rows, index_entries);
+        }
+    }
+    Ok(checker.errors)
+}
+
+struct Checker<'a> {
+    pager: &'a Pager,
+    usable_space: usize,
+    database_size: usize,
+    /// Pages that something was found to refer to, indexed by page number.
+    referenced: Vec<bool>,
+    errors: Vec<String>,
+    max_errors: usize,
+}
+
+impl Checker<'_> {
+    fn done(&self) -> bool {
+        self.errors.len() >= self.max_errors
+    }
+
+    fn error(&mut self, message: String) {
+        if !self.done() {
+            self.errors.push(message);
+        }
+    }
+
+    /// Marks a page as referenced, or reports why it can't be.
+    fn reference(&mut self, context: &str, page_id: usize) -> bool {
+        if page_id < 1 || page_id > self.database_size {
+            self.error(format!("{}invalid page number {}", context, page_id));
+            return false;
+        }
+        if self.referenced[page_id] {
+            self.error(format!("{}2nd reference to page {}", context, page_id));
+            return false;
+        }
+        self.referenced[page_id] = true;
+        true
+    }
+
+    // read sync for now
+    fn read_page(&self, page_id: usize) -> Result<Option<PageRef>> {
+        let page = self.pager.read_page(page_id)?;
+        loop {
+            if page.is_locked() {
+                self.pager.io.run_once()?;
+            } else if page.is_error() {
+                return Ok(None);
+            } else if !page.is_loaded() {
+                self.pager.load_page(page.clone())?;
+            } else {
+                return Ok(Some(page));
+            }
+        }
+    }
+
+    fn check_tree(&mut self, root: usize, collect: bool) -> Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        if self.reference(&format!("Tree {}: ", root), root) {
+            let mut walk = TreeWalk {
+                root,
+                collect,
+                entries: &mut entries,
+                last_rowid: None,
+                is_table: None,
+            };
+            self.check_page_of_tree(&mut walk, root, None, None)?;
+        }
+        Ok(entries)
+    }
+
+    /// Checks a page of a b-tree and the pages below it, returning the depth of its leaves.
+    /// Rowids of table b-trees must be above `min_rowid` and at most `max_rowid`.
+    fn check_page_of_tree(
+        &mut self,
+        walk: &mut TreeWalk,
+        page_id: usize,
+        min_rowid: Option<i64>,
+        max_rowid: Option<i64>,
+    ) -> Result<Option<usize>> {
+        let context = format!("Tree {} page {}: ", walk.root, page_id);
+        if self.done() {
+            return Ok(None);
+        }
+        let Some(page) = self.read_page(page_id)? else {
+            self.error(format!("{}unable to read the page", context));
+            return Ok(None);
+        };
+        let contents = page.get_contents();
+        if let Err(reason) = check_page(contents, self.usable_space, self.database_size)
+            .and_then(|_| check_page_layout(contents, self.usable_space))
+        {
+            self.error(format!("{}{}", context, reason));
+            return Ok(None);
+        }
+        let page_type = contents.page_type();
+        let is_table = matches!(page_type, PageType::TableInterior | PageType::TableLeaf);
+        if *walk.is_table.get_or_insert(is_table) != is_table {
+            self.error(format!("{}unexpected page type {:?}", context, page_type));
+            return Ok(None);
+        }
+
+        let max_local = payload_overflow_threshold_max(page_type, self.usable_space as u16);
+        let min_local = payload_overflow_threshold_min(page_type, self.usable_space as u16);
+        // Reading other pages may evict this one, so copy the cells out before going further.
+        let mut cells = Vec::with_capacity(contents.cell_count());
+        for idx in 0..contents.cell_count() {
+            let cell = match contents.cell_get(idx, max_local, min_local, self.usable_space)? {
+                BTreeCell::TableInteriorCell(cell) => CellCopy {
+                    child: Some(cell._left_child_page as usize),
+                    rowid: Some(cell._rowid as i64),
+                    payload: None,
+                },
+                BTreeCell::TableLeafCell(cell) => CellCopy {
+                    child: None,
+                    rowid: Some(cell._rowid as i64),
+                    payload: Some((
+                        cell._payload.as_slice().to_vec(),
+                        cell.payload_size as usize,
+                        cell.first_overflow_page,
+                    )),
+                },
+                BTreeCell::IndexInteriorCell(cell) => CellCopy {
+                    child: Some(cell.left_child_page as usize),
+                    rowid: None,
+                    payload: Some((
+                        cell.payload.as_slice().to_vec(),
+                        cell.payload_size as usize,
+                        cell.first_overflow_page,
+                    )),
+                },
+                BTreeCell::IndexLeafCell(cell) => CellCopy {
+                    child: None,
+                    rowid: None,
+                    payload: Some((
+                        cell.payload.as_slice().to_vec(),
+                        cell.payload_size as usize,
+                        cell.first_overflow_page,
+                    )),
+                },
+            };
+            cells.push(cell);
+        }
+        let rightmost = contents.rightmost_pointer();
+        let is_leaf = contents.is_leaf();
+
+        let mut depth = None;
+        let mut lower = min_rowid;
+        for (idx, cell) in cells.into_iter().enumerate() {
+            if self.done() {
+                return Ok(None);
+            }
+            let cell_context = format!("Tree {} page {} cell {}: ", walk.root, page_id, idx);
+            let mut child_bounds = (None, None);
+            if let Some(rowid) = cell.rowid {
+                if is_leaf {
+                    if walk.last_rowid.is_some_and(|last| rowid <= last)
+                        || min_rowid.is_some_and(|min| rowid <= min)
+                        || max_rowid.is_some_and(|max| rowid > max)
+                    {
+                        self.error(format!("{}Rowid {} out of order", cell_context, rowid));
+                    }
+                    walk.last_rowid = Some(rowid);
+                } else {
+                    if lower.is_some_and(|lower| rowid < lower)
+                        || max_rowid.is_some_and(|max| rowid > max)
+                    {
+                        self.error(format!("{}Rowid {} out of order", cell_context, rowid));
+                    }
+                    child_bounds = (lower, Some(rowid));
+                    lower = Some(rowid);
+                }
+            }
+            if let Some(child) = cell.child {
+                let (min, max) = child_bounds;
+                let child_depth = self.check_child(walk, &cell_context, child, min, max)?;
+                self.check_depth(&cell_context, &mut depth, child_depth);
+            }
+            if let Some((local, payload_size, first_overflow_page)) = cell.payload {
+                let payload = self.check_payload(
+                    &cell_context,
+                    local,
+                    payload_size,
+                    first_overflow_page,
+                    walk.collect,
+                )?;
+                if let Some(payload) = payload {
+                    self.collect_entry(walk, &cell_context, cell.rowid.unwrap_or(0), &payload);
+                }
+            }
+        }
+        if let Some(rightmost) = rightmost {
+            let child_depth =
+                self.check_child(walk, &context, rightmost as usize, lower, max_rowid)?;
+            self.check_depth(&context, &mut depth, child_depth);
+        }
+        Ok(match depth {
+            Some(depth) => Some(depth + 1),
+            None if is_leaf => Some(0),
+            None => None,
+        })
+    }
+
+    fn check_child(
+        &mut self,
+        walk: &mut TreeWalk,
+        context: &str,
+        child: usize,
+        min_rowid: Option<i64>,
+        max_rowid: Option<i64>,
+    ) -> Result<Option<usize>> {
+        if !self.reference(context, child) {
+            return Ok(None);
+        }
+        self.check_page_of_tree(walk, child, min_rowid, max_rowid)
+    }
+
+    fn check_depth(
+        &mut self,
+        context: &str,
+        depth: &mut Option<usize>,
+        child_depth: Option<usize>,
+    ) {
+        match (*depth, child_depth) {
+            (Some(depth), Some(child_depth)) if depth != child_depth => {
+                self.error(format!("{}Child page depth differs", context));
+            }
+            (None, Some(_)) => *depth = child_depth,
+            _ => {}
+        }
+    }
+
+    /// Follows the overflow chain of a cell, returning the whole payload if asked to and the
+    /// chain is intact.
+    fn check_payload(
+        &mut self,
+        context: &str,
+        local: Vec<u8>,
+        payload_size: usize,
+        first_overflow_page: Option<u32>,
+        collect: bool,
+    ) -> Result<Option<Vec<u8>>> {
+        let local_size = local.len();
+        let mut payload = collect.then_some(local);
+        let Some(first_overflow_page) = first_overflow_page else {
+            return Ok(payload);
+        };
+        let expected = (payload_size.saturating_sub(local_size)).div_ceil(self.usable_space - 4);
+        let mut remaining = payload_size.saturating_sub(local_size);
+        let mut next = first_overflow_page as usize;
+        let mut found = 0;
+        while next != 0 && found < expected {
+            if !self.reference(context, next) {
+                return Ok(None);
+            }
+            let Some(page) = self.read_page(next)? else {
+                self.error(format!("{}unable to read overflow page {}", context, next));
+                return Ok(None);
+            };
+            let buf = page.get_contents().as_slice();
+            let to_read = remaining.min(self.usable_space - 4);
+            if let Some(payload) = payload.as_mut() {
+                payload.extend_from_slice(&buf[4..4 + to_read]);
+            }
+            remaining -= to_read;
+            found += 1;
+            next = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        }
+        if found != expected || next != 0 {
+            self.error(format!(
+                "{}overflow list length is {} but should be {}",
+                context,
+                if next != 0 { found + 1 } else { found },
+                expected
+            ));
+            return Ok(None);
+        }
+        Ok(payload)
+    }
+
+    fn collect_entry(&mut self, walk: &mut TreeWalk, context: &str, rowid: i64, payload: &[u8]) {
+        let mut record = ImmutableRecord::new(payload.len(), 8);
+        if let Err(e) = read_record(payload, &mut record) {
+            self.error(format!("{}{}", context, e));
+            return;
+        }
+        let values = record.get_values().iter().map(|v| v.to_owned()).collect();
+        walk.entries.push(Entry { rowid, values });
+    }
+
+    fn check_freelist(&mut self) -> Result<()> {
+        let (mut trunk, expected) = {
+            let header = self.pager.db_header.lock();
+            (
+                header.freelist_trunk_page as usize,
+                header.freelist_pages as usize,
+            )
+        };
+        let max_leaves = self.usable_space / 4 - 2;
+        let mut found = 0;
+        while trunk != 0 && !self.done() {
+            if !self.reference("Main freelist: ", trunk) {
+                return Ok(());
+            }
+            found += 1;
+            let Some(page) = self.read_page(trunk)? else {
+                self.error(format!("Main freelist: unable to read page {}", trunk));
+                return Ok(());
+            };
+            let buf = page.get_contents().as_slice();
+            let read_u32 = |pos: usize| {
+                u32::from_be_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]]) as usize
+            };
+            let leaves = read_u32(4);
+            if leaves > max_leaves {
+                self.error(format!(
+                    "Main freelist: leaf count too big on page {}",
+                    trunk
+                ));
+                return Ok(());
+            }
+            for i in 0..leaves {
+                if self.reference("Main freelist: ", read_u32(8 + i * 4)) {
+                    found += 1;
+                }
+            }
+            trunk = read_u32(0);
+        }
+        if found != expected && !self.done() {
+            self.error(format!(
+                "Main freelist: size is {} but should be {}",
+                found, expected
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_unreferenced(&mut self) {
+        // The page holding the byte at 1GiB is never used, as SQLite locks that byte.
+        let pending_byte_page = 0x40000000 / self.pager.db_header.lock().page_size as usize + 1;
+        for page_id in 1..=self.database_size {
+            if !self.referenced[page_id] && page_id != pending_byte_page {
+                self.error(format!("Page {} is never used", page_id));
+            }
+        }
+    }
+
+    /// Compares the entries of an index with the ones its table rows call for.
+    fn check_index(&mut self, index: &IndexCheck, rows: &[Entry], entries: &[Entry]) {
+        let compare =
+            |a: &Vec<OwnedValue>, b: &Vec<OwnedValue>| a.partial_cmp(b).unwrap_or(Ordering::Equal);
+        let mut expected: Vec<Vec<OwnedValue>> = rows
+            .iter()
+            .map(|row| {
+                let mut key: Vec<OwnedValue> = index
+                    .columns
+                    .iter()
+                    .map(|column| match column {
+                        Some(column) => {
+                            row.values.get(*column).cloned().unwrap_or(OwnedValue::Null)
+                        }
+                        None => OwnedValue::Integer(row.rowid),
+                    })
+                    .collect();
+                key.push(OwnedValue::Integer(row.rowid));
+                key
+            })
+            .collect();
+        expected.sort_by(compare);
+        let mut actual: Vec<&Vec<OwnedValue>> = entries.iter().map(|entry| &entry.values).collect();
+        actual.sort_by(|a, b| compare(a, b));
+
+        // Both are sorted, so they are merged and whatever only one of them has is reported.
+        let rowid = |key: &Vec<OwnedValue>| match key.last() {
+            Some(OwnedValue::Integer(rowid)) => *rowid,
+            _ => 0,
+        };
+        let mut actual_iter = actual.iter().peekable();
+        for key in &expected {
+            while let Some(entry) =
+                actual_iter.next_if(|entry| compare(entry, key) == Ordering::Less)
+            {
+                self.error(format!(
+                    "stray entry for row {} in index {}",
+                    rowid(entry),
+                    index.name
+                ));
+            }
+            if actual_iter
+                .next_if(|entry| compare(entry, key) == Ordering::Equal)
+                .is_none()
+            {
+                self.error(format!(
+                    "row {} missing from index {}",
+                    rowid(key),
+                    index.name
+                ));
+            }
+        }
+        for entry in actual_iter {
+            self.error(format!(
+                "stray entry for row {} in index {}",
+                rowid(entry),
+                index.name
+            ));
+        }
+        if index.unique {
+            let columns = index.columns.len();
+            for pair in actual.windows(2) {
+                let (a, b) = (
+                    &pair[0][..columns.min(pair[0].len())],
+                    &pair[1][..columns.min(pair[1].len())],
+                );
+                if a == b && !a.iter().any(|v| matches!(v, OwnedValue::Null)) {
+                    self.error(format!("non-unique entry in UNIQUE index {}", index.name));
+                }
+            }
+        }
+        if expected.len() != entries.len() {
+            self.error(format!("wrong # of entries in index {}", index.name));
+        }
+    }
+}
+
+/// A cell copied out of its page: the child it points to, its rowid and its local payload
+/// with the payload size and first overflow page.
+struct CellCopy {
+    child: Option<usize>,
+    rowid: Option<i64>,
+    payload: Option<(Vec<u8>, usize, Option<u32>)>,
+}
+
+/// State of the walk of a single b-tree.
+struct TreeWalk<'a> {
+    root: usize,
+    /// Whether the rows or index entries are kept, for index checks.
+    collect: bool,
+    entries: &'a mut Vec<Entry>,
+    /// The last rowid seen on a leaf of a table b-tree, which the next one must be above.
+    last_rowid: Option<i64>,
+    /// Whether the b-tree is a table or index b-tree, known once its root was read.
+    is_table: Option<bool>,
+}
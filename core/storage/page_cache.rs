@@ -1,8 +1,9 @@
-use std::{cell::RefCell, collections::HashMap, ptr::NonNull};
+use std::{cell::RefCell, collections::HashMap, ptr::NonNull, sync::Arc};
 
 use tracing::{debug, trace};
 
 use super::pager::PageRef;
+use crate::memory_limit::{MemoryLimit, MemoryPressure};
 
 // In limbo, page cache is shared by default, meaning that multiple frames from WAL can reside in
 // the cache, meaning, we need a way to differentiate between pages cached in different
@@ -36,6 +37,9 @@ pub struct DumbLruPageCache {
     map: RefCell<HashMap<PageCacheKey, NonNull<PageCacheEntry>>>,
     head: RefCell<Option<NonNull<PageCacheEntry>>>,
     tail: RefCell<Option<NonNull<PageCacheEntry>>>,
+    /// The limit cached pages count against, along with the size of a page, see
+    /// [Self::set_memory_limit].
+    memory_limit: Option<(Arc<MemoryLimit>, usize)>,
 }
 unsafe impl Send for DumbLruPageCache {}
 unsafe impl Sync for DumbLruPageCache {}
@@ -52,6 +56,45 @@ impl DumbLruPageCache {
             map: RefCell::new(HashMap::new()),
             head: RefCell::new(None),
             tail: RefCell::new(None),
+            memory_limit: None,
+        }
+    }
+
+    /// Counts every cached page as `page_size` bytes against `limit`, evicting pages to stay
+    /// under it even while the cache is below capacity.
+    pub(crate) fn set_memory_limit(&mut self, limit: Arc<MemoryLimit>, page_size: usize) {
+        if let Some((previous, page_size)) = &self.memory_limit {
+            previous.free(self.len() * page_size);
+        }
+        limit.allocate(self.len() * page_size);
+        self.memory_limit = Some((limit, page_size));
+    }
+
+    /// Evicts pages until there is room for `incoming` more bytes under the memory limit, or
+    /// until no page can be evicted.
+    pub(crate) fn release_memory(&mut self, incoming: usize) {
+        let Some((limit, _)) = &self.memory_limit else {
+            return;
+        };
+        let limit = limit.clone();
+        let mut evicted = 0;
+        while limit.would_exceed(incoming) && self.pop_evictable() {
+            evicted += 1;
+        }
+        if evicted > 0 {
+            limit.report(MemoryPressure::PageCache { pages: evicted });
+        }
+    }
+
+    fn page_allocated(&self) {
+        if let Some((limit, page_size)) = &self.memory_limit {
+            limit.allocate(*page_size);
+        }
+    }
+
+    fn page_freed(&self) {
+        if let Some((limit, page_size)) = &self.memory_limit {
+            limit.free(*page_size);
         }
     }
 
@@ -62,6 +105,14 @@ impl DumbLruPageCache {
     pub fn insert(&mut self, key: PageCacheKey, value: PageRef) {
         self._delete(key.clone(), false);
         trace!("cache_insert(key={:?})", key);
+        // Make room first, so that the page being inserted isn't the one evicted.
+        if self.len() >= self.capacity {
+            self.pop_evictable();
+        }
+        if let Some((_, page_size)) = &self.memory_limit {
+            self.release_memory(*page_size);
+        }
+        self.page_allocated();
         let entry = Box::new(PageCacheEntry {
             key: key.clone(),
             next: None,
@@ -73,9 +124,6 @@ impl DumbLruPageCache {
         self.touch(ptr);
 
         self.map.borrow_mut().insert(key, ptr);
-        if self.len() > self.capacity {
-            self.pop_if_not_dirty();
-        }
     }
 
     pub fn delete(&mut self, key: PageCacheKey) {
@@ -91,6 +139,7 @@ impl DumbLruPageCache {
         let ptr = ptr.unwrap();
         self.detach(ptr, clean_page);
         unsafe { std::ptr::drop_in_place(ptr.as_ptr()) };
+        self.page_freed();
     }
 
     fn get_ptr(&mut self, key: &PageCacheKey) -> Option<NonNull<PageCacheEntry>> {
@@ -177,20 +226,36 @@ impl DumbLruPageCache {
         self.head.borrow_mut().replace(entry);
     }
 
-    fn pop_if_not_dirty(&mut self) {
-        let tail = *self.tail.borrow();
-        if tail.is_none() {
-            return;
-        }
-        let mut tail = tail.unwrap();
-        let tail_entry = unsafe { tail.as_mut() };
-        if tail_entry.page.is_dirty() {
-            // TODO: drop from another clean entry?
-            return;
+    /// Evicts the least recently used page that can be, i.e. that isn't dirty, pinned or being
+    /// read. If there is none the cache stays over capacity until pages are written or unpinned.
+    /// Returns whether a page was evicted.
+    fn pop_evictable(&mut self) -> bool {
+        let mut entry = *self.tail.borrow();
+        while let Some(mut candidate) = entry {
+            let candidate_entry = unsafe { candidate.as_mut() };
+            let page = &candidate_entry.page;
+            if page.is_dirty() || page.is_pinned() || page.is_locked() {
+                entry = candidate_entry.prev;
+                continue;
+            }
+            tracing::debug!("pop_evictable(key={:?})", candidate_entry.key);
+            #[cfg(debug_assertions)]
+            if let Some(contents) = &page.get().contents {
+                // Cells read from the page hold on to its buffer, so one that is still around
+                // was read from a page that isn't pinned.
+                debug_assert!(
+                    std::sync::Arc::strong_count(&contents.buffer) == 1,
+                    "evicting page {} while cells of it are still referenced",
+                    page.get().id
+                );
+            }
+            self.detach(candidate, true);
+            assert!(self.map.borrow_mut().remove(&candidate_entry.key).is_some());
+            unsafe { std::ptr::drop_in_place(candidate.as_ptr()) };
+            self.page_freed();
+            return true;
         }
-        tracing::debug!("pop_if_not_dirty(key={:?})", tail_entry.key);
-        self.detach(tail, true);
-        assert!(self.map.borrow_mut().remove(&tail_entry.key).is_some());
+        false
     }
 
     pub fn clear(&mut self) {
@@ -210,6 +275,14 @@ impl DumbLruPageCache {
     }
 }
 
+impl Drop for DumbLruPageCache {
+    fn drop(&mut self) {
+        if let Some((limit, page_size)) = &self.memory_limit {
+            limit.free(self.len() * page_size);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{num::NonZeroUsize, sync::Arc};
@@ -220,7 +293,7 @@ mod tests {
         ChaCha8Rng,
     };
 
-    use crate::{storage::page_cache::DumbLruPageCache, Page};
+    use crate::{memory_limit::MemoryLimit, storage::page_cache::DumbLruPageCache, Page};
 
     use super::PageCacheKey;
 
@@ -327,6 +400,46 @@ mod tests {
         assert!(cache.get(&key2).is_none());
     }
 
+    #[test]
+    fn test_page_cache_skips_pinned_pages() {
+        let mut cache = DumbLruPageCache::new(2);
+        let key1 = insert_page(&mut cache, 1);
+        let page1 = cache.peek(&key1, false).unwrap();
+        page1.pin();
+        let key2 = insert_page(&mut cache, 2);
+        let key3 = insert_page(&mut cache, 3);
+        assert_eq!(cache.peek(&key1, false).unwrap().get().id, 1);
+        assert!(cache.peek(&key2, false).is_none());
+        assert_eq!(cache.peek(&key3, false).unwrap().get().id, 3);
+
+        page1.unpin();
+        let key4 = insert_page(&mut cache, 4);
+        assert!(cache.peek(&key1, false).is_none());
+        assert_eq!(cache.peek(&key4, false).unwrap().get().id, 4);
+    }
+
+    #[test]
+    fn test_page_cache_memory_limit() {
+        let memory = Arc::new(MemoryLimit::default());
+        let mut cache = DumbLruPageCache::new(10);
+        let key1 = insert_page(&mut cache, 1);
+        cache.set_memory_limit(memory.clone(), 100);
+        assert_eq!(memory.used(), 100);
+        memory.set_limit(250);
+        let key2 = insert_page(&mut cache, 2);
+        let key3 = insert_page(&mut cache, 3);
+        assert!(cache.peek(&key1, false).is_none());
+        assert!(cache.peek(&key2, false).is_some());
+        assert!(cache.peek(&key3, false).is_some());
+        assert_eq!(memory.used(), 200);
+
+        memory.set_limit(100);
+        cache.release_memory(0);
+        assert_eq!(cache.len(), 1);
+        drop(cache);
+        assert_eq!(memory.used(), 0);
+    }
+
     fn insert_page(cache: &mut DumbLruPageCache, id: usize) -> PageCacheKey {
         let key = PageCacheKey::new(id, None);
         #[allow(clippy::arc_with_non_send_sync)]
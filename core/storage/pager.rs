@@ -1,25 +1,30 @@
 use crate::fast_lock::SpinLock;
+use crate::memory_limit::{MemoryLimit, MemoryPressure};
 use crate::result::LimboResult;
 use crate::storage::buffer_pool::BufferPool;
 use crate::storage::database::DatabaseStorage;
 use crate::storage::sqlite3_ondisk::{self, DatabaseHeader, PageContent, PageType};
-use crate::storage::wal::{CheckpointResult, Wal};
-use crate::{Buffer, LimboError, Result};
+use crate::storage::wal::{CheckpointResult, Wal, WalCommit};
+use crate::{Buffer, Instant, LimboError, Result};
 use parking_lot::RwLock;
-use std::cell::{RefCell, UnsafeCell};
-use std::collections::HashSet;
+use std::cell::{Cell, RefCell, UnsafeCell};
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::trace;
 
 use super::page_cache::{DumbLruPageCache, PageCacheKey};
-use super::wal::{CheckpointMode, CheckpointStatus};
+use super::wal::{CheckpointMode, CheckpointStatus, LockingMode, SyncMode};
 
 pub struct PageInner {
     pub flags: AtomicUsize,
     pub contents: Option<PageContent>,
     pub id: usize,
+    /// Number of holders that read the page's contents across calls that may evict pages, see
+    /// [Page::pin].
+    pins: AtomicUsize,
 }
 
 pub struct Page {
@@ -40,6 +45,8 @@ const PAGE_ERROR: usize = 0b100;
 const PAGE_DIRTY: usize = 0b1000;
 /// Page's contents are loaded in memory.
 const PAGE_LOADED: usize = 0b10000;
+/// Page's contents passed the structural checks of untrusted mode.
+const PAGE_CHECKED: usize = 0b100000;
 
 impl Page {
     pub fn new(id: usize) -> Self {
@@ -48,6 +55,7 @@ impl Page {
                 flags: AtomicUsize::new(0),
                 contents: None,
                 id,
+                pins: AtomicUsize::new(0),
             }),
         }
     }
@@ -121,7 +129,37 @@ impl Page {
 
     pub fn clear_loaded(&self) {
         tracing::debug!("clear loaded {}", self.get().id);
-        self.get().flags.fetch_and(!PAGE_LOADED, Ordering::SeqCst);
+        self.get()
+            .flags
+            .fetch_and(!(PAGE_LOADED | PAGE_CHECKED), Ordering::SeqCst);
+    }
+
+    pub fn is_checked(&self) -> bool {
+        self.get().flags.load(Ordering::SeqCst) & PAGE_CHECKED != 0
+    }
+
+    pub fn set_checked(&self) {
+        self.get().flags.fetch_or(PAGE_CHECKED, Ordering::SeqCst);
+    }
+
+    /// Keeps the page's contents in memory until the matching [Page::unpin]. The page cache
+    /// doesn't evict pinned pages, so references to their contents and cells read from them stay
+    /// valid while more pages are read.
+    pub fn pin(&self) {
+        self.get().pins.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn unpin(&self) {
+        let previous = self.get().pins.fetch_sub(1, Ordering::SeqCst);
+        debug_assert!(
+            previous > 0,
+            "page {} unpinned more than pinned",
+            self.get().id
+        );
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        self.get().pins.load(Ordering::SeqCst) > 0
     }
 }
 
@@ -150,6 +188,50 @@ struct FlushInfo {
     in_flight_writes: Rc<RefCell<usize>>,
 }
 
+/// Contents of the pages changed by the running statement from before the change, so that the
+/// statement can be undone on its own, like the statement journal of SQLite. Savepoints keep
+/// one of these as well, from when they began.
+struct StatementJournal {
+    /// The database header when the statement started.
+    header: DatabaseHeader,
+    /// Pages that existed when the statement started, by id. Pages allocated by the statement
+    /// are past `header.database_size` and need no copy.
+    pages: HashMap<usize, JournaledPage>,
+}
+
+impl StatementJournal {
+    fn new(header: DatabaseHeader) -> Self {
+        Self {
+            header,
+            pages: HashMap::new(),
+        }
+    }
+
+    /// Copies `page` unless it was copied already or didn't exist when the journal began.
+    fn record(&mut self, page: &PageRef, was_dirty: bool) {
+        let page_id = page.get().id;
+        if page_id > self.header.database_size as usize || self.pages.contains_key(&page_id) {
+            return;
+        }
+        let contents = page.get_contents();
+        self.pages.insert(
+            page_id,
+            JournaledPage {
+                offset: contents.offset,
+                data: contents.as_slice().to_vec(),
+                was_dirty,
+            },
+        );
+    }
+}
+
+struct JournaledPage {
+    offset: usize,
+    data: Vec<u8>,
+    /// Whether an earlier statement of the transaction changed the page already.
+    was_dirty: bool,
+}
+
 /// The pager interface implements the persistence layer by providing access
 /// to pages of the database file, including caching, concurrency control, and
 /// transaction management.
@@ -165,12 +247,59 @@ pub struct Pager {
     /// I/O interface for input/output operations.
     pub io: Arc<dyn crate::io::IO>,
     dirty_pages: Rc<RefCell<HashSet<usize>>>,
+    statement_journal: RefCell<Option<StatementJournal>>,
+    /// Journals of the open savepoints of the transaction, the innermost last.
+    savepoints: RefCell<Vec<StatementJournal>>,
     pub db_header: Arc<SpinLock<DatabaseHeader>>,
 
     flush_info: RefCell<FlushInfo>,
     checkpoint_state: RefCell<CheckpointState>,
     checkpoint_inflight: Rc<RefCell<usize>>,
     syncing: Rc<RefCell<bool>>,
+    /// Whether scans skip corrupt pages instead of failing, see [Self::set_salvage_mode].
+    salvage_mode: Cell<bool>,
+    /// Corrupt pages skipped since they were last taken.
+    corrupt_pages: RefCell<Vec<CorruptPage>>,
+    /// Whether b-tree pages are fully checked before use, see [Self::set_untrusted].
+    untrusted: Cell<bool>,
+    /// Dirty pages allowed before they are written to the database file, see
+    /// [Self::set_spill_threshold].
+    spill_threshold: Cell<Option<usize>>,
+    /// The memory limit of the database, see [Self::set_memory_limit].
+    memory_limit: RefCell<Option<Arc<MemoryLimit>>>,
+}
+
+/// Writes a spill keeps in flight, few enough for the submission queue of io_uring.
+const MAX_SPILL_WRITES: usize = 64;
+
+/// Where transient b-trees keep their pages, set with `PRAGMA temp_store`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TempStore {
+    /// Same as [TempStore::File].
+    Default,
+    /// Pages beyond the cache size are spilled to an anonymous temp file.
+    File,
+    /// Every page stays in memory.
+    Memory,
+}
+
+impl TempStore {
+    /// The value `PRAGMA temp_store` reports, as in SQLite.
+    pub fn as_int(&self) -> i64 {
+        match self {
+            TempStore::Default => 0,
+            TempStore::File => 1,
+            TempStore::Memory => 2,
+        }
+    }
+}
+
+/// A b-tree page that a scan skipped in salvage mode, along with the rows below it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptPage {
+    pub page_id: usize,
+    /// What is wrong with the page.
+    pub reason: String,
 }
 
 impl Pager {
@@ -194,6 +323,8 @@ impl Pager {
             page_cache,
             io,
             dirty_pages: Rc::new(RefCell::new(HashSet::new())),
+            statement_journal: RefCell::new(None),
+            savepoints: RefCell::new(Vec::new()),
             db_header: db_header_ref.clone(),
             flush_info: RefCell::new(FlushInfo {
                 state: FlushState::Start,
@@ -203,6 +334,11 @@ impl Pager {
             checkpoint_state: RefCell::new(CheckpointState::Checkpoint),
             checkpoint_inflight: Rc::new(RefCell::new(0)),
             buffer_pool,
+            salvage_mode: Cell::new(false),
+            corrupt_pages: RefCell::new(Vec::new()),
+            untrusted: Cell::new(false),
+            spill_threshold: Cell::new(None),
+            memory_limit: RefCell::new(None),
         })
     }
 
@@ -247,9 +383,77 @@ impl Pager {
         self.wal.borrow_mut().begin_read_tx()
     }
 
+    /// Begins a read transaction that sees the database as of the commit marker `frame`.
+    pub fn begin_read_tx_as_of(&self, frame: u64) -> Result<LimboResult> {
+        self.wal.borrow_mut().begin_read_tx_as_of(frame)
+    }
+
+    /// The commits a read transaction can still begin as of, oldest first.
+    pub fn wal_history(&self) -> Vec<WalCommit> {
+        self.wal.borrow().history()
+    }
+
     #[inline(always)]
     pub fn begin_write_tx(&self) -> Result<LimboResult> {
-        self.wal.borrow_mut().begin_write_tx()
+        let result = self.wal.borrow_mut().begin_write_tx()?;
+        if let LimboResult::Ok = result {
+            if self.wal.borrow_mut().restart_log_if_backfilled()? {
+                // Frame numbers start over, so pages cached for the old ones must not be found.
+                self.page_cache.write().clear();
+            }
+        }
+        Ok(result)
+    }
+
+    /// Delays the write transaction that just began while the WAL is longer than
+    /// [super::wal::WalBackpressure] allows, so checkpoints can catch up. It checkpoints what it can, then
+    /// returns true as long as it should wait for readers to move on, to be called again after
+    /// running the IO. `delayed_since` keeps when the delay started between the calls.
+    pub fn throttle_write(&self, delayed_since: &mut Option<Instant>) -> Result<bool> {
+        let backpressure = self.wal.borrow().backpressure();
+        let wal_frames = self.wal.borrow().get_max_frame_in_wal();
+        let now = self.io.now();
+        if backpressure.max_frames == 0 || wal_frames < backpressure.max_frames {
+            if let Some(start) = delayed_since.take() {
+                self.wal
+                    .borrow()
+                    .record_write_delay(elapsed(start, now), false);
+            }
+            return Ok(false);
+        }
+        let start = match *delayed_since {
+            Some(start) => start,
+            None => {
+                tracing::debug!("throttle_write(wal_frames={})", wal_frames);
+                *delayed_since = Some(now);
+                // The WAL may only be long because no commit checkpointed since the readers
+                // holding it back went away.
+                return self.throttle_checkpoint();
+            }
+        };
+        if elapsed(start, now) >= backpressure.max_delay {
+            delayed_since.take();
+            self.wal
+                .borrow()
+                .record_write_delay(elapsed(start, now), true);
+            return Ok(false);
+        }
+        if self.wal.borrow().other_readers() {
+            return Ok(true);
+        }
+        self.throttle_checkpoint()
+    }
+
+    /// Checkpoints while a write transaction is delayed, returning true to be called again.
+    fn throttle_checkpoint(&self) -> Result<bool> {
+        if let CheckpointStatus::IO = self.checkpoint()? {
+            return Ok(true);
+        }
+        if self.wal.borrow().get_max_frame_in_wal() == 0 {
+            // Frame numbers start over, so pages cached for the old ones must not be found.
+            self.page_cache.write().clear();
+        }
+        Ok(true)
     }
 
     pub fn end_tx(&self) -> Result<CheckpointStatus> {
@@ -257,6 +461,7 @@ impl Pager {
         match checkpoint_status {
             CheckpointStatus::IO => Ok(checkpoint_status),
             CheckpointStatus::Done(_) => {
+                self.savepoints.borrow_mut().clear();
                 self.wal.borrow().end_write_tx()?;
                 self.wal.borrow().end_read_tx()?;
                 Ok(checkpoint_status)
@@ -264,11 +469,181 @@ impl Pager {
         }
     }
 
+    pub fn salvage_mode(&self) -> bool {
+        self.salvage_mode.get()
+    }
+
+    /// In salvage mode, a table or index scan that comes across a corrupt page skips it and
+    /// the pages below it rather than failing, so what is left of a damaged database can be
+    /// read. The skipped pages are recorded, see [Self::take_corrupt_pages].
+    pub fn set_salvage_mode(&self, enabled: bool) {
+        self.salvage_mode.set(enabled);
+    }
+
+    pub(crate) fn quarantine_page(&self, page_id: usize, reason: String) {
+        tracing::warn!("skipping corrupt page {}: {}", page_id, reason);
+        self.corrupt_pages
+            .borrow_mut()
+            .push(CorruptPage { page_id, reason });
+    }
+
+    pub fn take_corrupt_pages(&self) -> Vec<CorruptPage> {
+        std::mem::take(&mut self.corrupt_pages.borrow_mut())
+    }
+
+    pub fn untrusted(&self) -> bool {
+        self.untrusted.get()
+    }
+
+    /// For database files that may have been crafted. Every b-tree page is checked against the
+    /// structural invariants of the file format the first time a cursor uses it, and a
+    /// violation fails with [crate::LimboError::Corrupt] instead of being trusted.
+    pub fn set_untrusted(&self, enabled: bool) {
+        self.untrusted.set(enabled);
+    }
+
+    /// The limit that transient storage opened for statements of this pager counts against,
+    /// which also makes [Self::spill_if_needed] spill early while it is exceeded.
+    pub(crate) fn set_memory_limit(&self, limit: Arc<MemoryLimit>) {
+        self.memory_limit.replace(Some(limit));
+    }
+
+    pub(crate) fn memory_limit(&self) -> Option<Arc<MemoryLimit>> {
+        self.memory_limit.borrow().clone()
+    }
+
+    /// The last WAL frame the open read transaction sees.
+    pub fn wal_max_frame(&self) -> u64 {
+        self.wal.borrow().get_max_frame()
+    }
+
     pub fn end_read_tx(&self) -> Result<()> {
         self.wal.borrow().end_read_tx()?;
         Ok(())
     }
 
+    /// Ends the write transaction without committing it. Pages it changed that weren't undone
+    /// already are dropped from the cache, to be read again as last committed.
+    pub fn rollback_write_tx(&self) -> Result<()> {
+        let max_frame = self.wal.borrow().get_max_frame();
+        {
+            let mut cache = self.page_cache.write();
+            for page_id in self.dirty_pages.borrow_mut().drain() {
+                let page_key = PageCacheKey::new(page_id, Some(max_frame));
+                if let Some(page) = cache.peek(&page_key, false) {
+                    page.clear_dirty();
+                }
+                cache.delete(page_key);
+            }
+        }
+        self.savepoints.borrow_mut().clear();
+        self.wal.borrow().end_write_tx()?;
+        self.wal.borrow().end_read_tx()?;
+        Ok(())
+    }
+
+    /// Starts recording the pages changed by the statement about to run, see
+    /// [Self::rollback_statement].
+    pub fn begin_statement(&self) {
+        let header = self.db_header.lock().clone();
+        self.statement_journal
+            .replace(Some(StatementJournal::new(header)));
+    }
+
+    /// Forgets the pages recorded for the statement that completed.
+    pub fn end_statement(&self) {
+        self.statement_journal.replace(None);
+    }
+
+    /// Undoes the changes of the running statement, leaving the ones of the earlier statements
+    /// of the transaction in place.
+    pub fn rollback_statement(&self) -> Result<()> {
+        let Some(journal) = self.statement_journal.take() else {
+            return Ok(());
+        };
+        tracing::debug!("rollback_statement(pages={})", journal.pages.len());
+        self.restore_journal(journal);
+        Ok(())
+    }
+
+    /// Starts a savepoint of the write transaction, returning the number of savepoints that
+    /// were open before it, which identifies it to [Self::release_savepoint] and
+    /// [Self::rollback_to_savepoint].
+    pub fn begin_savepoint(&self) -> usize {
+        let header = self.db_header.lock().clone();
+        let mut savepoints = self.savepoints.borrow_mut();
+        savepoints.push(StatementJournal::new(header));
+        savepoints.len() - 1
+    }
+
+    /// The number of open savepoints.
+    pub fn savepoint_count(&self) -> usize {
+        self.savepoints.borrow().len()
+    }
+
+    /// Ends savepoint `depth` and the ones in it, keeping their changes.
+    pub fn release_savepoint(&self, depth: usize) {
+        self.savepoints.borrow_mut().truncate(depth);
+    }
+
+    /// Undoes the changes made since savepoint `depth` began and ends it, along with the ones
+    /// in it.
+    pub fn rollback_to_savepoint(&self, depth: usize) {
+        let journal = {
+            let mut savepoints = self.savepoints.borrow_mut();
+            if depth >= savepoints.len() {
+                return;
+            }
+            savepoints.truncate(depth + 1);
+            savepoints.pop().unwrap()
+        };
+        tracing::debug!("rollback_to_savepoint(pages={})", journal.pages.len());
+        self.restore_journal(journal);
+    }
+
+    /// Puts back the pages and header recorded in `journal`.
+    fn restore_journal(&self, journal: StatementJournal) {
+        let max_frame = self.wal.borrow().get_max_frame();
+        let mut dirty_pages = self.dirty_pages.borrow_mut();
+        let mut cache = self.page_cache.write();
+        // Pages allocated since the journal began are past the end of the database again.
+        let database_size = journal.header.database_size as usize;
+        dirty_pages.retain(|page_id| {
+            if *page_id <= database_size {
+                return true;
+            }
+            let page_key = PageCacheKey::new(*page_id, Some(max_frame));
+            if let Some(page) = cache.peek(&page_key, false) {
+                page.clear_dirty();
+            }
+            cache.delete(page_key);
+            false
+        });
+        for (page_id, journaled) in journal.pages {
+            let page_key = PageCacheKey::new(page_id, Some(max_frame));
+            // Dirty pages aren't evicted, but the ones a statement made dirty only right
+            // before failing may have been.
+            let page = match cache.peek(&page_key, false) {
+                Some(page) => page,
+                None => {
+                    let page = allocate_page(page_id, &self.buffer_pool, 0);
+                    page.set_uptodate();
+                    cache.insert(page_key, page.clone());
+                    page
+                }
+            };
+            let contents = page.get().contents.as_mut().unwrap();
+            contents.offset = journaled.offset;
+            contents.overflow_cells.clear();
+            contents.as_mut_slice().copy_from_slice(&journaled.data);
+            if !journaled.was_dirty {
+                page.clear_dirty();
+                dirty_pages.remove(&page_id);
+            }
+        }
+        *self.db_header.lock() = journal.header;
+    }
+
     /// Reads a page from the database.
     pub fn read_page(&self, page_idx: usize) -> Result<PageRef> {
         tracing::trace!("read_page(page_idx = {})", page_idx);
@@ -304,6 +679,21 @@ impl Pager {
         Ok(page)
     }
 
+    /// Starts reading a page into the page cache without waiting for it, so that a later
+    /// read_page() finds it there. Pages that are already cached are left untouched.
+    pub fn prefetch_page(&self, page_idx: usize) -> Result<()> {
+        {
+            let mut page_cache = self.page_cache.write();
+            let page_key = PageCacheKey::new(page_idx, Some(self.wal.borrow().get_max_frame()));
+            if page_cache.peek(&page_key, false).is_some() {
+                return Ok(());
+            }
+        }
+        tracing::trace!("prefetch_page(page_idx = {})", page_idx);
+        self.read_page(page_idx)?;
+        Ok(())
+    }
+
     /// Loads pages if not loaded
     pub fn load_page(&self, page: PageRef) -> Result<()> {
         let id = page.get().id;
@@ -342,18 +732,124 @@ impl Pager {
         sqlite3_ondisk::begin_write_database_header(header, self).expect("failed to write header");
     }
 
+    /// The number of pages `PRAGMA cache_size` asks for, which it may give in KiB instead.
+    pub fn page_cache_size(&self) -> usize {
+        let header = self.db_header.lock();
+        let pages = if header.default_page_cache_size < 0 {
+            header.default_page_cache_size.unsigned_abs() as usize * 1024
+                / header.page_size as usize
+        } else {
+            header.default_page_cache_size as usize
+        };
+        pages.max(sqlite3_ondisk::MIN_PAGE_CACHE_SIZE)
+    }
+
     /// Changes the size of the page cache.
     pub fn change_page_cache_size(&self, capacity: usize) {
         let mut page_cache = self.page_cache.write();
         page_cache.resize(capacity);
     }
 
-    pub fn add_dirty(&self, page_id: usize) {
-        // TODO: check duplicates?
+    /// Returns the size the WAL file is truncated to after checkpoints, -1 meaning no limit.
+    pub fn journal_size_limit(&self) -> i64 {
+        self.wal.borrow().journal_size_limit()
+    }
+
+    pub fn set_journal_size_limit(&self, limit: i64) {
+        self.wal.borrow_mut().set_journal_size_limit(limit);
+    }
+
+    pub fn data_version(&self) -> u64 {
+        self.wal.borrow().data_version()
+    }
+
+    pub fn locking_mode(&self) -> LockingMode {
+        self.wal.borrow().locking_mode()
+    }
+
+    pub fn set_locking_mode(&self, mode: LockingMode) {
+        self.wal.borrow_mut().set_locking_mode(mode);
+    }
+
+    pub fn sync_mode(&self) -> SyncMode {
+        self.wal.borrow().sync_mode()
+    }
+
+    pub fn set_sync_mode(&self, mode: SyncMode) {
+        self.wal.borrow_mut().set_sync_mode(mode);
+    }
+
+    /// Marks a page as changed by the transaction. It must be called before the page is
+    /// changed, so that the running statement can record its contents first.
+    pub fn add_dirty(&self, page: &PageRef) {
+        let page_id = page.get().id;
         let mut dirty_pages = RefCell::borrow_mut(&self.dirty_pages);
+        let was_dirty = dirty_pages.contains(&page_id);
+        if let Some(journal) = self.statement_journal.borrow_mut().as_mut() {
+            journal.record(page, was_dirty);
+        }
+        for journal in self.savepoints.borrow_mut().iter_mut() {
+            journal.record(page, was_dirty);
+        }
         dirty_pages.insert(page_id);
     }
 
+    /// Makes [Self::spill_if_needed] write the dirty pages to the database file once there are
+    /// more than `pages` of them. Only meant for pagers of transient b-trees, whose file is
+    /// scratch space that is never committed.
+    pub fn set_spill_threshold(&self, pages: usize) {
+        self.spill_threshold.set(Some(pages));
+    }
+
+    /// Writes the dirty pages to the database file if there are too many of them or the memory
+    /// limit is exceeded, after which they can be evicted and read back like any other page.
+    /// Must only be called between
+    /// b-tree operations, since pages changed later in the same operation are not marked dirty
+    /// again.
+    pub fn spill_if_needed(&self) -> Result<()> {
+        let Some(threshold) = self.spill_threshold.get() else {
+            return Ok(());
+        };
+        let over_threshold = self.dirty_pages.borrow().len() > threshold;
+        let memory_limit = self.memory_limit();
+        let over_limit = memory_limit
+            .as_ref()
+            .is_some_and(|limit| limit.is_exceeded());
+        if !over_threshold && !over_limit {
+            return Ok(());
+        }
+        let dirty_pages: Vec<usize> = self.dirty_pages.borrow_mut().drain().collect();
+        trace!("spill(pages={})", dirty_pages.len());
+        let spilled = dirty_pages.len();
+
+        let write_counter = Rc::new(RefCell::new(0));
+        for page_id in dirty_pages {
+            let page = {
+                let mut cache = self.page_cache.write();
+                let page_key = PageCacheKey::new(page_id, Some(self.wal.borrow().get_max_frame()));
+                cache
+                    .peek(&page_key, false)
+                    .expect("dirty pages are never evicted")
+            };
+            sqlite3_ondisk::begin_write_btree_page(self, &page, write_counter.clone())?;
+            if *write_counter.borrow() >= MAX_SPILL_WRITES {
+                self.wait_for_writes(&write_counter)?;
+            }
+        }
+        self.wait_for_writes(&write_counter)?;
+        if let Some(limit) = memory_limit.filter(|_| !over_threshold && spilled > 0) {
+            limit.report(MemoryPressure::TransientStorage { pages: spilled });
+        }
+        Ok(())
+    }
+
+    fn wait_for_writes(&self, write_counter: &Rc<RefCell<usize>>) -> Result<()> {
+        while *write_counter.borrow() > 0 {
+            self.io.run_once()?;
+        }
+        Ok(())
+    }
+
     pub fn cacheflush(&self) -> Result<CheckpointStatus> {
         let mut checkpoint_result = CheckpointResult::default();
         loop {
@@ -362,16 +858,30 @@ impl Pager {
             match state {
                 FlushState::Start => {
                     let db_size = self.db_header.lock().database_size;
-                    for page_id in self.dirty_pages.borrow().iter() {
+                    let dirty_pages = self.dirty_pages.borrow();
+                    if dirty_pages.is_empty() {
+                        // Nothing was written, so there is nothing to append or sync either.
+                        break;
+                    }
+                    // Appending frames moves the snapshot of the writer, the dirty pages were
+                    // cached for the one before.
+                    let max_frame = self.wal.borrow().get_max_frame();
+                    for (i, page_id) in dirty_pages.iter().enumerate() {
                         let mut cache = self.page_cache.write();
-                        let page_key =
-                            PageCacheKey::new(*page_id, Some(self.wal.borrow().get_max_frame()));
+                        let page_key = PageCacheKey::new(*page_id, Some(max_frame));
                         let page = cache.get(&page_key).expect("we somehow added a page to dirty list but we didn't mark it as dirty, causing cache to drop it.");
                         let page_type = page.get().contents.as_ref().unwrap().maybe_page_type();
                         trace!("cacheflush(page={}, page_type={:?}", page_id, page_type);
+                        // Only the last frame of the transaction holds the database size,
+                        // which marks it as the commit frame.
+                        let commit_size = if i == dirty_pages.len() - 1 {
+                            db_size
+                        } else {
+                            0
+                        };
                         self.wal.borrow_mut().append_frame(
                             page.clone(),
-                            db_size,
+                            commit_size,
                             self.flush_info.borrow().in_flight_writes.clone(),
                         )?;
                         // This page is no longer valid.
@@ -379,6 +889,7 @@ impl Pager {
                         // We took page with key (page_num, max_frame) -- this page is no longer valid for that max_frame so it must be invalidated.
                         cache.delete(page_key);
                     }
+                    drop(dirty_pages);
                     self.dirty_pages.borrow_mut().clear();
                     self.flush_info.borrow_mut().state = FlushState::WaitAppendFrames;
                     return Ok(CheckpointStatus::IO);
@@ -392,13 +903,19 @@ impl Pager {
                     }
                 }
                 FlushState::SyncWal => {
+                    let should_checkpoint = self.wal.borrow().should_checkpoint();
+                    // Commits that skip the sync are made durable by a later one, or by the
+                    // checkpoint, which syncs whatever frames it is about to backfill.
+                    if !should_checkpoint && !self.wal.borrow().commit_needs_sync() {
+                        self.flush_info.borrow_mut().state = FlushState::Start;
+                        break;
+                    }
                     match self.wal.borrow_mut().sync() {
                         Ok(CheckpointStatus::IO) => return Ok(CheckpointStatus::IO),
                         Ok(CheckpointStatus::Done(res)) => checkpoint_result = res,
                         Err(e) => return Err(e),
                     }
 
-                    let should_checkpoint = self.wal.borrow().should_checkpoint();
                     if should_checkpoint {
                         self.flush_info.borrow_mut().state = FlushState::Checkpoint;
                     } else {
@@ -479,13 +996,18 @@ impl Pager {
 
     // WARN: used for testing purposes
     pub fn clear_page_cache(&self) -> CheckpointResult {
+        self.wal_checkpoint(CheckpointMode::Passive)
+    }
+
+    /// Runs a checkpoint to completion and clears the page cache.
+    pub fn wal_checkpoint(&self, mode: CheckpointMode) -> CheckpointResult {
         let checkpoint_result: CheckpointResult;
         loop {
-            match self.wal.borrow_mut().checkpoint(
-                self,
-                Rc::new(RefCell::new(0)),
-                CheckpointMode::Passive,
-            ) {
+            match self
+                .wal
+                .borrow_mut()
+                .checkpoint(self, Rc::new(RefCell::new(0)), mode)
+            {
                 Ok(CheckpointStatus::IO) => {
                     let _ = self.io.run_once();
                 }
@@ -523,16 +1045,20 @@ impl Pager {
                 assert_eq!(page.get().id, page_id, "Page id mismatch");
                 page
             }
-            None => self.read_page(page_id)?,
+            None => self.read_page_sync(page_id)?,
         };
 
-        self.db_header.lock().freelist_pages += 1;
+        {
+            let mut header = self.db_header.lock();
+            header.freelist_pages += 1;
+            self.write_header_page(&header)?;
+        }
 
         let trunk_page_id = self.db_header.lock().freelist_trunk_page;
 
         if trunk_page_id != 0 {
             // Add as leaf to current trunk
-            let trunk_page = self.read_page(trunk_page_id as usize)?;
+            let trunk_page = self.read_page_sync(trunk_page_id as usize)?;
             let trunk_page_contents = trunk_page.get().contents.as_ref().unwrap();
             let number_of_leaf_pages = trunk_page_contents.read_u32(TRUNK_PAGE_LEAF_COUNT_OFFSET);
 
@@ -541,7 +1067,7 @@ impl Pager {
 
             if number_of_leaf_pages < max_free_list_entries as u32 {
                 trunk_page.set_dirty();
-                self.add_dirty(trunk_page_id as usize);
+                self.add_dirty(&trunk_page);
 
                 trunk_page_contents
                     .write_u32(TRUNK_PAGE_LEAF_COUNT_OFFSET, number_of_leaf_pages + 1);
@@ -558,7 +1084,7 @@ impl Pager {
 
         // If we get here, need to make this page a new trunk
         page.set_dirty();
-        self.add_dirty(page_id);
+        self.add_dirty(&page);
 
         let contents = page.get().contents.as_mut().unwrap();
         // Point to previous trunk
@@ -566,7 +1092,11 @@ impl Pager {
         // Zero leaf count
         contents.write_u32(TRUNK_PAGE_LEAF_COUNT_OFFSET, 0);
         // Update page 1 to point to new trunk
-        self.db_header.lock().freelist_trunk_page = page_id as u32;
+        {
+            let mut header = self.db_header.lock();
+            header.freelist_trunk_page = page_id as u32;
+            self.write_header_page(&header)?;
+        }
         // Clear flags
         page.clear_uptodate();
         page.clear_loaded();
@@ -582,29 +1112,14 @@ impl Pager {
         let header = &self.db_header;
         let mut header = header.lock();
         header.database_size += 1;
-        {
-            // update database size
-            // read sync for now
-            loop {
-                let first_page_ref = self.read_page(1)?;
-                if first_page_ref.is_locked() {
-                    self.io.run_once()?;
-                    continue;
-                }
-                first_page_ref.set_dirty();
-                self.add_dirty(1);
-
-                let contents = first_page_ref.get().contents.as_ref().unwrap();
-                contents.write_database_header(&header);
-                break;
-            }
-        }
+        // update database size
+        self.write_header_page(&header)?;
 
         let page = allocate_page(header.database_size as usize, &self.buffer_pool, 0);
         {
             // setup page and add to cache
             page.set_dirty();
-            self.add_dirty(page.get().id);
+            self.add_dirty(&page);
             let mut cache = self.page_cache.write();
             let page_key =
                 PageCacheKey::new(page.get().id, Some(self.wal.borrow().get_max_frame()));
@@ -613,6 +1128,42 @@ impl Pager {
         Ok(page)
     }
 
+    /// The schema cookie as of the open read transaction, which differs from the one in
+    /// [Self::db_header] when it reads as of a commit made before the schema changed.
+    pub fn read_schema_cookie(&self) -> Result<u32> {
+        let page = self.read_page_sync(1)?;
+        let contents = page.get().contents.as_ref().unwrap();
+        Ok(contents.read_u32_no_offset(40))
+    }
+
+    /// Like [Self::read_page], waiting for the page to be read if it isn't cached.
+    pub(crate) fn read_page_sync(&self, page_idx: usize) -> Result<PageRef> {
+        let page = self.read_page(page_idx)?;
+        while page.is_locked() {
+            self.io.run_once()?;
+        }
+        Ok(page)
+    }
+
+    /// Copies the header into the first page, which is written with the rest of the
+    /// transaction.
+    pub fn write_header_page(&self, header: &DatabaseHeader) -> Result<()> {
+        // read sync for now
+        loop {
+            let first_page_ref = self.read_page(1)?;
+            if first_page_ref.is_locked() {
+                self.io.run_once()?;
+                continue;
+            }
+            first_page_ref.set_dirty();
+            self.add_dirty(&first_page_ref);
+
+            let contents = first_page_ref.get().contents.as_ref().unwrap();
+            contents.write_database_header(header);
+            return Ok(());
+        }
+    }
+
     pub fn put_loaded_page(&self, id: usize, page: PageRef) {
         let mut cache = self.page_cache.write();
         // cache insert invalidates previous page
@@ -646,6 +1197,10 @@ pub fn allocate_page(page_id: usize, buffer_pool: &Rc<BufferPool>, offset: usize
     page
 }
 
+fn elapsed(start: Instant, now: Instant) -> Duration {
+    Duration::from_micros(now.micros_since(start).max(0) as u64)
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
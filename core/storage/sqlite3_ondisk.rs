@@ -43,13 +43,13 @@
 
 use crate::error::LimboError;
 use crate::fast_lock::SpinLock;
-use crate::io::{Buffer, Completion, ReadCompletion, SyncCompletion, WriteCompletion};
+use crate::io::{Buffer, Complete, Completion, ReadCompletion, SyncCompletion, WriteCompletion};
 use crate::storage::buffer_pool::BufferPool;
 use crate::storage::database::DatabaseStorage;
 use crate::storage::pager::Pager;
 use crate::types::{ImmutableRecord, RawSlice, RefValue, TextRef, TextSubtype};
 use crate::{File, Result};
-use std::cell::RefCell;
+use std::cell::{Ref, RefCell, RefMut};
 use std::mem::MaybeUninit;
 use std::pin::Pin;
 use std::rc::Rc;
@@ -113,7 +113,7 @@ pub struct DatabaseHeader {
     pub freelist_pages: u32,
 
     /// The schema cookie. Incremented when the database schema changes.
-    schema_cookie: u32,
+    pub schema_cookie: u32,
 
     /// The schema format number. Supported formats are 1, 2, 3, and 4.
     schema_format: u32,
@@ -135,7 +135,7 @@ pub struct DatabaseHeader {
     incremental_vacuum_enabled: u32,
 
     /// The "Application ID" set by PRAGMA application_id.
-    application_id: u32,
+    pub application_id: u32,
 
     /// Reserved for expansion. Must be zero.
     reserved_for_expansion: [u8; 20],
@@ -260,6 +260,24 @@ pub fn begin_read_database_header(
     Ok(result)
 }
 
+/// Reads the database header from a WAL frame of page 1, whose page content starts at `offset`.
+pub fn begin_read_wal_database_header(
+    io: &Arc<dyn File>,
+    offset: usize,
+    header: Arc<SpinLock<DatabaseHeader>>,
+) -> Result<()> {
+    let drop_fn = Rc::new(|_buf| {});
+    #[allow(clippy::arc_with_non_send_sync)]
+    let buf = Arc::new(RefCell::new(Buffer::allocate(512, drop_fn)));
+    let complete = Box::new(move |buf: Arc<RefCell<Buffer>>| {
+        let header = header.clone();
+        finish_read_database_header(buf, header).unwrap();
+    });
+    let c = Completion::Read(ReadCompletion::new(buf, complete));
+    io.pread(offset, c)?;
+    Ok(())
+}
+
 fn finish_read_database_header(
     buf: Arc<RefCell<Buffer>>,
     header: Arc<SpinLock<DatabaseHeader>>,
@@ -296,6 +314,26 @@ fn finish_read_database_header(
     Ok(())
 }
 
+/// Checks the parts of the header that the rest of the reader relies on, for databases opened
+/// as untrusted.
+pub fn check_database_header(header: &DatabaseHeader) -> Result<()> {
+    if &header.magic != b"SQLite format 3\0" {
+        crate::bail_corrupt_error!("not a database file");
+    }
+    let page_size = header.page_size as usize;
+    if page_size < 512 || !page_size.is_power_of_two() {
+        crate::bail_corrupt_error!("invalid page size {}", page_size);
+    }
+    // The usable size of a page may not be less than 480 bytes.
+    if page_size - (header.reserved_space as usize) < 480 {
+        crate::bail_corrupt_error!("invalid reserved space {}", header.reserved_space);
+    }
+    if header.database_size == 0 {
+        crate::bail_corrupt_error!("database size is 0 pages");
+    }
+    Ok(())
+}
+
 pub fn begin_write_database_header(header: &DatabaseHeader, pager: &Pager) -> Result<()> {
     let page_source = pager.db_file.clone();
     let header = Rc::new(header.clone());
@@ -424,62 +462,71 @@ impl PageContent {
         }
     }
 
-    #[allow(clippy::mut_from_ref)]
-    pub fn as_ptr(&self) -> &mut [u8] {
-        unsafe {
-            // unsafe trick to borrow twice
-            let buf_pointer = &self.buffer.as_ptr();
-            let buf = (*buf_pointer).as_mut().unwrap().as_mut_slice();
-            buf
-        }
+    /// The bytes of the page, borrowed until the guard is dropped.
+    pub fn as_slice(&self) -> Ref<'_, [u8]> {
+        Ref::map(self.buffer.borrow(), |buf| buf.as_slice())
+    }
+
+    /// The bytes of the page for writing, borrowed until the guard is dropped.
+    ///
+    /// Panics if another guard of the page is alive, including one of a cell payload that is
+    /// being read.
+    pub fn as_mut_slice(&self) -> RefMut<'_, [u8]> {
+        RefMut::map(self.buffer.borrow_mut(), |buf| buf.as_mut_slice())
     }
 
     pub fn read_u8(&self, pos: usize) -> u8 {
-        let buf = self.as_ptr();
+        let buf = self.as_slice();
         buf[self.offset + pos]
     }
 
     pub fn read_u16(&self, pos: usize) -> u16 {
-        let buf = self.as_ptr();
+        let buf = self.as_slice();
         u16::from_be_bytes([buf[self.offset + pos], buf[self.offset + pos + 1]])
     }
 
     pub fn read_u16_no_offset(&self, pos: usize) -> u16 {
-        let buf = self.as_ptr();
+        let buf = self.as_slice();
         u16::from_be_bytes([buf[pos], buf[pos + 1]])
     }
 
     pub fn read_u32_no_offset(&self, pos: usize) -> u32 {
-        let buf = self.as_ptr();
+        let buf = self.as_slice();
         u32::from_be_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]])
     }
 
     pub fn read_u32(&self, pos: usize) -> u32 {
-        let buf = self.as_ptr();
-        read_u32(buf, self.offset + pos)
+        let buf = self.as_slice();
+        read_u32(&buf, self.offset + pos)
     }
 
     pub fn write_u8(&self, pos: usize, value: u8) {
         tracing::trace!("write_u8(pos={}, value={})", pos, value);
-        let buf = self.as_ptr();
+        let mut buf = self.as_mut_slice();
         buf[self.offset + pos] = value;
     }
 
     pub fn write_u16(&self, pos: usize, value: u16) {
         tracing::trace!("write_u16(pos={}, value={})", pos, value);
-        let buf = self.as_ptr();
+        let mut buf = self.as_mut_slice();
         buf[self.offset + pos..self.offset + pos + 2].copy_from_slice(&value.to_be_bytes());
     }
 
     pub fn write_u16_no_offset(&self, pos: usize, value: u16) {
         tracing::trace!("write_u16(pos={}, value={})", pos, value);
-        let buf = self.as_ptr();
+        let mut buf = self.as_mut_slice();
         buf[pos..pos + 2].copy_from_slice(&value.to_be_bytes());
     }
 
+    pub fn write_u32_no_offset(&self, pos: usize, value: u32) {
+        tracing::trace!("write_u32(pos={}, value={})", pos, value);
+        let mut buf = self.as_mut_slice();
+        buf[pos..pos + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
     pub fn write_u32(&self, pos: usize, value: u32) {
         tracing::trace!("write_u32(pos={}, value={})", pos, value);
-        let buf = self.as_ptr();
+        let mut buf = self.as_mut_slice();
         buf[self.offset + pos..self.offset + pos + 4].copy_from_slice(&value.to_be_bytes());
     }
 
@@ -550,14 +597,9 @@ impl PageContent {
         }
     }
 
-    pub fn rightmost_pointer_raw(&self) -> Option<*mut u8> {
-        match self.page_type() {
-            PageType::IndexInterior | PageType::TableInterior => {
-                Some(unsafe { self.as_ptr().as_mut_ptr().add(self.offset + 8) })
-            }
-            PageType::IndexLeaf => None,
-            PageType::TableLeaf => None,
-        }
+    pub fn write_rightmost_pointer(&self, page: u32) {
+        assert!(!self.is_leaf(), "leaf pages have no rightmost pointer");
+        self.write_u32(8, page);
     }
 
     pub fn cell_get(
@@ -568,8 +610,6 @@ impl PageContent {
         usable_size: usize,
     ) -> Result<BTreeCell> {
         tracing::trace!("cell_get(idx={})", idx);
-        let buf = self.as_ptr();
-
         let ncells = self.cell_count();
         // the page header is 12 bytes for interior pages, 8 bytes for leaf pages
         // this is because the 4 last bytes in the interior page's header are used for the rightmost pointer.
@@ -577,12 +617,15 @@ impl PageContent {
         assert!(idx < ncells, "cell_get: idx out of bounds");
         let cell_pointer = cell_pointer_array_start + (idx * 2);
         let cell_pointer = self.read_u16(cell_pointer) as usize;
+        if cell_pointer < self.offset + cell_pointer_array_start + ncells * 2 {
+            crate::bail_corrupt_error!(
+                "cell pointer {} overlaps the cell pointer array",
+                cell_pointer
+            );
+        }
 
-        // SAFETY: this buffer is valid as long as the page is alive. We could store the page in the cell and do some lifetime magic
-        // but that is extra memory for no reason at all. Just be careful like in the old times :).
-        let static_buf: &'static [u8] = unsafe { std::mem::transmute::<&[u8], &'static [u8]>(buf) };
         read_btree_cell(
-            static_buf,
+            &self.buffer,
             &self.page_type(),
             cell_pointer,
             payload_overflow_threshold_max,
@@ -608,17 +651,21 @@ impl PageContent {
         payload_overflow_threshold_max: usize,
         payload_overflow_threshold_min: usize,
         usable_size: usize,
-    ) -> (usize, usize) {
-        let buf = self.as_ptr();
+    ) -> Result<(usize, usize)> {
+        let buf = self.as_slice();
         let ncells = self.cell_count();
         let (cell_pointer_array_start, _) = self.cell_pointer_array_offset_and_size();
         assert!(idx < ncells, "cell_get: idx out of bounds");
         let cell_pointer = cell_pointer_array_start + (idx * 2); // pointers are 2 bytes each
         let cell_pointer = self.read_u16_no_offset(cell_pointer) as usize;
+        if cell_pointer < cell_pointer_array_start + ncells * 2 || cell_pointer >= buf.len() {
+            crate::bail_corrupt_error!("cell pointer {} out of page bounds", cell_pointer);
+        }
         let start = cell_pointer;
         let len = match self.page_type() {
             PageType::IndexInterior => {
-                let (len_payload, n_payload) = read_varint(&buf[cell_pointer + 4..]).unwrap();
+                let (len_payload, n_payload) =
+                    read_varint(buf.get(cell_pointer + 4..).unwrap_or(&[]))?;
                 let (overflows, to_read) = payload_overflows(
                     len_payload as usize,
                     payload_overflow_threshold_max,
@@ -626,17 +673,17 @@ impl PageContent {
                     usable_size,
                 );
                 if overflows {
-                    4 + to_read + n_payload + 4
+                    4 + to_read + n_payload
                 } else {
-                    4 + len_payload as usize + n_payload + 4
+                    4 + len_payload as usize + n_payload
                 }
             }
             PageType::TableInterior => {
-                let (_, n_rowid) = read_varint(&buf[cell_pointer + 4..]).unwrap();
+                let (_, n_rowid) = read_varint(buf.get(cell_pointer + 4..).unwrap_or(&[]))?;
                 4 + n_rowid
             }
             PageType::IndexLeaf => {
-                let (len_payload, n_payload) = read_varint(&buf[cell_pointer..]).unwrap();
+                let (len_payload, n_payload) = read_varint(&buf[cell_pointer..])?;
                 let (overflows, to_read) = payload_overflows(
                     len_payload as usize,
                     payload_overflow_threshold_max,
@@ -644,14 +691,14 @@ impl PageContent {
                     usable_size,
                 );
                 if overflows {
-                    to_read + n_payload + 4
+                    to_read + n_payload
                 } else {
-                    len_payload as usize + n_payload + 4
+                    len_payload as usize + n_payload
                 }
             }
             PageType::TableLeaf => {
-                let (len_payload, n_payload) = read_varint(&buf[cell_pointer..]).unwrap();
-                let (_, n_rowid) = read_varint(&buf[cell_pointer + n_payload..]).unwrap();
+                let (len_payload, n_payload) = read_varint(&buf[cell_pointer..])?;
+                let (_, n_rowid) = read_varint(&buf[cell_pointer + n_payload..])?;
                 let (overflows, to_read) = payload_overflows(
                     len_payload as usize,
                     payload_overflow_threshold_max,
@@ -665,7 +712,14 @@ impl PageContent {
                 }
             }
         };
-        (start, len)
+        if start + len > buf.len() {
+            crate::bail_corrupt_error!(
+                "cell at offset {} with length {} runs past the end of the page",
+                start,
+                len
+            );
+        }
+        Ok((start, len))
     }
 
     pub fn is_leaf(&self) -> bool {
@@ -678,8 +732,8 @@ impl PageContent {
     }
 
     pub fn write_database_header(&self, header: &DatabaseHeader) {
-        let buf = self.as_ptr();
-        write_header_to_buf(buf, header);
+        let mut buf = self.as_mut_slice();
+        write_header_to_buf(&mut buf, header);
     }
 
     pub fn debug_print_freelist(&self, usable_space: u16) {
@@ -804,6 +858,42 @@ pub fn begin_sync(db_file: Arc<dyn DatabaseStorage>, syncing: Rc<RefCell<bool>>)
     Ok(())
 }
 
+/// Bytes of a page that a cell points at, see [BTreeCell].
+///
+/// The slice holds on to the page's buffer, so it stays valid after the page is evicted, and only
+/// borrows it while it is being read, so the page can be written to in the meantime.
+#[derive(Debug, Clone)]
+pub struct PageSlice {
+    buffer: Arc<RefCell<Buffer>>,
+    start: usize,
+    len: usize,
+}
+
+impl PageSlice {
+    /// The `len` bytes of `buffer` from `start`.
+    pub(crate) fn new(buffer: Arc<RefCell<Buffer>>, start: usize, len: usize) -> Self {
+        debug_assert!(start + len <= buffer.borrow().len());
+        Self { buffer, start, len }
+    }
+
+    /// The bytes, borrowed from the page until the guard is dropped.
+    pub fn as_slice(&self) -> Ref<'_, [u8]> {
+        Ref::map(self.buffer.borrow(), |buf| {
+            &buf.as_slice()[self.start..self.start + self.len]
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// A cell of a b-tree page. Payloads are read from the page without copying, through a
+/// [PageSlice].
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone)]
 pub enum BTreeCell {
@@ -813,6 +903,18 @@ pub enum BTreeCell {
     IndexLeafCell(IndexLeafCell),
 }
 
+impl BTreeCell {
+    /// The first overflow page of the cell's payload, if it doesn't fit in the page.
+    pub fn first_overflow_page(&self) -> Option<u32> {
+        match self {
+            BTreeCell::TableInteriorCell(_) => None,
+            BTreeCell::TableLeafCell(cell) => cell.first_overflow_page,
+            BTreeCell::IndexInteriorCell(cell) => cell.first_overflow_page,
+            BTreeCell::IndexLeafCell(cell) => cell.first_overflow_page,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TableInteriorCell {
     pub _left_child_page: u32,
@@ -823,7 +925,7 @@ pub struct TableInteriorCell {
 pub struct TableLeafCell {
     pub _rowid: u64,
     /// Payload of cell, if it overflows it won't include overflowed payload.
-    pub _payload: &'static [u8],
+    pub _payload: PageSlice,
     /// This is the complete payload size including overflow pages.
     pub payload_size: u64,
     pub first_overflow_page: Option<u32>,
@@ -832,7 +934,7 @@ pub struct TableLeafCell {
 #[derive(Debug, Clone)]
 pub struct IndexInteriorCell {
     pub left_child_page: u32,
-    pub payload: &'static [u8],
+    pub payload: PageSlice,
     /// This is the complete payload size including overflow pages.
     pub payload_size: u64,
     pub first_overflow_page: Option<u32>,
@@ -840,27 +942,31 @@ pub struct IndexInteriorCell {
 
 #[derive(Debug, Clone)]
 pub struct IndexLeafCell {
-    pub payload: &'static [u8],
+    pub payload: PageSlice,
     pub first_overflow_page: Option<u32>,
     /// This is the complete payload size including overflow pages.
     pub payload_size: u64,
 }
 
 /// read_btree_cell contructs a BTreeCell which is basically a wrapper around pointer to the payload of a cell.
-/// buffer input "page" is static because we want the cell to point to the data in the page in case it has any payload.
+/// The payload holds on to `buffer`, the buffer of the page.
 pub fn read_btree_cell(
-    page: &'static [u8],
+    buffer: &Arc<RefCell<Buffer>>,
     page_type: &PageType,
     pos: usize,
     max_local: usize,
     min_local: usize,
     usable_size: usize,
 ) -> Result<BTreeCell> {
+    let page = buffer.borrow();
+    let page = page.as_slice();
+    if pos >= page.len() {
+        crate::bail_corrupt_error!("cell offset {} out of page bounds", pos);
+    }
     match page_type {
         PageType::IndexInterior => {
             let mut pos = pos;
-            let left_child_page =
-                u32::from_be_bytes([page[pos], page[pos + 1], page[pos + 2], page[pos + 3]]);
+            let left_child_page = read_cell_u32(page, pos)?;
             pos += 4;
             let (payload_size, nr) = read_varint(&page[pos..])?;
             pos += nr;
@@ -869,8 +975,9 @@ pub fn read_btree_cell(
                 payload_overflows(payload_size as usize, max_local, min_local, usable_size);
             let to_read = if overflows { to_read } else { page.len() - pos };
 
-            let (payload, first_overflow_page) =
-                read_payload(&page[pos..pos + to_read], payload_size as usize);
+            let (payload_len, first_overflow_page) =
+                read_payload(cell_slice(page, pos, to_read)?, payload_size as usize)?;
+            let payload = PageSlice::new(buffer.clone(), pos, payload_len);
             Ok(BTreeCell::IndexInteriorCell(IndexInteriorCell {
                 left_child_page,
                 payload,
@@ -880,8 +987,7 @@ pub fn read_btree_cell(
         }
         PageType::TableInterior => {
             let mut pos = pos;
-            let left_child_page =
-                u32::from_be_bytes([page[pos], page[pos + 1], page[pos + 2], page[pos + 3]]);
+            let left_child_page = read_cell_u32(page, pos)?;
             pos += 4;
             let (rowid, _) = read_varint(&page[pos..])?;
             Ok(BTreeCell::TableInteriorCell(TableInteriorCell {
@@ -898,8 +1004,9 @@ pub fn read_btree_cell(
                 payload_overflows(payload_size as usize, max_local, min_local, usable_size);
             let to_read = if overflows { to_read } else { page.len() - pos };
 
-            let (payload, first_overflow_page) =
-                read_payload(&page[pos..pos + to_read], payload_size as usize);
+            let (payload_len, first_overflow_page) =
+                read_payload(cell_slice(page, pos, to_read)?, payload_size as usize)?;
+            let payload = PageSlice::new(buffer.clone(), pos, payload_len);
             Ok(BTreeCell::IndexLeafCell(IndexLeafCell {
                 payload,
                 first_overflow_page,
@@ -917,8 +1024,9 @@ pub fn read_btree_cell(
                 payload_overflows(payload_size as usize, max_local, min_local, usable_size);
             let to_read = if overflows { to_read } else { page.len() - pos };
 
-            let (payload, first_overflow_page) =
-                read_payload(&page[pos..pos + to_read], payload_size as usize);
+            let (payload_len, first_overflow_page) =
+                read_payload(cell_slice(page, pos, to_read)?, payload_size as usize)?;
+            let payload = PageSlice::new(buffer.clone(), pos, payload_len);
             Ok(BTreeCell::TableLeafCell(TableLeafCell {
                 _rowid: rowid,
                 _payload: payload,
@@ -929,16 +1037,39 @@ pub fn read_btree_cell(
     }
 }
 
+/// Reads the 4-byte big-endian child pointer that starts interior cells.
+fn read_cell_u32(page: &[u8], pos: usize) -> Result<u32> {
+    match page.get(pos..pos + 4) {
+        Some(bytes) => Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        None => crate::bail_corrupt_error!("cell at offset {} runs past the end of the page", pos),
+    }
+}
+
+/// Returns `len` bytes of the page starting at `pos`, or a corruption error if the
+/// range does not fit in the page.
+fn cell_slice(page: &[u8], pos: usize, len: usize) -> Result<&[u8]> {
+    match pos.checked_add(len).and_then(|end| page.get(pos..end)) {
+        Some(slice) => Ok(slice),
+        None => crate::bail_corrupt_error!(
+            "cell payload at offset {} with length {} runs past the end of the page",
+            pos,
+            len
+        ),
+    }
+}
+
 /// read_payload takes in the unread bytearray with the payload size
-/// and returns the payload on the page, and optionally the first overflow page number.
-#[allow(clippy::readonly_write_lock)]
-fn read_payload(unread: &'static [u8], payload_size: usize) -> (&'static [u8], Option<u32>) {
+/// and returns the length of the payload on the page, and optionally the first overflow page number.
+fn read_payload(unread: &[u8], payload_size: usize) -> Result<(usize, Option<u32>)> {
     let cell_len = unread.len();
     // We will let overflow be constructed back if needed or requested.
     if payload_size <= cell_len {
         // fit within 1 page
-        (&unread[..payload_size], None)
+        Ok((payload_size, None))
     } else {
+        if cell_len < 4 {
+            crate::bail_corrupt_error!("overflowing cell too small to hold an overflow pointer");
+        }
         // overflow
         let first_overflow_page = u32::from_be_bytes([
             unread[cell_len - 4],
@@ -946,7 +1077,7 @@ fn read_payload(unread: &'static [u8], payload_size: usize) -> (&'static [u8], O
             unread[cell_len - 2],
             unread[cell_len - 1],
         ]);
-        (&unread[..cell_len - 4], Some(first_overflow_page))
+        Ok((cell_len - 4, Some(first_overflow_page)))
     }
 }
 
@@ -1091,7 +1222,9 @@ pub fn read_record(payload: &[u8], reuse_immutable: &mut ImmutableRecord) -> Res
 
     let mut pos = 0;
     let (header_size, nr) = read_varint(payload)?;
-    assert!((header_size as usize) >= nr);
+    if (header_size as usize) < nr || header_size as usize > payload.len() {
+        crate::bail_corrupt_error!("Invalid record header size: {}", header_size);
+    }
     let mut header_size = (header_size as usize) - nr;
     pos += nr;
 
@@ -1101,7 +1234,9 @@ pub fn read_record(payload: &[u8], reuse_immutable: &mut ImmutableRecord) -> Res
         let serial_type = validate_serial_type(serial_type)?;
         serial_types.push(serial_type);
         pos += nr;
-        assert!(header_size >= nr);
+        if header_size < nr {
+            crate::bail_corrupt_error!("Record header overruns its declared size");
+        }
         header_size -= nr;
     }
 
@@ -1244,6 +1379,10 @@ pub fn read_value(buf: &[u8], serial_type: SerialType) -> Result<(RefValue, usiz
                 n
             );
         }
+        // Text values are handed out as &str, so bytes that aren't UTF-8 can't be let through.
+        if std::str::from_utf8(&buf[..n]).is_err() {
+            crate::bail_corrupt_error!("Invalid String value, not valid UTF-8");
+        }
         let slice = if n == 0 {
             RawSlice::new(std::ptr::null(), 0)
         } else {
@@ -1279,8 +1418,10 @@ pub fn read_varint(buf: &[u8]) -> Result<(u64, usize)> {
             }
         }
     }
-    v = (v << 8) + buf[8] as u64;
-    Ok((v, 9))
+    match buf.get(8) {
+        Some(&c) => Ok(((v << 8) + c as u64, 9)),
+        None => crate::bail_corrupt_error!("Invalid varint"),
+    }
 }
 
 pub fn write_varint(buf: &mut [u8], value: u64) -> usize {
@@ -1389,6 +1530,23 @@ pub fn begin_read_wal_frame(
     Ok(())
 }
 
+/// Reads a whole WAL frame, header included, without interpreting it. Recovery uses it to
+/// validate the frames before indexing them.
+pub fn begin_read_wal_frame_raw(
+    io: &Arc<dyn File>,
+    offset: usize,
+    frame_size: usize,
+    complete: Box<Complete>,
+) -> Result<()> {
+    trace!("begin_read_wal_frame_raw(offset={})", offset);
+    let drop_fn = Rc::new(|_buf| {});
+    #[allow(clippy::arc_with_non_send_sync)]
+    let buf = Arc::new(RefCell::new(Buffer::allocate(frame_size, drop_fn)));
+    let c = Completion::Read(ReadCompletion::new(buf, complete));
+    io.pread(offset, c)?;
+    Ok(())
+}
+
 pub fn begin_write_wal_frame(
     io: &Arc<dyn File>,
     offset: usize,
@@ -1425,10 +1583,10 @@ pub fn begin_write_wal_frame(
         buf[8..12].copy_from_slice(&header.salt_1.to_be_bytes());
         buf[12..16].copy_from_slice(&header.salt_2.to_be_bytes());
 
-        let contents_buf = contents.as_ptr();
+        let contents_buf = contents.as_slice();
         let content_len = contents_buf.len();
         buf[WAL_FRAME_HEADER_SIZE..WAL_FRAME_HEADER_SIZE + content_len]
-            .copy_from_slice(contents_buf);
+            .copy_from_slice(&contents_buf);
         if content_len < 4096 {
             buf[WAL_FRAME_HEADER_SIZE + content_len..WAL_FRAME_HEADER_SIZE + 4096].fill(0);
         }
@@ -1476,7 +1634,7 @@ pub fn begin_write_wal_header(io: &Arc<dyn File>, header: &WalHeader) -> Result<
     let buffer = {
         let drop_fn = Rc::new(|_buf| {});
 
-        let mut buffer = Buffer::allocate(512, drop_fn);
+        let mut buffer = Buffer::allocate(WAL_HEADER_SIZE, drop_fn);
         let buf = buffer.as_mut_slice();
 
         buf[0..4].copy_from_slice(&header.magic.to_be_bytes());
@@ -1681,4 +1839,47 @@ mod tests {
         let result = validate_serial_type(10);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_read_varint_truncated() {
+        assert_eq!(read_varint(&[0xff; 9]).unwrap(), (u64::MAX, 9));
+        assert!(read_varint(&[0xff; 8]).is_err());
+        assert!(read_varint(&[]).is_err());
+    }
+
+    #[rstest]
+    // Child pointer cut off by the end of the page.
+    #[case(PageType::TableInterior, b"\x00\x00\x02")]
+    // Payload that is neither in the page nor has room for an overflow pointer.
+    #[case(PageType::TableLeaf, b"\x7f\x01\x02")]
+    #[case(PageType::IndexLeaf, b"\x81\x00")]
+    fn test_read_btree_cell_malformed(#[case] page_type: PageType, #[case] page: &'static [u8]) {
+        #[allow(clippy::arc_with_non_send_sync)]
+        let buffer = Arc::new(RefCell::new(Buffer::new(
+            Pin::new(page.to_vec()),
+            Rc::new(|_| {}),
+        )));
+        let result = read_btree_cell(&buffer, &page_type, 0, 4061, 489, 4096);
+        assert!(matches!(result, Err(LimboError::Corrupt(_))));
+    }
+
+    #[rstest]
+    // Header size larger than the record.
+    #[case(&[0x05, 0x01])]
+    // Header size smaller than its own varint.
+    #[case(&[0x00, 0x01])]
+    // Serial type spilling out of the declared header.
+    #[case(&[0x02, 0x81, 0x01])]
+    fn test_read_record_malformed(#[case] payload: &[u8]) {
+        let mut record = ImmutableRecord::new(payload.len(), 1);
+        assert!(matches!(
+            read_record(payload, &mut record),
+            Err(LimboError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_value_invalid_utf8() {
+        assert!(read_value(&[0xff], 15).is_err());
+    }
 }
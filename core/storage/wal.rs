@@ -1,10 +1,16 @@
 use std::cell::UnsafeCell;
-use std::collections::HashMap;
+use std::collections::HashSet;
 use tracing::{debug, trace};
 
 use std::fmt::Formatter;
-use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
-use std::{cell::RefCell, fmt, rc::Rc, sync::Arc};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    rc::Rc,
+    sync::Arc,
+    time::Duration,
+};
 
 use crate::fast_lock::SpinLock;
 use crate::io::{File, SyncCompletion, IO};
@@ -12,10 +18,13 @@ use crate::result::LimboResult;
 use crate::storage::sqlite3_ondisk::{
     begin_read_wal_frame, begin_write_wal_frame, WAL_FRAME_HEADER_SIZE, WAL_HEADER_SIZE,
 };
+use crate::Instant;
 use crate::{Buffer, LimboError, Result};
 use crate::{Completion, Page};
 
-use self::sqlite3_ondisk::{checksum_wal, PageContent, WAL_MAGIC_BE, WAL_MAGIC_LE};
+use self::sqlite3_ondisk::{
+    checksum_wal, read_u32, DatabaseHeader, PageContent, WAL_MAGIC_BE, WAL_MAGIC_LE,
+};
 
 use super::buffer_pool::BufferPool;
 use super::pager::{PageRef, Pager};
@@ -33,6 +42,8 @@ pub struct CheckpointResult {
     pub num_wal_frames: u64,
     /// number of frames moved successfully from WAL to db file after checkpoint
     pub num_checkpointed_frames: u64,
+    /// readers kept the checkpoint from doing everything its mode asks for (SQLITE_BUSY)
+    pub busy: bool,
 }
 
 impl Default for CheckpointResult {
@@ -46,6 +57,7 @@ impl CheckpointResult {
         Self {
             num_wal_frames: 0,
             num_checkpointed_frames: 0,
+            busy: false,
         }
     }
 }
@@ -58,6 +70,96 @@ pub enum CheckpointMode {
     Truncate,
 }
 
+/// How long a connection holds on to the WAL locks, set with `PRAGMA locking_mode`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LockingMode {
+    /// Locks are released at the end of every transaction, unless the connection is the only
+    /// one open on the database.
+    Normal,
+    /// Locks are kept once taken and other connections get [LimboResult::Busy] until this
+    /// connection switches back to [LockingMode::Normal] or is closed.
+    Exclusive,
+}
+
+impl LockingMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LockingMode::Normal => "normal",
+            LockingMode::Exclusive => "exclusive",
+        }
+    }
+}
+
+/// When commits wait for the WAL to reach stable storage, set with `PRAGMA synchronous`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SyncMode {
+    /// The WAL is never synced when committing, only before its frames are checkpointed.
+    Off,
+    /// Commits share syncs: a commit only syncs the WAL once [GROUP_COMMIT_WINDOW_MICROS]
+    /// passed since the oldest commit that isn't durable yet, which then covers every commit
+    /// made by any connection in the meantime. A crash can lose the commits of the last window.
+    Normal,
+    /// Every commit syncs the WAL, unless another connection's sync already covered it.
+    Full,
+}
+
+impl SyncMode {
+    /// The value `PRAGMA synchronous` reports, as in SQLite.
+    pub fn as_int(&self) -> i64 {
+        match self {
+            SyncMode::Off => 0,
+            SyncMode::Normal => 1,
+            SyncMode::Full => 2,
+        }
+    }
+}
+
+/// How long commits in [SyncMode::Normal] may wait for another commit to share the WAL sync.
+pub const GROUP_COMMIT_WINDOW_MICROS: i64 = 10_000;
+
+/// Delays write transactions while the WAL is long, so that checkpoints held back by readers
+/// get the chance to catch up instead of the WAL growing without bound, e.g. during a long
+/// ingest. Set for the whole database with [crate::Database::set_wal_backpressure].
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct WalBackpressure {
+    /// Frames in the WAL from which write transactions are delayed, 0 to never delay them.
+    pub max_frames: u64,
+    /// Longest a write transaction is delayed, after which it goes ahead anyway.
+    pub max_delay: Duration,
+}
+
+/// What [WalBackpressure] did to writers so far, see [crate::Database::wal_backpressure_stats].
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct WalBackpressureStats {
+    /// Write transactions that were delayed.
+    pub delayed_writes: u64,
+    /// Time the delayed write transactions spent waiting, as measured by the IO's clock.
+    pub total_delay: Duration,
+    /// Delayed write transactions that went ahead after `max_delay` with the WAL still long.
+    pub timed_out_writes: u64,
+}
+
+/// A commit still in the WAL, as of which the database can be read with `SELECT ... AS OF`, see
+/// [crate::Database::history].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WalCommit {
+    /// The commit marker: the last WAL frame of the commit, like [crate::Snapshot::wal_frame].
+    /// Markers start over from 1 when the WAL restarts.
+    pub frame: u64,
+    /// When it was committed, as measured by the IO's clock. Unknown for the commits that were
+    /// recovered from the WAL file when the database was opened.
+    pub committed_at: Option<Instant>,
+}
+
+/// Locks a connection kept after its last transaction ended, so the next one can reuse them
+/// without going through the read marks again.
+#[derive(Debug, Copy, Clone)]
+struct HeldLocks {
+    owner: u64,
+    read_lock_index: usize,
+    write_lock: bool,
+}
+
 #[derive(Debug)]
 struct LimboRwLock {
     lock: AtomicU32,
@@ -152,9 +254,17 @@ pub trait Wal {
     /// Begin a read transaction.
     fn begin_read_tx(&mut self) -> Result<LimboResult>;
 
+    /// Begins a read transaction that sees the database as of the commit ending at `frame`.
+    /// Fails if the WAL no longer has that commit.
+    fn begin_read_tx_as_of(&mut self, frame: u64) -> Result<LimboResult>;
+
     /// Begin a write transaction.
     fn begin_write_tx(&mut self) -> Result<LimboResult>;
 
+    /// Starts the log over if a checkpoint backfilled all of it but couldn't restart it because
+    /// of readers, now that they are done. Returns whether it did.
+    fn restart_log_if_backfilled(&mut self) -> Result<bool>;
+
     /// End a read transaction.
     fn end_read_tx(&self) -> Result<LimboResult>;
 
@@ -176,6 +286,8 @@ pub trait Wal {
     ) -> Result<()>;
 
     fn should_checkpoint(&self) -> bool;
+    /// Whether the commit that just appended its frames has to sync the WAL before it is done.
+    fn commit_needs_sync(&self) -> bool;
     fn checkpoint(
         &mut self,
         pager: &Pager,
@@ -186,6 +298,28 @@ pub trait Wal {
     fn get_max_frame_in_wal(&self) -> u64;
     fn get_max_frame(&self) -> u64;
     fn get_min_frame(&self) -> u64;
+
+    /// Size in bytes the WAL file is truncated to when the log restarts, or -1 for no limit.
+    fn journal_size_limit(&self) -> i64;
+    fn set_journal_size_limit(&mut self, limit: i64);
+
+    fn locking_mode(&self) -> LockingMode;
+    fn set_locking_mode(&mut self, mode: LockingMode);
+
+    fn sync_mode(&self) -> SyncMode;
+    fn set_sync_mode(&mut self, mode: SyncMode);
+
+    /// Changes whenever another connection commits, like `PRAGMA data_version`.
+    fn data_version(&self) -> u64;
+
+    fn backpressure(&self) -> WalBackpressure;
+    /// Counts a write transaction that [WalBackpressure] delayed for `delay`.
+    fn record_write_delay(&self, delay: Duration, timed_out: bool);
+    /// Whether another connection holds a read mark, i.e. reads a snapshot that may need frames
+    /// of the WAL. The log can't restart under it, as its frame numbers would be reused.
+    fn other_readers(&self) -> bool;
+    /// The commits still in the WAL, oldest first.
+    fn history(&self) -> Vec<WalCommit>;
 }
 
 // Syncing requires a state machine because we need to schedule a sync and then wait until it is
@@ -218,14 +352,15 @@ pub enum CheckpointStatus {
 // be placed back in pager page cache or anything, it's just a helper.
 // min_frame and max_frame is the range of frames that can be safely transferred from WAL to db
 // file.
-// current_page is a helper to iterate through all the pages that might have a frame in the safe
-// range. This is inefficient for now.
+// frames holds the latest frame of every page in that range as (page, frame) pairs, sorted by page
+// so that the database file is written sequentially, and current_frame is the next one to copy.
 struct OngoingCheckpoint {
     page: PageRef,
     state: CheckpointState,
     min_frame: u64,
     max_frame: u64,
-    current_page: u64,
+    frames: Vec<(u64, u64)>,
+    current_frame: usize,
 }
 
 impl fmt::Debug for OngoingCheckpoint {
@@ -234,7 +369,8 @@ impl fmt::Debug for OngoingCheckpoint {
             .field("state", &self.state)
             .field("min_frame", &self.min_frame)
             .field("max_frame", &self.max_frame)
-            .field("current_page", &self.current_page)
+            .field("frames", &self.frames.len())
+            .field("current_frame", &self.current_frame)
             .finish()
     }
 }
@@ -259,6 +395,25 @@ pub struct WalFile {
     max_frame: u64,
     /// Start of range to look for frames range=(minframe..max_frame)
     min_frame: u64,
+    /// Size the WAL file is truncated to once the log restarts. Negative means the file is
+    /// left as large as it grew.
+    journal_size_limit: i64,
+    /// Identifies this connection in [WalFileShared::held_locks] and
+    /// [WalFileShared::exclusive_owner].
+    id: u64,
+    locking_mode: LockingMode,
+    /// Whether this connection holds `write_lock`, either for an ongoing write transaction or
+    /// kept from a previous one.
+    holds_write_lock: Cell<bool>,
+    /// Whether this connection is in a read transaction.
+    reading: Cell<bool>,
+    /// Commits made by this connection, see [WalFileShared::commits].
+    own_commits: u64,
+    /// Whether the current write transaction appended frames yet.
+    wrote_frames: bool,
+    sync_mode: SyncMode,
+    /// Last frame of the WAL when the ongoing sync started, which it makes durable.
+    syncing_frame: u64,
 }
 
 impl fmt::Debug for WalFile {
@@ -273,11 +428,155 @@ impl fmt::Debug for WalFile {
             .field("max_frame_read_lock_index", &self.max_frame_read_lock_index)
             .field("max_frame", &self.max_frame)
             .field("min_frame", &self.min_frame)
+            .field("journal_size_limit", &self.journal_size_limit)
+            .field("id", &self.id)
+            .field("locking_mode", &self.locking_mode)
+            .field("holds_write_lock", &self.holds_write_lock)
+            .field("reading", &self.reading)
+            .field("own_commits", &self.own_commits)
+            .field("sync_mode", &self.sync_mode)
+            .field("syncing_frame", &self.syncing_frame)
             // Excluding other fields
             .finish()
     }
 }
 
+/// Number of frames covered by each segment of the [WalIndex], as in SQLite's wal-index.
+const WAL_INDEX_SEGMENT_FRAMES: usize = 4096;
+/// Hash slots of a segment. Keeping the table at most half full keeps the probe sequences short.
+const WAL_INDEX_SEGMENT_SLOTS: usize = 2 * WAL_INDEX_SEGMENT_FRAMES;
+
+/// In-memory counterpart of SQLite's wal-index, mapping page numbers to the frames that hold
+/// them. Frames are split in segments of [WAL_INDEX_SEGMENT_FRAMES], each with the page number of
+/// every frame and a hash table over them, so finding the latest frame of a page takes a few
+/// probes per segment no matter how many times the page was written.
+#[derive(Debug, Default)]
+struct WalIndex {
+    segments: Vec<WalIndexSegment>,
+}
+
+struct WalIndexSegment {
+    /// Page number of every frame in the segment, in frame order.
+    pages: Vec<u32>,
+    /// Open addressing hash table keyed by page number. A slot holds the 1-based position of a
+    /// frame in `pages`, or 0 when it is empty.
+    slots: Box<[u16]>,
+}
+
+impl fmt::Debug for WalIndexSegment {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WalIndexSegment")
+            .field("frames", &self.pages.len())
+            .finish()
+    }
+}
+
+impl WalIndexSegment {
+    fn new() -> Self {
+        Self {
+            pages: Vec::with_capacity(WAL_INDEX_SEGMENT_FRAMES),
+            slots: vec![0; WAL_INDEX_SEGMENT_SLOTS].into_boxed_slice(),
+        }
+    }
+
+    fn first_slot(page_id: u32) -> usize {
+        (page_id as usize).wrapping_mul(383) % WAL_INDEX_SEGMENT_SLOTS
+    }
+
+    fn insert(&mut self, page_id: u32) {
+        self.pages.push(page_id);
+        let mut slot = Self::first_slot(page_id);
+        while self.slots[slot] != 0 {
+            slot = (slot + 1) % WAL_INDEX_SEGMENT_SLOTS;
+        }
+        self.slots[slot] = self.pages.len() as u16;
+    }
+
+    /// Position in the segment of the latest frame of `page_id` that is not after `max_position`.
+    fn find(&self, page_id: u32, max_position: usize) -> Option<usize> {
+        let mut found = None;
+        let mut slot = Self::first_slot(page_id);
+        while self.slots[slot] != 0 {
+            let position = self.slots[slot] as usize - 1;
+            if self.pages[position] == page_id && position <= max_position {
+                found = found.max(Some(position));
+            }
+            slot = (slot + 1) % WAL_INDEX_SEGMENT_SLOTS;
+        }
+        found
+    }
+}
+
+impl WalIndex {
+    /// Records that `frame_id`, which must be the frame right after the last one, holds `page_id`.
+    fn append(&mut self, frame_id: u64, page_id: u64) {
+        assert_eq!(
+            frame_id,
+            self.max_frame() + 1,
+            "frames are appended in order"
+        );
+        if self
+            .segments
+            .last()
+            .is_none_or(|segment| segment.pages.len() == WAL_INDEX_SEGMENT_FRAMES)
+        {
+            self.segments.push(WalIndexSegment::new());
+        }
+        self.segments.last_mut().unwrap().insert(page_id as u32);
+    }
+
+    fn max_frame(&self) -> u64 {
+        match self.segments.last() {
+            Some(segment) => {
+                ((self.segments.len() - 1) * WAL_INDEX_SEGMENT_FRAMES + segment.pages.len()) as u64
+            }
+            None => 0,
+        }
+    }
+
+    /// Latest frame holding `page_id` that is not after `max_frame`.
+    fn find(&self, page_id: u64, max_frame: u64) -> Option<u64> {
+        let max_frame = max_frame.min(self.max_frame());
+        if max_frame == 0 {
+            return None;
+        }
+        let last_segment = (max_frame as usize - 1) / WAL_INDEX_SEGMENT_FRAMES;
+        for segment_idx in (0..=last_segment).rev() {
+            let max_position = if segment_idx == last_segment {
+                (max_frame as usize - 1) % WAL_INDEX_SEGMENT_FRAMES
+            } else {
+                WAL_INDEX_SEGMENT_FRAMES - 1
+            };
+            if let Some(position) = self.segments[segment_idx].find(page_id as u32, max_position) {
+                return Some((segment_idx * WAL_INDEX_SEGMENT_FRAMES + position + 1) as u64);
+            }
+        }
+        None
+    }
+
+    /// Latest frame of every page written between `min_frame` and `max_frame`, as
+    /// (page, frame) pairs sorted by page.
+    fn latest_frames(&self, min_frame: u64, max_frame: u64) -> Vec<(u64, u64)> {
+        let max_frame = max_frame.min(self.max_frame());
+        let mut seen = HashSet::new();
+        let mut frames = Vec::new();
+        for frame_id in (min_frame.max(1)..=max_frame).rev() {
+            let position = frame_id as usize - 1;
+            let page_id = self.segments[position / WAL_INDEX_SEGMENT_FRAMES].pages
+                [position % WAL_INDEX_SEGMENT_FRAMES] as u64;
+            if seen.insert(page_id) {
+                frames.push((page_id, frame_id));
+            }
+        }
+        frames.sort_unstable();
+        frames
+    }
+
+    fn clear(&mut self) {
+        self.segments.clear();
+    }
+}
+
 // TODO(pere): lock only important parts + pin WalFileShared
 /// WalFileShared is the part of a WAL that will be shared between threads. A wal has information
 /// that needs to be communicated between threads so this struct does the job.
@@ -287,15 +586,9 @@ pub struct WalFileShared {
     min_frame: AtomicU64,
     max_frame: AtomicU64,
     nbackfills: AtomicU64,
-    // Frame cache maps a Page to all the frames it has stored in WAL in ascending order.
-    // This is to easily find the frame it must checkpoint each connection if a checkpoint is
-    // necessary.
-    // One difference between SQLite and limbo is that we will never support multi process, meaning
-    // we don't need WAL's index file. So we can do stuff like this without shared memory.
-    // TODO: this will need refactoring because this is incredible memory inefficient.
-    frame_cache: Arc<SpinLock<HashMap<u64, Vec<u64>>>>,
-    // Another memory inefficient array made to just keep track of pages that are in frame_cache.
-    pages_in_frames: Arc<SpinLock<Vec<u64>>>,
+    /// Finds the frames holding a page. One difference between SQLite and limbo is that we will
+    /// never support multi process, so the index lives in memory instead of a shared memory file.
+    wal_index: SpinLock<WalIndex>,
     last_checksum: (u32, u32), // Check of last frame in WAL, this is a cumulative checksum over all frames in the WAL
     file: Arc<dyn File>,
     /// read_locks is a list of read locks that can coexist with the max_frame number stored in
@@ -306,6 +599,34 @@ pub struct WalFileShared {
     /// There is only one write allowed in WAL mode. This lock takes care of ensuring there is only
     /// one used.
     write_lock: LimboRwLock,
+    /// Number of connections with a [WalFile] on top of this WAL. The database file is locked
+    /// for the process, so a single connection is the only user of the WAL and can keep its
+    /// locks between transactions.
+    connections: AtomicU64,
+    next_connection_id: AtomicU64,
+    /// Locks kept by a connection that is not in a transaction. A new connection releases them
+    /// unless the owner is in exclusive locking mode.
+    held_locks: SpinLock<Option<HeldLocks>>,
+    /// Id of the connection in exclusive locking mode, 0 if there is none.
+    exclusive_owner: AtomicU64,
+    /// Number of transactions that wrote to the WAL.
+    commits: AtomicU64,
+    /// Last frame known to be on stable storage. Frames after it were committed without a sync.
+    synced_frame: AtomicU64,
+    /// When the oldest commit that isn't durable yet was made, see [SyncMode::Normal].
+    first_unsynced_commit: SpinLock<Option<Instant>>,
+    backpressure: SpinLock<WalBackpressure>,
+    backpressure_stats: SpinLock<WalBackpressureStats>,
+    /// Whether commits leave checkpointing to [crate::Database::checkpoint_if_needed], see
+    /// [crate::Database::defer_checkpoints].
+    defer_checkpoints: AtomicBool,
+    /// Commits appended to the WAL since it last restarted, oldest first.
+    history: SpinLock<Vec<WalCommit>>,
+    /// Whether commits and deferred checkpoints leave the WAL alone, so that its
+    /// history stays readable, see [crate::Database::set_retain_history].
+    retain_history: AtomicBool,
+    /// Frame up to which the checkpoint in progress may backfill, 0 while none runs.
+    checkpoint_frame: AtomicU64,
 }
 
 impl fmt::Debug for WalFileShared {
@@ -315,10 +636,13 @@ impl fmt::Debug for WalFileShared {
             .field("min_frame", &self.min_frame)
             .field("max_frame", &self.max_frame)
             .field("nbackfills", &self.nbackfills)
-            .field("frame_cache", &self.frame_cache)
-            .field("pages_in_frames", &self.pages_in_frames)
+            .field("wal_index", &self.wal_index)
             .field("last_checksum", &self.last_checksum)
-            // Excluding `file`, `read_locks`, and `write_lock`
+            .field("connections", &self.connections)
+            .field("exclusive_owner", &self.exclusive_owner)
+            .field("commits", &self.commits)
+            .field("synced_frame", &self.synced_frame)
+            // Excluding `file`, `read_locks`, `write_lock` and `held_locks`
             .finish()
     }
 }
@@ -326,7 +650,35 @@ impl fmt::Debug for WalFileShared {
 impl Wal for WalFile {
     /// Begin a read transaction.
     fn begin_read_tx(&mut self) -> Result<LimboResult> {
+        if self.locked_out() {
+            return Ok(LimboResult::Busy);
+        }
         let max_frame_in_wal = self.get_shared().max_frame.load(Ordering::SeqCst);
+        if let Some(held) = self.take_held_locks() {
+            let shared = self.get_shared();
+            self.holds_write_lock.set(held.write_lock);
+            if self.locking_mode == LockingMode::Exclusive {
+                shared.exclusive_owner.store(self.id, Ordering::SeqCst);
+            }
+            // Nobody else can have joined the read lock we kept, so its mark can be moved
+            // forward to the end of the WAL.
+            shared.read_locks[held.read_lock_index]
+                .value
+                .store(max_frame_in_wal as u32, Ordering::SeqCst);
+            self.min_frame = shared.nbackfills.load(Ordering::SeqCst) + 1;
+            self.max_frame_read_lock_index = held.read_lock_index;
+            self.max_frame = max_frame_in_wal;
+            tracing::debug!(
+                "begin_read_tx(min_frame={}, max_frame={}, lock={}, held)",
+                self.min_frame,
+                self.max_frame,
+                self.max_frame_read_lock_index,
+            );
+            self.reading.set(true);
+            return Ok(LimboResult::Ok);
+        }
+        // Whatever was kept from the last transaction was released by another connection.
+        self.holds_write_lock.set(false);
 
         let mut max_read_mark = 0;
         let mut max_read_mark_index = -1;
@@ -367,6 +719,9 @@ impl Wal for WalFile {
                 return Ok(LimboResult::Busy);
             }
         }
+        if self.locking_mode == LockingMode::Exclusive {
+            shared.exclusive_owner.store(self.id, Ordering::SeqCst);
+        }
         self.min_frame = shared.nbackfills.load(Ordering::SeqCst) + 1;
         self.max_frame_read_lock_index = max_read_mark_index as usize;
         self.max_frame = max_read_mark as u64;
@@ -377,50 +732,156 @@ impl Wal for WalFile {
             self.max_frame_read_lock_index,
             max_frame_in_wal
         );
+        self.reading.set(true);
+        Ok(LimboResult::Ok)
+    }
+
+    fn begin_read_tx_as_of(&mut self, frame: u64) -> Result<LimboResult> {
+        if self.locked_out() {
+            return Ok(LimboResult::Busy);
+        }
+        let read_lock_index = match self.take_held_locks() {
+            Some(held) => {
+                self.holds_write_lock.set(held.write_lock);
+                // Nobody else can have joined the read lock we kept, so its mark can be moved
+                // back to the commit.
+                self.get_shared().read_locks[held.read_lock_index]
+                    .value
+                    .store(frame as u32, Ordering::SeqCst);
+                held.read_lock_index
+            }
+            None => {
+                self.holds_write_lock.set(false);
+                match self.get_shared().join_read_mark(frame as u32) {
+                    Some(index) => index,
+                    None => return Ok(LimboResult::Busy),
+                }
+            }
+        };
+        if self.locking_mode == LockingMode::Exclusive {
+            self.get_shared()
+                .exclusive_owner
+                .store(self.id, Ordering::SeqCst);
+        }
+        let nbackfills = self.get_shared().nbackfills.load(Ordering::SeqCst);
+        self.min_frame = nbackfills + 1;
+        self.max_frame_read_lock_index = read_lock_index;
+        self.max_frame = frame;
+        self.reading.set(true);
+        tracing::debug!(
+            "begin_read_tx_as_of(min_frame={}, max_frame={}, lock={})",
+            self.min_frame,
+            self.max_frame,
+            self.max_frame_read_lock_index,
+        );
+        let shared = self.get_shared();
+        // Checkpoints that start from now on see the mark and don't backfill past it, so only
+        // the frames that were backfilled already, or are being backfilled, are lost.
+        let retained = frame >= nbackfills
+            && shared
+                .history
+                .lock()
+                .binary_search_by_key(&frame, |commit| commit.frame)
+                .is_ok();
+        let backfilling = shared.checkpoint_frame.load(Ordering::SeqCst) > frame;
+        if !retained || backfilling {
+            self.end_read_tx()?;
+            if !retained {
+                return Err(LimboError::InvalidArgument(format!(
+                    "commit {} is not in the WAL",
+                    frame
+                )));
+            }
+            return Ok(LimboResult::Busy);
+        }
         Ok(LimboResult::Ok)
     }
 
     /// End a read transaction.
     #[inline(always)]
     fn end_read_tx(&self) -> Result<LimboResult> {
-        tracing::debug!("end_read_tx");
-        let read_lock = &mut self.get_shared().read_locks[self.max_frame_read_lock_index];
-        read_lock.unlock();
+        let shared = self.get_shared();
+        let mut held_locks = shared.held_locks.lock();
+        let read_lock = &mut shared.read_locks[self.max_frame_read_lock_index];
+        let keep = self.keeps_locks() && read_lock.nreads.load(Ordering::SeqCst) == 1;
+        tracing::debug!("end_read_tx(keep={})", keep);
+        self.reading.set(false);
+        if keep {
+            // The mark is moved to the end of the WAL so that keeping the lock doesn't stop
+            // checkpoints from backfilling the frames of the transaction that just ended.
+            read_lock.value.store(
+                shared.max_frame.load(Ordering::SeqCst) as u32,
+                Ordering::SeqCst,
+            );
+            *held_locks = Some(HeldLocks {
+                owner: self.id,
+                read_lock_index: self.max_frame_read_lock_index,
+                write_lock: self.holds_write_lock.get(),
+            });
+        } else {
+            read_lock.unlock();
+            if self.holds_write_lock.replace(false) {
+                shared.write_lock.unlock();
+            }
+        }
         Ok(LimboResult::Ok)
     }
 
     /// Begin a write transaction
     fn begin_write_tx(&mut self) -> Result<LimboResult> {
-        let busy = !self.get_shared().write_lock.write();
+        self.wrote_frames = false;
+        if self.holds_write_lock.get() {
+            tracing::debug!("begin_write_transaction(held)");
+            return Ok(LimboResult::Ok);
+        }
+        if self.locked_out() {
+            return Ok(LimboResult::Busy);
+        }
+        let shared = self.get_shared();
+        let busy = !shared.write_lock.write();
         tracing::debug!("begin_write_transaction(busy={})", busy);
         if busy {
             return Ok(LimboResult::Busy);
         }
+        if shared.max_frame.load(Ordering::SeqCst) != self.max_frame {
+            // Another connection committed after this one's read transaction started. Waiting
+            // doesn't help, the read transaction has to start over.
+            shared.write_lock.unlock();
+            return Err(LimboError::BusySnapshot);
+        }
+        self.holds_write_lock.set(true);
         Ok(LimboResult::Ok)
     }
 
+    fn restart_log_if_backfilled(&mut self) -> Result<bool> {
+        let shared = self.get_shared();
+        let max_frame = shared.max_frame.load(Ordering::SeqCst);
+        if max_frame == 0
+            || shared.nbackfills.load(Ordering::SeqCst) != max_frame
+            || self.other_readers()
+        {
+            return Ok(false);
+        }
+        debug!("restart_log_if_backfilled(max_frame={})", max_frame);
+        self.reset_log(CheckpointMode::Passive)?;
+        Ok(true)
+    }
+
     /// End a write transaction
     fn end_write_tx(&self) -> Result<LimboResult> {
+        // Whether the lock is kept is decided with the read lock in `end_read_tx`, which
+        // always follows.
         tracing::debug!("end_write_txn");
-        self.get_shared().write_lock.unlock();
         Ok(LimboResult::Ok)
     }
 
     /// Find the latest frame containing a page.
     fn find_frame(&self, page_id: u64) -> Result<Option<u64>> {
-        let shared = self.get_shared();
-        let frames = shared.frame_cache.lock();
-        let frames = frames.get(&page_id);
-        if frames.is_none() {
-            return Ok(None);
-        }
-        let frames = frames.unwrap();
-        for frame in frames.iter().rev() {
-            if *frame <= self.max_frame {
-                return Ok(Some(*frame));
-            }
-        }
-        Ok(None)
+        Ok(self
+            .get_shared()
+            .wal_index
+            .lock()
+            .find(page_id, self.max_frame))
     }
 
     /// Read a frame from the WAL.
@@ -444,42 +905,34 @@ impl Wal for WalFile {
         db_size: u32,
         write_counter: Rc<RefCell<usize>>,
     ) -> Result<()> {
-        let page_id = page.get().id;
-        let shared = self.get_shared();
-        let max_frame = shared.max_frame.load(Ordering::SeqCst);
-        let frame_id = if max_frame == 0 { 1 } else { max_frame + 1 };
-        let offset = self.frame_offset(frame_id);
-        tracing::debug!(
-            "append_frame(frame={}, offset={}, page_id={})",
-            frame_id,
-            offset,
-            page_id
-        );
-        let header = shared.wal_header.clone();
-        let header = header.lock();
-        let checksums = shared.last_checksum;
-        let checksums = begin_write_wal_frame(
-            &shared.file,
-            offset,
-            &page,
-            db_size,
-            write_counter,
-            &header,
-            checksums,
-        )?;
-        shared.last_checksum = checksums;
-        shared.max_frame.store(frame_id, Ordering::SeqCst);
-        {
-            let mut frame_cache = shared.frame_cache.lock();
-            let frames = frame_cache.get_mut(&(page_id as u64));
-            match frames {
-                Some(frames) => frames.push(frame_id),
-                None => {
-                    frame_cache.insert(page_id as u64, vec![frame_id]);
-                    shared.pages_in_frames.lock().push(page_id as u64);
+        if !self.wrote_frames {
+            self.wrote_frames = true;
+            self.own_commits += 1;
+            self.get_shared().commits.fetch_add(1, Ordering::SeqCst);
+        }
+        let mut frame_id = self.write_frame(&page, db_size, write_counter.clone())?;
+        if db_size == 0 {
+            return Ok(());
+        }
+        if self.sync_mode == SyncMode::Full {
+            let characteristics = self.get_shared().file.device_characteristics();
+            if !characteristics.powersafe_overwrite {
+                // A torn write of the next commit's first frame could damage the sector this
+                // commit ends in once it is synced, so the commit frame is repeated until the
+                // next frame starts in a new sector, like SQLite does.
+                let frame_size = self.page_size + WAL_FRAME_HEADER_SIZE;
+                let end = self.frame_offset(frame_id) + frame_size;
+                let padding = commit_padding_frames(end, frame_size, characteristics.sector_size);
+                for _ in 0..padding {
+                    frame_id = self.write_frame(&page, db_size, write_counter.clone())?;
                 }
             }
         }
+        let commit = WalCommit {
+            frame: frame_id,
+            committed_at: Some(self.io.now()),
+        };
+        self.get_shared().history.lock().push(commit);
         Ok(())
     }
 
@@ -487,6 +940,26 @@ impl Wal for WalFile {
         let shared = self.get_shared();
         let frame_id = shared.max_frame.load(Ordering::SeqCst) as usize;
         frame_id >= self.checkpoint_threshold
+            && !shared.defer_checkpoints.load(Ordering::SeqCst)
+            && !shared.retain_history.load(Ordering::SeqCst)
+    }
+
+    fn commit_needs_sync(&self) -> bool {
+        let shared = self.get_shared();
+        if shared.synced_frame.load(Ordering::SeqCst) >= shared.max_frame.load(Ordering::SeqCst) {
+            // Another connection synced after these frames were written.
+            return false;
+        }
+        match self.sync_mode {
+            SyncMode::Off => false,
+            SyncMode::Full => true,
+            SyncMode::Normal => {
+                let now = self.io.now();
+                let mut first_unsynced_commit = shared.first_unsynced_commit.lock();
+                let first = *first_unsynced_commit.get_or_insert(now);
+                now.micros_since(first) >= GROUP_COMMIT_WINDOW_MICROS
+            }
+        }
     }
 
     fn checkpoint(
@@ -495,19 +968,32 @@ impl Wal for WalFile {
         write_counter: Rc<RefCell<usize>>,
         mode: CheckpointMode,
     ) -> Result<CheckpointStatus> {
-        assert!(
-            matches!(mode, CheckpointMode::Passive),
-            "only passive mode supported for now"
-        );
+        // There is no busy handler to wait for readers and writers, so the modes only differ in
+        // how much of the WAL file is kept once everything is backfilled, see `restart_log`.
         'checkpoint_loop: loop {
             let state = self.ongoing_checkpoint.state;
             debug!("checkpoint(state={:?})", state);
             match state {
                 CheckpointState::Start => {
+                    // Frames are only backfilled once they are durable in the WAL, otherwise a
+                    // crash could leave the database file with pages the WAL no longer has.
+                    if self.get_shared().synced_frame.load(Ordering::SeqCst)
+                        < self.get_shared().max_frame.load(Ordering::SeqCst)
+                    {
+                        if let CheckpointStatus::IO = self.sync()? {
+                            return Ok(CheckpointStatus::IO);
+                        }
+                        continue 'checkpoint_loop;
+                    }
                     // TODO(pere): check what frames are safe to checkpoint between many readers!
                     self.ongoing_checkpoint.min_frame = self.min_frame;
                     let shared = self.get_shared();
                     let mut max_safe_frame = shared.max_frame.load(Ordering::SeqCst);
+                    // Published before the marks are looked at, so that a reader that moves a
+                    // mark back behind the checkpoint's back finds out.
+                    shared
+                        .checkpoint_frame
+                        .store(max_safe_frame, Ordering::SeqCst);
                     for (read_lock_idx, read_lock) in shared.read_locks.iter_mut().enumerate() {
                         let this_mark = read_lock.value.load(Ordering::SeqCst);
                         if this_mark < max_safe_frame as u32 {
@@ -525,8 +1011,16 @@ impl Wal for WalFile {
                             }
                         }
                     }
+                    shared
+                        .checkpoint_frame
+                        .store(max_safe_frame, Ordering::SeqCst);
+                    let frames = shared
+                        .wal_index
+                        .lock()
+                        .latest_frames(self.min_frame, max_safe_frame);
                     self.ongoing_checkpoint.max_frame = max_safe_frame;
-                    self.ongoing_checkpoint.current_page = 0;
+                    self.ongoing_checkpoint.frames = frames;
+                    self.ongoing_checkpoint.current_frame = 0;
                     self.ongoing_checkpoint.state = CheckpointState::ReadFrame;
                     trace!(
                         "checkpoint_start(min_frame={}, max_frame={})",
@@ -535,43 +1029,26 @@ impl Wal for WalFile {
                     );
                 }
                 CheckpointState::ReadFrame => {
-                    let shared = self.get_shared();
-                    let min_frame = self.ongoing_checkpoint.min_frame;
-                    let max_frame = self.ongoing_checkpoint.max_frame;
-                    let pages_in_frames = shared.pages_in_frames.clone();
-                    let pages_in_frames = pages_in_frames.lock();
-
-                    let frame_cache = shared.frame_cache.clone();
-                    let frame_cache = frame_cache.lock();
-                    assert!(self.ongoing_checkpoint.current_page as usize <= pages_in_frames.len());
-                    if self.ongoing_checkpoint.current_page as usize == pages_in_frames.len() {
+                    let Some(&(page, frame)) = self
+                        .ongoing_checkpoint
+                        .frames
+                        .get(self.ongoing_checkpoint.current_frame)
+                    else {
                         self.ongoing_checkpoint.state = CheckpointState::Done;
                         continue 'checkpoint_loop;
-                    }
-                    let page = pages_in_frames[self.ongoing_checkpoint.current_page as usize];
-                    let frames = frame_cache
-                        .get(&page)
-                        .expect("page must be in frame cache if it's in list");
-
-                    for frame in frames.iter().rev() {
-                        if *frame >= min_frame && *frame <= max_frame {
-                            debug!(
-                                "checkpoint page(state={:?}, page={}, frame={})",
-                                state, page, *frame
-                            );
-                            self.ongoing_checkpoint.page.get().id = page as usize;
-
-                            self.read_frame(
-                                *frame,
-                                self.ongoing_checkpoint.page.clone(),
-                                self.buffer_pool.clone(),
-                            )?;
-                            self.ongoing_checkpoint.state = CheckpointState::WaitReadFrame;
-                            self.ongoing_checkpoint.current_page += 1;
-                            continue 'checkpoint_loop;
-                        }
-                    }
-                    self.ongoing_checkpoint.current_page += 1;
+                    };
+                    debug!(
+                        "checkpoint page(state={:?}, page={}, frame={})",
+                        state, page, frame
+                    );
+                    self.ongoing_checkpoint.page.get().id = page as usize;
+                    self.read_frame(
+                        frame,
+                        self.ongoing_checkpoint.page.clone(),
+                        self.buffer_pool.clone(),
+                    )?;
+                    self.ongoing_checkpoint.state = CheckpointState::WaitReadFrame;
+                    self.ongoing_checkpoint.current_frame += 1;
                 }
                 CheckpointState::WaitReadFrame => {
                     if self.ongoing_checkpoint.page.is_locked() {
@@ -593,9 +1070,7 @@ impl Wal for WalFile {
                     if *write_counter.borrow() > 0 {
                         return Ok(CheckpointStatus::IO);
                     }
-                    let shared = self.get_shared();
-                    if (self.ongoing_checkpoint.current_page as usize)
-                        < shared.pages_in_frames.lock().len()
+                    if self.ongoing_checkpoint.current_frame < self.ongoing_checkpoint.frames.len()
                     {
                         self.ongoing_checkpoint.state = CheckpointState::ReadFrame;
                     } else {
@@ -606,30 +1081,37 @@ impl Wal for WalFile {
                     if *write_counter.borrow() > 0 {
                         return Ok(CheckpointStatus::IO);
                     }
-                    let shared = self.get_shared();
-
-                    // Record two num pages fields to return as checkpoint result to caller.
-                    // Ref: pnLog, pnCkpt on https://www.sqlite.org/c3ref/wal_checkpoint_v2.html
-                    let checkpoint_result = CheckpointResult {
-                        num_wal_frames: shared.max_frame.load(Ordering::SeqCst),
-                        num_checkpointed_frames: self.ongoing_checkpoint.max_frame,
-                    };
-                    let everything_backfilled = shared.max_frame.load(Ordering::SeqCst)
-                        == self.ongoing_checkpoint.max_frame;
-                    if everything_backfilled {
+                    let max_frame = self.get_shared().max_frame.load(Ordering::SeqCst);
+                    let everything_backfilled = max_frame == self.ongoing_checkpoint.max_frame;
+                    // Without a busy handler to wait for readers, the modes that must backfill
+                    // the whole log report that they couldn't, like SQLite does once it gives up.
+                    let mut busy =
+                        !everything_backfilled && !matches!(mode, CheckpointMode::Passive);
+                    if everything_backfilled && !self.other_readers() {
                         // Here we know that we backfilled everything, therefore we can safely
                         // reset the wal.
-                        shared.frame_cache.lock().clear();
-                        shared.pages_in_frames.lock().clear();
-                        shared.max_frame.store(0, Ordering::SeqCst);
-                        shared.nbackfills.store(0, Ordering::SeqCst);
-                        // TODO(pere): truncate wal file here.
+                        self.reset_log(mode)?;
                     } else {
-                        shared
+                        // Readers still using the log keep it from restarting, which the next
+                        // write transaction does once they are done.
+                        busy |= matches!(mode, CheckpointMode::Restart | CheckpointMode::Truncate);
+                        self.get_shared()
                             .nbackfills
                             .store(self.ongoing_checkpoint.max_frame, Ordering::SeqCst);
                     }
+
+                    // Record two num pages fields to return as checkpoint result to caller.
+                    // Ref: pnLog, pnCkpt on https://www.sqlite.org/c3ref/wal_checkpoint_v2.html
+                    let checkpoint_result = CheckpointResult {
+                        num_wal_frames: max_frame,
+                        num_checkpointed_frames: self.ongoing_checkpoint.max_frame,
+                        busy,
+                    };
+                    self.ongoing_checkpoint.frames.clear();
                     self.ongoing_checkpoint.state = CheckpointState::Start;
+                    self.get_shared()
+                        .checkpoint_frame
+                        .store(0, Ordering::SeqCst);
                     return Ok(CheckpointStatus::Done(checkpoint_result));
                 }
             }
@@ -640,6 +1122,7 @@ impl Wal for WalFile {
         let state = *self.sync_state.borrow();
         match state {
             SyncState::NotSyncing => {
+                self.syncing_frame = self.get_shared().max_frame.load(Ordering::SeqCst);
                 let shared = self.get_shared();
                 debug!("wal_sync");
                 {
@@ -651,7 +1134,12 @@ impl Wal for WalFile {
                             *syncing.borrow_mut() = false;
                         }),
                     });
-                    shared.file.sync(completion)?;
+                    if shared.file.device_characteristics().sync_on_write {
+                        // The frames were durable once their writes completed.
+                        completion.complete(0);
+                    } else {
+                        shared.file.sync(completion)?;
+                    }
                 }
                 self.sync_state.replace(SyncState::Syncing);
                 Ok(CheckpointStatus::IO)
@@ -661,9 +1149,19 @@ impl Wal for WalFile {
                     Ok(CheckpointStatus::IO)
                 } else {
                     self.sync_state.replace(SyncState::NotSyncing);
+                    let shared = self.get_shared();
+                    shared
+                        .synced_frame
+                        .fetch_max(self.syncing_frame, Ordering::SeqCst);
+                    if shared.synced_frame.load(Ordering::SeqCst)
+                        >= shared.max_frame.load(Ordering::SeqCst)
+                    {
+                        *shared.first_unsynced_commit.lock() = None;
+                    }
                     let checkpoint_result = CheckpointResult {
                         num_wal_frames: self.max_frame,
                         num_checkpointed_frames: self.ongoing_checkpoint.max_frame,
+                        busy: false,
                     };
                     Ok(CheckpointStatus::Done(checkpoint_result))
                 }
@@ -682,6 +1180,97 @@ impl Wal for WalFile {
     fn get_min_frame(&self) -> u64 {
         self.min_frame
     }
+
+    fn journal_size_limit(&self) -> i64 {
+        self.journal_size_limit
+    }
+
+    fn set_journal_size_limit(&mut self, limit: i64) {
+        self.journal_size_limit = limit;
+    }
+
+    fn locking_mode(&self) -> LockingMode {
+        self.locking_mode
+    }
+
+    fn sync_mode(&self) -> SyncMode {
+        self.sync_mode
+    }
+
+    fn set_sync_mode(&mut self, mode: SyncMode) {
+        self.sync_mode = mode;
+    }
+
+    fn data_version(&self) -> u64 {
+        self.get_shared().commits.load(Ordering::SeqCst) - self.own_commits + 1
+    }
+
+    fn backpressure(&self) -> WalBackpressure {
+        self.get_shared().backpressure()
+    }
+
+    fn record_write_delay(&self, delay: Duration, timed_out: bool) {
+        debug!(
+            "record_write_delay(delay={:?}, timed_out={})",
+            delay, timed_out
+        );
+        let mut stats = self.get_shared().backpressure_stats.lock();
+        stats.delayed_writes += 1;
+        stats.total_delay += delay;
+        stats.timed_out_writes += timed_out as u64;
+    }
+
+    fn other_readers(&self) -> bool {
+        let own_read_lock = self.own_read_lock();
+        self.get_shared()
+            .read_locks
+            .iter()
+            .enumerate()
+            .any(|(index, lock)| {
+                lock.nreads.load(Ordering::SeqCst) > (own_read_lock == Some(index)) as u32
+            })
+    }
+
+    fn history(&self) -> Vec<WalCommit> {
+        self.get_shared().history()
+    }
+
+    fn set_locking_mode(&mut self, mode: LockingMode) {
+        self.locking_mode = mode;
+        if mode == LockingMode::Exclusive {
+            // Exclusivity starts with the next transaction, like in SQLite.
+            return;
+        }
+        let shared = self.get_shared();
+        let _ =
+            shared
+                .exclusive_owner
+                .compare_exchange(self.id, 0, Ordering::SeqCst, Ordering::SeqCst);
+        if !self.keeps_locks() {
+            if let Some(held) = self.take_held_locks() {
+                shared.release_locks(held);
+                self.holds_write_lock.set(false);
+            }
+        }
+    }
+}
+
+impl Drop for WalFile {
+    fn drop(&mut self) {
+        let shared = self.get_shared();
+        let held = {
+            let mut held_locks = shared.held_locks.lock();
+            shared.connections.fetch_sub(1, Ordering::SeqCst);
+            held_locks.take_if(|held| held.owner == self.id)
+        };
+        if let Some(held) = held {
+            shared.release_locks(held);
+        }
+        let _ =
+            shared
+                .exclusive_owner
+                .compare_exchange(self.id, 0, Ordering::SeqCst, Ordering::SeqCst);
+    }
 }
 
 impl WalFile {
@@ -691,6 +1280,24 @@ impl WalFile {
         shared: Arc<UnsafeCell<WalFileShared>>,
         buffer_pool: Rc<BufferPool>,
     ) -> Self {
+        let id = {
+            let shared = unsafe { shared.get().as_mut().unwrap() };
+            // The connection that was alone so far keeps its locks between transactions,
+            // which would leave this one busy.
+            let held = {
+                let mut held_locks = shared.held_locks.lock();
+                shared.connections.fetch_add(1, Ordering::SeqCst);
+                if shared.exclusive_owner.load(Ordering::SeqCst) == 0 {
+                    held_locks.take()
+                } else {
+                    None
+                }
+            };
+            if let Some(held) = held {
+                shared.release_locks(held);
+            }
+            shared.next_connection_id.fetch_add(1, Ordering::SeqCst) + 1
+        };
         let checkpoint_page = Arc::new(Page::new(0));
         let buffer = buffer_pool.get();
         {
@@ -712,7 +1319,8 @@ impl WalFile {
                 state: CheckpointState::Start,
                 min_frame: 0,
                 max_frame: 0,
-                current_page: 0,
+                frames: Vec::new(),
+                current_frame: 0,
             },
             syncing: Rc::new(RefCell::new(false)),
             checkpoint_threshold: 1000,
@@ -722,9 +1330,152 @@ impl WalFile {
             max_frame: 0,
             min_frame: 0,
             max_frame_read_lock_index: 0,
+            journal_size_limit: -1,
+            id,
+            locking_mode: LockingMode::Normal,
+            holds_write_lock: Cell::new(false),
+            reading: Cell::new(false),
+            own_commits: 0,
+            wrote_frames: false,
+            sync_mode: SyncMode::Full,
+            syncing_frame: 0,
         }
     }
 
+    /// Whether the locks should be kept once the current transaction ends: always in exclusive
+    /// locking mode, otherwise only while no other connection could be waiting for them.
+    fn keeps_locks(&self) -> bool {
+        self.locking_mode == LockingMode::Exclusive
+            || self.get_shared().connections.load(Ordering::SeqCst) == 1
+    }
+
+    /// Whether another connection holds the WAL in exclusive locking mode.
+    fn locked_out(&self) -> bool {
+        let owner = self.get_shared().exclusive_owner.load(Ordering::SeqCst);
+        owner != 0 && owner != self.id
+    }
+
+    /// Takes back the locks this connection kept after its last transaction, if another
+    /// connection didn't release them in the meantime.
+    fn take_held_locks(&self) -> Option<HeldLocks> {
+        self.get_shared()
+            .held_locks
+            .lock()
+            .take_if(|held| held.owner == self.id)
+    }
+
+    /// Index of the read lock this connection holds, for its read transaction or kept from the
+    /// last one.
+    fn own_read_lock(&self) -> Option<usize> {
+        if self.reading.get() {
+            return Some(self.max_frame_read_lock_index);
+        }
+        self.get_shared()
+            .held_locks
+            .lock()
+            .as_ref()
+            .filter(|held| held.owner == self.id)
+            .map(|held| held.read_lock_index)
+    }
+
+    /// Forgets every frame of the log, which must all be backfilled, and restarts it. The read
+    /// marks that aren't in use are reset as they refer to frames of the old log, and the
+    /// snapshot of this connection is now entirely in the database file.
+    fn reset_log(&mut self, mode: CheckpointMode) -> Result<()> {
+        let own_read_lock = self.own_read_lock();
+        let shared = self.get_shared();
+        shared.wal_index.lock().clear();
+        shared.history.lock().clear();
+        shared.max_frame.store(0, Ordering::SeqCst);
+        shared.nbackfills.store(0, Ordering::SeqCst);
+        shared.synced_frame.store(0, Ordering::SeqCst);
+        for (index, lock) in shared.read_locks.iter_mut().enumerate() {
+            if own_read_lock == Some(index) {
+                lock.value.store(0, Ordering::SeqCst);
+            } else if lock.write() {
+                let mark = if index == 0 { 0 } else { READMARK_NOT_USED };
+                lock.value.store(mark, Ordering::SeqCst);
+                lock.unlock();
+            }
+        }
+        self.max_frame = 0;
+        self.min_frame = 1;
+        self.restart_log(mode)
+    }
+
+    /// Starts the log over after every frame was backfilled. New frames are written from the
+    /// start of the file again, so the header gets the next checkpoint sequence number and new
+    /// salts, which tells the old frames apart from the new ones. Then the file is truncated to
+    /// `journal_size_limit`, or to just the header for [CheckpointMode::Truncate].
+    fn restart_log(&mut self, mode: CheckpointMode) -> Result<()> {
+        let shared = self.get_shared();
+        let header = {
+            let mut header = shared.wal_header.lock();
+            header.checkpoint_seq = header.checkpoint_seq.wrapping_add(1);
+            header.salt_1 = header.salt_1.wrapping_add(1);
+            header.salt_2 = self.io.generate_random_number() as u32;
+            checksum_header(&mut header);
+            *header
+        };
+        sqlite3_ondisk::begin_write_wal_header(&shared.file, &header)?;
+        // The checksums of the first frame are seeded with the ones of the header.
+        shared.last_checksum = (header.checksum_1, header.checksum_2);
+
+        let limit = match mode {
+            CheckpointMode::Truncate => Some(0),
+            _ if self.journal_size_limit >= 0 => Some(self.journal_size_limit as usize),
+            _ => None,
+        };
+        if let Some(limit) = limit {
+            // The header was just rewritten in place, so it is always kept.
+            let limit = limit.max(WAL_HEADER_SIZE);
+            if shared.file.size()? > limit as u64 {
+                debug!("truncating wal to {} bytes", limit);
+                shared.file.truncate(limit)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `page` as the next frame of the WAL and returns its id.
+    fn write_frame(
+        &mut self,
+        page: &PageRef,
+        db_size: u32,
+        write_counter: Rc<RefCell<usize>>,
+    ) -> Result<u64> {
+        let page_id = page.get().id;
+        let shared = self.get_shared();
+        let max_frame = shared.max_frame.load(Ordering::SeqCst);
+        let frame_id = if max_frame == 0 { 1 } else { max_frame + 1 };
+        let offset = self.frame_offset(frame_id);
+        tracing::debug!(
+            "write_frame(frame={}, offset={}, page_id={})",
+            frame_id,
+            offset,
+            page_id
+        );
+        let header = shared.wal_header.clone();
+        let header = header.lock();
+        let checksums = shared.last_checksum;
+        let checksums = begin_write_wal_frame(
+            &shared.file,
+            offset,
+            page,
+            db_size,
+            write_counter,
+            &header,
+            checksums,
+        )?;
+        shared.last_checksum = checksums;
+        shared.wal_index.lock().append(frame_id, page_id as u64);
+        shared.max_frame.store(frame_id, Ordering::SeqCst);
+        // The writer reads its own frames, also when they are appended before the transaction
+        // ends, e.g. when a statement of an explicit transaction flushes the page cache.
+        self.max_frame = frame_id;
+        Ok(frame_id)
+    }
+
     fn frame_offset(&self, frame_id: u64) -> usize {
         assert!(frame_id > 0, "Frame ID must be 1-based");
         let page_size = self.page_size;
@@ -739,19 +1490,201 @@ impl WalFile {
     }
 }
 
+/// Computes the checksum of the first 24 bytes of the header into its checksum fields.
+fn checksum_header(header: &mut WalHeader) {
+    let native = cfg!(target_endian = "big"); // if target_endian is
+                                              // already big then we don't care but if isn't, header hasn't yet been
+                                              // encoded to big endian, therefore we want to swap bytes to compute this
+                                              // checksum.
+    let checksums = (0, 0);
+    let checksums = checksum_wal(
+        &header.as_bytes()[..WAL_HEADER_SIZE - 2 * 4], // first 24 bytes
+        header,
+        checksums,
+        native, // this is false because we haven't encoded the wal header yet
+    );
+    header.checksum_1 = checksums.0;
+    header.checksum_2 = checksums.1;
+}
+
 impl WalFileShared {
+    pub fn backpressure(&self) -> WalBackpressure {
+        *self.backpressure.lock()
+    }
+
+    pub fn set_backpressure(&self, backpressure: WalBackpressure) {
+        *self.backpressure.lock() = backpressure;
+    }
+
+    pub fn backpressure_stats(&self) -> WalBackpressureStats {
+        *self.backpressure_stats.lock()
+    }
+
+    /// The number of frames in the WAL.
+    pub fn max_frame(&self) -> u64 {
+        self.max_frame.load(Ordering::SeqCst)
+    }
+
+    pub fn set_defer_checkpoints(&self, enabled: bool) {
+        self.defer_checkpoints.store(enabled, Ordering::SeqCst);
+    }
+
+    pub fn set_retain_history(&self, enabled: bool) {
+        self.retain_history.store(enabled, Ordering::SeqCst);
+    }
+
+    pub fn retains_history(&self) -> bool {
+        self.retain_history.load(Ordering::SeqCst)
+    }
+
+    /// The commits that can still be read as of, oldest first: those after the frames that
+    /// were backfilled, and the one they end with.
+    pub fn history(&self) -> Vec<WalCommit> {
+        let nbackfills = self.nbackfills.load(Ordering::SeqCst);
+        let history = self.history.lock();
+        let start = history.partition_point(|commit| commit.frame < nbackfills);
+        history[start..].to_vec()
+    }
+
+    /// Joins the read lock whose mark is `mark`, or sets a free one to it. Returns the index of
+    /// the lock, None if all of them are busy with other marks.
+    fn join_read_mark(&mut self, mark: u32) -> Option<usize> {
+        for index in 0..self.read_locks.len() {
+            let lock = &mut self.read_locks[index];
+            if lock.value.load(Ordering::SeqCst) == mark && lock.read() {
+                // A checkpoint may have reset the mark before the lock was taken.
+                if lock.value.load(Ordering::SeqCst) == mark {
+                    return Some(index);
+                }
+                lock.unlock();
+            }
+        }
+        for index in 0..self.read_locks.len() {
+            let lock = &mut self.read_locks[index];
+            if lock.write() {
+                lock.value.store(mark, Ordering::SeqCst);
+                lock.unlock();
+                if lock.read() {
+                    if lock.value.load(Ordering::SeqCst) == mark {
+                        return Some(index);
+                    }
+                    lock.unlock();
+                }
+            }
+        }
+        None
+    }
+
+    fn release_locks(&mut self, held: HeldLocks) {
+        self.read_locks[held.read_lock_index].unlock();
+        if held.write_lock {
+            self.write_lock.unlock();
+        }
+    }
+
+    /// Rebuilds the wal-index from the frames left in the WAL file, like SQLite's
+    /// walIndexRecover(). Frames are replayed up to the last commit frame whose salts match the
+    /// header and whose checksums continue the chain seeded by it. Anything after that belongs to
+    /// a transaction that never committed, or to the log before it was restarted.
+    fn recover(&mut self, io: &Arc<dyn IO>) -> Result<()> {
+        let header = *self.wal_header.lock();
+        if header.magic != WAL_MAGIC_LE && header.magic != WAL_MAGIC_BE {
+            return Ok(());
+        }
+        let frame_size = WAL_FRAME_HEADER_SIZE + header.page_size as usize;
+        let frame_count = (self.file.size()? as usize).saturating_sub(WAL_HEADER_SIZE) / frame_size;
+        let use_native_endian = cfg!(target_endian = "big") as u32 == header.magic & 1;
+        let mut checksums = (header.checksum_1, header.checksum_2);
+        let mut uncommitted = Vec::new();
+        let mut wal_index = WalIndex::default();
+        let mut history = Vec::new();
+        for frame_idx in 0..frame_count {
+            let frame = Rc::new(RefCell::new(None));
+            {
+                let frame = frame.clone();
+                sqlite3_ondisk::begin_read_wal_frame_raw(
+                    &self.file,
+                    WAL_HEADER_SIZE + frame_idx * frame_size,
+                    frame_size,
+                    Box::new(move |buf: Arc<RefCell<Buffer>>| {
+                        *frame.borrow_mut() = Some(buf.borrow().as_slice().to_vec());
+                    }),
+                )?;
+            }
+            while frame.borrow().is_none() {
+                io.run_once()?;
+            }
+            let frame = frame.take().unwrap();
+            let page_id = read_u32(&frame, 0);
+            let db_size = read_u32(&frame, 4);
+            if page_id == 0
+                || read_u32(&frame, 8) != header.salt_1
+                || read_u32(&frame, 12) != header.salt_2
+            {
+                break;
+            }
+            let frame_checksums = checksum_wal(&frame[..8], &header, checksums, use_native_endian);
+            let frame_checksums = checksum_wal(
+                &frame[WAL_FRAME_HEADER_SIZE..],
+                &header,
+                frame_checksums,
+                use_native_endian,
+            );
+            if frame_checksums != (read_u32(&frame, 16), read_u32(&frame, 20)) {
+                break;
+            }
+            checksums = frame_checksums;
+            uncommitted.push(page_id as u64);
+            // Only the last frame of a transaction holds the size of the database.
+            if db_size != 0 {
+                for page_id in uncommitted.drain(..) {
+                    wal_index.append(wal_index.max_frame() + 1, page_id);
+                }
+                history.push(WalCommit {
+                    frame: wal_index.max_frame(),
+                    committed_at: None,
+                });
+                self.last_checksum = checksums;
+            }
+        }
+        let max_frame = wal_index.max_frame();
+        debug!("recovered {} frames from the wal", max_frame);
+        *self.wal_index.lock() = wal_index;
+        *self.history.lock() = history;
+        self.max_frame.store(max_frame, Ordering::SeqCst);
+        self.synced_frame.store(max_frame, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Starts reading the database header from the latest frame of page 1, which is newer than
+    /// the one in the database file when recovered commits changed it. Returns whether there is
+    /// such a frame.
+    pub fn begin_read_database_header(
+        &self,
+        header: Arc<SpinLock<DatabaseHeader>>,
+    ) -> Result<bool> {
+        let max_frame = self.max_frame.load(Ordering::SeqCst);
+        let Some(frame_id) = self.wal_index.lock().find(1, max_frame) else {
+            return Ok(false);
+        };
+        let frame_size = WAL_FRAME_HEADER_SIZE + self.wal_header.lock().page_size as usize;
+        let offset = WAL_HEADER_SIZE + (frame_id as usize - 1) * frame_size + WAL_FRAME_HEADER_SIZE;
+        sqlite3_ondisk::begin_read_wal_database_header(&self.file, offset, header)?;
+        Ok(true)
+    }
+
     pub fn open_shared(
         io: &Arc<dyn IO>,
         path: &str,
         page_size: u16,
     ) -> Result<Arc<UnsafeCell<WalFileShared>>> {
         let file = io.open_file(path, crate::io::OpenFlags::Create, false)?;
-        let header = if file.size()? > 0 {
+        let existing = file.size()? > 0;
+        let header = if existing {
             let wal_header = match sqlite3_ondisk::begin_read_wal_header(&file) {
                 Ok(header) => header,
                 Err(err) => return Err(LimboError::ParseError(err.to_string())),
             };
-            tracing::info!("recover not implemented yet");
             // TODO: Return a completion instead.
             io.run_once()?;
             wal_header
@@ -765,25 +1698,13 @@ impl WalFileShared {
                 magic,
                 file_format: 3007000,
                 page_size: page_size as u32,
-                checkpoint_seq: 0, // TODO implement sequence number
+                checkpoint_seq: 0,
                 salt_1: io.generate_random_number() as u32,
                 salt_2: io.generate_random_number() as u32,
                 checksum_1: 0,
                 checksum_2: 0,
             };
-            let native = cfg!(target_endian = "big"); // if target_endian is
-                                                      // already big then we don't care but if isn't, header hasn't yet been
-                                                      // encoded to big endian, therefore we want to swap bytes to compute this
-                                                      // checksum.
-            let checksums = (0, 0);
-            let checksums = checksum_wal(
-                &wal_header.as_bytes()[..WAL_HEADER_SIZE - 2 * 4], // first 24 bytes
-                &wal_header,
-                checksums,
-                native, // this is false because we haven't encoded the wal header yet
-            );
-            wal_header.checksum_1 = checksums.0;
-            wal_header.checksum_2 = checksums.1;
+            checksum_header(&mut wal_header);
             sqlite3_ondisk::begin_write_wal_header(&file, &wal_header)?;
             Arc::new(SpinLock::new(wal_header))
         };
@@ -791,15 +1712,14 @@ impl WalFileShared {
             let checksum = header.lock();
             (checksum.checksum_1, checksum.checksum_2)
         };
-        let shared = WalFileShared {
+        let mut shared = WalFileShared {
             wal_header: header,
             min_frame: AtomicU64::new(0),
             max_frame: AtomicU64::new(0),
             nbackfills: AtomicU64::new(0),
-            frame_cache: Arc::new(SpinLock::new(HashMap::new())),
+            wal_index: SpinLock::new(WalIndex::default()),
             last_checksum: checksum,
             file,
-            pages_in_frames: Arc::new(SpinLock::new(Vec::new())),
             read_locks: [
                 LimboRwLock {
                     lock: AtomicU32::new(NO_LOCK),
@@ -832,7 +1752,84 @@ impl WalFileShared {
                 nreads: AtomicU32::new(0),
                 value: AtomicU32::new(READMARK_NOT_USED),
             },
+            connections: AtomicU64::new(0),
+            next_connection_id: AtomicU64::new(0),
+            held_locks: SpinLock::new(None),
+            exclusive_owner: AtomicU64::new(0),
+            commits: AtomicU64::new(0),
+            synced_frame: AtomicU64::new(0),
+            first_unsynced_commit: SpinLock::new(None),
+            backpressure: SpinLock::new(WalBackpressure::default()),
+            backpressure_stats: SpinLock::new(WalBackpressureStats::default()),
+            defer_checkpoints: AtomicBool::new(false),
+            history: SpinLock::new(Vec::new()),
+            retain_history: AtomicBool::new(false),
+            checkpoint_frame: AtomicU64::new(0),
         };
+        if existing {
+            shared.recover(io)?;
+        }
         Ok(Arc::new(UnsafeCell::new(shared)))
     }
 }
+
+/// Number of times a commit frame ending at offset `end` has to be repeated so that the frame
+/// after them starts in a new sector.
+fn commit_padding_frames(end: usize, frame_size: usize, sector_size: usize) -> usize {
+    let next_sector = end.next_multiple_of(sector_size);
+    (next_sector - end).div_ceil(frame_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{commit_padding_frames, WalIndex, WAL_INDEX_SEGMENT_FRAMES};
+
+    #[test]
+    fn test_wal_index_find() {
+        let mut index = WalIndex::default();
+        // Pages 1 to 10 over and over, spanning a few segments.
+        let frames = 3 * WAL_INDEX_SEGMENT_FRAMES as u64 + 5;
+        for frame_id in 1..=frames {
+            index.append(frame_id, (frame_id - 1) % 10 + 1);
+        }
+        assert_eq!(index.max_frame(), frames);
+        assert_eq!(index.find(3, frames), Some(frames));
+        assert_eq!(index.find(7, frames), Some(frames - 6));
+        assert_eq!(index.find(7, 100), Some(97));
+        assert_eq!(
+            index.find(1, WAL_INDEX_SEGMENT_FRAMES as u64 + 1),
+            Some(4091)
+        );
+        assert_eq!(index.find(11, frames), None);
+        assert_eq!(index.find(5, 4), None);
+        assert_eq!(index.find(5, 0), None);
+
+        index.clear();
+        assert_eq!(index.find(3, frames), None);
+        index.append(1, 3);
+        assert_eq!(index.find(3, frames), Some(1));
+    }
+
+    #[test]
+    fn test_wal_index_latest_frames() {
+        let mut index = WalIndex::default();
+        for (frame_id, page_id) in [5, 2, 5, 9, 2, 5].into_iter().enumerate() {
+            index.append(frame_id as u64 + 1, page_id);
+        }
+        assert_eq!(index.latest_frames(1, 6), vec![(2, 5), (5, 6), (9, 4)]);
+        assert_eq!(index.latest_frames(1, 3), vec![(2, 2), (5, 3)]);
+        assert_eq!(index.latest_frames(4, 5), vec![(2, 5), (9, 4)]);
+    }
+
+    #[test]
+    fn test_commit_padding_frames() {
+        // 4096 byte pages, the first frame ends 56 bytes into the second sector.
+        let frame_size = 4096 + 24;
+        assert_eq!(commit_padding_frames(32 + frame_size, frame_size, 4096), 1);
+        // A commit that ends on a sector boundary needs no padding.
+        assert_eq!(commit_padding_frames(8192, frame_size, 4096), 0);
+        // Sectors larger than a frame take several.
+        assert_eq!(commit_padding_frames(32 + frame_size, frame_size, 16384), 3);
+        assert_eq!(commit_padding_frames(32 + 1048, 1048, 512), 1);
+    }
+}
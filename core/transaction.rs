@@ -0,0 +1,212 @@
+//! Transactions and savepoints as guards, which roll back what was done through them unless
+//! they are committed, like the ones of rusqlite.
+//!
+//! A [Transaction] is a write transaction started with `BEGIN IMMEDIATE`, and the connection
+//! is used through it for the statements to run in it. [Savepoint]s nest in a transaction and
+//! in each other, and undo the changes made since they began on their own. Rolling back puts
+//! back the schema the connection had too, in case tables or indexes were created or dropped.
+
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::schema::{Index, Table};
+use crate::storage::sqlite3_ondisk::DatabaseHeader;
+use crate::{Connection, LimboError, Result};
+
+pub struct Transaction {
+    conn: Rc<Connection>,
+    /// The database header when the transaction began, to put back on rollback.
+    header: DatabaseHeader,
+    state: SavedState,
+    ended: bool,
+}
+
+impl Transaction {
+    pub(crate) fn begin(conn: &Rc<Connection>) -> Result<Self> {
+        if !conn.get_auto_commit() {
+            return Err(LimboError::TxError(
+                "cannot start a transaction within a transaction".to_string(),
+            ));
+        }
+        conn.execute("BEGIN IMMEDIATE")?;
+        let header = conn.pager.db_header.lock().clone();
+        Ok(Self {
+            conn: conn.clone(),
+            header,
+            state: SavedState::capture(conn),
+            ended: false,
+        })
+    }
+
+    /// Starts a savepoint, whose changes can be rolled back without ending the transaction.
+    pub fn savepoint(&mut self) -> Result<Savepoint<'_>> {
+        Savepoint::begin(&self.conn)
+    }
+
+    /// Commits the transaction, or rolls it back if committing fails.
+    pub fn commit(mut self) -> Result<()> {
+        self.ended = true;
+        let result = self.conn.execute("COMMIT");
+        if result.is_err() {
+            self.undo()?;
+        }
+        result
+    }
+
+    /// Rolls the transaction back, which dropping it does as well.
+    pub fn rollback(mut self) -> Result<()> {
+        self.ended = true;
+        self.undo()
+    }
+
+    fn undo(&mut self) -> Result<()> {
+        // Statements run in the transaction may have ended it already.
+        if self.conn.get_auto_commit() {
+            return Ok(());
+        }
+        self.conn.rollback_write_tx(self.header.clone())?;
+        self.state.restore(&self.conn);
+        Ok(())
+    }
+}
+
+impl Deref for Transaction {
+    type Target = Rc<Connection>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if !self.ended {
+            let _ = self.undo();
+        }
+    }
+}
+
+/// A savepoint of a [Transaction], see [Transaction::savepoint].
+pub struct Savepoint<'a> {
+    conn: &'a Rc<Connection>,
+    /// Savepoints open when it began, which identifies it to the pager.
+    depth: usize,
+    state: SavedState,
+    ended: bool,
+}
+
+impl Savepoint<'_> {
+    fn begin(conn: &Rc<Connection>) -> Result<Savepoint<'_>> {
+        if conn.get_auto_commit() {
+            return Err(LimboError::TxError(
+                "cannot start a savepoint outside of a transaction".to_string(),
+            ));
+        }
+        Ok(Savepoint {
+            conn,
+            state: SavedState::capture(conn),
+            depth: conn.pager.begin_savepoint(),
+            ended: false,
+        })
+    }
+
+    /// Starts a savepoint within this one.
+    pub fn savepoint(&mut self) -> Result<Savepoint<'_>> {
+        Savepoint::begin(self.conn)
+    }
+
+    /// Ends the savepoint, keeping its changes in the transaction.
+    pub fn commit(mut self) -> Result<()> {
+        self.ended = true;
+        self.conn.pager.release_savepoint(self.depth);
+        Ok(())
+    }
+
+    /// Undoes the changes made since the savepoint began and ends it, which dropping it does
+    /// as well.
+    pub fn rollback(mut self) -> Result<()> {
+        self.ended = true;
+        self.undo();
+        Ok(())
+    }
+
+    fn undo(&mut self) {
+        // The transaction may have been ended by a statement run in the savepoint.
+        if self.conn.pager.savepoint_count() <= self.depth {
+            return;
+        }
+        self.conn.pager.rollback_to_savepoint(self.depth);
+        self.state.restore(self.conn);
+    }
+}
+
+impl Deref for Savepoint<'_> {
+    type Target = Rc<Connection>;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn
+    }
+}
+
+impl Drop for Savepoint<'_> {
+    fn drop(&mut self) {
+        if !self.ended {
+            self.undo();
+        }
+    }
+}
+
+/// What rolling back an explicit transaction that was started with SQL puts back, taken when
+/// it begins to write, see [Connection::rollback].
+pub(crate) struct TxSnapshot {
+    header: DatabaseHeader,
+    state: SavedState,
+}
+
+impl TxSnapshot {
+    pub(crate) fn capture(conn: &Connection) -> Self {
+        Self {
+            header: conn.pager.db_header.lock().clone(),
+            state: SavedState::capture(conn),
+        }
+    }
+
+    pub(crate) fn restore(self, conn: &Connection) -> Result<()> {
+        conn.rollback_write_tx(self.header)?;
+        self.state.restore(conn);
+        Ok(())
+    }
+}
+
+/// What a rollback puts back besides the pages of the database.
+struct SavedState {
+    tables: HashMap<String, Arc<Table>>,
+    indexes: HashMap<String, Vec<Arc<Index>>>,
+    schema_version: u64,
+    /// Row changes captured for subscribers, see [crate::Database::subscribe_changes].
+    changes: usize,
+}
+
+impl SavedState {
+    fn capture(conn: &Connection) -> Self {
+        let schema = conn.schema.read();
+        Self {
+            tables: schema.tables.clone(),
+            indexes: schema.indexes.clone(),
+            schema_version: schema.schema_version,
+            changes: conn.changes.borrow().len(),
+        }
+    }
+
+    fn restore(&self, conn: &Connection) {
+        conn.changes.borrow_mut().truncate(self.changes);
+        let mut schema = conn.schema.write();
+        if schema.schema_version != self.schema_version {
+            schema.tables = self.tables.clone();
+            schema.indexes = self.indexes.clone();
+            schema.schema_version += 1;
+        }
+    }
+}
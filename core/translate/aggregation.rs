@@ -2,7 +2,10 @@ use limbo_sqlite3_parser::ast;
 
 use crate::{
     function::AggFunc,
-    vdbe::{builder::ProgramBuilder, insn::Insn},
+    vdbe::{
+        builder::{CursorType, ProgramBuilder},
+        insn::Insn,
+    },
     LimboError, Result,
 };
 
@@ -46,6 +49,38 @@ pub fn emit_ungrouped_aggregation<'a>(
     Ok(())
 }
 
+/// Emits the bytecode for a `SELECT count(*) FROM t` that was marked as a simple count by the
+/// optimizer: the entries of t's b-tree are counted with a single Count instead of a loop.
+pub fn emit_simple_count<'a>(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx<'a>,
+    plan: &'a SelectPlan,
+) -> Result<()> {
+    let table = &plan.table_references[0];
+    let btree = table.btree().unwrap();
+    let cursor_id = program.alloc_cursor_id(
+        Some(table.identifier.clone()),
+        CursorType::BTreeTable(btree.clone()),
+    );
+    program.emit_insn(Insn::OpenReadAsync {
+        cursor_id,
+        root_page: btree.root_page,
+    });
+    program.emit_insn(Insn::OpenReadAwait {});
+    let count_reg = program.alloc_register();
+    program.emit_insn(Insn::Count {
+        cursor_id,
+        target_reg: count_reg,
+    });
+    t_ctx
+        .resolver
+        .expr_to_reg_cache
+        .push((&plan.aggregates[0].original_expr, count_reg));
+    emit_select_result(program, t_ctx, plan, None, None)?;
+
+    Ok(())
+}
+
 /// Emits the bytecode for processing an aggregate step.
 /// E.g. in `SELECT SUM(price) FROM t`, 'price' is evaluated for every row, and the result is added to the accumulator.
 ///
@@ -0,0 +1,468 @@
+use std::rc::{Rc, Weak};
+
+use crate::schema::{BTreeTable, Index, Schema};
+use crate::translate::schema::{emit_schema_entry, SchemaEntryType, SQLITE_TABLEID};
+use crate::translate::ProgramBuilder;
+use crate::translate::ProgramBuilderOpts;
+use crate::translate::QueryMode;
+use crate::util::normalize_ident;
+use crate::vdbe::builder::CursorType;
+use crate::vdbe::insn::{CmpInsFlags, Insn, RegisterOrLiteral};
+use crate::vdbe::BranchOffset;
+use crate::{ast, bail_parse_error, Connection, Result};
+use std::sync::Arc;
+
+pub const STAT1_TABLE_NAME: &str = "sqlite_stat1";
+const STAT1_TABLE_SQL: &str = "CREATE TABLE sqlite_stat1(tbl,idx,stat)";
+
+pub fn translate_analyze(
+    query_mode: QueryMode,
+    target: Option<ast::QualifiedName>,
+    schema: &Schema,
+    connection: &Weak<Connection>,
+) -> Result<ProgramBuilder> {
+    let tables = match target {
+        None => analyzable_tables(schema),
+        Some(name) => {
+            let name = normalize_ident(&name.name.0);
+            if name == "main" {
+                analyzable_tables(schema)
+            } else if let Some(table) = schema.get_btree_table(&name) {
+                vec![table]
+            } else if let Some(index) = schema
+                .indexes
+                .values()
+                .flatten()
+                .find(|index| index.name == name)
+            {
+                // Only whole tables are analyzed, so the table of the index is.
+                vec![schema.get_btree_table(&index.table_name).unwrap()]
+            } else {
+                bail_parse_error!("no such table or index: {}", name);
+            }
+        }
+    };
+    translate_analyze_tables(query_mode, schema, &tables, connection)
+}
+
+/// B-tree tables whose statistics ANALYZE collects, which excludes the internal tables.
+pub fn analyzable_tables(schema: &Schema) -> Vec<Rc<BTreeTable>> {
+    let mut tables = schema
+        .tables
+        .values()
+        .filter_map(|table| table.btree())
+        .filter(|table| !table.name.starts_with("sqlite_"))
+        .collect::<Vec<_>>();
+    tables.sort_by(|a, b| a.name.cmp(&b.name));
+    tables
+}
+
+/// Collects the statistics of `tables` into sqlite_stat1, creating the table if needed.
+///
+/// Like SQLite, an index gets a row `N d1 d2 ..` where N is the number of rows and dK is the
+/// average number of rows sharing the same values in the first K index columns. A table
+/// without indexes gets a row with just N, and empty tables get no rows.
+pub fn translate_analyze_tables(
+    query_mode: QueryMode,
+    schema: &Schema,
+    tables: &[Rc<BTreeTable>],
+    connection: &Weak<Connection>,
+) -> Result<ProgramBuilder> {
+    let mut program = ProgramBuilder::new(ProgramBuilderOpts {
+        query_mode,
+        num_cursors: 3,
+        approx_num_insns: 40 * (tables.len() + 1),
+        approx_num_labels: 4 * (tables.len() + 1),
+    });
+    let init_label = program.emit_init();
+    let start_offset = program.offset();
+
+    let existing_stat_table = schema.get_btree_table(STAT1_TABLE_NAME);
+    let stat_table = match &existing_stat_table {
+        Some(table) => table.clone(),
+        None => Rc::new(BTreeTable::from_sql(STAT1_TABLE_SQL, 0)?),
+    };
+    let stat_cursor_id = program.alloc_cursor_id(
+        Some(STAT1_TABLE_NAME.to_owned()),
+        CursorType::BTreeTable(stat_table.clone()),
+    );
+
+    let mut sqlite_schema_cursor_id = None;
+    if existing_stat_table.is_some() {
+        program.emit_insn(Insn::OpenWriteAsync {
+            cursor_id: stat_cursor_id,
+            root_page: RegisterOrLiteral::Literal(stat_table.root_page),
+        });
+        program.emit_insn(Insn::OpenWriteAwait {});
+        let all_tables = tables.len() == analyzable_tables(schema).len();
+        emit_clear_stats(&mut program, stat_cursor_id, tables, all_tables);
+    } else {
+        let root_reg = program.alloc_register();
+        program.emit_insn(Insn::CreateBtree {
+            db: 0,
+            root: root_reg,
+            flags: 1, // Table leaf page
+        });
+        let sqlite_table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
+        let cursor_id = program.alloc_cursor_id(
+            Some(SQLITE_TABLEID.to_owned()),
+            CursorType::BTreeTable(sqlite_table.clone()),
+        );
+        program.emit_insn(Insn::OpenWriteAsync {
+            cursor_id,
+            root_page: RegisterOrLiteral::Literal(sqlite_table.root_page),
+        });
+        program.emit_insn(Insn::OpenWriteAwait {});
+        emit_schema_entry(
+            &mut program,
+            cursor_id,
+            SchemaEntryType::Table,
+            STAT1_TABLE_NAME,
+            STAT1_TABLE_NAME,
+            root_reg,
+            Some(STAT1_TABLE_SQL.to_string()),
+        );
+        program.emit_insn(Insn::OpenWriteAsync {
+            cursor_id: stat_cursor_id,
+            root_page: RegisterOrLiteral::Register(root_reg),
+        });
+        program.emit_insn(Insn::OpenWriteAwait {});
+        sqlite_schema_cursor_id = Some(cursor_id);
+    }
+
+    for table in tables {
+        let indexes = schema.get_indices(&table.name);
+        if indexes.is_empty() {
+            emit_table_stat(&mut program, stat_cursor_id, table);
+        }
+        for index in indexes {
+            emit_index_stat(&mut program, stat_cursor_id, index);
+        }
+    }
+    program.emit_insn(Insn::Close {
+        cursor_id: stat_cursor_id,
+    });
+
+    if let Some(cursor_id) = sqlite_schema_cursor_id {
+        let parse_schema_where_clause =
+            format!("tbl_name = '{}' AND type = 'table'", STAT1_TABLE_NAME);
+        program.emit_insn(Insn::ParseSchema {
+            db: cursor_id,
+            where_clause: parse_schema_where_clause,
+        });
+        program.emit_insn(Insn::Close { cursor_id });
+    }
+
+    program.emit_insn(Insn::LoadAnalysis { db: 0 });
+
+    // The statistics are fresh again as far as PRAGMA optimize is concerned.
+    if let Some(conn) = connection.upgrade() {
+        for table in tables {
+            conn.reset_table_writes(&table.name);
+        }
+    }
+
+    program.emit_halt();
+    program.resolve_label(init_label, program.offset());
+    program.emit_transaction(true);
+    program.emit_constant_insns();
+    program.emit_goto(start_offset);
+
+    Ok(program)
+}
+
+/// Deletes the sqlite_stat1 rows of `tables`, or every row when all tables are analyzed.
+fn emit_clear_stats(
+    program: &mut ProgramBuilder,
+    stat_cursor_id: usize,
+    tables: &[Rc<BTreeTable>],
+    all_tables: bool,
+) {
+    let loop_start = program.allocate_label();
+    let loop_end = program.allocate_label();
+    let next_label = program.allocate_label();
+    let delete_label = program.allocate_label();
+    program.emit_insn(Insn::RewindAsync {
+        cursor_id: stat_cursor_id,
+    });
+    program.emit_insn(Insn::RewindAwait {
+        cursor_id: stat_cursor_id,
+        pc_if_empty: loop_end,
+    });
+    program.resolve_label(loop_start, program.offset());
+    if !all_tables {
+        let tbl_reg = program.alloc_register();
+        program.emit_insn(Insn::Column {
+            cursor_id: stat_cursor_id,
+            column: 0,
+            dest: tbl_reg,
+        });
+        let name_reg = program.alloc_register();
+        for table in tables {
+            program.emit_string8(table.name.clone(), name_reg);
+            program.emit_insn(Insn::Eq {
+                lhs: tbl_reg,
+                rhs: name_reg,
+                target_pc: delete_label,
+                flags: CmpInsFlags::default(),
+            });
+        }
+        program.emit_goto(next_label);
+    }
+    program.resolve_label(delete_label, program.offset());
+    program.emit_insn(Insn::DeleteAsync {
+        cursor_id: stat_cursor_id,
+    });
+    program.emit_insn(Insn::DeleteAwait {
+        cursor_id: stat_cursor_id,
+    });
+    program.resolve_label(next_label, program.offset());
+    program.emit_insn(Insn::NextAsync {
+        cursor_id: stat_cursor_id,
+    });
+    program.emit_insn(Insn::NextAwait {
+        cursor_id: stat_cursor_id,
+        pc_if_next: loop_start,
+    });
+    program.resolve_label(loop_end, program.offset());
+}
+
+/// Counts the rows of a table without indexes.
+fn emit_table_stat(program: &mut ProgramBuilder, stat_cursor_id: usize, table: &Rc<BTreeTable>) {
+    let cursor_id = program.alloc_cursor_id(
+        Some(table.name.clone()),
+        CursorType::BTreeTable(table.clone()),
+    );
+    program.emit_insn(Insn::OpenReadAsync {
+        cursor_id,
+        root_page: table.root_page,
+    });
+    program.emit_insn(Insn::OpenReadAwait {});
+
+    let count_reg = program.alloc_register();
+    let one_reg = program.alloc_register();
+    program.emit_int(0, count_reg);
+    program.emit_int(1, one_reg);
+    let loop_start = program.allocate_label();
+    let loop_end = program.allocate_label();
+    program.emit_insn(Insn::RewindAsync { cursor_id });
+    program.emit_insn(Insn::RewindAwait {
+        cursor_id,
+        pc_if_empty: loop_end,
+    });
+    program.resolve_label(loop_start, program.offset());
+    program.emit_insn(Insn::Add {
+        lhs: count_reg,
+        rhs: one_reg,
+        dest: count_reg,
+    });
+    program.emit_insn(Insn::NextAsync { cursor_id });
+    program.emit_insn(Insn::NextAwait {
+        cursor_id,
+        pc_if_next: loop_start,
+    });
+    program.resolve_label(loop_end, program.offset());
+    program.emit_insn(Insn::Close { cursor_id });
+
+    let skip_label = program.allocate_label();
+    program.emit_insn(Insn::IfNot {
+        reg: count_reg,
+        target_pc: skip_label,
+        jump_if_null: true,
+    });
+    let stat_reg = program.alloc_register();
+    let empty_reg = program.alloc_register();
+    program.emit_string8(String::new(), empty_reg);
+    program.emit_insn(Insn::Concat {
+        lhs: count_reg,
+        rhs: empty_reg,
+        dest: stat_reg,
+    });
+    emit_stat_row(program, stat_cursor_id, &table.name, None, stat_reg);
+    program.resolve_label(skip_label, program.offset());
+}
+
+/// Counts the rows of an index and the distinct values of each prefix of its columns. The
+/// index is scanned in order, so a prefix changes value whenever one of its columns differs
+/// from the previous row. NULLs compare equal to each other here.
+fn emit_index_stat(program: &mut ProgramBuilder, stat_cursor_id: usize, index: &Arc<Index>) {
+    let num_cols = index.columns.len();
+    let cursor_id = program.alloc_cursor_id(
+        Some(index.name.clone()),
+        CursorType::BTreeIndex(index.clone()),
+    );
+    program.emit_insn(Insn::OpenReadAsync {
+        cursor_id,
+        root_page: index.root_page,
+    });
+    program.emit_insn(Insn::OpenReadAwait {});
+
+    let count_reg = program.alloc_register();
+    let one_reg = program.alloc_register();
+    // Number of distinct values of the first K+1 columns.
+    let distinct_start = program.alloc_registers(num_cols);
+    let prev_start = program.alloc_registers(num_cols);
+    let cur_start = program.alloc_registers(num_cols);
+    program.emit_int(0, count_reg);
+    program.emit_int(1, one_reg);
+    for i in 0..num_cols {
+        program.emit_int(0, distinct_start + i);
+    }
+
+    let loop_start = program.allocate_label();
+    let loop_end = program.allocate_label();
+    let next_label = program.allocate_label();
+    let differs_labels: Vec<BranchOffset> =
+        (0..num_cols).map(|_| program.allocate_label()).collect();
+    program.emit_insn(Insn::RewindAsync { cursor_id });
+    program.emit_insn(Insn::RewindAwait {
+        cursor_id,
+        pc_if_empty: loop_end,
+    });
+    program.resolve_label(loop_start, program.offset());
+    program.emit_insn(Insn::Add {
+        lhs: count_reg,
+        rhs: one_reg,
+        dest: count_reg,
+    });
+    for i in 0..num_cols {
+        program.emit_insn(Insn::Column {
+            cursor_id,
+            column: i,
+            dest: cur_start + i,
+        });
+    }
+    // The first row starts a new value for every prefix.
+    program.emit_insn(Insn::Eq {
+        lhs: count_reg,
+        rhs: one_reg,
+        target_pc: differs_labels[0],
+        flags: CmpInsFlags::default(),
+    });
+    for (i, differs_label) in differs_labels.iter().enumerate() {
+        program.emit_insn(Insn::Ne {
+            lhs: cur_start + i,
+            rhs: prev_start + i,
+            target_pc: *differs_label,
+            flags: CmpInsFlags::default().null_eq(),
+        });
+    }
+    program.emit_goto(next_label);
+    // When column K differs, so do all the prefixes that include it.
+    for (i, differs_label) in differs_labels.iter().enumerate() {
+        program.resolve_label(*differs_label, program.offset());
+        program.emit_insn(Insn::Add {
+            lhs: distinct_start + i,
+            rhs: one_reg,
+            dest: distinct_start + i,
+        });
+    }
+    program.emit_insn(Insn::Copy {
+        src_reg: cur_start,
+        dst_reg: prev_start,
+        amount: num_cols - 1,
+    });
+    program.resolve_label(next_label, program.offset());
+    program.emit_insn(Insn::NextAsync { cursor_id });
+    program.emit_insn(Insn::NextAwait {
+        cursor_id,
+        pc_if_next: loop_start,
+    });
+    program.resolve_label(loop_end, program.offset());
+    program.emit_insn(Insn::Close { cursor_id });
+
+    let skip_label = program.allocate_label();
+    program.emit_insn(Insn::IfNot {
+        reg: count_reg,
+        target_pc: skip_label,
+        jump_if_null: true,
+    });
+    // stat = N || ' ' || ceil(N / d1) || ' ' || ..
+    let stat_reg = program.alloc_register();
+    let space_reg = program.alloc_register();
+    let avg_reg = program.alloc_register();
+    program.emit_string8(String::new(), space_reg);
+    program.emit_insn(Insn::Concat {
+        lhs: count_reg,
+        rhs: space_reg,
+        dest: stat_reg,
+    });
+    program.emit_string8(" ".to_string(), space_reg);
+    for i in 0..num_cols {
+        program.emit_insn(Insn::Add {
+            lhs: count_reg,
+            rhs: distinct_start + i,
+            dest: avg_reg,
+        });
+        program.emit_insn(Insn::Subtract {
+            lhs: avg_reg,
+            rhs: one_reg,
+            dest: avg_reg,
+        });
+        program.emit_insn(Insn::Divide {
+            lhs: avg_reg,
+            rhs: distinct_start + i,
+            dest: avg_reg,
+        });
+        program.emit_insn(Insn::Concat {
+            lhs: stat_reg,
+            rhs: space_reg,
+            dest: stat_reg,
+        });
+        program.emit_insn(Insn::Concat {
+            lhs: stat_reg,
+            rhs: avg_reg,
+            dest: stat_reg,
+        });
+    }
+    emit_stat_row(
+        program,
+        stat_cursor_id,
+        &index.table_name,
+        Some(&index.name),
+        stat_reg,
+    );
+    program.resolve_label(skip_label, program.offset());
+}
+
+/// Appends the row (tbl, idx, stat) to sqlite_stat1.
+fn emit_stat_row(
+    program: &mut ProgramBuilder,
+    stat_cursor_id: usize,
+    tbl_name: &str,
+    idx_name: Option<&str>,
+    stat_reg: usize,
+) {
+    let rowid_reg = program.alloc_register();
+    program.emit_insn(Insn::NewRowid {
+        cursor: stat_cursor_id,
+        rowid_reg,
+        prev_largest_reg: 0,
+    });
+    let start_reg = program.alloc_registers(3);
+    program.emit_string8(tbl_name.to_string(), start_reg);
+    match idx_name {
+        Some(idx_name) => program.emit_string8(idx_name.to_string(), start_reg + 1),
+        None => program.emit_null(start_reg + 1, None),
+    }
+    program.emit_insn(Insn::Copy {
+        src_reg: stat_reg,
+        dst_reg: start_reg + 2,
+        amount: 0,
+    });
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg,
+        count: 3,
+        dest_reg: record_reg,
+    });
+    program.emit_insn(Insn::InsertAsync {
+        cursor: stat_cursor_id,
+        key_reg: rowid_reg,
+        record_reg,
+        flag: 0,
+    });
+    program.emit_insn(Insn::InsertAwait {
+        cursor_id: stat_cursor_id,
+    });
+}
@@ -0,0 +1,57 @@
+//! Collating sequences, which define how text values compare to each other.
+
+use std::cmp::Ordering;
+
+use crate::util::normalize_ident;
+use crate::{bail_parse_error, Result};
+
+/// The collating sequences built into SQLite.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CollationSeq {
+    /// Compares the bytes of the text.
+    #[default]
+    Binary = 0,
+    /// Like [CollationSeq::Binary], with the 26 upper case ASCII letters folded to lower case.
+    NoCase = 1,
+    /// Like [CollationSeq::Binary], ignoring trailing spaces.
+    Rtrim = 2,
+}
+
+impl CollationSeq {
+    pub fn new(name: &str) -> Result<Self> {
+        match normalize_ident(name).as_str() {
+            "binary" => Ok(Self::Binary),
+            "nocase" => Ok(Self::NoCase),
+            "rtrim" => Ok(Self::Rtrim),
+            _ => bail_parse_error!("no such collation sequence: {}", name),
+        }
+    }
+
+    /// The collating sequence encoded as an integer by `self as i64`.
+    pub fn from_i64(value: i64) -> Self {
+        match value {
+            1 => Self::NoCase,
+            2 => Self::Rtrim,
+            _ => Self::Binary,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Binary => "BINARY",
+            Self::NoCase => "NOCASE",
+            Self::Rtrim => "RTRIM",
+        }
+    }
+
+    pub fn compare_strings(&self, lhs: &str, rhs: &str) -> Ordering {
+        match self {
+            Self::Binary => lhs.cmp(rhs),
+            Self::NoCase => lhs
+                .bytes()
+                .map(|b| b.to_ascii_lowercase())
+                .cmp(rhs.bytes().map(|b| b.to_ascii_lowercase())),
+            Self::Rtrim => lhs.trim_end_matches(' ').cmp(rhs.trim_end_matches(' ')),
+        }
+    }
+}
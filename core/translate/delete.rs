@@ -2,10 +2,10 @@ use crate::schema::Table;
 use crate::translate::emitter::emit_program;
 use crate::translate::optimizer::optimize_plan;
 use crate::translate::plan::{DeletePlan, Operation, Plan};
-use crate::translate::planner::{parse_limit, parse_where};
+use crate::translate::planner::{parse_limit, parse_order_by, parse_where};
 use crate::vdbe::builder::{ProgramBuilder, ProgramBuilderOpts, QueryMode};
-use crate::{schema::Schema, Result, SymbolTable};
-use limbo_sqlite3_parser::ast::{Expr, Limit, QualifiedName};
+use crate::{bail_parse_error, schema::Schema, Result, SymbolTable};
+use limbo_sqlite3_parser::ast::{Expr, Limit, QualifiedName, SortedColumn};
 
 use super::plan::TableReference;
 
@@ -14,10 +14,11 @@ pub fn translate_delete(
     schema: &Schema,
     tbl_name: &QualifiedName,
     where_clause: Option<Box<Expr>>,
+    order_by: Option<Vec<SortedColumn>>,
     limit: Option<Box<Limit>>,
     syms: &SymbolTable,
 ) -> Result<ProgramBuilder> {
-    let mut delete_plan = prepare_delete_plan(schema, tbl_name, where_clause, limit)?;
+    let mut delete_plan = prepare_delete_plan(schema, tbl_name, where_clause, order_by, limit)?;
     optimize_plan(&mut delete_plan, schema)?;
     let Plan::Delete(ref delete) = delete_plan else {
         panic!("delete_plan is not a DeletePlan");
@@ -36,11 +37,12 @@ pub fn prepare_delete_plan(
     schema: &Schema,
     tbl_name: &QualifiedName,
     where_clause: Option<Box<Expr>>,
+    order_by: Option<Vec<SortedColumn>>,
     limit: Option<Box<Limit>>,
 ) -> Result<Plan> {
     let table = match schema.get_table(tbl_name.name.0.as_str()) {
         Some(table) => table,
-        None => crate::bail_corrupt_error!("Parse error: no such table: {}", tbl_name),
+        None => crate::bail_parse_error!("no such table: {}", tbl_name),
     };
     let table = if let Some(table) = table.virtual_table() {
         Table::Virtual(table.clone())
@@ -53,7 +55,10 @@ pub fn prepare_delete_plan(
     let table_references = vec![TableReference {
         table,
         identifier: name,
-        op: Operation::Scan { iter_dir: None },
+        op: Operation::Scan {
+            iter_dir: None,
+            index: None,
+        },
         join_info: None,
     }];
 
@@ -67,6 +72,16 @@ pub fn prepare_delete_plan(
         &mut where_predicates,
     )?;
 
+    // Like SQLite built with SQLITE_ENABLE_UPDATE_DELETE_LIMIT, the rows to delete can be
+    // ordered to delete the first ones of them.
+    if order_by.is_some() && limit.is_none() {
+        bail_parse_error!("ORDER BY without LIMIT on DELETE");
+    }
+    if order_by.is_some() && table_references[0].virtual_table().is_some() {
+        bail_parse_error!("ORDER BY on DELETE from a virtual table is not supported");
+    }
+    let order_by = parse_order_by(order_by.as_deref(), &table_references)?;
+
     // Parse the LIMIT/OFFSET clause
     let (resolved_limit, resolved_offset) = limit.map_or(Ok((None, None)), |l| parse_limit(&l))?;
 
@@ -74,7 +89,7 @@ pub fn prepare_delete_plan(
         table_references,
         result_columns: vec![],
         where_clause: where_predicates,
-        order_by: None,
+        order_by,
         limit: resolved_limit,
         offset: resolved_offset,
         contains_constant_false_condition: false,
@@ -1,21 +1,24 @@
 // This module contains code for emitting bytecode instructions for SQL query execution.
 // It handles translating high-level SQL operations into low-level bytecode that can be executed by the virtual machine.
 
+use std::rc::Rc;
+
 use limbo_sqlite3_parser::ast::{self};
 
 use crate::function::Func;
+use crate::schema::{Column, PseudoTable};
 use crate::translate::plan::{DeletePlan, Plan, Search};
 use crate::util::exprs_are_equivalent;
-use crate::vdbe::builder::ProgramBuilder;
+use crate::vdbe::builder::{CursorType, ProgramBuilder};
 use crate::vdbe::{insn::Insn, BranchOffset};
 use crate::{Result, SymbolTable};
 
-use super::aggregation::emit_ungrouped_aggregation;
+use super::aggregation::{emit_simple_count, emit_ungrouped_aggregation};
 use super::expr::{translate_condition_expr, translate_expr, ConditionMetadata};
 use super::group_by::{emit_group_by, init_group_by, GroupByMetadata};
 use super::main_loop::{close_loop, emit_loop, init_loop, open_loop, LeftJoinMetadata, LoopLabels};
-use super::order_by::{emit_order_by, init_order_by, SortMetadata};
-use super::plan::{Operation, SelectPlan, TableReference, UpdatePlan};
+use super::order_by::{emit_order_by, init_order_by, sort_key, sorter_insert, SortMetadata};
+use super::plan::{Direction, Operation, SelectPlan, TableReference, UpdatePlan};
 use super::subquery::emit_subqueries;
 
 #[derive(Debug)]
@@ -171,6 +174,46 @@ fn epilogue(
     Ok(())
 }
 
+/// Like [epilogue] with [TransactionMode::Read], for a read transaction that sees the database
+/// as of the commit marker or timestamp `as_of` evaluates to.
+fn epilogue_as_of(
+    program: &mut ProgramBuilder,
+    init_label: BranchOffset,
+    start_offset: BranchOffset,
+    as_of: &ast::Expr,
+    syms: &SymbolTable,
+) -> Result<()> {
+    program.emit_insn(Insn::Halt {
+        err_code: 0,
+        description: String::new(),
+    });
+
+    program.resolve_label(init_label, program.offset());
+
+    program.emit_constant_insns();
+    // The constants the expression hoists are only emitted after it, so they are jumped to
+    // first and jump back to it.
+    let as_of_constants_label = program.allocate_label();
+    let as_of_label = program.allocate_label();
+    program.emit_insn(Insn::Goto {
+        target_pc: as_of_constants_label,
+    });
+    program.resolve_label(as_of_label, program.offset());
+    let point = program.alloc_register();
+    translate_expr(program, None, as_of, point, &Resolver::new(syms))?;
+    program.emit_insn(Insn::TransactionAsOf { point });
+    program.emit_insn(Insn::Goto {
+        target_pc: start_offset,
+    });
+    program.resolve_label(as_of_constants_label, program.offset());
+    program.emit_constant_insns();
+    program.emit_insn(Insn::Goto {
+        target_pc: as_of_label,
+    });
+
+    Ok(())
+}
+
 /// Main entry point for emitting bytecode for a SQL query
 /// Takes a query plan and generates the corresponding bytecode program
 pub fn emit_program(program: &mut ProgramBuilder, plan: Plan, syms: &SymbolTable) -> Result<()> {
@@ -196,7 +239,10 @@ fn emit_program_for_select(
     // Trivial exit on LIMIT 0
     if let Some(limit) = plan.limit {
         if limit == 0 {
-            epilogue(program, init_label, start_offset, TransactionMode::Read)?;
+            match &plan.as_of {
+                Some(as_of) => epilogue_as_of(program, init_label, start_offset, as_of, syms)?,
+                None => epilogue(program, init_label, start_offset, TransactionMode::Read)?,
+            }
             program.result_columns = plan.result_columns;
             program.table_references = plan.table_references;
             return Ok(());
@@ -206,7 +252,10 @@ fn emit_program_for_select(
     emit_query(program, &mut plan, &mut t_ctx)?;
 
     // Finalize program
-    if plan.table_references.is_empty() {
+    if let Some(as_of) = &plan.as_of {
+        // Also without tables, so that a commit that can't be read is still an error.
+        epilogue_as_of(program, init_label, start_offset, as_of, syms)?;
+    } else if plan.table_references.is_empty() {
         epilogue(program, init_label, start_offset, TransactionMode::None)?;
     } else {
         epilogue(program, init_label, start_offset, TransactionMode::Read)?;
@@ -263,9 +312,20 @@ pub fn emit_query<'a>(
     // Allocate registers for result columns
     t_ctx.reg_result_cols_start = Some(program.alloc_registers(plan.result_columns.len()));
 
+    if plan.simple_count {
+        emit_simple_count(program, t_ctx, plan)?;
+        return Ok(t_ctx.reg_result_cols_start.unwrap());
+    }
+
     // Initialize cursors and other resources needed for query execution
+    // Only the rows that LIMIT and OFFSET can reach need to be kept by the ORDER BY sorter.
     if let Some(ref mut order_by) = plan.order_by {
-        init_order_by(program, t_ctx, order_by)?;
+        init_order_by(
+            program,
+            t_ctx,
+            order_by,
+            sorter_max_rows(plan.limit, plan.offset),
+        )?;
     }
 
     if let Some(ref group_by) = plan.group_by {
@@ -328,7 +388,7 @@ pub fn emit_query<'a>(
 
 fn emit_program_for_delete(
     program: &mut ProgramBuilder,
-    plan: DeletePlan,
+    mut plan: DeletePlan,
     syms: &SymbolTable,
 ) -> Result<()> {
     let (mut t_ctx, init_label, start_offset) = prologue(
@@ -346,6 +406,15 @@ fn emit_program_for_delete(
         return Ok(());
     }
 
+    if let Some(ref order_by) = plan.order_by {
+        init_order_by(
+            program,
+            &mut t_ctx,
+            order_by,
+            sorter_max_rows(plan.limit, plan.offset),
+        )?;
+    }
+
     // No rows will be read from source table loops if there is a constant false condition eg. WHERE 0
     let after_main_loop_label = program.allocate_label();
     t_ctx.label_main_loop_end = Some(after_main_loop_label);
@@ -370,13 +439,41 @@ fn emit_program_for_delete(
         &plan.table_references,
         &plan.where_clause,
     )?;
-    emit_delete_insns(program, &mut t_ctx, &plan.table_references, &plan.limit)?;
+    if let Some(ref order_by) = plan.order_by {
+        emit_rowid_sorter_insert(program, &t_ctx, &plan.table_references, order_by)?;
+    } else {
+        emit_delete_insns(
+            program,
+            &mut t_ctx,
+            &plan.table_references,
+            &plan.limit,
+            &plan.offset,
+        )?;
+    }
 
     // Clean up and close the main execution loop
     close_loop(program, &mut t_ctx, &plan.table_references)?;
 
     program.resolve_label(after_main_loop_label, program.offset());
 
+    // With ORDER BY, the rows are deleted in a second loop over the sorted rowids
+    if let Some(ref order_by) = plan.order_by {
+        let end_label = open_sorted_rowid_loop(
+            program,
+            &mut t_ctx,
+            &mut plan.table_references,
+            order_by.len(),
+        );
+        emit_delete_insns(
+            program,
+            &mut t_ctx,
+            &plan.table_references,
+            &plan.limit,
+            &plan.offset,
+        )?;
+        close_sorted_rowid_loop(program, &t_ctx, end_label);
+    }
+
     // Finalize program
     epilogue(program, init_label, start_offset, TransactionMode::Write)?;
     program.result_columns = plan.result_columns;
@@ -389,6 +486,7 @@ fn emit_delete_insns(
     t_ctx: &mut TranslateCtx,
     table_references: &[TableReference],
     limit: &Option<isize>,
+    offset: &Option<isize>,
 ) -> Result<()> {
     let table_reference = table_references.first().unwrap();
     let cursor_id = match &table_reference.op {
@@ -398,10 +496,27 @@ fn emit_delete_insns(
                 program.resolve_cursor_id(&table_reference.identifier)
             }
             Search::IndexSearch { index, .. } => program.resolve_cursor_id(&index.name),
+            #[cfg(feature = "vector")]
+            Search::VectorMatch { .. } => program.resolve_cursor_id(&table_reference.identifier),
         },
         _ => return Ok(()),
     };
 
+    // Skip the rows before OFFSET
+    if let Some(offset) = offset.filter(|offset| *offset > 0) {
+        let offset_reg = program.alloc_register();
+        program.emit_insn(Insn::Integer {
+            value: offset as i64,
+            dest: offset_reg,
+        });
+        program.mark_last_insn_constant();
+        program.emit_insn(Insn::IfPos {
+            reg: offset_reg,
+            target_pc: t_ctx.labels_main_loop.first().unwrap().next,
+            decrement_by: 1,
+        });
+    }
+
     // Emit the instructions to delete the row
     let key_reg = program.alloc_register();
     program.emit_insn(Insn::RowId {
@@ -447,7 +562,7 @@ fn emit_delete_insns(
 
 fn emit_program_for_update(
     program: &mut ProgramBuilder,
-    plan: UpdatePlan,
+    mut plan: UpdatePlan,
     syms: &SymbolTable,
 ) -> Result<()> {
     let (mut t_ctx, init_label, start_offset) = prologue(
@@ -464,6 +579,8 @@ fn emit_program_for_update(
         program.table_references = plan.table_references;
         return Ok(());
     }
+    // Emit the subqueries of UPDATE ... FROM first so the results can be read in the main loop.
+    emit_subqueries(program, &mut t_ctx, &mut plan.table_references)?;
     if t_ctx.reg_limit.is_none() && plan.limit.is_some() {
         let reg = program.alloc_register();
         t_ctx.reg_limit = Some(reg);
@@ -489,6 +606,14 @@ fn emit_program_for_update(
             });
         }
     }
+    if let Some(ref order_by) = plan.order_by {
+        init_order_by(
+            program,
+            &mut t_ctx,
+            order_by,
+            sorter_max_rows(plan.limit, plan.offset),
+        )?;
+    }
     let after_main_loop_label = program.allocate_label();
     t_ctx.label_main_loop_end = Some(after_main_loop_label);
     if plan.contains_constant_false_condition {
@@ -508,11 +633,27 @@ fn emit_program_for_update(
         &plan.table_references,
         &plan.where_clause,
     )?;
-    emit_update_insns(&plan, &t_ctx, program)?;
+    if let Some(ref order_by) = plan.order_by {
+        emit_rowid_sorter_insert(program, &t_ctx, &plan.table_references, order_by)?;
+    } else {
+        emit_update_insns(&plan, &t_ctx, program)?;
+    }
     close_loop(program, &mut t_ctx, &plan.table_references)?;
 
     program.resolve_label(after_main_loop_label, program.offset());
 
+    // With ORDER BY, the rows are updated in a second loop over the sorted rowids
+    if let Some(ref order_by) = plan.order_by {
+        let end_label = open_sorted_rowid_loop(
+            program,
+            &mut t_ctx,
+            &mut plan.table_references,
+            order_by.len(),
+        );
+        emit_update_insns(&plan, &t_ctx, program)?;
+        close_sorted_rowid_loop(program, &t_ctx, end_label);
+    }
+
     // Finalize program
     epilogue(program, init_label, start_offset, TransactionMode::Write)?;
     program.result_columns = plan.returning.unwrap_or_default();
@@ -537,6 +678,8 @@ fn emit_update_insns(
                 program.resolve_cursor_id(&table_ref.identifier),
                 Some((index.clone(), program.resolve_cursor_id(&index.name))),
             ),
+            #[cfg(feature = "vector")]
+            Search::VectorMatch { .. } => (program.resolve_cursor_id(&table_ref.identifier), None),
         },
         _ => return Ok(()),
     };
@@ -614,6 +757,15 @@ fn emit_update_insns(
             }
         }
     }
+    program.emit_insn(Insn::Affinity {
+        start_reg: first_col_reg,
+        count: table_ref.columns().len(),
+        affinities: table_ref
+            .columns()
+            .iter()
+            .map(|col| col.affinity().aff_mask())
+            .collect(),
+    });
     let record_reg = program.alloc_register();
     program.emit_insn(Insn::MakeRecord {
         start_reg: first_col_reg,
@@ -634,6 +786,149 @@ fn emit_update_insns(
             target_pc: t_ctx.label_main_loop_end.unwrap(),
         })
     }
+    // With UPDATE ... FROM, a row is updated with the first row of the join it matches, like
+    // SQLite does, rather than once for each of them.
+    if plan.table_references.len() > 1 {
+        program.emit_insn(Insn::Goto {
+            target_pc: loop_labels.next,
+        });
+    }
     // TODO(pthorpe): handle RETURNING clause
     Ok(())
 }
+
+/// The number of rows an ORDER BY sorter has to keep for LIMIT and OFFSET to be applied to its
+/// output.
+fn sorter_max_rows(limit: Option<isize>, offset: Option<isize>) -> Option<usize> {
+    match (limit, offset) {
+        (Some(limit), offset) if limit >= 0 => {
+            Some(limit as usize + offset.unwrap_or(0).max(0) as usize)
+        }
+        _ => None,
+    }
+}
+
+/// Emits the bytecode for inserting the sort keys and the rowid of the current row into the
+/// ORDER BY sorter of an UPDATE or DELETE, which changes the rows once they are sorted.
+fn emit_rowid_sorter_insert(
+    program: &mut ProgramBuilder,
+    t_ctx: &TranslateCtx,
+    table_references: &[TableReference],
+    order_by: &[(ast::Expr, Direction)],
+) -> Result<()> {
+    let start_reg = program.alloc_registers(order_by.len() + 1);
+    for (i, (expr, _)) in order_by.iter().enumerate() {
+        translate_expr(
+            program,
+            Some(table_references),
+            sort_key(expr)?.0,
+            start_reg + i,
+            &t_ctx.resolver,
+        )?;
+    }
+    // The rowid is read from the table cursor, which also completes a deferred seek from an index.
+    let table_reference = table_references.first().unwrap();
+    program.emit_insn(Insn::RowId {
+        cursor_id: program.resolve_cursor_id(&table_reference.identifier),
+        dest: start_reg + order_by.len(),
+    });
+    let SortMetadata {
+        sort_cursor,
+        reg_sorter_data,
+    } = *t_ctx.meta_sort.as_ref().unwrap();
+    sorter_insert(
+        program,
+        start_reg,
+        order_by.len() + 1,
+        sort_cursor,
+        reg_sorter_data,
+    );
+    Ok(())
+}
+
+/// Opens a loop over the rowids in the ORDER BY sorter of an UPDATE or DELETE, which positions
+/// the table cursor on each of their rows in turn. The table is then read through its cursor
+/// like in a full scan, and the main loop labels point into this loop, so that the instructions
+/// changing the rows can be emitted as they are for the main loop.
+/// Returns the label of the end of the loop.
+fn open_sorted_rowid_loop(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    table_references: &mut [TableReference],
+    num_sort_keys: usize,
+) -> BranchOffset {
+    let loop_start = program.allocate_label();
+    let next = program.allocate_label();
+    let end = program.allocate_label();
+    let ty = crate::schema::Type::Null;
+    let pseudo_columns = (0..=num_sort_keys)
+        .map(|_| Column {
+            name: None,
+            primary_key: false,
+            ty,
+            ty_str: ty.to_string().to_uppercase(),
+            is_rowid_alias: false,
+            notnull: false,
+            default: None,
+        })
+        .collect();
+    let pseudo_table = Rc::new(PseudoTable {
+        columns: pseudo_columns,
+    });
+    let pseudo_cursor = program.alloc_cursor_id(None, CursorType::Pseudo(pseudo_table));
+    let SortMetadata {
+        sort_cursor,
+        reg_sorter_data,
+    } = *t_ctx.meta_sort.as_ref().unwrap();
+    program.emit_insn(Insn::OpenPseudo {
+        cursor_id: pseudo_cursor,
+        content_reg: reg_sorter_data,
+        num_fields: num_sort_keys + 1,
+    });
+    program.emit_insn(Insn::SorterSort {
+        cursor_id: sort_cursor,
+        pc_if_empty: end,
+    });
+
+    program.resolve_label(loop_start, program.offset());
+    program.emit_insn(Insn::SorterData {
+        cursor_id: sort_cursor,
+        dest_reg: reg_sorter_data,
+        pseudo_cursor,
+    });
+    let rowid_reg = program.alloc_register();
+    program.emit_insn(Insn::Column {
+        cursor_id: pseudo_cursor,
+        column: num_sort_keys,
+        dest: rowid_reg,
+    });
+    let table_reference = table_references.first_mut().unwrap();
+    program.emit_insn(Insn::SeekRowid {
+        cursor_id: program.resolve_cursor_id(&table_reference.identifier),
+        src_reg: rowid_reg,
+        target_pc: next,
+    });
+    table_reference.op = Operation::Scan {
+        iter_dir: None,
+        index: None,
+    };
+
+    t_ctx.labels_main_loop[0] = LoopLabels {
+        loop_start,
+        next,
+        loop_end: end,
+    };
+    t_ctx.label_main_loop_end = Some(end);
+    end
+}
+
+fn close_sorted_rowid_loop(program: &mut ProgramBuilder, t_ctx: &TranslateCtx, end: BranchOffset) {
+    let SortMetadata { sort_cursor, .. } = *t_ctx.meta_sort.as_ref().unwrap();
+    let labels = t_ctx.labels_main_loop.first().unwrap();
+    program.resolve_label(labels.next, program.offset());
+    program.emit_insn(Insn::SorterNext {
+        cursor_id: sort_cursor,
+        pc_if_next: labels.loop_start,
+    });
+    program.resolve_label(end, program.offset());
+}
@@ -2,15 +2,17 @@ use limbo_sqlite3_parser::ast::{self, UnaryOperator};
 
 #[cfg(feature = "json")]
 use crate::function::JsonFunc;
-use crate::function::{Func, FuncCtx, MathFuncArity, ScalarFunc, VectorFunc};
+#[cfg(feature = "vector")]
+use crate::function::VectorFunc;
+use crate::function::{Func, FuncCtx, MathFuncArity, ScalarFunc};
 use crate::schema::{Table, Type};
-use crate::util::normalize_ident;
+use crate::util::{expr_is_deterministic, normalize_ident, vtable_args};
 use crate::vdbe::{
-    builder::ProgramBuilder,
+    builder::{CursorType, ProgramBuilder},
     insn::{CmpInsFlags, Insn},
     BranchOffset,
 };
-use crate::Result;
+use crate::{Result, VirtualTable};
 
 use super::emitter::Resolver;
 use super::plan::{Operation, TableReference};
@@ -292,7 +294,8 @@ pub fn translate_condition_expr(
         | ast::Expr::FunctionCall { .. }
         | ast::Expr::Column { .. }
         | ast::Expr::RowId { .. }
-        | ast::Expr::Case { .. } => {
+        | ast::Expr::Case { .. }
+        | ast::Expr::InTable { .. } => {
             let reg = program.alloc_register();
             translate_expr(program, Some(referenced_tables), expr, reg, resolver)?;
             emit_cond_jump(program, condition_metadata, reg);
@@ -495,7 +498,7 @@ pub fn translate_expr(
         ast::Expr::Between { .. } => todo!(),
         ast::Expr::Binary(e1, op, e2) => {
             // Check if both sides of the expression are identical and reuse the same register if so
-            if e1 == e2 {
+            if e1 == e2 && expr_is_deterministic(e1) {
                 let shared_reg = program.alloc_register();
                 translate_expr(program, referenced_tables, e1, shared_reg, resolver)?;
 
@@ -813,6 +816,7 @@ pub fn translate_expr(
                         )
                     }
                 },
+                #[cfg(feature = "vector")]
                 Func::Vector(vector_func) => match vector_func {
                     VectorFunc::Vector | VectorFunc::Vector32 => {
                         let args = expect_arguments_exact!(args, 1, vector_func);
@@ -850,7 +854,7 @@ pub fn translate_expr(
                         });
                         Ok(target_register)
                     }
-                    VectorFunc::VectorDistanceCos => {
+                    VectorFunc::VectorDistanceCos | VectorFunc::VectorDistanceL2 => {
                         let args = expect_arguments_exact!(args, 2, vector_func);
                         let regs = program.alloc_registers(2);
                         translate_expr(program, referenced_tables, &args[0], regs, resolver)?;
@@ -1017,46 +1021,54 @@ pub fn translate_expr(
                             Ok(target_register)
                         }
                         ScalarFunc::Iif => {
-                            let args = match args {
-                                Some(args) if args.len() == 3 => args,
-                                _ => crate::bail_parse_error!(
-                                    "{} requires exactly 3 arguments",
-                                    srf.to_string()
-                                ),
-                            };
+                            let args = expect_arguments_min!(args, 2, srf);
+                            // iif(c1, v1, c2, v2, ..., else) works like CASE WHEN c1 THEN v1
+                            // WHEN c2 THEN v2 ... ELSE else END: the conditions are evaluated in
+                            // order, and only the value of the first true one is.
                             let temp_reg = program.alloc_register();
-                            translate_expr(
-                                program,
-                                referenced_tables,
-                                &args[0],
-                                temp_reg,
-                                resolver,
-                            )?;
-                            let jump_target_when_false = program.allocate_label();
-                            program.emit_insn(Insn::IfNot {
-                                reg: temp_reg,
-                                target_pc: jump_target_when_false,
-                                jump_if_null: true,
-                            });
-                            translate_expr(
-                                program,
-                                referenced_tables,
-                                &args[1],
-                                target_register,
-                                resolver,
-                            )?;
                             let jump_target_result = program.allocate_label();
-                            program.emit_insn(Insn::Goto {
-                                target_pc: jump_target_result,
-                            });
-                            program.resolve_label(jump_target_when_false, program.offset());
-                            translate_expr(
-                                program,
-                                referenced_tables,
-                                &args[2],
-                                target_register,
-                                resolver,
-                            )?;
+                            for pair in args.chunks(2) {
+                                let [condition, value] = pair else {
+                                    translate_expr(
+                                        program,
+                                        referenced_tables,
+                                        &pair[0],
+                                        target_register,
+                                        resolver,
+                                    )?;
+                                    break;
+                                };
+                                translate_expr(
+                                    program,
+                                    referenced_tables,
+                                    condition,
+                                    temp_reg,
+                                    resolver,
+                                )?;
+                                let jump_target_when_false = program.allocate_label();
+                                program.emit_insn(Insn::IfNot {
+                                    reg: temp_reg,
+                                    target_pc: jump_target_when_false,
+                                    jump_if_null: true,
+                                });
+                                translate_expr(
+                                    program,
+                                    referenced_tables,
+                                    value,
+                                    target_register,
+                                    resolver,
+                                )?;
+                                program.emit_insn(Insn::Goto {
+                                    target_pc: jump_target_result,
+                                });
+                                program.resolve_label(jump_target_when_false, program.offset());
+                            }
+                            if args.len() % 2 == 0 {
+                                program.emit_insn(Insn::Null {
+                                    dest: target_register,
+                                    dest_end: None,
+                                });
+                            }
                             program.resolve_label(jump_target_result, program.offset());
                             Ok(target_register)
                         }
@@ -1260,30 +1272,44 @@ pub fn translate_expr(
                             Ok(target_register)
                         }
                         ScalarFunc::UnixEpoch | ScalarFunc::JulianDay => {
-                            let mut start_reg = 0;
-                            match args {
-                                Some(args) if args.len() > 1 => {
-                                    crate::bail_parse_error!("epoch or julianday function with > 1 arguments. Modifiers are not yet supported.");
-                                }
-                                Some(args) if args.len() == 1 => {
-                                    let arg_reg = program.alloc_register();
-                                    let _ = translate_expr(
-                                        program,
-                                        referenced_tables,
-                                        &args[0],
-                                        arg_reg,
-                                        resolver,
-                                    )?;
-                                    start_reg = arg_reg;
-                                }
-                                _ => {}
+                            let args = args.as_deref().unwrap_or_default();
+                            // With only literal arguments the result is the same for every row
+                            // (even for 'now', which is fixed for the whole statement), so it is
+                            // computed once before the loops, where e.g. an index seek can use it.
+                            let is_constant =
+                                args.iter().all(|arg| matches!(arg, ast::Expr::Literal(_)));
+                            let start_reg = program.alloc_registers(args.len());
+                            for (i, arg) in args.iter().enumerate() {
+                                translate_and_mark(
+                                    program,
+                                    referenced_tables,
+                                    arg,
+                                    start_reg + i,
+                                    resolver,
+                                )?;
+                            }
+                            if !is_constant {
+                                program.emit_insn(Insn::Function {
+                                    constant_mask: 0,
+                                    start_reg,
+                                    dest: target_register,
+                                    func: func_ctx,
+                                });
+                                return Ok(target_register);
                             }
+                            let result_reg = program.alloc_register();
                             program.emit_insn(Insn::Function {
                                 constant_mask: 0,
                                 start_reg,
-                                dest: target_register,
+                                dest: result_reg,
                                 func: func_ctx,
                             });
+                            program.mark_last_insn_constant();
+                            program.emit_insn(Insn::Copy {
+                                src_reg: result_reg,
+                                dst_reg: target_register,
+                                amount: 0,
+                            });
                             Ok(target_register)
                         }
                         ScalarFunc::Time => {
@@ -1571,31 +1597,27 @@ pub fn translate_expr(
                             target_register,
                             func_ctx,
                         ),
-                        ScalarFunc::Likely => {
-                            let args = if let Some(args) = args {
-                                if args.len() != 1 {
+                        ScalarFunc::Likely | ScalarFunc::Unlikely | ScalarFunc::Likelihood => {
+                            let args = if *srf == ScalarFunc::Likelihood {
+                                let args = expect_arguments_exact!(args, 2, srf);
+                                if likelihood_probability(&args[1]).is_none() {
                                     crate::bail_parse_error!(
-                                        "likely function must have exactly 1 argument",
+                                        "second argument to likelihood() must be a constant between 0.0 and 1.0"
                                     );
                                 }
                                 args
                             } else {
-                                crate::bail_parse_error!("likely function with no arguments",);
+                                expect_arguments_exact!(args, 1, srf)
                             };
-                            let start_reg = program.alloc_register();
+                            // The hints only tell the planner how often the argument is true,
+                            // so they evaluate to the argument itself.
                             translate_and_mark(
                                 program,
                                 referenced_tables,
                                 &args[0],
-                                start_reg,
+                                target_register,
                                 resolver,
                             )?;
-                            program.emit_insn(Insn::Function {
-                                constant_mask: 0,
-                                start_reg,
-                                dest: target_register,
-                                func: func_ctx,
-                            });
                             Ok(target_register)
                         }
                     }
@@ -1726,6 +1748,18 @@ pub fn translate_expr(
                     }
                     _ => unreachable!(),
                 },
+                // A materialized subquery is read from its transient table.
+                Operation::Subquery {
+                    materialized: true, ..
+                } => {
+                    let cursor_id = program.resolve_cursor_id(&table_reference.identifier);
+                    program.emit_insn(Insn::Column {
+                        cursor_id,
+                        column: *column,
+                        dest: target_register,
+                    });
+                    Ok(target_register)
+                }
                 // If we are reading a column from a subquery, we instead copy the column from the
                 // subquery's result registers.
                 Operation::Subquery {
@@ -1752,8 +1786,24 @@ pub fn translate_expr(
         }
         ast::Expr::InList { .. } => todo!(),
         ast::Expr::InSelect { .. } => todo!(),
-        ast::Expr::InTable { .. } => todo!(),
-        ast::Expr::IsNull(_) => todo!(),
+        ast::Expr::InTable { .. } => {
+            translate_in_table(program, referenced_tables, expr, target_register, resolver)
+        }
+        ast::Expr::IsNull(expr) => {
+            let reg = program.alloc_register();
+            translate_expr(program, referenced_tables, expr, reg, resolver)?;
+            let if_true_label = program.allocate_label();
+            wrap_eval_jump_expr(
+                program,
+                Insn::IsNull {
+                    reg,
+                    target_pc: if_true_label,
+                },
+                target_register,
+                if_true_label,
+            );
+            Ok(target_register)
+        }
         ast::Expr::Like { not, .. } => {
             let like_reg = if *not {
                 program.alloc_register()
@@ -1823,7 +1873,21 @@ pub fn translate_expr(
             ast::Literal::CurrentTimestamp => todo!(),
         },
         ast::Expr::Name(_) => todo!(),
-        ast::Expr::NotNull(_) => todo!(),
+        ast::Expr::NotNull(expr) => {
+            let reg = program.alloc_register();
+            translate_expr(program, referenced_tables, expr, reg, resolver)?;
+            let if_true_label = program.allocate_label();
+            wrap_eval_jump_expr(
+                program,
+                Insn::NotNull {
+                    reg,
+                    target_pc: if_true_label,
+                },
+                target_register,
+                if_true_label,
+            );
+            Ok(target_register)
+        }
         ast::Expr::Parenthesized(exprs) => {
             if exprs.is_empty() {
                 crate::bail_parse_error!("parenthesized expression with no arguments");
@@ -2189,6 +2253,133 @@ fn emit_binary_insn(
     Ok(())
 }
 
+/// Translates `lhs [NOT] IN tvf(args)` for a table-valued function such as `carray()` by
+/// scanning the function's first column for a match, which sets the result to 1 or 0.
+/// Like `IN (SELECT ...)`, the result is NULL instead of "not found" if the lhs is NULL or
+/// the function returns a NULL, unless it returns no rows at all.
+fn translate_in_table(
+    program: &mut ProgramBuilder,
+    referenced_tables: Option<&[TableReference]>,
+    expr: &ast::Expr,
+    target_register: usize,
+    resolver: &Resolver,
+) -> Result<usize> {
+    let ast::Expr::InTable {
+        lhs,
+        not,
+        rhs,
+        args,
+    } = expr
+    else {
+        crate::bail_parse_error!("expected IN table expression");
+    };
+    let name = normalize_ident(rhs.name.0.as_str());
+    if !resolver.symbol_table.vtab_modules.contains_key(&name) {
+        crate::bail_parse_error!("IN {} is only supported for table-valued functions", name);
+    }
+    let args = args.as_deref().unwrap_or_default();
+    let vtab = VirtualTable::from_args(
+        None,
+        &name,
+        vtable_args(args),
+        resolver.symbol_table,
+        limbo_ext::VTabKind::TableValuedFunction,
+        Some(args.to_vec()),
+    )?;
+    let cursor_id = program.alloc_cursor_id(None, CursorType::VirtualTable(vtab));
+
+    let lhs_reg = program.alloc_register();
+    translate_expr(program, referenced_tables, lhs, lhs_reg, resolver)?;
+    let args_reg = program.alloc_registers(args.len());
+    for (i, arg) in args.iter().enumerate() {
+        translate_expr(program, referenced_tables, arg, args_reg + i, resolver)?;
+    }
+    let value_reg = program.alloc_register();
+    let saw_null_reg = program.alloc_register();
+    program.emit_insn(Insn::Integer {
+        value: 0,
+        dest: saw_null_reg,
+    });
+
+    let label_loop = program.allocate_label();
+    let label_next = program.allocate_label();
+    let label_found = program.allocate_label();
+    let label_not_found = program.allocate_label();
+    let label_null = program.allocate_label();
+    let label_end = program.allocate_label();
+
+    program.emit_insn(Insn::VOpenAsync { cursor_id });
+    program.emit_insn(Insn::VOpenAwait {});
+    program.emit_insn(Insn::VFilter {
+        cursor_id,
+        pc_if_empty: label_not_found,
+        arg_count: args.len(),
+        args_reg,
+        idx_str: None,
+        idx_num: 0,
+    });
+    program.emit_insn(Insn::IsNull {
+        reg: lhs_reg,
+        target_pc: label_null,
+    });
+    program.resolve_label(label_loop, program.offset());
+    // Table-valued functions return their values in the first column, ahead of the hidden
+    // columns for their arguments.
+    program.emit_insn(Insn::VColumn {
+        cursor_id,
+        column: 0,
+        dest: value_reg,
+    });
+    program.emit_insn(Insn::Eq {
+        lhs: lhs_reg,
+        rhs: value_reg,
+        target_pc: label_found,
+        flags: CmpInsFlags::default(),
+    });
+    program.emit_insn(Insn::NotNull {
+        reg: value_reg,
+        target_pc: label_next,
+    });
+    program.emit_insn(Insn::Integer {
+        value: 1,
+        dest: saw_null_reg,
+    });
+    program.resolve_label(label_next, program.offset());
+    program.emit_insn(Insn::VNext {
+        cursor_id,
+        pc_if_next: label_loop,
+    });
+    program.emit_insn(Insn::If {
+        reg: saw_null_reg,
+        target_pc: label_null,
+        jump_if_null: false,
+    });
+
+    program.resolve_label(label_not_found, program.offset());
+    program.emit_insn(Insn::Integer {
+        value: *not as i64,
+        dest: target_register,
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: label_end,
+    });
+    program.resolve_label(label_found, program.offset());
+    program.emit_insn(Insn::Integer {
+        value: !*not as i64,
+        dest: target_register,
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: label_end,
+    });
+    program.resolve_label(label_null, program.offset());
+    program.emit_insn(Insn::Null {
+        dest: target_register,
+        dest_end: None,
+    });
+    program.resolve_label(label_end, program.offset());
+    Ok(target_register)
+}
+
 /// The base logic for translating LIKE and GLOB expressions.
 /// The logic for handling "NOT LIKE" is different depending on whether the expression
 /// is a conditional jump or not. This is why the caller handles the "NOT LIKE" behavior;
@@ -2247,8 +2438,26 @@ fn translate_like_base(
                 },
             });
         }
-        ast::LikeOperator::Match => todo!(),
-        ast::LikeOperator::Regexp => todo!(),
+        // MATCH is only answered by a vector index, which the optimizer makes the table search.
+        ast::LikeOperator::Match => {
+            crate::bail_parse_error!("MATCH needs a column with a vector index and a LIMIT")
+        }
+        ast::LikeOperator::Regexp => {
+            // Like in SQLite, X REGEXP Y calls the regexp(Y, X) function, which has to come
+            // from an extension.
+            let Some(func) = resolver.resolve_function("regexp", 2) else {
+                crate::bail_parse_error!("no such function: regexp");
+            };
+            let start_reg = program.alloc_registers(2);
+            translate_expr(program, referenced_tables, rhs, start_reg, resolver)?;
+            translate_expr(program, referenced_tables, lhs, start_reg + 1, resolver)?;
+            program.emit_insn(Insn::Function {
+                constant_mask: 0,
+                start_reg,
+                dest: target_register,
+                func: FuncCtx { func, arg_count: 2 },
+            });
+        }
     }
 
     Ok(target_register)
@@ -2322,6 +2531,19 @@ fn wrap_eval_jump_expr_zero_or_null(
     program.preassign_label_to_next_insn(if_true_label);
 }
 
+/// The probability given to likelihood(X, P), which like in SQLite must be a floating point
+/// literal between 0.0 and 1.0.
+pub fn likelihood_probability(expr: &ast::Expr) -> Option<f64> {
+    match expr {
+        ast::Expr::Literal(ast::Literal::Numeric(n))
+            if !n.starts_with("0x") && n.contains(['.', 'e', 'E']) =>
+        {
+            n.parse::<f64>().ok().filter(|p| (0.0..=1.0).contains(p))
+        }
+        _ => None,
+    }
+}
+
 pub fn maybe_apply_affinity(col_type: Type, target_register: usize, program: &mut ProgramBuilder) {
     if col_type == Type::Real {
         program.emit_insn(Insn::RealAffinity {
@@ -82,6 +82,7 @@ pub fn init_group_by(
     program.emit_insn(Insn::SorterOpen {
         cursor_id: sort_cursor,
         columns: non_aggregate_count + plan.aggregates.len(),
+        max_rows: None,
         order: Record::new(order),
     });
 
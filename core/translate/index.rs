@@ -60,6 +60,7 @@ pub fn translate_create_index(
             })
             .collect(),
         unique: unique_if_not_exists.0,
+        ephemeral: false,
     });
 
     // Allocate the necessary cursors:
@@ -126,6 +127,7 @@ pub fn translate_create_index(
     program.emit_insn(Insn::SorterOpen {
         cursor_id: sorter_cursor_id,
         columns: columns.len(),
+        max_rows: None,
         order: Record::new(order),
     });
     let content_reg = program.alloc_register();
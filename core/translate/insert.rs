@@ -52,7 +52,7 @@ pub fn translate_insert(
     let table_name = &tbl_name.name;
     let table = match schema.get_table(table_name.0.as_str()) {
         Some(table) => table,
-        None => crate::bail_corrupt_error!("Parse error: no such table: {}", table_name),
+        None => crate::bail_parse_error!("no such table: {}", table_name),
     };
     let resolver = Resolver::new(syms);
     if let Some(virtual_table) = &table.virtual_table() {
@@ -73,7 +73,7 @@ pub fn translate_insert(
     let start_offset = program.offset();
 
     let Some(btree_table) = table.btree() else {
-        crate::bail_corrupt_error!("Parse error: no such table: {}", table_name);
+        crate::bail_parse_error!("no such table: {}", table_name);
     };
     if !btree_table.has_rowid {
         crate::bail_parse_error!("INSERT into WITHOUT ROWID table is not supported");
@@ -92,15 +92,15 @@ pub fn translate_insert(
         InsertBody::DefaultValues => &vec![vec![]],
     };
 
-    let column_mappings = resolve_columns_for_insert(&table, columns, values)?;
-    // Check if rowid was provided (through INTEGER PRIMARY KEY as a rowid alias)
+    let (column_mappings, rowid_value_index) = resolve_columns_for_insert(&table, columns, values)?;
+    // Check if rowid was provided, through INTEGER PRIMARY KEY as a rowid alias or by name
     let rowid_alias_index = btree_table.columns.iter().position(|c| c.is_rowid_alias);
     let has_user_provided_rowid = {
         assert_eq!(column_mappings.len(), btree_table.columns.len());
         if let Some(index) = rowid_alias_index {
             column_mappings[index].value_index.is_some()
         } else {
-            false
+            rowid_value_index.is_some()
         }
     };
 
@@ -109,13 +109,9 @@ pub fn translate_insert(
     let num_cols = btree_table.columns.len();
     let rowid_reg = program.alloc_registers(num_cols + 1);
     let column_registers_start = rowid_reg + 1;
-    let rowid_alias_reg = {
-        if has_user_provided_rowid {
-            Some(column_registers_start + rowid_alias_index.unwrap())
-        } else {
-            None
-        }
-    };
+    let rowid_alias_reg = rowid_alias_index
+        .filter(|_| has_user_provided_rowid)
+        .map(|index| column_registers_start + index);
 
     let record_register = program.alloc_register();
     let halt_label = program.allocate_label();
@@ -138,6 +134,7 @@ pub fn translate_insert(
                 &mut program,
                 value,
                 &column_mappings,
+                rowid_value_index,
                 column_registers_start,
                 true,
                 rowid_reg,
@@ -177,6 +174,7 @@ pub fn translate_insert(
             &mut program,
             &values[0],
             &column_mappings,
+            rowid_value_index,
             column_registers_start,
             false,
             rowid_reg,
@@ -185,7 +183,7 @@ pub fn translate_insert(
     }
 
     // Common record insertion logic for both single and multiple rows
-    let check_rowid_is_integer_label = rowid_alias_reg.and(Some(program.allocate_label()));
+    let check_rowid_is_integer_label = has_user_provided_rowid.then(|| program.allocate_label());
     if let Some(reg) = rowid_alias_reg {
         // for the row record, the rowid alias column (INTEGER PRIMARY KEY) is always set to NULL
         // and its value is copied to the rowid register. in the case where a single row is inserted,
@@ -202,6 +200,8 @@ pub fn translate_insert(
             // for the row record, the rowid alias column is always set to NULL
             program.emit_insn(Insn::SoftNull { reg });
         }
+    }
+    if has_user_provided_rowid {
         // the user provided rowid value might itself be NULL. If it is, we create a new rowid on the next instruction.
         program.emit_insn(Insn::NotNull {
             reg: rowid_reg,
@@ -252,6 +252,15 @@ pub fn translate_insert(
     }
 
     // Create and insert the record
+    program.emit_insn(Insn::Affinity {
+        start_reg: column_registers_start,
+        count: num_cols,
+        affinities: btree_table
+            .columns
+            .iter()
+            .map(|col| col.affinity().aff_mask())
+            .collect(),
+    });
     program.emit_insn(Insn::MakeRecord {
         start_reg: column_registers_start,
         count: num_cols,
@@ -318,11 +327,13 @@ struct ColumnMapping<'a> {
 /// 2. Column list specified (INSERT INTO t (col1, col3) VALUES ...):
 ///    - Named columns map to their corresponding value index
 ///    - Unspecified columns map to None
+///    - `rowid`, `oid` or `_rowid_`, unless the table has a column of that name, names the rowid:
+///      the rowid alias column if there is one, otherwise the value index returned alongside
 fn resolve_columns_for_insert<'a>(
     table: &'a Table,
     columns: &Option<DistinctNames>,
     values: &[Vec<Expr>],
-) -> Result<Vec<ColumnMapping<'a>>> {
+) -> Result<(Vec<ColumnMapping<'a>>, Option<usize>)> {
     if values.is_empty() {
         crate::bail_parse_error!("no values to insert");
     }
@@ -349,7 +360,7 @@ fn resolve_columns_for_insert<'a>(
         }
 
         // Map each column to either its corresponding value index or None
-        return Ok(table_columns
+        let mappings = table_columns
             .iter()
             .enumerate()
             .map(|(i, col)| ColumnMapping {
@@ -357,7 +368,8 @@ fn resolve_columns_for_insert<'a>(
                 value_index: if i < num_values { Some(i) } else { None },
                 default_value: col.default.as_ref(),
             })
-            .collect());
+            .collect();
+        return Ok((mappings, None));
     }
 
     // Case 2: Columns specified - map named columns to their values
@@ -371,13 +383,21 @@ fn resolve_columns_for_insert<'a>(
         .collect();
 
     // Map each named column to its value index
+    let mut rowid_value_index = None;
     for (value_index, column_name) in columns.as_ref().unwrap().iter().enumerate() {
         let column_name = normalize_ident(column_name.0.as_str());
-        let table_index = table_columns.iter().position(|c| {
+        let mut table_index = table_columns.iter().position(|c| {
             c.name
                 .as_ref()
                 .map_or(false, |name| name.eq_ignore_ascii_case(&column_name))
         });
+        if table_index.is_none() && ["rowid", "oid", "_rowid_"].contains(&column_name.as_str()) {
+            table_index = table_columns.iter().position(|c| c.is_rowid_alias);
+            if table_index.is_none() {
+                rowid_value_index = Some(value_index);
+                continue;
+            }
+        }
 
         if table_index.is_none() {
             crate::bail_parse_error!(
@@ -390,19 +410,31 @@ fn resolve_columns_for_insert<'a>(
         mappings[table_index.unwrap()].value_index = Some(value_index);
     }
 
-    Ok(mappings)
+    Ok((mappings, rowid_value_index))
 }
 
-/// Populates the column registers with values for a single row
+/// Populates the column registers with values for a single row, and the rowid register if the
+/// rowid was named in the column list
+#[allow(clippy::too_many_arguments)]
 fn populate_column_registers(
     program: &mut ProgramBuilder,
     value: &[Expr],
     column_mappings: &[ColumnMapping],
+    rowid_value_index: Option<usize>,
     column_registers_start: usize,
     inserting_multiple_rows: bool,
     rowid_reg: usize,
     resolver: &Resolver,
 ) -> Result<()> {
+    if let Some(value_index) = rowid_value_index {
+        translate_expr(
+            program,
+            None,
+            value.get(value_index).expect("value index out of bounds"),
+            rowid_reg,
+            resolver,
+        )?;
+    }
     for (i, mapping) in column_mappings.iter().enumerate() {
         let target_reg = column_registers_start + i;
 
@@ -470,17 +502,26 @@ fn translate_virtual_table_insert(
             OneSelect::Values(values) => values,
             _ => crate::bail_parse_error!("Virtual tables only support VALUES clause in INSERT"),
         },
-        InsertBody::DefaultValues => &vec![],
+        InsertBody::DefaultValues => &vec![vec![]],
         _ => crate::bail_parse_error!("Unsupported INSERT body for virtual tables"),
     };
 
     let table = Table::Virtual(virtual_table.clone());
-    let column_mappings = resolve_columns_for_insert(&table, columns, values)?;
+    let (column_mappings, rowid_value_index) = resolve_columns_for_insert(&table, columns, values)?;
+    if rowid_value_index.is_some() {
+        crate::bail_parse_error!(
+            "cannot set the rowid of virtual table {}",
+            virtual_table.name
+        );
+    }
+    let conflict_action = on_conflict.as_ref().map(|c| c.bit_value()).unwrap_or(0) as u16;
+
+    let cursor_id = program.alloc_cursor_id(
+        Some(virtual_table.name.clone()),
+        CursorType::VirtualTable(virtual_table.clone()),
+    );
 
     let value_registers_start = program.alloc_registers(values[0].len());
-    for (i, expr) in values[0].iter().enumerate() {
-        translate_expr(program, None, expr, value_registers_start + i, resolver)?;
-    }
     /* *
      * Inserts for virtual tables are done in a single step.
      * argv[0] = (NULL for insert)
@@ -492,6 +533,43 @@ fn translate_virtual_table_insert(
     let insert_rowid_reg = rowid_reg + 1; // argv[1] = insert_rowid
     let data_start_reg = rowid_reg + 2; // argv[2..] = column values
 
+    let halt_label = program.allocate_label();
+    let inserting_multiple_rows = values.len() > 1;
+    let mut loop_start_offset = BranchOffset::Offset(0);
+
+    // Multiple rows - a coroutine yields the values of each row to the insert loop, like for
+    // btree tables.
+    if inserting_multiple_rows {
+        let yield_reg = program.alloc_register();
+        let jump_on_definition_label = program.allocate_label();
+        program.emit_insn(Insn::InitCoroutine {
+            yield_reg,
+            jump_on_definition: jump_on_definition_label,
+            start_offset: program.offset().add(1u32),
+        });
+        for value in values {
+            for (i, expr) in value.iter().enumerate() {
+                translate_expr(program, None, expr, value_registers_start + i, resolver)?;
+            }
+            program.emit_insn(Insn::Yield {
+                yield_reg,
+                end_offset: halt_label,
+            });
+        }
+        program.emit_insn(Insn::EndCoroutine { yield_reg });
+        program.resolve_label(jump_on_definition_label, program.offset());
+
+        loop_start_offset = program.offset();
+        program.emit_insn(Insn::Yield {
+            yield_reg,
+            end_offset: halt_label,
+        });
+    } else {
+        for (i, expr) in values[0].iter().enumerate() {
+            translate_expr(program, None, expr, value_registers_start + i, resolver)?;
+        }
+    }
+
     program.emit_insn(Insn::Null {
         dest: rowid_reg,
         dest_end: None,
@@ -517,13 +595,6 @@ fn translate_virtual_table_insert(
         }
     }
 
-    let conflict_action = on_conflict.as_ref().map(|c| c.bit_value()).unwrap_or(0) as u16;
-
-    let cursor_id = program.alloc_cursor_id(
-        Some(virtual_table.name.clone()),
-        CursorType::VirtualTable(virtual_table.clone()),
-    );
-
     program.emit_insn(Insn::VUpdate {
         cursor_id,
         arg_count: column_mappings.len() + 2,
@@ -532,13 +603,18 @@ fn translate_virtual_table_insert(
         conflict_action,
     });
 
-    let halt_label = program.allocate_label();
+    if inserting_multiple_rows {
+        program.emit_insn(Insn::Goto {
+            target_pc: loop_start_offset,
+        });
+    }
+
+    program.resolve_label(halt_label, program.offset());
     program.emit_insn(Insn::Halt {
         err_code: 0,
         description: String::new(),
     });
 
-    program.resolve_label(halt_label, program.offset());
     program.resolve_label(init_label, program.offset());
 
     program.emit_insn(Insn::Goto {
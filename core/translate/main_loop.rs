@@ -1,14 +1,18 @@
+use std::sync::Arc;
+
+use limbo_ext::{ConstraintInfo, ConstraintOp};
 use limbo_sqlite3_parser::ast;
 
 use crate::{
-    schema::Table,
+    schema::{Index, PseudoTable, Table},
     translate::result_row::emit_select_result,
+    types::Record,
     vdbe::{
         builder::{CursorType, ProgramBuilder},
-        insn::{CmpInsFlags, Insn},
-        BranchOffset,
+        insn::{CmpInsFlags, IdxInsertFlags, Insn},
+        BranchOffset, CursorID,
     },
-    Result,
+    OwnedValue, Result,
 };
 
 use super::{
@@ -18,9 +22,10 @@ use super::{
     group_by::is_column_in_group_by,
     order_by::{order_by_sorter_insert, sorter_insert},
     plan::{
-        IterationDirection, Operation, Search, SelectPlan, SelectQueryType, TableReference,
+        EvalAt, IterationDirection, Operation, Search, SelectPlan, SelectQueryType, TableReference,
         WhereTerm,
     },
+    planner::determine_where_to_eval_expr,
 };
 
 // Metadata for handling LEFT JOIN operations
@@ -67,6 +72,12 @@ pub fn init_loop(
         "meta_left_joins length does not match tables length"
     );
     for (table_index, table) in tables.iter().enumerate() {
+        // Only the first table is written to, the other ones of UPDATE ... FROM are only read.
+        let mode = if table_index > 0 {
+            OperationMode::SELECT
+        } else {
+            mode
+        };
         // Initialize bookkeeping for OUTER JOIN
         if let Some(join_info) = table.join_info.as_ref() {
             if join_info.outer {
@@ -79,7 +90,7 @@ pub fn init_loop(
             }
         }
         match &table.op {
-            Operation::Scan { .. } => {
+            Operation::Scan { index, .. } => {
                 let cursor_id = program.alloc_cursor_id(
                     Some(table.identifier.clone()),
                     match &table.table {
@@ -98,6 +109,17 @@ pub fn init_loop(
                             root_page,
                         });
                         program.emit_insn(Insn::OpenReadAwait {});
+                        if let Some(index) = index {
+                            let index_cursor_id = program.alloc_cursor_id(
+                                Some(index.name.clone()),
+                                CursorType::BTreeIndex(index.clone()),
+                            );
+                            program.emit_insn(Insn::OpenReadAsync {
+                                cursor_id: index_cursor_id,
+                                root_page: index.root_page,
+                            });
+                            program.emit_insn(Insn::OpenReadAwait {});
+                        }
                     }
                     (OperationMode::DELETE, Table::BTree(btree)) => {
                         let root_page = btree.root_page;
@@ -168,6 +190,15 @@ pub fn init_loop(
                     );
 
                     match mode {
+                        OperationMode::SELECT if index.ephemeral => {
+                            emit_automatic_index(
+                                program,
+                                table,
+                                index,
+                                table_cursor_id,
+                                index_cursor_id,
+                            );
+                        }
                         OperationMode::SELECT => {
                             program.emit_insn(Insn::OpenReadAsync {
                                 cursor_id: index_cursor_id,
@@ -202,9 +233,139 @@ pub fn init_loop(
     Ok(())
 }
 
+/// Fills an automatic index with the (column, rowid) pairs of the table, leaving out NULLs
+/// since they never satisfy the equality the index is searched with. Like CREATE INDEX, the
+/// entries go through a sorter first so that they are appended to the index in order.
+fn emit_automatic_index(
+    program: &mut ProgramBuilder,
+    table: &TableReference,
+    index: &Index,
+    table_cursor_id: CursorID,
+    index_cursor_id: CursorID,
+) {
+    let (column, column_def) = table
+        .btree()
+        .unwrap()
+        .get_column(&index.columns[0].name)
+        .map(|(i, c)| (i, c.clone()))
+        .expect("automatic index column not found");
+    let sorter_cursor_id = program.alloc_cursor_id(None, CursorType::Sorter);
+    let pseudo_table = PseudoTable::new_with_columns(vec![column_def]);
+    let pseudo_cursor_id = program.alloc_cursor_id(None, CursorType::Pseudo(pseudo_table.into()));
+    program.emit_insn(Insn::OpenAutoindex {
+        cursor_id: index_cursor_id,
+    });
+    program.emit_insn(Insn::SorterOpen {
+        cursor_id: sorter_cursor_id,
+        columns: 1,
+        max_rows: None,
+        order: Record::new(vec![OwnedValue::Integer(0)]),
+    });
+    let content_reg = program.alloc_register();
+    program.emit_insn(Insn::OpenPseudo {
+        cursor_id: pseudo_cursor_id,
+        content_reg,
+        num_fields: 2,
+    });
+
+    let loop_start = program.allocate_label();
+    let loop_end = program.allocate_label();
+    let next_label = program.allocate_label();
+    program.emit_insn(Insn::RewindAsync {
+        cursor_id: table_cursor_id,
+    });
+    program.emit_insn(Insn::RewindAwait {
+        cursor_id: table_cursor_id,
+        pc_if_empty: loop_end,
+    });
+    program.resolve_label(loop_start, program.offset());
+    let start_reg = program.alloc_registers(2);
+    program.emit_insn(Insn::Column {
+        cursor_id: table_cursor_id,
+        column,
+        dest: start_reg,
+    });
+    program.emit_insn(Insn::IsNull {
+        reg: start_reg,
+        target_pc: next_label,
+    });
+    program.emit_insn(Insn::RowId {
+        cursor_id: table_cursor_id,
+        dest: start_reg + 1,
+    });
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg,
+        count: 2,
+        dest_reg: record_reg,
+    });
+    program.emit_insn(Insn::SorterInsert {
+        cursor_id: sorter_cursor_id,
+        record_reg,
+    });
+    program.resolve_label(next_label, program.offset());
+    program.emit_insn(Insn::NextAsync {
+        cursor_id: table_cursor_id,
+    });
+    program.emit_insn(Insn::NextAwait {
+        cursor_id: table_cursor_id,
+        pc_if_next: loop_start,
+    });
+    program.resolve_label(loop_end, program.offset());
+
+    let sorted_loop_start = program.allocate_label();
+    let sorted_loop_end = program.allocate_label();
+    program.emit_insn(Insn::SorterSort {
+        cursor_id: sorter_cursor_id,
+        pc_if_empty: sorted_loop_end,
+    });
+    program.resolve_label(sorted_loop_start, program.offset());
+    let sorted_record_reg = program.alloc_register();
+    program.emit_insn(Insn::SorterData {
+        pseudo_cursor: pseudo_cursor_id,
+        cursor_id: sorter_cursor_id,
+        dest_reg: sorted_record_reg,
+    });
+    program.emit_insn(Insn::SeekEnd {
+        cursor_id: index_cursor_id,
+    });
+    program.emit_insn(Insn::IdxInsertAsync {
+        cursor_id: index_cursor_id,
+        record_reg: sorted_record_reg,
+        unpacked_start: None,
+        unpacked_count: None,
+        flags: IdxInsertFlags::new().use_seek(false),
+    });
+    program.emit_insn(Insn::IdxInsertAwait {
+        cursor_id: index_cursor_id,
+    });
+    program.emit_insn(Insn::SorterNext {
+        cursor_id: sorter_cursor_id,
+        pc_if_next: sorted_loop_start,
+    });
+    program.resolve_label(sorted_loop_end, program.offset());
+    program.close_cursors(&[sorter_cursor_id, pseudo_cursor_id]);
+}
+
 /// Set up the main query execution loop
 /// For example in the case of a nested table scan, this means emitting the RewindAsync instruction
 /// for all tables involved, outermost first.
+/// Emits a jump out of a seek loop when the seek key is NULL, since NULL compares unequal to
+/// every key. Only IS, which is also true for NULL IS NULL, seeks to NULL keys.
+fn emit_seek_key_null_check(
+    program: &mut ProgramBuilder,
+    cmp_op: ast::Operator,
+    cmp_reg: usize,
+    loop_end: BranchOffset,
+) {
+    if cmp_op != ast::Operator::Is {
+        program.emit_insn(Insn::IsNull {
+            reg: cmp_reg,
+            target_pc: loop_end,
+        });
+    }
+}
+
 pub fn open_loop(
     program: &mut ProgramBuilder,
     t_ctx: &mut TranslateCtx,
@@ -235,6 +396,37 @@ pub fn open_loop(
         }
 
         match &table.op {
+            Operation::Subquery {
+                materialized: true, ..
+            } => {
+                // A materialized subquery is read back from its transient table like a regular table.
+                let cursor_id = program.resolve_cursor_id(&table.identifier);
+                program.emit_insn(Insn::RewindAsync { cursor_id });
+                program.emit_insn(Insn::RewindAwait {
+                    cursor_id,
+                    pc_if_empty: loop_end,
+                });
+                program.resolve_label(loop_start, program.offset());
+                for cond in predicates
+                    .iter()
+                    .filter(|cond| cond.should_eval_at_loop(table_index))
+                {
+                    let jump_target_when_true = program.allocate_label();
+                    let condition_metadata = ConditionMetadata {
+                        jump_if_condition_is_true: false,
+                        jump_target_when_true,
+                        jump_target_when_false: next,
+                    };
+                    translate_condition_expr(
+                        program,
+                        tables,
+                        &cond.expr,
+                        condition_metadata,
+                        &t_ctx.resolver,
+                    )?;
+                    program.resolve_label(jump_target_when_true, program.offset());
+                }
+            }
             Operation::Subquery { plan, .. } => {
                 let (yield_reg, coroutine_implementation_start) = match &plan.query_type {
                     SelectQueryType::Subquery {
@@ -282,8 +474,13 @@ pub fn open_loop(
                     program.resolve_label(jump_target_when_true, program.offset());
                 }
             }
-            Operation::Scan { iter_dir } => {
-                let cursor_id = program.resolve_cursor_id(&table.identifier);
+            Operation::Scan { iter_dir, index } => {
+                let table_cursor_id = program.resolve_cursor_id(&table.identifier);
+                // An index scan walks the index and looks up each row of the table by its rowid.
+                let index_cursor_id = index
+                    .as_ref()
+                    .map(|index| program.resolve_cursor_id(&index.name));
+                let cursor_id = index_cursor_id.unwrap_or(table_cursor_id);
 
                 if !matches!(&table.table, Table::Virtual(_)) {
                     if iter_dir
@@ -312,30 +509,62 @@ pub fn open_loop(
                             }
                         },
                     ),
-                    Table::Virtual(ref table) => {
-                        let start_reg = program
-                            .alloc_registers(table.args.as_ref().map(|a| a.len()).unwrap_or(0));
-                        let mut cur_reg = start_reg;
-                        let args = match table.args.as_ref() {
-                            Some(args) => args,
-                            None => &vec![],
+                    Table::Virtual(ref vtab) => {
+                        let args = match vtab.args.as_ref() {
+                            Some(args) => args.as_slice(),
+                            None => &[],
                         };
+                        let constraints =
+                            virtual_table_constraints(table, table_index, predicates)?;
+                        let index_info = vtab.best_index(
+                            &constraints
+                                .iter()
+                                .map(|(constraint, _)| *constraint)
+                                .collect::<Vec<_>>(),
+                        );
+                        // The values of the constraints the table uses follow its own arguments.
+                        let constraint_arg_count = index_info
+                            .constraint_usages
+                            .iter()
+                            .filter_map(|usage| usage.argv_index)
+                            .max()
+                            .unwrap_or(0)
+                            as usize;
+                        let arg_count = args.len() + constraint_arg_count;
+                        let start_reg = program.alloc_registers(arg_count);
+                        let mut cur_reg = start_reg;
                         for arg in args {
                             let reg = cur_reg;
                             cur_reg += 1;
                             let _ =
                                 translate_expr(program, Some(tables), arg, reg, &t_ctx.resolver)?;
                         }
+                        for (usage, (_, value)) in
+                            index_info.constraint_usages.iter().zip(constraints.iter())
+                        {
+                            if let Some(argv_index) = usage.argv_index {
+                                let reg = start_reg + args.len() + argv_index as usize - 1;
+                                translate_expr(program, Some(tables), value, reg, &t_ctx.resolver)?;
+                            }
+                        }
                         program.emit_insn(Insn::VFilter {
                             cursor_id,
                             pc_if_empty: loop_end,
-                            arg_count: table.args.as_ref().map_or(0, |args| args.len()),
+                            arg_count,
                             args_reg: start_reg,
+                            idx_str: index_info.idx_str,
+                            idx_num: index_info.idx_num,
                         });
                     }
                     other => panic!("Unsupported table reference type: {:?}", other),
                 }
                 program.resolve_label(loop_start, program.offset());
+                if let Some(index_cursor_id) = index_cursor_id {
+                    program.emit_insn(Insn::DeferredSeek {
+                        index_cursor_id,
+                        table_cursor_id,
+                    });
+                }
 
                 for cond in predicates
                     .iter()
@@ -361,63 +590,36 @@ pub fn open_loop(
                 let table_cursor_id = program.resolve_cursor_id(&table.identifier);
                 // Open the loop for the index search.
                 // Rowid equality point lookups are handled with a SeekRowid instruction which does not loop, since it is a single row lookup.
-                if !matches!(search, Search::RowidEq { .. }) {
+                if matches!(
+                    search,
+                    Search::RowidSearch { .. } | Search::IndexSearch { .. }
+                ) {
                     let index_cursor_id = if let Search::IndexSearch { index, .. } = search {
                         Some(program.resolve_cursor_id(&index.name))
                     } else {
                         None
                     };
                     let cmp_reg = program.alloc_register();
-                    let (cmp_expr, cmp_op) = match search {
+                    let (cmp_expr, cmp_op, iter_dir) = match search {
                         Search::IndexSearch {
-                            cmp_expr, cmp_op, ..
-                        } => (cmp_expr, cmp_op),
-                        Search::RowidSearch { cmp_expr, cmp_op } => (cmp_expr, cmp_op),
-                        Search::RowidEq { .. } => unreachable!(),
+                            cmp_expr,
+                            cmp_op,
+                            iter_dir,
+                            ..
+                        } => (cmp_expr, cmp_op, iter_dir),
+                        Search::RowidSearch {
+                            cmp_expr,
+                            cmp_op,
+                            iter_dir,
+                        } => (cmp_expr, cmp_op, iter_dir),
+                        _ => unreachable!(),
                     };
 
-                    // TODO this only handles ascending indexes
-                    match cmp_op {
-                        ast::Operator::Equals
-                        | ast::Operator::Greater
-                        | ast::Operator::GreaterEquals => {
-                            translate_expr(
-                                program,
-                                Some(tables),
-                                &cmp_expr.expr,
-                                cmp_reg,
-                                &t_ctx.resolver,
-                            )?;
-                        }
-                        ast::Operator::Less | ast::Operator::LessEquals => {
-                            program.emit_insn(Insn::Null {
-                                dest: cmp_reg,
-                                dest_end: None,
-                            });
-                        }
-                        _ => unreachable!(),
-                    }
-                    // If we try to seek to a key that is not present in the table/index, we exit the loop entirely.
-                    program.emit_insn(match cmp_op {
-                        ast::Operator::Equals | ast::Operator::GreaterEquals => Insn::SeekGE {
-                            is_index: index_cursor_id.is_some(),
-                            cursor_id: index_cursor_id.unwrap_or(table_cursor_id),
-                            start_reg: cmp_reg,
-                            num_regs: 1,
-                            target_pc: loop_end,
-                        },
-                        ast::Operator::Greater
-                        | ast::Operator::Less
-                        | ast::Operator::LessEquals => Insn::SeekGT {
-                            is_index: index_cursor_id.is_some(),
-                            cursor_id: index_cursor_id.unwrap_or(table_cursor_id),
-                            start_reg: cmp_reg,
-                            num_regs: 1,
-                            target_pc: loop_end,
-                        },
-                        _ => unreachable!(),
-                    });
-                    if *cmp_op == ast::Operator::Less || *cmp_op == ast::Operator::LessEquals {
+                    if *iter_dir == IterationDirection::Backwards {
+                        // For backwards searches the seek value is always known up front:
+                        // index_key > 10 and index_key >= 10 start from the end of the index and scan backwards until the key drops below 10,
+                        // index_key < 10 and index_key <= 10 seek to the last key below (or at) 10 and scan backwards from there,
+                        // index_key = 10 seeks to the last key equal to 10 and scans backwards until the key drops below 10.
                         translate_expr(
                             program,
                             Some(tables),
@@ -425,65 +627,221 @@ pub fn open_loop(
                             cmp_reg,
                             &t_ctx.resolver,
                         )?;
-                    }
-
-                    program.resolve_label(loop_start, program.offset());
-                    // TODO: We are currently only handling ascending indexes.
-                    // For conditions like index_key > 10, we have already sought to the first key greater than 10, and can just scan forward.
-                    // For conditions like index_key < 10, we are at the beginning of the index, and will scan forward and emit IdxGE(10) with a conditional jump to the end.
-                    // For conditions like index_key = 10, we have already sought to the first key greater than or equal to 10, and can just scan forward and emit IdxGT(10) with a conditional jump to the end.
-                    // For conditions like index_key >= 10, we have already sought to the first key greater than or equal to 10, and can just scan forward.
-                    // For conditions like index_key <= 10, we are at the beginning of the index, and will scan forward and emit IdxGT(10) with a conditional jump to the end.
-                    // For conditions like index_key != 10, TODO. probably the optimal way is not to use an index at all.
-                    //
-                    // For primary key searches we emit RowId and then compare it to the seek value.
-
-                    match cmp_op {
-                        ast::Operator::Equals | ast::Operator::LessEquals => {
-                            if let Some(index_cursor_id) = index_cursor_id {
-                                program.emit_insn(Insn::IdxGT {
-                                    cursor_id: index_cursor_id,
+                        emit_seek_key_null_check(program, *cmp_op, cmp_reg, loop_end);
+                        let cursor_id = index_cursor_id.unwrap_or(table_cursor_id);
+                        match cmp_op {
+                            ast::Operator::Greater | ast::Operator::GreaterEquals => {
+                                program.emit_insn(Insn::LastAsync { cursor_id });
+                                program.emit_insn(Insn::LastAwait {
+                                    cursor_id,
+                                    pc_if_empty: loop_end,
+                                });
+                            }
+                            ast::Operator::Equals
+                            | ast::Operator::Is
+                            | ast::Operator::LessEquals => {
+                                program.emit_insn(Insn::SeekLE {
+                                    is_index: index_cursor_id.is_some(),
+                                    cursor_id,
                                     start_reg: cmp_reg,
                                     num_regs: 1,
                                     target_pc: loop_end,
                                 });
-                            } else {
-                                let rowid_reg = program.alloc_register();
-                                program.emit_insn(Insn::RowId {
-                                    cursor_id: table_cursor_id,
-                                    dest: rowid_reg,
-                                });
-                                program.emit_insn(Insn::Gt {
-                                    lhs: rowid_reg,
-                                    rhs: cmp_reg,
+                            }
+                            ast::Operator::Less => {
+                                program.emit_insn(Insn::SeekLT {
+                                    is_index: index_cursor_id.is_some(),
+                                    cursor_id,
+                                    start_reg: cmp_reg,
+                                    num_regs: 1,
                                     target_pc: loop_end,
-                                    flags: CmpInsFlags::default(),
                                 });
                             }
+                            _ => unreachable!(),
                         }
-                        ast::Operator::Less => {
-                            if let Some(index_cursor_id) = index_cursor_id {
-                                program.emit_insn(Insn::IdxGE {
+
+                        program.resolve_label(loop_start, program.offset());
+                        match (cmp_op, index_cursor_id) {
+                            (ast::Operator::Greater, Some(index_cursor_id)) => {
+                                program.emit_insn(Insn::IdxLE {
                                     cursor_id: index_cursor_id,
                                     start_reg: cmp_reg,
                                     num_regs: 1,
                                     target_pc: loop_end,
                                 });
-                            } else {
+                            }
+                            (
+                                ast::Operator::GreaterEquals
+                                | ast::Operator::Equals
+                                | ast::Operator::Is,
+                                Some(index_cursor_id),
+                            ) => {
+                                program.emit_insn(Insn::IdxLT {
+                                    cursor_id: index_cursor_id,
+                                    start_reg: cmp_reg,
+                                    num_regs: 1,
+                                    target_pc: loop_end,
+                                });
+                            }
+                            (
+                                ast::Operator::Less | ast::Operator::LessEquals,
+                                Some(index_cursor_id),
+                            ) => {
+                                // NULLs sort first in the index and never satisfy the comparison, so stop when we reach them.
+                                let null_reg = program.alloc_register();
+                                program.emit_insn(Insn::Null {
+                                    dest: null_reg,
+                                    dest_end: None,
+                                });
+                                program.emit_insn(Insn::IdxLE {
+                                    cursor_id: index_cursor_id,
+                                    start_reg: null_reg,
+                                    num_regs: 1,
+                                    target_pc: loop_end,
+                                });
+                            }
+                            (ast::Operator::Greater | ast::Operator::GreaterEquals, None) => {
                                 let rowid_reg = program.alloc_register();
                                 program.emit_insn(Insn::RowId {
                                     cursor_id: table_cursor_id,
                                     dest: rowid_reg,
                                 });
-                                program.emit_insn(Insn::Ge {
-                                    lhs: rowid_reg,
-                                    rhs: cmp_reg,
-                                    target_pc: loop_end,
-                                    flags: CmpInsFlags::default(),
+                                let flags = CmpInsFlags::default();
+                                program.emit_insn(if *cmp_op == ast::Operator::Greater {
+                                    Insn::Le {
+                                        lhs: rowid_reg,
+                                        rhs: cmp_reg,
+                                        target_pc: loop_end,
+                                        flags,
+                                    }
+                                } else {
+                                    Insn::Lt {
+                                        lhs: rowid_reg,
+                                        rhs: cmp_reg,
+                                        target_pc: loop_end,
+                                        flags,
+                                    }
+                                });
+                            }
+                            _ => {}
+                        }
+                    } else {
+                        // TODO this only handles ascending indexes
+                        match cmp_op {
+                            ast::Operator::Equals
+                            | ast::Operator::Is
+                            | ast::Operator::Greater
+                            | ast::Operator::GreaterEquals => {
+                                translate_expr(
+                                    program,
+                                    Some(tables),
+                                    &cmp_expr.expr,
+                                    cmp_reg,
+                                    &t_ctx.resolver,
+                                )?;
+                                emit_seek_key_null_check(program, *cmp_op, cmp_reg, loop_end);
+                            }
+                            ast::Operator::Less | ast::Operator::LessEquals => {
+                                program.emit_insn(Insn::Null {
+                                    dest: cmp_reg,
+                                    dest_end: None,
                                 });
                             }
+                            _ => unreachable!(),
+                        }
+                        // If we try to seek to a key that is not present in the table/index, we exit the loop entirely.
+                        program.emit_insn(match cmp_op {
+                            ast::Operator::Equals
+                            | ast::Operator::Is
+                            | ast::Operator::GreaterEquals => Insn::SeekGE {
+                                is_index: index_cursor_id.is_some(),
+                                cursor_id: index_cursor_id.unwrap_or(table_cursor_id),
+                                start_reg: cmp_reg,
+                                num_regs: 1,
+                                target_pc: loop_end,
+                            },
+                            ast::Operator::Greater
+                            | ast::Operator::Less
+                            | ast::Operator::LessEquals => Insn::SeekGT {
+                                is_index: index_cursor_id.is_some(),
+                                cursor_id: index_cursor_id.unwrap_or(table_cursor_id),
+                                start_reg: cmp_reg,
+                                num_regs: 1,
+                                target_pc: loop_end,
+                            },
+                            _ => unreachable!(),
+                        });
+                        if *cmp_op == ast::Operator::Less || *cmp_op == ast::Operator::LessEquals {
+                            translate_expr(
+                                program,
+                                Some(tables),
+                                &cmp_expr.expr,
+                                cmp_reg,
+                                &t_ctx.resolver,
+                            )?;
+                            emit_seek_key_null_check(program, *cmp_op, cmp_reg, loop_end);
+                        }
+
+                        program.resolve_label(loop_start, program.offset());
+                        // TODO: We are currently only handling ascending indexes.
+                        // For conditions like index_key > 10, we have already sought to the first key greater than 10, and can just scan forward.
+                        // For conditions like index_key < 10, we are at the beginning of the index, and will scan forward and emit IdxGE(10) with a conditional jump to the end.
+                        // For conditions like index_key = 10, we have already sought to the first key greater than or equal to 10, and can just scan forward and emit IdxGT(10) with a conditional jump to the end.
+                        // For conditions like index_key >= 10, we have already sought to the first key greater than or equal to 10, and can just scan forward.
+                        // For conditions like index_key <= 10, we are at the beginning of the index, and will scan forward and emit IdxGT(10) with a conditional jump to the end.
+                        // For conditions like index_key != 10, TODO. probably the optimal way is not to use an index at all.
+                        //
+                        // For primary key searches we emit RowId and then compare it to the seek value.
+
+                        match cmp_op {
+                            ast::Operator::Equals
+                            | ast::Operator::Is
+                            | ast::Operator::LessEquals => {
+                                if let Some(index_cursor_id) = index_cursor_id {
+                                    program.emit_insn(Insn::IdxGT {
+                                        cursor_id: index_cursor_id,
+                                        start_reg: cmp_reg,
+                                        num_regs: 1,
+                                        target_pc: loop_end,
+                                    });
+                                } else {
+                                    let rowid_reg = program.alloc_register();
+                                    program.emit_insn(Insn::RowId {
+                                        cursor_id: table_cursor_id,
+                                        dest: rowid_reg,
+                                    });
+                                    program.emit_insn(Insn::Gt {
+                                        lhs: rowid_reg,
+                                        rhs: cmp_reg,
+                                        target_pc: loop_end,
+                                        flags: CmpInsFlags::default(),
+                                    });
+                                }
+                            }
+                            ast::Operator::Less => {
+                                if let Some(index_cursor_id) = index_cursor_id {
+                                    program.emit_insn(Insn::IdxGE {
+                                        cursor_id: index_cursor_id,
+                                        start_reg: cmp_reg,
+                                        num_regs: 1,
+                                        target_pc: loop_end,
+                                    });
+                                } else {
+                                    let rowid_reg = program.alloc_register();
+                                    program.emit_insn(Insn::RowId {
+                                        cursor_id: table_cursor_id,
+                                        dest: rowid_reg,
+                                    });
+                                    program.emit_insn(Insn::Ge {
+                                        lhs: rowid_reg,
+                                        rhs: cmp_reg,
+                                        target_pc: loop_end,
+                                        flags: CmpInsFlags::default(),
+                                    });
+                                }
+                            }
+                            _ => {}
                         }
-                        _ => {}
                     }
 
                     if let Some(index_cursor_id) = index_cursor_id {
@@ -494,6 +852,38 @@ pub fn open_loop(
                     }
                 }
 
+                // The rows a vector index finds are looped over nearest first, seeking the table
+                // cursor to each of their rowids.
+                #[cfg(feature = "vector")]
+                if let Search::VectorMatch { index, query, k } = search {
+                    let query_reg = program.alloc_register();
+                    translate_expr(
+                        program,
+                        Some(tables),
+                        &query.expr,
+                        query_reg,
+                        &t_ctx.resolver,
+                    )?;
+                    program.emit_insn(Insn::VectorSearch {
+                        cursor_id: table_cursor_id,
+                        index: index.name.clone(),
+                        query_reg,
+                        k: *k,
+                        pc_if_empty: loop_end,
+                    });
+                    program.resolve_label(loop_start, program.offset());
+                    let rowid_reg = program.alloc_register();
+                    program.emit_insn(Insn::VectorRowid {
+                        cursor_id: table_cursor_id,
+                        dest: rowid_reg,
+                    });
+                    program.emit_insn(Insn::SeekRowid {
+                        cursor_id: table_cursor_id,
+                        src_reg: rowid_reg,
+                        target_pc: next,
+                    });
+                }
+
                 if let Search::RowidEq { cmp_expr } = search {
                     let src_reg = program.alloc_register();
                     translate_expr(
@@ -550,6 +940,34 @@ pub fn open_loop(
     Ok(())
 }
 
+/// The index a loop over a b-tree table walks, if any. The table cursor of such a loop is
+/// positioned by a DeferredSeek on the index cursor.
+fn loop_index(table: &TableReference) -> Option<&Arc<Index>> {
+    match &table.op {
+        Operation::Scan {
+            index: Some(index), ..
+        } if matches!(table.table, Table::BTree(_)) => Some(index),
+        Operation::Search(Search::IndexSearch { index, .. }) => Some(index),
+        _ => None,
+    }
+}
+
+/// For each column of `index`, the position of that column in the table. The rowid alias is
+/// left out since it is read with RowId rather than Column.
+fn index_column_positions(table: &TableReference, index: &Index) -> Vec<Option<usize>> {
+    let btree = table.btree().unwrap();
+    index
+        .columns
+        .iter()
+        .map(|column| {
+            btree
+                .get_column(&column.name)
+                .filter(|(_, column)| !column.is_rowid_alias)
+                .map(|(pos, _)| pos)
+        })
+        .collect()
+}
+
 /// SQLite (and so Limbo) processes joins as a nested loop.
 /// The loop may emit rows to various destinations depending on the query:
 /// - a GROUP BY sorter (grouping is done by sorting based on the GROUP BY keys and aggregating while the GROUP BY keys match)
@@ -755,6 +1173,13 @@ fn emit_loop_source(
                 program.emit_int(1, flag);
             }
 
+            // The first row of the ordered scan already holds the min() or max().
+            if plan.min_max_early_out {
+                program.emit_insn(Insn::Goto {
+                    target_pc: t_ctx.label_main_loop_end.unwrap(),
+                });
+            }
+
             Ok(())
         }
         LoopEmitTarget::QueryResult => {
@@ -803,7 +1228,28 @@ pub fn close_loop(
             .get(table_index)
             .expect("source has no loop labels");
 
+        if let Some(index) = loop_index(table) {
+            let index_columns = index_column_positions(table, index);
+            program.read_columns_from_index(
+                loop_labels.loop_start,
+                program.resolve_cursor_id(&table.identifier),
+                program.resolve_cursor_id(&index.name),
+                &index_columns,
+            );
+        }
+
         match &table.op {
+            Operation::Subquery {
+                materialized: true, ..
+            } => {
+                program.resolve_label(loop_labels.next, program.offset());
+                let cursor_id = program.resolve_cursor_id(&table.identifier);
+                program.emit_insn(Insn::NextAsync { cursor_id });
+                program.emit_insn(Insn::NextAwait {
+                    cursor_id,
+                    pc_if_next: loop_labels.loop_start,
+                });
+            }
             Operation::Subquery { .. } => {
                 program.resolve_label(loop_labels.next, program.offset());
                 // A subquery has no cursor to call NextAsync on, so it just emits a Goto
@@ -813,9 +1259,12 @@ pub fn close_loop(
                     target_pc: loop_labels.loop_start,
                 });
             }
-            Operation::Scan { iter_dir, .. } => {
+            Operation::Scan { iter_dir, index } => {
                 program.resolve_label(loop_labels.next, program.offset());
-                let cursor_id = program.resolve_cursor_id(&table.identifier);
+                let cursor_id = match index {
+                    Some(index) => program.resolve_cursor_id(&index.name),
+                    None => program.resolve_cursor_id(&table.identifier),
+                };
                 match &table.table {
                     Table::BTree(_) => {
                         if iter_dir
@@ -852,19 +1301,42 @@ pub fn close_loop(
             }
             Operation::Search(search) => {
                 program.resolve_label(loop_labels.next, program.offset());
+                #[cfg(feature = "vector")]
+                if let Search::VectorMatch { .. } = search {
+                    program.emit_insn(Insn::VectorNext {
+                        cursor_id: program.resolve_cursor_id(&table.identifier),
+                        pc_if_next: loop_labels.loop_start,
+                    });
+                }
                 // Rowid equality point lookups are handled with a SeekRowid instruction which does not loop, so there is no need to emit a NextAsync instruction.
-                if !matches!(search, Search::RowidEq { .. }) {
+                if matches!(
+                    search,
+                    Search::RowidSearch { .. } | Search::IndexSearch { .. }
+                ) {
                     let cursor_id = match search {
                         Search::IndexSearch { index, .. } => program.resolve_cursor_id(&index.name),
                         Search::RowidSearch { .. } => program.resolve_cursor_id(&table.identifier),
-                        Search::RowidEq { .. } => unreachable!(),
+                        _ => unreachable!(),
                     };
 
-                    program.emit_insn(Insn::NextAsync { cursor_id });
-                    program.emit_insn(Insn::NextAwait {
-                        cursor_id,
-                        pc_if_next: loop_labels.loop_start,
-                    });
+                    let iter_dir = match search {
+                        Search::IndexSearch { iter_dir, .. }
+                        | Search::RowidSearch { iter_dir, .. } => iter_dir,
+                        _ => unreachable!(),
+                    };
+                    if *iter_dir == IterationDirection::Backwards {
+                        program.emit_insn(Insn::PrevAsync { cursor_id });
+                        program.emit_insn(Insn::PrevAwait {
+                            cursor_id,
+                            pc_if_next: loop_labels.loop_start,
+                        });
+                    } else {
+                        program.emit_insn(Insn::NextAsync { cursor_id });
+                        program.emit_insn(Insn::NextAwait {
+                            cursor_id,
+                            pc_if_next: loop_labels.loop_start,
+                        });
+                    }
                 }
             }
         }
@@ -881,7 +1353,13 @@ pub fn close_loop(
                 // If the left join match flag has been set to 1, we jump to the next row on the outer table,
                 // i.e. continue to the next row of t1 in our example.
                 program.resolve_label(lj_meta.label_match_flag_check_value, program.offset());
-                let jump_offset = program.offset().add(3u32);
+                let index_cursor_id =
+                    loop_index(table).map(|index| program.resolve_cursor_id(&index.name));
+                let jump_offset = program.offset().add(if index_cursor_id.is_some() {
+                    4u32
+                } else {
+                    3u32
+                });
                 program.emit_insn(Insn::IfPos {
                     reg: lj_meta.reg_match_flag,
                     target_pc: jump_offset,
@@ -894,11 +1372,20 @@ pub fn close_loop(
                 let right_cursor_id = match &table.op {
                     Operation::Scan { .. } => program.resolve_cursor_id(&table.identifier),
                     Operation::Search { .. } => program.resolve_cursor_id(&table.identifier),
+                    Operation::Subquery {
+                        materialized: true, ..
+                    } => program.resolve_cursor_id(&table.identifier),
                     _ => unreachable!(),
                 };
                 program.emit_insn(Insn::NullRow {
                     cursor_id: right_cursor_id,
                 });
+                // Columns read from the index cursor must be NULL as well.
+                if let Some(index_cursor_id) = index_cursor_id {
+                    program.emit_insn(Insn::NullRow {
+                        cursor_id: index_cursor_id,
+                    });
+                }
                 // Then we jump to setting the left join match flag to 1 again,
                 // but this time the right table cursor will set everything to null.
                 // This leads to emitting a row with cols from the left + nulls from the right,
@@ -915,3 +1402,65 @@ pub fn close_loop(
     }
     Ok(())
 }
+
+/// Collects the comparisons of a column of the virtual table at `table_index` with a value that
+/// is known when its loop is opened, which the table may use to skip rows that can't match.
+/// The comparisons are still evaluated on every row the table returns.
+fn virtual_table_constraints<'a>(
+    table: &TableReference,
+    table_index: usize,
+    predicates: &'a [WhereTerm],
+) -> Result<Vec<(ConstraintInfo, &'a ast::Expr)>> {
+    // WHERE terms on the right side of an outer join must also see its null row.
+    let outer = table
+        .join_info
+        .as_ref()
+        .is_some_and(|join_info| join_info.outer);
+    let mut constraints = vec![];
+    for term in predicates
+        .iter()
+        .filter(|term| term.should_eval_at_loop(table_index))
+    {
+        if outer && !term.from_outer_join {
+            continue;
+        }
+        let ast::Expr::Binary(lhs, op, rhs) = &term.expr else {
+            continue;
+        };
+        let (column, op, value) = match (lhs.as_ref(), rhs.as_ref()) {
+            (ast::Expr::Column { table, column, .. }, value) if *table == table_index => {
+                (*column, *op, value)
+            }
+            (value, ast::Expr::Column { table, column, .. }) if *table == table_index => {
+                let op = match op {
+                    ast::Operator::Less => ast::Operator::Greater,
+                    ast::Operator::LessEquals => ast::Operator::GreaterEquals,
+                    ast::Operator::Greater => ast::Operator::Less,
+                    ast::Operator::GreaterEquals => ast::Operator::LessEquals,
+                    op => *op,
+                };
+                (*column, op, value)
+            }
+            _ => continue,
+        };
+        let op = match op {
+            ast::Operator::Equals => ConstraintOp::Eq,
+            ast::Operator::Less => ConstraintOp::Lt,
+            ast::Operator::LessEquals => ConstraintOp::Le,
+            ast::Operator::Greater => ConstraintOp::Gt,
+            ast::Operator::GreaterEquals => ConstraintOp::Ge,
+            _ => continue,
+        };
+        if determine_where_to_eval_expr(value)? >= EvalAt::Loop(table_index) {
+            continue;
+        }
+        constraints.push((
+            ConstraintInfo {
+                column_index: column as u32,
+                op,
+            },
+            value,
+        ));
+    }
+    Ok(constraints)
+}
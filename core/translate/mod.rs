@@ -8,6 +8,8 @@
 //! will read rows from the database and filter them according to a WHERE clause.
 
 pub(crate) mod aggregation;
+pub(crate) mod analyze;
+pub(crate) mod collate;
 pub(crate) mod delete;
 pub(crate) mod emitter;
 pub(crate) mod expr;
@@ -38,7 +40,10 @@ use crate::{bail_parse_error, Connection, Result, SymbolTable};
 use index::translate_create_index;
 use insert::translate_insert;
 use limbo_sqlite3_parser::ast::{self, Delete, Insert};
-use schema::{translate_create_table, translate_create_virtual_table, translate_drop_table};
+use schema::{
+    translate_create_table, translate_create_virtual_table, translate_drop_table,
+    translate_materialized_view,
+};
 use select::translate_select;
 use std::rc::{Rc, Weak};
 use std::sync::Arc;
@@ -59,10 +64,16 @@ pub fn translate(
 
     let program = match stmt {
         ast::Stmt::AlterTable(_) => bail_parse_error!("ALTER TABLE not supported yet"),
-        ast::Stmt::Analyze(_) => bail_parse_error!("ANALYZE not supported yet"),
+        ast::Stmt::Analyze(target) => {
+            analyze::translate_analyze(query_mode, target, schema, &connection)?
+        }
         ast::Stmt::Attach { .. } => bail_parse_error!("ATTACH not supported yet"),
         ast::Stmt::Begin(tx_type, tx_name) => translate_tx_begin(tx_type, tx_name)?,
         ast::Stmt::Commit(tx_name) => translate_tx_commit(tx_name)?,
+        #[cfg(feature = "vector")]
+        stmt if crate::vector::index::is_vector_index_stmt(schema, &stmt) => {
+            schema::translate_vector_index(query_mode, stmt)?
+        }
         ast::Stmt::CreateIndex {
             unique,
             if_not_exists,
@@ -96,6 +107,9 @@ pub fn translate(
         )?,
         ast::Stmt::CreateTrigger { .. } => bail_parse_error!("CREATE TRIGGER not supported yet"),
         ast::Stmt::CreateView { .. } => bail_parse_error!("CREATE VIEW not supported yet"),
+        stmt @ (ast::Stmt::CreateMaterializedView { .. }
+        | ast::Stmt::RefreshMaterializedView { .. }
+        | ast::Stmt::DropMaterializedView { .. }) => translate_materialized_view(query_mode, stmt)?,
         ast::Stmt::CreateVirtualTable(vtab) => {
             translate_create_virtual_table(*vtab, schema, query_mode)?
         }
@@ -103,11 +117,20 @@ pub fn translate(
             let Delete {
                 tbl_name,
                 where_clause,
+                order_by,
                 limit,
                 ..
             } = *delete;
             change_cnt_on = true;
-            translate_delete(query_mode, schema, &tbl_name, where_clause, limit, syms)?
+            translate_delete(
+                query_mode,
+                schema,
+                &tbl_name,
+                where_clause,
+                order_by,
+                limit,
+                syms,
+            )?
         }
         ast::Stmt::Detach(_) => bail_parse_error!("DETACH not supported yet"),
         ast::Stmt::DropIndex { .. } => bail_parse_error!("DROP INDEX not supported yet"),
@@ -124,12 +147,31 @@ pub fn translate(
             body.map(|b| *b),
             database_header.clone(),
             pager,
+            &connection,
         )?,
         ast::Stmt::Reindex { .. } => bail_parse_error!("REINDEX not supported yet"),
         ast::Stmt::Release(_) => bail_parse_error!("RELEASE not supported yet"),
         ast::Stmt::Rollback { .. } => bail_parse_error!("ROLLBACK not supported yet"),
         ast::Stmt::Savepoint(_) => bail_parse_error!("SAVEPOINT not supported yet"),
-        ast::Stmt::Select(select) => translate_select(query_mode, schema, *select, syms)?,
+        ast::Stmt::Select(select) => {
+            let automatic_index = connection
+                .upgrade()
+                .is_some_and(|conn| conn.automatic_index());
+            translate_select(query_mode, schema, *select, None, syms, automatic_index)?
+        }
+        ast::Stmt::SelectAsOf { select, as_of } => {
+            let automatic_index = connection
+                .upgrade()
+                .is_some_and(|conn| conn.automatic_index());
+            translate_select(
+                query_mode,
+                schema,
+                *select,
+                Some(*as_of),
+                syms,
+                automatic_index,
+            )?
+        }
         ast::Stmt::Update(mut update) => translate_update(query_mode, schema, &mut update, syms)?,
         ast::Stmt::Vacuum(_, _) => bail_parse_error!("VACUUM not supported yet"),
         ast::Stmt::Insert(insert) => {
@@ -1,16 +1,23 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
-use limbo_sqlite3_parser::ast;
+use limbo_sqlite3_parser::ast::{self, SortOrder};
 
 use crate::{
+    function::AggFunc,
     schema::{Index, Schema},
-    util::exprs_are_equivalent,
+    util::{expr_is_deterministic, exprs_are_equivalent},
     Result,
 };
 
-use super::plan::{
-    DeletePlan, Direction, IterationDirection, Operation, Plan, Search, SelectPlan, TableReference,
-    UpdatePlan, WhereTerm,
+use super::{
+    plan::{
+        DeletePlan, Direction, EvalAt, IterationDirection, Operation, Plan, Search, SelectPlan,
+        TableReference, UpdatePlan, WhereTerm,
+    },
+    planner::determine_where_to_eval_expr,
 };
 
 pub fn optimize_plan(plan: &mut Plan, schema: &Schema) -> Result<()> {
@@ -27,6 +34,10 @@ pub fn optimize_plan(plan: &mut Plan, schema: &Schema) -> Result<()> {
  * but having them separate makes them easier to understand
  */
 fn optimize_select_plan(plan: &mut SelectPlan, schema: &Schema) -> Result<()> {
+    materialize_shared_ctes(plan)?;
+    flatten_subqueries(plan)?;
+    push_predicates_into_subqueries(plan)?;
+    materialize_inner_subqueries(plan)?;
     optimize_subqueries(plan, schema)?;
     rewrite_exprs_select(plan)?;
     if let ConstantConditionEliminationResult::ImpossibleCondition =
@@ -36,6 +47,9 @@ fn optimize_select_plan(plan: &mut SelectPlan, schema: &Schema) -> Result<()> {
         return Ok(());
     }
 
+    #[cfg(feature = "vector")]
+    use_vector_indexes(plan, schema)?;
+
     use_indexes(
         &mut plan.table_references,
         &schema.indexes,
@@ -46,6 +60,10 @@ fn optimize_select_plan(plan: &mut SelectPlan, schema: &Schema) -> Result<()> {
 
     eliminate_orderby_like_groupby(plan)?;
 
+    optimize_min_max(plan, schema)?;
+
+    optimize_simple_count(plan)?;
+
     Ok(())
 }
 
@@ -68,6 +86,11 @@ fn optimize_delete_plan(plan: &mut DeletePlan, schema: &Schema) -> Result<()> {
 }
 
 fn optimize_update_plan(plan: &mut UpdatePlan, schema: &Schema) -> Result<()> {
+    for table in plan.table_references.iter_mut() {
+        if let Operation::Subquery { plan, .. } = &mut table.op {
+            optimize_select_plan(&mut *plan, schema)?;
+        }
+    }
     rewrite_exprs_update(plan)?;
     if let ConstantConditionEliminationResult::ImpossibleCondition =
         eliminate_constant_conditions(&mut plan.where_clause)?
@@ -103,11 +126,13 @@ fn query_is_already_ordered_by(
         return Ok(false);
     }
     let table_reference = first_table.unwrap();
+    let key_is_rowid = key.is_rowid_alias_of(0) || matches!(key, ast::Expr::RowId { table: 0, .. });
     match &table_reference.op {
-        Operation::Scan { .. } => Ok(key.is_rowid_alias_of(0)),
+        Operation::Scan { index: None, .. } => Ok(key_is_rowid),
+        Operation::Scan { .. } => Ok(false),
         Operation::Search(search) => match search {
-            Search::RowidEq { .. } => Ok(key.is_rowid_alias_of(0)),
-            Search::RowidSearch { .. } => Ok(key.is_rowid_alias_of(0)),
+            Search::RowidEq { .. } => Ok(key_is_rowid),
+            Search::RowidSearch { .. } => Ok(key_is_rowid),
             Search::IndexSearch { index, .. } => {
                 let index_rc = key.check_index_scan(0, table_reference, available_indexes)?;
                 let index_is_the_same = index_rc
@@ -115,6 +140,9 @@ fn query_is_already_ordered_by(
                     .unwrap_or(false);
                 Ok(index_is_the_same)
             }
+            // Rows are found nearest first, not in the order of any key.
+            #[cfg(feature = "vector")]
+            Search::VectorMatch { .. } => Ok(false),
         },
         _ => Ok(false),
     }
@@ -215,11 +243,145 @@ fn eliminate_unnecessary_orderby(plan: &mut SelectPlan, schema: &Schema) -> Resu
     if already_ordered {
         push_scan_direction(&mut plan.table_references[0], direction);
         plan.order_by = None;
+        return Ok(());
+    }
+
+    // With a LIMIT, reading the first table in the order of an index on the ORDER BY key
+    // stops after the first rows instead of sorting the whole table.
+    if plan.limit.is_none() {
+        return Ok(());
+    }
+    let table_reference = &mut plan.table_references[0];
+    if !matches!(
+        table_reference.op,
+        Operation::Scan {
+            iter_dir: None,
+            index: None
+        }
+    ) || table_reference.btree().is_none()
+    {
+        return Ok(());
+    }
+    let Some(index) = key.check_index_scan(0, table_reference, &schema.indexes)? else {
+        return Ok(());
+    };
+    if index.columns[0].order != SortOrder::Asc {
+        return Ok(());
     }
+    table_reference.op = Operation::Scan {
+        iter_dir: Some(match direction {
+            Direction::Ascending => IterationDirection::Forwards,
+            Direction::Descending => IterationDirection::Backwards,
+        }),
+        index: Some(index),
+    };
+    plan.order_by = None;
 
     Ok(())
 }
 
+/**
+ * Turn `SELECT min(x) FROM t` and `SELECT max(x) FROM t` into a scan of t in the order of x
+ * that stops at the first row, when x is the rowid or the first column of an index.
+ * min() scans forwards past the NULLs, which sort first, and max() scans backwards.
+ */
+fn optimize_min_max(plan: &mut SelectPlan, schema: &Schema) -> Result<()> {
+    if plan.table_references.len() != 1
+        || plan.group_by.is_some()
+        || plan.aggregates.len() != 1
+        || !plan.where_clause.is_empty()
+    {
+        return Ok(());
+    }
+    let agg = &plan.aggregates[0];
+    let iter_dir = match agg.func {
+        AggFunc::Min => IterationDirection::Forwards,
+        AggFunc::Max => IterationDirection::Backwards,
+        _ => return Ok(()),
+    };
+    let [arg] = agg.args.as_slice() else {
+        return Ok(());
+    };
+    let table_reference = &mut plan.table_references[0];
+    if !matches!(
+        table_reference.op,
+        Operation::Scan {
+            iter_dir: None,
+            index: None
+        }
+    ) || table_reference.btree().is_none()
+    {
+        return Ok(());
+    }
+    if arg.is_rowid_alias_of(0) || matches!(arg, ast::Expr::RowId { table: 0, .. }) {
+        table_reference.op = Operation::Scan {
+            iter_dir: Some(iter_dir),
+            index: None,
+        };
+        plan.min_max_early_out = true;
+        return Ok(());
+    }
+    let mut arg = arg.clone();
+    let Some(index) = arg.check_index_scan(0, table_reference, &schema.indexes)? else {
+        return Ok(());
+    };
+    if index.columns[0].order != SortOrder::Asc {
+        return Ok(());
+    }
+    if iter_dir == IterationDirection::Forwards {
+        plan.where_clause.push(WhereTerm {
+            expr: ast::Expr::NotNull(Box::new(arg)),
+            from_outer_join: false,
+            eval_at: EvalAt::Loop(0),
+        });
+    }
+    table_reference.op = Operation::Scan {
+        iter_dir: Some(iter_dir),
+        index: Some(index),
+    };
+    plan.min_max_early_out = true;
+    Ok(())
+}
+
+/**
+ * Turn `SELECT count(*) FROM t` into a count of the entries of t's b-tree, which adds up the
+ * cell counts of the leaf pages instead of reading every row.
+ */
+fn optimize_simple_count(plan: &mut SelectPlan) -> Result<()> {
+    if plan.table_references.len() != 1
+        || plan.group_by.is_some()
+        || plan.aggregates.len() != 1
+        || !plan.where_clause.is_empty()
+        || plan.offset.is_some_and(|offset| offset > 0)
+    {
+        return Ok(());
+    }
+    let agg = &plan.aggregates[0];
+    if !matches!(agg.func, AggFunc::Count0) {
+        return Ok(());
+    }
+    if !plan
+        .result_columns
+        .iter()
+        .all(|rc| exprs_are_equivalent(&rc.expr, &agg.original_expr))
+    {
+        return Ok(());
+    }
+    let table_reference = &plan.table_references[0];
+    if !matches!(
+        table_reference.op,
+        Operation::Scan {
+            iter_dir: None,
+            index: None
+        }
+    ) || table_reference.btree().is_none()
+    {
+        return Ok(());
+    }
+    plan.simple_count = true;
+    Ok(())
+}
+
 /**
  * Use indexes where possible.
  * Right now we make decisions about using indexes ONLY based on condition expressions, not e.g. ORDER BY or others.
@@ -261,12 +423,638 @@ fn use_indexes(
     Ok(())
 }
 
+/// Searches a table with a vector index for `column MATCH vector LIMIT k`, which finds the k
+/// (plus OFFSET) rows whose column is nearest to the vector. As the LIMIT is what bounds the
+/// rows looked up, this is only done for a query of a single table without aggregation.
+#[cfg(feature = "vector")]
+fn use_vector_indexes(plan: &mut SelectPlan, schema: &Schema) -> Result<()> {
+    if plan.table_references.len() != 1 || plan.group_by.is_some() || !plan.aggregates.is_empty() {
+        return Ok(());
+    }
+    let Some(limit) = plan.limit.filter(|limit| *limit >= 0) else {
+        return Ok(());
+    };
+    let k = limit as usize + plan.offset.unwrap_or(0).max(0) as usize;
+    let table_reference = &mut plan.table_references[0];
+    if !matches!(table_reference.op, Operation::Scan { index: None, .. }) {
+        return Ok(());
+    }
+    let Some(table) = table_reference.btree() else {
+        return Ok(());
+    };
+    let indexes = schema.vector_indexes_on(&table.name);
+    if indexes.is_empty() {
+        return Ok(());
+    }
+    for i in 0..plan.where_clause.len() {
+        let term = &mut plan.where_clause[i];
+        if !term.should_eval_at_loop(0) {
+            continue;
+        }
+        let ast::Expr::Like {
+            lhs,
+            not: false,
+            op: ast::LikeOperator::Match,
+            rhs,
+            escape: None,
+        } = &mut term.expr
+        else {
+            continue;
+        };
+        let ast::Expr::Column {
+            table: 0, column, ..
+        } = lhs.as_ref()
+        else {
+            continue;
+        };
+        let Some(index) = indexes.iter().find(|index| index.column == *column) else {
+            continue;
+        };
+        // The vector is looked up once, before the loop.
+        if determine_where_to_eval_expr(rhs)? != EvalAt::BeforeLoop {
+            continue;
+        }
+        let query = WhereTerm {
+            expr: rhs.take_ownership(),
+            from_outer_join: false,
+            eval_at: EvalAt::BeforeLoop,
+        };
+        table_reference.op = Operation::Search(Search::VectorMatch {
+            index: index.clone(),
+            query,
+            k,
+        });
+        plan.where_clause.remove(i);
+        break;
+    }
+    Ok(())
+}
+
+/**
+ * Build automatic indexes for joins on unindexed columns.
+ * An inner table of a join that is still scanned would be scanned in full for every row of the
+ * outer tables. If it is joined with an equality on one of its columns, we instead build a
+ * transient index on that column once, before the loops, and search it like a regular index.
+ * Like SQLite without statistics, we assume every inner table is large enough for that to pay off.
+ *
+ * To keep the index lookups equivalent to the join condition, only equalities between two columns
+ * of the same affinity are used.
+ */
+pub fn use_automatic_indexes(plan: &mut Plan) -> Result<()> {
+    let Plan::Select(plan) = plan else {
+        return Ok(());
+    };
+    use_automatic_indexes_select(plan)
+}
+
+fn use_automatic_indexes_select(plan: &mut SelectPlan) -> Result<()> {
+    for table_reference in plan.table_references.iter_mut() {
+        if let Operation::Subquery { plan, .. } = &mut table_reference.op {
+            use_automatic_indexes_select(plan)?;
+        }
+    }
+    for table_index in 1..plan.table_references.len() {
+        let table_reference = &plan.table_references[table_index];
+        if !matches!(table_reference.op, Operation::Scan { .. }) {
+            continue;
+        }
+        let Some(table) = table_reference.btree() else {
+            continue;
+        };
+        let Some((i, column)) = plan.where_clause.iter().enumerate().find_map(|(i, cond)| {
+            automatic_index_column(cond, table_index, &plan.table_references).map(|c| (i, c))
+        }) else {
+            continue;
+        };
+        let cond = plan.where_clause.remove(i);
+        let ast::Expr::Binary(lhs, _, rhs) = cond.expr else {
+            unreachable!()
+        };
+        let other = if lhs.is_column_of(table_index) {
+            *rhs
+        } else {
+            *lhs
+        };
+        let column_name = table.columns[column].name.clone().unwrap();
+        let table_reference = &mut plan.table_references[table_index];
+        let index = Index::automatic(&table, &table_reference.identifier, &column_name);
+        table_reference.op = Operation::Search(Search::IndexSearch {
+            index: Arc::new(index),
+            cmp_op: ast::Operator::Equals,
+            cmp_expr: WhereTerm {
+                expr: other,
+                from_outer_join: cond.from_outer_join,
+                eval_at: cond.eval_at,
+            },
+            iter_dir: IterationDirection::Forwards,
+        });
+    }
+    Ok(())
+}
+
+/// If `cond` is an equality between a column of the table at `table_index` and a column of the
+/// same affinity of an outer table, returns the index of the former column.
+fn automatic_index_column(
+    cond: &WhereTerm,
+    table_index: usize,
+    tables: &[TableReference],
+) -> Option<usize> {
+    if !cond.should_eval_at_loop(table_index) {
+        return None;
+    }
+    let ast::Expr::Binary(lhs, ast::Operator::Equals, rhs) = &cond.expr else {
+        return None;
+    };
+    let (inner, other) = if lhs.is_column_of(table_index) {
+        (lhs, rhs)
+    } else {
+        (rhs, lhs)
+    };
+    let ast::Expr::Column {
+        column,
+        is_rowid_alias: false,
+        ..
+    } = inner.as_ref()
+    else {
+        return None;
+    };
+    let ast::Expr::Column {
+        table: other_table,
+        column: other_column,
+        ..
+    } = other.as_ref()
+    else {
+        return None;
+    };
+    if *other_table >= table_index {
+        return None;
+    }
+    let affinity = tables[table_index].table.get_column_at(*column)?.affinity();
+    let other_affinity = tables[*other_table]
+        .table
+        .get_column_at(*other_column)?
+        .affinity();
+    (affinity == other_affinity).then_some(*column)
+}
+
 #[derive(Debug, PartialEq, Clone)]
 enum ConstantConditionEliminationResult {
     Continue,
     ImpossibleCondition,
 }
 
+/**
+ * Materialize the CTEs that are referenced more than once in the same FROM clause,
+ * as well as the ones marked `AS MATERIALIZED`. The first reference computes the rows
+ * of the CTE into a transient table, and the later ones read that same table instead
+ * of running the CTE again. Materialized subqueries are never flattened.
+ */
+fn materialize_shared_ctes(plan: &mut SelectPlan) -> Result<()> {
+    let shared = shared_cte_names(plan);
+    for table_reference in plan.table_references.iter_mut() {
+        if let Operation::Subquery {
+            cte_name,
+            materialize_hint,
+            materialized,
+            ..
+        } = &mut table_reference.op
+        {
+            *materialized = match materialize_hint {
+                ast::Materialized::Yes => true,
+                ast::Materialized::No => false,
+                ast::Materialized::Any => {
+                    cte_name.as_ref().is_some_and(|name| shared.contains(name))
+                }
+            };
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Materialize the subqueries that are left after flattening and that are not the
+ * outermost loop. A coroutine is restarted on every iteration of the loops around it,
+ * whereas a transient table is computed once and rewound.
+ * The right side of an outer join is always materialized, since the rows it is
+ * completed with are read through the cursor of its table.
+ */
+fn materialize_inner_subqueries(plan: &mut SelectPlan) -> Result<()> {
+    for table_reference in plan.table_references.iter_mut().skip(1) {
+        let outer = table_reference
+            .join_info
+            .as_ref()
+            .is_some_and(|join_info| join_info.outer);
+        if let Operation::Subquery {
+            materialize_hint,
+            materialized,
+            ..
+        } = &mut table_reference.op
+        {
+            *materialized |= outer || *materialize_hint != ast::Materialized::No;
+        }
+    }
+    Ok(())
+}
+
+/// The names of the CTEs that more than one table of the FROM clause refers to.
+fn shared_cte_names(plan: &SelectPlan) -> HashSet<String> {
+    let mut seen = HashSet::new();
+    let mut shared = HashSet::new();
+    for table_reference in plan.table_references.iter() {
+        if let Operation::Subquery {
+            cte_name: Some(name),
+            ..
+        } = &table_reference.op
+        {
+            if !seen.insert(name.clone()) {
+                shared.insert(name.clone());
+            }
+        }
+    }
+    shared
+}
+
+/**
+ * Flatten subqueries in the FROM clause into the parent query.
+ * A subquery like `SELECT * FROM (SELECT a, b FROM t WHERE b > 1) sub WHERE sub.a = 5`
+ * is executed as `SELECT t.a, t.b FROM t WHERE t.b > 1 AND t.a = 5`, so its rows are read
+ * straight from the table instead of being produced by a coroutine, and the merged WHERE
+ * terms can use indexes.
+ *
+ * Only subqueries without aggregates, GROUP BY, ORDER BY, LIMIT or OFFSET are flattened.
+ * On the right side of an outer join, the subquery must also select plain columns of a
+ * single table, and its WHERE clause becomes part of the ON clause.
+ */
+fn flatten_subqueries(plan: &mut SelectPlan) -> Result<()> {
+    let mut table_index = 0;
+    while table_index < plan.table_references.len() {
+        if subquery_can_be_flattened(plan, table_index) {
+            // The tables of the subquery take its place, so look at the first of them next.
+            flatten_subquery(plan, table_index);
+        } else {
+            table_index += 1;
+        }
+    }
+    Ok(())
+}
+
+fn subquery_can_be_flattened(plan: &mut SelectPlan, table_index: usize) -> bool {
+    let table_reference = &plan.table_references[table_index];
+    let Operation::Subquery {
+        plan: subplan,
+        materialized: false,
+        ..
+    } = &table_reference.op
+    else {
+        return false;
+    };
+    // The right side of an outer join is completed with NULLs through the cursor of its table,
+    // which only works for a single table whose columns are selected as they are.
+    let outer = table_reference
+        .join_info
+        .as_ref()
+        .is_some_and(|join_info| join_info.outer);
+    if outer
+        && (subplan.table_references.len() != 1
+            || !subplan
+                .result_columns
+                .iter()
+                .all(|rc| matches!(rc.expr, ast::Expr::Column { .. })))
+    {
+        return false;
+    }
+    if !subplan.aggregates.is_empty()
+        || subplan.group_by.is_some()
+        || subplan.order_by.is_some()
+        || subplan.limit.is_some()
+        || subplan.offset.is_some()
+        || subplan.contains_constant_false_condition
+    {
+        return false;
+    }
+    // A subquery without a FROM clause can only disappear if nothing else is joined with it.
+    if subplan.table_references.is_empty() && plan.table_references.len() > 1 {
+        return false;
+    }
+    // Cursors are resolved by table identifier, so the merged tables must not share one with
+    // the other tables of the query, including those of its other subqueries.
+    let mut identifiers = Vec::new();
+    for (i, table_reference) in plan.table_references.iter().enumerate() {
+        if i != table_index {
+            collect_table_identifiers(table_reference, &mut identifiers);
+        }
+    }
+    if subplan
+        .table_references
+        .iter()
+        .any(|inner| identifiers.contains(&inner.identifier.as_str()))
+    {
+        return false;
+    }
+    // The subquery has no rowid that its tables could provide.
+    let mut references_rowid = false;
+    for_each_expr_mut(plan, &mut |expr| {
+        if matches!(expr, ast::Expr::RowId { table, .. } if *table == table_index) {
+            references_rowid = true;
+        }
+        true
+    });
+    !references_rowid
+}
+
+fn collect_table_identifiers<'a>(table_reference: &'a TableReference, out: &mut Vec<&'a str>) {
+    out.push(&table_reference.identifier);
+    if let Operation::Subquery { plan, .. } = &table_reference.op {
+        for table_reference in plan.table_references.iter() {
+            collect_table_identifiers(table_reference, out);
+        }
+    }
+}
+
+fn flatten_subquery(plan: &mut SelectPlan, table_index: usize) {
+    let table_reference = plan.table_references.remove(table_index);
+    // Keep the names the subquery gave its columns.
+    for rc in plan.result_columns.iter_mut() {
+        if rc.alias.is_some() {
+            continue;
+        }
+        if let ast::Expr::Column { table, column, .. } = &rc.expr {
+            if *table == table_index {
+                rc.alias = table_reference.columns()[*column].name.clone();
+            }
+        }
+    }
+    let Operation::Subquery { plan: subplan, .. } = table_reference.op else {
+        unreachable!("only subqueries are flattened");
+    };
+    let SelectPlan {
+        table_references: mut inner_tables,
+        result_columns: mut inner_columns,
+        where_clause: inner_where_clause,
+        ..
+    } = *subplan;
+    let inner_table_count = inner_tables.len();
+
+    // Renumber the references of the subquery and the parent query to the merged table list.
+    for rc in inner_columns.iter_mut() {
+        shift_table_references(&mut rc.expr, table_index);
+    }
+    for_each_expr_mut(plan, &mut |expr| match expr {
+        ast::Expr::Column { table, column, .. } if *table == table_index => {
+            *expr = inner_columns[*column].expr.clone();
+            false
+        }
+        ast::Expr::Column { table, .. } | ast::Expr::RowId { table, .. }
+            if *table > table_index =>
+        {
+            *table = *table + inner_table_count - 1;
+            true
+        }
+        _ => true,
+    });
+    for term in plan.where_clause.iter_mut() {
+        if term.from_outer_join {
+            if let EvalAt::Loop(i) = &mut term.eval_at {
+                if *i > table_index {
+                    *i = *i + inner_table_count - 1;
+                }
+            }
+        } else {
+            term.eval_at = determine_where_to_eval_expr(&term.expr)
+                .expect("flattened WHERE terms only contain resolved expressions");
+        }
+    }
+    let outer = table_reference
+        .join_info
+        .as_ref()
+        .is_some_and(|join_info| join_info.outer);
+    for mut term in inner_where_clause {
+        shift_table_references(&mut term.expr, table_index);
+        if outer {
+            // The WHERE clause of the subquery becomes part of the ON clause.
+            term.from_outer_join = true;
+            term.eval_at = EvalAt::Loop(table_index);
+        } else if let EvalAt::Loop(i) = &mut term.eval_at {
+            *i += table_index;
+        }
+        plan.where_clause.push(term);
+    }
+
+    if let Some(first) = inner_tables.first_mut() {
+        first.join_info = table_reference.join_info;
+    }
+    plan.table_references
+        .splice(table_index..table_index, inner_tables);
+}
+
+/**
+ * Push WHERE terms that only reference a FROM clause subquery down into that subquery,
+ * so that they filter its rows before they are handed to the parent query.
+ * For example, `SELECT * FROM (SELECT a, count(*) c FROM t GROUP BY a) WHERE a = 5`
+ * only groups the rows of t where a = 5.
+ *
+ * Terms are not pushed into subqueries with a LIMIT or OFFSET, into aggregates without
+ * GROUP BY, or into a grouped subquery unless they only use its GROUP BY expressions.
+ */
+fn push_predicates_into_subqueries(plan: &mut SelectPlan) -> Result<()> {
+    // The rows of a shared CTE are computed once for all of its references,
+    // so none of them can filter it on its own.
+    let shared = shared_cte_names(plan);
+    let mut i = 0;
+    while i < plan.where_clause.len() {
+        let term = &mut plan.where_clause[i];
+        let EvalAt::Loop(table_index) = term.eval_at else {
+            i += 1;
+            continue;
+        };
+        let table_reference = &mut plan.table_references[table_index];
+        let outer = table_reference
+            .join_info
+            .as_ref()
+            .is_some_and(|join_info| join_info.outer);
+        let Operation::Subquery {
+            plan: subplan,
+            cte_name,
+            materialized,
+            ..
+        } = &mut table_reference.op
+        else {
+            i += 1;
+            continue;
+        };
+        if *materialized && cte_name.as_ref().is_some_and(|name| shared.contains(name)) {
+            i += 1;
+            continue;
+        }
+        // A WHERE term on the right side of an outer join also filters out the rows
+        // the join completes with NULLs; only its ON terms can move into the subquery.
+        if term.from_outer_join != outer || !predicate_can_be_pushed(term, table_index, subplan) {
+            i += 1;
+            continue;
+        }
+        let mut term = plan.where_clause.remove(i);
+        for_each_expr_in(&mut term.expr, &mut |expr| {
+            if let ast::Expr::Column { column, .. } = expr {
+                *expr = subplan.result_columns[*column].expr.clone();
+                return false;
+            }
+            true
+        });
+        let eval_at = determine_where_to_eval_expr(&term.expr)?;
+        subplan.where_clause.push(WhereTerm {
+            expr: term.expr,
+            from_outer_join: false,
+            eval_at,
+        });
+    }
+    Ok(())
+}
+
+fn predicate_can_be_pushed(term: &mut WhereTerm, table_index: usize, subplan: &SelectPlan) -> bool {
+    if subplan.limit.is_some() || subplan.offset.is_some() || !expr_is_deterministic(&term.expr) {
+        return false;
+    }
+    if !subplan.aggregates.is_empty() && subplan.group_by.is_none() {
+        return false;
+    }
+    let mut can_be_pushed = true;
+    for_each_expr_in(&mut term.expr, &mut |expr| {
+        match expr {
+            ast::Expr::Column { table, column, .. } if *table == table_index => {
+                let rc = &subplan.result_columns[*column];
+                let grouped = subplan.group_by.as_ref().map_or(true, |group_by| {
+                    group_by
+                        .exprs
+                        .iter()
+                        .any(|group_expr| exprs_are_equivalent(group_expr, &rc.expr))
+                });
+                can_be_pushed &= grouped && !rc.contains_aggregates;
+            }
+            ast::Expr::Column { .. } | ast::Expr::RowId { .. } => can_be_pushed = false,
+            _ => {}
+        }
+        true
+    });
+    can_be_pushed
+}
+
+/// Adds `offset` to every table reference in `expr`.
+fn shift_table_references(expr: &mut ast::Expr, offset: usize) {
+    for_each_expr_in(expr, &mut |expr| {
+        if let ast::Expr::Column { table, .. } | ast::Expr::RowId { table, .. } = expr {
+            *table += offset;
+        }
+        true
+    });
+}
+
+/// Calls `func` on every expression of a select plan, see [for_each_expr_in].
+fn for_each_expr_mut(plan: &mut SelectPlan, func: &mut impl FnMut(&mut ast::Expr) -> bool) {
+    for rc in plan.result_columns.iter_mut() {
+        for_each_expr_in(&mut rc.expr, func);
+    }
+    for term in plan.where_clause.iter_mut() {
+        for_each_expr_in(&mut term.expr, func);
+    }
+    if let Some(group_by) = &mut plan.group_by {
+        for expr in group_by.exprs.iter_mut() {
+            for_each_expr_in(expr, func);
+        }
+        for expr in group_by.having.iter_mut().flatten() {
+            for_each_expr_in(expr, func);
+        }
+    }
+    if let Some(order_by) = &mut plan.order_by {
+        for (expr, _) in order_by.iter_mut() {
+            for_each_expr_in(expr, func);
+        }
+    }
+    for agg in plan.aggregates.iter_mut() {
+        for arg in agg.args.iter_mut() {
+            for_each_expr_in(arg, func);
+        }
+        for_each_expr_in(&mut agg.original_expr, func);
+    }
+}
+
+/// Calls `func` on `expr` and its subexpressions, parents first, not descending into
+/// subqueries. The subexpressions of an expression are skipped when `func` returns false for it.
+pub(crate) fn for_each_expr_in(
+    expr: &mut ast::Expr,
+    func: &mut impl FnMut(&mut ast::Expr) -> bool,
+) {
+    if !func(expr) {
+        return;
+    }
+    match expr {
+        ast::Expr::Between {
+            lhs, start, end, ..
+        } => {
+            for_each_expr_in(lhs, func);
+            for_each_expr_in(start, func);
+            for_each_expr_in(end, func);
+        }
+        ast::Expr::Binary(lhs, _, rhs) => {
+            for_each_expr_in(lhs, func);
+            for_each_expr_in(rhs, func);
+        }
+        ast::Expr::Case {
+            base,
+            when_then_pairs,
+            else_expr,
+        } => {
+            if let Some(base) = base {
+                for_each_expr_in(base, func);
+            }
+            for (when, then) in when_then_pairs.iter_mut() {
+                for_each_expr_in(when, func);
+                for_each_expr_in(then, func);
+            }
+            if let Some(else_expr) = else_expr {
+                for_each_expr_in(else_expr, func);
+            }
+        }
+        ast::Expr::Cast { expr, .. }
+        | ast::Expr::Collate(expr, _)
+        | ast::Expr::IsNull(expr)
+        | ast::Expr::NotNull(expr)
+        | ast::Expr::Unary(_, expr) => for_each_expr_in(expr, func),
+        ast::Expr::FunctionCall { args, .. } => {
+            for arg in args.iter_mut().flatten() {
+                for_each_expr_in(arg, func);
+            }
+        }
+        ast::Expr::InList { lhs, rhs, .. } => {
+            for_each_expr_in(lhs, func);
+            for expr in rhs.iter_mut().flatten() {
+                for_each_expr_in(expr, func);
+            }
+        }
+        ast::Expr::InSelect { lhs, .. } => for_each_expr_in(lhs, func),
+        ast::Expr::InTable { lhs, args, .. } => {
+            for_each_expr_in(lhs, func);
+            for arg in args.iter_mut().flatten() {
+                for_each_expr_in(arg, func);
+            }
+        }
+        ast::Expr::Like {
+            lhs, rhs, escape, ..
+        } => {
+            for_each_expr_in(lhs, func);
+            for_each_expr_in(rhs, func);
+            if let Some(escape) = escape {
+                for_each_expr_in(escape, func);
+            }
+        }
+        ast::Expr::Parenthesized(exprs) => {
+            for expr in exprs.iter_mut() {
+                for_each_expr_in(expr, func);
+            }
+        }
+        _ => {}
+    }
+}
+
 /// Removes predicates that are always true.
 /// Returns a ConstantEliminationResult indicating whether any predicates are always false.
 /// This is used to determine whether the query can be aborted early.
@@ -297,16 +1085,25 @@ fn eliminate_constant_conditions(
 }
 
 fn push_scan_direction(table: &mut TableReference, direction: &Direction) {
-    if let Operation::Scan {
-        ref mut iter_dir, ..
-    } = table.op
-    {
-        if iter_dir.is_none() {
-            match direction {
-                Direction::Ascending => *iter_dir = Some(IterationDirection::Forwards),
-                Direction::Descending => *iter_dir = Some(IterationDirection::Backwards),
+    let dir = match direction {
+        Direction::Ascending => IterationDirection::Forwards,
+        Direction::Descending => IterationDirection::Backwards,
+    };
+    match &mut table.op {
+        Operation::Scan { iter_dir, .. } => {
+            if iter_dir.is_none() {
+                *iter_dir = Some(dir);
             }
         }
+        Operation::Search(Search::RowidSearch { iter_dir, .. }) => *iter_dir = dir,
+        // Every row of an equality search has the same key, so like SQLite we keep scanning forwards
+        // (which returns ties in rowid order) regardless of the requested direction.
+        Operation::Search(Search::IndexSearch {
+            cmp_op: ast::Operator::Equals,
+            ..
+        }) => {}
+        Operation::Search(Search::IndexSearch { iter_dir, .. }) => *iter_dir = dir,
+        _ => {}
     }
 }
 
@@ -338,6 +1135,11 @@ fn rewrite_exprs_delete(plan: &mut DeletePlan) -> Result<()> {
     for cond in plan.where_clause.iter_mut() {
         rewrite_expr(&mut cond.expr)?;
     }
+    if let Some(order_by) = &mut plan.order_by {
+        for (expr, _) in order_by.iter_mut() {
+            rewrite_expr(expr)?;
+        }
+    }
     Ok(())
 }
 
@@ -385,6 +1187,7 @@ pub trait Optimizable {
             .map_or(false, |c| c == ConstantPredicate::AlwaysFalse))
     }
     fn is_rowid_alias_of(&self, table_index: usize) -> bool;
+    fn is_column_of(&self, table_index: usize) -> bool;
     fn check_index_scan(
         &mut self,
         table_index: usize,
@@ -394,6 +1197,9 @@ pub trait Optimizable {
 }
 
 impl Optimizable for ast::Expr {
+    fn is_column_of(&self, table_index: usize) -> bool {
+        matches!(self, Self::Column { table, .. } if *table == table_index)
+    }
     fn is_rowid_alias_of(&self, table_index: usize) -> bool {
         match self {
             Self::Column {
@@ -443,6 +1249,7 @@ impl Optimizable for ast::Expr {
                 if !matches!(
                     *op,
                     ast::Operator::Equals
+                        | ast::Operator::Is
                         | ast::Operator::Greater
                         | ast::Operator::GreaterEquals
                         | ast::Operator::Less
@@ -461,6 +1268,7 @@ impl Optimizable for ast::Expr {
                     // swap lhs and rhs
                     let swapped_operator = match *op {
                         ast::Operator::Equals => ast::Operator::Equals,
+                        ast::Operator::Is => ast::Operator::Is,
                         ast::Operator::Greater => ast::Operator::Less,
                         ast::Operator::GreaterEquals => ast::Operator::LessEquals,
                         ast::Operator::Less => ast::Operator::Greater,
@@ -598,6 +1406,7 @@ impl Optimizable for ast::Expr {
 fn opposite_cmp_op(op: ast::Operator) -> ast::Operator {
     match op {
         ast::Operator::Equals => ast::Operator::Equals,
+        ast::Operator::Is => ast::Operator::Is,
         ast::Operator::Greater => ast::Operator::Less,
         ast::Operator::GreaterEquals => ast::Operator::LessEquals,
         ast::Operator::Less => ast::Operator::Greater,
@@ -619,7 +1428,8 @@ pub fn try_extract_index_search_expression(
         ast::Expr::Binary(lhs, operator, rhs) => {
             if lhs.is_rowid_alias_of(table_index) {
                 match operator {
-                    ast::Operator::Equals => {
+                    // A rowid is never NULL, so IS finds the same row as =.
+                    ast::Operator::Equals | ast::Operator::Is => {
                         let rhs_owned = rhs.take_ownership();
                         return Ok(Some(Search::RowidEq {
                             cmp_expr: WhereTerm {
@@ -641,6 +1451,7 @@ pub fn try_extract_index_search_expression(
                                 from_outer_join: cond.from_outer_join,
                                 eval_at: cond.eval_at,
                             },
+                            iter_dir: IterationDirection::Forwards,
                         }));
                     }
                     _ => {}
@@ -649,7 +1460,7 @@ pub fn try_extract_index_search_expression(
 
             if rhs.is_rowid_alias_of(table_index) {
                 match operator {
-                    ast::Operator::Equals => {
+                    ast::Operator::Equals | ast::Operator::Is => {
                         let lhs_owned = lhs.take_ownership();
                         return Ok(Some(Search::RowidEq {
                             cmp_expr: WhereTerm {
@@ -671,6 +1482,7 @@ pub fn try_extract_index_search_expression(
                                 from_outer_join: cond.from_outer_join,
                                 eval_at: cond.eval_at,
                             },
+                            iter_dir: IterationDirection::Forwards,
                         }));
                     }
                     _ => {}
@@ -682,6 +1494,7 @@ pub fn try_extract_index_search_expression(
             {
                 match operator {
                     ast::Operator::Equals
+                    | ast::Operator::Is
                     | ast::Operator::Greater
                     | ast::Operator::GreaterEquals
                     | ast::Operator::Less
@@ -695,6 +1508,7 @@ pub fn try_extract_index_search_expression(
                                 from_outer_join: cond.from_outer_join,
                                 eval_at: cond.eval_at,
                             },
+                            iter_dir: IterationDirection::Forwards,
                         }));
                     }
                     _ => {}
@@ -706,6 +1520,7 @@ pub fn try_extract_index_search_expression(
             {
                 match operator {
                     ast::Operator::Equals
+                    | ast::Operator::Is
                     | ast::Operator::Greater
                     | ast::Operator::GreaterEquals
                     | ast::Operator::Less
@@ -719,6 +1534,7 @@ pub fn try_extract_index_search_expression(
                                 from_outer_join: cond.from_outer_join,
                                 eval_at: cond.eval_at,
                             },
+                            iter_dir: IterationDirection::Forwards,
                         }));
                     }
                     _ => {}
@@ -14,6 +14,7 @@ use crate::{
 };
 
 use super::{
+    collate::CollationSeq,
     emitter::TranslateCtx,
     expr::translate_expr,
     plan::{Direction, ResultSetColumn, SelectPlan},
@@ -34,6 +35,7 @@ pub fn init_order_by(
     program: &mut ProgramBuilder,
     t_ctx: &mut TranslateCtx,
     order_by: &[(ast::Expr, Direction)],
+    max_rows: Option<usize>,
 ) -> Result<()> {
     let sort_cursor = program.alloc_cursor_id(None, CursorType::Sorter);
     t_ctx.meta_sort = Some(SortMetadata {
@@ -41,12 +43,17 @@ pub fn init_order_by(
         reg_sorter_data: program.alloc_register(),
     });
     let mut order = Vec::new();
-    for (_, direction) in order_by.iter() {
-        order.push(OwnedValue::Integer(*direction as i64));
+    for (expr, direction) in order_by.iter() {
+        // The sorter takes the direction of each key in the low bit and its collation above it.
+        let (_, collation) = sort_key(expr)?;
+        order.push(OwnedValue::Integer(
+            *direction as i64 | (collation as i64) << 1,
+        ));
     }
     program.emit_insn(Insn::SorterOpen {
         cursor_id: sort_cursor,
         columns: order_by.len(),
+        max_rows,
         order: Record::new(order),
     });
     Ok(())
@@ -185,7 +192,7 @@ pub fn order_by_sorter_insert(
         translate_expr(
             program,
             Some(&plan.table_references),
-            expr,
+            sort_key(expr)?.0,
             key_reg,
             &t_ctx.resolver,
         )?;
@@ -232,6 +239,20 @@ pub fn order_by_sorter_insert(
     Ok(())
 }
 
+/// Splits an ORDER BY term into the expression it sorts by and the collating sequence of its
+/// COLLATE clause. Of nested COLLATE clauses, the outermost one wins.
+pub fn sort_key(expr: &ast::Expr) -> Result<(&ast::Expr, CollationSeq)> {
+    let mut collation = None;
+    let mut inner = expr;
+    while let ast::Expr::Collate(expr, name) = inner {
+        if collation.is_none() {
+            collation = Some(CollationSeq::new(name)?);
+        }
+        inner = expr;
+    }
+    Ok((inner, collation.unwrap_or_default()))
+}
+
 /// Emits the bytecode for inserting a row into a sorter.
 /// This can be either a GROUP BY sorter or an ORDER BY sorter.
 pub fn sorter_insert(
@@ -8,6 +8,8 @@ use std::{
 };
 
 use crate::schema::{PseudoTable, Type};
+#[cfg(feature = "vector")]
+use crate::vector::index::VectorIndex;
 use crate::{
     function::AggFunc,
     schema::{BTreeTable, Column, Index, Table},
@@ -145,8 +147,16 @@ pub struct SelectPlan {
     pub offset: Option<isize>,
     /// query contains a constant condition that is always false
     pub contains_constant_false_condition: bool,
+    /// the query is a single min() or max() whose first table is scanned in the order of its argument,
+    /// so the loop stops after the first row
+    pub min_max_early_out: bool,
+    /// the query is a single count(*) over a whole table, so the rows are counted from the b-tree
+    /// pages instead of being read one by one
+    pub simple_count: bool,
     /// query type (top level or subquery)
     pub query_type: SelectQueryType,
+    /// the commit marker or timestamp of `SELECT ... AS OF`, as of which the database is read
+    pub as_of: Option<ast::Expr>,
 }
 
 #[allow(dead_code)]
@@ -265,6 +275,8 @@ pub enum Operation {
     // assignments. for more detailed discussions, please refer to https://github.com/tursodatabase/limbo/pull/376
     Scan {
         iter_dir: Option<IterationDirection>,
+        /// When set, the table is read in the order of this index instead of rowid order.
+        index: Option<Arc<Index>>,
     },
     // Search operation
     // This operation is used to search for a row in a table using an index
@@ -276,6 +288,13 @@ pub enum Operation {
     Subquery {
         plan: Box<SelectPlan>,
         result_columns_start_reg: usize,
+        /// The name of the CTE this subquery was written as, if any.
+        cte_name: Option<String>,
+        /// The `AS [NOT] MATERIALIZED` hint of the CTE.
+        materialize_hint: ast::Materialized,
+        /// Whether the rows of the subquery are stored in a transient table before the loops start,
+        /// instead of being produced by a coroutine every time the subquery is looped over.
+        materialized: bool,
     },
 }
 
@@ -314,6 +333,9 @@ impl TableReference {
             op: Operation::Subquery {
                 plan: Box::new(plan),
                 result_columns_start_reg: 0, // Will be set in the bytecode emission phase
+                cte_name: None,
+                materialize_hint: ast::Materialized::Any,
+                materialized: false,
             },
             table,
             identifier: identifier.clone(),
@@ -321,6 +343,26 @@ impl TableReference {
         }
     }
 
+    /// Creates a new TableReference for a reference to a CTE.
+    pub fn new_cte(
+        identifier: String,
+        cte_name: String,
+        plan: SelectPlan,
+        materialize_hint: ast::Materialized,
+    ) -> Self {
+        let mut table_reference = Self::new_subquery(identifier, plan, None);
+        if let Operation::Subquery {
+            cte_name: name,
+            materialize_hint: hint,
+            ..
+        } = &mut table_reference.op
+        {
+            *name = Some(cte_name);
+            *hint = materialize_hint;
+        }
+        table_reference
+    }
+
     pub fn columns(&self) -> &[Column] {
         self.table.columns()
     }
@@ -337,12 +379,22 @@ pub enum Search {
     RowidSearch {
         cmp_op: ast::Operator,
         cmp_expr: WhereTerm,
+        iter_dir: IterationDirection,
     },
     /// A secondary index search. Uses bytecode instructions like SeekGE, SeekGT etc.
     IndexSearch {
         index: Arc<Index>,
         cmp_op: ast::Operator,
         cmp_expr: WhereTerm,
+        iter_dir: IterationDirection,
+    },
+    /// A lookup of the `k` rows nearest to the vector `query` with a vector index, from a
+    /// `column MATCH query` condition. Uses the VectorSearch bytecode instruction.
+    #[cfg(feature = "vector")]
+    VectorMatch {
+        index: Arc<VectorIndex>,
+        query: WhereTerm,
+        k: usize,
     },
 }
 
@@ -409,14 +461,21 @@ impl Display for SelectPlan {
             };
 
             match &reference.op {
-                Operation::Scan { .. } => {
+                Operation::Scan { index, .. } => {
                     let table_name = if reference.table.get_name() == reference.identifier {
                         reference.identifier.clone()
                     } else {
                         format!("{} AS {}", reference.table.get_name(), reference.identifier)
                     };
 
-                    writeln!(f, "{}SCAN {}", indent, table_name)?;
+                    match index {
+                        Some(index) => writeln!(
+                            f,
+                            "{}SCAN {} USING INDEX {}",
+                            indent, table_name, index.name
+                        )?,
+                        None => writeln!(f, "{}SCAN {}", indent, table_name)?,
+                    }
                 }
                 Operation::Search(search) => match search {
                     Search::RowidEq { .. } | Search::RowidSearch { .. } => {
@@ -426,6 +485,13 @@ impl Display for SelectPlan {
                             indent, reference.identifier
                         )?;
                     }
+                    Search::IndexSearch { index, .. } if index.ephemeral => {
+                        writeln!(
+                            f,
+                            "{}SEARCH {} USING AUTOMATIC INDEX ({}=?)",
+                            indent, reference.identifier, index.columns[0].name
+                        )?;
+                    }
                     Search::IndexSearch { index, .. } => {
                         writeln!(
                             f,
@@ -433,9 +499,24 @@ impl Display for SelectPlan {
                             indent, reference.identifier, index.name
                         )?;
                     }
+                    #[cfg(feature = "vector")]
+                    Search::VectorMatch { index, .. } => {
+                        writeln!(
+                            f,
+                            "{}SEARCH {} USING VECTOR INDEX {}",
+                            indent, reference.identifier, index.name
+                        )?;
+                    }
                 },
-                Operation::Subquery { plan, .. } => {
-                    writeln!(f, "{}SUBQUERY {}", indent, reference.identifier)?;
+                Operation::Subquery {
+                    plan, materialized, ..
+                } => {
+                    let kind = if *materialized {
+                        "MATERIALIZE"
+                    } else {
+                        "SUBQUERY"
+                    };
+                    writeln!(f, "{}{} {}", indent, kind, reference.identifier)?;
                     // Indent and format the subquery plan
                     for line in format!("{}", plan).lines() {
                         writeln!(f, "{}   {}", indent, line)?;
@@ -522,6 +603,14 @@ impl fmt::Display for UpdatePlan {
                             indent, reference.identifier, index.name
                         )?;
                     }
+                    #[cfg(feature = "vector")]
+                    Search::VectorMatch { index, .. } => {
+                        writeln!(
+                            f,
+                            "{}SEARCH {} USING VECTOR INDEX {}",
+                            indent, reference.identifier, index.name
+                        )?;
+                    }
                 },
                 Operation::Subquery { plan, .. } => {
                     writeln!(f, "{}SUBQUERY {}", indent, reference.identifier)?;
@@ -1,25 +1,24 @@
 use super::{
+    expr::likelihood_probability,
     plan::{
-        Aggregate, EvalAt, JoinInfo, Operation, Plan, ResultSetColumn, SelectPlan, SelectQueryType,
-        TableReference, WhereTerm,
+        Aggregate, Direction, EvalAt, JoinInfo, Operation, Plan, ResultSetColumn, SelectPlan,
+        SelectQueryType, TableReference, WhereTerm,
     },
     select::prepare_select_plan,
     SymbolTable,
 };
 use crate::{
-    function::Func,
+    function::{AggFunc, ExtFunc, Func, ScalarFunc},
     schema::{Schema, Table},
-    util::{exprs_are_equivalent, normalize_ident, vtable_args},
+    util::{expr_is_deterministic, exprs_are_equivalent, normalize_ident, vtable_args},
     vdbe::BranchOffset,
     Result,
 };
-use limbo_sqlite3_parser::ast::{
-    self, Expr, FromClause, JoinType, Limit, Materialized, UnaryOperator, With,
-};
+use limbo_sqlite3_parser::ast::{self, Expr, FromClause, JoinType, Limit, UnaryOperator, With};
 
 pub const ROWID: &str = "rowid";
 
-pub fn resolve_aggregates(expr: &Expr, aggs: &mut Vec<Aggregate>) -> bool {
+pub fn resolve_aggregates(expr: &Expr, aggs: &mut Vec<Aggregate>, syms: &SymbolTable) -> bool {
     if aggs
         .iter()
         .any(|a| exprs_are_equivalent(&a.original_expr, expr))
@@ -33,8 +32,19 @@ pub fn resolve_aggregates(expr: &Expr, aggs: &mut Vec<Aggregate>) -> bool {
             } else {
                 0
             };
-            match Func::resolve_function(normalize_ident(name.0.as_str()).as_str(), args_count) {
-                Ok(Func::Agg(f)) => {
+            let agg_func =
+                match Func::resolve_function(normalize_ident(name.0.as_str()).as_str(), args_count)
+                {
+                    Ok(Func::Agg(f)) => Some(f),
+                    Ok(_) => None,
+                    // Aggregates of extensions are only known to the symbol table.
+                    Err(_) => syms
+                        .resolve_function(&name.0, args_count)
+                        .filter(|f| matches!(f.func, ExtFunc::Aggregate { .. }))
+                        .map(|f| AggFunc::External(f.func.clone().into())),
+                };
+            match agg_func {
+                Some(f) => {
                     aggs.push(Aggregate {
                         func: f,
                         args: args.clone().unwrap_or_default(),
@@ -42,11 +52,11 @@ pub fn resolve_aggregates(expr: &Expr, aggs: &mut Vec<Aggregate>) -> bool {
                     });
                     true
                 }
-                _ => {
+                None => {
                     let mut contains_aggregates = false;
                     if let Some(args) = args {
                         for arg in args.iter() {
-                            contains_aggregates |= resolve_aggregates(arg, aggs);
+                            contains_aggregates |= resolve_aggregates(arg, aggs, syms);
                         }
                     }
                     contains_aggregates
@@ -69,13 +79,13 @@ pub fn resolve_aggregates(expr: &Expr, aggs: &mut Vec<Aggregate>) -> bool {
         }
         Expr::Binary(lhs, _, rhs) => {
             let mut contains_aggregates = false;
-            contains_aggregates |= resolve_aggregates(lhs, aggs);
-            contains_aggregates |= resolve_aggregates(rhs, aggs);
+            contains_aggregates |= resolve_aggregates(lhs, aggs, syms);
+            contains_aggregates |= resolve_aggregates(rhs, aggs, syms);
             contains_aggregates
         }
         Expr::Unary(_, expr) => {
             let mut contains_aggregates = false;
-            contains_aggregates |= resolve_aggregates(expr, aggs);
+            contains_aggregates |= resolve_aggregates(expr, aggs, syms);
             contains_aggregates
         }
         // TODO: handle other expressions that may contain aggregates
@@ -248,7 +258,13 @@ pub fn bind_column_references(
             Ok(())
         }
         Expr::InSelect { .. } => todo!(),
-        Expr::InTable { .. } => todo!(),
+        Expr::InTable { lhs, args, .. } => {
+            bind_column_references(lhs, referenced_tables, result_columns)?;
+            for arg in args.iter_mut().flatten() {
+                bind_column_references(arg, referenced_tables, result_columns)?;
+            }
+            Ok(())
+        }
         Expr::IsNull(expr) => {
             bind_column_references(expr, referenced_tables, result_columns)?;
             Ok(())
@@ -289,6 +305,10 @@ fn parse_from_clause_table<'a>(
     match table {
         ast::SelectTable::Table(qualified_name, maybe_alias, _) => {
             let normalized_qualified_name = normalize_ident(qualified_name.name.0.as_str());
+            let cte_alias = maybe_alias.as_ref().map(|a| match a {
+                ast::As::As(id) => normalize_ident(&id.0),
+                ast::As::Elided(id) => normalize_ident(&id.0),
+            });
             // Check if the FROM clause table is referring to a CTE in the current scope.
             if let Some(cte) = scope
                 .ctes
@@ -297,8 +317,7 @@ fn parse_from_clause_table<'a>(
             {
                 // CTE can be rewritten as a subquery.
                 // TODO: find a way not to clone the CTE plan here.
-                let cte_table =
-                    TableReference::new_subquery(cte.name.clone(), cte.plan.clone(), None);
+                let cte_table = cte.table_reference(cte_alias);
                 scope.tables.push(cte_table);
                 return Ok(());
             };
@@ -320,7 +339,10 @@ fn parse_from_clause_table<'a>(
                     ));
                 };
                 scope.tables.push(TableReference {
-                    op: Operation::Scan { iter_dir: None },
+                    op: Operation::Scan {
+                        iter_dir: None,
+                        index: None,
+                    },
                     table: tbl_ref,
                     identifier: alias.unwrap_or(normalized_qualified_name),
                     join_info: None,
@@ -345,8 +367,7 @@ fn parse_from_clause_table<'a>(
                     .find(|cte| cte.name == normalized_qualified_name)
                 {
                     // TODO: avoid cloning the CTE plan here.
-                    let cte_table =
-                        TableReference::new_subquery(cte.name.clone(), cte.plan.clone(), None);
+                    let cte_table = cte.table_reference(cte_alias);
                     scope.tables.push(cte_table);
                     return Ok(());
                 }
@@ -399,7 +420,10 @@ fn parse_from_clause_table<'a>(
                 .unwrap_or(normalized_name.to_string());
 
             scope.tables.push(TableReference {
-                op: Operation::Scan { iter_dir: None },
+                op: Operation::Scan {
+                    iter_dir: None,
+                    index: None,
+                },
                 join_info: None,
                 table: Table::Virtual(vtab),
                 identifier: alias,
@@ -449,8 +473,24 @@ pub struct Cte {
     /// The query plan for the CTE.
     /// Currently we only support SELECT queries in CTEs.
     plan: SelectPlan,
+    /// The `AS [NOT] MATERIALIZED` hint of the CTE.
+    materialized: ast::Materialized,
+}
+
+impl Cte {
+    /// Creates a table reference to the CTE, as a subquery.
+    fn table_reference(&self, alias: Option<String>) -> TableReference {
+        TableReference::new_cte(
+            alias.unwrap_or_else(|| self.name.clone()),
+            self.name.clone(),
+            self.plan.clone(),
+            self.materialized.clone(),
+        )
+    }
 }
 
+/// Parses the FROM clause into the tables it joins, after `tables`: the tables already in scope
+/// before it, like the table changed by UPDATE ... FROM.
 pub fn parse_from<'a>(
     schema: &Schema,
     mut from: Option<FromClause>,
@@ -458,13 +498,14 @@ pub fn parse_from<'a>(
     with: Option<With>,
     out_where_clause: &mut Vec<WhereTerm>,
     outer_scope: Option<&'a Scope<'a>>,
+    tables: Vec<TableReference>,
 ) -> Result<Vec<TableReference>> {
     if from.as_ref().and_then(|f| f.select.as_ref()).is_none() {
-        return Ok(vec![]);
+        return Ok(tables);
     }
 
     let mut scope = Scope {
-        tables: vec![],
+        tables,
         ctes: vec![],
         parent: outer_scope,
     };
@@ -474,9 +515,6 @@ pub fn parse_from<'a>(
             crate::bail_parse_error!("Recursive CTEs are not yet supported");
         }
         for cte in with.ctes {
-            if cte.materialized == Materialized::Yes {
-                crate::bail_parse_error!("Materialized CTEs are not yet supported");
-            }
             if cte.columns.is_some() {
                 crate::bail_parse_error!("CTE columns are not yet supported");
             }
@@ -515,6 +553,7 @@ pub fn parse_from<'a>(
             scope.ctes.push(Cte {
                 name: cte_name_normalized,
                 plan: cte_plan,
+                materialized: cte.materialized,
             });
         }
     }
@@ -544,7 +583,11 @@ pub fn parse_where(
             bind_column_references(expr, table_references, result_columns)?;
         }
         for expr in predicates {
-            let eval_at = determine_where_to_eval_expr(&expr)?;
+            let eval_at = match determine_where_to_eval_expr(&expr)? {
+                // without tables there is a single row, whose terms are evaluated before the loop
+                EvalAt::Loop(_) if table_references.is_empty() => EvalAt::BeforeLoop,
+                eval_at => eval_at,
+            };
             out_where_clause.push(WhereTerm {
                 expr,
                 from_outer_join: false,
@@ -564,7 +607,7 @@ pub fn parse_where(
   For expressions not referencing any tables (e.g. constants), this is before the main loop is
   opened, because they do not need any table data.
 */
-fn determine_where_to_eval_expr<'a>(predicate: &'a ast::Expr) -> Result<EvalAt> {
+pub fn determine_where_to_eval_expr(predicate: &ast::Expr) -> Result<EvalAt> {
     let mut eval_at: EvalAt = EvalAt::BeforeLoop;
     match predicate {
         ast::Expr::Binary(e1, _, e2) => {
@@ -592,6 +635,9 @@ fn determine_where_to_eval_expr<'a>(predicate: &'a ast::Expr) -> Result<EvalAt>
             for arg in args {
                 eval_at = eval_at.max(determine_where_to_eval_expr(arg)?);
             }
+            if !expr_is_deterministic(predicate) {
+                eval_at = eval_at.max(EvalAt::Loop(0));
+            }
         }
         ast::Expr::InList { lhs, rhs, .. } => {
             eval_at = eval_at.max(determine_where_to_eval_expr(lhs)?);
@@ -640,13 +686,20 @@ fn determine_where_to_eval_expr<'a>(predicate: &'a ast::Expr) -> Result<EvalAt>
             for arg in args.as_ref().unwrap_or(&vec![]).iter() {
                 eval_at = eval_at.max(determine_where_to_eval_expr(arg)?);
             }
+            // e.g. random() returns something else for every row
+            if !expr_is_deterministic(predicate) {
+                eval_at = eval_at.max(EvalAt::Loop(0));
+            }
         }
         Expr::FunctionCallStar { .. } => {}
         Expr::InSelect { .. } => {
             todo!("in select not supported yet")
         }
-        Expr::InTable { .. } => {
-            todo!("in table not supported yet")
+        Expr::InTable { lhs, args, .. } => {
+            eval_at = eval_at.max(determine_where_to_eval_expr(lhs)?);
+            for arg in args.iter().flatten() {
+                eval_at = eval_at.max(determine_where_to_eval_expr(arg)?);
+            }
         }
         Expr::IsNull(expr) => {
             eval_at = eval_at.max(determine_where_to_eval_expr(expr)?);
@@ -850,6 +903,87 @@ fn parse_join<'a>(
     Ok(())
 }
 
+/// Binds the ORDER BY clause of an UPDATE or DELETE to the table it changes.
+pub fn parse_order_by(
+    order_by: Option<&[ast::SortedColumn]>,
+    table_references: &[TableReference],
+) -> Result<Option<Vec<(Expr, Direction)>>> {
+    order_by
+        .map(|order_by| {
+            order_by
+                .iter()
+                .map(|column| {
+                    let mut expr = column.expr.clone();
+                    bind_column_references(&mut expr, table_references, None)?;
+                    Ok(order_by_term_keys(
+                        expr,
+                        column.order,
+                        column.nulls,
+                        table_references,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()
+                .map(|keys| keys.into_iter().flatten().collect())
+        })
+        .transpose()
+}
+
+/// Returns the sort keys of a bound ORDER BY term. NULLs come first in ascending order and last
+/// in descending order, so NULLS LAST on an ascending term, or NULLS FIRST on a descending one,
+/// sorts by `expr IS NULL` before sorting by the term itself.
+pub fn order_by_term_keys(
+    expr: Expr,
+    order: Option<ast::SortOrder>,
+    nulls: Option<ast::NullsOrder>,
+    table_references: &[TableReference],
+) -> Vec<(Expr, Direction)> {
+    let direction = match order {
+        Some(ast::SortOrder::Desc) => Direction::Descending,
+        _ => Direction::Ascending,
+    };
+    let nulls_out_of_default_place = matches!(
+        (direction, nulls),
+        (Direction::Ascending, Some(ast::NullsOrder::Last))
+            | (Direction::Descending, Some(ast::NullsOrder::First))
+    );
+    if !nulls_out_of_default_place || !expr_can_be_null(&expr, table_references) {
+        return vec![(expr, direction)];
+    }
+    let mut inner = &expr;
+    while let Expr::Collate(expr, _) = inner {
+        inner = expr;
+    }
+    vec![
+        (Expr::IsNull(Box::new(inner.clone())), direction),
+        (expr, direction),
+    ]
+}
+
+/// Whether a bound expression may evaluate to NULL. Only rowids and NOT NULL columns are known
+/// not to, which keeps an ORDER BY on them satisfiable by the table or an index.
+fn expr_can_be_null(expr: &Expr, table_references: &[TableReference]) -> bool {
+    let is_outer = |table: usize| {
+        table_references[table]
+            .join_info
+            .as_ref()
+            .is_some_and(|join_info| join_info.outer)
+    };
+    match expr {
+        Expr::Collate(expr, _) => expr_can_be_null(expr, table_references),
+        Expr::RowId { table, .. } => is_outer(*table),
+        Expr::Column {
+            table,
+            column,
+            is_rowid_alias,
+            ..
+        } => {
+            let column = &table_references[*table].columns()[*column];
+            is_outer(*table) || !(*is_rowid_alias || column.notnull)
+        }
+        _ => true,
+    }
+}
+
 pub fn parse_limit(limit: &Limit) -> Result<(Option<isize>, Option<isize>)> {
     let offset_val = match &limit.offset {
         Some(offset_expr) => match offset_expr {
@@ -895,9 +1029,34 @@ pub fn break_predicate_at_and_boundaries(predicate: Expr, out_predicates: &mut V
             break_predicate_at_and_boundaries(*left, out_predicates);
             break_predicate_at_and_boundaries(*right, out_predicates);
         }
-        _ => {
-            out_predicates.push(predicate);
+        _ => match planner_hint_argument(&predicate) {
+            Some(arg) => break_predicate_at_and_boundaries(arg.clone(), out_predicates),
+            None => out_predicates.push(predicate),
+        },
+    }
+}
+
+/// Returns X of a likely(X), unlikely(X) or likelihood(X, P) predicate. The hints only tell how
+/// often X is true, which the planner doesn't use for costing, so X is planned in their place.
+fn planner_hint_argument(expr: &Expr) -> Option<&Expr> {
+    let Expr::FunctionCall {
+        name,
+        args: Some(args),
+        ..
+    } = expr
+    else {
+        return None;
+    };
+    match Func::resolve_function(normalize_ident(name.0.as_str()).as_str(), args.len()) {
+        Ok(Func::Scalar(ScalarFunc::Likely | ScalarFunc::Unlikely)) if args.len() == 1 => {
+            Some(&args[0])
+        }
+        Ok(Func::Scalar(ScalarFunc::Likelihood))
+            if args.len() == 2 && likelihood_probability(&args[1]).is_some() =>
+        {
+            Some(&args[0])
         }
+        _ => None,
     }
 }
 
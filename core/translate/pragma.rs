@@ -3,37 +3,26 @@
 
 use limbo_sqlite3_parser::ast;
 use limbo_sqlite3_parser::ast::PragmaName;
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 use std::sync::Arc;
 
 use crate::fast_lock::SpinLock;
-use crate::schema::Schema;
+use crate::function::{builtin_functions, ExtFunc};
+use crate::functions::datetime::instant_to_date_time;
+use crate::introspection;
+use crate::schema::{BTreeTable, Index, Schema};
+use crate::storage::integrity::{IndexCheck, IntegrityCheck};
+use crate::storage::pager::TempStore;
 use crate::storage::sqlite3_ondisk::{DatabaseHeader, MIN_PAGE_CACHE_SIZE};
-use crate::storage::wal::CheckpointMode;
-use crate::util::normalize_ident;
+use crate::storage::wal::{CheckpointMode, LockingMode, SyncMode};
+use crate::translate::analyze::{analyzable_tables, translate_analyze_tables};
+use crate::util::{normalize_ident, PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX};
 use crate::vdbe::builder::{ProgramBuilder, ProgramBuilderOpts, QueryMode};
 use crate::vdbe::insn::{Cookie, Insn};
-use crate::vdbe::BranchOffset;
-use crate::{bail_parse_error, Pager};
+use crate::{bail_parse_error, Connection, Pager};
 use std::str::FromStr;
 use strum::IntoEnumIterator;
 
-fn list_pragmas(
-    program: &mut ProgramBuilder,
-    init_label: BranchOffset,
-    start_offset: BranchOffset,
-) {
-    for x in PragmaName::iter() {
-        let register = program.emit_string8_new_reg(x.to_string());
-        program.emit_result_row(register, 1);
-    }
-
-    program.emit_halt();
-    program.resolve_label(init_label, program.offset());
-    program.emit_constant_insns();
-    program.emit_goto(start_offset);
-}
-
 pub fn translate_pragma(
     query_mode: QueryMode,
     schema: &Schema,
@@ -41,6 +30,7 @@ pub fn translate_pragma(
     body: Option<ast::PragmaBody>,
     database_header: Arc<SpinLock<DatabaseHeader>>,
     pager: Rc<Pager>,
+    connection: &Weak<Connection>,
 ) -> crate::Result<ProgramBuilder> {
     let mut program = ProgramBuilder::new(ProgramBuilderOpts {
         query_mode,
@@ -52,27 +42,46 @@ pub fn translate_pragma(
     let start_offset = program.offset();
     let mut write = false;
 
-    if name.name.0.to_lowercase() == "pragma_list" {
-        list_pragmas(&mut program, init_label, start_offset);
-        return Ok(program);
-    }
-
     let pragma = match PragmaName::from_str(&name.name.0) {
         Ok(pragma) => pragma,
         Err(_) => bail_parse_error!("Not a valid pragma name"),
     };
 
+    if pragma == PragmaName::Optimize {
+        return optimize(query_mode, schema, connection);
+    }
+
     match body {
         None => {
-            query_pragma(pragma, schema, None, database_header.clone(), &mut program)?;
+            query_pragma(
+                pragma,
+                schema,
+                None,
+                database_header.clone(),
+                pager,
+                connection,
+                &mut program,
+            )?;
         }
         Some(ast::PragmaBody::Equals(value)) => match pragma {
-            PragmaName::TableInfo => {
+            PragmaName::TableInfo
+            | PragmaName::TableList
+            | PragmaName::IndexList
+            | PragmaName::IndexInfo
+            | PragmaName::IndexXinfo
+            | PragmaName::FunctionList
+            | PragmaName::PragmaList
+            | PragmaName::LimboTableStats
+            | PragmaName::IntegrityCheck
+            | PragmaName::QuickCheck
+            | PragmaName::IndexCheck => {
                 query_pragma(
                     pragma,
                     schema,
                     Some(value),
                     database_header.clone(),
+                    pager,
+                    connection,
                     &mut program,
                 )?;
             }
@@ -84,17 +93,31 @@ pub fn translate_pragma(
                     value,
                     database_header.clone(),
                     pager,
+                    connection,
                     &mut program,
                 )?;
             }
         },
         Some(ast::PragmaBody::Call(value)) => match pragma {
-            PragmaName::TableInfo => {
+            PragmaName::TableInfo
+            | PragmaName::TableList
+            | PragmaName::IndexList
+            | PragmaName::IndexInfo
+            | PragmaName::IndexXinfo
+            | PragmaName::FunctionList
+            | PragmaName::PragmaList
+            | PragmaName::LimboTableStats
+            | PragmaName::WalCheckpoint
+            | PragmaName::IntegrityCheck
+            | PragmaName::QuickCheck
+            | PragmaName::IndexCheck => {
                 query_pragma(
                     pragma,
                     schema,
                     Some(value),
                     database_header.clone(),
+                    pager,
+                    connection,
                     &mut program,
                 )?;
             }
@@ -112,12 +135,47 @@ pub fn translate_pragma(
     Ok(program)
 }
 
+/// Analyzes the tables with indexes that were written since they were last analyzed by this
+/// connection, as their statistics may no longer match the data.
+fn optimize(
+    query_mode: QueryMode,
+    schema: &Schema,
+    connection: &Weak<Connection>,
+) -> crate::Result<ProgramBuilder> {
+    let Some(conn) = connection.upgrade() else {
+        bail_parse_error!("connection is closed");
+    };
+    let stale_tables = analyzable_tables(schema)
+        .into_iter()
+        .filter(|table| {
+            !schema.get_indices(&table.name).is_empty() && conn.table_writes(&table.name) > 0
+        })
+        .collect::<Vec<_>>();
+    if stale_tables.is_empty() {
+        let mut program = ProgramBuilder::new(ProgramBuilderOpts {
+            query_mode,
+            num_cursors: 0,
+            approx_num_insns: 4,
+            approx_num_labels: 0,
+        });
+        let init_label = program.emit_init();
+        let start_offset = program.offset();
+        program.emit_halt();
+        program.resolve_label(init_label, program.offset());
+        program.emit_constant_insns();
+        program.emit_goto(start_offset);
+        return Ok(program);
+    }
+    translate_analyze_tables(query_mode, schema, &stale_tables, connection)
+}
+
 fn update_pragma(
     pragma: PragmaName,
     schema: &Schema,
     value: ast::Expr,
     header: Arc<SpinLock<DatabaseHeader>>,
     pager: Rc<Pager>,
+    connection: &Weak<Connection>,
     program: &mut ProgramBuilder,
 ) -> crate::Result<()> {
     match pragma {
@@ -138,23 +196,181 @@ fn update_pragma(
             Ok(())
         }
         PragmaName::JournalMode => {
-            query_pragma(PragmaName::JournalMode, schema, None, header, program)?;
+            query_pragma(
+                PragmaName::JournalMode,
+                schema,
+                None,
+                header,
+                pager,
+                connection,
+                program,
+            )?;
+            Ok(())
+        }
+        PragmaName::JournalSizeLimit => {
+            let limit = match value {
+                ast::Expr::Literal(ast::Literal::Numeric(numeric_value)) => {
+                    numeric_value.parse::<i64>()?
+                }
+                ast::Expr::Unary(ast::UnaryOperator::Negative, expr) => match *expr {
+                    ast::Expr::Literal(ast::Literal::Numeric(numeric_value)) => {
+                        -numeric_value.parse::<i64>()?
+                    }
+                    _ => bail_parse_error!("Not a valid value"),
+                },
+                _ => bail_parse_error!("Not a valid value"),
+            };
+            // Like in SQLite, any negative value removes the limit.
+            pager.set_journal_size_limit(limit.max(-1));
+            query_pragma(
+                PragmaName::JournalSizeLimit,
+                schema,
+                None,
+                header,
+                pager,
+                connection,
+                program,
+            )?;
+            Ok(())
+        }
+        PragmaName::AutomaticIndex => {
+            let enabled = pragma_bool_value(&value)?;
+            if let Some(conn) = connection.upgrade() {
+                conn.set_automatic_index(enabled);
+            }
+            Ok(())
+        }
+        PragmaName::VdbeTrace => {
+            let enabled = pragma_bool_value(&value)?;
+            if let Some(conn) = connection.upgrade() {
+                conn.set_vdbe_trace(enabled);
+            }
             Ok(())
         }
         PragmaName::LegacyFileFormat => Ok(()),
+        PragmaName::LockingMode => {
+            let mode = match value {
+                ast::Expr::Id(ast::Id(mode)) | ast::Expr::Name(ast::Name(mode)) => {
+                    match normalize_ident(&mode).as_str() {
+                        "normal" => LockingMode::Normal,
+                        "exclusive" => LockingMode::Exclusive,
+                        _ => bail_parse_error!("Not a valid locking mode: {}", mode),
+                    }
+                }
+                _ => bail_parse_error!("Not a valid locking mode"),
+            };
+            pager.set_locking_mode(mode);
+            query_pragma(
+                PragmaName::LockingMode,
+                schema,
+                None,
+                header,
+                pager,
+                connection,
+                program,
+            )?;
+            Ok(())
+        }
+        PragmaName::Synchronous => {
+            let mode = match value {
+                ast::Expr::Id(ast::Id(mode)) | ast::Expr::Name(ast::Name(mode)) => {
+                    match normalize_ident(&mode).as_str() {
+                        "off" => SyncMode::Off,
+                        "normal" => SyncMode::Normal,
+                        // EXTRA only differs from FULL for rollback journals.
+                        "full" | "extra" => SyncMode::Full,
+                        _ => bail_parse_error!("Not a valid synchronous mode: {}", mode),
+                    }
+                }
+                ast::Expr::Literal(ast::Literal::Numeric(numeric_value)) => {
+                    match numeric_value.parse::<i64>()? {
+                        0 => SyncMode::Off,
+                        1 => SyncMode::Normal,
+                        2 | 3 => SyncMode::Full,
+                        _ => bail_parse_error!("Not a valid synchronous mode: {}", numeric_value),
+                    }
+                }
+                _ => bail_parse_error!("Not a valid synchronous mode"),
+            };
+            pager.set_sync_mode(mode);
+            Ok(())
+        }
+        PragmaName::TempStore => {
+            let temp_store = match value {
+                ast::Expr::Id(ast::Id(mode)) | ast::Expr::Name(ast::Name(mode)) => {
+                    match normalize_ident(&mode).as_str() {
+                        "default" => TempStore::Default,
+                        "file" => TempStore::File,
+                        "memory" => TempStore::Memory,
+                        _ => bail_parse_error!("Not a valid temp_store mode: {}", mode),
+                    }
+                }
+                ast::Expr::Literal(ast::Literal::Numeric(numeric_value)) => {
+                    match numeric_value.parse::<i64>()? {
+                        0 => TempStore::Default,
+                        1 => TempStore::File,
+                        2 => TempStore::Memory,
+                        _ => bail_parse_error!("Not a valid temp_store mode: {}", numeric_value),
+                    }
+                }
+                _ => bail_parse_error!("Not a valid temp_store mode"),
+            };
+            if let Some(conn) = connection.upgrade() {
+                conn.set_temp_store(temp_store);
+            }
+            Ok(())
+        }
         PragmaName::WalCheckpoint => {
-            query_pragma(PragmaName::WalCheckpoint, schema, None, header, program)?;
+            query_pragma(
+                PragmaName::WalCheckpoint,
+                schema,
+                None,
+                header,
+                pager,
+                connection,
+                program,
+            )?;
             Ok(())
         }
         PragmaName::PageCount => {
-            query_pragma(PragmaName::PageCount, schema, None, header, program)?;
+            query_pragma(
+                PragmaName::PageCount,
+                schema,
+                None,
+                header,
+                pager,
+                connection,
+                program,
+            )?;
+            Ok(())
+        }
+        PragmaName::UserVersion | PragmaName::ApplicationId => {
+            let cookie = match pragma {
+                PragmaName::UserVersion => Cookie::UserVersion,
+                _ => Cookie::ApplicationId,
+            };
+            program.emit_insn(Insn::SetCookie {
+                db: 0,
+                cookie,
+                value: pragma_int32_value(&value)?,
+            });
             Ok(())
         }
-        PragmaName::UserVersion => {
-            // TODO: Implement updating user_version
-            todo!("updating user_version not yet implemented")
+        PragmaName::SchemaVersion | PragmaName::DataVersion | PragmaName::LimboHistory => {
+            bail_parse_error!("{} is read-only", pragma)
         }
-        PragmaName::TableInfo => {
+        PragmaName::Optimize => unreachable!("PRAGMA optimize is translated on its own"),
+        PragmaName::IntegrityCheck | PragmaName::QuickCheck | PragmaName::IndexCheck => {
+            unreachable!("integrity checks only take an argument to query with")
+        }
+        PragmaName::TableInfo
+        | PragmaName::TableList
+        | PragmaName::IndexList
+        | PragmaName::IndexInfo
+        | PragmaName::IndexXinfo
+        | PragmaName::FunctionList
+        | PragmaName::PragmaList
+        | PragmaName::LimboTableStats => {
             // because we need control over the write parameter for the transaction,
             // this should be unreachable. We have to force-call query_pragma before
             // getting here
@@ -168,6 +384,8 @@ fn query_pragma(
     schema: &Schema,
     value: Option<ast::Expr>,
     database_header: Arc<SpinLock<DatabaseHeader>>,
+    pager: Rc<Pager>,
+    connection: &Weak<Connection>,
     program: &mut ProgramBuilder,
 ) -> crate::Result<()> {
     let register = program.alloc_register();
@@ -183,19 +401,72 @@ fn query_pragma(
             program.emit_string8("wal".into(), register);
             program.emit_result_row(register, 1);
         }
+        PragmaName::JournalSizeLimit => {
+            program.emit_int(pager.journal_size_limit(), register);
+            program.emit_result_row(register, 1);
+        }
+        PragmaName::AutomaticIndex => {
+            let enabled = connection
+                .upgrade()
+                .is_some_and(|conn| conn.automatic_index());
+            program.emit_int(enabled as i64, register);
+            program.emit_result_row(register, 1);
+        }
+        PragmaName::VdbeTrace => {
+            let enabled = connection.upgrade().is_some_and(|conn| conn.vdbe_trace());
+            program.emit_int(enabled as i64, register);
+            program.emit_result_row(register, 1);
+        }
         PragmaName::LegacyFileFormat => {}
+        PragmaName::LockingMode => {
+            program.emit_string8(pager.locking_mode().as_str().into(), register);
+            program.emit_result_row(register, 1);
+        }
+        PragmaName::Synchronous => {
+            program.emit_int(pager.sync_mode().as_int(), register);
+            program.emit_result_row(register, 1);
+        }
+        PragmaName::TempStore => {
+            let temp_store = connection
+                .upgrade()
+                .map_or(TempStore::Default, |conn| conn.temp_store());
+            program.emit_int(temp_store.as_int(), register);
+            program.emit_result_row(register, 1);
+        }
         PragmaName::WalCheckpoint => {
+            let checkpoint_mode = match value {
+                None => CheckpointMode::Passive,
+                Some(ast::Expr::Id(ast::Id(mode)) | ast::Expr::Name(ast::Name(mode))) => {
+                    match normalize_ident(&mode).as_str() {
+                        "passive" => CheckpointMode::Passive,
+                        "full" => CheckpointMode::Full,
+                        "restart" => CheckpointMode::Restart,
+                        "truncate" => CheckpointMode::Truncate,
+                        _ => bail_parse_error!("Not a valid checkpoint mode: {}", mode),
+                    }
+                }
+                Some(_) => bail_parse_error!("Not a valid checkpoint mode"),
+            };
             // Checkpoint uses 3 registers: P1, P2, P3. Ref Insn::Checkpoint for more info.
             // Allocate two more here as one was allocated at the top.
             program.alloc_register();
             program.alloc_register();
             program.emit_insn(Insn::Checkpoint {
                 database: 0,
-                checkpoint_mode: CheckpointMode::Passive,
+                checkpoint_mode,
                 dest: register,
             });
             program.emit_result_row(register, 3);
         }
+        PragmaName::Optimize => unreachable!("PRAGMA optimize is translated on its own"),
+        PragmaName::IntegrityCheck | PragmaName::QuickCheck | PragmaName::IndexCheck => {
+            let check = integrity_check(schema, value, &pragma)?;
+            program.emit_insn(Insn::IntegrityCk {
+                check,
+                message_register: register,
+            });
+            program.emit_result_row(register, 1);
+        }
         PragmaName::PageCount => {
             program.emit_insn(Insn::PageCount {
                 db: 0,
@@ -248,12 +519,201 @@ fn query_pragma(
                 }
             }
         }
-        PragmaName::UserVersion => {
+        PragmaName::TableList => {
+            let name = value.as_ref().and_then(pragma_name_value);
+            let base_reg = register;
+            program.alloc_registers(5);
+            for table in introspection::tables(schema) {
+                if name.as_ref().is_some_and(|name| *name != table.name) {
+                    continue;
+                }
+                let ncol = schema
+                    .get_table(&table.name)
+                    .map_or(0, |table| table.columns().len());
+                let ty = if table.is_virtual { "virtual" } else { "table" };
+                program.emit_string8("main".into(), base_reg);
+                program.emit_string8(table.name, base_reg + 1);
+                program.emit_string8(ty.into(), base_reg + 2);
+                program.emit_int(ncol as i64, base_reg + 3);
+                program.emit_bool(!table.is_virtual && !table.has_rowid, base_reg + 4);
+                // strict
+                program.emit_bool(false, base_reg + 5);
+                program.emit_result_row(base_reg, 6);
+            }
+        }
+        PragmaName::IndexList => {
+            let table = value
+                .as_ref()
+                .and_then(pragma_name_value)
+                .and_then(|name| schema.get_btree_table(&name));
+            let base_reg = register;
+            program.alloc_registers(4);
+            if let Some(table) = table {
+                // Like SQLite, the most recently created index comes first and the ones created
+                // along with the table come last, whatever order the schema was loaded in.
+                let (automatic, created): (Vec<_>, Vec<_>) = schema
+                    .get_indices(&table.name)
+                    .iter()
+                    .filter(|index| !index.ephemeral)
+                    .partition(|index| {
+                        index
+                            .name
+                            .starts_with(PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX)
+                    });
+                let indexes = created.into_iter().rev().chain(automatic.into_iter().rev());
+                for (seq, index) in indexes.enumerate() {
+                    program.emit_int(seq as i64, base_reg);
+                    program.emit_string8(index.name.clone(), base_reg + 1);
+                    program.emit_bool(index.unique, base_reg + 2);
+                    program.emit_string8(index_origin(&table, index).into(), base_reg + 3);
+                    // partial
+                    program.emit_bool(false, base_reg + 4);
+                    program.emit_result_row(base_reg, 5);
+                }
+            }
+        }
+        PragmaName::IndexInfo | PragmaName::IndexXinfo => {
+            let extended = pragma == PragmaName::IndexXinfo;
+            let index = value
+                .as_ref()
+                .and_then(pragma_name_value)
+                .and_then(|name| find_index(schema, &name));
+            let base_reg = register;
+            program.alloc_registers(5);
+            let num_cols = if extended { 6 } else { 3 };
+            if let Some((table, index)) = index {
+                let mut columns: Vec<(Option<usize>, Option<String>, bool, bool)> = index
+                    .columns
+                    .iter()
+                    .map(|column| {
+                        let pos = table.get_column(&column.name).map(|(pos, _)| pos);
+                        let desc = column.order == ast::SortOrder::Desc;
+                        (pos, Some(column.name.clone()), desc, true)
+                    })
+                    .collect();
+                if extended {
+                    // The columns stored after the key to find the row in the table.
+                    if table.has_rowid {
+                        columns.push((None, None, false, false));
+                    } else {
+                        for name in &table.primary_key_column_names {
+                            let name = normalize_ident(name);
+                            if index.columns.iter().any(|column| column.name == name) {
+                                continue;
+                            }
+                            let pos = table.get_column(&name).map(|(pos, _)| pos);
+                            columns.push((pos, Some(name), false, false));
+                        }
+                    }
+                }
+                for (seqno, (pos, name, desc, key)) in columns.into_iter().enumerate() {
+                    program.emit_int(seqno as i64, base_reg);
+                    program.emit_int(pos.map_or(-1, |pos| pos as i64), base_reg + 1);
+                    match name {
+                        Some(name) => program.emit_string8(name, base_reg + 2),
+                        None => program.emit_null(base_reg + 2, None),
+                    }
+                    if extended {
+                        program.emit_bool(desc, base_reg + 3);
+                        program.emit_string8("BINARY".into(), base_reg + 4);
+                        program.emit_bool(key, base_reg + 5);
+                    }
+                    program.emit_result_row(base_reg, num_cols);
+                }
+            }
+        }
+        PragmaName::FunctionList => {
+            let mut functions: Vec<(String, bool, bool, i64)> = builtin_functions()
+                .map(|func| {
+                    (
+                        func.name.to_string(),
+                        true,
+                        func.aggregate,
+                        func.narg as i64,
+                    )
+                })
+                .collect();
+            if let Some(conn) = connection.upgrade() {
+                for (name, func) in conn.syms.borrow().functions.iter() {
+                    let (aggregate, narg) = match func.func {
+                        ExtFunc::Scalar(_) => (false, -1),
+                        ExtFunc::Aggregate { argc, .. } => (true, argc as i64),
+                        #[cfg(feature = "wasm_ext")]
+                        ExtFunc::Wasm(_) => (false, -1),
+                    };
+                    functions.push((name.clone(), false, aggregate, narg));
+                }
+            }
+            functions.sort();
+            let base_reg = register;
+            program.alloc_registers(5);
+            for (name, builtin, aggregate, narg) in functions {
+                program.emit_string8(name, base_reg);
+                program.emit_bool(builtin, base_reg + 1);
+                program.emit_string8(if aggregate { "a" } else { "s" }.into(), base_reg + 2);
+                program.emit_string8("utf8".into(), base_reg + 3);
+                program.emit_int(narg, base_reg + 4);
+                // flags
+                program.emit_int(0, base_reg + 5);
+                program.emit_result_row(base_reg, 6);
+            }
+        }
+        PragmaName::LimboHistory => {
+            program.alloc_register();
+            for commit in pager.wal_history() {
+                program.emit_int(commit.frame as i64, register);
+                match commit.committed_at {
+                    Some(committed_at) => program.emit_string8(
+                        instant_to_date_time(committed_at)
+                            .format("%Y-%m-%d %H:%M:%S%.3f")
+                            .to_string(),
+                        register + 1,
+                    ),
+                    None => program.emit_null(register + 1, None),
+                }
+                program.emit_result_row(register, 2);
+            }
+        }
+        PragmaName::LimboTableStats => {
+            let name = value.as_ref().and_then(pragma_name_value);
+            let stats = connection
+                .upgrade()
+                .map_or_else(Vec::new, |conn| conn.table_stats());
+            let base_reg = register;
+            program.alloc_registers(4);
+            for (table, stats) in stats {
+                if name.as_ref().is_some_and(|name| *name != table) {
+                    continue;
+                }
+                program.emit_string8(table, base_reg);
+                program.emit_int(stats.rows_read as i64, base_reg + 1);
+                program.emit_int(stats.rows_written as i64, base_reg + 2);
+                program.emit_int(stats.seeks as i64, base_reg + 3);
+                program.emit_int(stats.full_scans as i64, base_reg + 4);
+                program.emit_result_row(base_reg, 5);
+            }
+        }
+        PragmaName::PragmaList => {
+            for pragma in PragmaName::iter() {
+                program.emit_string8(pragma.to_string(), register);
+                program.emit_result_row(register, 1);
+            }
+        }
+        PragmaName::UserVersion
+        | PragmaName::ApplicationId
+        | PragmaName::SchemaVersion
+        | PragmaName::DataVersion => {
+            let cookie = match pragma {
+                PragmaName::UserVersion => Cookie::UserVersion,
+                PragmaName::ApplicationId => Cookie::ApplicationId,
+                PragmaName::SchemaVersion => Cookie::SchemaVersion,
+                _ => Cookie::DataVersion,
+            };
             program.emit_transaction(false);
             program.emit_insn(Insn::ReadCookie {
                 db: 0,
                 dest: register,
-                cookie: Cookie::UserVersion,
+                cookie,
             });
             program.emit_result_row(register, 1);
         }
@@ -262,6 +722,195 @@ fn query_pragma(
     Ok(())
 }
 
+/// The name a pragma like `index_list` takes as its argument, which may be quoted.
+fn pragma_name_value(value: &ast::Expr) -> Option<String> {
+    match value {
+        ast::Expr::Id(ast::Id(name))
+        | ast::Expr::Name(ast::Name(name))
+        | ast::Expr::Literal(ast::Literal::String(name)) => {
+            Some(normalize_ident(name.trim_matches('\'')))
+        }
+        _ => None,
+    }
+}
+
+/// Finds an index of the schema by name, along with the table it belongs to.
+fn find_index(schema: &Schema, name: &str) -> Option<(Rc<BTreeTable>, Arc<Index>)> {
+    let index = schema
+        .indexes
+        .values()
+        .flatten()
+        .find(|index| !index.ephemeral && index.name == name)?;
+    let table = schema.get_btree_table(&index.table_name)?;
+    Some((table, index.clone()))
+}
+
+/// How an index came to be, as `PRAGMA index_list` reports it: "c" for `CREATE INDEX`, "pk" for
+/// the one a `PRIMARY KEY` creates and "u" for the ones `UNIQUE` constraints create.
+fn index_origin(table: &BTreeTable, index: &Index) -> &'static str {
+    if !index
+        .name
+        .starts_with(PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX)
+    {
+        return "c";
+    }
+    let is_primary_key = index.columns.len() == table.primary_key_column_names.len()
+        && index
+            .columns
+            .iter()
+            .zip(&table.primary_key_column_names)
+            .all(|(column, pk)| column.name == normalize_ident(pk));
+    if is_primary_key {
+        "pk"
+    } else {
+        "u"
+    }
+}
+
+/// Works out what an integrity check covers. Like in SQLite, the argument is either the maximum
+/// number of problems to report, 100 by default, or the name of the only table to check along
+/// with its indexes. Only checks of the whole database look for pages that are never used.
+/// `PRAGMA index_check` only walks the b-trees of indexes and their tables to compare them, and
+/// also takes the name of the only index to compare.
+fn integrity_check(
+    schema: &Schema,
+    value: Option<ast::Expr>,
+    pragma: &PragmaName,
+) -> crate::Result<IntegrityCheck> {
+    const DEFAULT_MAX_ERRORS: usize = 100;
+    let quick = *pragma == PragmaName::QuickCheck;
+    let indexes_only = *pragma == PragmaName::IndexCheck;
+    let table_name = value.as_ref().and_then(pragma_name_value);
+    let only_index = match &table_name {
+        Some(name) if indexes_only => find_index(schema, name),
+        _ => None,
+    };
+    let max_errors = match &value {
+        Some(value) if table_name.is_none() => match pragma_int32_value(value)? {
+            n if n > 0 => n as usize,
+            _ => DEFAULT_MAX_ERRORS,
+        },
+        _ => DEFAULT_MAX_ERRORS,
+    };
+    let tables: Vec<Rc<BTreeTable>> = match &table_name {
+        Some(_) if only_index.is_some() => vec![only_index.as_ref().unwrap().0.clone()],
+        Some(name) => {
+            let name = if name == "sqlite_master" {
+                "sqlite_schema"
+            } else {
+                name.as_str()
+            };
+            match schema.get_btree_table(name) {
+                Some(table) => vec![table],
+                None => bail_parse_error!("no such table: {}", name),
+            }
+        }
+        None => {
+            let mut tables: Vec<_> = schema
+                .tables
+                .values()
+                .filter_map(|table| table.btree())
+                .collect();
+            tables.sort_by_key(|table| table.root_page);
+            tables
+        }
+    };
+    let mut roots = Vec::new();
+    let mut indexes = Vec::new();
+    for table in &tables {
+        let table_indexes: Vec<_> = schema
+            .get_indices(&table.name)
+            .iter()
+            .filter(|index| !index.ephemeral)
+            .filter(|index| {
+                only_index
+                    .as_ref()
+                    .map_or(true, |(_, only)| only.name == index.name)
+            })
+            .collect();
+        if indexes_only && (table_indexes.is_empty() || !table.has_rowid) {
+            continue;
+        }
+        roots.push(table.root_page);
+        for index in table_indexes {
+            roots.push(index.root_page);
+            if quick || !table.has_rowid {
+                continue;
+            }
+            let columns = index
+                .columns
+                .iter()
+                .map(|column| {
+                    table
+                        .get_column(&column.name)
+                        .filter(|(_, column)| !column.is_rowid_alias)
+                        .map(|(pos, _)| pos)
+                })
+                .collect();
+            indexes.push(IndexCheck {
+                name: index.name.clone(),
+                root_page: index.root_page,
+                table_root_page: table.root_page,
+                columns,
+                unique: index.unique,
+            });
+        }
+    }
+    Ok(IntegrityCheck {
+        roots,
+        indexes,
+        whole_database: table_name.is_none() && !indexes_only,
+        max_errors,
+    })
+}
+
+/// Reads the value of a pragma that sets a 32-bit header field the way SQLite does: text is
+/// accepted, only the leading integer counts and values that don't fit become 0.
+fn pragma_int32_value(value: &ast::Expr) -> crate::Result<i32> {
+    let (negative, value) = match value {
+        ast::Expr::Unary(ast::UnaryOperator::Negative, expr) => (true, expr.as_ref()),
+        ast::Expr::Unary(ast::UnaryOperator::Positive, expr) => (false, expr.as_ref()),
+        value => (false, value),
+    };
+    let text = match value {
+        ast::Expr::Literal(ast::Literal::Numeric(text) | ast::Literal::String(text))
+        | ast::Expr::Id(ast::Id(text))
+        | ast::Expr::Name(ast::Name(text)) => text.trim_matches(|c| c == '\'' || c == '"'),
+        _ => bail_parse_error!("Not a valid value"),
+    };
+    let text = text.trim();
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(text) => (!negative, text),
+        None => (negative, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let value = if let Some(hex) = text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        // Hex values are the bits of the integer.
+        u32::from_str_radix(hex, 16).map(|v| v as i64).unwrap_or(0) as i32 as i64
+    } else {
+        let digits = text
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(text, |end| &text[..end]);
+        digits.parse::<i64>().unwrap_or(0)
+    };
+    let value = if negative { -value } else { value };
+    Ok(i32::try_from(value).unwrap_or(0))
+}
+
+/// Reads the value of a boolean pragma like SQLite: on, yes and true or any non-zero integer.
+fn pragma_bool_value(value: &ast::Expr) -> crate::Result<bool> {
+    let text = match value {
+        ast::Expr::Literal(ast::Literal::String(text) | ast::Literal::Keyword(text))
+        | ast::Expr::Id(ast::Id(text))
+        | ast::Expr::Name(ast::Name(text)) => text.trim_matches(|c| c == '\'' || c == '"'),
+        _ => return Ok(pragma_int32_value(value)? != 0),
+    };
+    Ok(match text.to_lowercase().as_str() {
+        "on" | "yes" | "true" => true,
+        "off" | "no" | "false" => false,
+        _ => pragma_int32_value(value)? != 0,
+    })
+}
+
 fn update_cache_size(value: i64, header: Arc<SpinLock<DatabaseHeader>>, pager: Rc<Pager>) {
     let mut cache_size_unformatted: i64 = value;
     let mut cache_size = if cache_size_unformatted < 0 {
@@ -1,6 +1,7 @@
 use std::fmt::Display;
 
 use crate::ast;
+use crate::matview::ViewStatement;
 use crate::schema::Schema;
 use crate::translate::ProgramBuilder;
 use crate::translate::ProgramBuilderOpts;
@@ -468,7 +469,7 @@ pub fn translate_create_virtual_table(
     let table_name_reg = program.emit_string8_new_reg(table_name.clone());
 
     let args_reg = if !args_vec.is_empty() {
-        let args_start = program.alloc_register();
+        let args_start = program.alloc_registers(args_vec.len());
 
         // Emit string8 instructions for each arg
         for (i, arg) in args_vec.iter().enumerate() {
@@ -530,6 +531,51 @@ pub fn translate_create_virtual_table(
     Ok(program)
 }
 
+/// Translates `CREATE`, `REFRESH` and `DROP MATERIALIZED VIEW`, which run as a single
+/// instruction, see [crate::matview].
+pub fn translate_materialized_view(
+    query_mode: QueryMode,
+    stmt: ast::Stmt,
+) -> Result<ProgramBuilder> {
+    let mut program = ProgramBuilder::new(ProgramBuilderOpts {
+        query_mode,
+        num_cursors: 0,
+        approx_num_insns: 3,
+        approx_num_labels: 0,
+    });
+    let init_label = program.emit_init();
+    let start_offset = program.offset();
+    program.emit_insn(Insn::MaterializedView {
+        statement: ViewStatement::from_stmt(stmt),
+    });
+    program.emit_halt();
+    program.resolve_label(init_label, program.offset());
+    program.emit_goto(start_offset);
+    Ok(program)
+}
+
+/// Translates a statement creating or dropping a vector index to a single instruction, see
+/// [crate::vector::index].
+#[cfg(feature = "vector")]
+pub fn translate_vector_index(query_mode: QueryMode, stmt: ast::Stmt) -> Result<ProgramBuilder> {
+    let mut program = ProgramBuilder::new(ProgramBuilderOpts {
+        query_mode,
+        num_cursors: 0,
+        approx_num_insns: 3,
+        approx_num_labels: 0,
+    });
+    let init_label = program.emit_init();
+    let start_offset = program.offset();
+    let sql = ast::Cmd::Stmt(stmt).to_string();
+    program.emit_insn(Insn::VectorIndex {
+        sql: sql.trim_end_matches(';').to_string(),
+    });
+    program.emit_halt();
+    program.resolve_label(init_label, program.offset());
+    program.emit_goto(start_offset);
+    Ok(program)
+}
+
 pub fn translate_drop_table(
     query_mode: QueryMode,
     tbl_name: ast::QualifiedName,
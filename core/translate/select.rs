@@ -2,11 +2,11 @@ use super::emitter::emit_program;
 use super::plan::{select_star, Operation, Search, SelectQueryType};
 use super::planner::Scope;
 use crate::function::{AggFunc, ExtFunc, Func};
-use crate::translate::optimizer::optimize_plan;
-use crate::translate::plan::{Aggregate, Direction, GroupBy, Plan, ResultSetColumn, SelectPlan};
+use crate::translate::optimizer::{optimize_plan, use_automatic_indexes};
+use crate::translate::plan::{Aggregate, GroupBy, Plan, ResultSetColumn, SelectPlan};
 use crate::translate::planner::{
-    bind_column_references, break_predicate_at_and_boundaries, parse_from, parse_limit,
-    parse_where, resolve_aggregates,
+    bind_column_references, break_predicate_at_and_boundaries, order_by_term_keys, parse_from,
+    parse_limit, parse_where, resolve_aggregates,
 };
 use crate::util::normalize_ident;
 use crate::vdbe::builder::{ProgramBuilderOpts, QueryMode};
@@ -19,13 +19,19 @@ pub fn translate_select(
     query_mode: QueryMode,
     schema: &Schema,
     select: ast::Select,
+    as_of: Option<ast::Expr>,
     syms: &SymbolTable,
+    automatic_index: bool,
 ) -> Result<ProgramBuilder> {
     let mut select_plan = prepare_select_plan(schema, select, syms, None)?;
     optimize_plan(&mut select_plan, schema)?;
-    let Plan::Select(ref select) = select_plan else {
+    if automatic_index {
+        use_automatic_indexes(&mut select_plan)?;
+    }
+    let Plan::Select(ref mut select) = select_plan else {
         panic!("select_plan is not a SelectPlan");
     };
+    select.as_of = as_of;
 
     let mut program = ProgramBuilder::new(ProgramBuilderOpts {
         query_mode,
@@ -62,8 +68,15 @@ pub fn prepare_select_plan<'a>(
             let with = select.with;
 
             // Parse the FROM clause into a vec of TableReferences. Fold all the join conditions expressions into the WHERE clause.
-            let table_references =
-                parse_from(schema, from, syms, with, &mut where_predicates, outer_scope)?;
+            let table_references = parse_from(
+                schema,
+                from,
+                syms,
+                with,
+                &mut where_predicates,
+                outer_scope,
+                vec![],
+            )?;
 
             // Preallocate space for the result columns
             let result_columns = Vec::with_capacity(
@@ -96,7 +109,10 @@ pub fn prepare_select_plan<'a>(
                 limit: None,
                 offset: None,
                 contains_constant_false_condition: false,
+                min_max_early_out: false,
+                simple_count: false,
                 query_type: SelectQueryType::TopLevel,
+                as_of: None,
             };
 
             let mut aggregate_expressions = Vec::new();
@@ -184,8 +200,11 @@ pub fn prepare_select_plan<'a>(
                                         });
                                     }
                                     Ok(_) => {
-                                        let contains_aggregates =
-                                            resolve_aggregates(expr, &mut aggregate_expressions);
+                                        let contains_aggregates = resolve_aggregates(
+                                            expr,
+                                            &mut aggregate_expressions,
+                                            syms,
+                                        );
                                         plan.result_columns.push(ResultSetColumn {
                                             alias: maybe_alias.as_ref().map(|alias| match alias {
                                                 ast::As::Elided(alias) => alias.0.clone(),
@@ -198,10 +217,12 @@ pub fn prepare_select_plan<'a>(
                                     Err(e) => {
                                         if let Some(f) = syms.resolve_function(&name.0, args_count)
                                         {
-                                            if let ExtFunc::Scalar(_) = f.as_ref().func {
+                                            if !matches!(f.as_ref().func, ExtFunc::Aggregate { .. })
+                                            {
                                                 let contains_aggregates = resolve_aggregates(
                                                     expr,
                                                     &mut aggregate_expressions,
+                                                    syms,
                                                 );
                                                 plan.result_columns.push(ResultSetColumn {
                                                     alias: maybe_alias.as_ref().map(|alias| {
@@ -275,7 +296,7 @@ pub fn prepare_select_plan<'a>(
                             }
                             expr => {
                                 let contains_aggregates =
-                                    resolve_aggregates(expr, &mut aggregate_expressions);
+                                    resolve_aggregates(expr, &mut aggregate_expressions, syms);
                                 plan.result_columns.push(ResultSetColumn {
                                     alias: maybe_alias.as_ref().map(|alias| match alias {
                                         ast::As::Elided(alias) => alias.0.clone(),
@@ -320,7 +341,7 @@ pub fn prepare_select_plan<'a>(
                                 Some(&plan.result_columns),
                             )?;
                             let contains_aggregates =
-                                resolve_aggregates(expr, &mut aggregate_expressions);
+                                resolve_aggregates(expr, &mut aggregate_expressions, syms);
                             if !contains_aggregates {
                                 // TODO: sqlite allows HAVING clauses with non aggregate expressions like
                                 // HAVING id = 5. We should support this too eventually (I guess).
@@ -355,14 +376,13 @@ pub fn prepare_select_plan<'a>(
                         &plan.table_references,
                         Some(&plan.result_columns),
                     )?;
-                    resolve_aggregates(&o.expr, &mut plan.aggregates);
+                    resolve_aggregates(&o.expr, &mut plan.aggregates, syms);
 
-                    key.push((
+                    key.extend(order_by_term_keys(
                         o.expr,
-                        o.order.map_or(Direction::Ascending, |o| match o {
-                            ast::SortOrder::Asc => Direction::Ascending,
-                            ast::SortOrder::Desc => Direction::Descending,
-                        }),
+                        o.order,
+                        o.nulls,
+                        &plan.table_references,
                     ));
                 }
                 plan.order_by = Some(key);
@@ -386,6 +406,9 @@ fn replace_column_number_with_copy_of_column_expr(
     order_by_or_group_by_expr: &mut ast::Expr,
     columns: &[ResultSetColumn],
 ) -> Result<()> {
+    if let ast::Expr::Collate(expr, _) = order_by_or_group_by_expr {
+        return replace_column_number_with_copy_of_column_expr(expr, columns);
+    }
     if let ast::Expr::Literal(ast::Literal::Numeric(num)) = order_by_or_group_by_expr {
         let column_number = num.parse::<usize>()?;
         if column_number == 0 {
@@ -413,6 +436,8 @@ fn count_plan_required_cursors(plan: &SelectPlan) -> usize {
             Operation::Search(search) => match search {
                 Search::RowidEq { .. } | Search::RowidSearch { .. } => 1,
                 Search::IndexSearch { .. } => 2, // btree cursor and index cursor
+                #[cfg(feature = "vector")]
+                Search::VectorMatch { .. } => 1,
             },
             Operation::Subquery { plan, .. } => count_plan_required_cursors(plan),
         })
@@ -1,5 +1,12 @@
+use std::{collections::HashMap, rc::Rc};
+
 use crate::{
-    vdbe::{builder::ProgramBuilder, insn::Insn},
+    schema::BTreeTable,
+    vdbe::{
+        builder::{CursorType, ProgramBuilder},
+        insn::Insn,
+        BranchOffset, CursorID,
+    },
     Result,
 };
 
@@ -16,23 +23,109 @@ pub fn emit_subqueries(
     t_ctx: &mut TranslateCtx,
     tables: &mut [TableReference],
 ) -> Result<()> {
+    // CTEs that were already materialized, so that later references to the same CTE
+    // can share its transient table instead of running the CTE again.
+    let mut materialized_ctes: HashMap<String, CursorID> = HashMap::new();
     for table in tables.iter_mut() {
+        let columns = table.columns().to_vec();
         if let Operation::Subquery {
             plan,
             result_columns_start_reg,
+            cte_name,
+            materialized,
+            ..
         } = &mut table.op
         {
-            // Emit the subquery and get the start register of the result columns.
+            if !*materialized {
+                // Emit the subquery and get the start register of the result columns.
+                let result_columns_start = emit_subquery(program, plan, t_ctx)?;
+                // Set the start register of the subquery's result columns.
+                // This is done so that translate_expr() can read the result columns of the subquery,
+                // as if it were reading from a regular table.
+                *result_columns_start_reg = result_columns_start;
+                continue;
+            }
+            let cursor_id = program.alloc_cursor_id(
+                Some(table.identifier.clone()),
+                CursorType::BTreeTable(Rc::new(BTreeTable::ephemeral(&table.identifier, columns))),
+            );
+            if let Some(original_cursor_id) = cte_name
+                .as_ref()
+                .and_then(|name| materialized_ctes.get(name))
+            {
+                program.emit_insn(Insn::OpenDup {
+                    new_cursor_id: cursor_id,
+                    original_cursor_id: *original_cursor_id,
+                });
+                continue;
+            }
             let result_columns_start = emit_subquery(program, plan, t_ctx)?;
-            // Set the start register of the subquery's result columns.
-            // This is done so that translate_expr() can read the result columns of the subquery,
-            // as if it were reading from a regular table.
             *result_columns_start_reg = result_columns_start;
+            emit_materialization(program, plan, cursor_id, result_columns_start);
+            if let Some(name) = cte_name {
+                materialized_ctes.insert(name.clone(), cursor_id);
+            }
         }
     }
     Ok(())
 }
 
+/// Run a subquery coroutine to completion, storing every row it yields in a transient table
+/// that the main query loop then scans like a regular table.
+fn emit_materialization(
+    program: &mut ProgramBuilder,
+    plan: &SelectPlan,
+    cursor_id: CursorID,
+    result_columns_start: usize,
+) {
+    let SelectQueryType::Subquery {
+        yield_reg,
+        coroutine_implementation_start,
+    } = plan.query_type
+    else {
+        unreachable!("emit_materialization called on non-subquery");
+    };
+    program.emit_insn(Insn::OpenEphemeral {
+        cursor_id,
+        is_table: true,
+    });
+    program.emit_insn(Insn::InitCoroutine {
+        yield_reg,
+        jump_on_definition: BranchOffset::Offset(0),
+        start_offset: coroutine_implementation_start,
+    });
+    let loop_start = program.allocate_label();
+    let loop_end = program.allocate_label();
+    program.resolve_label(loop_start, program.offset());
+    program.emit_insn(Insn::Yield {
+        yield_reg,
+        end_offset: loop_end,
+    });
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: result_columns_start,
+        count: plan.result_columns.len(),
+        dest_reg: record_reg,
+    });
+    let rowid_reg = program.alloc_register();
+    program.emit_insn(Insn::NewRowid {
+        cursor: cursor_id,
+        rowid_reg,
+        prev_largest_reg: 0,
+    });
+    program.emit_insn(Insn::InsertAsync {
+        cursor: cursor_id,
+        key_reg: rowid_reg,
+        record_reg,
+        flag: 0,
+    });
+    program.emit_insn(Insn::InsertAwait { cursor_id });
+    program.emit_insn(Insn::Goto {
+        target_pc: loop_start,
+    });
+    program.resolve_label(loop_end, program.offset());
+}
+
 /// Emit a subquery and return the start register of the result columns.
 /// This is done by emitting a coroutine that stores the result columns in sequential registers.
 /// Each subquery in a FROM clause has its own separate SelectPlan which is wrapped in a coroutine.
@@ -6,14 +6,14 @@ use crate::{
     vdbe::builder::{ProgramBuilder, ProgramBuilderOpts, QueryMode},
     SymbolTable,
 };
-use limbo_sqlite3_parser::ast::{self, Expr, ResultColumn, SortOrder, Update};
+use limbo_sqlite3_parser::ast::{self, Expr, ResultColumn, Update};
 
 use super::emitter::emit_program;
 use super::optimizer::optimize_plan;
-use super::plan::{
-    Direction, IterationDirection, Plan, ResultSetColumn, TableReference, UpdatePlan,
+use super::plan::{Plan, ResultSetColumn, TableReference, UpdatePlan};
+use super::planner::{
+    bind_column_references, parse_from, parse_limit, parse_order_by, parse_where,
 };
-use super::planner::{bind_column_references, parse_limit, parse_where};
 
 /*
 * Update is simple. By default we scan the table, and for each row, we check the WHERE
@@ -50,7 +50,7 @@ pub fn translate_update(
     body: &mut Update,
     syms: &SymbolTable,
 ) -> crate::Result<ProgramBuilder> {
-    let mut plan = prepare_update_plan(schema, body)?;
+    let mut plan = prepare_update_plan(schema, body, syms)?;
     optimize_plan(&mut plan, schema)?;
     // TODO: freestyling these numbers
     let mut program = ProgramBuilder::new(ProgramBuilderOpts {
@@ -63,7 +63,11 @@ pub fn translate_update(
     Ok(program)
 }
 
-pub fn prepare_update_plan(schema: &Schema, body: &mut Update) -> crate::Result<Plan> {
+pub fn prepare_update_plan(
+    schema: &Schema,
+    body: &mut Update,
+    syms: &SymbolTable,
+) -> crate::Result<Plan> {
     let table_name = &body.tbl_name.name;
     let table = match schema.get_table(table_name.0.as_str()) {
         Some(table) => table,
@@ -72,20 +76,30 @@ pub fn prepare_update_plan(schema: &Schema, body: &mut Update) -> crate::Result<
     let Some(btree_table) = table.btree() else {
         bail_parse_error!("Error: {} is not a btree table", table_name);
     };
-    let iter_dir: Option<IterationDirection> = body.order_by.as_ref().and_then(|order_by| {
-        order_by.first().and_then(|ob| {
-            ob.order.map(|o| match o {
-                SortOrder::Asc => IterationDirection::Forwards,
-                SortOrder::Desc => IterationDirection::Backwards,
-            })
-        })
-    });
-    let table_references = vec![TableReference {
+    let target = TableReference {
         table: Table::BTree(btree_table.clone()),
         identifier: table_name.0.clone(),
-        op: Operation::Scan { iter_dir },
+        op: Operation::Scan {
+            iter_dir: None,
+            index: None,
+        },
         join_info: None,
-    }];
+    };
+    // The tables of UPDATE ... FROM are joined to the updated table, which is the first one,
+    // and the join constraints of the FROM clause go into the WHERE clause.
+    let mut where_clause = vec![];
+    if body.from.is_some() && body.order_by.is_some() {
+        bail_parse_error!("ORDER BY is not supported on UPDATE ... FROM");
+    }
+    let table_references = parse_from(
+        schema,
+        body.from.take(),
+        syms,
+        body.with.take(),
+        &mut where_clause,
+        None,
+        vec![target],
+    )?;
     let set_clauses = body
         .sets
         .iter_mut()
@@ -113,7 +127,6 @@ pub fn prepare_update_plan(schema: &Schema, body: &mut Update) -> crate::Result<
         })
         .collect::<Result<Vec<(usize, Expr)>, crate::LimboError>>()?;
 
-    let mut where_clause = vec![];
     let mut result_columns = vec![];
     if let Some(returning) = &mut body.returning {
         for rc in returning.iter_mut() {
@@ -135,22 +148,12 @@ pub fn prepare_update_plan(schema: &Schema, body: &mut Update) -> crate::Result<
             }
         }
     }
-    let order_by = body.order_by.as_ref().map(|order| {
-        order
-            .iter()
-            .map(|o| {
-                (
-                    o.expr.clone(),
-                    o.order
-                        .map(|s| match s {
-                            SortOrder::Asc => Direction::Ascending,
-                            SortOrder::Desc => Direction::Descending,
-                        })
-                        .unwrap_or(Direction::Ascending),
-                )
-            })
-            .collect()
-    });
+    // Like SQLite built with SQLITE_ENABLE_UPDATE_DELETE_LIMIT, the rows to update can be
+    // ordered to update the first ones of them.
+    if body.order_by.is_some() && body.limit.is_none() {
+        bail_parse_error!("ORDER BY without LIMIT on UPDATE");
+    }
+    let order_by = parse_order_by(body.order_by.as_deref(), &table_references)?;
     // Parse the WHERE clause
     parse_where(
         body.where_clause.as_ref().map(|w| *w.clone()),
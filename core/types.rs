@@ -200,95 +200,36 @@ impl Display for OwnedValue {
                 if fl.is_nan() {
                     return write!(f, "");
                 }
-                // handle negative 0
-                if fl == -0.0 {
-                    return write!(f, "{:.1}", fl.abs());
+                if fl.is_infinite() {
+                    return write!(f, "{}", if fl < 0.0 { "-Inf" } else { "Inf" });
                 }
-
-                // handle scientific notation without trailing zeros
-                if (fl.abs() < 1e-4 || fl.abs() >= 1e15) && fl != 0.0 {
-                    let sci_notation = format!("{:.14e}", fl);
-                    let parts: Vec<&str> = sci_notation.split('e').collect();
-
-                    if parts.len() == 2 {
-                        let mantissa = parts[0];
-                        let exponent = parts[1];
-
-                        let decimal_parts: Vec<&str> = mantissa.split('.').collect();
-                        if decimal_parts.len() == 2 {
-                            let whole = decimal_parts[0];
-                            // 1.{this part}
-                            let mut fraction = String::from(decimal_parts[1]);
-
-                            //removing trailing 0 from fraction
-                            while fraction.ends_with('0') {
-                                fraction.pop();
-                            }
-
-                            let trimmed_mantissa = if fraction.is_empty() {
-                                whole.to_string()
-                            } else {
-                                format!("{}.{}", whole, fraction)
-                            };
-                            let (prefix, exponent) = if exponent.starts_with('-') {
-                                ("-0", &exponent[1..])
-                            } else {
-                                ("+", exponent)
-                            };
-                            return write!(f, "{}e{}{}", trimmed_mantissa, prefix, exponent);
-                        }
-                    }
-
-                    // fallback
-                    return write!(f, "{}", sci_notation);
-                }
-
-                // handle floating point max size is 15.
-                // If left > right && right + left > 15 go to sci notation
-                // If right > left && right + left > 15 truncate left so right + left == 15
-                let rounded = fl.round();
-                if (fl - rounded).abs() < 1e-14 {
-                    // if we very close to integer trim decimal part to 1 digit
-                    if rounded == rounded as i64 as f64 {
-                        return write!(f, "{:.1}", fl);
-                    }
+                if fl == 0.0 {
+                    return write!(f, "0.0");
                 }
-
-                let fl_str = format!("{}", fl);
-                let splitted = fl_str.split('.').collect::<Vec<&str>>();
-                // fallback
-                if splitted.len() != 2 {
-                    return write!(f, "{:.14e}", fl);
-                }
-
-                let first_part = if fl < 0.0 {
-                    // remove -
-                    &splitted[0][1..]
+                // Like SQLite's "%!.15g": 15 significant digits, in scientific notation when the
+                // exponent is below -4 or at least 15, and always with a fractional part.
+                let sci_notation = format!("{:.*e}", MAX_REAL_SIZE as usize - 1, fl);
+                let (mantissa, exponent) = sci_notation.split_once('e').unwrap();
+                let exponent: i32 = exponent.parse().unwrap();
+                let (digits, exponent) = if (-4..MAX_REAL_SIZE as i32).contains(&exponent) {
+                    let decimals = (MAX_REAL_SIZE as i32 - 1 - exponent) as usize;
+                    (format!("{:.*}", decimals, fl), None)
                 } else {
-                    splitted[0]
+                    (mantissa.to_string(), Some(exponent))
                 };
-
-                let second = splitted[1];
-
-                // We want more precision for smaller numbers. in SQLite case we want 15 non zero digits in 0 < number < 1
-                // leading zeroes added to max real size. But if float < 1e-4 we go to scientific notation
-                let leading_zeros = second.chars().take_while(|c| c == &'0').count();
-                let reminder = if first_part != "0" {
-                    MAX_REAL_SIZE as isize - first_part.len() as isize
-                } else {
-                    MAX_REAL_SIZE as isize + leading_zeros as isize
-                };
-                // float that have integer part > 15 converted to sci notation
-                if reminder < 0 {
-                    return write!(f, "{:.14e}", fl);
+                match digits.split_once('.') {
+                    Some((whole, fraction)) => {
+                        let fraction = fraction.trim_end_matches('0');
+                        let fraction = if fraction.is_empty() { "0" } else { fraction };
+                        write!(f, "{}.{}", whole, fraction)?;
+                    }
+                    None => write!(f, "{}.0", digits)?,
                 }
-                // trim decimal part to reminder or self len so total digits is 15;
-                let mut fl = format!("{:.*}", second.len().min(reminder as usize), fl);
-                // if decimal part ends with 0 we trim it
-                while fl.ends_with('0') {
-                    fl.pop();
+                match exponent {
+                    Some(exponent) if exponent < 0 => write!(f, "e-{:02}", -exponent),
+                    Some(exponent) => write!(f, "e+{:02}", exponent),
+                    None => Ok(()),
                 }
-                write!(f, "{}", fl)
             }
             Self::Text(s) => {
                 write!(f, "{}", s.as_str())
@@ -392,15 +333,33 @@ impl AggContext {
     }
 }
 
+/// Compares an integer with a real without first converting the integer to a real, which would
+/// lose precision for integers larger than 2^53.
+fn compare_int_float(int: i64, float: f64) -> Option<std::cmp::Ordering> {
+    if float.is_nan() {
+        return None;
+    }
+    if float < -9223372036854775808.0 {
+        return Some(std::cmp::Ordering::Greater);
+    }
+    if float >= 9223372036854775808.0 {
+        return Some(std::cmp::Ordering::Less);
+    }
+    match int.cmp(&(float as i64)) {
+        std::cmp::Ordering::Equal => 0.0.partial_cmp(&float.fract()),
+        ordering => Some(ordering),
+    }
+}
+
 impl PartialEq<OwnedValue> for OwnedValue {
     fn eq(&self, other: &OwnedValue) -> bool {
         match (self, other) {
             (Self::Integer(int_left), Self::Integer(int_right)) => int_left == int_right,
             (Self::Integer(int_left), Self::Float(float_right)) => {
-                (*int_left as f64) == (*float_right)
+                compare_int_float(*int_left, *float_right) == Some(std::cmp::Ordering::Equal)
             }
             (Self::Float(float_left), Self::Integer(int_right)) => {
-                float_left == (&(*int_right as f64))
+                compare_int_float(*int_right, *float_left) == Some(std::cmp::Ordering::Equal)
             }
             (Self::Float(float_left), Self::Float(float_right)) => float_left == float_right,
             (Self::Integer(_) | Self::Float(_), Self::Text(_) | Self::Blob(_)) => false,
@@ -425,10 +384,10 @@ impl PartialOrd<OwnedValue> for OwnedValue {
         match (self, other) {
             (Self::Integer(int_left), Self::Integer(int_right)) => int_left.partial_cmp(int_right),
             (Self::Integer(int_left), Self::Float(float_right)) => {
-                (*int_left as f64).partial_cmp(float_right)
+                compare_int_float(*int_left, *float_right)
             }
             (Self::Float(float_left), Self::Integer(int_right)) => {
-                float_left.partial_cmp(&(*int_right as f64))
+                compare_int_float(*int_right, *float_left).map(std::cmp::Ordering::reverse)
             }
             (Self::Float(float_left), Self::Float(float_right)) => {
                 float_left.partial_cmp(float_right)
@@ -478,6 +437,112 @@ impl Ord for OwnedValue {
     }
 }
 
+/// Arrays are bound as the blob that `carray()` reads them from.
+#[cfg(feature = "carray")]
+impl From<limbo_carray::CArray> for OwnedValue {
+    fn from(array: limbo_carray::CArray) -> Self {
+        OwnedValue::Blob(array.into_blob())
+    }
+}
+
+/// Values serialize as their natural serde counterparts: NULL as none, text as a string and
+/// blobs as bytes.
+#[cfg(feature = "serde")]
+impl serde::Serialize for OwnedValue {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            Self::Null => serializer.serialize_none(),
+            Self::Integer(i) => serializer.serialize_i64(*i),
+            Self::Float(f) => serializer.serialize_f64(*f),
+            Self::Text(t) => serializer.serialize_str(t.as_str()),
+            Self::Blob(b) => serializer.serialize_bytes(b),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for OwnedValue {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(OwnedValueVisitor)
+    }
+}
+
+#[cfg(feature = "serde")]
+struct OwnedValueVisitor;
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for OwnedValueVisitor {
+    type Value = OwnedValue;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("null, a number, a string or bytes")
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<OwnedValue, E> {
+        Ok(OwnedValue::Null)
+    }
+
+    fn visit_none<E>(self) -> std::result::Result<OwnedValue, E> {
+        Ok(OwnedValue::Null)
+    }
+
+    fn visit_some<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<OwnedValue, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_bool<E>(self, v: bool) -> std::result::Result<OwnedValue, E> {
+        Ok(OwnedValue::Integer(v as i64))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> std::result::Result<OwnedValue, E> {
+        Ok(OwnedValue::Integer(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> std::result::Result<OwnedValue, E> {
+        // Like SQLite, integers too large for 64 bits become reals.
+        Ok(match i64::try_from(v) {
+            Ok(i) => OwnedValue::Integer(i),
+            Err(_) => OwnedValue::Float(v as f64),
+        })
+    }
+
+    fn visit_f64<E>(self, v: f64) -> std::result::Result<OwnedValue, E> {
+        Ok(OwnedValue::Float(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<OwnedValue, E> {
+        Ok(OwnedValue::build_text(v))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<OwnedValue, E> {
+        Ok(OwnedValue::from_blob(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> std::result::Result<OwnedValue, E> {
+        Ok(OwnedValue::from_blob(v))
+    }
+
+    // Formats without a bytes type, such as JSON, write blobs as arrays of bytes.
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> std::result::Result<OwnedValue, A::Error> {
+        let mut blob = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element::<u8>()? {
+            blob.push(byte);
+        }
+        Ok(OwnedValue::from_blob(blob))
+    }
+}
+
 impl std::ops::Add<OwnedValue> for OwnedValue {
     type Output = OwnedValue;
 
@@ -741,18 +806,27 @@ impl ImmutableRecord {
     }
 
     pub fn from_registers(registers: &[Register]) -> Self {
-        let mut values = Vec::with_capacity(registers.len());
-        let mut serials = Vec::with_capacity(registers.len());
+        Self::from_registers_in(registers, Vec::new(), Vec::new())
+    }
+
+    /// Like [Self::from_registers], but serializes into the given buffers, which only allocate
+    /// if the record doesn't fit.
+    pub fn from_registers_in(
+        registers: &[Register],
+        mut buf: Vec<u8>,
+        mut values: Vec<RefValue>,
+    ) -> Self {
+        values.clear();
+        values.reserve(registers.len());
         let mut size_header = 0;
         let mut size_values = 0;
 
         let mut serial_type_buf = [0; 9];
-        // write serial types
+        // compute the size of the serial types and values
         for value in registers {
             let value = value.get_owned_value();
             let serial_type = SerialType::from(value);
-            let n = write_varint(&mut serial_type_buf[0..], serial_type.into());
-            serials.push((serial_type_buf, n));
+            let n = write_varint(&mut serial_type_buf, serial_type.into());
 
             let value_size = match serial_type {
                 SerialType::Null => 0,
@@ -786,19 +860,19 @@ impl ImmutableRecord {
             // if( nVarint<sqlite3VarintLen(nHdr) ) nHdr++;
         }
         // 1. write header size
-        let mut buf = Vec::new();
-        buf.reserve_exact(header_size + size_values);
-        assert_eq!(buf.capacity(), header_size + size_values);
+        buf.clear();
+        buf.resize(header_size + size_values, 0);
         assert!(header_size <= 126);
         let n = write_varint(&mut serial_type_buf, header_size as u64);
 
-        buf.resize(buf.capacity(), 0);
         let mut writer = AppendWriter::new(&mut buf, 0);
         writer.extend_from_slice(&serial_type_buf[..n]);
 
         // 2. Write serial
-        for (value, n) in serials {
-            writer.extend_from_slice(&value[..n]);
+        for value in registers {
+            let serial_type = SerialType::from(value.get_owned_value());
+            let n = write_varint(&mut serial_type_buf[0..], serial_type.into());
+            writer.extend_from_slice(&serial_type_buf[..n]);
         }
 
         // write content
@@ -879,12 +953,30 @@ impl ImmutableRecord {
     pub fn get_payload(&self) -> &[u8] {
         &self.payload
     }
-}
 
-impl Clone for ImmutableRecord {
-    fn clone(&self) -> Self {
-        let mut new_values = Vec::new();
-        let new_payload = self.payload.clone();
+    /// Empties the record, returning its buffers for [Self::from_registers_in] or
+    /// [Self::clone_in].
+    pub fn into_buffers(mut self) -> (Vec<u8>, Vec<RefValue>) {
+        self.invalidate();
+        (self.payload, self.values)
+    }
+
+    /// An empty record that fills the given buffers, like [Self::new] but without allocating.
+    pub fn from_buffers(mut payload: Vec<u8>, mut values: Vec<RefValue>) -> Self {
+        payload.clear();
+        values.clear();
+        Self {
+            payload,
+            values,
+            recreating: false,
+        }
+    }
+
+    /// Copies the record into the given buffers, which only allocate if it doesn't fit.
+    pub fn clone_in(&self, mut new_payload: Vec<u8>, mut new_values: Vec<RefValue>) -> Self {
+        new_payload.clear();
+        new_payload.extend_from_slice(&self.payload);
+        new_values.clear();
         for value in &self.values {
             let value = match value {
                 RefValue::Null => RefValue::Null,
@@ -919,6 +1011,12 @@ impl Clone for ImmutableRecord {
     }
 }
 
+impl Clone for ImmutableRecord {
+    fn clone(&self) -> Self {
+        self.clone_in(Vec::new(), Vec::new())
+    }
+}
+
 impl RefValue {
     pub fn to_ffi(&self) -> ExtValue {
         match self {
@@ -979,10 +1077,10 @@ impl PartialOrd<RefValue> for RefValue {
         match (self, other) {
             (Self::Integer(int_left), Self::Integer(int_right)) => int_left.partial_cmp(int_right),
             (Self::Integer(int_left), Self::Float(float_right)) => {
-                (*int_left as f64).partial_cmp(float_right)
+                compare_int_float(*int_left, *float_right)
             }
             (Self::Float(float_left), Self::Integer(int_right)) => {
-                float_left.partial_cmp(&(*int_right as f64))
+                compare_int_float(*int_right, *float_left).map(std::cmp::Ordering::reverse)
             }
             (Self::Float(float_left), Self::Float(float_right)) => {
                 float_left.partial_cmp(float_right)
@@ -1202,6 +1300,16 @@ pub enum SeekOp {
     EQ,
     GE,
     GT,
+    LE,
+    LT,
+}
+
+impl SeekOp {
+    /// Whether the seek positions the cursor for backwards iteration, i.e. on the
+    /// last record that satisfies the comparison rather than the first one.
+    pub fn is_backwards(&self) -> bool {
+        matches!(self, SeekOp::LE | SeekOp::LT)
+    }
 }
 
 #[derive(Clone, PartialEq, Debug)]
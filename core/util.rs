@@ -26,16 +26,33 @@ pub fn normalize_ident(identifier: &str) -> String {
         .iter()
         .find(|&(start, end)| identifier.starts_with(*start) && identifier.ends_with(*end));
 
-    if let Some(&(_, _)) = quote_pair {
-        &identifier[1..identifier.len() - 1]
+    if let Some(&(start, end)) = quote_pair {
+        let quoted = &identifier[1..identifier.len() - 1];
+        // A quote within the name is doubled, except in brackets, which can't hold a `]`.
+        if start == end {
+            quoted.replace(&format!("{end}{end}"), &end.to_string())
+        } else {
+            quoted.to_string()
+        }
     } else {
-        identifier
+        identifier.to_string()
     }
     .to_lowercase()
 }
 
 pub const PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX: &str = "sqlite_autoindex_";
 
+/// Root pages come from the schema table, which a damaged or crafted file can fill with anything.
+fn schema_root_page(root_page: i64) -> Result<usize> {
+    if root_page < 1 {
+        return Err(LimboError::Corrupt(format!(
+            "invalid root page {} in schema",
+            root_page
+        )));
+    }
+    Ok(root_page as usize)
+}
+
 pub fn parse_schema_rows(
     rows: Option<Statement>,
     schema: &mut Schema,
@@ -60,10 +77,17 @@ pub fn parse_schema_rows(
                             let sql: &str = row.get::<&str>(4)?;
                             if root_page == 0 && sql.to_lowercase().contains("create virtual") {
                                 let name: &str = row.get::<&str>(1)?;
-                                let vtab = syms.vtabs.get(name).unwrap().clone();
+                                let vtab = match syms.vtabs.get(name) {
+                                    Some(vtab) => vtab.clone(),
+                                    // Created by an earlier connection.
+                                    None => crate::VirtualTable::from_sql(name, sql, syms)?,
+                                };
                                 schema.add_virtual_table(vtab);
                             } else {
-                                let table = schema::BTreeTable::from_sql(sql, root_page as usize)?;
+                                let table = schema::BTreeTable::from_sql(
+                                    sql,
+                                    schema_root_page(root_page)?,
+                                )?;
                                 schema.add_btree_table(Rc::new(table));
                             }
                         }
@@ -71,7 +95,8 @@ pub fn parse_schema_rows(
                             let root_page: i64 = row.get::<i64>(3)?;
                             match row.get::<&str>(4) {
                                 Ok(sql) => {
-                                    let index = schema::Index::from_sql(sql, root_page as usize)?;
+                                    let index =
+                                        schema::Index::from_sql(sql, schema_root_page(root_page)?)?;
                                     schema.add_index(Arc::new(index));
                                 }
                                 _ => {
@@ -104,9 +129,17 @@ pub fn parse_schema_rows(
         }
         for (index_name, table_name, root_page) in automatic_indexes {
             // We need to process these after all tables are loaded into memory due to the schema.get_table() call
-            let table = schema.get_btree_table(&table_name).unwrap();
-            let index =
-                schema::Index::automatic_from_primary_key(&table, &index_name, root_page as usize)?;
+            let Some(table) = schema.get_btree_table(&table_name) else {
+                return Err(LimboError::Corrupt(format!(
+                    "index {} refers to missing table {}",
+                    index_name, table_name
+                )));
+            };
+            let index = schema::Index::automatic_from_primary_key(
+                &table,
+                &index_name,
+                schema_root_page(root_page)?,
+            )?;
             schema.add_index(Arc::new(index));
         }
     }
@@ -328,6 +361,57 @@ pub fn exprs_are_equivalent(expr1: &Expr, expr2: &Expr) -> bool {
     }
 }
 
+/// Whether evaluating an expression twice always gives the same result, which is not the case
+/// once it calls a function like random(). Such expressions must be evaluated every time they
+/// appear, e.g. for every row in a WHERE clause.
+pub fn expr_is_deterministic(expr: &Expr) -> bool {
+    match expr {
+        Expr::FunctionCall { name, args, .. } => {
+            let arg_count = args.as_ref().map_or(0, |args| args.len());
+            let deterministic = crate::function::Func::resolve_function(
+                &normalize_ident(name.0.as_str()),
+                arg_count,
+            )
+            .map_or(true, |func| func.is_deterministic());
+            deterministic && args.iter().flatten().all(expr_is_deterministic)
+        }
+        Expr::Between {
+            lhs, start, end, ..
+        } => {
+            expr_is_deterministic(lhs) && expr_is_deterministic(start) && expr_is_deterministic(end)
+        }
+        Expr::Binary(lhs, _, rhs) => expr_is_deterministic(lhs) && expr_is_deterministic(rhs),
+        Expr::Case {
+            base,
+            when_then_pairs,
+            else_expr,
+        } => {
+            base.as_deref().map_or(true, expr_is_deterministic)
+                && when_then_pairs
+                    .iter()
+                    .all(|(when, then)| expr_is_deterministic(when) && expr_is_deterministic(then))
+                && else_expr.as_deref().map_or(true, expr_is_deterministic)
+        }
+        Expr::Cast { expr, .. }
+        | Expr::Collate(expr, _)
+        | Expr::IsNull(expr)
+        | Expr::NotNull(expr)
+        | Expr::Unary(_, expr) => expr_is_deterministic(expr),
+        Expr::InList { lhs, rhs, .. } => {
+            expr_is_deterministic(lhs) && rhs.iter().flatten().all(expr_is_deterministic)
+        }
+        Expr::Like {
+            lhs, rhs, escape, ..
+        } => {
+            expr_is_deterministic(lhs)
+                && expr_is_deterministic(rhs)
+                && escape.as_deref().map_or(true, expr_is_deterministic)
+        }
+        Expr::Parenthesized(exprs) => exprs.iter().all(expr_is_deterministic),
+        _ => true,
+    }
+}
+
 pub fn columns_from_create_table_body(body: &ast::CreateTableBody) -> crate::Result<Vec<Column>> {
     let CreateTableBody::ColumnsAndConstraints { columns, .. } = body else {
         return Err(crate::LimboError::ParseError(
@@ -345,7 +429,7 @@ pub fn columns_from_create_table_body(body: &ast::CreateTableBody) -> crate::Res
                 }
             }
             let column = Column {
-                name: Some(name.0.clone()),
+                name: Some(normalize_ident(&name.0)),
                 ty: match column_def.col_type {
                     Some(ref data_type) => {
                         // https://www.sqlite.org/datatype3.html
@@ -419,6 +503,10 @@ pub struct OpenOptions<'a> {
     pub cache: CacheMode,
     /// immutable=1|0 specifies that the database is stored on read-only media
     pub immutable: bool,
+    /// compress=zstd|lz4|none stores the database file with compressed pages
+    pub compress: Option<String>,
+    /// checksum=1|0 stores a checksum of every page in the database file
+    pub checksum: bool,
 }
 
 #[derive(Clone, Default, Debug, Copy, PartialEq)]
@@ -558,6 +646,8 @@ fn parse_query_params(query: &str, opts: &mut OpenOptions) -> Result<()> {
                 "cache" => opts.cache = decoded_value.as_str().into(),
                 "immutable" => opts.immutable = decoded_value == "1",
                 "vfs" => opts.vfs = Some(decoded_value),
+                "compress" => opts.compress = Some(decoded_value),
+                "checksum" => opts.checksum = decoded_value == "1",
                 _ => {}
             }
         }
@@ -719,7 +809,7 @@ fn parse_numeric_str(text: &str) -> Result<(OwnedValueType, &str), ()> {
     if bytes.is_empty()
         || bytes[0] == b'e'
         || bytes[0] == b'E'
-        || (bytes[0] == b'.' && (bytes[1] == b'e' || bytes[1] == b'E'))
+        || (bytes[0] == b'.' && matches!(bytes.get(1), Some(b'e' | b'E')))
     {
         return Err(());
     }
@@ -769,6 +859,41 @@ fn parse_numeric_str(text: &str) -> Result<(OwnedValueType, &str), ()> {
     ))
 }
 
+/// Converts text to INTEGER or REAL the way a column with NUMERIC affinity stores it: only when
+/// the whole text, ignoring surrounding spaces, is a well-formed integer or real literal.
+/// Hexadecimal literals are not considered well-formed. Reals that are exactly integers become
+/// INTEGER, so '3.0e+5' yields 300000.
+pub fn text_to_numeric_affinity(text: &str) -> Option<OwnedValue> {
+    let text = text.trim();
+    // parse_numeric_str() only understands a leading '-'
+    let number = match text.strip_prefix('+') {
+        Some(rest) if rest.starts_with(['-', '+']) => return None,
+        Some(rest) => rest,
+        None => text,
+    };
+    if !number.bytes().any(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let (_, prefix) = parse_numeric_str(number).ok()?;
+    if prefix.len() != number.len() {
+        return None;
+    }
+    match checked_cast_text_to_numeric(prefix).ok()? {
+        OwnedValue::Float(f) => Some(float_to_integer_affinity(f)),
+        value => Some(value),
+    }
+}
+
+/// Converts a REAL to INTEGER if it can be represented exactly as one, otherwise keeps it.
+pub fn float_to_integer_affinity(f: f64) -> OwnedValue {
+    // -2^63 and 2^63 are excluded, like sqlite3VdbeIntegerAffinity does
+    if f.fract() == 0.0 && f > -9223372036854775808.0 && f < 9223372036854775808.0 {
+        OwnedValue::Integer(f as i64)
+    } else {
+        OwnedValue::Float(f)
+    }
+}
+
 pub fn cast_text_to_numeric(txt: &str) -> OwnedValue {
     checked_cast_text_to_numeric(txt).unwrap_or(OwnedValue::Integer(0))
 }
@@ -822,6 +947,9 @@ pub mod tests {
         assert_eq!(normalize_ident("`foo`"), "foo");
         assert_eq!(normalize_ident("[foo]"), "foo");
         assert_eq!(normalize_ident("\"foo\""), "foo");
+        assert_eq!(normalize_ident("\"b\"\"q\""), "b\"q");
+        assert_eq!(normalize_ident("`b``q`"), "b`q");
+        assert_eq!(normalize_ident("[b\"\"q]"), "b\"\"q");
     }
 
     #[test]
@@ -1182,6 +1310,15 @@ pub mod tests {
         assert_eq!(opts.immutable, false);
     }
 
+    #[test]
+    fn test_uri_with_compression_params() {
+        let uri = "file:/home/user/db.sqlite?compress=zstd&checksum=1";
+        let opts = parse_sqlite_uri(uri).unwrap();
+        assert_eq!(opts.path, "/home/user/db.sqlite");
+        assert_eq!(opts.compress, Some("zstd".to_string()));
+        assert!(opts.checksum);
+    }
+
     #[test]
     fn test_uri_with_unknown_query_param() {
         let uri = "file:/home/user/db.sqlite?unknown=param";
@@ -1523,6 +1660,43 @@ pub mod tests {
         assert_eq!(cast_text_to_numeric("-E"), OwnedValue::Integer(0));
     }
 
+    #[test]
+    fn test_text_to_numeric_affinity() {
+        assert_eq!(
+            text_to_numeric_affinity("12"),
+            Some(OwnedValue::Integer(12))
+        );
+        assert_eq!(
+            text_to_numeric_affinity(" +12 "),
+            Some(OwnedValue::Integer(12))
+        );
+        assert_eq!(
+            text_to_numeric_affinity("-12"),
+            Some(OwnedValue::Integer(-12))
+        );
+        assert_eq!(
+            text_to_numeric_affinity("3.0e+5"),
+            Some(OwnedValue::Integer(300000))
+        );
+        assert_eq!(text_to_numeric_affinity("1."), Some(OwnedValue::Integer(1)));
+        assert_eq!(
+            text_to_numeric_affinity("1.5"),
+            Some(OwnedValue::Float(1.5))
+        );
+        assert_eq!(text_to_numeric_affinity(".5"), Some(OwnedValue::Float(0.5)));
+        assert_eq!(
+            text_to_numeric_affinity("9223372036854775808"),
+            Some(OwnedValue::Float(9.22337203685478e18))
+        );
+        assert_eq!(text_to_numeric_affinity(""), None);
+        assert_eq!(text_to_numeric_affinity("."), None);
+        assert_eq!(text_to_numeric_affinity("-"), None);
+        assert_eq!(text_to_numeric_affinity("+-1"), None);
+        assert_eq!(text_to_numeric_affinity("1e"), None);
+        assert_eq!(text_to_numeric_affinity("12abc"), None);
+        assert_eq!(text_to_numeric_affinity("0x10"), None);
+    }
+
     #[test]
     fn test_parse_numeric_str_valid_integer() {
         assert_eq!(
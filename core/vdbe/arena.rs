@@ -0,0 +1,171 @@
+//! Buffers of the records a statement builds, kept for reuse so that loops building a record
+//! per row stop going through the global allocator once they warmed up.
+//!
+//! Records that are done with, like the previous value of a MakeRecord register, a seek key
+//! after the seek or a sorted row after SorterNext, hand their payload and value buffers back
+//! to the arena. The next record takes them and only allocates when it doesn't fit. B-tree
+//! cursors read their rows into a record from the arena as well, which goes back once the
+//! cursor is closed or replaced, so the cursors of a join that is run again or the ephemeral
+//! tables it materializes don't allocate one each. The arena lives as long as the statement, so
+//! executions after a reset start out warm as well.
+
+use crate::types::{Cursor, ImmutableRecord, RefValue};
+
+use super::Register;
+
+/// Free buffers kept at most, so that a statement that once held many records at a time, like
+/// a sorter, doesn't keep their memory around.
+const MAX_FREE_BUFFERS: usize = 64;
+
+/// Capacity of new payload buffers, so that recycled ones fit most rows of a narrow table
+/// although their sizes vary a little.
+const MIN_PAYLOAD_CAPACITY: usize = 64;
+
+/// Buffer allocations of a statement, see [crate::Statement::allocation_stats].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocationStats {
+    /// Records that needed a new or bigger buffer.
+    pub allocations: u64,
+    /// Records that fit in a recycled buffer.
+    pub reuses: u64,
+}
+
+#[derive(Default)]
+pub struct Arena {
+    payloads: Vec<Vec<u8>>,
+    values: Vec<Vec<RefValue>>,
+    stats: AllocationStats,
+}
+
+impl Arena {
+    /// Serializes `registers` into a record, in recycled buffers if there are any.
+    pub fn make_record(&mut self, registers: &[Register]) -> ImmutableRecord {
+        let (payload, values, capacity) = self.take_buffers();
+        let record = ImmutableRecord::from_registers_in(registers, payload, values);
+        self.count(capacity, record.get_payload().len());
+        record
+    }
+
+    /// Copies `record`, in recycled buffers if there are any.
+    pub fn clone_record(&mut self, record: &ImmutableRecord) -> ImmutableRecord {
+        let (payload, values, capacity) = self.take_buffers();
+        let record = record.clone_in(payload, values);
+        self.count(capacity, record.get_payload().len());
+        record
+    }
+
+    /// An empty record for a cursor to read rows into, in recycled buffers if there are any.
+    pub fn record_buffer(&mut self) -> ImmutableRecord {
+        let (payload, values, capacity) = self.take_buffers();
+        self.count(capacity, 0);
+        ImmutableRecord::from_buffers(payload, values)
+    }
+
+    /// Takes back the buffers of a record that is no longer used.
+    pub fn recycle(&mut self, record: ImmutableRecord) {
+        if self.payloads.len() >= MAX_FREE_BUFFERS {
+            return;
+        }
+        let (payload, values) = record.into_buffers();
+        if payload.capacity() > 0 {
+            self.payloads.push(payload);
+            self.values.push(values);
+        }
+    }
+
+    /// Takes back the records held by a cursor that is closed or replaced.
+    pub fn recycle_cursor(&mut self, cursor: Cursor) {
+        match cursor {
+            Cursor::BTree(mut btree) => {
+                if let Some(record) = btree.take_record_buffer() {
+                    self.recycle(record);
+                }
+            }
+            Cursor::Sorter(sorter) => sorter
+                .into_records()
+                .for_each(|record| self.recycle(record)),
+            Cursor::Pseudo(pseudo) => {
+                if let Some(record) = pseudo.into_record() {
+                    self.recycle(record);
+                }
+            }
+            Cursor::Virtual(_) => {}
+        }
+    }
+
+    pub fn stats(&self) -> AllocationStats {
+        self.stats
+    }
+
+    fn take_buffers(&mut self) -> (Vec<u8>, Vec<RefValue>, Option<usize>) {
+        match (self.payloads.pop(), self.values.pop()) {
+            (Some(payload), Some(values)) => {
+                let capacity = payload.capacity();
+                (payload, values, Some(capacity))
+            }
+            _ => (Vec::with_capacity(MIN_PAYLOAD_CAPACITY), Vec::new(), None),
+        }
+    }
+
+    fn count(&mut self, capacity: Option<usize>, len: usize) {
+        match capacity {
+            Some(capacity) if len <= capacity => self.stats.reuses += 1,
+            _ => self.stats.allocations += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{OwnedValue, Text};
+
+    fn registers(text: &str) -> Vec<Register> {
+        vec![
+            Register::OwnedValue(OwnedValue::Integer(1)),
+            Register::OwnedValue(OwnedValue::Text(Text::new(text))),
+        ]
+    }
+
+    #[test]
+    fn test_recycled_buffers_are_reused() {
+        let mut arena = Arena::default();
+        let first = arena.make_record(&registers("hello"));
+        assert_eq!(
+            arena.stats(),
+            AllocationStats {
+                allocations: 1,
+                reuses: 0
+            }
+        );
+        arena.recycle(first);
+
+        let second = arena.make_record(&registers("world"));
+        assert_eq!(
+            second.get_value(1).to_owned(),
+            OwnedValue::build_text("world")
+        );
+        assert_eq!(
+            second.get_payload(),
+            ImmutableRecord::from_registers(&registers("world")).get_payload()
+        );
+        assert_eq!(arena.stats().reuses, 1);
+
+        let copy = arena.clone_record(&second);
+        assert_eq!(copy.get_payload(), second.get_payload());
+        assert_eq!(
+            copy.get_value(1).to_owned(),
+            OwnedValue::build_text("world")
+        );
+        assert_eq!(arena.stats().allocations, 2);
+        arena.recycle(second);
+
+        // A record that doesn't fit the recycled buffer counts as an allocation.
+        let bigger = arena.make_record(&registers(&"x".repeat(100)));
+        assert_eq!(
+            bigger.get_value(1).to_owned(),
+            OwnedValue::build_text(&"x".repeat(100))
+        );
+        assert_eq!(arena.stats().allocations, 3);
+    }
+}
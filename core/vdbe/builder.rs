@@ -1,5 +1,4 @@
 use std::{
-    cell::Cell,
     collections::HashMap,
     rc::{Rc, Weak},
     sync::Arc,
@@ -233,6 +232,38 @@ impl ProgramBuilder {
         self.next_insn_labels.push(label);
     }
 
+    /// Makes the instructions emitted since `start` (a resolved label) read the rowid, and the
+    /// table columns that `index_columns` maps to index columns, from the index cursor of a loop
+    /// instead of from its table cursor. The table cursor only follows the index cursor by a
+    /// DeferredSeek, so rows that are rejected by conditions on the indexed columns, or that need
+    /// no other columns at all, are never looked up in the table.
+    pub fn read_columns_from_index(
+        &mut self,
+        start: BranchOffset,
+        table_cursor_id: CursorID,
+        index_cursor_id: CursorID,
+        index_columns: &[Option<usize>],
+    ) {
+        let start = self.label_to_resolved_offset[start.to_label_value() as usize]
+            .expect("loop start label must be resolved") as usize;
+        for (insn, _) in self.insns[start..].iter_mut() {
+            match insn {
+                Insn::Column {
+                    cursor_id, column, ..
+                } if *cursor_id == table_cursor_id => {
+                    if let Some(pos) = index_columns.iter().position(|c| *c == Some(*column)) {
+                        *cursor_id = index_cursor_id;
+                        *column = pos;
+                    }
+                }
+                Insn::RowId { cursor_id, .. } if *cursor_id == table_cursor_id => {
+                    *cursor_id = index_cursor_id;
+                }
+                _ => {}
+            }
+        }
+    }
+
     pub fn resolve_label(&mut self, label: BranchOffset, to_offset: BranchOffset) {
         assert!(matches!(label, BranchOffset::Label(_)));
         assert!(matches!(to_offset, BranchOffset::Offset(_)));
@@ -395,6 +426,12 @@ impl ProgramBuilder {
                 Insn::SeekRowid { target_pc, .. } => {
                     resolve(target_pc, "SeekRowid");
                 }
+                Insn::VectorSearch { pc_if_empty, .. } => {
+                    resolve(pc_if_empty, "VectorSearch");
+                }
+                Insn::VectorNext { pc_if_next, .. } => {
+                    resolve(pc_if_next, "VectorNext");
+                }
                 Insn::Gosub { target_pc, .. } => {
                     resolve(target_pc, "Gosub");
                 }
@@ -413,6 +450,12 @@ impl ProgramBuilder {
                 Insn::SeekGT { target_pc, .. } => {
                     resolve(target_pc, "SeekGT");
                 }
+                Insn::SeekLE { target_pc, .. } => {
+                    resolve(target_pc, "SeekLE");
+                }
+                Insn::SeekLT { target_pc, .. } => {
+                    resolve(target_pc, "SeekLT");
+                }
                 Insn::IdxGE { target_pc, .. } => {
                     resolve(target_pc, "IdxGE");
                 }
@@ -441,10 +484,12 @@ impl ProgramBuilder {
     }
 
     // translate table to cursor id
+    // The most recent cursor wins: FROM clause subqueries are emitted before the tables of
+    // their parent query are opened, and they may use the same table identifiers.
     pub fn resolve_cursor_id(&self, table_identifier: &str) -> CursorID {
         self.cursor_ref
             .iter()
-            .position(|(t_ident, _)| {
+            .rposition(|(t_ident, _)| {
                 t_ident
                     .as_ref()
                     .is_some_and(|ident| ident == table_identifier)
@@ -473,10 +518,10 @@ impl ProgramBuilder {
             comments: self.comments,
             connection,
             parameters: self.parameters,
-            n_change: Cell::new(0),
             change_cnt_on,
             result_columns: self.result_columns,
             table_references: self.table_references,
+            sql: String::new(),
         }
     }
 }
@@ -1,30 +1,42 @@
 #![allow(unused_variables)]
-use crate::error::{LimboError, SQLITE_CONSTRAINT_PRIMARYKEY};
+use crate::error::{
+    LimboError, SQLITE_CONSTRAINT, SQLITE_CONSTRAINT_PRIMARYKEY, SQLITE_CONSTRAINT_UNIQUE,
+};
 use crate::ext::ExtValue;
-use crate::function::{AggFunc, ExtFunc, MathFunc, MathFuncArity, ScalarFunc, VectorFunc};
+#[cfg(feature = "vector")]
+use crate::function::VectorFunc;
+use crate::function::{AggFunc, ExtFunc, MathFunc, MathFuncArity, ScalarFunc};
 use crate::functions::datetime::{
     exec_date, exec_datetime_full, exec_julianday, exec_strftime, exec_time, exec_unixepoch,
+    instant_to_date_time, parse_naive_date_time,
 };
 use crate::functions::printf::exec_printf;
 use std::{borrow::BorrowMut, rc::Rc};
 
+use crate::cdc::{self, ChangeOp, RowChange};
+use crate::matview;
 use crate::pseudo::PseudoCursor;
 use crate::result::LimboResult;
-use crate::schema::{affinity, Affinity};
+use crate::schema::{affinity, Affinity, BTreeTable};
 use crate::storage::btree::{BTreeCursor, BTreeKey};
+use crate::storage::integrity::check_integrity;
+use crate::storage::pager::TempStore;
 use crate::storage::wal::CheckpointResult;
+use crate::translate::collate::CollationSeq;
 use crate::types::{
     AggContext, Cursor, CursorResult, ExternalAggState, OwnedValue, SeekKey, SeekOp,
 };
 use crate::util::{
     cast_real_to_integer, cast_text_to_integer, cast_text_to_numeric, cast_text_to_real,
-    checked_cast_text_to_numeric, parse_schema_rows, RoundToPrecision,
+    checked_cast_text_to_numeric, float_to_integer_affinity, parse_schema_rows,
+    text_to_numeric_affinity, RoundToPrecision,
 };
 use crate::vdbe::builder::CursorType;
 use crate::vdbe::insn::{IdxInsertFlags, Insn};
-use crate::vector::{vector32, vector64, vector_distance_cos, vector_extract};
+#[cfg(feature = "vector")]
+use crate::vector::{vector32, vector64, vector_distance_cos, vector_distance_l2, vector_extract};
 
-use crate::{info, MvCursor, RefValue, Row, StepResult, TransactionState};
+use crate::{info, Connection, MvCursor, RefValue, Row, StepResult, TransactionState};
 
 use super::insn::{
     exec_add, exec_and, exec_bit_and, exec_bit_not, exec_bit_or, exec_boolean_not, exec_concat,
@@ -32,10 +44,10 @@ use super::insn::{
     exec_subtract, Cookie, RegisterOrLiteral,
 };
 use super::HaltState;
-use rand::thread_rng;
+use rand::{rngs::StdRng, SeedableRng};
 
 use super::likeop::{construct_like_escape_arg, exec_glob, exec_like_with_escape};
-use super::sorter::Sorter;
+use super::sorter::{SortKey, Sorter};
 use regex::{Regex, RegexBuilder};
 use std::cell::RefCell;
 use std::collections::HashMap;
@@ -51,7 +63,7 @@ use crate::{
     json::jsonb_patch, json::jsonb_remove, json::jsonb_replace, json::jsonb_set,
 };
 
-use super::{get_new_rowid, make_record, Program, ProgramState, Register};
+use super::{get_new_rowid, Program, ProgramState, Register, TableStats};
 use crate::{
     bail_constraint_error, must_be_btree_cursor, resolve_ext_path, MvStore, Pager, Result,
     DATABASE_VERSION,
@@ -248,21 +260,26 @@ pub fn op_checkpoint(
 ) -> Result<InsnFunctionStepResult> {
     let Insn::Checkpoint {
         database: _,
-        checkpoint_mode: _,
+        checkpoint_mode,
         dest,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    let result = program.connection.upgrade().unwrap().checkpoint();
+    let result = program
+        .connection
+        .upgrade()
+        .unwrap()
+        .checkpoint_with_mode(*checkpoint_mode);
     match result {
         Ok(CheckpointResult {
             num_wal_frames: num_wal_pages,
             num_checkpointed_frames: num_checkpointed_pages,
+            busy,
         }) => {
             // https://sqlite.org/pragma.html#pragma_wal_checkpoint
             // 1st col: 1 (checkpoint SQLITE_BUSY) or 0 (not busy).
-            state.registers[*dest] = Register::OwnedValue(OwnedValue::Integer(0));
+            state.registers[*dest] = Register::OwnedValue(OwnedValue::Integer(busy as i64));
             // 2nd col: # modified pages written to wal file
             state.registers[*dest + 1] =
                 Register::OwnedValue(OwnedValue::Integer(num_wal_pages as i64));
@@ -308,6 +325,8 @@ pub fn op_null_row(
     let Insn::NullRow { cursor_id } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
+    // The row the table cursor was going to be moved to is not the one being read anymore.
+    state.deferred_seeks[*cursor_id] = None;
     {
         let mut cursor = must_be_btree_cursor!(*cursor_id, program.cursor_ref, state, "NullRow");
         let cursor = cursor.as_btree_mut();
@@ -822,19 +841,9 @@ pub fn op_open_read_async(
         None => None,
     };
     let cursor = BTreeCursor::new(mv_cursor, pager.clone(), *root_page);
-    let mut cursors = state.cursors.borrow_mut();
     match cursor_type {
-        CursorType::BTreeTable(_) => {
-            cursors
-                .get_mut(*cursor_id)
-                .unwrap()
-                .replace(Cursor::new_btree(cursor));
-        }
-        CursorType::BTreeIndex(_) => {
-            cursors
-                .get_mut(*cursor_id)
-                .unwrap()
-                .replace(Cursor::new_btree(cursor));
+        CursorType::BTreeTable(_) | CursorType::BTreeIndex(_) => {
+            state.open_cursor(*cursor_id, Cursor::new_btree(cursor));
         }
         CursorType::Pseudo(_) => {
             panic!("OpenReadAsync on pseudo cursor");
@@ -903,7 +912,7 @@ pub fn op_vcreate(
     let table_name = state.registers[*table_name].get_owned_value().to_string();
     let args = if let Some(args_reg) = args_reg {
         if let Register::Record(rec) = &state.registers[*args_reg] {
-            rec.get_values().iter().map(|v| v.to_ffi()).collect()
+            rec.get_values().iter().map(|v| v.to_string()).collect()
         } else {
             return Err(LimboError::InternalError(
                 "VCreate: args_reg is not a record".to_string(),
@@ -917,14 +926,7 @@ pub fn op_vcreate(
             "Failed to upgrade Connection".to_string(),
         ));
     };
-    let table = crate::VirtualTable::from_args(
-        Some(&table_name),
-        &module_name,
-        args,
-        &conn.syms.borrow(),
-        limbo_ext::VTabKind::VirtualTable,
-        None,
-    )?;
+    let table = crate::VirtualTable::create(&table_name, &module_name, args, &conn.syms.borrow())?;
     {
         conn.syms
             .borrow_mut()
@@ -958,6 +960,8 @@ pub fn op_vfilter(
         pc_if_empty,
         arg_count,
         args_reg,
+        idx_str,
+        idx_num,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
@@ -973,7 +977,7 @@ pub fn op_vfilter(
         for i in 0..*arg_count {
             args.push(state.registers[args_reg + i].get_owned_value().clone());
         }
-        virtual_table.filter(cursor, *arg_count, args)?
+        virtual_table.filter(cursor, *arg_count, args, idx_str.as_deref(), *idx_num)?
     };
     if !has_rows {
         state.pc = pc_if_empty.to_offset_int();
@@ -1122,14 +1126,87 @@ pub fn op_open_pseudo(
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    {
-        let mut cursors = state.cursors.borrow_mut();
-        let cursor = PseudoCursor::new();
-        cursors
-            .get_mut(*cursor_id)
-            .unwrap()
-            .replace(Cursor::new_pseudo(cursor));
-    }
+    state.open_cursor(*cursor_id, Cursor::new_pseudo(PseudoCursor::new()));
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_open_autoindex(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::OpenAutoindex { cursor_id } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    open_ephemeral_btree(program, state, pager, *cursor_id, false)?;
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_open_ephemeral(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::OpenEphemeral {
+        cursor_id,
+        is_table,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    open_ephemeral_btree(program, state, pager, *cursor_id, *is_table)?;
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+fn open_ephemeral_btree(
+    program: &Program,
+    state: &mut ProgramState,
+    pager: &Rc<Pager>,
+    cursor_id: usize,
+    is_table: bool,
+) -> Result<()> {
+    let temp_store = program
+        .connection
+        .upgrade()
+        .map_or(TempStore::Default, |conn| conn.temp_store());
+    let pager = crate::open_ephemeral_pager(
+        &pager.io,
+        temp_store,
+        pager.page_cache_size(),
+        pager.memory_limit(),
+    )?;
+    let root_page = pager.btree_create(if is_table { 1 } else { 2 }) as usize;
+    let cursor = BTreeCursor::new(None, pager, root_page);
+    state.open_cursor(cursor_id, Cursor::new_btree(cursor));
+    Ok(())
+}
+
+pub fn op_open_dup(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::OpenDup {
+        new_cursor_id,
+        original_cursor_id,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let cursor = {
+        let mut original = state.get_cursor(*original_cursor_id);
+        original.as_btree_mut().open_dup()
+    };
+    state.open_cursor(*new_cursor_id, Cursor::new_btree(cursor));
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
@@ -1194,6 +1271,10 @@ pub fn op_last_await(
         cursor.wait_for_completion()?;
         cursor.is_empty()
     };
+    count_table_access(program, state, *cursor_id, |stats| {
+        stats.full_scans += 1;
+        stats.rows_read += !is_empty as u64;
+    });
     if is_empty {
         state.pc = pc_if_empty.to_offset_int();
     } else {
@@ -1224,6 +1305,10 @@ pub fn op_rewind_await(
         cursor.wait_for_completion()?;
         cursor.is_empty()
     };
+    count_table_access(program, state, *cursor_id, |stats| {
+        stats.full_scans += 1;
+        stats.rows_read += !is_empty as u64;
+    });
     if is_empty {
         state.pc = pc_if_empty.to_offset_int();
     } else {
@@ -1247,25 +1332,7 @@ pub fn op_column(
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    if let Some((index_cursor_id, table_cursor_id)) = state.deferred_seek.take() {
-        let deferred_seek = {
-            let rowid = {
-                let mut index_cursor = state.get_cursor(index_cursor_id);
-                let index_cursor = index_cursor.as_btree_mut();
-                index_cursor.rowid()?
-            };
-            let mut table_cursor = state.get_cursor(table_cursor_id);
-            let table_cursor = table_cursor.as_btree_mut();
-            match table_cursor.seek(SeekKey::TableRowId(rowid.unwrap()), SeekOp::EQ)? {
-                CursorResult::Ok(_) => None,
-                CursorResult::IO => Some((index_cursor_id, table_cursor_id)),
-            }
-        };
-        if let Some(deferred_seek) = deferred_seek {
-            state.deferred_seek = Some(deferred_seek);
-            return Ok(InsnFunctionStepResult::IO);
-        }
-    }
+    return_if_io!(finish_deferred_seek(program, state, *cursor_id));
     let (_, cursor_type) = program.cursor_ref.get(*cursor_id).unwrap();
     match cursor_type {
         CursorType::BTreeTable(_) | CursorType::BTreeIndex(_) => {
@@ -1305,20 +1372,18 @@ pub fn op_column(
             }
         }
         CursorType::Sorter => {
-            let record = {
+            let value = {
                 let mut cursor = state.get_cursor(*cursor_id);
                 let cursor = cursor.as_sorter_mut();
-                cursor.record().cloned()
+                match cursor
+                    .record()
+                    .and_then(|record| record.get_value_opt(*column))
+                {
+                    Some(val) => val.to_owned(),
+                    None => OwnedValue::Null,
+                }
             };
-            if let Some(record) = record {
-                state.registers[*dest] =
-                    Register::OwnedValue(match record.get_value_opt(*column) {
-                        Some(val) => val.to_owned(),
-                        None => OwnedValue::Null,
-                    });
-            } else {
-                state.registers[*dest] = Register::OwnedValue(OwnedValue::Null);
-            }
+            state.registers[*dest] = Register::OwnedValue(value);
         }
         CursorType::Pseudo(_) => {
             let value = {
@@ -1341,6 +1406,31 @@ pub fn op_column(
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_affinity(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::Affinity {
+        start_reg,
+        count,
+        affinities,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    for (reg, aff) in (*start_reg..*start_reg + *count).zip(affinities.chars()) {
+        let value = state.registers[reg].get_owned_value();
+        if let Some(value) = apply_affinity(value, Affinity::from_char(aff)) {
+            state.registers[reg] = Register::OwnedValue(value);
+        }
+    }
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_make_record(
     program: &Program,
     state: &mut ProgramState,
@@ -1356,8 +1446,8 @@ pub fn op_make_record(
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    let record = make_record(&state.registers, start_reg, count);
-    state.registers[*dest_reg] = Register::Record(record);
+    let record = state.make_record(start_reg, count);
+    state.set_record(*dest_reg, record);
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
@@ -1444,6 +1534,7 @@ pub fn op_prev_await(
         cursor.is_empty()
     };
     if !is_empty {
+        count_table_access(program, state, *cursor_id, |stats| stats.rows_read += 1);
         state.pc = pc_if_next.to_offset_int();
     } else {
         state.pc += 1;
@@ -1473,6 +1564,7 @@ pub fn op_next_await(
         cursor.is_empty()
     };
     if !is_empty {
+        count_table_access(program, state, *cursor_id, |stats| stats.rows_read += 1);
         state.pc = pc_if_next.to_offset_int();
     } else {
         state.pc += 1;
@@ -1496,11 +1588,17 @@ pub fn op_halt(
     };
     match *err_code {
         0 => {}
-        SQLITE_CONSTRAINT_PRIMARYKEY => {
-            return Err(LimboError::Constraint(format!(
-                "UNIQUE constraint failed: {} (19)",
-                description
-            )));
+        SQLITE_CONSTRAINT_PRIMARYKEY | SQLITE_CONSTRAINT_UNIQUE => {
+            return Err(LimboError::ConstraintViolation {
+                code: *err_code,
+                message: format!("UNIQUE constraint failed: {}", description),
+            });
+        }
+        code if code & 0xff == SQLITE_CONSTRAINT => {
+            return Err(LimboError::ConstraintViolation {
+                code,
+                message: format!("constraint failed: {}", description),
+            });
         }
         _ => {
             return Err(LimboError::Constraint(format!(
@@ -1509,6 +1607,10 @@ pub fn op_halt(
             )));
         }
     }
+    pager.end_statement();
+    if let Some(conn) = program.connection.upgrade() {
+        conn.changes.borrow_mut().end_statement();
+    }
     match program.halt(pager.clone(), state, mv_store.clone())? {
         StepResult::Done => Ok(InsnFunctionStepResult::Done),
         StepResult::IO => Ok(InsnFunctionStepResult::IO),
@@ -1559,14 +1661,115 @@ pub fn op_transaction(
         }
 
         if updated && matches!(new_transaction_state, TransactionState::Write) {
-            if let LimboResult::Busy = pager.begin_write_tx()? {
+            let result = pager.begin_write_tx().inspect_err(|_| {
+                if matches!(current_state, TransactionState::None) {
+                    // The read transaction started above isn't recorded in the connection yet.
+                    let _ = pager.end_read_tx();
+                }
+            })?;
+            if let LimboResult::Busy = result {
                 tracing::trace!("begin_write_tx busy");
                 return Ok(InsnFunctionStepResult::Busy);
             }
         }
+        let begins_write = updated && matches!(new_transaction_state, TransactionState::Write);
         if updated {
             connection.transaction_state.replace(new_transaction_state);
         }
+        if begins_write
+            && (!*connection.auto_commit.borrow()
+                || connection.schema.read().has_maintained_tables())
+        {
+            connection.snapshot_write_tx();
+        }
+        if (begins_write || state.write_delayed_since.is_some())
+            && pager.throttle_write(&mut state.write_delayed_since)?
+        {
+            return Ok(InsnFunctionStepResult::IO);
+        }
+        if *write {
+            pager.begin_statement();
+            connection.changes.borrow_mut().begin_statement();
+        }
+    }
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_transaction_as_of(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::TransactionAsOf { point } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    if mv_store.is_some() {
+        return Err(LimboError::TxError(
+            "AS OF is not supported with MVCC".to_string(),
+        ));
+    }
+    let connection = program.connection.upgrade().unwrap();
+    if !*connection.auto_commit.borrow()
+        || !matches!(
+            *connection.transaction_state.borrow(),
+            TransactionState::None
+        )
+    {
+        return Err(LimboError::TxError(
+            "cannot read AS OF inside a transaction".to_string(),
+        ));
+    }
+    let frame = match state.registers[*point].get_owned_value() {
+        OwnedValue::Integer(marker) if *marker >= 0 => *marker as u64,
+        time @ OwnedValue::Text(_) => {
+            let Some(at) = parse_naive_date_time(time, instant_to_date_time(pager.io.now())) else {
+                return Err(LimboError::InvalidArgument(format!(
+                    "invalid AS OF timestamp: {}",
+                    time
+                )));
+            };
+            // Timestamps have millisecond precision, like the ones PRAGMA limbo_history lists.
+            let at = at.and_utc().timestamp_millis();
+            // Commits recovered when the database was opened have no time, but they are all
+            // older than the ones that do.
+            pager
+                .wal_history()
+                .iter()
+                .rev()
+                .find(|commit| {
+                    commit.committed_at.is_some_and(|committed_at| {
+                        committed_at.secs * 1000 + committed_at.micros as i64 / 1000 <= at
+                    })
+                })
+                .map(|commit| commit.frame)
+                .ok_or_else(|| {
+                    LimboError::InvalidArgument(format!(
+                        "no commit at or before {} is in the WAL",
+                        time
+                    ))
+                })?
+        }
+        value => {
+            return Err(LimboError::InvalidArgument(format!(
+                "AS OF needs a commit marker or a timestamp, not {}",
+                value
+            )))
+        }
+    };
+    if let LimboResult::Busy = pager.begin_read_tx_as_of(frame)? {
+        return Ok(InsnFunctionStepResult::Busy);
+    }
+    connection.transaction_state.replace(TransactionState::Read);
+    // The statement was planned with the current schema, e.g. with root pages that were
+    // allocated after the commit.
+    if pager.read_schema_cookie()? != pager.db_header.lock().schema_cookie {
+        return Err(LimboError::InvalidArgument(format!(
+            "the schema changed since commit {}",
+            frame
+        )));
     }
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
@@ -1602,6 +1805,10 @@ pub fn op_auto_commit(
             todo!("Rollback is not implemented");
         } else {
             conn.auto_commit.replace(*auto_commit);
+            // BEGIN IMMEDIATE starts writing before the transaction becomes explicit.
+            if !*auto_commit && *conn.transaction_state.borrow() == TransactionState::Write {
+                conn.snapshot_write_tx();
+            }
         }
     } else if !*auto_commit {
         return Err(LimboError::TxError(
@@ -1770,31 +1977,12 @@ pub fn op_row_id(
     let Insn::RowId { cursor_id, dest } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    if let Some((index_cursor_id, table_cursor_id)) = state.deferred_seek.take() {
-        let deferred_seek = {
-            let rowid = {
-                let mut index_cursor = state.get_cursor(index_cursor_id);
-                let index_cursor = index_cursor.as_btree_mut();
-                let rowid = index_cursor.rowid()?;
-                rowid
-            };
-            let mut table_cursor = state.get_cursor(table_cursor_id);
-            let table_cursor = table_cursor.as_btree_mut();
-            let deferred_seek =
-                match table_cursor.seek(SeekKey::TableRowId(rowid.unwrap()), SeekOp::EQ)? {
-                    CursorResult::Ok(_) => None,
-                    CursorResult::IO => Some((index_cursor_id, table_cursor_id)),
-                };
-            deferred_seek
-        };
-        if let Some(deferred_seek) = deferred_seek {
-            state.deferred_seek = Some(deferred_seek);
-            return Ok(InsnFunctionStepResult::IO);
-        }
-    }
+    return_if_io!(finish_deferred_seek(program, state, *cursor_id));
     let mut cursors = state.cursors.borrow_mut();
     if let Some(Cursor::BTree(btree_cursor)) = cursors.get_mut(*cursor_id).unwrap() {
-        if let Some(ref rowid) = btree_cursor.rowid()? {
+        if btree_cursor.get_null_flag() {
+            state.registers[*dest] = Register::OwnedValue(OwnedValue::Null);
+        } else if let Some(ref rowid) = btree_cursor.rowid()? {
             state.registers[*dest] = Register::OwnedValue(OwnedValue::Integer(*rowid as i64));
         } else {
             state.registers[*dest] = Register::OwnedValue(OwnedValue::Null);
@@ -1860,7 +2048,9 @@ pub fn op_seek_rowid(
             None => target_pc.to_offset_int(),
         }
     };
+    let found = pc != target_pc.to_offset_int();
     state.pc = pc;
+    count_seek(program, state, *cursor_id, found);
     Ok(InsnFunctionStepResult::Step)
 }
 
@@ -1878,7 +2068,7 @@ pub fn op_deferred_seek(
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    state.deferred_seek = Some((*index_cursor_id, *table_cursor_id));
+    state.deferred_seeks[*table_cursor_id] = Some(*index_cursor_id);
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
@@ -1905,9 +2095,10 @@ pub fn op_seek_ge(
         let found = {
             let mut cursor = state.get_cursor(*cursor_id);
             let cursor = cursor.as_btree_mut();
-            let record_from_regs = make_record(&state.registers, start_reg, num_regs);
+            let record_from_regs = state.make_record(start_reg, num_regs);
             let found =
                 return_if_io!(cursor.seek(SeekKey::IndexKey(&record_from_regs), SeekOp::GE));
+            state.recycle_record(record_from_regs);
             found
         };
         if !found {
@@ -1946,6 +2137,8 @@ pub fn op_seek_ge(
         };
         state.pc = pc;
     }
+    let found = state.pc != target_pc.to_offset_int();
+    count_seek(program, state, *cursor_id, found);
     Ok(InsnFunctionStepResult::Step)
 }
 
@@ -1971,9 +2164,10 @@ pub fn op_seek_gt(
         let found = {
             let mut cursor = state.get_cursor(*cursor_id);
             let cursor = cursor.as_btree_mut();
-            let record_from_regs = make_record(&state.registers, start_reg, num_regs);
+            let record_from_regs = state.make_record(start_reg, num_regs);
             let found =
                 return_if_io!(cursor.seek(SeekKey::IndexKey(&record_from_regs), SeekOp::GT));
+            state.recycle_record(record_from_regs);
             found
         };
         if !found {
@@ -2013,6 +2207,134 @@ pub fn op_seek_gt(
         };
         state.pc = pc;
     }
+    let found = state.pc != target_pc.to_offset_int();
+    count_seek(program, state, *cursor_id, found);
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_seek_le(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::SeekLE {
+        cursor_id,
+        start_reg,
+        num_regs,
+        target_pc,
+        is_index,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    assert!(target_pc.is_offset());
+    if *is_index {
+        let found = {
+            let mut cursor = state.get_cursor(*cursor_id);
+            let cursor = cursor.as_btree_mut();
+            let record_from_regs = state.make_record(start_reg, num_regs);
+            let found =
+                return_if_io!(cursor.seek(SeekKey::IndexKey(&record_from_regs), SeekOp::LE));
+            state.recycle_record(record_from_regs);
+            found
+        };
+        if !found {
+            state.pc = target_pc.to_offset_int();
+        } else {
+            state.pc += 1;
+        }
+    } else {
+        let pc = {
+            let mut cursor = state.get_cursor(*cursor_id);
+            let cursor = cursor.as_btree_mut();
+            match state.registers[*start_reg].get_owned_value() {
+                // No integer is less than null, so there is nothing to seek to
+                OwnedValue::Null => target_pc.to_offset_int(),
+                OwnedValue::Integer(rowid) => {
+                    let found =
+                        return_if_io!(cursor.seek(SeekKey::TableRowId(*rowid as u64), SeekOp::LE));
+                    if !found {
+                        target_pc.to_offset_int()
+                    } else {
+                        state.pc + 1
+                    }
+                }
+                _ => {
+                    return Err(LimboError::InternalError(
+                        "SeekLE: the value in the register is not an integer".into(),
+                    ));
+                }
+            }
+        };
+        state.pc = pc;
+    }
+    let found = state.pc != target_pc.to_offset_int();
+    count_seek(program, state, *cursor_id, found);
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_seek_lt(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::SeekLT {
+        cursor_id,
+        start_reg,
+        num_regs,
+        target_pc,
+        is_index,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    assert!(target_pc.is_offset());
+    if *is_index {
+        let found = {
+            let mut cursor = state.get_cursor(*cursor_id);
+            let cursor = cursor.as_btree_mut();
+            let record_from_regs = state.make_record(start_reg, num_regs);
+            let found =
+                return_if_io!(cursor.seek(SeekKey::IndexKey(&record_from_regs), SeekOp::LT));
+            state.recycle_record(record_from_regs);
+            found
+        };
+        if !found {
+            state.pc = target_pc.to_offset_int();
+        } else {
+            state.pc += 1;
+        }
+    } else {
+        let pc = {
+            let mut cursor = state.get_cursor(*cursor_id);
+            let cursor = cursor.as_btree_mut();
+            match state.registers[*start_reg].get_owned_value() {
+                // No integer is less than null, so there is nothing to seek to
+                OwnedValue::Null => target_pc.to_offset_int(),
+                OwnedValue::Integer(rowid) => {
+                    let found =
+                        return_if_io!(cursor.seek(SeekKey::TableRowId(*rowid as u64), SeekOp::LT));
+                    if !found {
+                        target_pc.to_offset_int()
+                    } else {
+                        state.pc + 1
+                    }
+                }
+                _ => {
+                    return Err(LimboError::InternalError(
+                        "SeekLT: the value in the register is not an integer".into(),
+                    ));
+                }
+            }
+        };
+        state.pc = pc;
+    }
+    let found = state.pc != target_pc.to_offset_int();
+    count_seek(program, state, *cursor_id, found);
     Ok(InsnFunctionStepResult::Step)
 }
 
@@ -2036,7 +2358,7 @@ pub fn op_idx_ge(
     let pc = {
         let mut cursor = state.get_cursor(*cursor_id);
         let cursor = cursor.as_btree_mut();
-        let record_from_regs = make_record(&state.registers, start_reg, num_regs);
+        let record_from_regs = state.make_record(start_reg, num_regs);
         let pc = if let Some(ref idx_record) = *cursor.record() {
             // Compare against the same number of values
             let ord = idx_record.get_values()[..record_from_regs.len()]
@@ -2050,6 +2372,7 @@ pub fn op_idx_ge(
         } else {
             target_pc.to_offset_int()
         };
+        state.recycle_record(record_from_regs);
         pc
     };
     state.pc = pc;
@@ -2094,7 +2417,7 @@ pub fn op_idx_le(
     let pc = {
         let mut cursor = state.get_cursor(*cursor_id);
         let cursor = cursor.as_btree_mut();
-        let record_from_regs = make_record(&state.registers, start_reg, num_regs);
+        let record_from_regs = state.make_record(start_reg, num_regs);
         let pc = if let Some(ref idx_record) = *cursor.record() {
             // Compare against the same number of values
             let ord = idx_record.get_values()[..record_from_regs.len()]
@@ -2108,6 +2431,7 @@ pub fn op_idx_le(
         } else {
             target_pc.to_offset_int()
         };
+        state.recycle_record(record_from_regs);
         pc
     };
     state.pc = pc;
@@ -2134,7 +2458,7 @@ pub fn op_idx_gt(
     let pc = {
         let mut cursor = state.get_cursor(*cursor_id);
         let cursor = cursor.as_btree_mut();
-        let record_from_regs = make_record(&state.registers, start_reg, num_regs);
+        let record_from_regs = state.make_record(start_reg, num_regs);
         let pc = if let Some(ref idx_record) = *cursor.record() {
             // Compare against the same number of values
             let ord = idx_record.get_values()[..record_from_regs.len()]
@@ -2148,6 +2472,7 @@ pub fn op_idx_gt(
         } else {
             target_pc.to_offset_int()
         };
+        state.recycle_record(record_from_regs);
         pc
     };
     state.pc = pc;
@@ -2174,7 +2499,7 @@ pub fn op_idx_lt(
     let pc = {
         let mut cursor = state.get_cursor(*cursor_id);
         let cursor = cursor.as_btree_mut();
-        let record_from_regs = make_record(&state.registers, start_reg, num_regs);
+        let record_from_regs = state.make_record(start_reg, num_regs);
         let pc = if let Some(ref idx_record) = *cursor.record() {
             // Compare against the same number of values
             let ord = idx_record.get_values()[..record_from_regs.len()]
@@ -2188,6 +2513,7 @@ pub fn op_idx_lt(
         } else {
             target_pc.to_offset_int()
         };
+        state.recycle_record(record_from_regs);
         pc
     };
     state.pc = pc;
@@ -2252,28 +2578,8 @@ pub fn op_agg_step(
             AggFunc::Count | AggFunc::Count0 => {
                 Register::Aggregate(AggContext::Count(OwnedValue::Integer(0)))
             }
-            AggFunc::Max => {
-                let col = state.registers[*col].get_owned_value();
-                match col {
-                    OwnedValue::Integer(_) => Register::Aggregate(AggContext::Max(None)),
-                    OwnedValue::Float(_) => Register::Aggregate(AggContext::Max(None)),
-                    OwnedValue::Text(_) => Register::Aggregate(AggContext::Max(None)),
-                    _ => {
-                        unreachable!();
-                    }
-                }
-            }
-            AggFunc::Min => {
-                let col = state.registers[*col].get_owned_value();
-                match col {
-                    OwnedValue::Integer(_) => Register::Aggregate(AggContext::Min(None)),
-                    OwnedValue::Float(_) => Register::Aggregate(AggContext::Min(None)),
-                    OwnedValue::Text(_) => Register::Aggregate(AggContext::Min(None)),
-                    _ => {
-                        unreachable!();
-                    }
-                }
-            }
+            AggFunc::Max => Register::Aggregate(AggContext::Max(None)),
+            AggFunc::Min => Register::Aggregate(AggContext::Min(None)),
             AggFunc::GroupConcat | AggFunc::StringAgg => {
                 Register::Aggregate(AggContext::GroupConcat(OwnedValue::build_text("")))
             }
@@ -2322,11 +2628,19 @@ pub fn op_agg_step(
             let AggContext::Sum(acc) = agg.borrow_mut() else {
                 unreachable!();
             };
-            match col {
-                Register::OwnedValue(owned_value) => {
-                    *acc += owned_value;
-                }
-                _ => unreachable!(),
+            if let Some(value) = sum_operand(col.get_owned_value()) {
+                *acc = match (&*acc, value) {
+                    (OwnedValue::Null, value) => value,
+                    (OwnedValue::Integer(lhs), OwnedValue::Integer(rhs)) => {
+                        // total() starts from a real, so only sum() can overflow
+                        OwnedValue::Integer(
+                            lhs.checked_add(rhs).ok_or(LimboError::IntegerOverflow)?,
+                        )
+                    }
+                    (lhs, rhs) => {
+                        OwnedValue::Float(sum_operand_as_f64(lhs) + sum_operand_as_f64(&rhs))
+                    }
+                };
             }
         }
         AggFunc::Count | AggFunc::Count0 => {
@@ -2358,28 +2672,17 @@ pub fn op_agg_step(
                 unreachable!();
             };
 
+            // NULLs are skipped, and values of different types compare like in ORDER BY.
             match (acc.as_mut(), col.get_owned_value()) {
+                (_, OwnedValue::Null) => {}
                 (None, value) => {
                     *acc = Some(value.clone());
                 }
-                (Some(OwnedValue::Integer(ref mut current_max)), OwnedValue::Integer(value)) => {
-                    if *value > *current_max {
+                (Some(current_max), value) => {
+                    if value > current_max {
                         *current_max = value.clone();
                     }
                 }
-                (Some(OwnedValue::Float(ref mut current_max)), OwnedValue::Float(value)) => {
-                    if *value > *current_max {
-                        *current_max = *value;
-                    }
-                }
-                (Some(OwnedValue::Text(ref mut current_max)), OwnedValue::Text(value)) => {
-                    if value.value > current_max.value {
-                        *current_max = value.clone();
-                    }
-                }
-                _ => {
-                    eprintln!("Unexpected types in max aggregation");
-                }
             }
         }
         AggFunc::Min => {
@@ -2391,28 +2694,17 @@ pub fn op_agg_step(
                 unreachable!();
             };
 
+            // NULLs are skipped, and values of different types compare like in ORDER BY.
             match (acc.as_mut(), col.get_owned_value()) {
+                (_, OwnedValue::Null) => {}
                 (None, value) => {
-                    *acc.borrow_mut() = Some(value.clone());
-                }
-                (Some(OwnedValue::Integer(ref mut current_min)), OwnedValue::Integer(value)) => {
-                    if *value < *current_min {
-                        *current_min = *value;
-                    }
-                }
-                (Some(OwnedValue::Float(ref mut current_min)), OwnedValue::Float(value)) => {
-                    if *value < *current_min {
-                        *current_min = *value;
-                    }
+                    *acc = Some(value.clone());
                 }
-                (Some(OwnedValue::Text(ref mut current_min)), OwnedValue::Text(text)) => {
-                    if text.value < current_min.value {
-                        *current_min = text.clone();
+                (Some(current_min), value) => {
+                    if value < current_min {
+                        *current_min = value.clone();
                     }
                 }
-                _ => {
-                    eprintln!("Unexpected types in min aggregation");
-                }
             }
         }
         AggFunc::GroupConcat | AggFunc::StringAgg => {
@@ -2541,12 +2833,8 @@ pub fn op_agg_final(
                 let AggContext::Sum(acc) = agg.borrow_mut() else {
                     unreachable!();
                 };
-                let value = match acc {
-                    OwnedValue::Integer(i) => OwnedValue::Integer(*i),
-                    OwnedValue::Float(f) => OwnedValue::Float(*f),
-                    _ => OwnedValue::Float(0.0),
-                };
-                state.registers[*register] = Register::OwnedValue(value);
+                // sum() of only NULLs is NULL, while total() started from 0.0
+                state.registers[*register] = Register::OwnedValue(acc.clone());
             }
             AggFunc::Count | AggFunc::Count0 => {
                 let AggContext::Count(count) = agg.borrow_mut() else {
@@ -2655,6 +2943,7 @@ pub fn op_sorter_open(
     let Insn::SorterOpen {
         cursor_id,
         columns: _,
+        max_rows,
         order,
     } = insn
     else {
@@ -2664,16 +2953,18 @@ pub fn op_sorter_open(
         .get_values()
         .iter()
         .map(|v| match v {
-            OwnedValue::Integer(i) => *i == 0,
+            OwnedValue::Integer(i) => SortKey {
+                ascending: *i & 1 == 0,
+                collation: CollationSeq::from_i64(*i >> 1),
+            },
             _ => unreachable!(),
         })
         .collect();
-    let cursor = Sorter::new(order);
-    let mut cursors = state.cursors.borrow_mut();
-    cursors
-        .get_mut(*cursor_id)
-        .unwrap()
-        .replace(Cursor::new_sorter(cursor));
+    let mut cursor = Sorter::new(order, *max_rows);
+    if let Some(memory_limit) = pager.memory_limit() {
+        cursor.set_memory_limit(pager.io.clone(), memory_limit);
+    }
+    state.open_cursor(*cursor_id, Cursor::new_sorter(cursor));
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
@@ -2696,7 +2987,7 @@ pub fn op_sorter_data(
     let record = {
         let mut cursor = state.get_cursor(*cursor_id);
         let cursor = cursor.as_sorter_mut();
-        cursor.record().map(|r| r.clone())
+        cursor.record().map(|r| state.clone_record(r))
     };
     let record = match record {
         Some(record) => record,
@@ -2705,10 +2996,13 @@ pub fn op_sorter_data(
             return Ok(InsnFunctionStepResult::Step);
         }
     };
-    state.registers[*dest_reg] = Register::Record(record.clone());
+    let copy = state.clone_record(&record);
+    state.set_record(*dest_reg, copy);
     {
         let mut pseudo_cursor = state.get_cursor(*pseudo_cursor);
-        pseudo_cursor.as_pseudo_mut().insert(record);
+        if let Some(previous) = pseudo_cursor.as_pseudo_mut().insert(record) {
+            state.recycle_record(previous);
+        }
     }
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
@@ -2732,10 +3026,12 @@ pub fn op_sorter_insert(
         let mut cursor = state.get_cursor(*cursor_id);
         let cursor = cursor.as_sorter_mut();
         let record = match &state.registers[*record_reg] {
-            Register::Record(record) => record,
+            Register::Record(record) => state.clone_record(record),
             _ => unreachable!("SorterInsert on non-record register"),
         };
-        cursor.insert(record);
+        for dropped in cursor.insert(record)? {
+            state.recycle_record(dropped);
+        }
     }
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
@@ -2760,7 +3056,9 @@ pub fn op_sorter_sort(
         let cursor = cursor.as_sorter_mut();
         let is_empty = cursor.is_empty();
         if !is_empty {
-            cursor.sort();
+            for dropped in cursor.sort()? {
+                state.recycle_record(dropped);
+            }
         }
         is_empty
     };
@@ -2790,7 +3088,9 @@ pub fn op_sorter_next(
     let has_more = {
         let mut cursor = state.get_cursor(*cursor_id);
         let cursor = cursor.as_sorter_mut();
-        cursor.next();
+        if let Some(previous) = cursor.next()? {
+            state.recycle_record(previous);
+        }
         cursor.has_more()
     };
     if has_more {
@@ -3237,7 +3537,10 @@ pub fn op_function(
                     ScalarFunc::Typeof => Some(exec_typeof(reg_value)),
                     ScalarFunc::Unicode => Some(exec_unicode(reg_value)),
                     ScalarFunc::Quote => Some(exec_quote(reg_value)),
-                    ScalarFunc::RandomBlob => Some(exec_randomblob(reg_value)),
+                    ScalarFunc::RandomBlob => {
+                        let conn = program.connection.upgrade().unwrap();
+                        Some(exec_randomblob(reg_value, || conn.random_number()))
+                    }
                     ScalarFunc::ZeroBlob => Some(exec_zeroblob(reg_value)),
                     ScalarFunc::Soundex => Some(exec_soundex(reg_value)),
                     _ => unreachable!(),
@@ -3259,7 +3562,8 @@ pub fn op_function(
                 state.registers[*dest] = Register::OwnedValue(result);
             }
             ScalarFunc::Random => {
-                state.registers[*dest] = Register::OwnedValue(exec_random());
+                let conn = program.connection.upgrade().unwrap();
+                state.registers[*dest] = Register::OwnedValue(exec_random(|| conn.random_number()));
             }
             ScalarFunc::Trim => {
                 let reg_value = &state.registers[*start_reg];
@@ -3346,12 +3650,15 @@ pub fn op_function(
                 state.registers[*dest] = Register::OwnedValue(result);
             }
             ScalarFunc::Date => {
-                let result = exec_date(&state.registers[*start_reg..*start_reg + arg_count]);
+                let result = exec_date(
+                    &state.registers[*start_reg..*start_reg + arg_count],
+                    instant_to_date_time(pager.io.now()),
+                );
                 state.registers[*dest] = Register::OwnedValue(result);
             }
             ScalarFunc::Time => {
                 let values = &state.registers[*start_reg..*start_reg + arg_count];
-                let result = exec_time(values);
+                let result = exec_time(values, instant_to_date_time(pager.io.now()));
                 state.registers[*dest] = Register::OwnedValue(result);
             }
             ScalarFunc::TotalChanges => {
@@ -3360,53 +3667,25 @@ pub fn op_function(
                 state.registers[*dest] = Register::OwnedValue(OwnedValue::Integer(total_changes));
             }
             ScalarFunc::DateTime => {
-                let result =
-                    exec_datetime_full(&state.registers[*start_reg..*start_reg + arg_count]);
+                let result = exec_datetime_full(
+                    &state.registers[*start_reg..*start_reg + arg_count],
+                    instant_to_date_time(pager.io.now()),
+                );
                 state.registers[*dest] = Register::OwnedValue(result);
             }
             ScalarFunc::JulianDay => {
-                if *start_reg == 0 {
-                    let julianday: String = exec_julianday(&OwnedValue::build_text("now"))?;
-                    state.registers[*dest] =
-                        Register::OwnedValue(OwnedValue::build_text(&julianday));
-                } else {
-                    let datetime_value = &state.registers[*start_reg];
-                    let julianday = exec_julianday(datetime_value.get_owned_value());
-                    match julianday {
-                        Ok(time) => {
-                            state.registers[*dest] =
-                                Register::OwnedValue(OwnedValue::build_text(&time))
-                        }
-                        Err(e) => {
-                            return Err(LimboError::ParseError(format!(
-                                "Error encountered while parsing datetime value: {}",
-                                e
-                            )));
-                        }
-                    }
-                }
+                let result = exec_julianday(
+                    &state.registers[*start_reg..*start_reg + arg_count],
+                    instant_to_date_time(pager.io.now()),
+                );
+                state.registers[*dest] = Register::OwnedValue(result);
             }
             ScalarFunc::UnixEpoch => {
-                if *start_reg == 0 {
-                    let unixepoch: String = exec_unixepoch(&OwnedValue::build_text("now"))?;
-                    state.registers[*dest] =
-                        Register::OwnedValue(OwnedValue::build_text(&unixepoch));
-                } else {
-                    let datetime_value = &state.registers[*start_reg];
-                    let unixepoch = exec_unixepoch(datetime_value.get_owned_value());
-                    match unixepoch {
-                        Ok(time) => {
-                            state.registers[*dest] =
-                                Register::OwnedValue(OwnedValue::build_text(&time))
-                        }
-                        Err(e) => {
-                            return Err(LimboError::ParseError(format!(
-                                "Error encountered while parsing datetime value: {}",
-                                e
-                            )));
-                        }
-                    }
-                }
+                let result = exec_unixepoch(
+                    &state.registers[*start_reg..*start_reg + arg_count],
+                    instant_to_date_time(pager.io.now()),
+                );
+                state.registers[*dest] = Register::OwnedValue(result);
             }
             ScalarFunc::SqliteVersion => {
                 let version_integer: i64 = DATABASE_VERSION.get().unwrap().parse()?;
@@ -3441,19 +3720,23 @@ pub fn op_function(
                 }
             }
             ScalarFunc::StrfTime => {
-                let result = exec_strftime(&state.registers[*start_reg..*start_reg + arg_count]);
+                let result = exec_strftime(
+                    &state.registers[*start_reg..*start_reg + arg_count],
+                    instant_to_date_time(pager.io.now()),
+                );
                 state.registers[*dest] = Register::OwnedValue(result);
             }
             ScalarFunc::Printf => {
                 let result = exec_printf(&state.registers[*start_reg..*start_reg + arg_count])?;
                 state.registers[*dest] = Register::OwnedValue(result);
             }
-            ScalarFunc::Likely => {
+            ScalarFunc::Likely | ScalarFunc::Unlikely | ScalarFunc::Likelihood => {
                 let value = &state.registers[*start_reg].borrow_mut();
                 let result = exec_likely(value.get_owned_value());
                 state.registers[*dest] = Register::OwnedValue(result);
             }
         },
+        #[cfg(feature = "vector")]
         crate::function::Func::Vector(vector_func) => match vector_func {
             VectorFunc::Vector => {
                 let result = vector32(&state.registers[*start_reg..*start_reg + arg_count])?;
@@ -3476,6 +3759,11 @@ pub fn op_function(
                     vector_distance_cos(&state.registers[*start_reg..*start_reg + arg_count])?;
                 state.registers[*dest] = Register::OwnedValue(result);
             }
+            VectorFunc::VectorDistanceL2 => {
+                let result =
+                    vector_distance_l2(&state.registers[*start_reg..*start_reg + arg_count])?;
+                state.registers[*dest] = Register::OwnedValue(result);
+            }
         },
         crate::function::Func::External(f) => match f.func {
             ExtFunc::Scalar(f) => {
@@ -3508,6 +3796,14 @@ pub fn op_function(
                     }
                 }
             }
+            #[cfg(feature = "wasm_ext")]
+            ExtFunc::Wasm(ref wasm) => {
+                let args = state.registers[*start_reg..*start_reg + arg_count]
+                    .iter()
+                    .map(|reg| reg.get_owned_value().clone())
+                    .collect::<Vec<_>>();
+                state.registers[*dest] = Register::OwnedValue(wasm.call(&args)?);
+            }
             _ => unreachable!("aggregate called in scalar context"),
         },
         crate::function::Func::Math(math_func) => match math_func.arity() {
@@ -3671,6 +3967,43 @@ pub fn op_insert_async(
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
+    return_if_io!(finish_deferred_seek(program, state, *cursor));
+    // The insert may take several steps when it has to wait for I/O, by which point the row
+    // it replaces is gone, so the change is captured on the first one.
+    if state.pending_change.is_none() {
+        if let Some(table) = change_capture_table(program, *cursor) {
+            let record = match &state.registers[*record_reg] {
+                Register::Record(r) => r,
+                _ => unreachable!("Not a record! Cannot insert a non record value."),
+            };
+            let key = match &state.registers[*key_reg].get_owned_value() {
+                OwnedValue::Integer(i) => *i,
+                _ => unreachable!("expected integer key"),
+            };
+            let old = {
+                let mut cursor = state.get_cursor(*cursor);
+                let cursor = cursor.as_btree_mut();
+                match cursor.rowid()? {
+                    Some(rowid) if rowid as i64 == key => cursor
+                        .record()
+                        .as_ref()
+                        .map(|old| cdc::row_values(&table, key, old)),
+                    _ => None,
+                }
+            };
+            state.pending_change = Some(RowChange {
+                table: table.name.clone(),
+                op: if old.is_some() {
+                    ChangeOp::Update
+                } else {
+                    ChangeOp::Insert
+                },
+                rowid: key,
+                old,
+                new: Some(cdc::row_values(&table, key, record)),
+            });
+        }
+    }
     {
         let mut cursor = state.get_cursor(*cursor);
         let cursor = cursor.as_btree_mut();
@@ -3705,17 +4038,29 @@ pub fn op_insert_await(
         let mut cursor = state.get_cursor(*cursor_id);
         let cursor = cursor.as_btree_mut();
         cursor.wait_for_completion()?;
+    }
+    publish_pending_change(program, state);
+    let inserted = {
+        let mut cursor = state.get_cursor(*cursor_id);
+        let cursor = cursor.as_btree_mut();
         // Only update last_insert_rowid for regular table inserts, not schema modifications
-        if cursor.root_page() != 1 {
-            if let Some(rowid) = cursor.rowid()? {
+        // or the rows of transient tables
+        if cursor.root_page() != 1 && !is_ephemeral_table(program, *cursor_id) {
+            let rowid = cursor.rowid()?;
+            if let Some(rowid) = rowid {
                 if let Some(conn) = program.connection.upgrade() {
                     conn.update_last_rowid(rowid);
                 }
-                let prev_changes = program.n_change.get();
-                program.n_change.set(prev_changes + 1);
             }
+            rowid.is_some()
+        } else {
+            false
         }
+    };
+    if inserted {
+        state.n_change += 1;
     }
+    record_table_write(program, state, *cursor_id);
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
@@ -3730,6 +4075,26 @@ pub fn op_delete_async(
     let Insn::DeleteAsync { cursor_id } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
+    return_if_io!(finish_deferred_seek(program, state, *cursor_id));
+    if state.pending_change.is_none() {
+        if let Some(table) = change_capture_table(program, *cursor_id) {
+            let change = {
+                let mut cursor = state.get_cursor(*cursor_id);
+                let cursor = cursor.as_btree_mut();
+                cursor.rowid()?.map(|rowid| RowChange {
+                    table: table.name.clone(),
+                    op: ChangeOp::Delete,
+                    rowid: rowid as i64,
+                    old: cursor
+                        .record()
+                        .as_ref()
+                        .map(|old| cdc::row_values(&table, rowid as i64, old)),
+                    new: None,
+                })
+            };
+            state.pending_change = change;
+        }
+    }
     {
         let mut cursor = state.get_cursor(*cursor_id);
         let cursor = cursor.as_btree_mut();
@@ -3768,9 +4133,15 @@ pub fn op_idx_insert_async(
                 // check for uniqueness violation
                 match cursor.key_exists_in_index(record)? {
                     CursorResult::Ok(true) => {
-                        return Err(LimboError::Constraint(
-                            "UNIQUE constraint failed: duplicate key".into(),
-                        ))
+                        let columns = index_meta
+                            .columns
+                            .iter()
+                            .map(|column| format!("{}.{}", index_meta.table_name, column.name))
+                            .collect::<Vec<_>>();
+                        return Err(LimboError::ConstraintViolation {
+                            code: SQLITE_CONSTRAINT_UNIQUE,
+                            message: format!("UNIQUE constraint failed: {}", columns.join(", ")),
+                        });
                     }
                     CursorResult::IO => return Ok(InsnFunctionStepResult::IO),
                     CursorResult::Ok(false) => {}
@@ -3821,12 +4192,124 @@ pub fn op_delete_await(
         let cursor = cursor.as_btree_mut();
         cursor.wait_for_completion()?;
     }
-    let prev_changes = program.n_change.get();
-    program.n_change.set(prev_changes + 1);
+    publish_pending_change(program, state);
+    record_table_write(program, state, *cursor_id);
+    state.n_change += 1;
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
 
+/// The table written through `cursor_id` if its changes are captured for subscribers.
+fn change_capture_table(program: &Program, cursor_id: usize) -> Option<Rc<BTreeTable>> {
+    let Some((_, CursorType::BTreeTable(table))) = program.cursor_ref.get(cursor_id) else {
+        return None;
+    };
+    let conn = program.connection.upgrade()?;
+    if !cdc::captures_table(table) {
+        return None;
+    }
+    // Materialized views and vector indexes are maintained from the changes of their tables.
+    if !conn.capturing_changes() && !conn.schema.read().is_maintained_from(&table.name) {
+        return None;
+    }
+    Some(table.clone())
+}
+
+/// Adds the change of a completed row write to the transaction's changes.
+fn publish_pending_change(program: &Program, state: &mut ProgramState) {
+    if let Some(change) = state.pending_change.take() {
+        if let Some(conn) = program.connection.upgrade() {
+            conn.changes.borrow_mut().push(change);
+        }
+    }
+}
+
+/// Counts a row written through a table cursor towards the staleness of its statistics.
+fn record_table_write(program: &Program, state: &mut ProgramState, cursor_id: usize) {
+    let Some((_, CursorType::BTreeTable(table))) = program.cursor_ref.get(cursor_id) else {
+        return;
+    };
+    if table.ephemeral {
+        return;
+    }
+    if let Some(conn) = program.connection.upgrade() {
+        conn.record_table_write(&table.name);
+    }
+    count_table_access(program, state, cursor_id, |stats| stats.rows_written += 1);
+}
+
+/// Counts an access through a cursor towards the [TableStats] of its table, or of the table
+/// of its index. Transient tables and indexes aren't counted.
+fn count_table_access(
+    program: &Program,
+    state: &mut ProgramState,
+    cursor_id: usize,
+    count: impl FnOnce(&mut TableStats),
+) {
+    let counter = &mut state.table_stats[cursor_id];
+    if counter.is_none() {
+        let table_name = match program.cursor_ref.get(cursor_id) {
+            Some((_, CursorType::BTreeTable(table))) if !table.ephemeral => &table.name,
+            Some((_, CursorType::BTreeIndex(index))) if !index.ephemeral => &index.table_name,
+            _ => return,
+        };
+        let Some(conn) = program.connection.upgrade() else {
+            return;
+        };
+        *counter = Some(conn.table_stats_counter(table_name));
+    }
+    if let Some(counter) = counter {
+        let mut stats = counter.get();
+        count(&mut stats);
+        counter.set(stats);
+    }
+}
+
+/// Moves the table cursor `cursor_id` to the row that a DeferredSeek postponed looking up, if
+/// there is one. Reads of the index cursor leave the seek pending.
+fn finish_deferred_seek(
+    program: &Program,
+    state: &mut ProgramState,
+    cursor_id: usize,
+) -> Result<CursorResult<()>> {
+    let Some(index_cursor_id) = state.deferred_seeks[cursor_id] else {
+        return Ok(CursorResult::Ok(()));
+    };
+    let rowid = {
+        let mut index_cursor = state.get_cursor(index_cursor_id);
+        let index_cursor = index_cursor.as_btree_mut();
+        index_cursor.rowid()?
+    };
+    {
+        let mut table_cursor = state.get_cursor(cursor_id);
+        let table_cursor = table_cursor.as_btree_mut();
+        if let CursorResult::IO =
+            table_cursor.seek(SeekKey::TableRowId(rowid.unwrap()), SeekOp::EQ)?
+        {
+            return Ok(CursorResult::IO);
+        }
+    }
+    state.deferred_seeks[cursor_id] = None;
+    count_seek(program, state, cursor_id, true);
+    Ok(CursorResult::Ok(()))
+}
+
+/// Counts a seek through a cursor, and the row it found.
+fn count_seek(program: &Program, state: &mut ProgramState, cursor_id: usize, found: bool) {
+    count_table_access(program, state, cursor_id, |stats| {
+        stats.seeks += 1;
+        stats.rows_read += found as u64;
+    });
+}
+
+/// Whether the cursor writes to a transient table of the statement, like a materialized subquery.
+fn is_ephemeral_table(program: &Program, cursor_id: usize) -> bool {
+    matches!(
+        program.cursor_ref.get(cursor_id),
+        Some((_, CursorType::BTreeTable(table))) if table.ephemeral
+    )
+}
+
 pub fn op_new_rowid(
     program: &Program,
     state: &mut ProgramState,
@@ -3843,8 +4326,8 @@ pub fn op_new_rowid(
     let rowid = {
         let mut cursor = state.get_cursor(*cursor);
         let cursor = cursor.as_btree_mut();
-        // TODO: make io handle rng
-        let rowid = return_if_io!(get_new_rowid(cursor, thread_rng()));
+        let rng = StdRng::seed_from_u64(pager.io.generate_random_number() as u64);
+        let rowid = return_if_io!(get_new_rowid(cursor, rng));
         rowid
     };
     state.registers[*rowid_reg] = Register::OwnedValue(OwnedValue::Integer(rowid));
@@ -3925,6 +4408,7 @@ pub fn op_not_exists(
         let exists = return_if_io!(cursor.exists(state.registers[*rowid_reg].get_owned_value()));
         exists
     };
+    count_seek(program, state, *cursor, exists);
     if exists {
         state.pc += 1;
     } else {
@@ -4005,9 +4489,6 @@ pub fn op_open_write_async(
             }
         },
     };
-    let (_, cursor_type) = program.cursor_ref.get(*cursor_id).unwrap();
-    let mut cursors = state.cursors.borrow_mut();
-    let is_index = cursor_type.is_index();
     let mv_cursor = match state.mv_tx_id {
         Some(tx_id) => {
             let table_id = root_page;
@@ -4019,18 +4500,15 @@ pub fn op_open_write_async(
         }
         None => None,
     };
-    let cursor = BTreeCursor::new(mv_cursor, pager.clone(), root_page as usize);
-    if is_index {
-        cursors
-            .get_mut(*cursor_id)
-            .unwrap()
-            .replace(Cursor::new_btree(cursor));
-    } else {
-        cursors
-            .get_mut(*cursor_id)
-            .unwrap()
-            .replace(Cursor::new_btree(cursor));
+    if root_page == 1 && mv_store.is_none() {
+        // Like in SQLite, the schema cookie changes with every change to sqlite_schema, which
+        // tells apart versions of the database with different schemas.
+        let mut header = pager.db_header.lock();
+        header.schema_cookie = header.schema_cookie.wrapping_add(1);
+        pager.write_header_page(&header)?;
     }
+    let cursor = BTreeCursor::new(mv_cursor, pager.clone(), root_page as usize);
+    state.open_cursor(*cursor_id, Cursor::new_btree(cursor));
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
@@ -4109,8 +4587,14 @@ pub fn op_destroy(
     if *is_temp == 1 {
         todo!("temp databases not implemented yet.");
     }
-    let mut cursor = BTreeCursor::new(None, pager.clone(), *root);
-    cursor.btree_destroy()?;
+    let mut cursor = state
+        .destroying
+        .take()
+        .unwrap_or_else(|| BTreeCursor::new(None, pager.clone(), *root));
+    if let CursorResult::IO = cursor.btree_destroy()? {
+        state.destroying = Some(cursor);
+        return Ok(InsnFunctionStepResult::IO);
+    }
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
@@ -4153,8 +4637,7 @@ pub fn op_close(
     let Insn::Close { cursor_id } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    let mut cursors = state.cursors.borrow_mut();
-    cursors.get_mut(*cursor_id).unwrap().take();
+    state.close_cursor(*cursor_id);
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
@@ -4180,6 +4663,31 @@ pub fn op_is_null(
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_count(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::Count {
+        cursor_id,
+        target_reg,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let count = {
+        let mut cursor = state.get_cursor(*cursor_id);
+        let cursor = cursor.as_btree_mut();
+        return_if_io!(cursor.count())
+    };
+    count_table_access(program, state, *cursor_id, |stats| stats.full_scans += 1);
+    state.registers[*target_reg] = Register::OwnedValue(OwnedValue::Integer(count as i64));
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_page_count(
     program: &Program,
     state: &mut ProgramState,
@@ -4205,6 +4713,31 @@ pub fn op_page_count(
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_integrity_ck(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::IntegrityCk {
+        check,
+        message_register,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let errors = check_integrity(pager, check)?;
+    let message = if errors.is_empty() {
+        "ok".to_string()
+    } else {
+        errors.join("\n")
+    };
+    state.registers[*message_register] = Register::OwnedValue(OwnedValue::build_text(&message));
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_parse_schema(
     program: &Program,
     state: &mut ProgramState,
@@ -4238,6 +4771,183 @@ pub fn op_parse_schema(
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_materialized_view(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    _pager: &Rc<Pager>,
+    _mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::MaterializedView { statement } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let conn = program.connection.upgrade();
+    let conn = conn.as_ref().unwrap();
+    matview::run(conn, statement)?;
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_vector_index(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    _pager: &Rc<Pager>,
+    _mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::VectorIndex { sql } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let conn = program.connection.upgrade();
+    let conn = conn.as_ref().unwrap();
+    run_vector_index(conn, sql)?;
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_vector_search(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    _pager: &Rc<Pager>,
+    _mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::VectorSearch {
+        cursor_id,
+        index,
+        query_reg,
+        k,
+        pc_if_empty,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let conn = program.connection.upgrade().unwrap();
+    let rowids = search_vector_index(&conn, index, &state.registers[*query_reg], *k)?;
+    if rowids.is_empty() {
+        state.vector_matches.remove(cursor_id);
+        state.pc = pc_if_empty.to_offset_int();
+    } else {
+        state.vector_matches.insert(*cursor_id, rowids.into());
+        state.pc += 1;
+    }
+    Ok(InsnFunctionStepResult::Step)
+}
+
+#[cfg(feature = "vector")]
+fn run_vector_index(conn: &Rc<Connection>, sql: &str) -> Result<()> {
+    crate::vector::index::run(conn, sql)
+}
+
+#[cfg(not(feature = "vector"))]
+fn run_vector_index(_conn: &Rc<Connection>, _sql: &str) -> Result<()> {
+    Err(LimboError::ParseError(
+        "vector indexes are not supported in this build".to_string(),
+    ))
+}
+
+/// The rowids of the `k` rows nearest to the vector `query` in the vector index `index`.
+#[cfg(feature = "vector")]
+fn search_vector_index(
+    conn: &Rc<Connection>,
+    index: &str,
+    query: &Register,
+    k: usize,
+) -> Result<Vec<i64>> {
+    let Some(vector_index) = conn.schema.read().get_vector_index(index) else {
+        return Err(LimboError::InternalError(format!(
+            "no such vector index: {index}"
+        )));
+    };
+    // Nothing is near a NULL vector.
+    if matches!(query.get_owned_value(), OwnedValue::Null) {
+        return Ok(Vec::new());
+    }
+    let query = crate::vector::vector_types::parse_vector(query, None)?;
+    vector_index.search(&query, k)
+}
+
+#[cfg(not(feature = "vector"))]
+fn search_vector_index(
+    _conn: &Rc<Connection>,
+    _index: &str,
+    _query: &Register,
+    _k: usize,
+) -> Result<Vec<i64>> {
+    Err(LimboError::ParseError(
+        "vector indexes are not supported in this build".to_string(),
+    ))
+}
+
+pub fn op_vector_rowid(
+    _program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    _pager: &Rc<Pager>,
+    _mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::VectorRowid { cursor_id, dest } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let rowid = state
+        .vector_matches
+        .get(cursor_id)
+        .and_then(|rowids| rowids.front().copied());
+    state.registers[*dest] = Register::OwnedValue(match rowid {
+        Some(rowid) => OwnedValue::Integer(rowid),
+        None => OwnedValue::Null,
+    });
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_vector_next(
+    _program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    _pager: &Rc<Pager>,
+    _mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::VectorNext {
+        cursor_id,
+        pc_if_next,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let has_next = state
+        .vector_matches
+        .get_mut(cursor_id)
+        .is_some_and(|rowids| {
+            rowids.pop_front();
+            !rowids.is_empty()
+        });
+    if has_next {
+        state.pc = pc_if_next.to_offset_int();
+    } else {
+        state.vector_matches.remove(cursor_id);
+        state.pc += 1;
+    }
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_load_analysis(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    _pager: &Rc<Pager>,
+    _mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::LoadAnalysis { db: _ } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    if let Some(conn) = program.connection.upgrade() {
+        conn.schema.write().stats_version += 1;
+    }
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_read_cookie(
     program: &Program,
     state: &mut ProgramState,
@@ -4250,17 +4960,58 @@ pub fn op_read_cookie(
     };
     if *db > 0 {
         // TODO: implement temp databases
-        todo!("temp databases not implemented yet");
+        return Err(LimboError::InternalError(
+            "temp databases not implemented yet".to_string(),
+        ));
     }
     let cookie_value = match cookie {
-        Cookie::UserVersion => pager.db_header.lock().user_version.into(),
-        cookie => todo!("{cookie:?} is not yet implement for ReadCookie"),
+        // Both are signed in SQLite.
+        Cookie::UserVersion => pager.db_header.lock().user_version as i32 as i64,
+        Cookie::ApplicationId => pager.db_header.lock().application_id as i32 as i64,
+        Cookie::SchemaVersion => pager.db_header.lock().schema_cookie.into(),
+        Cookie::DataVersion => pager.data_version() as i64,
+        cookie => {
+            return Err(LimboError::InternalError(format!(
+                "{cookie:?} is not yet implemented for ReadCookie"
+            )))
+        }
     };
     state.registers[*dest] = Register::OwnedValue(OwnedValue::Integer(cookie_value));
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_set_cookie(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::SetCookie { db, cookie, value } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    if *db > 0 {
+        // TODO: implement temp databases
+        return Err(LimboError::InternalError(
+            "temp databases not implemented yet".to_string(),
+        ));
+    }
+    let mut header = pager.db_header.lock();
+    match cookie {
+        Cookie::UserVersion => header.user_version = *value as u32,
+        Cookie::ApplicationId => header.application_id = *value as u32,
+        cookie => {
+            return Err(LimboError::InternalError(format!(
+                "{cookie:?} is not yet implemented for SetCookie"
+            )))
+        }
+    }
+    pager.write_header_page(&header)?;
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_shift_right(
     program: &Program,
     state: &mut ProgramState,
@@ -4649,24 +5400,27 @@ fn exec_abs(reg: &OwnedValue) -> Result<OwnedValue> {
     }
 }
 
-fn exec_random() -> OwnedValue {
-    let mut buf = [0u8; 8];
-    getrandom::getrandom(&mut buf).unwrap();
-    let random_number = i64::from_ne_bytes(buf);
-    OwnedValue::Integer(random_number)
+fn exec_random(mut random_number: impl FnMut() -> i64) -> OwnedValue {
+    OwnedValue::Integer(random_number())
 }
 
-fn exec_randomblob(reg: &OwnedValue) -> OwnedValue {
+fn exec_randomblob(reg: &OwnedValue, mut random_number: impl FnMut() -> i64) -> OwnedValue {
     let length = match reg {
         OwnedValue::Integer(i) => *i,
         OwnedValue::Float(f) => *f as i64,
-        OwnedValue::Text(t) => t.as_str().parse().unwrap_or(1),
+        OwnedValue::Text(t) => match cast_text_to_integer(t.as_str()) {
+            OwnedValue::Integer(i) => i,
+            _ => 1,
+        },
         _ => 1,
     }
     .max(1) as usize;
 
-    let mut blob: Vec<u8> = vec![0; length];
-    getrandom::getrandom(&mut blob).expect("Failed to generate random blob");
+    let mut blob: Vec<u8> = Vec::with_capacity(length.next_multiple_of(8));
+    while blob.len() < length {
+        blob.extend_from_slice(&random_number().to_ne_bytes());
+    }
+    blob.truncate(length);
     OwnedValue::Blob(blob)
 }
 
@@ -4674,7 +5428,15 @@ fn exec_quote(value: &OwnedValue) -> OwnedValue {
     match value {
         OwnedValue::Null => OwnedValue::build_text("NULL"),
         OwnedValue::Integer(_) | OwnedValue::Float(_) => value.to_owned(),
-        OwnedValue::Blob(_) => todo!(),
+        OwnedValue::Blob(blob) => {
+            let mut quoted = String::with_capacity(blob.len() * 2 + 3);
+            quoted.push_str("X'");
+            for byte in blob {
+                quoted.push_str(&format!("{:02X}", byte));
+            }
+            quoted.push('\'');
+            OwnedValue::build_text(&quoted)
+        }
         OwnedValue::Text(s) => {
             let mut quoted = String::with_capacity(s.as_str().len() + 2);
             quoted.push('\'');
@@ -4698,11 +5460,23 @@ fn exec_char(values: &[Register]) -> OwnedValue {
     let result: String = values
         .iter()
         .filter_map(|x| {
-            if let OwnedValue::Integer(i) = x.get_owned_value() {
-                Some(*i as u8 as char)
-            } else {
-                None
-            }
+            let code_point = match x.get_owned_value() {
+                OwnedValue::Integer(i) => *i,
+                OwnedValue::Float(f) => *f as i64,
+                OwnedValue::Text(t) => match checked_cast_text_to_numeric(t.as_str()) {
+                    Ok(OwnedValue::Integer(i)) => i,
+                    Ok(OwnedValue::Float(f)) => f as i64,
+                    _ => return None,
+                },
+                _ => return None,
+            };
+            // Code points that are not valid characters become the replacement character.
+            Some(
+                u32::try_from(code_point)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .unwrap_or('\u{fffd}'),
+            )
         })
         .collect();
     OwnedValue::build_text(&result)
@@ -4860,10 +5634,8 @@ fn exec_typeof(reg: &OwnedValue) -> OwnedValue {
 
 fn exec_hex(reg: &OwnedValue) -> OwnedValue {
     match reg {
-        OwnedValue::Text(_)
-        | OwnedValue::Integer(_)
-        | OwnedValue::Float(_)
-        | OwnedValue::Blob(_) => {
+        OwnedValue::Blob(blob) => OwnedValue::build_text(&hex::encode_upper(blob)),
+        OwnedValue::Text(_) | OwnedValue::Integer(_) | OwnedValue::Float(_) => {
             let text = reg.to_string();
             OwnedValue::build_text(&hex::encode_upper(text))
         }
@@ -5012,6 +5784,54 @@ fn exec_if(reg: &OwnedValue, jump_if_null: bool, not: bool) -> bool {
     }
 }
 
+/// The number that sum() and total() add for a value, or `None` if the value is skipped.
+/// Text that is a well-formed integer is added as an integer, other text as the real its numeric
+/// prefix describes.
+fn sum_operand(value: &OwnedValue) -> Option<OwnedValue> {
+    match value {
+        OwnedValue::Integer(_) | OwnedValue::Float(_) => Some(value.clone()),
+        OwnedValue::Text(t) => match text_to_numeric_affinity(t.as_str()) {
+            Some(OwnedValue::Integer(i)) if !t.as_str().contains(['.', 'e', 'E']) => {
+                Some(OwnedValue::Integer(i))
+            }
+            _ => Some(cast_text_to_real(t.as_str())),
+        },
+        OwnedValue::Blob(b) => Some(cast_text_to_real(&String::from_utf8_lossy(b))),
+        _ => None,
+    }
+}
+
+fn sum_operand_as_f64(value: &OwnedValue) -> f64 {
+    match value {
+        OwnedValue::Integer(i) => *i as f64,
+        OwnedValue::Float(f) => *f,
+        _ => unreachable!("sum operands are numeric"),
+    }
+}
+
+/// Converts a value about to be stored in a column with the given affinity, following
+/// https://www.sqlite.org/datatype3.html#type_affinity. Unlike [exec_cast], values that cannot be
+/// converted losslessly are kept as they are, in which case `None` is returned.
+fn apply_affinity(value: &OwnedValue, affinity: Affinity) -> Option<OwnedValue> {
+    match (affinity, value) {
+        (Affinity::Text, OwnedValue::Integer(_) | OwnedValue::Float(_)) => {
+            Some(OwnedValue::build_text(&value.to_string()))
+        }
+        (Affinity::Integer | Affinity::Numeric, OwnedValue::Float(f)) => {
+            Some(float_to_integer_affinity(*f))
+        }
+        (Affinity::Integer | Affinity::Numeric, OwnedValue::Text(t)) => {
+            text_to_numeric_affinity(t.as_str())
+        }
+        (Affinity::Real, OwnedValue::Integer(i)) => Some(OwnedValue::Float(*i as f64)),
+        (Affinity::Real, OwnedValue::Text(t)) => match text_to_numeric_affinity(t.as_str())? {
+            OwnedValue::Integer(i) => Some(OwnedValue::Float(i as f64)),
+            value => Some(value),
+        },
+        _ => None,
+    }
+}
+
 fn exec_cast(value: &OwnedValue, datatype: &str) -> OwnedValue {
     if matches!(value, OwnedValue::Null) {
         return OwnedValue::Null;
@@ -5235,6 +6055,7 @@ mod tests {
         execute::{exec_likely, exec_replace},
         Bitfield, Register,
     };
+    use crate::{MemoryIO, IO};
 
     use super::{
         exec_abs, exec_char, exec_hex, exec_if, exec_instr, exec_length, exec_like, exec_lower,
@@ -5276,6 +6097,10 @@ mod tests {
         let input = OwnedValue::build_text("hello''world");
         let expected = OwnedValue::build_text("'hello''''world'");
         assert_eq!(exec_quote(&input), expected);
+
+        let input = OwnedValue::Blob(vec![0x0a, 0xff]);
+        let expected = OwnedValue::build_text("X'0AFF'");
+        assert_eq!(exec_quote(&input), expected);
     }
 
     #[test]
@@ -5485,6 +6310,10 @@ mod tests {
         let input_float = OwnedValue::Float(12.34);
         let expected_val = OwnedValue::build_text("31322E3334");
         assert_eq!(exec_hex(&input_float), expected_val);
+
+        let input_blob = OwnedValue::Blob(vec![0x00, 0x80, 0xff]);
+        let expected_val = OwnedValue::build_text("0080FF");
+        assert_eq!(exec_hex(&input_blob), expected_val);
     }
 
     #[test]
@@ -5554,6 +6383,15 @@ mod tests {
             exec_char(&[Register::OwnedValue(OwnedValue::build_text("a"))]),
             OwnedValue::build_text("")
         );
+        assert_eq!(
+            exec_char(&[
+                Register::OwnedValue(OwnedValue::Integer(8364)),
+                Register::OwnedValue(OwnedValue::build_text("66")),
+                Register::OwnedValue(OwnedValue::Float(67.9)),
+                Register::OwnedValue(OwnedValue::Integer(-1)),
+            ]),
+            OwnedValue::build_text("€BC\u{fffd}")
+        );
     }
 
     #[test]
@@ -5590,7 +6428,8 @@ mod tests {
 
     #[test]
     fn test_random() {
-        match exec_random() {
+        let io = MemoryIO::new();
+        match exec_random(|| io.generate_random_number()) {
             OwnedValue::Integer(value) => {
                 // Check that the value is within the range of i64
                 assert!(
@@ -5653,7 +6492,8 @@ mod tests {
         ];
 
         for test_case in &test_cases {
-            let result = exec_randomblob(&test_case.input);
+            let io = MemoryIO::new();
+            let result = exec_randomblob(&test_case.input, || io.generate_random_number());
             match result {
                 OwnedValue::Blob(blob) => {
                     assert_eq!(blob.len(), test_case.expected_len);
@@ -2,6 +2,7 @@ use crate::vdbe::{builder::CursorType, insn::RegisterOrLiteral};
 
 use super::{Insn, InsnReference, OwnedValue, Program};
 use crate::function::{Func, ScalarFunc};
+use crate::translate::collate::CollationSeq;
 use std::rc::Rc;
 
 pub fn insn_to_str(
@@ -166,7 +167,12 @@ pub fn insn_to_str(
                 target_pc_gt.to_debug_int(),
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "goto {} if <, {} if ==, {} if >",
+                    target_pc_lt.to_debug_int(),
+                    target_pc_eq.to_debug_int(),
+                    target_pc_gt.to_debug_int()
+                ),
             ),
             Insn::Move {
                 source_reg,
@@ -348,10 +354,7 @@ pub fn insn_to_str(
                 0,
                 format!(
                     "table={}, root={}",
-                    program.cursor_ref[*cursor_id]
-                        .0
-                        .as_ref()
-                        .unwrap_or(&format!("cursor {}", cursor_id)),
+                    cursor_name(program, *cursor_id),
                     root_page
                 ),
             ),
@@ -371,7 +374,7 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!("table={}", cursor_name(program, *cursor_id)),
             ),
             Insn::VOpenAwait => (
                 "VOpenAwait",
@@ -399,15 +402,21 @@ pub fn insn_to_str(
                 cursor_id,
                 pc_if_empty,
                 arg_count,
+                idx_str,
+                idx_num,
                 ..
             } => (
                 "VFilter",
                 *cursor_id as i32,
                 pc_if_empty.to_debug_int(),
                 *arg_count as i32,
-                OwnedValue::build_text(""),
-                0,
-                "".to_string(),
+                OwnedValue::build_text(idx_str.as_deref().unwrap_or("")),
+                *idx_num as u16,
+                format!(
+                    "if {} is empty goto {}",
+                    cursor_name(program, *cursor_id),
+                    pc_if_empty.to_debug_int()
+                ),
             ),
             Insn::VColumn {
                 cursor_id,
@@ -420,7 +429,12 @@ pub fn insn_to_str(
                 *dest as i32,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "r[{}]={}.{}",
+                    dest,
+                    cursor_name(program, *cursor_id),
+                    column_name(program, *cursor_id, *column)
+                ),
             ),
             Insn::VUpdate {
                 cursor_id,
@@ -447,7 +461,11 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "if {} has next goto {}",
+                    cursor_name(program, *cursor_id),
+                    pc_if_next.to_debug_int()
+                ),
             ),
             Insn::OpenPseudo {
                 cursor_id,
@@ -462,6 +480,42 @@ pub fn insn_to_str(
                 0,
                 format!("{} columns in r[{}]", num_fields, content_reg),
             ),
+            Insn::OpenAutoindex { cursor_id } => (
+                "OpenAutoindex",
+                *cursor_id as i32,
+                0,
+                0,
+                OwnedValue::build_text(""),
+                0,
+                format!("cursor={}", cursor_id),
+            ),
+            Insn::OpenEphemeral {
+                cursor_id,
+                is_table,
+            } => (
+                "OpenEphemeral",
+                *cursor_id as i32,
+                *is_table as i32,
+                0,
+                OwnedValue::build_text(""),
+                0,
+                format!("cursor={}", cursor_id),
+            ),
+            Insn::OpenDup {
+                new_cursor_id,
+                original_cursor_id,
+            } => (
+                "OpenDup",
+                *new_cursor_id as i32,
+                *original_cursor_id as i32,
+                0,
+                OwnedValue::build_text(""),
+                0,
+                format!(
+                    "cursor={} dup of cursor={}",
+                    new_cursor_id, original_cursor_id
+                ),
+            ),
             Insn::RewindAsync { cursor_id } => (
                 "RewindAsync",
                 *cursor_id as i32,
@@ -482,52 +536,46 @@ pub fn insn_to_str(
                 OwnedValue::build_text(""),
                 0,
                 format!(
-                    "Rewind table {}",
-                    program.cursor_ref[*cursor_id]
-                        .0
-                        .as_ref()
-                        .unwrap_or(&format!("cursor {}", cursor_id))
+                    "Rewind table {}, if empty goto {}",
+                    cursor_name(program, *cursor_id),
+                    pc_if_empty.to_debug_int()
                 ),
             ),
             Insn::Column {
                 cursor_id,
                 column,
                 dest,
-            } => {
-                let (table_identifier, cursor_type) = &program.cursor_ref[*cursor_id];
-                let column_name: Option<&String> = match cursor_type {
-                    CursorType::BTreeTable(table) => {
-                        let name = table.columns.get(*column).unwrap().name.as_ref();
-                        name
-                    }
-                    CursorType::BTreeIndex(index) => {
-                        let name = &index.columns.get(*column).unwrap().name;
-                        Some(name)
-                    }
-                    CursorType::Pseudo(pseudo_table) => {
-                        let name = pseudo_table.columns.get(*column).unwrap().name.as_ref();
-                        name
-                    }
-                    CursorType::Sorter => None,
-                    CursorType::VirtualTable(v) => v.columns.get(*column).unwrap().name.as_ref(),
-                };
-                (
-                    "Column",
-                    *cursor_id as i32,
-                    *column as i32,
-                    *dest as i32,
-                    OwnedValue::build_text(""),
-                    0,
-                    format!(
-                        "r[{}]={}.{}",
-                        dest,
-                        table_identifier
-                            .as_ref()
-                            .unwrap_or(&format!("cursor {}", cursor_id)),
-                        column_name.unwrap_or(&format!("column {}", *column))
-                    ),
-                )
-            }
+            } => (
+                "Column",
+                *cursor_id as i32,
+                *column as i32,
+                *dest as i32,
+                OwnedValue::build_text(""),
+                0,
+                format!(
+                    "r[{}]={}.{}",
+                    dest,
+                    cursor_name(program, *cursor_id),
+                    column_name(program, *cursor_id, *column)
+                ),
+            ),
+            Insn::Affinity {
+                start_reg,
+                count,
+                affinities,
+            } => (
+                "Affinity",
+                *start_reg as i32,
+                *count as i32,
+                0,
+                OwnedValue::build_text(affinities),
+                0,
+                format!(
+                    "affinity(r[{}..{}])",
+                    start_reg,
+                    start_reg + count.saturating_sub(1)
+                ),
+            ),
             Insn::MakeRecord {
                 start_reg,
                 count,
@@ -578,11 +626,15 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "if {} has next goto {}",
+                    cursor_name(program, *cursor_id),
+                    pc_if_next.to_debug_int()
+                ),
             ),
             Insn::Halt {
                 err_code,
-                description: _,
+                description,
             } => (
                 "Halt",
                 *err_code as i32,
@@ -590,7 +642,7 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                description.clone(),
             ),
             Insn::Transaction { write } => (
                 "Transaction",
@@ -601,6 +653,15 @@ pub fn insn_to_str(
                 0,
                 format!("write={}", write),
             ),
+            Insn::TransactionAsOf { point } => (
+                "TransactionAsOf",
+                0,
+                *point as i32,
+                0,
+                OwnedValue::build_text(""),
+                0,
+                format!("as_of=r[{}]", point),
+            ),
             Insn::Goto { target_pc } => (
                 "Goto",
                 0,
@@ -608,7 +669,7 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!("goto {}", target_pc.to_debug_int()),
             ),
             Insn::Gosub {
                 target_pc,
@@ -620,7 +681,11 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "r[{}]=return address, goto {}",
+                    return_reg,
+                    target_pc.to_debug_int()
+                ),
             ),
             Insn::Return { return_reg } => (
                 "Return",
@@ -629,7 +694,7 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!("goto r[{}]", return_reg),
             ),
             Insn::Integer { value, dest } => (
                 "Integer",
@@ -656,7 +721,7 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!("r[{}]=real(r[{}])", register, register),
             ),
             Insn::String8 { value, dest } => (
                 "String8",
@@ -688,14 +753,7 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                format!(
-                    "r[{}]={}.rowid",
-                    dest,
-                    &program.cursor_ref[*cursor_id]
-                        .0
-                        .as_ref()
-                        .unwrap_or(&format!("cursor {}", cursor_id))
-                ),
+                format!("r[{}]={}.rowid", dest, cursor_name(program, *cursor_id)),
             ),
             Insn::SeekRowid {
                 cursor_id,
@@ -711,10 +769,7 @@ pub fn insn_to_str(
                 format!(
                     "if (r[{}]!={}.rowid) goto {}",
                     src_reg,
-                    &program.cursor_ref[*cursor_id]
-                        .0
-                        .as_ref()
-                        .unwrap_or(&format!("cursor {}", cursor_id)),
+                    cursor_name(program, *cursor_id),
                     target_pc.to_debug_int()
                 ),
             ),
@@ -728,13 +783,17 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "move {} to the rowid of {}",
+                    cursor_name(program, *table_cursor_id),
+                    cursor_name(program, *index_cursor_id)
+                ),
             ),
             Insn::SeekGT {
                 is_index: _,
                 cursor_id,
                 start_reg,
-                num_regs: _,
+                num_regs,
                 target_pc,
             } => (
                 "SeekGT",
@@ -743,13 +802,58 @@ pub fn insn_to_str(
                 *start_reg as i32,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "seek {} to key>{}, if none goto {}",
+                    cursor_name(program, *cursor_id),
+                    key_regs(*start_reg, *num_regs),
+                    target_pc.to_debug_int()
+                ),
+            ),
+            Insn::SeekLE {
+                is_index: _,
+                cursor_id,
+                start_reg,
+                num_regs,
+                target_pc,
+            } => (
+                "SeekLE",
+                *cursor_id as i32,
+                target_pc.to_debug_int(),
+                *start_reg as i32,
+                OwnedValue::build_text(""),
+                0,
+                format!(
+                    "seek {} to key<={}, if none goto {}",
+                    cursor_name(program, *cursor_id),
+                    key_regs(*start_reg, *num_regs),
+                    target_pc.to_debug_int()
+                ),
+            ),
+            Insn::SeekLT {
+                is_index: _,
+                cursor_id,
+                start_reg,
+                num_regs,
+                target_pc,
+            } => (
+                "SeekLT",
+                *cursor_id as i32,
+                target_pc.to_debug_int(),
+                *start_reg as i32,
+                OwnedValue::build_text(""),
+                0,
+                format!(
+                    "seek {} to key<{}, if none goto {}",
+                    cursor_name(program, *cursor_id),
+                    key_regs(*start_reg, *num_regs),
+                    target_pc.to_debug_int()
+                ),
             ),
             Insn::SeekGE {
                 is_index: _,
                 cursor_id,
                 start_reg,
-                num_regs: _,
+                num_regs,
                 target_pc,
             } => (
                 "SeekGE",
@@ -758,7 +862,12 @@ pub fn insn_to_str(
                 *start_reg as i32,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "seek {} to key>={}, if none goto {}",
+                    cursor_name(program, *cursor_id),
+                    key_regs(*start_reg, *num_regs),
+                    target_pc.to_debug_int()
+                ),
             ),
             Insn::SeekEnd { cursor_id } => (
                 "SeekEnd",
@@ -767,7 +876,7 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!("seek {} to the end", cursor_name(program, *cursor_id)),
             ),
             Insn::IdxInsertAsync {
                 cursor_id,
@@ -796,7 +905,7 @@ pub fn insn_to_str(
             Insn::IdxGT {
                 cursor_id,
                 start_reg,
-                num_regs: _,
+                num_regs,
                 target_pc,
             } => (
                 "IdxGT",
@@ -805,12 +914,17 @@ pub fn insn_to_str(
                 *start_reg as i32,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "if {} key>{} goto {}",
+                    cursor_name(program, *cursor_id),
+                    key_regs(*start_reg, *num_regs),
+                    target_pc.to_debug_int()
+                ),
             ),
             Insn::IdxGE {
                 cursor_id,
                 start_reg,
-                num_regs: _,
+                num_regs,
                 target_pc,
             } => (
                 "IdxGE",
@@ -819,12 +933,17 @@ pub fn insn_to_str(
                 *start_reg as i32,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "if {} key>={} goto {}",
+                    cursor_name(program, *cursor_id),
+                    key_regs(*start_reg, *num_regs),
+                    target_pc.to_debug_int()
+                ),
             ),
             Insn::IdxLT {
                 cursor_id,
                 start_reg,
-                num_regs: _,
+                num_regs,
                 target_pc,
             } => (
                 "IdxLT",
@@ -833,12 +952,17 @@ pub fn insn_to_str(
                 *start_reg as i32,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "if {} key<{} goto {}",
+                    cursor_name(program, *cursor_id),
+                    key_regs(*start_reg, *num_regs),
+                    target_pc.to_debug_int()
+                ),
             ),
             Insn::IdxLE {
                 cursor_id,
                 start_reg,
-                num_regs: _,
+                num_regs,
                 target_pc,
             } => (
                 "IdxLE",
@@ -847,7 +971,12 @@ pub fn insn_to_str(
                 *start_reg as i32,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "if {} key<={} goto {}",
+                    cursor_name(program, *cursor_id),
+                    key_regs(*start_reg, *num_regs),
+                    target_pc.to_debug_int()
+                ),
             ),
             Insn::DecrJumpZero { reg, target_pc } => (
                 "DecrJumpZero",
@@ -884,6 +1013,7 @@ pub fn insn_to_str(
             Insn::SorterOpen {
                 cursor_id,
                 columns,
+                max_rows,
                 order,
             } => {
                 let _p4 = String::new();
@@ -892,10 +1022,10 @@ pub fn insn_to_str(
                     .iter()
                     .map(|v| match v {
                         OwnedValue::Integer(i) => {
-                            if *i == 0 {
-                                "B".to_string()
-                            } else {
-                                "-B".to_string()
+                            let desc = if *i & 1 == 0 { "" } else { "-" };
+                            match CollationSeq::from_i64(*i >> 1) {
+                                CollationSeq::Binary => format!("{}B", desc),
+                                collation => format!("{}{}", desc, collation.name()),
                             }
                         }
                         _ => unreachable!(),
@@ -905,7 +1035,7 @@ pub fn insn_to_str(
                     "SorterOpen",
                     *cursor_id as i32,
                     *columns as i32,
-                    0,
+                    max_rows.unwrap_or(0) as i32,
                     OwnedValue::build_text(&(format!("k({},{})", order.len(), to_print.join(",")))),
                     0,
                     format!("cursor={}", cursor_id),
@@ -946,7 +1076,11 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "if {} is empty goto {}",
+                    cursor_name(program, *cursor_id),
+                    pc_if_empty.to_debug_int()
+                ),
             ),
             Insn::SorterNext {
                 cursor_id,
@@ -958,7 +1092,11 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "if {} has next goto {}",
+                    cursor_name(program, *cursor_id),
+                    pc_if_next.to_debug_int()
+                ),
             ),
             Insn::Function {
                 constant_mask,
@@ -1003,7 +1141,12 @@ pub fn insn_to_str(
                 start_offset.to_debug_int(),
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "r[{}]={}, goto {}",
+                    yield_reg,
+                    start_offset.to_debug_int(),
+                    jump_on_definition.to_debug_int()
+                ),
             ),
             Insn::EndCoroutine { yield_reg } => (
                 "EndCoroutine",
@@ -1012,7 +1155,7 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!("goto r[{}]", yield_reg),
             ),
             Insn::Yield {
                 yield_reg,
@@ -1024,7 +1167,11 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "swap pc with r[{}], goto {} once ended",
+                    yield_reg,
+                    end_offset.to_debug_int()
+                ),
             ),
             Insn::InsertAsync {
                 cursor,
@@ -1038,7 +1185,12 @@ pub fn insn_to_str(
                 *key_reg as i32,
                 OwnedValue::build_text(""),
                 *flag as u16,
-                "".to_string(),
+                format!(
+                    "table={}, intkey=r[{}], data=r[{}]",
+                    cursor_name(program, *cursor),
+                    key_reg,
+                    record_reg
+                ),
             ),
             Insn::InsertAwait { cursor_id } => (
                 "InsertAwait",
@@ -1056,7 +1208,7 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!("table={}", cursor_name(program, *cursor_id)),
             ),
             Insn::DeleteAwait { cursor_id } => (
                 "DeleteAwait",
@@ -1078,7 +1230,11 @@ pub fn insn_to_str(
                 *prev_largest_reg as i32,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "r[{}]=new rowid of {}",
+                    rowid_reg,
+                    cursor_name(program, *cursor)
+                ),
             ),
             Insn::MustBeInt { reg } => (
                 "MustBeInt",
@@ -1096,7 +1252,7 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!("r[{}]=NULL", reg),
             ),
             Insn::NotExists {
                 cursor,
@@ -1109,7 +1265,12 @@ pub fn insn_to_str(
                 *rowid_reg as i32,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "if (r[{}]!={}.rowid) goto {}",
+                    rowid_reg,
+                    cursor_name(program, *cursor),
+                    target_pc.to_debug_int()
+                ),
             ),
             Insn::OffsetLimit {
                 limit_reg,
@@ -1141,7 +1302,14 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "table={}, root={}",
+                    cursor_name(program, *cursor_id),
+                    match root_page {
+                        RegisterOrLiteral::Literal(i) => i.to_string(),
+                        RegisterOrLiteral::Register(i) => format!("r[{}]", i),
+                    }
+                ),
             ),
             Insn::OpenWriteAwait {} => (
                 "OpenWriteAwait",
@@ -1211,11 +1379,11 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!("table={}", cursor_name(program, *cursor_id)),
             ),
-            Insn::LastAsync { .. } => (
+            Insn::LastAsync { cursor_id } => (
                 "LastAsync",
-                0,
+                *cursor_id as i32,
                 0,
                 0,
                 OwnedValue::build_text(""),
@@ -1240,33 +1408,124 @@ pub fn insn_to_str(
                 0,
                 where_clause.clone(),
             ),
-            Insn::LastAwait { .. } => (
-                "LastAwait",
+            Insn::MaterializedView { statement } => (
+                "MaterializedView",
                 0,
                 0,
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!("{:?}", statement),
             ),
-            Insn::PrevAsync { .. } => (
-                "PrevAsync",
+            Insn::VectorIndex { sql } => (
+                "VectorIndex",
+                0,
                 0,
                 0,
+                OwnedValue::build_text(sql),
+                0,
+                sql.clone(),
+            ),
+            Insn::VectorSearch {
+                cursor_id,
+                index,
+                query_reg,
+                k,
+                pc_if_empty,
+            } => (
+                "VectorSearch",
+                *cursor_id as i32,
+                pc_if_empty.to_debug_int(),
+                *query_reg as i32,
+                OwnedValue::build_text(index),
+                0,
+                format!(
+                    "{} nearest to r[{}] in {} on {}",
+                    k,
+                    query_reg,
+                    index,
+                    cursor_name(program, *cursor_id)
+                ),
+            ),
+            Insn::VectorRowid { cursor_id, dest } => (
+                "VectorRowid",
+                *cursor_id as i32,
+                *dest as i32,
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!(
+                    "r[{}]=nearest {}.rowid",
+                    dest,
+                    cursor_name(program, *cursor_id)
+                ),
             ),
-            Insn::PrevAwait { .. } => (
-                "PrevAwait",
+            Insn::VectorNext {
+                cursor_id,
+                pc_if_next,
+            } => (
+                "VectorNext",
+                *cursor_id as i32,
+                pc_if_next.to_debug_int(),
+                0,
+                OwnedValue::build_text(""),
+                0,
+                format!(
+                    "if {} has next nearest goto {}",
+                    cursor_name(program, *cursor_id),
+                    pc_if_next.to_debug_int()
+                ),
+            ),
+            Insn::LoadAnalysis { db } => (
+                "LoadAnalysis",
+                *db as i32,
+                0,
+                0,
+                OwnedValue::build_text(""),
+                0,
+                String::new(),
+            ),
+            Insn::LastAwait {
+                cursor_id,
+                pc_if_empty,
+            } => (
+                "LastAwait",
+                *cursor_id as i32,
+                pc_if_empty.to_debug_int(),
                 0,
+                OwnedValue::build_text(""),
+                0,
+                format!(
+                    "Last table {}, if empty goto {}",
+                    cursor_name(program, *cursor_id),
+                    pc_if_empty.to_debug_int()
+                ),
+            ),
+            Insn::PrevAsync { cursor_id } => (
+                "PrevAsync",
+                *cursor_id as i32,
                 0,
                 0,
                 OwnedValue::build_text(""),
                 0,
                 "".to_string(),
             ),
+            Insn::PrevAwait {
+                cursor_id,
+                pc_if_next,
+            } => (
+                "PrevAwait",
+                *cursor_id as i32,
+                pc_if_next.to_debug_int(),
+                0,
+                OwnedValue::build_text(""),
+                0,
+                format!(
+                    "if {} has prev goto {}",
+                    cursor_name(program, *cursor_id),
+                    pc_if_next.to_debug_int()
+                ),
+            ),
             Insn::ShiftRight { lhs, rhs, dest } => (
                 "ShiftRight",
                 *rhs as i32,
@@ -1351,6 +1610,18 @@ pub fn insn_to_str(
                 0,
                 String::new(),
             ),
+            Insn::Count {
+                cursor_id,
+                target_reg,
+            } => (
+                "Count",
+                *cursor_id as i32,
+                *target_reg as i32,
+                0,
+                OwnedValue::build_text(""),
+                0,
+                format!("r[{}]=count()", target_reg),
+            ),
             Insn::PageCount { db, dest } => (
                 "Pagecount",
                 *db as i32,
@@ -1358,7 +1629,22 @@ pub fn insn_to_str(
                 0,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!("r[{}]=page_count", dest),
+            ),
+            Insn::IntegrityCk {
+                check,
+                message_register,
+            } => (
+                "IntegrityCk",
+                check.max_errors as i32,
+                *message_register as i32,
+                0,
+                OwnedValue::build_text(""),
+                0,
+                format!(
+                    "r[{}]=integrity_check(roots={:?})",
+                    message_register, check.roots
+                ),
             ),
             Insn::ReadCookie { db, dest, cookie } => (
                 "ReadCookie",
@@ -1367,7 +1653,16 @@ pub fn insn_to_str(
                 *cookie as i32,
                 OwnedValue::build_text(""),
                 0,
-                "".to_string(),
+                format!("r[{}]={:?}", dest, cookie),
+            ),
+            Insn::SetCookie { db, cookie, value } => (
+                "SetCookie",
+                *db as i32,
+                *cookie as i32,
+                *value,
+                OwnedValue::build_text(""),
+                0,
+                format!("{:?}={}", cookie, value),
             ),
             Insn::AutoCommit {
                 auto_commit,
@@ -1391,6 +1686,39 @@ pub fn insn_to_str(
         p3,
         p4.to_string(),
         p5,
-        manual_comment.map_or(comment.to_string(), |mc| format!("{}; {}", comment, mc))
+        match manual_comment {
+            Some(mc) if comment.is_empty() => mc.to_string(),
+            Some(mc) => format!("{}; {}", comment, mc),
+            None => comment,
+        }
     )
 }
+
+/// The table, index or subquery a cursor reads, for comments.
+fn cursor_name(program: &Program, cursor_id: usize) -> String {
+    match &program.cursor_ref[cursor_id].0 {
+        Some(name) => name.clone(),
+        None => format!("cursor {}", cursor_id),
+    }
+}
+
+/// The name of a column of the rows a cursor reads, for comments.
+fn column_name(program: &Program, cursor_id: usize, column: usize) -> String {
+    let name = match &program.cursor_ref[cursor_id].1 {
+        CursorType::BTreeTable(table) => table.columns.get(column).and_then(|c| c.name.clone()),
+        CursorType::BTreeIndex(index) => index.columns.get(column).map(|c| c.name.clone()),
+        CursorType::Pseudo(table) => table.columns.get(column).and_then(|c| c.name.clone()),
+        CursorType::VirtualTable(table) => table.columns.get(column).and_then(|c| c.name.clone()),
+        CursorType::Sorter => None,
+    };
+    name.unwrap_or_else(|| format!("column {}", column))
+}
+
+/// A range of registers holding a key, for comments.
+fn key_regs(start_reg: usize, num_regs: usize) -> String {
+    if num_regs <= 1 {
+        format!("r[{}]", start_reg)
+    } else {
+        format!("r[{}..{}]", start_reg, start_reg + num_regs - 1)
+    }
+}
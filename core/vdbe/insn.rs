@@ -3,6 +3,8 @@ use std::num::NonZero;
 use super::{
     cast_text_to_numeric, execute, AggFunc, BranchOffset, CursorID, FuncCtx, InsnFunction, PageIdx,
 };
+use crate::matview::ViewStatement;
+use crate::storage::integrity::IntegrityCheck;
 use crate::storage::wal::CheckpointMode;
 use crate::types::{OwnedValue, Record};
 use limbo_macros::Description;
@@ -285,6 +287,9 @@ pub enum Insn {
         pc_if_empty: BranchOffset,
         arg_count: usize,
         args_reg: usize,
+        /// The plan chosen by the table's best_index.
+        idx_str: Option<String>,
+        idx_num: i32,
     },
 
     /// Read a column from the current row of the virtual table cursor.
@@ -317,6 +322,24 @@ pub enum Insn {
         num_fields: usize,
     },
 
+    /// Open a cursor on a new, empty index b-tree kept in memory for the rest of the statement.
+    OpenAutoindex {
+        cursor_id: CursorID,
+    },
+
+    /// Open a cursor on a new, empty b-tree kept in memory for the rest of the statement.
+    /// The b-tree is a table b-tree if `is_table` is set, and an index b-tree otherwise.
+    OpenEphemeral {
+        cursor_id: CursorID,
+        is_table: bool,
+    },
+
+    /// Open a second cursor on the same transient b-tree as another cursor.
+    OpenDup {
+        new_cursor_id: CursorID,
+        original_cursor_id: CursorID,
+    },
+
     /// Rewind the cursor to the beginning of the B-Tree.
     RewindAsync {
         cursor_id: CursorID,
@@ -344,6 +367,13 @@ pub enum Insn {
         dest: usize,
     },
 
+    /// Apply affinities to a range of registers, one character of `affinities` per register.
+    Affinity {
+        start_reg: usize,   // P1
+        count: usize,       // P2
+        affinities: String, // P4
+    },
+
     /// Make a record and write it to destination register.
     MakeRecord {
         start_reg: usize, // P1
@@ -388,6 +418,13 @@ pub enum Insn {
         write: bool,
     },
 
+    /// Start a read transaction that sees the database as of the commit marker (an integer) or
+    /// the latest commit at or before the timestamp (a text) in register `point`, for
+    /// `SELECT ... AS OF`.
+    TransactionAsOf {
+        point: usize,
+    },
+
     /// Set database auto-commit mode and potentially rollback.
     AutoCommit {
         auto_commit: bool,
@@ -484,6 +521,28 @@ pub enum Insn {
         target_pc: BranchOffset,
     },
 
+    /// If cursor_id refers to an SQL table (B-Tree that uses integer keys), use the value in start_reg as the key.
+    /// If cursor_id refers to an SQL index, then start_reg is the first in an array of num_regs registers that are used as an unpacked index key.
+    /// Seek to the last index entry that is less than or equal to the given key. If not found, jump to the given PC. Otherwise, continue to the next instruction.
+    SeekLE {
+        is_index: bool,
+        cursor_id: CursorID,
+        start_reg: usize,
+        num_regs: usize,
+        target_pc: BranchOffset,
+    },
+
+    /// If cursor_id refers to an SQL table (B-Tree that uses integer keys), use the value in start_reg as the key.
+    /// If cursor_id refers to an SQL index, then start_reg is the first in an array of num_regs registers that are used as an unpacked index key.
+    /// Seek to the last index entry that is less than the given key. If not found, jump to the given PC. Otherwise, continue to the next instruction.
+    SeekLT {
+        is_index: bool,
+        cursor_id: CursorID,
+        start_reg: usize,
+        num_regs: usize,
+        target_pc: BranchOffset,
+    },
+
     /// cursor_id is a cursor pointing to a B-Tree index that uses integer keys, this op writes the value obtained from MakeRecord into the index.
     /// P3 + P4 are for the original column values that make up that key in unpacked (pre-serialized) form.
     /// If P5 has the OPFLAG_APPEND bit set, that is a hint to the b-tree layer that this insert is likely to be an append.
@@ -555,9 +614,10 @@ pub enum Insn {
 
     /// Open a sorter.
     SorterOpen {
-        cursor_id: CursorID, // P1
-        columns: usize,      // P2
-        order: Record,       // P4. 0 if ASC and 1 if DESC
+        cursor_id: CursorID,     // P1
+        columns: usize,          // P2
+        max_rows: Option<usize>, // P3. Only the first rows in sort order are kept, e.g. for ORDER BY ... LIMIT.
+        order: Record,           // P4. 0 if ASC and 1 if DESC
     },
 
     /// Insert a row into the sorter.
@@ -716,6 +776,42 @@ pub enum Insn {
         where_clause: String,
     },
 
+    /// Note that the statistics of database P1 changed, so plans prepared before are replanned.
+    LoadAnalysis {
+        db: usize,
+    },
+
+    /// Create, refresh or drop a materialized view, in a write transaction of its own.
+    MaterializedView {
+        statement: ViewStatement,
+    },
+
+    /// Create or drop the vector index of the statement `sql`, in a write transaction of its own.
+    VectorIndex {
+        sql: String,
+    },
+
+    /// Look up the k rows nearest to the vector in register query_reg with the vector index `index`, to loop over their rowids on cursor_id. If there are none, jump to pc_if_empty.
+    VectorSearch {
+        cursor_id: CursorID,
+        index: String,
+        query_reg: usize,
+        k: usize,
+        pc_if_empty: BranchOffset,
+    },
+
+    /// Read the rowid of the current row found by the VectorSearch of cursor_id.
+    VectorRowid {
+        cursor_id: CursorID,
+        dest: usize,
+    },
+
+    /// Advance to the next row found by the VectorSearch of cursor_id, and jump to pc_if_next if there is one.
+    VectorNext {
+        cursor_id: CursorID,
+        pc_if_next: BranchOffset,
+    },
+
     /// Place the result of lhs >> rhs in dest register.
     ShiftRight {
         lhs: usize,
@@ -767,17 +863,35 @@ pub enum Insn {
     },
     /// Do nothing. Continue downward to the next opcode.
     Noop,
+    /// Store the number of entries in the b-tree of the cursor in register `target_reg`.
+    Count {
+        cursor_id: CursorID,
+        target_reg: usize,
+    },
     /// Write the current number of pages in database P1 to memory cell P2.
     PageCount {
         db: usize,
         dest: usize,
     },
+    /// Check the b-trees rooted at the pages of the check and, unless it is a quick check, that
+    /// indexes match their tables. The problems found are stored in the message register one
+    /// per line, or "ok" if there are none.
+    IntegrityCk {
+        check: IntegrityCheck,
+        message_register: usize,
+    },
     /// Read cookie number P3 from database P1 and write it into register P2
     ReadCookie {
         db: usize,
         dest: usize,
         cookie: Cookie,
     },
+    /// Write the integer P3 into cookie number P2 of database P1.
+    SetCookie {
+        db: usize,
+        cookie: Cookie,
+        value: i32,
+    },
 }
 
 // TODO: Add remaining cookies.
@@ -795,6 +909,19 @@ pub enum Cookie {
     DatabaseTextEncoding = 5,
     /// The "user version" as read and set by the user_version pragma.
     UserVersion = 6,
+    /// The "Application ID" as read and set by the application_id pragma.
+    ApplicationId = 8,
+    /// Not stored in the header: a number that changes when another connection commits.
+    DataVersion = 15,
+}
+
+/// Operators read a blob operand as the text of its bytes, which they then convert to a number
+/// like any other text.
+fn blob_as_text(value: &OwnedValue) -> OwnedValue {
+    match value {
+        OwnedValue::Blob(blob) => OwnedValue::build_text(&String::from_utf8_lossy(blob)),
+        _ => value.clone(),
+    }
 }
 
 pub fn exec_add(lhs: &OwnedValue, rhs: &OwnedValue) -> OwnedValue {
@@ -818,7 +945,7 @@ pub fn exec_add(lhs: &OwnedValue, rhs: &OwnedValue) -> OwnedValue {
         (OwnedValue::Text(text), other) | (other, OwnedValue::Text(text)) => {
             exec_add(&cast_text_to_numeric(text.as_str()), other)
         }
-        _ => todo!(),
+        _ => exec_add(&blob_as_text(lhs), &blob_as_text(rhs)),
     };
     match result {
         OwnedValue::Float(f) if f.is_nan() => OwnedValue::Null,
@@ -850,7 +977,7 @@ pub fn exec_subtract(lhs: &OwnedValue, rhs: &OwnedValue) -> OwnedValue {
         (other, OwnedValue::Text(text)) => {
             exec_subtract(other, &cast_text_to_numeric(text.as_str()))
         }
-        _ => todo!(),
+        _ => exec_subtract(&blob_as_text(lhs), &blob_as_text(rhs)),
     };
     match result {
         OwnedValue::Float(f) if f.is_nan() => OwnedValue::Null,
@@ -880,7 +1007,7 @@ pub fn exec_multiply(lhs: &OwnedValue, rhs: &OwnedValue) -> OwnedValue {
             exec_multiply(&cast_text_to_numeric(text.as_str()), other)
         }
 
-        _ => todo!(),
+        _ => exec_multiply(&blob_as_text(lhs), &blob_as_text(rhs)),
     };
     match result {
         OwnedValue::Float(f) if f.is_nan() => OwnedValue::Null,
@@ -909,7 +1036,7 @@ pub fn exec_divide(lhs: &OwnedValue, rhs: &OwnedValue) -> OwnedValue {
         ),
         (OwnedValue::Text(text), other) => exec_divide(&cast_text_to_numeric(text.as_str()), other),
         (other, OwnedValue::Text(text)) => exec_divide(other, &cast_text_to_numeric(text.as_str())),
-        _ => todo!(),
+        _ => exec_divide(&blob_as_text(lhs), &blob_as_text(rhs)),
     };
     match result {
         OwnedValue::Float(f) if f.is_nan() => OwnedValue::Null,
@@ -937,7 +1064,7 @@ pub fn exec_bit_and(lhs: &OwnedValue, rhs: &OwnedValue) -> OwnedValue {
         (OwnedValue::Text(text), other) | (other, OwnedValue::Text(text)) => {
             exec_bit_and(&cast_text_to_numeric(text.as_str()), other)
         }
-        _ => todo!(),
+        _ => exec_bit_and(&blob_as_text(lhs), &blob_as_text(rhs)),
     }
 }
 
@@ -957,7 +1084,7 @@ pub fn exec_bit_or(lhs: &OwnedValue, rhs: &OwnedValue) -> OwnedValue {
         (OwnedValue::Text(text), other) | (other, OwnedValue::Text(text)) => {
             exec_bit_or(&cast_text_to_numeric(text.as_str()), other)
         }
-        _ => todo!(),
+        _ => exec_bit_or(&blob_as_text(lhs), &blob_as_text(rhs)),
     }
 }
 
@@ -971,7 +1098,7 @@ pub fn exec_remainder(lhs: &OwnedValue, rhs: &OwnedValue) -> OwnedValue {
             if rhs == &0 {
                 OwnedValue::Null
             } else {
-                OwnedValue::Integer(lhs % rhs.abs())
+                OwnedValue::Integer(lhs.wrapping_rem(*rhs))
             }
         }
         (OwnedValue::Float(lhs), OwnedValue::Float(rhs)) => {
@@ -979,14 +1106,14 @@ pub fn exec_remainder(lhs: &OwnedValue, rhs: &OwnedValue) -> OwnedValue {
             if rhs_int == 0 {
                 OwnedValue::Null
             } else {
-                OwnedValue::Float(((*lhs as i64) % rhs_int.abs()) as f64)
+                OwnedValue::Float((*lhs as i64).wrapping_rem(rhs_int) as f64)
             }
         }
         (OwnedValue::Float(lhs), OwnedValue::Integer(rhs)) => {
             if rhs == &0 {
                 OwnedValue::Null
             } else {
-                OwnedValue::Float(((*lhs as i64) % rhs.abs()) as f64)
+                OwnedValue::Float((*lhs as i64).wrapping_rem(*rhs) as f64)
             }
         }
         (OwnedValue::Integer(lhs), OwnedValue::Float(rhs)) => {
@@ -994,7 +1121,7 @@ pub fn exec_remainder(lhs: &OwnedValue, rhs: &OwnedValue) -> OwnedValue {
             if rhs_int == 0 {
                 OwnedValue::Null
             } else {
-                OwnedValue::Float((lhs % rhs_int.abs()) as f64)
+                OwnedValue::Float(lhs.wrapping_rem(rhs_int) as f64)
             }
         }
         (OwnedValue::Text(lhs), OwnedValue::Text(rhs)) => exec_remainder(
@@ -1007,7 +1134,7 @@ pub fn exec_remainder(lhs: &OwnedValue, rhs: &OwnedValue) -> OwnedValue {
         (other, OwnedValue::Text(text)) => {
             exec_remainder(other, &cast_text_to_numeric(text.as_str()))
         }
-        other => todo!("remainder not implemented for: {:?} {:?}", lhs, other),
+        _ => exec_remainder(&blob_as_text(lhs), &blob_as_text(rhs)),
     }
 }
 
@@ -1017,7 +1144,7 @@ pub fn exec_bit_not(reg: &OwnedValue) -> OwnedValue {
         OwnedValue::Integer(i) => OwnedValue::Integer(!i),
         OwnedValue::Float(f) => OwnedValue::Integer(!(*f as i64)),
         OwnedValue::Text(text) => exec_bit_not(&cast_text_to_numeric(text.as_str())),
-        _ => todo!(),
+        OwnedValue::Blob(_) => exec_bit_not(&blob_as_text(reg)),
     }
 }
 
@@ -1046,7 +1173,7 @@ pub fn exec_shift_left(lhs: &OwnedValue, rhs: &OwnedValue) -> OwnedValue {
         (other, OwnedValue::Text(text)) => {
             exec_shift_left(other, &cast_text_to_numeric(text.as_str()))
         }
-        _ => todo!(),
+        _ => exec_shift_left(&blob_as_text(lhs), &blob_as_text(rhs)),
     }
 }
 
@@ -1099,7 +1226,7 @@ pub fn exec_shift_right(lhs: &OwnedValue, rhs: &OwnedValue) -> OwnedValue {
         (other, OwnedValue::Text(text)) => {
             exec_shift_right(other, &cast_text_to_numeric(text.as_str()))
         }
-        _ => todo!(),
+        _ => exec_shift_right(&blob_as_text(lhs), &blob_as_text(rhs)),
     }
 }
 
@@ -1146,27 +1273,28 @@ pub fn exec_concat(lhs: &OwnedValue, rhs: &OwnedValue) -> OwnedValue {
         (OwnedValue::Text(lhs_text), OwnedValue::Integer(rhs_int)) => {
             OwnedValue::build_text(&(lhs_text.as_str().to_string() + &rhs_int.to_string()))
         }
-        (OwnedValue::Text(lhs_text), OwnedValue::Float(rhs_float)) => {
-            OwnedValue::build_text(&(lhs_text.as_str().to_string() + &rhs_float.to_string()))
-        }
+        (OwnedValue::Text(lhs_text), OwnedValue::Float(rhs_float)) => OwnedValue::build_text(
+            &(lhs_text.as_str().to_string() + &OwnedValue::Float(*rhs_float).to_string()),
+        ),
         (OwnedValue::Integer(lhs_int), OwnedValue::Text(rhs_text)) => {
             OwnedValue::build_text(&(lhs_int.to_string() + rhs_text.as_str()))
         }
         (OwnedValue::Integer(lhs_int), OwnedValue::Integer(rhs_int)) => {
             OwnedValue::build_text(&(lhs_int.to_string() + &rhs_int.to_string()))
         }
-        (OwnedValue::Integer(lhs_int), OwnedValue::Float(rhs_float)) => {
-            OwnedValue::build_text(&(lhs_int.to_string() + &rhs_float.to_string()))
-        }
+        (OwnedValue::Integer(lhs_int), OwnedValue::Float(rhs_float)) => OwnedValue::build_text(
+            &(lhs_int.to_string() + &OwnedValue::Float(*rhs_float).to_string()),
+        ),
         (OwnedValue::Float(lhs_float), OwnedValue::Text(rhs_text)) => {
-            OwnedValue::build_text(&(lhs_float.to_string() + rhs_text.as_str()))
-        }
-        (OwnedValue::Float(lhs_float), OwnedValue::Integer(rhs_int)) => {
-            OwnedValue::build_text(&(lhs_float.to_string() + &rhs_int.to_string()))
-        }
-        (OwnedValue::Float(lhs_float), OwnedValue::Float(rhs_float)) => {
-            OwnedValue::build_text(&(lhs_float.to_string() + &rhs_float.to_string()))
+            OwnedValue::build_text(&(OwnedValue::Float(*lhs_float).to_string() + rhs_text.as_str()))
         }
+        (OwnedValue::Float(lhs_float), OwnedValue::Integer(rhs_int)) => OwnedValue::build_text(
+            &(OwnedValue::Float(*lhs_float).to_string() + &rhs_int.to_string()),
+        ),
+        (OwnedValue::Float(lhs_float), OwnedValue::Float(rhs_float)) => OwnedValue::build_text(
+            &(OwnedValue::Float(*lhs_float).to_string()
+                + &OwnedValue::Float(*rhs_float).to_string()),
+        ),
         (OwnedValue::Null, _) | (_, OwnedValue::Null) => OwnedValue::Null,
         (OwnedValue::Blob(_), _) | (_, OwnedValue::Blob(_)) => {
             todo!("TODO: Handle Blob conversion to String")
@@ -1267,6 +1395,9 @@ impl Insn {
             Insn::VUpdate { .. } => execute::op_vupdate,
             Insn::VNext { .. } => execute::op_vnext,
             Insn::OpenPseudo { .. } => execute::op_open_pseudo,
+            Insn::OpenAutoindex { .. } => execute::op_open_autoindex,
+            Insn::OpenEphemeral { .. } => execute::op_open_ephemeral,
+            Insn::OpenDup { .. } => execute::op_open_dup,
             Insn::RewindAsync { .. } => execute::op_rewind_async,
 
             Insn::RewindAwait { .. } => execute::op_rewind_await,
@@ -1274,6 +1405,7 @@ impl Insn {
 
             Insn::LastAwait { .. } => execute::op_last_await,
             Insn::Column { .. } => execute::op_column,
+            Insn::Affinity { .. } => execute::op_affinity,
             Insn::MakeRecord { .. } => execute::op_make_record,
             Insn::ResultRow { .. } => execute::op_result_row,
 
@@ -1285,6 +1417,7 @@ impl Insn {
             Insn::PrevAwait { .. } => execute::op_prev_await,
             Insn::Halt { .. } => execute::op_halt,
             Insn::Transaction { .. } => execute::op_transaction,
+            Insn::TransactionAsOf { .. } => execute::op_transaction_as_of,
 
             Insn::AutoCommit { .. } => execute::op_auto_commit,
             Insn::Goto { .. } => execute::op_goto,
@@ -1308,6 +1441,8 @@ impl Insn {
             Insn::DeferredSeek { .. } => execute::op_deferred_seek,
             Insn::SeekGE { .. } => execute::op_seek_ge,
             Insn::SeekGT { .. } => execute::op_seek_gt,
+            Insn::SeekLE { .. } => execute::op_seek_le,
+            Insn::SeekLT { .. } => execute::op_seek_lt,
             Insn::SeekEnd { .. } => execute::op_seek_end,
             Insn::IdxGE { .. } => execute::op_idx_ge,
             Insn::IdxGT { .. } => execute::op_idx_gt,
@@ -1356,6 +1491,13 @@ impl Insn {
             Insn::IsNull { .. } => execute::op_is_null,
 
             Insn::ParseSchema { .. } => execute::op_parse_schema,
+            Insn::MaterializedView { .. } => execute::op_materialized_view,
+            Insn::VectorIndex { .. } => execute::op_vector_index,
+            Insn::VectorSearch { .. } => execute::op_vector_search,
+            Insn::VectorRowid { .. } => execute::op_vector_rowid,
+            Insn::VectorNext { .. } => execute::op_vector_next,
+
+            Insn::LoadAnalysis { .. } => execute::op_load_analysis,
 
             Insn::ShiftRight { .. } => execute::op_shift_right,
 
@@ -1374,9 +1516,12 @@ impl Insn {
             Insn::Or { .. } => execute::op_or,
 
             Insn::Noop => execute::op_noop,
+            Insn::Count { .. } => execute::op_count,
             Insn::PageCount { .. } => execute::op_page_count,
+            Insn::IntegrityCk { .. } => execute::op_integrity_ck,
 
             Insn::ReadCookie { .. } => execute::op_read_cookie,
+            Insn::SetCookie { .. } => execute::op_set_cookie,
         }
     }
 }
@@ -17,6 +17,7 @@
 //!
 //! https://www.sqlite.org/opcode.html
 
+pub mod arena;
 pub mod builder;
 pub mod execute;
 pub mod explain;
@@ -24,9 +25,10 @@ pub mod insn;
 pub mod likeop;
 pub mod sorter;
 
-use crate::error::LimboError;
+use crate::error::{ExecutionLimit, LimboError};
 use crate::fast_lock::SpinLock;
 use crate::function::{AggFunc, FuncCtx};
+use crate::matview;
 
 use crate::storage::sqlite3_ondisk::DatabaseHeader;
 use crate::storage::{btree::BTreeCursor, pager::Pager};
@@ -42,19 +44,22 @@ use crate::CheckpointStatus;
 
 #[cfg(feature = "json")]
 use crate::json::JsonCacheCell;
-use crate::{Connection, MvStore, Result, TransactionState};
+use crate::parameters::{expand_sql, push_literal};
+use crate::{Connection, MvStore, Result, TraceEvent, Tracer, TransactionState};
+use arena::{AllocationStats, Arena};
 use execute::{InsnFunction, InsnFunctionStepResult};
 
 use rand::distributions::{Distribution, Uniform};
 use rand::Rng;
 use regex::Regex;
 use std::cell::{Cell, RefCell};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::ffi::c_void;
 use std::num::NonZero;
 use std::ops::Deref;
 use std::rc::{Rc, Weak};
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 /// Represents a target for a jump instruction.
@@ -227,13 +232,43 @@ pub struct ProgramState {
     registers: Vec<Register>,
     pub(crate) result_row: Option<Row>,
     last_compare: Option<std::cmp::Ordering>,
-    deferred_seek: Option<(CursorID, CursorID)>,
+    /// For each table cursor, the index cursor whose rowid a DeferredSeek is going to move it to.
+    deferred_seeks: Vec<Option<CursorID>>,
     ended_coroutine: Bitfield<4>, // flag to indicate that a coroutine has ended (key is the yield register. currently we assume that the yield register is always between 0-255, YOLO)
     regex_cache: RegexCache,
     pub(crate) mv_tx_id: Option<crate::mvcc::database::TxID>,
     interrupted: bool,
     parameters: HashMap<NonZero<usize>, OwnedValue>,
     halt_state: Option<HaltState>,
+    /// Rows changed so far by this execution, reported as the connection's changes when it halts.
+    n_change: i64,
+    /// The row change of an insert or delete that is waiting for I/O.
+    pending_change: Option<crate::cdc::RowChange>,
+    /// The cursor of a Destroy that is waiting for I/O.
+    destroying: Option<BTreeCursor>,
+    /// The registers as last reported with `PRAGMA vdbe_trace` on, see [trace_registers].
+    traced_registers: Vec<String>,
+    /// Execution count and time of every instruction, while profiling is enabled.
+    profile: Option<Vec<(u64, Duration)>>,
+    /// Buffers of records that are no longer used, kept across executions.
+    arena: RefCell<Arena>,
+    /// When the write transaction began to be delayed by WAL backpressure, see
+    /// [Pager::throttle_write].
+    write_delayed_since: Option<crate::Instant>,
+    /// Budgets of each execution, kept across executions.
+    limits: ExecutionLimits,
+    /// Instructions run and rows returned by this execution, and when it first stepped, to
+    /// check it against its limits.
+    steps_run: u64,
+    rows_returned: u64,
+    started_at: Option<crate::Instant>,
+    /// The counters of the table behind each cursor, looked up when first needed.
+    table_stats: Vec<Option<Rc<Cell<TableStats>>>>,
+    /// For each table cursor looping over the rows a vector index found, the rowids left.
+    vector_matches: HashMap<CursorID, VecDeque<i64>>,
+    /// The updates of the vector indexes in memory to make once the transaction commits.
+    #[cfg(feature = "vector")]
+    vector_index_updates: Vec<crate::vector::index::IndexUpdate>,
     #[cfg(feature = "json")]
     json_cache: JsonCacheCell,
 }
@@ -249,13 +284,28 @@ impl ProgramState {
             registers,
             result_row: None,
             last_compare: None,
-            deferred_seek: None,
+            deferred_seeks: vec![None; max_cursors],
             ended_coroutine: Bitfield::new(),
             regex_cache: RegexCache::new(),
             mv_tx_id: None,
             interrupted: false,
             parameters: HashMap::new(),
             halt_state: None,
+            n_change: 0,
+            pending_change: None,
+            destroying: None,
+            traced_registers: Vec::new(),
+            profile: None,
+            arena: RefCell::new(Arena::default()),
+            write_delayed_since: None,
+            limits: ExecutionLimits::default(),
+            steps_run: 0,
+            rows_returned: 0,
+            started_at: None,
+            table_stats: vec![None; max_cursors],
+            vector_matches: HashMap::new(),
+            #[cfg(feature = "vector")]
+            vector_index_updates: Vec::new(),
             #[cfg(feature = "json")]
             json_cache: JsonCacheCell::new(),
         }
@@ -273,6 +323,30 @@ impl ProgramState {
         self.interrupted = true;
     }
 
+    pub fn set_limits(&mut self, limits: ExecutionLimits) {
+        self.limits = limits;
+    }
+
+    /// Returns the limit the execution went over, if any. The clock is only read when
+    /// `check_time`, as doing that for every instruction would slow execution down.
+    fn exceeded_limit(&mut self, pager: &Pager, check_time: bool) -> Option<ExecutionLimit> {
+        let limits = self.limits;
+        if limits.max_steps.is_some_and(|max| self.steps_run > max) {
+            return Some(ExecutionLimit::Steps);
+        }
+        if limits.max_rows.is_some_and(|max| self.rows_returned > max) {
+            return Some(ExecutionLimit::Rows);
+        }
+        if let (Some(max), true) = (limits.max_duration, check_time) {
+            let now = pager.io.now();
+            let started_at = *self.started_at.get_or_insert(now);
+            if now.micros_since(started_at) > max.as_micros() as i64 {
+                return Some(ExecutionLimit::Duration);
+            }
+        }
+        None
+    }
+
     pub fn is_interrupted(&self) -> bool {
         self.interrupted
     }
@@ -285,22 +359,102 @@ impl ProgramState {
         self.parameters.get(&index)
     }
 
+    /// Enables or disables collecting per-instruction statistics.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        if enabled != self.profile.is_some() {
+            self.profile = enabled.then(Vec::new);
+        }
+    }
+
     pub fn reset(&mut self) {
         self.pc = 0;
-        self.cursors.borrow_mut().iter_mut().for_each(|c| *c = None);
-        self.registers
-            .iter_mut()
-            .for_each(|r| *r = Register::OwnedValue(OwnedValue::Null));
+        let arena = self.arena.get_mut();
+        for cursor in self.cursors.get_mut().iter_mut() {
+            if let Some(cursor) = cursor.take() {
+                arena.recycle_cursor(cursor);
+            }
+        }
+        for register in self.registers.iter_mut() {
+            if let Register::Record(record) =
+                std::mem::replace(register, Register::OwnedValue(OwnedValue::Null))
+            {
+                arena.recycle(record);
+            }
+        }
         self.last_compare = None;
-        self.deferred_seek = None;
+        self.deferred_seeks.fill(None);
         self.ended_coroutine.0 = [0; 4];
         self.regex_cache.like.clear();
         self.interrupted = false;
         self.parameters.clear();
+        self.pending_change = None;
+        self.destroying = None;
+        self.n_change = 0;
+        self.write_delayed_since = None;
+        self.steps_run = 0;
+        self.rows_returned = 0;
+        self.started_at = None;
+        self.traced_registers.clear();
+        if let Some(profile) = &mut self.profile {
+            profile.clear();
+        }
+        self.vector_matches.clear();
+        #[cfg(feature = "vector")]
+        self.vector_index_updates.clear();
         #[cfg(feature = "json")]
         self.json_cache.clear()
     }
 
+    /// Serializes `count` registers from `start_reg` into a record, see [Arena::make_record].
+    pub fn make_record(&self, start_reg: &usize, count: &usize) -> ImmutableRecord {
+        self.arena
+            .borrow_mut()
+            .make_record(&self.registers[*start_reg..*start_reg + *count])
+    }
+
+    pub fn clone_record(&self, record: &ImmutableRecord) -> ImmutableRecord {
+        self.arena.borrow_mut().clone_record(record)
+    }
+
+    /// Hands the buffers of a record that is no longer used back for reuse.
+    pub fn recycle_record(&self, record: ImmutableRecord) {
+        self.arena.borrow_mut().recycle(record);
+    }
+
+    /// Stores `record` in a register, recycling the record it held before.
+    pub fn set_record(&mut self, reg: usize, record: ImmutableRecord) {
+        if let Register::Record(previous) =
+            std::mem::replace(&mut self.registers[reg], Register::Record(record))
+        {
+            self.arena.get_mut().recycle(previous);
+        }
+    }
+
+    /// Puts `cursor` in slot `cursor_id`, giving a b-tree cursor a recycled record to read rows
+    /// into. The records of the cursor it replaces are recycled.
+    pub fn open_cursor(&self, cursor_id: CursorID, mut cursor: Cursor) {
+        let mut arena = self.arena.borrow_mut();
+        if let Cursor::BTree(btree) = &mut cursor {
+            btree.set_record_buffer(arena.record_buffer());
+        }
+        let previous = self.cursors.borrow_mut()[cursor_id].replace(cursor);
+        if let Some(previous) = previous {
+            arena.recycle_cursor(previous);
+        }
+    }
+
+    /// Empties slot `cursor_id`, recycling the records of the cursor it held.
+    pub fn close_cursor(&self, cursor_id: CursorID) {
+        let previous = self.cursors.borrow_mut()[cursor_id].take();
+        if let Some(previous) = previous {
+            self.arena.borrow_mut().recycle_cursor(previous);
+        }
+    }
+
+    pub fn allocation_stats(&self) -> AllocationStats {
+        self.arena.borrow().stats()
+    }
+
     pub fn get_cursor<'a>(&'a self, cursor_id: CursorID) -> std::cell::RefMut<'a, Cursor> {
         let cursors = self.cursors.borrow_mut();
         std::cell::RefMut::map(cursors, |c| {
@@ -345,10 +499,10 @@ pub struct Program {
     pub comments: Option<HashMap<InsnReference, &'static str>>,
     pub parameters: crate::parameters::Parameters,
     pub connection: Weak<Connection>,
-    pub n_change: Cell<i64>,
     pub change_cnt_on: bool,
     pub result_columns: Vec<ResultSetColumn>,
     pub table_references: Vec<TableReference>,
+    pub sql: String,
 }
 
 impl Program {
@@ -358,20 +512,66 @@ impl Program {
         mv_store: Option<Rc<MvStore>>,
         pager: Rc<Pager>,
     ) -> Result<StepResult> {
+        let conn = self.connection.upgrade();
+        let tracer = conn.as_ref().and_then(|conn| conn.tracer.borrow().clone());
+        let vdbe_trace = conn.is_some_and(|conn| conn.vdbe_trace());
+        if let Some(tracer) = &tracer {
+            // Execution always starts at the Init instruction, which never yields.
+            if state.pc == 0 {
+                let sql = expand_sql(&self.sql, |index| state.get_parameter(index));
+                (tracer.callback)(TraceEvent::Stmt(&sql));
+            }
+        }
+        // Time spent waiting for I/O between steps counts too, so the clock is checked on
+        // every step and then every so many instructions.
+        if let Some(limit) = state.exceeded_limit(&pager, true) {
+            return self.fail(&pager, mv_store.as_ref(), LimboError::LimitExceeded(limit));
+        }
         loop {
             if state.is_interrupted() {
                 return Ok(StepResult::Interrupt);
             }
+            state.steps_run += 1;
+            if let Some(limit) = state.exceeded_limit(&pager, state.steps_run % 1024 == 0) {
+                return self.fail(&pager, mv_store.as_ref(), LimboError::LimitExceeded(limit));
+            }
             // invalidate row
             let _ = state.result_row.take();
             let (insn, insn_function) = &self.insns[state.pc as usize];
-            trace_insn(self, state.pc as InsnReference, insn);
-            let res = insn_function(self, state, insn, &pager, mv_store.as_ref())?;
+            trace_insn(self, state.pc as InsnReference, insn, tracer.as_deref());
+            let pc = state.pc as usize;
+            let timer = state.profile.is_some().then(ProfileTimer::start);
+            let res = insn_function(self, state, insn, &pager, mv_store.as_ref());
+            if let (Some(profile), Some(timer)) = (&mut state.profile, timer) {
+                if profile.len() != self.insns.len() {
+                    profile.resize(self.insns.len(), (0, Duration::ZERO));
+                }
+                profile[pc].0 += 1;
+                profile[pc].1 += timer.elapsed();
+            }
+            if vdbe_trace {
+                trace_registers(state, tracer.as_deref());
+            }
+            let res = match res {
+                Ok(res) => res,
+                Err(err) => return self.fail(&pager, mv_store.as_ref(), err),
+            };
             match res {
                 InsnFunctionStepResult::Step => {}
                 InsnFunctionStepResult::Done => return Ok(StepResult::Done),
                 InsnFunctionStepResult::IO => return Ok(StepResult::IO),
-                InsnFunctionStepResult::Row => return Ok(StepResult::Row),
+                InsnFunctionStepResult::Row => {
+                    state.rows_returned += 1;
+                    if let Some(limit) = state.exceeded_limit(&pager, false) {
+                        let _ = state.result_row.take();
+                        return self.fail(
+                            &pager,
+                            mv_store.as_ref(),
+                            LimboError::LimitExceeded(limit),
+                        );
+                    }
+                    return Ok(StepResult::Row);
+                }
                 InsnFunctionStepResult::Interrupt => return Ok(StepResult::Interrupt),
                 InsnFunctionStepResult::Busy => return Ok(StepResult::Busy),
             }
@@ -407,15 +607,16 @@ impl Program {
                     || (matches!(program_state.halt_state.unwrap(), HaltState::Checkpointing))
             );
             if program_state.halt_state.is_some() {
-                self.step_end_write_txn(&pager, &mut program_state.halt_state, connection.deref())
+                self.step_end_write_txn(&pager, program_state, connection.deref())
             } else if auto_commit {
                 let current_state = connection.transaction_state.borrow().clone();
                 match current_state {
-                    TransactionState::Write => self.step_end_write_txn(
-                        &pager,
-                        &mut program_state.halt_state,
-                        connection.deref(),
-                    ),
+                    TransactionState::Write => {
+                        if !connection.writing_materialized_views() {
+                            self.maintain_tables(&connection, program_state)?;
+                        }
+                        self.step_end_write_txn(&pager, program_state, connection.deref())
+                    }
                     TransactionState::Read => {
                         connection.transaction_state.replace(TransactionState::None);
                         pager.end_read_tx()?;
@@ -426,7 +627,7 @@ impl Program {
             } else {
                 if self.change_cnt_on {
                     if let Some(conn) = self.connection.upgrade() {
-                        conn.set_changes(self.n_change.get());
+                        conn.set_changes(program_state.n_change);
                     }
                 }
                 Ok(StepResult::Done)
@@ -434,10 +635,70 @@ impl Program {
         }
     }
 
+    /// Aborts the statement because of `err`, which is returned.
+    fn fail(
+        &self,
+        pager: &Rc<Pager>,
+        mv_store: Option<&Rc<MvStore>>,
+        err: LimboError,
+    ) -> Result<StepResult> {
+        if mv_store.is_none() {
+            self.abort(pager)?;
+        }
+        Err(err)
+    }
+
+    /// Undoes the changes of the statement after an error, like ON CONFLICT ABORT does: the
+    /// changes of earlier statements of an explicit transaction stay, and an implicit one ends.
+    fn abort(&self, pager: &Rc<Pager>) -> Result<()> {
+        pager.rollback_statement()?;
+        let connection = self
+            .connection
+            .upgrade()
+            .expect("only weak ref to connection?");
+        connection.changes.borrow_mut().rollback_statement();
+        if !*connection.auto_commit.borrow() {
+            return Ok(());
+        }
+        match connection.transaction_state.replace(TransactionState::None) {
+            TransactionState::Write => pager.rollback_write_tx(),
+            TransactionState::Read => pager.end_read_tx(),
+            TransactionState::None => Ok(()),
+        }
+    }
+
+    /// Brings the materialized views and vector indexes maintained from the tables the
+    /// transaction wrote up to date with its changes, in the transaction so that they commit
+    /// together. If that fails, the transaction is rolled back.
+    #[cfg_attr(not(feature = "vector"), allow(unused_variables))]
+    fn maintain_tables(
+        &self,
+        connection: &Rc<Connection>,
+        program_state: &mut ProgramState,
+    ) -> Result<()> {
+        let changes = connection.changes.borrow_mut().take();
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let result = matview::maintain(connection, &changes);
+        #[cfg(feature = "vector")]
+        let result = result.and_then(|()| {
+            program_state.vector_index_updates =
+                crate::vector::index::maintain(connection, &changes)?;
+            Ok(())
+        });
+        connection.changes.borrow_mut().put_back(changes);
+        if let Err(err) = result {
+            connection.rollback()?;
+            return Err(err);
+        }
+        Ok(())
+    }
+
     fn step_end_write_txn(
         &self,
         pager: &Rc<Pager>,
-        halt_state: &mut Option<HaltState>,
+        program_state: &mut ProgramState,
         connection: &Connection,
     ) -> Result<StepResult> {
         let checkpoint_status = pager.end_tx()?;
@@ -445,15 +706,29 @@ impl Program {
             CheckpointStatus::Done(_) => {
                 if self.change_cnt_on {
                     if let Some(conn) = self.connection.upgrade() {
-                        conn.set_changes(self.n_change.get());
+                        conn.set_changes(program_state.n_change);
                     }
                 }
                 connection.transaction_state.replace(TransactionState::None);
-                let _ = halt_state.take();
+                let _ = program_state.halt_state.take();
+                #[cfg(feature = "vector")]
+                for update in program_state.vector_index_updates.drain(..) {
+                    update.apply();
+                }
+                let changes = connection.changes.borrow_mut().take();
+                if !changes.is_empty() {
+                    let now = connection._db.io.now();
+                    let change_set = connection._db.change_subscribers.publish(
+                        changes,
+                        connection.replicated_token.get(),
+                        now.secs as u64 * 1_000_000 + now.micros as u64,
+                    );
+                    connection.last_commit_token.set(Some(change_set.token));
+                }
             }
             CheckpointStatus::IO => {
                 tracing::trace!("Checkpointing IO");
-                *halt_state = Some(HaltState::Checkpointing);
+                program_state.halt_state = Some(HaltState::Checkpointing);
                 return Ok(StepResult::IO);
             }
         }
@@ -482,6 +757,96 @@ impl Program {
         }
         buff
     }
+
+    /// Builds the per-instruction statistics collected while profiling, see
+    /// [crate::Statement::profile].
+    pub fn profile(&self, state: &ProgramState) -> Vec<InsnProfile> {
+        let Some(profile) = &state.profile else {
+            return Vec::new();
+        };
+        self.insns
+            .iter()
+            .enumerate()
+            .map(|(addr, (insn, _))| {
+                let (executions, elapsed) = profile.get(addr).copied().unwrap_or_default();
+                InsnProfile {
+                    addr: addr as InsnReference,
+                    insn: explain::insn_to_str(
+                        self,
+                        addr as InsnReference,
+                        insn,
+                        String::new(),
+                        self.comments
+                            .as_ref()
+                            .and_then(|comments| comments.get(&{ addr as InsnReference }).copied()),
+                    ),
+                    executions,
+                    elapsed,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Budgets of one execution of a statement, see [crate::Statement::set_limits]. They let
+/// servers bound the work a single request can cause.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExecutionLimits {
+    /// Rows the statement may return.
+    pub max_rows: Option<u64>,
+    /// Time since the first step, by the clock of the database's I/O. Time waiting for I/O
+    /// counts too.
+    pub max_duration: Option<Duration>,
+    /// Instructions the statement may run.
+    pub max_steps: Option<u64>,
+}
+
+/// How the statements of a connection accessed a table, including through its indexes, see
+/// [crate::Connection::table_stats]. Many full scans of a big table suggest a missing index.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableStats {
+    /// Rows and index entries the statements moved onto.
+    pub rows_read: u64,
+    /// Rows inserted, updated or deleted.
+    pub rows_written: u64,
+    /// Lookups of a row or index entry by its key.
+    pub seeks: u64,
+    /// Scans of the table starting from its first or last row.
+    pub full_scans: u64,
+}
+
+/// How often one instruction of a statement ran and how long it took.
+#[derive(Debug, Clone)]
+pub struct InsnProfile {
+    pub addr: InsnReference,
+    /// The instruction as shown by `EXPLAIN`.
+    pub insn: String,
+    pub executions: u64,
+    /// Time spent executing the instruction. Always zero on wasm, which has no monotonic clock.
+    pub elapsed: Duration,
+}
+
+/// Measures how long an instruction takes for the profiler.
+#[derive(Clone, Copy)]
+struct ProfileTimer {
+    #[cfg(not(target_family = "wasm"))]
+    start: std::time::Instant,
+}
+
+impl ProfileTimer {
+    fn start() -> Self {
+        Self {
+            #[cfg(not(target_family = "wasm"))]
+            start: std::time::Instant::now(),
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        #[cfg(not(target_family = "wasm"))]
+        return self.start.elapsed();
+        #[cfg(target_family = "wasm")]
+        return Duration::ZERO;
+    }
 }
 
 fn get_new_rowid<R: Rng>(cursor: &mut BTreeCursor, mut rng: R) -> Result<CursorResult<i64>> {
@@ -517,27 +882,71 @@ fn get_new_rowid<R: Rng>(cursor: &mut BTreeCursor, mut rng: R) -> Result<CursorR
     Ok(CursorResult::Ok(rowid.try_into().unwrap()))
 }
 
-fn make_record(registers: &[Register], start_reg: &usize, count: &usize) -> ImmutableRecord {
-    ImmutableRecord::from_registers(&registers[*start_reg..*start_reg + *count])
+fn trace_insn(program: &Program, addr: InsnReference, insn: &Insn, tracer: Option<&Tracer>) {
+    let tracer = tracer.filter(|tracer| tracer.insns);
+    if tracer.is_none() && !tracing::enabled!(tracing::Level::TRACE) {
+        return;
+    }
+    let insn_str = explain::insn_to_str(
+        program,
+        addr,
+        insn,
+        String::new(),
+        program
+            .comments
+            .as_ref()
+            .and_then(|comments| comments.get(&{ addr }).copied()),
+    );
+    tracing::trace!("{}", insn_str);
+    if let Some(tracer) = tracer {
+        (tracer.callback)(TraceEvent::Insn(&insn_str));
+    }
 }
 
-fn trace_insn(program: &Program, addr: InsnReference, insn: &Insn) {
-    if !tracing::enabled!(tracing::Level::TRACE) {
+/// Reports the registers that changed since the last call, for `PRAGMA vdbe_trace`.
+fn trace_registers(state: &mut ProgramState, tracer: Option<&Tracer>) {
+    let traced = &mut state.traced_registers;
+    if traced.len() != state.registers.len() {
+        // Registers start out as NULL, which is not worth reporting.
+        traced.resize(state.registers.len(), "NULL".to_string());
+    }
+    let mut changed = String::new();
+    for (i, register) in state.registers.iter().enumerate() {
+        let value = register_literal(register);
+        if value != traced[i] {
+            if !changed.is_empty() {
+                changed.push(' ');
+            }
+            changed.push_str(&format!("r[{}]={}", i, value));
+            traced[i] = value;
+        }
+    }
+    if changed.is_empty() {
         return;
     }
-    tracing::trace!(
-        "{}",
-        explain::insn_to_str(
-            program,
-            addr,
-            insn,
-            String::new(),
-            program
-                .comments
-                .as_ref()
-                .and_then(|comments| comments.get(&{ addr }).copied())
-        )
-    );
+    tracing::trace!("{}", changed);
+    if let Some(tracer) = tracer {
+        (tracer.callback)(TraceEvent::Registers(&changed));
+    }
+}
+
+fn register_literal(register: &Register) -> String {
+    let mut literal = String::new();
+    match register {
+        Register::OwnedValue(value) => push_literal(&mut literal, value),
+        Register::Record(record) => {
+            literal.push_str("mkrec(");
+            for (i, value) in record.get_values().iter().enumerate() {
+                if i > 0 {
+                    literal.push(',');
+                }
+                push_literal(&mut literal, &value.to_owned());
+            }
+            literal.push(')');
+        }
+        Register::Aggregate(_) => literal.push_str("aggregate"),
+    }
+    literal
 }
 
 fn print_insn(program: &Program, addr: InsnReference, insn: &Insn, indent: String, w: &mut String) {
@@ -561,7 +970,9 @@ fn get_indent_count(indent_count: usize, curr_insn: &Insn, prev_insn: Option<&In
             | Insn::LastAwait { .. }
             | Insn::SorterSort { .. }
             | Insn::SeekGE { .. }
-            | Insn::SeekGT { .. } => indent_count + 1,
+            | Insn::SeekGT { .. }
+            | Insn::SeekLE { .. }
+            | Insn::SeekLT { .. } => indent_count + 1,
             _ => indent_count,
         }
     } else {
@@ -615,6 +1026,43 @@ impl<'a> FromValueRow<'a> for &'a OwnedValue {
     }
 }
 
+impl<'a> FromValueRow<'a> for f64 {
+    fn from_value(value: &'a OwnedValue) -> Result<Self> {
+        match value {
+            OwnedValue::Float(f) => Ok(*f),
+            OwnedValue::Integer(i) => Ok(*i as f64),
+            _ => Err(LimboError::ConversionError("Expected real value".into())),
+        }
+    }
+}
+
+impl<'a> FromValueRow<'a> for Vec<u8> {
+    fn from_value(value: &'a OwnedValue) -> Result<Self> {
+        match value {
+            OwnedValue::Blob(b) => Ok(b.clone()),
+            _ => Err(LimboError::ConversionError("Expected blob value".into())),
+        }
+    }
+}
+
+impl<'a, T: FromValueRow<'a> + 'a> FromValueRow<'a> for Option<T> {
+    fn from_value(value: &'a OwnedValue) -> Result<Self> {
+        match value {
+            OwnedValue::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+/// A type built from a result row, usually through `#[derive(FromRow)]`.
+pub trait FromRow: Sized {
+    /// Names of the result columns the type reads, in the order `from_row` expects them.
+    const COLUMNS: &'static [&'static str];
+
+    /// Builds the value from `row`, where `columns[i]` is the position of `COLUMNS[i]` in the row.
+    fn from_row(row: &Row, columns: &[usize]) -> Result<Self>;
+}
+
 impl Row {
     pub fn get<'a, T: FromValueRow<'a> + 'a>(&'a self, idx: usize) -> Result<T> {
         let value = unsafe { self.values.add(idx).as_ref().unwrap() };
@@ -645,3 +1093,15 @@ impl Row {
         self.count
     }
 }
+
+/// Serializes the row as a sequence of its values; see [`crate::RowMap`] for a map keyed by
+/// column name.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Row {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.get_values())
+    }
+}
@@ -1,61 +1,378 @@
-use crate::types::ImmutableRecord;
+use crate::io::{Buffer, Completion, File, ReadCompletion, WriteCompletion, IO};
+use crate::memory_limit::{MemoryLimit, MemoryPressure};
+use crate::storage::sqlite3_ondisk::{read_record, read_varint, write_varint_to_vec};
+use crate::translate::collate::CollationSeq;
+use crate::types::{ImmutableRecord, RefValue};
+use crate::Result;
+use std::cell::{Cell, RefCell};
 use std::cmp::Ordering;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::vec::Drain;
+
+/// Records held before a sorter spills them, however low the memory limit is, so that a sorter
+/// doesn't write runs of a handful of records while other parts of the database use the memory.
+const MIN_SPILL_SIZE: usize = 64 * 1024;
+
+/// Size of the writes of a spilled run, and of the reads when merging it back.
+const SPILL_CHUNK_SIZE: usize = 64 * 1024;
 
 pub struct Sorter {
     records: Vec<ImmutableRecord>,
     current: Option<ImmutableRecord>,
-    order: Vec<bool>,
+    order: Vec<SortKey>,
+    /// Only the first rows in sort order are kept, e.g. for ORDER BY ... LIMIT.
+    max_rows: Option<usize>,
+    /// Where records go once they take more memory than the limit allows, see
+    /// [Self::set_memory_limit].
+    spill: Option<Spill>,
+}
+
+/// How the sorter compares one column of its records.
+#[derive(Clone, Copy, Debug)]
+pub struct SortKey {
+    pub ascending: bool,
+    /// Used when both values are text.
+    pub collation: CollationSeq,
+}
+
+/// Sorted runs of records written to a temp file, which [Sorter::sort] merges with the records
+/// still in memory.
+struct Spill {
+    io: Arc<dyn IO>,
+    memory_limit: Arc<MemoryLimit>,
+    /// Bytes of the records in memory counted against the limit.
+    records_size: usize,
+    file: Option<Arc<dyn File>>,
+    file_size: usize,
+    runs: Vec<Run>,
+}
+
+/// A run in the spill file, as the length of each record followed by its payload.
+struct Run {
+    /// Offset of the bytes not read into `buf` yet.
+    offset: usize,
+    end: usize,
+    buf: Vec<u8>,
+    pos: usize,
+    /// The smallest record of the run not returned yet.
+    head: Option<ImmutableRecord>,
 }
 
 impl Sorter {
-    pub fn new(order: Vec<bool>) -> Self {
+    pub fn new(order: Vec<SortKey>, max_rows: Option<usize>) -> Self {
         Self {
             records: Vec::new(),
             current: None,
             order,
+            max_rows,
+            spill: None,
         }
     }
+
+    /// Counts the records against `memory_limit`, and writes them to a temp file of `io` while
+    /// it is exceeded. Sorters with a bound on the rows they keep already hold few of them and
+    /// never spill.
+    pub(crate) fn set_memory_limit(&mut self, io: Arc<dyn IO>, memory_limit: Arc<MemoryLimit>) {
+        if self.max_rows.is_some() {
+            return;
+        }
+        self.spill = Some(Spill {
+            io,
+            memory_limit,
+            records_size: 0,
+            file: None,
+            file_size: 0,
+            runs: Vec::new(),
+        });
+    }
+
     pub fn is_empty(&self) -> bool {
         self.records.is_empty()
+            && self
+                .spill
+                .as_ref()
+                .is_none_or(|spill| spill.runs.is_empty())
     }
 
     pub fn has_more(&self) -> bool {
         self.current.is_some()
     }
 
+    fn sort_records(&mut self) {
+        let order = &self.order;
+        self.records.sort_by(|a, b| compare_records(order, a, b));
+    }
+
     // We do the sorting here since this is what is called by the SorterSort instruction
-    pub fn sort(&mut self) {
-        self.records.sort_by(|a, b| {
-            let cmp_by_idx = |idx: usize, ascending: bool| {
-                let a = &a.get_value(idx);
-                let b = &b.get_value(idx);
-                if ascending {
-                    a.cmp(b)
-                } else {
-                    b.cmp(a)
-                }
+    // Returns the rows beyond the bound of ORDER BY ... LIMIT, which are dropped.
+    pub fn sort(&mut self) -> Result<Drain<'_, ImmutableRecord>> {
+        self.sort_records();
+        self.records.reverse();
+        if let Some(spill) = self.spill.as_mut() {
+            for run in spill.runs.iter_mut() {
+                run.head = run.next_record(&spill.file, &spill.io)?;
+            }
+        }
+        // Rows are taken from the back, so the ones beyond the bound are at the front.
+        let excess = self
+            .max_rows
+            .map_or(0, |max_rows| self.records.len().saturating_sub(max_rows));
+        let has_runs = self
+            .spill
+            .as_ref()
+            .is_some_and(|spill| !spill.runs.is_empty());
+        self.current = if self.records.len() > excess || has_runs {
+            self.pop_next()?
+        } else {
+            None
+        };
+        Ok(self.records.drain(..excess))
+    }
+
+    /// Moves to the next record, returning the one that was current.
+    pub fn next(&mut self) -> Result<Option<ImmutableRecord>> {
+        let next = self.pop_next()?;
+        Ok(std::mem::replace(&mut self.current, next))
+    }
+
+    /// Takes the smallest record left, from memory or from one of the spilled runs. Ties go to
+    /// the run written first, and records in memory come last, so the sort stays stable.
+    fn pop_next(&mut self) -> Result<Option<ImmutableRecord>> {
+        let Some(spill) = self.spill.as_mut().filter(|spill| !spill.runs.is_empty()) else {
+            return Ok(self.records.pop());
+        };
+        let mut smallest: Option<(usize, &ImmutableRecord)> = None;
+        for (i, run) in spill.runs.iter().enumerate() {
+            let Some(head) = &run.head else {
+                continue;
             };
+            if smallest.is_none_or(|(_, record)| {
+                compare_records(&self.order, head, record) == Ordering::Less
+            }) {
+                smallest = Some((i, head));
+            }
+        }
+        let from_memory = match (smallest, self.records.last()) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some((_, record)), Some(last)) => {
+                compare_records(&self.order, last, record) == Ordering::Less
+            }
+        };
+        if from_memory {
+            return Ok(self.records.pop());
+        }
+        let i = smallest.unwrap().0;
+        let run = &mut spill.runs[i];
+        let next = run.next_record(&spill.file, &spill.io)?;
+        Ok(std::mem::replace(&mut run.head, next))
+    }
+
+    pub fn record(&self) -> Option<&ImmutableRecord> {
+        self.current.as_ref()
+    }
+
+    /// The records that were not read yet and are in memory, including the current one.
+    pub fn into_records(mut self) -> impl Iterator<Item = ImmutableRecord> {
+        let heads: Vec<ImmutableRecord> = self
+            .spill
+            .as_mut()
+            .map(|spill| {
+                spill
+                    .runs
+                    .iter_mut()
+                    .filter_map(|run| run.head.take())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let current = self.current.take();
+        let records = std::mem::take(&mut self.records);
+        current.into_iter().chain(records).chain(heads)
+    }
+
+    /// Adds a record, returning the ones that can no longer make it into the result.
+    pub fn insert(&mut self, record: ImmutableRecord) -> Result<Drain<'_, ImmutableRecord>> {
+        if self.max_rows != Some(0) {
+            if let Some(spill) = self.spill.as_mut() {
+                let size = record.get_payload().len();
+                spill.memory_limit.allocate(size);
+                spill.records_size += size;
+            }
+            self.records.push(record);
+        }
+        // With a bound, the rows that can no longer make it are dropped as we go, so that
+        // at most twice the bound is held at a time. The sort is stable, so rows that
+        // compare equal keep their insertion order.
+        if let Some(max_rows) = self.max_rows {
+            if self.records.len() >= max_rows * 2 {
+                self.sort_records();
+                return Ok(self.records.drain(max_rows..));
+            }
+        }
+        if self.spill.as_ref().is_some_and(|spill| {
+            spill.records_size >= MIN_SPILL_SIZE && spill.memory_limit.is_exceeded()
+        }) {
+            self.write_run()?;
+        }
+        let len = self.records.len();
+        Ok(self.records.drain(len..))
+    }
 
-            let mut cmp_ret = Ordering::Equal;
-            for (idx, &is_asc) in self.order.iter().enumerate() {
-                cmp_ret = cmp_by_idx(idx, is_asc);
-                if cmp_ret != Ordering::Equal {
-                    break;
-                }
+    /// Writes the records in memory to the spill file as a sorted run.
+    fn write_run(&mut self) -> Result<()> {
+        self.sort_records();
+        let records = std::mem::take(&mut self.records);
+        let spill = self
+            .spill
+            .as_mut()
+            .expect("only sorters with a spill file write runs");
+        let file = match &spill.file {
+            Some(file) => file.clone(),
+            None => spill.file.insert(spill.io.open_temp_file()?).clone(),
+        };
+        let start = spill.file_size;
+        let mut chunk = Vec::with_capacity(SPILL_CHUNK_SIZE);
+        for record in &records {
+            let payload = record.get_payload();
+            write_varint_to_vec(payload.len() as u64, &mut chunk);
+            chunk.extend_from_slice(payload);
+            if chunk.len() >= SPILL_CHUNK_SIZE {
+                spill.file_size += write_chunk(&file, &spill.io, spill.file_size, &mut chunk)?;
             }
-            cmp_ret
+        }
+        spill.file_size += write_chunk(&file, &spill.io, spill.file_size, &mut chunk)?;
+        spill.runs.push(Run {
+            offset: start,
+            end: spill.file_size,
+            buf: Vec::new(),
+            pos: 0,
+            head: None,
         });
-        self.records.reverse();
-        self.next()
+        spill.memory_limit.free(spill.records_size);
+        spill.memory_limit.report(MemoryPressure::Sorter {
+            bytes: spill.records_size,
+        });
+        spill.records_size = 0;
+        Ok(())
     }
-    pub fn next(&mut self) {
-        self.current = self.records.pop();
+}
+
+impl Drop for Sorter {
+    fn drop(&mut self) {
+        if let Some(spill) = &self.spill {
+            spill.memory_limit.free(spill.records_size);
+        }
     }
-    pub fn record(&self) -> Option<&ImmutableRecord> {
-        self.current.as_ref()
+}
+
+impl Run {
+    fn next_record(
+        &mut self,
+        file: &Option<Arc<dyn File>>,
+        io: &Arc<dyn IO>,
+    ) -> Result<Option<ImmutableRecord>> {
+        let Some(file) = file else {
+            return Ok(None);
+        };
+        if self.pos == self.buf.len() && self.offset == self.end {
+            return Ok(None);
+        }
+        // A varint takes at most 9 bytes.
+        self.fill(file, io, 9)?;
+        let (size, n) = read_varint(&self.buf[self.pos..])?;
+        let size = size as usize;
+        self.fill(file, io, n + size)?;
+        let payload = &self.buf[self.pos + n..self.pos + n + size];
+        let mut record = ImmutableRecord::new(size, 0);
+        read_record(payload, &mut record)?;
+        self.pos += n + size;
+        Ok(Some(record))
+    }
+
+    /// Reads from the file until `len` bytes past `pos` are in the buffer, or the run ends.
+    fn fill(&mut self, file: &Arc<dyn File>, io: &Arc<dyn IO>, len: usize) -> Result<()> {
+        if self.buf.len() - self.pos >= len || self.offset == self.end {
+            return Ok(());
+        }
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        let size = (self.end - self.offset).min(SPILL_CHUNK_SIZE.max(len - self.buf.len()));
+        let drop_fn = Rc::new(|_buf| {});
+        #[allow(clippy::arc_with_non_send_sync)]
+        let buf = Arc::new(RefCell::new(Buffer::allocate(size, drop_fn)));
+        let done = Rc::new(Cell::new(false));
+        let complete = {
+            let done = done.clone();
+            Box::new(move |_buf: Arc<RefCell<Buffer>>| done.set(true))
+        };
+        file.pread(
+            self.offset,
+            Completion::Read(ReadCompletion::new(buf.clone(), complete)),
+        )?;
+        while !done.get() {
+            io.run_once()?;
+        }
+        self.buf.extend_from_slice(buf.borrow().as_slice());
+        self.offset += size;
+        Ok(())
+    }
+}
+
+/// Writes `chunk` at `offset` and waits for the write to complete, leaving `chunk` empty.
+/// Returns the number of bytes written.
+fn write_chunk(
+    file: &Arc<dyn File>,
+    io: &Arc<dyn IO>,
+    offset: usize,
+    chunk: &mut Vec<u8>,
+) -> Result<usize> {
+    let len = chunk.len();
+    if len == 0 {
+        return Ok(0);
     }
+    let data = std::mem::replace(chunk, Vec::with_capacity(SPILL_CHUNK_SIZE));
+    let drop_fn = Rc::new(|_buf| {});
+    #[allow(clippy::arc_with_non_send_sync)]
+    let buf = Arc::new(RefCell::new(Buffer::new(Pin::new(data), drop_fn)));
+    let done = Rc::new(Cell::new(false));
+    let complete = {
+        let done = done.clone();
+        Box::new(move |_| done.set(true))
+    };
+    file.pwrite(
+        offset,
+        buf,
+        Completion::Write(WriteCompletion::new(complete)),
+    )?;
+    while !done.get() {
+        io.run_once()?;
+    }
+    Ok(len)
+}
 
-    pub fn insert(&mut self, record: &ImmutableRecord) {
-        self.records.push(record.clone());
+fn compare_records(order: &[SortKey], a: &ImmutableRecord, b: &ImmutableRecord) -> Ordering {
+    let cmp_by_idx = |idx: usize, key: &SortKey| {
+        let ordering = match (a.get_value(idx), b.get_value(idx)) {
+            (RefValue::Text(a), RefValue::Text(b)) => {
+                key.collation.compare_strings(a.as_str(), b.as_str())
+            }
+            (a, b) => a.cmp(b),
+        };
+        if key.ascending {
+            ordering
+        } else {
+            ordering.reverse()
+        }
+    };
+
+    let mut cmp_ret = Ordering::Equal;
+    for (idx, key) in order.iter().enumerate() {
+        cmp_ret = cmp_by_idx(idx, key);
+        if cmp_ret != Ordering::Equal {
+            break;
+        }
     }
+    cmp_ret
 }
@@ -0,0 +1,877 @@
+//! Vector indexes: approximate nearest neighbor search over a column of vectors.
+//!
+//! `CREATE INDEX i ON t(libsql_vector_idx(embedding))` indexes a column declared with a vector
+//! type, `F32_BLOB(n)`, `FLOAT32(n)`, `F64_BLOB(n)` or `FLOAT64(n)` for vectors of n dimensions.
+//! A query then looks up the rows nearest to a vector with
+//! `WHERE embedding MATCH vector('[...]') LIMIT k`, which loops over the k (plus OFFSET) nearest
+//! rows the index finds, nearest first, instead of scanning the table.
+//!
+//! The index is an inverted file (IVF): the vectors are clustered around `lists` centroids, and a
+//! search only compares the query with the vectors of the `probes` lists whose centroids are the
+//! nearest to it. The centroids are computed with k-means when the index is created. New vectors
+//! join the list of their nearest centroid, or start a list of their own while there are fewer
+//! than `lists`, so an index created on an empty table is best created again once it has data.
+//! Options are extra arguments of `libsql_vector_idx`, e.g. `'metric=l2'`, `'lists=128'` or
+//! `'probes=16'`. The metric is `cos` by default.
+//!
+//! The centroids and vectors are kept in the shadow tables `i_centroids` and `i_shadow` and the
+//! definitions in `limbo_vector_indexes`, so the index survives reopening the database, while
+//! searches are served from a copy in memory. Like materialized views (see [crate::matview]), the
+//! index is maintained from the row changes captured for the table: the shadow tables are written
+//! in the transaction writing the table right before it commits, and the copy in memory is
+//! updated once it did, so searches don't see the changes of the open transaction. Writes of
+//! programs that don't know about the index, such as SQLite, don't reach it; creating the index
+//! again rebuilds it. Rows whose value isn't a vector of the column's type and dimensions are
+//! left out of it.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use fallible_iterator::FallibleIterator;
+use limbo_sqlite3_parser::ast;
+use limbo_sqlite3_parser::lexer::sql::Parser;
+use parking_lot::RwLock;
+
+use crate::cdc::RowChange;
+use crate::matview::{in_committing_transaction, in_transaction, quote_ident, Statements};
+use crate::schema::Schema;
+use crate::types::OwnedValue;
+use crate::util::normalize_ident;
+use crate::vdbe::Register;
+use crate::{Connection, LimboError, Result};
+
+use super::vector_types::{parse_vector, vector_serialize_f32, Vector, VectorType};
+
+/// The table with the name and `CREATE INDEX` statement of every vector index.
+pub const CATALOG_TABLE_NAME: &str = "limbo_vector_indexes";
+
+/// The function an index is created on to make it a vector index, as in libSQL.
+const INDEX_FUNCTION: &str = "libsql_vector_idx";
+
+const DEFAULT_LISTS: usize = 64;
+const DEFAULT_PROBES: usize = 8;
+/// The centroids are trained on at most this many vectors per list.
+const TRAINING_VECTORS_PER_LIST: usize = 64;
+const KMEANS_ITERATIONS: usize = 10;
+
+/// Whether `stmt` creates or drops a vector index.
+pub fn is_vector_index_stmt(schema: &Schema, stmt: &ast::Stmt) -> bool {
+    match stmt {
+        ast::Stmt::CreateIndex { columns, .. } => columns.iter().any(|column| {
+            matches!(
+                &column.expr,
+                ast::Expr::FunctionCall { name, .. } if name.0.eq_ignore_ascii_case(INDEX_FUNCTION)
+            )
+        }),
+        ast::Stmt::DropIndex { idx_name, .. } => {
+            schema.get_vector_index(&idx_name.name.0).is_some()
+        }
+        _ => false,
+    }
+}
+
+/// Runs `sql`, which creates or drops a vector index, see
+/// [crate::vdbe::insn::Insn::VectorIndex].
+pub(crate) fn run(conn: &Rc<Connection>, sql: &str) -> Result<()> {
+    let mut parser = Parser::new(sql.as_bytes());
+    match parser.next()? {
+        Some(ast::Cmd::Stmt(ast::Stmt::DropIndex {
+            if_exists,
+            idx_name,
+        })) => drop_index(conn, &idx_name.name.0, if_exists),
+        _ => create(conn, sql),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Metric {
+    Cosine,
+    L2,
+}
+
+impl Metric {
+    /// The distance between `a` and `b` for ranking: vectors are normalized for the cosine
+    /// metric and the L2 distance is left squared, which both keep the order of the distances.
+    fn distance(self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            Metric::Cosine => 1.0 - a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>(),
+            Metric::L2 => a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum(),
+        }
+    }
+}
+
+pub struct VectorIndex {
+    /// Normalized name of the index, which its shadow tables are named after.
+    pub name: String,
+    /// Normalized name of the indexed table.
+    pub table_name: String,
+    /// Position of the indexed column in the table.
+    pub column: usize,
+    /// The `CREATE INDEX` statement.
+    pub sql: String,
+    vector_type: VectorType,
+    dims: usize,
+    metric: Metric,
+    lists: usize,
+    probes: usize,
+    ivf: RwLock<Ivf>,
+}
+
+impl fmt::Debug for VectorIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VectorIndex")
+            .field("name", &self.name)
+            .field("table_name", &self.table_name)
+            .field("column", &self.column)
+            .finish()
+    }
+}
+
+impl VectorIndex {
+    /// Reads the definition of an index from its `CREATE INDEX` statement. Also returns whether
+    /// the statement has `IF NOT EXISTS`.
+    fn define(conn: &Rc<Connection>, sql: &str) -> Result<(Self, bool)> {
+        let mut parser = Parser::new(sql.as_bytes());
+        let Some(ast::Cmd::Stmt(ast::Stmt::CreateIndex {
+            unique,
+            if_not_exists,
+            idx_name,
+            tbl_name,
+            columns,
+            where_clause,
+        })) = parser.next()?
+        else {
+            return Err(LimboError::InternalError(format!(
+                "not a vector index definition: {sql}"
+            )));
+        };
+        if unique || where_clause.is_some() {
+            return Err(LimboError::ParseError(
+                "a vector index can't be UNIQUE or partial".to_string(),
+            ));
+        }
+        let [ast::SortedColumn {
+            expr:
+                ast::Expr::FunctionCall {
+                    name,
+                    args: Some(args),
+                    ..
+                },
+            ..
+        }] = columns.as_slice()
+        else {
+            return Err(LimboError::ParseError(format!(
+                "a vector index is created on a single {INDEX_FUNCTION}(column, options...)"
+            )));
+        };
+        if !name.0.eq_ignore_ascii_case(INDEX_FUNCTION) {
+            return Err(LimboError::ParseError(format!(
+                "a vector index is created on a single {INDEX_FUNCTION}(column, options...)"
+            )));
+        }
+        let column_name = match args.first() {
+            Some(ast::Expr::Id(id)) => normalize_ident(&id.0),
+            Some(ast::Expr::Qualified(_, column)) => normalize_ident(&column.0),
+            _ => {
+                return Err(LimboError::ParseError(format!(
+                    "the first argument of {INDEX_FUNCTION} must be a column"
+                )))
+            }
+        };
+        let mut metric = Metric::Cosine;
+        let mut lists = DEFAULT_LISTS;
+        let mut probes = DEFAULT_PROBES;
+        for arg in &args[1..] {
+            let ast::Expr::Literal(ast::Literal::String(option)) = arg else {
+                return Err(LimboError::ParseError(format!(
+                    "the options of {INDEX_FUNCTION} must be strings like 'metric=cos'"
+                )));
+            };
+            let option = option.trim_matches('\'');
+            let invalid =
+                || LimboError::ParseError(format!("invalid vector index option: {option}"));
+            let (key, value) = option.split_once('=').ok_or_else(invalid)?;
+            match key.trim().to_lowercase().as_str() {
+                "metric" => {
+                    metric = match value.trim().to_lowercase().as_str() {
+                        "cos" | "cosine" => Metric::Cosine,
+                        "l2" => Metric::L2,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "lists" => lists = value.trim().parse().map_err(|_| invalid())?,
+                "probes" => probes = value.trim().parse().map_err(|_| invalid())?,
+                _ => return Err(invalid()),
+            }
+        }
+        if lists == 0 || probes == 0 {
+            return Err(LimboError::ParseError(
+                "a vector index needs at least one list and one probe".to_string(),
+            ));
+        }
+
+        let table = conn
+            .schema
+            .read()
+            .get_btree_table(&tbl_name.0)
+            .ok_or_else(|| LimboError::ParseError(format!("no such table: {}", tbl_name.0)))?;
+        let (column, _) = table.get_column(&column_name).ok_or_else(|| {
+            LimboError::ParseError(format!("no such column: {}.{}", table.name, column_name))
+        })?;
+        let (vector_type, dims) = column_vector_type(conn, &table.name, &column_name)?;
+        let index = Self {
+            name: normalize_ident(&idx_name.name.0),
+            table_name: normalize_ident(&table.name),
+            column,
+            sql: sql.to_string(),
+            vector_type,
+            dims,
+            metric,
+            lists,
+            probes,
+            ivf: RwLock::new(Ivf::default()),
+        };
+        Ok((index, if_not_exists))
+    }
+
+    fn centroids_table(&self) -> String {
+        quote_ident(&format!("{}_centroids", self.name))
+    }
+
+    fn shadow_table(&self) -> String {
+        quote_ident(&format!("{}_shadow", self.name))
+    }
+
+    /// The vector in `value` as the index compares it, None if it isn't a vector of the column's
+    /// type and dimensions.
+    fn indexed_vector(&self, value: &OwnedValue) -> Option<Vec<f32>> {
+        let register = Register::OwnedValue(value.clone());
+        let vector = parse_vector(&register, Some(self.vector_type.clone())).ok()?;
+        self.prepare(&vector)
+    }
+
+    fn prepare(&self, vector: &Vector) -> Option<Vec<f32>> {
+        if vector.dims != self.dims {
+            return None;
+        }
+        let mut values: Vec<f32> = match vector.vector_type {
+            VectorType::Float32 => vector.as_f32_slice().to_vec(),
+            VectorType::Float64 => vector.as_f64_slice().iter().map(|v| *v as f32).collect(),
+        };
+        if values.iter().any(|v| !v.is_finite()) {
+            return None;
+        }
+        if self.metric == Metric::Cosine && !normalize(&mut values) {
+            return None;
+        }
+        Some(values)
+    }
+
+    /// The rowids of the (approximately) `k` nearest rows to `query`, nearest first.
+    pub fn search(&self, query: &Vector, k: usize) -> Result<Vec<i64>> {
+        if query.dims != self.dims {
+            return Err(LimboError::ConversionError(format!(
+                "vector index {} has {} dimensions, the query vector has {}",
+                self.name, self.dims, query.dims
+            )));
+        }
+        let Some(query) = self.prepare(query) else {
+            return Err(LimboError::ConversionError(
+                "Invalid vector value".to_string(),
+            ));
+        };
+        Ok(self.ivf.read().search(&query, k, self.probes, self.metric))
+    }
+
+    /// Computes the centroids from the rows of the table and stores them and the vectors.
+    fn build(&self, statements: &mut Statements) -> Result<()> {
+        let rows = statements.query(
+            &format!(
+                "SELECT rowid, {} FROM {}",
+                quote_ident(&self.column_name()?),
+                quote_ident(&self.table_name)
+            ),
+            Vec::new(),
+        )?;
+        let vectors: Vec<(i64, Vec<f32>)> = rows
+            .iter()
+            .filter_map(|row| match row.as_slice() {
+                [OwnedValue::Integer(rowid), value] => {
+                    self.indexed_vector(value).map(|vector| (*rowid, vector))
+                }
+                _ => None,
+            })
+            .collect();
+        let ivf = Ivf::build(vectors, self.lists, self.metric);
+        let insert_centroid = format!("INSERT INTO {} VALUES (?1, ?2)", self.centroids_table());
+        for (list, centroid) in ivf.centroids.iter().enumerate() {
+            statements.query(
+                &insert_centroid,
+                vec![OwnedValue::Integer(list as i64), encode(centroid)],
+            )?;
+        }
+        let insert_vector = format!("INSERT INTO {} VALUES (?1, ?2, ?3)", self.shadow_table());
+        for (list, entries) in ivf.lists.iter().enumerate() {
+            for (rowid, vector) in entries {
+                statements.query(
+                    &insert_vector,
+                    vec![
+                        OwnedValue::Integer(*rowid),
+                        OwnedValue::Integer(list as i64),
+                        encode(vector),
+                    ],
+                )?;
+            }
+        }
+        *self.ivf.write() = ivf;
+        Ok(())
+    }
+
+    /// Reads the centroids and vectors back from the shadow tables.
+    fn reload(&self, statements: &mut Statements) -> Result<()> {
+        let mut ivf = Ivf::default();
+        let centroids = statements.query(
+            &format!(
+                "SELECT list, centroid FROM {} ORDER BY list",
+                self.centroids_table()
+            ),
+            Vec::new(),
+        )?;
+        for row in centroids {
+            if let [OwnedValue::Integer(_), OwnedValue::Blob(blob)] = row.as_slice() {
+                ivf.centroids.push(decode(blob));
+                ivf.lists.push(Vec::new());
+            }
+        }
+        let vectors = statements.query(
+            &format!("SELECT id, list, vector FROM {}", self.shadow_table()),
+            Vec::new(),
+        )?;
+        for row in vectors {
+            if let [OwnedValue::Integer(rowid), OwnedValue::Integer(list), OwnedValue::Blob(blob)] =
+                row.as_slice()
+            {
+                let list = *list as usize;
+                if list < ivf.lists.len() {
+                    ivf.rows.insert(*rowid, list);
+                    ivf.lists[list].push((*rowid, decode(blob)));
+                }
+            }
+        }
+        *self.ivf.write() = ivf;
+        Ok(())
+    }
+
+    /// Writes `changes` to its table to the shadow tables, returning what to change in the copy
+    /// in memory once they commit.
+    fn apply(&self, statements: &mut Statements, changes: &[&RowChange]) -> Result<Vec<IvfOp>> {
+        let delete = format!("DELETE FROM {} WHERE id = ?1", self.shadow_table());
+        let insert_centroid = format!("INSERT INTO {} VALUES (?1, ?2)", self.centroids_table());
+        let insert_vector = format!("INSERT INTO {} VALUES (?1, ?2, ?3)", self.shadow_table());
+        let ivf = self.ivf.read();
+        let mut centroids = ivf.centroids.clone();
+        // Whether the rows changed so far are indexed, which the copy in memory doesn't know yet.
+        let mut indexed: HashMap<i64, bool> = HashMap::new();
+        let mut ops = Vec::new();
+        for change in changes {
+            let rowid = change.rowid;
+            if *indexed
+                .entry(rowid)
+                .or_insert_with(|| ivf.rows.contains_key(&rowid))
+            {
+                statements.query(&delete, vec![OwnedValue::Integer(rowid)])?;
+                indexed.insert(rowid, false);
+                ops.push(IvfOp::Remove(rowid));
+            }
+            let Some(vector) = change
+                .new
+                .as_ref()
+                .and_then(|values| values.get(self.column))
+                .and_then(|value| self.indexed_vector(value))
+            else {
+                continue;
+            };
+            let list = match nearest(&centroids, &vector, self.metric) {
+                Some(list) if centroids.len() >= self.lists => list,
+                _ => {
+                    statements.query(
+                        &insert_centroid,
+                        vec![OwnedValue::Integer(centroids.len() as i64), encode(&vector)],
+                    )?;
+                    centroids.push(vector.clone());
+                    ops.push(IvfOp::AddCentroid(vector.clone()));
+                    centroids.len() - 1
+                }
+            };
+            statements.query(
+                &insert_vector,
+                vec![
+                    OwnedValue::Integer(rowid),
+                    OwnedValue::Integer(list as i64),
+                    encode(&vector),
+                ],
+            )?;
+            indexed.insert(rowid, true);
+            ops.push(IvfOp::Add {
+                rowid,
+                list,
+                vector,
+            });
+        }
+        Ok(ops)
+    }
+
+    fn column_name(&self) -> Result<String> {
+        let mut parser = Parser::new(self.sql.as_bytes());
+        if let Some(ast::Cmd::Stmt(ast::Stmt::CreateIndex { columns, .. })) = parser.next()? {
+            if let Some(ast::Expr::FunctionCall {
+                args: Some(args), ..
+            }) = columns.first().map(|column| &column.expr)
+            {
+                match args.first() {
+                    Some(ast::Expr::Id(id)) => return Ok(normalize_ident(&id.0)),
+                    Some(ast::Expr::Qualified(_, column)) => return Ok(normalize_ident(&column.0)),
+                    _ => {}
+                }
+            }
+        }
+        Err(LimboError::InternalError(format!(
+            "not a vector index definition: {}",
+            self.sql
+        )))
+    }
+}
+
+/// A change to the copy in memory of an index, see [IndexUpdate].
+enum IvfOp {
+    Remove(i64),
+    AddCentroid(Vec<f32>),
+    Add {
+        rowid: i64,
+        list: usize,
+        vector: Vec<f32>,
+    },
+}
+
+/// The changes to the copy in memory of an index that a transaction about to commit wrote to its
+/// shadow tables, made with [IndexUpdate::apply] once it committed.
+pub(crate) struct IndexUpdate {
+    index: Arc<VectorIndex>,
+    ops: Vec<IvfOp>,
+}
+
+impl IndexUpdate {
+    pub(crate) fn apply(self) {
+        let mut ivf = self.index.ivf.write();
+        for op in self.ops {
+            match op {
+                IvfOp::Remove(rowid) => {
+                    ivf.remove(rowid);
+                }
+                IvfOp::AddCentroid(centroid) => {
+                    ivf.centroids.push(centroid);
+                    ivf.lists.push(Vec::new());
+                }
+                IvfOp::Add {
+                    rowid,
+                    list,
+                    vector,
+                } => {
+                    ivf.rows.insert(rowid, list);
+                    ivf.lists[list].push((rowid, vector));
+                }
+            }
+        }
+    }
+}
+
+/// The centroids of an index and the vectors of each of their lists.
+#[derive(Default)]
+struct Ivf {
+    centroids: Vec<Vec<f32>>,
+    /// The vectors of each list, with the rowid of their row.
+    lists: Vec<Vec<(i64, Vec<f32>)>>,
+    /// The list of each indexed row.
+    rows: HashMap<i64, usize>,
+}
+
+impl Ivf {
+    /// Clusters `vectors` around up to `lists` centroids with k-means.
+    fn build(vectors: Vec<(i64, Vec<f32>)>, lists: usize, metric: Metric) -> Self {
+        let step = vectors
+            .len()
+            .div_ceil(lists * TRAINING_VECTORS_PER_LIST)
+            .max(1);
+        let sample: Vec<&[f32]> = vectors
+            .iter()
+            .step_by(step)
+            .map(|(_, vector)| vector.as_slice())
+            .collect();
+        let lists = lists.min(sample.len());
+        let mut centroids: Vec<Vec<f32>> = (0..lists)
+            .map(|i| sample[i * sample.len() / lists].to_vec())
+            .collect();
+        for _ in 0..KMEANS_ITERATIONS {
+            let dims = centroids.first().map_or(0, |centroid| centroid.len());
+            let mut sums = vec![vec![0.0f32; dims]; lists];
+            let mut counts = vec![0usize; lists];
+            for vector in &sample {
+                let list = nearest(&centroids, vector, metric).expect("there are centroids");
+                for (sum, value) in sums[list].iter_mut().zip(vector.iter()) {
+                    *sum += value;
+                }
+                counts[list] += 1;
+            }
+            for ((centroid, mut sum), count) in centroids.iter_mut().zip(sums).zip(counts) {
+                // A centroid nothing is nearest to stays where it is.
+                if count == 0 {
+                    continue;
+                }
+                sum.iter_mut().for_each(|value| *value /= count as f32);
+                if metric == Metric::Cosine && !normalize(&mut sum) {
+                    continue;
+                }
+                *centroid = sum;
+            }
+        }
+        let mut ivf = Ivf {
+            lists: vec![Vec::new(); centroids.len()],
+            centroids,
+            rows: HashMap::new(),
+        };
+        for (rowid, vector) in vectors {
+            let list = ivf
+                .nearest_list(&vector, metric)
+                .expect("there are centroids when there are vectors");
+            ivf.rows.insert(rowid, list);
+            ivf.lists[list].push((rowid, vector));
+        }
+        ivf
+    }
+
+    fn nearest_list(&self, vector: &[f32], metric: Metric) -> Option<usize> {
+        nearest(&self.centroids, vector, metric)
+    }
+
+    /// Removes the vector of the row `rowid`, returning whether it was indexed.
+    fn remove(&mut self, rowid: i64) -> bool {
+        let Some(list) = self.rows.remove(&rowid) else {
+            return false;
+        };
+        let entries = &mut self.lists[list];
+        if let Some(position) = entries.iter().position(|(id, _)| *id == rowid) {
+            entries.swap_remove(position);
+        }
+        true
+    }
+
+    fn search(&self, query: &[f32], k: usize, probes: usize, metric: Metric) -> Vec<i64> {
+        if k == 0 {
+            return Vec::new();
+        }
+        let mut lists: Vec<(f32, usize)> = self
+            .centroids
+            .iter()
+            .enumerate()
+            .map(|(list, centroid)| (metric.distance(query, centroid), list))
+            .collect();
+        lists.sort_by(|a, b| a.0.total_cmp(&b.0));
+        lists.truncate(probes);
+        let mut candidates: Vec<(f32, i64)> = lists
+            .iter()
+            .flat_map(|(_, list)| self.lists[*list].iter())
+            .map(|(rowid, vector)| (metric.distance(query, vector), *rowid))
+            .collect();
+        if candidates.len() > k {
+            candidates.select_nth_unstable_by(k - 1, |a, b| a.0.total_cmp(&b.0));
+            candidates.truncate(k);
+        }
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+        candidates.into_iter().map(|(_, rowid)| rowid).collect()
+    }
+}
+
+fn nearest(centroids: &[Vec<f32>], vector: &[f32], metric: Metric) -> Option<usize> {
+    centroids
+        .iter()
+        .map(|centroid| metric.distance(vector, centroid))
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(list, _)| list)
+}
+
+/// Scales `vector` to a length of 1, returning false if it has none.
+fn normalize(vector: &mut [f32]) -> bool {
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm == 0.0 || !norm.is_finite() {
+        return false;
+    }
+    vector.iter_mut().for_each(|v| *v /= norm);
+    true
+}
+
+fn encode(vector: &[f32]) -> OwnedValue {
+    vector_serialize_f32(Vector {
+        vector_type: VectorType::Float32,
+        dims: vector.len(),
+        data: vector.iter().flat_map(|v| v.to_le_bytes()).collect(),
+    })
+}
+
+fn decode(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+        .collect()
+}
+
+/// The vector type and dimensions `column` of `table` is declared with.
+fn column_vector_type(
+    conn: &Rc<Connection>,
+    table: &str,
+    column: &str,
+) -> Result<(VectorType, usize)> {
+    let mut statements = Statements::new(conn);
+    let rows = statements.query(
+        "SELECT sql FROM sqlite_schema WHERE type = 'table' AND name = ?1",
+        vec![OwnedValue::build_text(table)],
+    )?;
+    let not_a_vector = || {
+        LimboError::ParseError(format!(
+            "column {table}.{column} must be declared as F32_BLOB(n), FLOAT32(n), F64_BLOB(n) or FLOAT64(n) to be indexed as vectors"
+        ))
+    };
+    let Some([OwnedValue::Text(sql)]) = rows.first().map(|row| row.as_slice()) else {
+        return Err(not_a_vector());
+    };
+    let mut parser = Parser::new(sql.as_str().as_bytes());
+    let Some(ast::Cmd::Stmt(ast::Stmt::CreateTable { body, .. })) = parser.next()? else {
+        return Err(not_a_vector());
+    };
+    let ast::CreateTableBody::ColumnsAndConstraints { columns, .. } = *body else {
+        return Err(not_a_vector());
+    };
+    let ty = columns
+        .values()
+        .find(|definition| normalize_ident(&definition.col_name.0) == column)
+        .and_then(|definition| definition.col_type.as_ref())
+        .ok_or_else(not_a_vector)?;
+    let vector_type = match ty.name.to_uppercase().as_str() {
+        "F32_BLOB" | "FLOAT32" => VectorType::Float32,
+        "F64_BLOB" | "FLOAT64" => VectorType::Float64,
+        _ => return Err(not_a_vector()),
+    };
+    let Some(ast::TypeSize::MaxSize(size)) = &ty.size else {
+        return Err(not_a_vector());
+    };
+    let ast::Expr::Literal(ast::Literal::Numeric(dims)) = size.as_ref() else {
+        return Err(not_a_vector());
+    };
+    match dims.parse::<usize>() {
+        Ok(dims) if dims > 0 => Ok((vector_type, dims)),
+        _ => Err(not_a_vector()),
+    }
+}
+
+fn create(conn: &Rc<Connection>, sql: &str) -> Result<()> {
+    if !conn.get_auto_commit() {
+        return Err(LimboError::TxError(
+            "cannot create a vector index within a transaction".to_string(),
+        ));
+    }
+    let (index, if_not_exists) = VectorIndex::define(conn, sql)?;
+    {
+        let schema = conn.schema.read();
+        if !schema.is_unique_idx_name(&index.name) {
+            if if_not_exists && schema.vector_indexes.contains_key(&index.name) {
+                return Ok(());
+            }
+            return Err(LimboError::ParseError(format!(
+                "index {} already exists",
+                index.name
+            )));
+        }
+        for table in [
+            format!("{}_centroids", index.name),
+            format!("{}_shadow", index.name),
+        ] {
+            if schema.get_table(&table).is_some() {
+                return Err(LimboError::ParseError(format!(
+                    "Table {table} already exists"
+                )));
+            }
+        }
+    }
+    let _suspended = conn.suspend_row_filter();
+    in_transaction(conn, |statements| {
+        statements.execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {CATALOG_TABLE_NAME}(name TEXT, sql TEXT)"
+        ))?;
+        statements.execute(&format!(
+            "CREATE TABLE {}(list INTEGER PRIMARY KEY, centroid BLOB)",
+            index.centroids_table()
+        ))?;
+        statements.execute(&format!(
+            "CREATE TABLE {}(id INTEGER PRIMARY KEY, list INTEGER, vector BLOB)",
+            index.shadow_table()
+        ))?;
+        statements.query(
+            &format!("INSERT INTO {CATALOG_TABLE_NAME} VALUES (?1, ?2)"),
+            vec![
+                OwnedValue::build_text(&index.name),
+                OwnedValue::build_text(&index.sql),
+            ],
+        )?;
+        index.build(statements)
+    })?;
+    conn.schema.write().add_vector_index(Arc::new(index));
+    Ok(())
+}
+
+fn drop_index(conn: &Rc<Connection>, name: &str, if_exists: bool) -> Result<()> {
+    if !conn.get_auto_commit() {
+        return Err(LimboError::TxError(
+            "cannot drop a vector index within a transaction".to_string(),
+        ));
+    }
+    let Some(index) = conn.schema.read().get_vector_index(name) else {
+        if if_exists {
+            return Ok(());
+        }
+        return Err(LimboError::ParseError(format!("no such index: {name}")));
+    };
+    let _suspended = conn.suspend_row_filter();
+    in_transaction(conn, |statements| {
+        statements.execute(&format!("DROP TABLE {}", index.centroids_table()))?;
+        statements.execute(&format!("DROP TABLE {}", index.shadow_table()))?;
+        statements.query(
+            &format!("DELETE FROM {CATALOG_TABLE_NAME} WHERE name = ?1"),
+            vec![OwnedValue::build_text(&index.name)],
+        )?;
+        Ok(())
+    })?;
+    conn.schema.write().remove_vector_index(&index.name);
+    Ok(())
+}
+
+/// Writes the `changes` of the transaction about to commit to the shadow tables of the indexes
+/// on the tables it wrote, returning the updates of their copies in memory to apply once it
+/// committed.
+pub(crate) fn maintain(conn: &Rc<Connection>, changes: &[RowChange]) -> Result<Vec<IndexUpdate>> {
+    let indexes: Vec<Arc<VectorIndex>> = {
+        let schema = conn.schema.read();
+        if schema.vector_indexes.is_empty() {
+            return Ok(Vec::new());
+        }
+        schema
+            .vector_indexes
+            .values()
+            .filter(|index| {
+                changes
+                    .iter()
+                    .any(|change| normalize_ident(&change.table) == index.table_name)
+            })
+            .cloned()
+            .collect()
+    };
+    if indexes.is_empty() {
+        return Ok(Vec::new());
+    }
+    in_committing_transaction(conn, |statements| {
+        indexes
+            .into_iter()
+            .map(|index| {
+                let changes: Vec<&RowChange> = changes
+                    .iter()
+                    .filter(|change| normalize_ident(&change.table) == index.table_name)
+                    .collect();
+                let ops = index.apply(statements, &changes)?;
+                Ok(IndexUpdate { index, ops })
+            })
+            .collect()
+    })
+}
+
+/// Loads the vector indexes of a database that was just opened.
+pub(crate) fn load(conn: &Rc<Connection>) -> Result<()> {
+    if conn
+        .schema
+        .read()
+        .get_btree_table(CATALOG_TABLE_NAME)
+        .is_none()
+    {
+        return Ok(());
+    }
+    let mut statements = Statements::new(conn);
+    let rows = statements.query(&format!("SELECT sql FROM {CATALOG_TABLE_NAME}"), Vec::new())?;
+    for row in rows {
+        let [OwnedValue::Text(sql)] = row.as_slice() else {
+            continue;
+        };
+        let (index, _) = VectorIndex::define(conn, sql.as_str())?;
+        index.reload(&mut statements)?;
+        conn.schema.write().add_vector_index(Arc::new(index));
+    }
+    Ok(())
+}
+
+/// Fails if `stmt` writes the shadow tables of a vector index, or drops a table with vector
+/// indexes, which have to be dropped first.
+pub(crate) fn check_write(schema: &Schema, stmt: &ast::Stmt) -> Result<()> {
+    if schema.vector_indexes.is_empty() {
+        return Ok(());
+    }
+    let name = match stmt {
+        ast::Stmt::Insert(insert) => &insert.tbl_name.name,
+        ast::Stmt::Update(update) => &update.tbl_name.name,
+        ast::Stmt::Delete(delete) => &delete.tbl_name.name,
+        ast::Stmt::DropTable { tbl_name, .. } => {
+            let name = normalize_ident(&tbl_name.name.0);
+            if let Some(index) = schema.vector_indexes_on(&name).first() {
+                return Err(LimboError::ParseError(format!(
+                    "cannot drop {name}: vector index {} is on it",
+                    index.name
+                )));
+            }
+            &tbl_name.name
+        }
+        _ => return Ok(()),
+    };
+    let name = normalize_ident(&name.0);
+    if let Some(index) = schema.vector_indexes.values().find(|index| {
+        name == format!("{}_centroids", index.name) || name == format!("{}_shadow", index.name)
+    }) {
+        return Err(LimboError::ParseError(format!(
+            "cannot modify {name}: it stores vector index {}",
+            index.name
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ivf_search_finds_nearest() {
+        let vectors: Vec<(i64, Vec<f32>)> = (0..100)
+            .map(|i| (i, vec![i as f32, (i % 10) as f32]))
+            .collect();
+        let ivf = Ivf::build(vectors, 8, Metric::L2);
+        assert_eq!(ivf.centroids.len(), 8);
+        assert_eq!(ivf.rows.len(), 100);
+        // Probing every list makes the search exact.
+        assert_eq!(ivf.search(&[41.2, 1.0], 3, 8, Metric::L2), vec![41, 42, 40]);
+    }
+
+    #[test]
+    fn test_ivf_remove() {
+        let vectors = vec![(1, vec![0.0, 1.0]), (2, vec![1.0, 0.0])];
+        let mut ivf = Ivf::build(vectors, 2, Metric::L2);
+        assert!(ivf.remove(1));
+        assert!(!ivf.remove(1));
+        assert_eq!(ivf.search(&[0.0, 1.0], 2, 2, Metric::L2), vec![2]);
+    }
+}
@@ -3,6 +3,7 @@ use crate::vdbe::Register;
 use crate::LimboError;
 use crate::Result;
 
+pub mod index;
 pub mod vector_types;
 use vector_types::*;
 
@@ -77,3 +78,16 @@ pub fn vector_distance_cos(args: &[Register]) -> Result<OwnedValue> {
     let dist = do_vector_distance_cos(&x, &y)?;
     Ok(OwnedValue::Float(dist))
 }
+
+pub fn vector_distance_l2(args: &[Register]) -> Result<OwnedValue> {
+    if args.len() != 2 {
+        return Err(LimboError::ConversionError(
+            "vector_distance_l2 requires exactly two arguments".to_string(),
+        ));
+    }
+
+    let x = parse_vector(&args[0], None)?;
+    let y = parse_vector(&args[1], None)?;
+    let dist = do_vector_distance_l2(&x, &y)?;
+    Ok(OwnedValue::Float(dist))
+}
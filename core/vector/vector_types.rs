@@ -280,6 +280,39 @@ pub fn vector_f64_distance_cos(v1: &Vector, v2: &Vector) -> Result<f64> {
     Ok(1.0 - (dot / (norm1 * norm2).sqrt()))
 }
 
+pub fn do_vector_distance_l2(v1: &Vector, v2: &Vector) -> Result<f64> {
+    if v1.dims != v2.dims {
+        return Err(LimboError::ConversionError(
+            "Invalid vector dimensions".to_string(),
+        ));
+    }
+    if v1.vector_type != v2.vector_type {
+        return Err(LimboError::ConversionError(
+            "Invalid vector type".to_string(),
+        ));
+    }
+    let sum = match v1.vector_type {
+        VectorType::Float32 => v1
+            .as_f32_slice()
+            .iter()
+            .zip(v2.as_f32_slice())
+            .map(|(e1, e2)| ((e1 - e2) as f64).powi(2))
+            .sum::<f64>(),
+        VectorType::Float64 => v1
+            .as_f64_slice()
+            .iter()
+            .zip(v2.as_f64_slice())
+            .map(|(e1, e2)| (e1 - e2).powi(2))
+            .sum::<f64>(),
+    };
+    if !sum.is_finite() {
+        return Err(LimboError::ConversionError(
+            "Invalid vector value".to_string(),
+        ));
+    }
+    Ok(sum.sqrt())
+}
+
 pub fn vector_type(blob: &[u8]) -> Result<VectorType> {
     if blob.is_empty() {
         return Err(LimboError::ConversionError(
@@ -564,6 +597,34 @@ mod tests {
         }
     }
 
+    #[quickcheck]
+    fn prop_vector_distance_l2_symmetric_4d(
+        v1: ArbitraryVector<4>,
+        v2: ArbitraryVector<4>,
+    ) -> bool {
+        let (v1, v2): (Vector, Vector) = (v1.into(), v2.into());
+        if v1.vector_type != v2.vector_type {
+            return true;
+        }
+        let (Ok(d12), Ok(d21), Ok(d11)) = (
+            do_vector_distance_l2(&v1, &v2),
+            do_vector_distance_l2(&v2, &v1),
+            do_vector_distance_l2(&v1, &v1),
+        ) else {
+            return false;
+        };
+        d12 >= 0.0 && d12 == d21 && d11 == 0.0
+    }
+
+    #[test]
+    fn test_vector_distance_l2() {
+        let v1 =
+            parse_string_vector(VectorType::Float32, &OwnedValue::build_text("[1, 2]")).unwrap();
+        let v2 =
+            parse_string_vector(VectorType::Float32, &OwnedValue::build_text("[4, 6]")).unwrap();
+        assert_eq!(do_vector_distance_l2(&v1, &v2).unwrap(), 5.0);
+    }
+
     #[test]
     fn parse_string_vector_zero_length() {
         let value = OwnedValue::from_text("[]");
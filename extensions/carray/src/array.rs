@@ -0,0 +1,167 @@
+//! The blob an array is bound as. It starts with a magic number and a tag for the type of
+//! the elements. Integers and floats follow as 8 little-endian bytes each; texts and blobs
+//! as a 4-byte little-endian length and their bytes.
+
+const MAGIC: &[u8; 4] = b"CARR";
+
+const INTEGERS: u8 = 1;
+const FLOATS: u8 = 2;
+const TEXTS: u8 = 3;
+const BLOBS: u8 = 4;
+
+/// An array to bind as the argument of `carray()`, e.g. for `WHERE id IN carray(?1)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CArray {
+    blob: Vec<u8>,
+}
+
+impl CArray {
+    pub fn from_integers(values: &[i64]) -> Self {
+        let mut blob = header(INTEGERS, values.len() * 8);
+        for value in values {
+            blob.extend_from_slice(&value.to_le_bytes());
+        }
+        Self { blob }
+    }
+
+    pub fn from_floats(values: &[f64]) -> Self {
+        let mut blob = header(FLOATS, values.len() * 8);
+        for value in values {
+            blob.extend_from_slice(&value.to_le_bytes());
+        }
+        Self { blob }
+    }
+
+    pub fn from_texts<S: AsRef<str>>(values: &[S]) -> Self {
+        Self::from_byte_strings(TEXTS, values.iter().map(|v| v.as_ref().as_bytes()))
+    }
+
+    pub fn from_blobs<B: AsRef<[u8]>>(values: &[B]) -> Self {
+        Self::from_byte_strings(BLOBS, values.iter().map(|v| v.as_ref()))
+    }
+
+    fn from_byte_strings<'a>(tag: u8, values: impl Iterator<Item = &'a [u8]>) -> Self {
+        let mut blob = header(tag, 0);
+        for value in values {
+            blob.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            blob.extend_from_slice(value);
+        }
+        Self { blob }
+    }
+
+    /// The blob to bind as a statement parameter.
+    pub fn into_blob(self) -> Vec<u8> {
+        self.blob
+    }
+}
+
+fn header(tag: u8, capacity: usize) -> Vec<u8> {
+    let mut blob = Vec::with_capacity(MAGIC.len() + 1 + capacity);
+    blob.extend_from_slice(MAGIC);
+    blob.push(tag);
+    blob
+}
+
+/// The elements of a bound array.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Elements {
+    Integers(Vec<i64>),
+    Floats(Vec<f64>),
+    Texts(Vec<String>),
+    Blobs(Vec<Vec<u8>>),
+}
+
+impl Elements {
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Elements::Integers(values) => values.len(),
+            Elements::Floats(values) => values.len(),
+            Elements::Texts(values) => values.len(),
+            Elements::Blobs(values) => values.len(),
+        }
+    }
+
+    /// Decodes the blob of a [CArray], `None` if it isn't one.
+    pub(crate) fn decode(blob: &[u8]) -> Option<Self> {
+        let data = blob.strip_prefix(MAGIC)?;
+        let (&tag, data) = data.split_first()?;
+        match tag {
+            INTEGERS | FLOATS => {
+                if data.len() % 8 != 0 {
+                    return None;
+                }
+                let words = data.chunks_exact(8).map(|c| c.try_into().unwrap());
+                Some(if tag == INTEGERS {
+                    Elements::Integers(words.map(i64::from_le_bytes).collect())
+                } else {
+                    Elements::Floats(words.map(f64::from_le_bytes).collect())
+                })
+            }
+            TEXTS => {
+                let values = decode_byte_strings(data)?
+                    .into_iter()
+                    .map(String::from_utf8)
+                    .collect::<Result<_, _>>()
+                    .ok()?;
+                Some(Elements::Texts(values))
+            }
+            BLOBS => Some(Elements::Blobs(decode_byte_strings(data)?)),
+            _ => None,
+        }
+    }
+}
+
+fn decode_byte_strings(mut data: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut values = Vec::new();
+    while !data.is_empty() {
+        let (len, rest) = data.split_first_chunk::<4>()?;
+        let len = u32::from_le_bytes(*len) as usize;
+        if rest.len() < len {
+            return None;
+        }
+        let (value, rest) = rest.split_at(len);
+        values.push(value.to_vec());
+        data = rest;
+    }
+    Some(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let decode = |array: CArray| Elements::decode(&array.into_blob()).unwrap();
+        assert_eq!(
+            decode(CArray::from_integers(&[1, -2, i64::MAX])),
+            Elements::Integers(vec![1, -2, i64::MAX])
+        );
+        assert_eq!(
+            decode(CArray::from_floats(&[0.5, -1e300])),
+            Elements::Floats(vec![0.5, -1e300])
+        );
+        assert_eq!(
+            decode(CArray::from_texts(&["a", "", "ಠ_ಠ"])),
+            Elements::Texts(vec!["a".into(), "".into(), "ಠ_ಠ".into()])
+        );
+        assert_eq!(
+            decode(CArray::from_blobs(&[vec![0u8, 255], vec![]])),
+            Elements::Blobs(vec![vec![0, 255], vec![]])
+        );
+        assert_eq!(
+            decode(CArray::from_integers(&[])),
+            Elements::Integers(vec![])
+        );
+    }
+
+    #[test]
+    fn test_decode_rejects_other_blobs() {
+        assert_eq!(Elements::decode(b""), None);
+        assert_eq!(Elements::decode(b"CARR"), None);
+        assert_eq!(Elements::decode(b"CARR\x01\x00"), None);
+        assert_eq!(Elements::decode(b"CARR\x03\x05\x00\x00\x00ab"), None);
+        assert_eq!(Elements::decode(b"CARR\x09"), None);
+        assert_eq!(Elements::decode(&[0xff; 13]), None);
+    }
+}
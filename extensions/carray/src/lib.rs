@@ -0,0 +1,118 @@
+//! A `carray()` table-valued function over an array bound as a statement parameter, like
+//! SQLite's [carray](https://sqlite.org/carray.html) extension. It lets batch lookups bind
+//! one parameter, as in `WHERE id IN carray(?1)`, instead of building a long `IN` list or
+//! filling a temporary table.
+//!
+//! Arrays are bound as blobs made by [CArray], since parameters can't hold pointers to
+//! application memory as they do in SQLite.
+mod array;
+
+pub use array::CArray;
+use array::Elements;
+use limbo_ext::{
+    register_extension, ResultCode, VTabCursor, VTabKind, VTabModule, VTabModuleDerive, Value,
+    ValueType,
+};
+
+register_extension! {
+    vtabs: { CArrayVTab }
+}
+
+#[derive(Debug, VTabModuleDerive, Default)]
+struct CArrayVTab;
+
+impl VTabModule for CArrayVTab {
+    type VCursor = CArrayCursor;
+    type Error = ResultCode;
+    const NAME: &'static str = "carray";
+    const VTAB_KIND: VTabKind = VTabKind::TableValuedFunction;
+
+    fn create_schema(_args: &[Value]) -> String {
+        "CREATE TABLE carray(value, array HIDDEN)".into()
+    }
+
+    fn open(&self) -> Result<Self::VCursor, Self::Error> {
+        Ok(CArrayCursor {
+            elements: Elements::Integers(Vec::new()),
+            index: 0,
+        })
+    }
+
+    fn filter(
+        cursor: &mut Self::VCursor,
+        args: &[Value],
+        _idx_info: Option<(&str, i32)>,
+    ) -> ResultCode {
+        let [array] = args else {
+            return ResultCode::InvalidArgs;
+        };
+        cursor.index = 0;
+        cursor.elements = match array.value_type() {
+            // A NULL array is empty, as in SQLite.
+            ValueType::Null => return ResultCode::EOF,
+            ValueType::Blob => match array.to_blob().as_deref().and_then(Elements::decode) {
+                Some(elements) => elements,
+                None => return ResultCode::InvalidArgs,
+            },
+            _ => return ResultCode::InvalidArgs,
+        };
+        if cursor.eof() {
+            return ResultCode::EOF;
+        }
+        ResultCode::OK
+    }
+
+    fn column(cursor: &Self::VCursor, idx: u32) -> Result<Value, Self::Error> {
+        cursor.column(idx)
+    }
+
+    fn next(cursor: &mut Self::VCursor) -> ResultCode {
+        cursor.next()
+    }
+
+    fn eof(cursor: &Self::VCursor) -> bool {
+        cursor.eof()
+    }
+}
+
+#[derive(Debug)]
+struct CArrayCursor {
+    elements: Elements,
+    index: usize,
+}
+
+impl VTabCursor for CArrayCursor {
+    type Error = ResultCode;
+
+    fn next(&mut self) -> ResultCode {
+        if self.eof() {
+            return ResultCode::EOF;
+        }
+        self.index += 1;
+        if self.eof() {
+            return ResultCode::EOF;
+        }
+        ResultCode::OK
+    }
+
+    fn eof(&self) -> bool {
+        self.index >= self.elements.len()
+    }
+
+    fn column(&self, idx: u32) -> Result<Value, Self::Error> {
+        if idx != 0 || self.eof() {
+            return Ok(Value::null());
+        }
+        let i = self.index;
+        Ok(match &self.elements {
+            Elements::Integers(values) => Value::from_integer(values[i]),
+            Elements::Floats(values) => Value::from_float(values[i]),
+            Elements::Texts(values) => Value::from_text(values[i].clone()),
+            Elements::Blobs(values) => Value::from_blob(values[i].clone()),
+        })
+    }
+
+    fn rowid(&self) -> i64 {
+        self.index as i64 + 1
+    }
+}
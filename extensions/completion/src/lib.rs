@@ -91,7 +91,11 @@ impl VTabModule for CompletionVTab {
         cursor.eof()
     }
 
-    fn filter(cursor: &mut Self::VCursor, args: &[Value]) -> ResultCode {
+    fn filter(
+        cursor: &mut Self::VCursor,
+        args: &[Value],
+        _idx_info: Option<(&str, i32)>,
+    ) -> ResultCode {
         if args.len() == 0 || args.len() > 2 {
             return ResultCode::InvalidArgs;
         }
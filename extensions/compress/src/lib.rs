@@ -0,0 +1,232 @@
+//! Functions to compress and decompress values, so compressed payloads can be stored in
+//! blob columns without help from the application.
+//!
+//! Each algorithm is behind a cargo feature of the same name: `zstd` and `gzip` are on by
+//! default, `brotli` is opt-in. Compressed values are plain zstd frames, gzip members and
+//! brotli streams, so other tools can read them. `uncompress` recognizes zstd and gzip by
+//! their magic numbers; brotli streams have none and must be named.
+use limbo_ext::{register_extension, scalar, ResultCode, Value, ValueType};
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+use std::io::{Read, Write};
+
+register_extension! {
+    scalars: { compress, uncompress },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Algorithm {
+    #[cfg(feature = "zstd")]
+    Zstd,
+    #[cfg(feature = "gzip")]
+    Gzip,
+    #[cfg(feature = "brotli")]
+    Brotli,
+}
+
+impl Algorithm {
+    /// The built in algorithms, the first one being used when none is named.
+    const ALL: &'static [Algorithm] = &[
+        #[cfg(feature = "zstd")]
+        Algorithm::Zstd,
+        #[cfg(feature = "gzip")]
+        Algorithm::Gzip,
+        #[cfg(feature = "brotli")]
+        Algorithm::Brotli,
+    ];
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|algorithm| algorithm.name().eq_ignore_ascii_case(name))
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd => "zstd",
+            #[cfg(feature = "gzip")]
+            Algorithm::Gzip => "gzip",
+            #[cfg(feature = "brotli")]
+            Algorithm::Brotli => "brotli",
+        }
+    }
+
+    #[allow(unused_variables)]
+    fn detect(data: &[u8]) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|algorithm| match *algorithm {
+                #[cfg(feature = "zstd")]
+                Algorithm::Zstd => data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]),
+                #[cfg(feature = "gzip")]
+                Algorithm::Gzip => data.starts_with(&[0x1f, 0x8b]),
+                #[cfg(feature = "brotli")]
+                Algorithm::Brotli => false,
+            })
+    }
+
+    fn levels(self) -> std::ops::RangeInclusive<i64> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd => 1..=22,
+            #[cfg(feature = "gzip")]
+            Algorithm::Gzip => 0..=9,
+            #[cfg(feature = "brotli")]
+            Algorithm::Brotli => 0..=11,
+        }
+    }
+
+    fn default_level(self) -> i64 {
+        match self {
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd => 3,
+            #[cfg(feature = "gzip")]
+            Algorithm::Gzip => 6,
+            // Brotli's own default of 11 is far slower than the others' defaults.
+            #[cfg(feature = "brotli")]
+            Algorithm::Brotli => 6,
+        }
+    }
+
+    #[allow(unused_variables)]
+    fn compress(self, data: &[u8], level: i64) -> Option<Vec<u8>> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd => zstd::encode_all(data, level as i32).ok(),
+            #[cfg(feature = "gzip")]
+            Algorithm::Gzip => {
+                use flate2::{write::GzEncoder, Compression};
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level as u32));
+                encoder.write_all(data).ok()?;
+                encoder.finish().ok()
+            }
+            #[cfg(feature = "brotli")]
+            Algorithm::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, level as u32, 22);
+                encoder.write_all(data).ok()?;
+                encoder.flush().ok()?;
+                Some(encoder.into_inner())
+            }
+        }
+    }
+
+    #[allow(unused_variables)]
+    fn decompress(self, data: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd => zstd::decode_all(data).ok(),
+            #[cfg(feature = "gzip")]
+            Algorithm::Gzip => {
+                let mut decompressed = Vec::new();
+                flate2::read::MultiGzDecoder::new(data)
+                    .read_to_end(&mut decompressed)
+                    .ok()?;
+                Some(decompressed)
+            }
+            #[cfg(feature = "brotli")]
+            Algorithm::Brotli => {
+                let mut decompressed = Vec::new();
+                brotli::Decompressor::new(data, 4096)
+                    .read_to_end(&mut decompressed)
+                    .ok()?;
+                Some(decompressed)
+            }
+        }
+    }
+}
+
+/// Compresses a text or blob value into a blob: `compress(X[, algorithm[, level]])`.
+#[scalar(name = "compress")]
+fn compress(args: &[Value]) -> Value {
+    if args.is_empty() || args.len() > 3 {
+        return Value::error(ResultCode::InvalidArgs);
+    }
+    let data = match args[0].value_type() {
+        ValueType::Blob => args[0].to_blob().unwrap_or_default(),
+        ValueType::Text => args[0].to_text().unwrap_or_default().as_bytes().to_vec(),
+        ValueType::Null => return Value::null(),
+        _ => return Value::error(ResultCode::InvalidArgs),
+    };
+    let algorithm = match args.get(1) {
+        Some(name) => name.to_text().and_then(Algorithm::from_name),
+        None => Algorithm::ALL.first().copied(),
+    };
+    let Some(algorithm) = algorithm else {
+        return Value::error(ResultCode::InvalidArgs);
+    };
+    let level = match args.get(2) {
+        Some(level) if level.value_type() == ValueType::Integer => level.to_integer().unwrap(),
+        Some(_) => return Value::error(ResultCode::InvalidArgs),
+        None => algorithm.default_level(),
+    };
+    if !algorithm.levels().contains(&level) {
+        return Value::error(ResultCode::InvalidArgs);
+    }
+    match algorithm.compress(&data, level) {
+        Some(compressed) => Value::from_blob(compressed),
+        None => Value::error(ResultCode::Error),
+    }
+}
+
+/// Decompresses a blob made by `compress`: `uncompress(X[, algorithm])`. The result is
+/// NULL if the blob isn't valid data for the algorithm.
+#[scalar(name = "uncompress")]
+fn uncompress(args: &[Value]) -> Value {
+    if args.is_empty() || args.len() > 2 {
+        return Value::error(ResultCode::InvalidArgs);
+    }
+    let data = match args[0].value_type() {
+        ValueType::Blob => args[0].to_blob().unwrap_or_default(),
+        ValueType::Null => return Value::null(),
+        _ => return Value::error(ResultCode::InvalidArgs),
+    };
+    let algorithm = match args.get(1) {
+        Some(name) => match name.to_text().and_then(Algorithm::from_name) {
+            Some(algorithm) => algorithm,
+            None => return Value::error(ResultCode::InvalidArgs),
+        },
+        None => match Algorithm::detect(&data) {
+            Some(algorithm) => algorithm,
+            None => return Value::null(),
+        },
+    };
+    match algorithm.decompress(&data) {
+        Some(decompressed) => Value::from_blob(decompressed),
+        None => Value::null(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data = b"limbo ".repeat(100);
+        for &algorithm in Algorithm::ALL {
+            let compressed = algorithm
+                .compress(&data, algorithm.default_level())
+                .unwrap();
+            assert!(compressed.len() < data.len(), "{}", algorithm.name());
+            assert_eq!(algorithm.decompress(&compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_detect() {
+        for &algorithm in Algorithm::ALL {
+            let compressed = algorithm
+                .compress(b"limbo", *algorithm.levels().start())
+                .unwrap();
+            #[cfg(feature = "brotli")]
+            if algorithm == Algorithm::Brotli {
+                assert_eq!(Algorithm::detect(&compressed), None);
+                continue;
+            }
+            assert_eq!(Algorithm::detect(&compressed), Some(algorithm));
+        }
+        assert_eq!(Algorithm::detect(b"limbo"), None);
+    }
+}
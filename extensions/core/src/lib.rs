@@ -15,14 +15,90 @@ pub use types::{ResultCode, Value, ValueType};
 #[cfg(feature = "vfs")]
 pub use vfs_modules::{RegisterVfsFn, VfsExtension, VfsFile, VfsFileImpl, VfsImpl, VfsInterface};
 use vtabs::RegisterModuleFn;
-pub use vtabs::{VTabCursor, VTabKind, VTabModule, VTabModuleImpl};
+pub use vtabs::{
+    ConstraintInfo, ConstraintOp, ConstraintUsage, ExtIndexInfo, IndexInfo, VTabCursor, VTabKind,
+    VTabModule, VTabModuleImpl,
+};
 
 pub type ExtResult<T> = std::result::Result<T, ResultCode>;
 
 pub type ExtensionEntryPoint = unsafe extern "C" fn(api: *const ExtensionApi) -> ResultCode;
+pub type ExtensionInfoFn = unsafe extern "C" fn() -> ExtensionInfo;
+
+/// The version of [ExtensionApi] and of the types passed through it. It must be bumped
+/// whenever they change in a way extensions built against an older version can't handle.
+pub const EXTENSION_API_VERSION: u32 = 2;
+
+/// Kinds of things an extension can register. Limbo advertises the ones it supports in
+/// [ExtensionApi], extensions declare the ones they need in [ExtensionInfo].
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities(pub u64);
+
+impl Capabilities {
+    pub const NONE: Self = Self(0);
+    pub const SCALARS: Self = Self(1);
+    pub const AGGREGATES: Self = Self(1 << 1);
+    pub const VTABS: Self = Self(1 << 2);
+    pub const VFS: Self = Self(1 << 3);
+
+    const NAMES: [(Self, &'static str); 4] = [
+        (Self::SCALARS, "scalar functions"),
+        (Self::AGGREGATES, "aggregate functions"),
+        (Self::VTABS, "virtual tables"),
+        (Self::VFS, "VFS modules"),
+    ];
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The capabilities in `self` that are not in `other`.
+    pub const fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut names = Vec::new();
+        let mut rest = *self;
+        for (capability, name) in Self::NAMES {
+            if self.contains(capability) {
+                names.push(name.to_string());
+                rest = rest.difference(capability);
+            }
+        }
+        if !rest.is_empty() {
+            names.push(format!("unknown capabilities {:#x}", rest.0));
+        }
+        write!(f, "{}", names.join(", "))
+    }
+}
+
+/// What a dynamically loaded extension was built for, returned by the `extension_info`
+/// function the `register_extension!` macro exports next to `register_extension`. Limbo
+/// checks it before passing an [ExtensionApi] whose layout the extension may not expect.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ExtensionInfo {
+    pub api_version: u32,
+    pub capabilities: Capabilities,
+}
 
 #[repr(C)]
 pub struct ExtensionApi {
+    /// Must stay the first field, so that extensions of any version can read it.
+    pub version: u32,
+    pub capabilities: Capabilities,
     pub ctx: *mut c_void,
     pub register_scalar_function: RegisterScalarFn,
     pub register_aggregate_function: RegisterAggFn,
@@ -42,6 +42,9 @@ pub trait VfsFile: Send + Sync {
     fn write(&mut self, buf: &[u8], count: usize, offset: i64) -> ExtResult<i32>;
     fn sync(&self) -> ExtResult<()>;
     fn size(&self) -> i64;
+    fn truncate(&mut self, _len: i64) -> ExtResult<()> {
+        Err(ResultCode::Unimplemented)
+    }
 }
 
 #[repr(C)]
@@ -56,6 +59,7 @@ pub struct VfsImpl {
     pub lock: VfsLock,
     pub unlock: VfsUnlock,
     pub size: VfsSize,
+    pub truncate: VfsTruncate,
     pub run_once: VfsRunOnce,
     pub current_time: VfsGetCurrentTime,
     pub gen_random_number: VfsGenerateRandomNumber,
@@ -87,6 +91,8 @@ pub type VfsUnlock = unsafe extern "C" fn(file: *const c_void) -> ResultCode;
 
 pub type VfsSize = unsafe extern "C" fn(file: *const c_void) -> i64;
 
+pub type VfsTruncate = unsafe extern "C" fn(file: *const c_void, len: i64) -> ResultCode;
+
 pub type VfsRunOnce = unsafe extern "C" fn(file: *const c_void) -> ResultCode;
 
 pub type VfsGetCurrentTime = unsafe extern "C" fn() -> *const c_char;
@@ -21,6 +21,7 @@ pub struct VTabModuleImpl {
     pub eof: VtabFnEof,
     pub update: VtabFnUpdate,
     pub rowid: VtabRowIDFn,
+    pub best_index: VtabFnBestIndex,
 }
 
 #[cfg(feature = "core_only")]
@@ -36,14 +37,24 @@ impl VTabModuleImpl {
         let schema = unsafe { std::ffi::CString::from_raw(schema) };
         Ok(schema.to_string_lossy().to_string())
     }
+
+    pub fn best_index(&self, constraints: &[ConstraintInfo]) -> IndexInfo {
+        let info = unsafe { (self.best_index)(constraints.as_ptr(), constraints.len() as i32) };
+        unsafe { info.into_index_info() }
+    }
 }
 
 pub type VtabFnCreateSchema = unsafe extern "C" fn(args: *const Value, argc: i32) -> *mut c_char;
 
 pub type VtabFnOpen = unsafe extern "C" fn(*const c_void) -> *const c_void;
 
-pub type VtabFnFilter =
-    unsafe extern "C" fn(cursor: *const c_void, argc: i32, argv: *const Value) -> ResultCode;
+pub type VtabFnFilter = unsafe extern "C" fn(
+    cursor: *const c_void,
+    argc: i32,
+    argv: *const Value,
+    idx_str: *const c_char,
+    idx_num: i32,
+) -> ResultCode;
 
 pub type VtabFnColumn = unsafe extern "C" fn(cursor: *const c_void, idx: u32) -> Value;
 
@@ -53,6 +64,9 @@ pub type VtabFnEof = unsafe extern "C" fn(cursor: *const c_void) -> bool;
 
 pub type VtabRowIDFn = unsafe extern "C" fn(cursor: *const c_void) -> i64;
 
+pub type VtabFnBestIndex =
+    unsafe extern "C" fn(constraints: *const ConstraintInfo, n_constraints: i32) -> ExtIndexInfo;
+
 pub type VtabFnUpdate = unsafe extern "C" fn(
     vtab: *const c_void,
     argc: i32,
@@ -75,7 +89,15 @@ pub trait VTabModule: 'static {
 
     fn create_schema(args: &[Value]) -> String;
     fn open(&self) -> Result<Self::VCursor, Self::Error>;
-    fn filter(cursor: &mut Self::VCursor, args: &[Value]) -> ResultCode;
+    /// Starts a scan. `args` are the arguments of the table-valued function, or those the
+    /// virtual table was created with, followed by the values of the constraints
+    /// [VTabModule::best_index] chose to use. `idx_info` is the `idx_str` and `idx_num` it
+    /// returned, if it returned an `idx_str`.
+    fn filter(
+        cursor: &mut Self::VCursor,
+        args: &[Value],
+        idx_info: Option<(&str, i32)>,
+    ) -> ResultCode;
     fn column(cursor: &Self::VCursor, idx: u32) -> Result<Value, Self::Error>;
     fn next(cursor: &mut Self::VCursor) -> ResultCode;
     fn eof(cursor: &Self::VCursor) -> bool;
@@ -88,6 +110,108 @@ pub trait VTabModule: 'static {
     fn delete(&mut self, _rowid: i64) -> Result<(), Self::Error> {
         Ok(())
     }
+    /// Chooses the constraints of the WHERE clause the scan passes to [VTabModule::filter], to
+    /// skip rows that can't satisfy them. Limbo still checks every constraint on the rows the
+    /// scan returns. By default, none are used.
+    fn best_index(_constraints: &[ConstraintInfo]) -> IndexInfo {
+        IndexInfo::default()
+    }
+}
+
+/// The operator of a [ConstraintInfo].
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConstraintOp {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A constraint `column op value` of the WHERE clause on a column of a virtual table, whose
+/// value is known before the scan starts.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConstraintInfo {
+    /// The position of the column in the table's schema.
+    pub column_index: u32,
+    pub op: ConstraintOp,
+}
+
+/// Whether a scan uses a constraint, see [VTabModule::best_index].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConstraintUsage {
+    /// If set, the value of the constraint is passed to [VTabModule::filter] at this
+    /// position, counted from 1, after the arguments of the table.
+    pub argv_index: Option<u32>,
+}
+
+/// How a scan uses the constraints passed to [VTabModule::best_index].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IndexInfo {
+    /// The usage of each constraint, in the order they were passed. Missing ones are unused.
+    pub constraint_usages: Vec<ConstraintUsage>,
+    pub idx_num: i32,
+    pub idx_str: Option<String>,
+}
+
+/// [IndexInfo] as it is passed from an extension to Limbo.
+#[repr(C)]
+pub struct ExtIndexInfo {
+    /// `argv_index` of each constraint, 0 for unused ones.
+    pub argv_indexes: *mut u32,
+    pub n_constraints: i32,
+    pub idx_num: i32,
+    /// Null if there is no `idx_str`.
+    pub idx_str: *mut c_char,
+}
+
+impl IndexInfo {
+    pub fn to_ffi(self) -> ExtIndexInfo {
+        let argv_indexes: Box<[u32]> = self
+            .constraint_usages
+            .iter()
+            .map(|usage| usage.argv_index.unwrap_or(0))
+            .collect();
+        let n_constraints = argv_indexes.len() as i32;
+        ExtIndexInfo {
+            argv_indexes: Box::into_raw(argv_indexes) as *mut u32,
+            n_constraints,
+            idx_num: self.idx_num,
+            idx_str: self
+                .idx_str
+                .and_then(|idx_str| std::ffi::CString::new(idx_str).ok())
+                .map_or(std::ptr::null_mut(), |idx_str| idx_str.into_raw()),
+        }
+    }
+}
+
+impl ExtIndexInfo {
+    /// # Safety
+    /// `self` must come from [IndexInfo::to_ffi], and is consumed.
+    #[cfg(feature = "core_only")]
+    unsafe fn into_index_info(self) -> IndexInfo {
+        let argv_indexes = Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+            self.argv_indexes,
+            self.n_constraints as usize,
+        ));
+        let idx_str = (!self.idx_str.is_null()).then(|| {
+            std::ffi::CString::from_raw(self.idx_str)
+                .to_string_lossy()
+                .to_string()
+        });
+        IndexInfo {
+            constraint_usages: argv_indexes
+                .iter()
+                .map(|argv_index| ConstraintUsage {
+                    argv_index: (*argv_index > 0).then_some(*argv_index),
+                })
+                .collect(),
+            idx_num: self.idx_num,
+            idx_str,
+        }
+    }
 }
 
 pub trait VTabCursor: Sized {
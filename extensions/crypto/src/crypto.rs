@@ -2,7 +2,11 @@ use crate::Error;
 use blake3::Hasher;
 use data_encoding::{BASE32, BASE64, HEXLOWER};
 use limbo_ext::{Value, ValueType};
-use ring::digest::{self, digest};
+use ring::{
+    digest::{self, digest},
+    hmac,
+};
+use sha3::{Digest, Sha3_224, Sha3_256, Sha3_384, Sha3_512};
 use std::{borrow::Cow, error::Error as StdError};
 
 pub fn sha256(data: &Value) -> Result<Vec<u8>, Error> {
@@ -67,6 +71,42 @@ pub fn md5(data: &Value) -> Result<Vec<u8>, Error> {
     }
 }
 
+pub fn sha3(data: &Value, size: i64) -> Result<Vec<u8>, Error> {
+    match data.value_type() {
+        ValueType::Error | ValueType::Null => Err(Error::InvalidType),
+        _ => {
+            let data = data.as_bytes();
+            let hash = match size {
+                224 => Sha3_224::digest(&data).to_vec(),
+                256 => Sha3_256::digest(&data).to_vec(),
+                384 => Sha3_384::digest(&data).to_vec(),
+                512 => Sha3_512::digest(&data).to_vec(),
+                _ => return Err(Error::UnknownOperation),
+            };
+            Ok(hash)
+        }
+    }
+}
+
+pub fn hmac(data: &Value, key: &Value, algorithm: &str) -> Result<Vec<u8>, Error> {
+    match (data.value_type(), key.value_type()) {
+        (ValueType::Error, _) | (ValueType::Null, _) => Err(Error::InvalidType),
+        (_, ValueType::Error) | (_, ValueType::Null) => Err(Error::InvalidType),
+        _ => {
+            let algorithm = match algorithm.to_lowercase().as_str() {
+                "sha1" => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+                "sha256" => hmac::HMAC_SHA256,
+                "sha384" => hmac::HMAC_SHA384,
+                "sha512" => hmac::HMAC_SHA512,
+                _ => return Err(Error::UnknownOperation),
+            };
+            let key = hmac::Key::new(algorithm, &key.as_bytes());
+            let tag = hmac::sign(&key, &data.as_bytes());
+            Ok(tag.as_ref().to_vec())
+        }
+    }
+}
+
 pub fn encode(data: &Value, format: &Value) -> Result<Value, Error> {
     match (data.value_type(), format.value_type()) {
         (ValueType::Error, _) | (ValueType::Null, _) => Err(Error::InvalidType),
@@ -1,5 +1,5 @@
-use crypto::{blake3, decode, encode, md5, sha1, sha256, sha384, sha512};
-use limbo_ext::{register_extension, scalar, ResultCode, Value};
+use crypto::{blake3, decode, encode, hmac, md5, sha1, sha256, sha3, sha384, sha512};
+use limbo_ext::{register_extension, scalar, ResultCode, Value, ValueType};
 
 mod crypto;
 
@@ -89,6 +89,42 @@ fn crypto_md5(args: &[Value]) -> Value {
     Value::from_blob(hash)
 }
 
+/// SHA3 hash of the first argument, with a size of 224, 256 (the default), 384 or 512 bits.
+#[scalar(name = "crypto_sha3", alias = "crypto_sha3")]
+fn crypto_sha3(args: &[Value]) -> Value {
+    let size = match args.len() {
+        1 => 256,
+        2 if args[1].value_type() == ValueType::Integer => args[1].to_integer().unwrap(),
+        _ => return Value::error(ResultCode::Error),
+    };
+
+    let Ok(hash) = sha3(&args[0], size) else {
+        return Value::error(ResultCode::Error);
+    };
+
+    Value::from_blob(hash)
+}
+
+/// HMAC of the first argument keyed by the second one, using SHA-256 or the hash named by
+/// the third argument: sha1, sha256, sha384 or sha512.
+#[scalar(name = "crypto_hmac", alias = "crypto_hmac")]
+fn crypto_hmac(args: &[Value]) -> Value {
+    let algorithm = match args.len() {
+        2 => "sha256",
+        3 => match args[2].to_text() {
+            Some(algorithm) => algorithm,
+            None => return Value::error(ResultCode::Error),
+        },
+        _ => return Value::error(ResultCode::Error),
+    };
+
+    let Ok(tag) = hmac(&args[0], &args[1], algorithm) else {
+        return Value::error(ResultCode::Error);
+    };
+
+    Value::from_blob(tag)
+}
+
 #[scalar(name = "crypto_encode", alias = "crypto_encode")]
 fn crypto_encode(args: &[Value]) -> Value {
     if args.len() != 2 {
@@ -116,5 +152,5 @@ fn crypto_decode(args: &[Value]) -> Value {
 }
 
 register_extension! {
-    scalars: { crypto_sha256, crypto_sha512, crypto_sha384, crypto_blake3, crypto_sha1, crypto_md5, crypto_encode, crypto_decode },
+    scalars: { crypto_sha256, crypto_sha512, crypto_sha384, crypto_blake3, crypto_sha1, crypto_md5, crypto_sha3, crypto_hmac, crypto_encode, crypto_decode },
 }
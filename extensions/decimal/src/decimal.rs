@@ -0,0 +1,452 @@
+//! Arbitrary precision decimal numbers, kept as a sign, a list of decimal digits and the
+//! number of those digits that are after the decimal point.
+
+use std::cmp::Ordering;
+
+/// The most digits a number may have, so that an exponent like `1e999999999` in the input
+/// can't make a number take gigabytes.
+const MAX_DIGITS: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Decimal {
+    negative: bool,
+    /// Most significant first, each between 0 and 9. There are at least `frac` of them.
+    digits: Vec<u8>,
+    /// How many of the digits are after the decimal point.
+    frac: usize,
+}
+
+impl Decimal {
+    pub(crate) fn zero() -> Self {
+        Self {
+            negative: false,
+            digits: vec![0],
+            frac: 0,
+        }
+    }
+
+    /// Parses text like `-12.50` or `1.5e-3`, surrounded by optional whitespace. Digits after
+    /// the decimal point are kept even if they are zeros.
+    pub(crate) fn parse(text: &str) -> Option<Self> {
+        let text = text.trim_ascii();
+        let (negative, text) = match text.as_bytes().first()? {
+            b'-' => (true, &text[1..]),
+            b'+' => (false, &text[1..]),
+            _ => (false, text),
+        };
+        let (mantissa, exponent) = match text.find(['e', 'E']) {
+            Some(pos) => (&text[..pos], Some(&text[pos + 1..])),
+            None => (text, None),
+        };
+        let (int_part, frac_part) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let is_digits = |part: &str| part.bytes().all(|c| c.is_ascii_digit());
+        if int_part.len() + frac_part.len() == 0 || !is_digits(int_part) || !is_digits(frac_part) {
+            return None;
+        }
+        let exponent: i64 = match exponent {
+            Some(exponent) => {
+                let digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+                if digits.is_empty() || !is_digits(digits) {
+                    return None;
+                }
+                exponent.parse().ok()?
+            }
+            None => 0,
+        };
+        let mut digits: Vec<u8> = int_part
+            .bytes()
+            .chain(frac_part.bytes())
+            .map(|c| c - b'0')
+            .collect();
+        // The exponent moves the decimal point, adding zeros on either side if needed.
+        let frac = frac_part.len() as i64 - exponent;
+        if frac.unsigned_abs() > MAX_DIGITS as u64 {
+            return None;
+        }
+        let frac = if frac < 0 {
+            digits.resize(digits.len() + frac.unsigned_abs() as usize, 0);
+            0
+        } else {
+            frac as usize
+        };
+        if frac > digits.len() {
+            digits.splice(0..0, std::iter::repeat(0).take(frac - digits.len()));
+        }
+        Some(Self {
+            negative,
+            digits,
+            frac,
+        })
+    }
+
+    pub(crate) fn from_integer(value: i64) -> Self {
+        Self {
+            negative: value < 0,
+            digits: value
+                .unsigned_abs()
+                .to_string()
+                .bytes()
+                .map(|c| c - b'0')
+                .collect(),
+            frac: 0,
+        }
+    }
+
+    /// The exact value of a float, without trailing zeros. `None` for infinities and NaN.
+    pub(crate) fn from_float(value: f64) -> Option<Self> {
+        if !value.is_finite() {
+            return None;
+        }
+        let bits = value.to_bits();
+        let biased_exponent = ((bits >> 52) & 0x7ff) as i64;
+        let fraction = bits & ((1 << 52) - 1);
+        // value = mantissa * 2^exponent
+        let (mantissa, exponent) = if biased_exponent == 0 {
+            (fraction, -1074)
+        } else {
+            (fraction | (1 << 52), biased_exponent - 1075)
+        };
+        let mut decimal = Self::from_integer(mantissa as i64);
+        decimal.negative = value.is_sign_negative();
+        if exponent >= 0 {
+            decimal.mul_pow(2, exponent as u32);
+        } else {
+            // 2^-n = 5^n / 10^n
+            decimal.mul_pow(5, exponent.unsigned_abs() as u32);
+            decimal.frac = exponent.unsigned_abs() as usize;
+            decimal.pad_int();
+            decimal.trim_frac(0);
+        }
+        Some(decimal)
+    }
+
+    /// 2 to the power of `exponent`, exactly.
+    pub(crate) fn pow2(exponent: i32) -> Self {
+        let mut decimal = Self::from_integer(1);
+        if exponent >= 0 {
+            decimal.mul_pow(2, exponent.unsigned_abs());
+        } else {
+            decimal.mul_pow(5, exponent.unsigned_abs());
+            decimal.frac = exponent.unsigned_abs() as usize;
+            decimal.pad_int();
+        }
+        decimal
+    }
+
+    pub(crate) fn is_zero(&self) -> bool {
+        self.digits.iter().all(|&d| d == 0)
+    }
+
+    pub(crate) fn neg(mut self) -> Self {
+        self.negative = !self.negative;
+        self
+    }
+
+    /// The sum, with as many digits after the decimal point as the operand that has more.
+    pub(crate) fn add(&self, other: &Self) -> Self {
+        let frac = self.frac.max(other.frac);
+        let int = self.int_len().max(other.int_len()) + 1;
+        let a = self.aligned(int, frac);
+        let b = other.aligned(int, frac);
+        let (negative, digits) = if self.negative == other.negative {
+            (self.negative, add_digits(&a, &b))
+        } else if a >= b {
+            (self.negative, sub_digits(&a, &b))
+        } else {
+            (other.negative, sub_digits(&b, &a))
+        };
+        let mut sum = Self {
+            negative,
+            digits,
+            frac,
+        };
+        sum.trim_int();
+        sum
+    }
+
+    pub(crate) fn sub(&self, other: &Self) -> Self {
+        self.add(&other.clone().neg())
+    }
+
+    /// The product, dropping trailing zeros after the decimal point beyond the digits of
+    /// the operand that has fewer of them.
+    pub(crate) fn mul(&self, other: &Self) -> Self {
+        let mut product = vec![0u32; self.digits.len() + other.digits.len()];
+        for (i, &a) in self.digits.iter().enumerate().rev() {
+            let mut carry = 0;
+            for (j, &b) in other.digits.iter().enumerate().rev() {
+                let cell = &mut product[i + j + 1];
+                let sum = *cell + a as u32 * b as u32 + carry;
+                *cell = sum % 10;
+                carry = sum / 10;
+            }
+            product[i] += carry;
+        }
+        let mut decimal = Self {
+            negative: self.negative != other.negative,
+            digits: product.into_iter().map(|d| d as u8).collect(),
+            frac: self.frac + other.frac,
+        };
+        decimal.trim_int();
+        decimal.trim_frac(self.frac.min(other.frac));
+        decimal
+    }
+
+    pub(crate) fn compare(&self, other: &Self) -> Ordering {
+        match (self.is_zero(), other.is_zero()) {
+            (true, true) => return Ordering::Equal,
+            (true, false) => {
+                return if other.negative {
+                    Ordering::Greater
+                } else {
+                    Ordering::Less
+                }
+            }
+            (false, true) => {
+                return if self.negative {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            }
+            (false, false) => {}
+        }
+        let frac = self.frac.max(other.frac);
+        let int = self.int_len().max(other.int_len());
+        let magnitude = self.aligned(int, frac).cmp(&other.aligned(int, frac));
+        match (self.negative, other.negative) {
+            (false, false) => magnitude,
+            (true, true) => magnitude.reverse(),
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+        }
+    }
+
+    /// Scientific notation with one digit before the decimal point, like `+1.25e-03`.
+    pub(crate) fn to_exp_string(&self) -> String {
+        let Some(first) = self.digits.iter().position(|&d| d != 0) else {
+            return "+0.0e+00".to_string();
+        };
+        let last = self.digits.iter().rposition(|&d| d != 0).unwrap();
+        let exponent = self.int_len() as i64 - first as i64 - 1;
+        let rest: String = if first == last {
+            "0".to_string()
+        } else {
+            digits_to_string(&self.digits[first + 1..=last])
+        };
+        format!(
+            "{}{}.{rest}e{}{:02}",
+            if self.negative { '-' } else { '+' },
+            self.digits[first],
+            if exponent < 0 { '-' } else { '+' },
+            exponent.abs()
+        )
+    }
+
+    fn int_len(&self) -> usize {
+        self.digits.len() - self.frac
+    }
+
+    /// The digits padded with zeros to `int` digits before the decimal point and `frac`
+    /// after it.
+    fn aligned(&self, int: usize, frac: usize) -> Vec<u8> {
+        let mut digits = vec![0; int - self.int_len()];
+        digits.extend_from_slice(&self.digits);
+        digits.resize(int + frac, 0);
+        digits
+    }
+
+    /// Multiplies by `base^exponent`, for a small `base`.
+    fn mul_pow(&mut self, base: u64, mut exponent: u32) {
+        while exponent > 0 {
+            // Powers up to this one keep the products below in a u64.
+            let step = exponent.min(20);
+            let factor = base.pow(step);
+            let mut carry = 0u64;
+            for digit in self.digits.iter_mut().rev() {
+                let product = *digit as u64 * factor + carry;
+                *digit = (product % 10) as u8;
+                carry = product / 10;
+            }
+            let mut head = Vec::new();
+            while carry > 0 {
+                head.push((carry % 10) as u8);
+                carry /= 10;
+            }
+            head.reverse();
+            self.digits.splice(0..0, head);
+            exponent -= step;
+        }
+    }
+
+    /// Adds leading zeros so that there is at least one digit before the decimal point.
+    fn pad_int(&mut self) {
+        if self.digits.len() <= self.frac {
+            let missing = self.frac + 1 - self.digits.len();
+            self.digits.splice(0..0, std::iter::repeat(0).take(missing));
+        }
+    }
+
+    /// Drops leading zeros before the decimal point but the last one.
+    fn trim_int(&mut self) {
+        let zeros = self.digits[..self.int_len()]
+            .iter()
+            .take_while(|&&d| d == 0)
+            .count();
+        self.digits
+            .drain(..zeros.min(self.int_len().saturating_sub(1)));
+    }
+
+    /// Drops trailing zeros after the decimal point while there are more than `min` digits
+    /// after it.
+    fn trim_frac(&mut self, min: usize) {
+        while self.frac > min && self.digits.last() == Some(&0) {
+            self.digits.pop();
+            self.frac -= 1;
+        }
+    }
+}
+
+impl std::fmt::Display for Decimal {
+    /// Formats the number without leading zeros, keeping the digits after the decimal point.
+    /// Zero has no sign.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.negative && !self.is_zero() {
+            f.write_str("-")?;
+        }
+        let (int, frac) = self.digits.split_at(self.int_len());
+        match int.iter().position(|&d| d != 0) {
+            Some(first) => f.write_str(&digits_to_string(&int[first..]))?,
+            None => f.write_str("0")?,
+        }
+        if !frac.is_empty() {
+            write!(f, ".{}", digits_to_string(frac))?;
+        }
+        Ok(())
+    }
+}
+
+fn digits_to_string(digits: &[u8]) -> String {
+    digits.iter().map(|&d| (b'0' + d) as char).collect()
+}
+
+/// Adds two digit lists of the same length, the first digit being zero so that the sum fits.
+fn add_digits(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut sum = vec![0; a.len()];
+    let mut carry = 0;
+    for i in (0..a.len()).rev() {
+        let digit = a[i] + b[i] + carry;
+        sum[i] = digit % 10;
+        carry = digit / 10;
+    }
+    sum
+}
+
+/// Subtracts two digit lists of the same length, `a` being at least `b`.
+fn sub_digits(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut difference = vec![0; a.len()];
+    let mut borrow = 0;
+    for i in (0..a.len()).rev() {
+        let mut digit = a[i] as i8 - b[i] as i8 - borrow;
+        borrow = (digit < 0) as i8;
+        if digit < 0 {
+            digit += 10;
+        }
+        difference[i] = digit as u8;
+    }
+    difference
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(text: &str) -> Decimal {
+        Decimal::parse(text).unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_format() {
+        for (input, expected) in [
+            ("1.50", "1.50"),
+            (" -0012.3400 ", "-12.3400"),
+            ("1e3", "1000"),
+            ("1.5e-3", "0.0015"),
+            ("+.5", "0.5"),
+            ("5.", "5"),
+            ("-0.0", "0.0"),
+            (".000", "0.000"),
+            ("1.25E+1", "12.5"),
+        ] {
+            assert_eq!(dec(input).to_string(), expected, "{input}");
+        }
+        for invalid in ["", "abc", "1 2", "12abc", "1.2.3", "1e", "-", ".", "1e+2x"] {
+            assert_eq!(Decimal::parse(invalid), None, "{invalid}");
+        }
+        assert_eq!(Decimal::parse("1e999999999"), None);
+    }
+
+    #[test]
+    fn test_from_float() {
+        assert_eq!(
+            Decimal::from_float(0.1).unwrap().to_string(),
+            "0.1000000000000000055511151231257827021181583404541015625"
+        );
+        assert_eq!(Decimal::from_float(-1.5).unwrap().to_string(), "-1.5");
+        assert_eq!(Decimal::from_float(3.0).unwrap().to_string(), "3");
+        assert_eq!(
+            Decimal::from_float(1e20).unwrap().to_string(),
+            "100000000000000000000"
+        );
+        assert_eq!(Decimal::from_float(0.0).unwrap().to_string(), "0");
+        assert_eq!(Decimal::from_float(f64::INFINITY), None);
+        let tiny = Decimal::from_float(f64::from_bits(1))
+            .unwrap()
+            .to_exp_string();
+        assert!(tiny.starts_with("+4.940656458412465441765687928682213723"));
+        assert!(tiny.ends_with("e-324"));
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(dec("0.1").add(&dec("0.2")).to_string(), "0.3");
+        assert_eq!(dec("1.50").add(&dec("2.0")).to_string(), "3.50");
+        assert_eq!(dec("1").sub(&dec("0.999")).to_string(), "0.001");
+        assert_eq!(dec("0.1").sub(&dec("0.35")).to_string(), "-0.25");
+        assert_eq!(dec("5").sub(&dec("5")).to_string(), "0");
+        assert_eq!(dec("-1.0").add(&dec("0.5")).to_string(), "-0.5");
+        assert_eq!(dec("99.99").add(&dec("0.01")).to_string(), "100.00");
+        assert_eq!(dec("1.25").mul(&dec("-4.4")).to_string(), "-5.5");
+        assert_eq!(dec("1.50").mul(&dec("2.0")).to_string(), "3.0");
+        assert_eq!(dec("0.10").mul(&dec("0.10")).to_string(), "0.01");
+        assert_eq!(dec("-1").mul(&dec("0")).to_string(), "0");
+        assert_eq!(
+            dec("99999999999999999999")
+                .mul(&dec("99999999999999999999"))
+                .to_string(),
+            "9999999999999999999800000000000000000001"
+        );
+        assert_eq!(Decimal::pow2(10).to_string(), "1024");
+        assert_eq!(Decimal::pow2(-2).to_string(), "0.25");
+    }
+
+    #[test]
+    fn test_cmp() {
+        assert_eq!(dec("1.10").compare(&dec("1.1")), Ordering::Equal);
+        assert_eq!(dec("-2").compare(&dec("1")), Ordering::Less);
+        assert_eq!(dec("10").compare(&dec("9.99")), Ordering::Greater);
+        assert_eq!(dec("-10").compare(&dec("-9.99")), Ordering::Less);
+        assert_eq!(dec("-0").compare(&dec("0.00")), Ordering::Equal);
+        assert_eq!(dec("0").compare(&dec("-0.01")), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_exp_string() {
+        assert_eq!(dec("123.45").to_exp_string(), "+1.2345e+02");
+        assert_eq!(dec("0").to_exp_string(), "+0.0e+00");
+        assert_eq!(dec("-0.00123").to_exp_string(), "-1.23e-03");
+        assert_eq!(dec("5").to_exp_string(), "+5.0e+00");
+        assert_eq!(dec("1.50").to_exp_string(), "+1.5e+00");
+        assert_eq!(dec("0.0100").to_exp_string(), "+1.0e-02");
+    }
+}
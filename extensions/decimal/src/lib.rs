@@ -0,0 +1,146 @@
+//! Exact decimal arithmetic on numbers stored as text, like SQLite's
+//! [decimal](https://sqlite.org/floatingpoint.html#decext) extension, e.g. for monetary
+//! values that floats can't represent exactly.
+//!
+//! Arguments can be text, integers, or floats and 8-byte big-endian float blobs, which are
+//! taken at their exact binary value. Text that isn't a decimal number makes the result
+//! NULL, where SQLite reads as much of it as looks like a number. The `decimal` collation is
+//! not provided, as there are no collating sequences to add it to yet.
+mod decimal;
+
+use decimal::Decimal;
+use limbo_ext::{register_extension, scalar, AggFunc, AggregateDerive, Value, ValueType};
+
+register_extension! {
+    scalars: {
+        decimal,
+        decimal_exp,
+        decimal_cmp,
+        decimal_add,
+        decimal_sub,
+        decimal_mul,
+        decimal_pow2,
+    },
+    aggregates: { DecimalSum },
+}
+
+/// The range of `decimal_pow2`, as in SQLite.
+const MAX_POW2_EXPONENT: i64 = 20_000;
+
+fn decimal_arg(value: Option<&Value>) -> Option<Decimal> {
+    let value = value?;
+    match value.value_type() {
+        ValueType::Text => Decimal::parse(value.to_text()?),
+        ValueType::Integer => Some(Decimal::from_integer(value.to_integer()?)),
+        ValueType::Float => Decimal::from_float(value.to_float()?),
+        ValueType::Blob => {
+            let bytes: [u8; 8] = value.to_blob()?.try_into().ok()?;
+            Decimal::from_float(f64::from_be_bytes(bytes))
+        }
+        ValueType::Null | ValueType::Error => None,
+    }
+}
+
+fn text_result(decimal: Option<Decimal>) -> Value {
+    match decimal {
+        Some(decimal) => Value::from_text(decimal.to_string()),
+        None => Value::null(),
+    }
+}
+
+/// The argument as decimal text, e.g. `decimal(0.1)` shows the exact value of the float.
+#[scalar(name = "decimal")]
+fn decimal(args: &[Value]) -> Value {
+    text_result(decimal_arg(args.first()))
+}
+
+/// The argument in scientific notation, like `+1.2345e+02`.
+#[scalar(name = "decimal_exp")]
+fn decimal_exp(args: &[Value]) -> Value {
+    match decimal_arg(args.first()) {
+        Some(decimal) => Value::from_text(decimal.to_exp_string()),
+        None => Value::null(),
+    }
+}
+
+/// -1, 0 or 1 as the first argument is less than, equal to or greater than the second one.
+#[scalar(name = "decimal_cmp")]
+fn decimal_cmp(args: &[Value]) -> Value {
+    match (decimal_arg(args.first()), decimal_arg(args.get(1))) {
+        (Some(a), Some(b)) => Value::from_integer(a.compare(&b) as i64),
+        _ => Value::null(),
+    }
+}
+
+#[scalar(name = "decimal_add")]
+fn decimal_add(args: &[Value]) -> Value {
+    let sum = decimal_arg(args.first()).zip(decimal_arg(args.get(1)));
+    text_result(sum.map(|(a, b)| a.add(&b)))
+}
+
+#[scalar(name = "decimal_sub")]
+fn decimal_sub(args: &[Value]) -> Value {
+    let difference = decimal_arg(args.first()).zip(decimal_arg(args.get(1)));
+    text_result(difference.map(|(a, b)| a.sub(&b)))
+}
+
+#[scalar(name = "decimal_mul")]
+fn decimal_mul(args: &[Value]) -> Value {
+    let product = decimal_arg(args.first()).zip(decimal_arg(args.get(1)));
+    text_result(product.map(|(a, b)| a.mul(&b)))
+}
+
+/// 2 to the power of an integer between -20000 and 20000, exactly, in scientific notation
+/// like SQLite's.
+#[scalar(name = "decimal_pow2")]
+fn decimal_pow2(args: &[Value]) -> Value {
+    let exponent = args
+        .first()
+        .filter(|arg| arg.value_type() == ValueType::Integer)
+        .and_then(Value::to_integer)
+        .filter(|exponent| exponent.abs() <= MAX_POW2_EXPONENT);
+    match exponent {
+        Some(exponent) => Value::from_text(Decimal::pow2(exponent as i32).to_exp_string()),
+        None => Value::null(),
+    }
+}
+
+/// The exact sum of the non-NULL values of a group, NULL if there are none.
+#[derive(AggregateDerive)]
+struct DecimalSum;
+
+#[derive(Default)]
+struct SumState {
+    sum: Option<Decimal>,
+    invalid: bool,
+}
+
+impl AggFunc for DecimalSum {
+    type State = SumState;
+    type Error = &'static str;
+    const NAME: &'static str = "decimal_sum";
+    const ARGS: i32 = 1;
+
+    fn step(state: &mut Self::State, args: &[Value]) {
+        if args
+            .first()
+            .map_or(true, |arg| arg.value_type() == ValueType::Null)
+        {
+            return;
+        }
+        match decimal_arg(args.first()) {
+            Some(value) => {
+                let sum = state.sum.take().unwrap_or_else(Decimal::zero);
+                state.sum = Some(sum.add(&value));
+            }
+            None => state.invalid = true,
+        }
+    }
+
+    fn finalize(state: Self::State) -> Result<Value, Self::Error> {
+        if state.invalid {
+            return Err("decimal_sum: value is not a decimal number");
+        }
+        Ok(text_result(state.sum))
+    }
+}
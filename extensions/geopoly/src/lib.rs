@@ -0,0 +1,351 @@
+//! The SQL functions of SQLite's [geopoly](https://sqlite.org/geopoly.html) extension, for
+//! simple polygons given as blobs or as JSON arrays of points.
+//!
+//! The `geopoly` virtual table itself is not provided: SQLite builds it on the R-tree
+//! module, which isn't available here. The functions work on polygons stored in ordinary
+//! tables.
+mod overlap;
+mod polygon;
+
+use limbo_ext::{register_extension, scalar, AggFunc, AggregateDerive, Value, ValueType};
+use overlap::{overlap, Overlap};
+use polygon::Polygon;
+
+register_extension! {
+    scalars: {
+        geopoly_blob,
+        geopoly_json,
+        geopoly_svg,
+        geopoly_area,
+        geopoly_ccw,
+        geopoly_bbox,
+        geopoly_contains_point,
+        geopoly_overlap,
+        geopoly_within,
+        geopoly_xform,
+        geopoly_regular,
+    },
+    aggregates: { GeopolyGroupBbox },
+}
+
+/// The maximum number of vertices of a polygon made by `geopoly_regular`.
+const MAX_REGULAR_VERTICES: i64 = 1000;
+
+/// Reads a polygon argument, either a blob or JSON text.
+fn polygon_arg(value: Option<&Value>) -> Option<Polygon> {
+    let value = value?;
+    match value.value_type() {
+        ValueType::Blob => Polygon::from_blob(&value.to_blob()?),
+        ValueType::Text => Polygon::from_json(value.to_text()?),
+        _ => None,
+    }
+}
+
+/// Reads the numeric arguments starting at `start`, `None` if any of them is missing or
+/// not a number.
+fn float_args<const N: usize>(args: &[Value], start: usize) -> Option<[f64; N]> {
+    let mut floats = [0.0; N];
+    for (i, float) in floats.iter_mut().enumerate() {
+        let arg = args.get(start + i)?;
+        if !matches!(arg.value_type(), ValueType::Integer | ValueType::Float) {
+            return None;
+        }
+        *float = arg.to_float()?;
+    }
+    Some(floats)
+}
+
+/// The polygon as a blob.
+#[scalar(name = "geopoly_blob")]
+fn geopoly_blob(args: &[Value]) -> Value {
+    match polygon_arg(args.first()) {
+        Some(polygon) => Value::from_blob(polygon.to_blob()),
+        None => Value::null(),
+    }
+}
+
+/// The polygon as a JSON array of points, ending with the first point again.
+#[scalar(name = "geopoly_json")]
+fn geopoly_json(args: &[Value]) -> Value {
+    match polygon_arg(args.first()) {
+        Some(polygon) => Value::from_text(polygon.to_json()),
+        None => Value::null(),
+    }
+}
+
+/// An SVG `<polyline>` for the polygon. The other arguments are added as attributes.
+#[scalar(name = "geopoly_svg")]
+fn geopoly_svg(args: &[Value]) -> Value {
+    let Some(polygon) = polygon_arg(args.first()) else {
+        return Value::null();
+    };
+    let mut svg = format!("<polyline points='{}'", polygon.svg_points());
+    for arg in &args[1..] {
+        if let Some(text) = arg.to_text().filter(|text| !text.is_empty()) {
+            svg.push(' ');
+            svg.push_str(text);
+        }
+    }
+    svg.push_str("></polyline>");
+    Value::from_text(svg)
+}
+
+/// The signed area of the polygon, negative if its vertices go clockwise.
+#[scalar(name = "geopoly_area")]
+fn geopoly_area(args: &[Value]) -> Value {
+    match polygon_arg(args.first()) {
+        Some(polygon) => Value::from_float(polygon.area()),
+        None => Value::null(),
+    }
+}
+
+/// The polygon with its vertices in counter-clockwise order.
+#[scalar(name = "geopoly_ccw")]
+fn geopoly_ccw(args: &[Value]) -> Value {
+    let Some(mut polygon) = polygon_arg(args.first()) else {
+        return Value::null();
+    };
+    polygon.make_ccw();
+    Value::from_blob(polygon.to_blob())
+}
+
+/// The smallest axis-aligned rectangle containing the polygon.
+#[scalar(name = "geopoly_bbox")]
+fn geopoly_bbox(args: &[Value]) -> Value {
+    let Some(polygon) = polygon_arg(args.first()) else {
+        return Value::null();
+    };
+    let (min_x, max_x, min_y, max_y) = polygon.bounds();
+    Value::from_blob(Polygon::rectangle(min_x, max_x, min_y, max_y).to_blob())
+}
+
+/// 2 if the point `(X, Y)` is inside the polygon, 1 if it is on its boundary, 0 otherwise.
+#[scalar(name = "geopoly_contains_point")]
+fn geopoly_contains_point(args: &[Value]) -> Value {
+    let (Some(polygon), Some([x, y])) = (polygon_arg(args.first()), float_args(args, 1)) else {
+        return Value::null();
+    };
+    Value::from_integer(polygon.contains_point(x, y))
+}
+
+/// 0 if the polygons don't overlap, 1 if they overlap partially, 2 if the first one is
+/// within the second one, 3 if the second one is within the first one and 4 if they are
+/// the same.
+#[scalar(name = "geopoly_overlap")]
+fn geopoly_overlap(args: &[Value]) -> Value {
+    let (Some(first), Some(second)) = (polygon_arg(args.first()), polygon_arg(args.get(1))) else {
+        return Value::null();
+    };
+    Value::from_integer(overlap(&first, &second).code())
+}
+
+/// 1 if the first polygon is within the second one, 2 if they are the same, 0 otherwise.
+#[scalar(name = "geopoly_within")]
+fn geopoly_within(args: &[Value]) -> Value {
+    let (Some(first), Some(second)) = (polygon_arg(args.first()), polygon_arg(args.get(1))) else {
+        return Value::null();
+    };
+    Value::from_integer(match overlap(&first, &second) {
+        Overlap::FirstWithin => 1,
+        Overlap::Same => 2,
+        _ => 0,
+    })
+}
+
+/// Applies the affine transformation `x' = A*x + B*y + E`, `y' = C*x + D*y + F` to every
+/// vertex of the polygon, given as `geopoly_xform(P, A, B, C, D, E, F)`.
+#[scalar(name = "geopoly_xform")]
+fn geopoly_xform(args: &[Value]) -> Value {
+    let (Some(mut polygon), Some([a, b, c, d, e, f])) =
+        (polygon_arg(args.first()), float_args(args, 1))
+    else {
+        return Value::null();
+    };
+    for (x, y) in polygon.vertices.iter_mut() {
+        let (x0, y0) = (*x as f64, *y as f64);
+        *x = (a * x0 + b * y0 + e) as f32;
+        *y = (c * x0 + d * y0 + f) as f32;
+    }
+    Value::from_blob(polygon.to_blob())
+}
+
+/// A regular polygon with N vertices centered at `(X, Y)` and radius R, given as
+/// `geopoly_regular(X, Y, R, N)`. The first vertex is to the right of the center.
+#[scalar(name = "geopoly_regular")]
+fn geopoly_regular(args: &[Value]) -> Value {
+    let (Some([x, y, radius]), Some(count)) =
+        (float_args(args, 0), args.get(3).and_then(Value::to_integer))
+    else {
+        return Value::null();
+    };
+    if count < 3 || radius <= 0.0 {
+        return Value::null();
+    }
+    let count = count.min(MAX_REGULAR_VERTICES);
+    let vertices = (0..count)
+        .map(|i| {
+            let angle = 2.0 * std::f64::consts::PI * i as f64 / count as f64;
+            (
+                (x + radius * angle.cos()) as f32,
+                (y + radius * angle.sin()) as f32,
+            )
+        })
+        .collect();
+    Value::from_blob(Polygon { vertices }.to_blob())
+}
+
+/// The bounding box of all the polygons of a group, ignoring values that aren't polygons.
+#[derive(AggregateDerive)]
+struct GeopolyGroupBbox;
+
+impl AggFunc for GeopolyGroupBbox {
+    type State = Option<(f32, f32, f32, f32)>;
+    type Error = &'static str;
+    const NAME: &'static str = "geopoly_group_bbox";
+    const ARGS: i32 = 1;
+
+    fn step(state: &mut Self::State, args: &[Value]) {
+        let Some(polygon) = polygon_arg(args.first()) else {
+            return;
+        };
+        let (min_x, max_x, min_y, max_y) = polygon.bounds();
+        *state = Some(match *state {
+            Some((x0, x1, y0, y1)) => (x0.min(min_x), x1.max(max_x), y0.min(min_y), y1.max(max_y)),
+            None => (min_x, max_x, min_y, max_y),
+        });
+    }
+
+    fn finalize(state: Self::State) -> Result<Value, Self::Error> {
+        Ok(match state {
+            Some((min_x, max_x, min_y, max_y)) => {
+                Value::from_blob(Polygon::rectangle(min_x, max_x, min_y, max_y).to_blob())
+            }
+            None => Value::null(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::polygon::format_g;
+    use std::fmt::Write;
+
+    fn poly(json: &str) -> Polygon {
+        Polygon::from_json(json).unwrap()
+    }
+
+    #[test]
+    fn test_json_and_blob() {
+        let square = poly(" [ [0, 0], [1e3,-0.5 , 9], [1,1],[0,0]] ");
+        assert_eq!(
+            square.vertices,
+            vec![(0.0, 0.0), (1000.0, -0.5), (1.0, 1.0)]
+        );
+        assert_eq!(
+            square.to_json(),
+            "[[0.0,0.0],[1000.0,-0.5],[1.0,1.0],[0.0,0.0]]"
+        );
+
+        let blob = poly("[[0,0],[1,0],[1,1],[0,0]]").to_blob();
+        assert_eq!(
+            hex(&blob),
+            "0100000300000000000000000000803F000000000000803F0000803F"
+        );
+        let mut big_endian = vec![0, 0, 0, 3];
+        for coord in [0.0f32, 0.0, 1.0, 0.0, 1.0, 1.0] {
+            big_endian.extend_from_slice(&coord.to_be_bytes());
+        }
+        assert_eq!(Polygon::from_blob(&big_endian), Polygon::from_blob(&blob));
+
+        for invalid in [
+            "[[0,0],[1,0],[1,1]]",
+            "[[0,0],[1,0],[1,1],[0,1]]",
+            "[[0,0],[1,0],[0,0]]",
+            "[[0,0],[1],[1,1],[0,0]]",
+            "[[0,0],[01,0],[1,1],[0,0]]",
+            "[[0,0],[1,0],[1,1],[0,0]] x",
+        ] {
+            assert!(Polygon::from_json(invalid).is_none(), "{invalid}");
+        }
+        assert!(Polygon::from_blob(&blob[..blob.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn test_format_g() {
+        assert_eq!(format_g(0.1, true), "0.1");
+        assert_eq!(format_g(1.5, false), "1.5");
+        assert_eq!(format_g(2.0, false), "2");
+        assert_eq!(format_g(123456789.0, true), "1.23457e+08");
+        assert_eq!(format_g(0.00001, true), "1.0e-05");
+        assert_eq!(format_g(-42.25, true), "-42.25");
+    }
+
+    #[test]
+    fn test_area_and_ccw() {
+        let clockwise = poly("[[0,0],[0,1],[1,1],[1,0],[0,0]]");
+        assert_eq!(clockwise.area(), -1.0);
+        let mut ccw = clockwise.clone();
+        ccw.make_ccw();
+        assert_eq!(
+            ccw.to_json(),
+            "[[0.0,0.0],[1.0,0.0],[1.0,1.0],[0.0,1.0],[0.0,0.0]]"
+        );
+        assert_eq!(ccw.area(), 1.0);
+        assert_eq!(poly("[[0,0],[1,0],[1,1],[0,0]]").area(), 0.5);
+    }
+
+    #[test]
+    fn test_bounds_and_svg() {
+        let triangle = poly("[[0,0],[3,-1],[1,2.5],[0,0]]");
+        assert_eq!(triangle.bounds(), (0.0, 3.0, -1.0, 2.5));
+        assert_eq!(triangle.svg_points(), "0,0 3,-1 1,2.5 0,0");
+    }
+
+    #[test]
+    fn test_contains_point() {
+        let square = poly("[[0,0],[2,0],[2,2],[0,2],[0,0]]");
+        assert_eq!(square.contains_point(1.0, 1.0), 2);
+        assert_eq!(square.contains_point(2.0, 1.0), 1);
+        assert_eq!(square.contains_point(0.0, 0.0), 1);
+        assert_eq!(square.contains_point(3.0, 1.0), 0);
+        assert_eq!(square.contains_point(1.0, 3.0), 0);
+    }
+
+    #[test]
+    fn test_overlap() {
+        let square = poly("[[0,0],[2,0],[2,2],[0,2],[0,0]]");
+        let big = poly("[[0,0],[4,0],[4,4],[0,4],[0,0]]");
+        let inner = poly("[[1,1],[2,1],[2,2],[1,1]]");
+        let cases = [
+            (
+                &square,
+                poly("[[1,1],[3,1],[3,3],[1,3],[1,1]]"),
+                Overlap::Partial,
+            ),
+            (
+                &square,
+                poly("[[5,5],[6,5],[6,6],[5,5]]"),
+                Overlap::Disjoint,
+            ),
+            (&big, inner.clone(), Overlap::SecondWithin),
+            (&inner, big.clone(), Overlap::FirstWithin),
+            (&square, square.clone(), Overlap::Same),
+            (
+                &poly("[[0,0],[1,0],[0,1],[0,0]]"),
+                poly("[[1,0],[2,0],[1,1],[1,0]]"),
+                Overlap::Disjoint,
+            ),
+        ];
+        for (first, second, expected) in cases {
+            assert_eq!(overlap(first, &second), expected, "{first:?} {second:?}");
+        }
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().fold(String::new(), |mut hex, b| {
+            write!(hex, "{b:02X}").unwrap();
+            hex
+        })
+    }
+}
@@ -0,0 +1,142 @@
+//! How two polygons overlap, found with a sweep line moving left to right over the edges of
+//! both. Between two stops of the line, the edges it crosses are kept sorted by Y, and the
+//! gaps between consecutive edges tell which polygons cover that strip: the sides of the
+//! edges below a gap, XOR-ed together, are a mask of the polygons covering it. Two edges of
+//! different polygons changing order between stops means that they cross.
+
+use crate::polygon::Polygon;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Overlap {
+    Disjoint,
+    /// The polygons overlap but neither contains the other.
+    Partial,
+    /// The first polygon is within the second one.
+    FirstWithin,
+    /// The second polygon is within the first one.
+    SecondWithin,
+    Same,
+}
+
+impl Overlap {
+    pub(crate) fn code(self) -> i64 {
+        match self {
+            Overlap::Disjoint => 0,
+            Overlap::Partial => 1,
+            Overlap::FirstWithin => 2,
+            Overlap::SecondWithin => 3,
+            Overlap::Same => 4,
+        }
+    }
+}
+
+/// An edge as `y = c * x + b` between the X of its two ends.
+struct Segment {
+    c: f64,
+    b: f64,
+    /// Y where the sweep line last crossed the edge.
+    y: f64,
+    /// Y of the left end.
+    y0: f64,
+    /// 1 for an edge of the first polygon, 2 for the second one.
+    side: u8,
+}
+
+struct Event {
+    x: f64,
+    add: bool,
+    segment: usize,
+}
+
+pub(crate) fn overlap(first: &Polygon, second: &Polygon) -> Overlap {
+    let mut segments = Vec::new();
+    let mut events = Vec::new();
+    for (polygon, side) in [(first, 1), (second, 2)] {
+        for ((x0, y0), (x1, y1)) in polygon.edges() {
+            // Vertical edges never separate two strips.
+            if x0 == x1 {
+                continue;
+            }
+            let ((x0, y0), (x1, y1)) = if x0 > x1 {
+                ((x1 as f64, y1 as f64), (x0 as f64, y0 as f64))
+            } else {
+                ((x0 as f64, y0 as f64), (x1 as f64, y1 as f64))
+            };
+            let c = (y1 - y0) / (x1 - x0);
+            events.push(Event {
+                x: x0,
+                add: true,
+                segment: segments.len(),
+            });
+            events.push(Event {
+                x: x1,
+                add: false,
+                segment: segments.len(),
+            });
+            segments.push(Segment {
+                c,
+                b: y1 - x1 * c,
+                y: y0,
+                y0,
+                side,
+            });
+        }
+    }
+    events.sort_by(|a, b| a.x.total_cmp(&b.x));
+
+    // Whether a strip covered by neither, only the first, only the second or both polygons
+    // was seen, indexed by the mask of the polygons covering it.
+    let mut covered = [false; 4];
+    let mut active: Vec<usize> = Vec::new();
+    let mut needs_sort = false;
+    let mut x = events.first().map_or(0.0, |event| event.x - 1.0);
+    for event in &events {
+        if event.x != x {
+            x = event.x;
+            if needs_sort {
+                active.sort_by(|&a, &b| {
+                    let (a, b) = (&segments[a], &segments[b]);
+                    a.y.total_cmp(&b.y).then(a.c.total_cmp(&b.c))
+                });
+                needs_sort = false;
+            }
+            let mut mask = 0;
+            for pair in active.windows(2) {
+                mask ^= segments[pair[0]].side;
+                if segments[pair[0]].y != segments[pair[1]].y {
+                    covered[mask as usize] = true;
+                }
+            }
+            for &segment in &active {
+                let segment = &mut segments[segment];
+                segment.y = segment.c * x + segment.b;
+            }
+            let mut mask = 0;
+            for pair in active.windows(2) {
+                let (below, above) = (&segments[pair[0]], &segments[pair[1]]);
+                mask ^= below.side;
+                if below.y > above.y && below.side != above.side {
+                    return Overlap::Partial;
+                }
+                if below.y != above.y {
+                    covered[mask as usize] = true;
+                }
+            }
+        }
+        if event.add {
+            let segment = &mut segments[event.segment];
+            segment.y = segment.y0;
+            active.push(event.segment);
+            needs_sort = true;
+        } else if let Some(pos) = active.iter().position(|&s| s == event.segment) {
+            active.remove(pos);
+        }
+    }
+    match (covered[1], covered[2], covered[3]) {
+        (_, _, false) => Overlap::Disjoint,
+        (true, false, true) => Overlap::SecondWithin,
+        (false, true, true) => Overlap::FirstWithin,
+        (false, false, true) => Overlap::Same,
+        (true, true, true) => Overlap::Partial,
+    }
+}
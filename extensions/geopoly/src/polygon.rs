@@ -0,0 +1,321 @@
+//! The polygon type shared by the geopoly functions and its two encodings.
+//!
+//! A polygon is stored as a blob: a header byte telling the byte order of the coordinates
+//! (0 for big-endian, 1 for little-endian), the vertex count as a 24-bit big-endian integer
+//! and then the X and Y of each vertex as 32-bit floats. The text form is a JSON array of
+//! `[x,y]` points whose last point repeats the first one. Vertices are kept without the
+//! closing point and coordinates are 32-bit floats, like in SQLite, so blobs written by
+//! either can be read by the other.
+
+const HEADER_SIZE: usize = 4;
+const VERTEX_SIZE: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Polygon {
+    pub(crate) vertices: Vec<(f32, f32)>,
+}
+
+impl Polygon {
+    pub(crate) fn from_blob(blob: &[u8]) -> Option<Self> {
+        if blob.len() < HEADER_SIZE + 3 * VERTEX_SIZE {
+            return None;
+        }
+        let little_endian = match blob[0] {
+            0 => false,
+            1 => true,
+            _ => return None,
+        };
+        let count = u32::from_be_bytes([0, blob[1], blob[2], blob[3]]) as usize;
+        if HEADER_SIZE + count * VERTEX_SIZE != blob.len() {
+            return None;
+        }
+        let coord = |bytes: &[u8]| {
+            let bytes = bytes.try_into().unwrap();
+            if little_endian {
+                f32::from_le_bytes(bytes)
+            } else {
+                f32::from_be_bytes(bytes)
+            }
+        };
+        let vertices = blob[HEADER_SIZE..]
+            .chunks_exact(VERTEX_SIZE)
+            .map(|vertex| (coord(&vertex[..4]), coord(&vertex[4..])))
+            .collect();
+        Some(Self { vertices })
+    }
+
+    /// Parses a JSON array of at least four points, the last one equal to the first.
+    /// Numbers after the first two of a point are ignored.
+    pub(crate) fn from_json(json: &str) -> Option<Self> {
+        let mut parser = JsonParser {
+            input: json.as_bytes(),
+            pos: 0,
+        };
+        let mut vertices = Vec::new();
+        parser.expect(b'[')?;
+        while parser.peek() == Some(b'[') {
+            parser.pos += 1;
+            let mut coords = Vec::with_capacity(2);
+            loop {
+                coords.push(parser.number()?);
+                match parser.next()? {
+                    b',' => continue,
+                    b']' if coords.len() >= 2 => break,
+                    _ => return None,
+                }
+            }
+            vertices.push((coords[0] as f32, coords[1] as f32));
+            if parser.peek() != Some(b',') {
+                break;
+            }
+            parser.pos += 1;
+        }
+        parser.expect(b']')?;
+        if parser.peek().is_some() || vertices.len() < 4 || vertices.first() != vertices.last() {
+            return None;
+        }
+        vertices.pop();
+        Some(Self { vertices })
+    }
+
+    /// Encodes the polygon with little-endian coordinates.
+    pub(crate) fn to_blob(&self) -> Vec<u8> {
+        let count = self.vertices.len() as u32;
+        let mut blob = Vec::with_capacity(HEADER_SIZE + self.vertices.len() * VERTEX_SIZE);
+        blob.push(1);
+        blob.extend_from_slice(&count.to_be_bytes()[1..]);
+        for (x, y) in &self.vertices {
+            blob.extend_from_slice(&x.to_le_bytes());
+            blob.extend_from_slice(&y.to_le_bytes());
+        }
+        blob
+    }
+
+    pub(crate) fn to_json(&self) -> String {
+        let points: Vec<String> = self
+            .vertices
+            .iter()
+            .chain(self.vertices.first())
+            .map(|&(x, y)| format!("[{},{}]", format_g(x, true), format_g(y, true)))
+            .collect();
+        format!("[{}]", points.join(","))
+    }
+
+    /// The `points` attribute of an SVG `<polyline>`, closing the ring.
+    pub(crate) fn svg_points(&self) -> String {
+        let points: Vec<String> = self
+            .vertices
+            .iter()
+            .chain(self.vertices.first())
+            .map(|&(x, y)| format!("{},{}", format_g(x, false), format_g(y, false)))
+            .collect();
+        points.join(" ")
+    }
+
+    /// The signed area, positive when the vertices go counter-clockwise.
+    pub(crate) fn area(&self) -> f64 {
+        self.edges()
+            .map(|((x0, y0), (x1, y1))| (x0 - x1) as f64 * (y0 + y1) as f64 * 0.5)
+            .sum()
+    }
+
+    /// Reverses the vertices after the first one if they go clockwise.
+    pub(crate) fn make_ccw(&mut self) {
+        if self.area() < 0.0 {
+            self.vertices[1..].reverse();
+        }
+    }
+
+    /// The smallest axis-aligned rectangle around the polygon, as
+    /// `(min_x, max_x, min_y, max_y)`.
+    pub(crate) fn bounds(&self) -> (f32, f32, f32, f32) {
+        let (x, y) = self.vertices[0];
+        self.vertices[1..]
+            .iter()
+            .fold((x, x, y, y), |(min_x, max_x, min_y, max_y), &(x, y)| {
+                (min_x.min(x), max_x.max(x), min_y.min(y), max_y.max(y))
+            })
+    }
+
+    pub(crate) fn rectangle(min_x: f32, max_x: f32, min_y: f32, max_y: f32) -> Self {
+        Self {
+            vertices: vec![
+                (min_x, min_y),
+                (max_x, min_y),
+                (max_x, max_y),
+                (min_x, max_y),
+            ],
+        }
+    }
+
+    /// 2 if the point is inside the polygon, 1 if it is on its boundary and 0 if it is
+    /// outside. Counts the edges above the point, which is inside if there is an odd
+    /// number of them.
+    pub(crate) fn contains_point(&self, x: f64, y: f64) -> i64 {
+        let mut crossings = 0;
+        for ((x1, y1), (x2, y2)) in self.edges() {
+            match point_beneath_edge(x, y, x1 as f64, y1 as f64, x2 as f64, y2 as f64) {
+                Beneath::OnEdge => return 1,
+                Beneath::Yes => crossings += 1,
+                Beneath::No => {}
+            }
+        }
+        if crossings % 2 == 1 {
+            2
+        } else {
+            0
+        }
+    }
+
+    /// Every edge of the polygon, including the one back to the first vertex.
+    pub(crate) fn edges(&self) -> impl Iterator<Item = ((f32, f32), (f32, f32))> + '_ {
+        self.vertices
+            .iter()
+            .copied()
+            .zip(self.vertices.iter().copied().cycle().skip(1))
+    }
+}
+
+enum Beneath {
+    Yes,
+    No,
+    OnEdge,
+}
+
+/// Whether the point `(x0, y0)` lies below the edge from `(x1, y1)` to `(x2, y2)`, counting
+/// only one end of the edge so that a vertex above the point is counted once.
+fn point_beneath_edge(x0: f64, y0: f64, x1: f64, y1: f64, x2: f64, y2: f64) -> Beneath {
+    if x0 == x1 && y0 == y1 {
+        return Beneath::OnEdge;
+    }
+    if x1 < x2 {
+        if x0 <= x1 || x0 > x2 {
+            return Beneath::No;
+        }
+    } else if x1 > x2 {
+        if x0 <= x2 || x0 > x1 {
+            return Beneath::No;
+        }
+    } else {
+        if x0 != x1 || (y0 < y1 && y0 < y2) || (y0 > y1 && y0 > y2) {
+            return Beneath::No;
+        }
+        return Beneath::OnEdge;
+    }
+    let y = y1 + (y2 - y1) * (x0 - x1) / (x2 - x1);
+    if y0 == y {
+        Beneath::OnEdge
+    } else if y0 < y {
+        Beneath::Yes
+    } else {
+        Beneath::No
+    }
+}
+
+struct JsonParser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl JsonParser<'_> {
+    /// The next byte that isn't whitespace, without consuming it.
+    fn peek(&mut self) -> Option<u8> {
+        while let Some(c) = self.input.get(self.pos) {
+            if !c.is_ascii_whitespace() {
+                return Some(*c);
+            }
+            self.pos += 1;
+        }
+        None
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn expect(&mut self, expected: u8) -> Option<()> {
+        (self.next()? == expected).then_some(())
+    }
+
+    /// A JSON number: an optional minus sign, an integer part without leading zeros, and
+    /// optional fraction and exponent.
+    fn number(&mut self) -> Option<f64> {
+        self.peek()?;
+        let start = self.pos;
+        let digits = |parser: &mut Self| {
+            let from = parser.pos;
+            while parser.input.get(parser.pos).is_some_and(u8::is_ascii_digit) {
+                parser.pos += 1;
+            }
+            parser.pos - from
+        };
+        if self.input.get(self.pos) == Some(&b'-') {
+            self.pos += 1;
+        }
+        let int_start = self.pos;
+        let int_digits = digits(self);
+        if int_digits == 0 || (int_digits > 1 && self.input[int_start] == b'0') {
+            return None;
+        }
+        if self.input.get(self.pos) == Some(&b'.') {
+            self.pos += 1;
+            if digits(self) == 0 {
+                return None;
+            }
+        }
+        if matches!(self.input.get(self.pos), Some(b'e' | b'E')) {
+            self.pos += 1;
+            if matches!(self.input.get(self.pos), Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            if digits(self) == 0 {
+                return None;
+            }
+        }
+        std::str::from_utf8(&self.input[start..self.pos])
+            .ok()?
+            .parse()
+            .ok()
+    }
+}
+
+/// Formats a coordinate like C's `%g`: six significant digits, trailing zeros removed and
+/// an exponent for very small or large values. With `point`, there is always a digit after
+/// the decimal point, like SQLite's `%!g`.
+pub(crate) fn format_g(value: f32, point: bool) -> String {
+    let value = value as f64;
+    if value == 0.0 || !value.is_finite() {
+        let zero = if value.is_sign_negative() { "-0" } else { "0" };
+        return match (value.is_finite(), point) {
+            (true, true) => format!("{zero}.0"),
+            (true, false) => zero.to_string(),
+            (false, _) if value.is_nan() => "NaN".to_string(),
+            (false, _) if value > 0.0 => "Inf".to_string(),
+            (false, _) => "-Inf".to_string(),
+        };
+    }
+    let scientific = format!("{value:.5e}");
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let trim = |digits: &str| {
+        let digits = if digits.contains('.') {
+            digits.trim_end_matches('0').trim_end_matches('.')
+        } else {
+            digits
+        };
+        if point && !digits.contains('.') {
+            format!("{digits}.0")
+        } else {
+            digits.to_string()
+        }
+    };
+    if !(-4..6).contains(&exponent) {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{sign}{:02}", trim(mantissa), exponent.abs())
+    } else {
+        let decimals = (5 - exponent) as usize;
+        trim(&format!("{value:.decimals$}"))
+    }
+}
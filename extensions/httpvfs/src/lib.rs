@@ -0,0 +1,341 @@
+//! A read-only VFS that reads a database over HTTP(S) range requests, so that large
+//! static databases hosted on object storage or a CDN can be queried without
+//! downloading them first.
+//!
+//! Open a database with the `http` VFS and its URL as the path, for example
+//! `limbo --vfs http https://example.com/data.db`. The file is fetched on demand in
+//! blocks that are kept in a bounded cache, and the read-ahead window grows while
+//! reads are sequential so that scans need few round trips. The WAL lives in memory.
+use limbo_ext::{register_extension, ExtResult, ResultCode, VfsDerive, VfsExtension, VfsFile};
+use std::{collections::HashMap, io::Read};
+
+register_extension! {
+    vfs: { HttpVfs },
+}
+
+/// Size of the blocks that are fetched and cached.
+const BLOCK_SIZE: u64 = 4096;
+/// Maximum number of cached blocks (16 MiB).
+const CACHE_BLOCKS: usize = 4096;
+/// Maximum number of blocks read ahead of a sequential read (1 MiB).
+const MAX_READAHEAD: u64 = 256;
+
+#[derive(VfsDerive, Default)]
+pub struct HttpVfs;
+
+impl VfsExtension for HttpVfs {
+    const NAME: &'static str = "http";
+    type File = HttpFile;
+
+    fn open_file(&self, path: &str, _flags: i32, _direct: bool) -> ExtResult<Self::File> {
+        if path.ends_with("-wal") {
+            return Ok(HttpFile::Memory(Vec::new()));
+        }
+        RemoteFile::open(path).map(HttpFile::Remote)
+    }
+}
+
+pub enum HttpFile {
+    /// The database file, read from the server.
+    Remote(RemoteFile),
+    /// Files that only exist locally, such as the WAL.
+    Memory(Vec<u8>),
+}
+
+impl VfsFile for HttpFile {
+    fn read(&mut self, buf: &mut [u8], count: usize, offset: i64) -> ExtResult<i32> {
+        match self {
+            HttpFile::Remote(file) => file.read(&mut buf[..count], offset as u64),
+            HttpFile::Memory(data) => {
+                let start = (offset as usize).min(data.len());
+                let end = (start + count).min(data.len());
+                buf[..end - start].copy_from_slice(&data[start..end]);
+                Ok((end - start) as i32)
+            }
+        }
+    }
+
+    fn write(&mut self, buf: &[u8], count: usize, offset: i64) -> ExtResult<i32> {
+        match self {
+            HttpFile::Remote(_) => Err(ResultCode::ReadOnly),
+            HttpFile::Memory(data) => {
+                let start = offset as usize;
+                if data.len() < start + count {
+                    data.resize(start + count, 0);
+                }
+                data[start..start + count].copy_from_slice(&buf[..count]);
+                Ok(count as i32)
+            }
+        }
+    }
+
+    fn sync(&self) -> ExtResult<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> i64 {
+        match self {
+            HttpFile::Remote(file) => file.size as i64,
+            HttpFile::Memory(data) => data.len() as i64,
+        }
+    }
+}
+
+pub struct RemoteFile {
+    url: String,
+    agent: ureq::Agent,
+    size: u64,
+    cache: HashMap<u64, Block>,
+    /// Incremented on every read, used to find the least recently used blocks.
+    clock: u64,
+    /// Offset right after the previous read, used to detect sequential reads.
+    next_offset: u64,
+    readahead: u64,
+}
+
+struct Block {
+    data: Vec<u8>,
+    last_used: u64,
+}
+
+impl RemoteFile {
+    fn open(url: &str) -> ExtResult<Self> {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            log::error!("http vfs: not an http(s) URL: {}", url);
+            return Err(ResultCode::InvalidArgs);
+        }
+        let agent = ureq::AgentBuilder::new().build();
+        // A one-byte range request both checks that the server supports ranges
+        // and reports the size of the file in its Content-Range header.
+        let response = range_request(&agent, url, 0, 1)?;
+        let size = response
+            .header("Content-Range")
+            .and_then(|range| range.rsplit_once('/'))
+            .and_then(|(_, size)| size.parse().ok())
+            .ok_or_else(|| {
+                log::error!("http vfs: {} did not report its size", url);
+                ResultCode::Error
+            })?;
+        Ok(Self {
+            url: url.to_string(),
+            agent,
+            size,
+            cache: HashMap::new(),
+            clock: 0,
+            next_offset: 0,
+            readahead: 0,
+        })
+    }
+
+    fn read(&mut self, buf: &mut [u8], offset: u64) -> ExtResult<i32> {
+        let end = (offset + buf.len() as u64).min(self.size);
+        if offset >= end {
+            return Ok(0);
+        }
+        self.readahead = if offset == self.next_offset {
+            (self.readahead * 2).clamp(1, MAX_READAHEAD)
+        } else {
+            0
+        };
+        self.next_offset = end;
+        self.clock += 1;
+
+        let first = offset / BLOCK_SIZE;
+        let last = (end - 1) / BLOCK_SIZE;
+        let last_in_file = (self.size - 1) / BLOCK_SIZE;
+        let mut block = first;
+        while block <= last {
+            if let Some(cached) = self.cache.get_mut(&block) {
+                cached.last_used = self.clock;
+                block += 1;
+                continue;
+            }
+            // Fetch the whole run of missing blocks at once, extended by the
+            // read-ahead window when it reaches the end of the read.
+            let mut run_end = block;
+            let limit = (last + self.readahead).min(last_in_file);
+            while run_end < limit && !self.cache.contains_key(&(run_end + 1)) {
+                run_end += 1;
+            }
+            self.fetch(block, run_end)?;
+            block = run_end + 1;
+        }
+
+        let mut pos = offset;
+        while pos < end {
+            let block = self
+                .cache
+                .get(&(pos / BLOCK_SIZE))
+                .ok_or(ResultCode::Internal)?;
+            let start = (pos % BLOCK_SIZE) as usize;
+            let len = (block.data.len() - start).min((end - pos) as usize);
+            let dst = (pos - offset) as usize;
+            buf[dst..dst + len].copy_from_slice(&block.data[start..start + len]);
+            pos += len as u64;
+        }
+        Ok((end - offset) as i32)
+    }
+
+    /// Fetches blocks `first..=last` with a single request and caches them.
+    fn fetch(&mut self, first: u64, last: u64) -> ExtResult<()> {
+        let start = first * BLOCK_SIZE;
+        let end = ((last + 1) * BLOCK_SIZE).min(self.size);
+        let response = range_request(&self.agent, &self.url, start, end)?;
+        let mut data = Vec::with_capacity((end - start) as usize);
+        response
+            .into_reader()
+            .take(end - start)
+            .read_to_end(&mut data)
+            .map_err(|e| {
+                log::error!("http vfs: reading {} failed: {}", self.url, e);
+                ResultCode::Error
+            })?;
+        if data.len() as u64 != end - start {
+            log::error!("http vfs: short read from {}", self.url);
+            return Err(ResultCode::Error);
+        }
+        self.evict((last - first + 1) as usize);
+        for (i, chunk) in data.chunks(BLOCK_SIZE as usize).enumerate() {
+            self.cache.insert(
+                first + i as u64,
+                Block {
+                    data: chunk.to_vec(),
+                    last_used: self.clock,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Makes room for `incoming` blocks by dropping the least recently used ones.
+    fn evict(&mut self, incoming: usize) {
+        let excess = (self.cache.len() + incoming).saturating_sub(CACHE_BLOCKS);
+        if excess == 0 {
+            return;
+        }
+        let mut blocks: Vec<(u64, u64)> = self
+            .cache
+            .iter()
+            .map(|(index, block)| (block.last_used, *index))
+            .collect();
+        blocks.sort_unstable();
+        for (_, index) in blocks.into_iter().take(excess) {
+            self.cache.remove(&index);
+        }
+    }
+}
+
+/// Requests bytes `start..end` of `url`, failing unless the server honours the range.
+fn range_request(
+    agent: &ureq::Agent,
+    url: &str,
+    start: u64,
+    end: u64,
+) -> ExtResult<ureq::Response> {
+    let response = agent
+        .get(url)
+        .set("Range", &format!("bytes={}-{}", start, end - 1))
+        .call()
+        .map_err(|e| {
+            log::error!("http vfs: request to {} failed: {}", url, e);
+            ResultCode::Error
+        })?;
+    if response.status() != 206 {
+        log::error!("http vfs: {} does not support range requests", url);
+        return Err(ResultCode::Unimplemented);
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    /// Serves `data` with support for single range requests, counting requests.
+    fn serve(data: Vec<u8>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/test.db", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut range = None;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("range: bytes=") {
+                        let (start, end) = value.trim().split_once('-').unwrap();
+                        range = Some((
+                            start.parse::<usize>().unwrap(),
+                            end.parse::<usize>().unwrap(),
+                        ));
+                    }
+                    line.clear();
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+                let (start, end) = range.unwrap();
+                let end = end.min(data.len() - 1);
+                let body = &data[start..=end];
+                write!(
+                    stream,
+                    "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    start,
+                    end,
+                    data.len(),
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(body).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    #[test]
+    fn test_reads_match_remote_file() {
+        let data: Vec<u8> = (0..5 * BLOCK_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        let (url, requests) = serve(data.clone());
+        let mut file = RemoteFile::open(&url).unwrap();
+        assert_eq!(file.size, data.len() as u64);
+
+        for (offset, len) in [
+            (0, 100),
+            (4000, 200),
+            (3 * BLOCK_SIZE, 4096),
+            (5 * BLOCK_SIZE, 500),
+        ] {
+            let mut buf = vec![0; len];
+            let n = file.read(&mut buf, offset).unwrap() as usize;
+            let expected = &data[offset as usize..(offset as usize + len).min(data.len())];
+            assert_eq!(&buf[..n], expected);
+        }
+
+        let mut buf = vec![0; data.len()];
+        assert_eq!(file.read(&mut buf, 0).unwrap() as usize, data.len());
+        assert_eq!(buf, data);
+
+        // The whole file is cached now, so reading it again needs no requests.
+        let before = requests.load(Ordering::SeqCst);
+        let mut buf = vec![0; data.len()];
+        file.read(&mut buf, 0).unwrap();
+        assert_eq!(buf, data);
+        assert_eq!(requests.load(Ordering::SeqCst), before);
+    }
+
+    #[test]
+    fn test_writes_are_rejected() {
+        let (url, _) = serve(vec![1; 10]);
+        let mut file = HttpFile::Remote(RemoteFile::open(&url).unwrap());
+        assert!(file.write(&[0], 1, 0).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+const VERSION_FILE: &str = "version";
+
+/// An on-disk cache of the blocks of one object, evicting the least recently
+/// used blocks beyond its capacity. The cache is tied to a version of the
+/// object and starts out empty when the object has changed since it was filled.
+/// Failing to read or write the cache only costs extra requests, so errors are
+/// logged and otherwise ignored.
+pub struct PageCache {
+    dir: Option<PathBuf>,
+    capacity: usize,
+    /// Last use of every cached block.
+    blocks: HashMap<u64, u64>,
+    clock: u64,
+}
+
+impl PageCache {
+    /// A cache that keeps nothing.
+    pub fn disabled() -> Self {
+        Self {
+            dir: None,
+            capacity: 0,
+            blocks: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Opens the cache in `dir` for `version` of the object, holding at most
+    /// `capacity` blocks.
+    pub fn open(dir: PathBuf, version: &str, capacity: usize) -> Self {
+        if capacity == 0 {
+            return Self::disabled();
+        }
+        let mut cache = Self {
+            dir: Some(dir.clone()),
+            capacity,
+            blocks: HashMap::new(),
+            clock: 0,
+        };
+        if fs::read_to_string(dir.join(VERSION_FILE)).ok().as_deref() == Some(version) {
+            if let Ok(entries) = fs::read_dir(&dir) {
+                for entry in entries.flatten() {
+                    if let Ok(index) = entry.file_name().to_string_lossy().parse() {
+                        cache.blocks.insert(index, 0);
+                    }
+                }
+            }
+        } else {
+            let _ = fs::remove_dir_all(&dir);
+            cache.set_version(version);
+        }
+        cache.evict(0);
+        cache
+    }
+
+    fn block_path(dir: &Path, index: u64) -> PathBuf {
+        dir.join(index.to_string())
+    }
+
+    pub fn contains(&self, index: u64) -> bool {
+        self.blocks.contains_key(&index)
+    }
+
+    pub fn get(&mut self, index: u64) -> Option<Vec<u8>> {
+        let dir = self.dir.as_ref()?;
+        let last_used = self.blocks.get_mut(&index)?;
+        self.clock += 1;
+        *last_used = self.clock;
+        match fs::read(Self::block_path(dir, index)) {
+            Ok(data) => Some(data),
+            Err(e) => {
+                log::warn!("object vfs: dropping cached block {}: {}", index, e);
+                self.blocks.remove(&index);
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, index: u64, data: &[u8]) {
+        let Some(dir) = self.dir.clone() else {
+            return;
+        };
+        if !self.blocks.contains_key(&index) {
+            self.evict(1);
+        }
+        if let Err(e) = fs::write(Self::block_path(&dir, index), data) {
+            log::warn!("object vfs: caching block {} failed: {}", index, e);
+            self.blocks.remove(&index);
+            return;
+        }
+        self.clock += 1;
+        self.blocks.insert(index, self.clock);
+    }
+
+    /// Marks the cache as belonging to no version while the object is being
+    /// replaced, so that a crash midway cannot leave stale blocks behind.
+    pub fn invalidate(&mut self) {
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_file(dir.join(VERSION_FILE));
+        }
+    }
+
+    pub fn set_version(&mut self, version: &str) {
+        let Some(dir) = &self.dir else {
+            return;
+        };
+        if let Err(e) =
+            fs::create_dir_all(dir).and_then(|_| fs::write(dir.join(VERSION_FILE), version))
+        {
+            log::warn!(
+                "object vfs: disabling page cache in {}: {}",
+                dir.display(),
+                e
+            );
+            self.dir = None;
+        }
+    }
+
+    /// Makes room for `incoming` blocks by dropping the least recently used ones.
+    fn evict(&mut self, incoming: usize) {
+        let Some(dir) = &self.dir else {
+            return;
+        };
+        let excess = (self.blocks.len() + incoming).saturating_sub(self.capacity);
+        if excess == 0 {
+            return;
+        }
+        let mut blocks: Vec<(u64, u64)> = self
+            .blocks
+            .iter()
+            .map(|(index, last_used)| (*last_used, *index))
+            .collect();
+        blocks.sort_unstable();
+        for (_, index) in blocks.into_iter().take(excess) {
+            let _ = fs::remove_file(Self::block_path(dir, index));
+            self.blocks.remove(&index);
+        }
+    }
+}
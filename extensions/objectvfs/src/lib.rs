@@ -0,0 +1,669 @@
+//! A VFS that keeps the database in object storage, for serverless deployments
+//! where there is no persistent disk.
+//!
+//! Open a database with the `object` VFS and an object URL as the path:
+//!
+//! * `s3://bucket/path/data.db` for Amazon S3 or any S3-compatible store, configured
+//!   with the usual `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, `AWS_SESSION_TOKEN`,
+//!   `AWS_REGION` and `AWS_ENDPOINT_URL` environment variables.
+//! * `gs://bucket/path/data.db` for Google Cloud Storage through its S3-compatible
+//!   API, using HMAC keys in the same variables.
+//! * `file:///path/data.db` for a local directory, mostly useful for testing.
+//!
+//! Other stores can be added by implementing [`ObjectStore`].
+//!
+//! The database file is read in blocks that are kept in an on-disk page cache below
+//! `LIMBO_OBJECT_CACHE_DIR` (a directory in the system temporary directory by
+//! default), holding up to `LIMBO_OBJECT_CACHE_SIZE` MiB (256 by default, 0 disables
+//! it). Changes to the database file are uploaded as a whole object when it is
+//! synced, which happens on checkpoints. The WAL is kept in memory and every sync
+//! uploads the frames written since the previous one as a segment object next to the
+//! database, so that commits are durable without rewriting the database. Opening a
+//! database replays its WAL segments. Only one process may write to a database at a
+//! time.
+mod cache;
+mod s3;
+mod store;
+
+pub use cache::PageCache;
+pub use s3::S3Store;
+pub use store::{LocalStore, ObjectMeta, ObjectStore};
+
+use limbo_ext::{register_extension, ExtResult, ResultCode, VfsDerive, VfsExtension, VfsFile};
+use ring::digest;
+use std::{collections::HashMap, env, fmt::Write, path::PathBuf, sync::Mutex};
+
+register_extension! {
+    vfs: { ObjectVfs },
+}
+
+/// Size of the blocks of the database file that are fetched and cached.
+const BLOCK_SIZE: u64 = 64 * 1024;
+const DEFAULT_CACHE_MIB: u64 = 256;
+/// Number of WAL segments after which the whole WAL is uploaded as a single segment.
+const MAX_WAL_SEGMENTS: usize = 64;
+
+#[derive(VfsDerive, Default)]
+pub struct ObjectVfs;
+
+impl VfsExtension for ObjectVfs {
+    const NAME: &'static str = "object";
+    type File = ObjectFile;
+
+    fn open_file(&self, path: &str, _flags: i32, _direct: bool) -> ExtResult<Self::File> {
+        let (store, key) = open_store(path)?;
+        if path.ends_with("-wal") {
+            return WalFile::open(store, &key).map(ObjectFile::Wal);
+        }
+        DbFile::open(store, &key, |version| cache_for(path, version)).map(ObjectFile::Db)
+    }
+}
+
+/// Splits an object URL into the store holding it and its key.
+fn open_store(path: &str) -> ExtResult<(Box<dyn ObjectStore>, String)> {
+    let Some((scheme, rest)) = path.split_once("://") else {
+        log::error!("object vfs: not an object URL: {}", path);
+        return Err(ResultCode::InvalidArgs);
+    };
+    if scheme == "file" {
+        let path = PathBuf::from(rest);
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return Err(ResultCode::InvalidArgs);
+        };
+        let store = LocalStore::new(dir);
+        return Ok((Box::new(store), name.to_string_lossy().into_owned()));
+    }
+    let Some((bucket, key)) = rest.split_once('/').filter(|(_, key)| !key.is_empty()) else {
+        log::error!("object vfs: missing object key in {}", path);
+        return Err(ResultCode::InvalidArgs);
+    };
+    let store = match scheme {
+        "s3" => S3Store::from_env(bucket, None)?,
+        "gs" => S3Store::from_env(bucket, Some("https://storage.googleapis.com"))?,
+        _ => {
+            log::error!("object vfs: unsupported scheme {}", scheme);
+            return Err(ResultCode::InvalidArgs);
+        }
+    };
+    Ok((Box::new(store), key.to_string()))
+}
+
+/// Opens the page cache configured in the environment for the object at `url`.
+fn cache_for(url: &str, version: &str) -> PageCache {
+    let root = env::var_os("LIMBO_OBJECT_CACHE_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| env::temp_dir().join("limbo-object-cache"));
+    let mib = env::var("LIMBO_OBJECT_CACHE_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_CACHE_MIB);
+    let name = hex(&digest::digest(&digest::SHA256, url.as_bytes()).as_ref()[..16]);
+    PageCache::open(
+        root.join(name),
+        version,
+        (mib * 1024 * 1024 / BLOCK_SIZE) as usize,
+    )
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut out, b| {
+        let _ = write!(out, "{:02x}", b);
+        out
+    })
+}
+
+pub enum ObjectFile {
+    Db(DbFile),
+    Wal(WalFile),
+}
+
+impl VfsFile for ObjectFile {
+    fn read(&mut self, buf: &mut [u8], count: usize, offset: i64) -> ExtResult<i32> {
+        match self {
+            ObjectFile::Db(file) => file.read(&mut buf[..count], offset as u64),
+            ObjectFile::Wal(file) => file.read(&mut buf[..count], offset as u64),
+        }
+    }
+
+    fn write(&mut self, buf: &[u8], count: usize, offset: i64) -> ExtResult<i32> {
+        match self {
+            ObjectFile::Db(file) => file.write(&buf[..count], offset as u64),
+            ObjectFile::Wal(file) => file.write(&buf[..count], offset as u64),
+        }
+    }
+
+    fn sync(&self) -> ExtResult<()> {
+        match self {
+            ObjectFile::Db(file) => file.sync(),
+            ObjectFile::Wal(file) => file.sync(),
+        }
+    }
+
+    fn size(&self) -> i64 {
+        match self {
+            ObjectFile::Db(file) => file.size() as i64,
+            ObjectFile::Wal(file) => file.size() as i64,
+        }
+    }
+
+    fn truncate(&mut self, len: i64) -> ExtResult<()> {
+        match self {
+            ObjectFile::Db(file) => file.truncate(len as u64),
+            ObjectFile::Wal(file) => file.truncate(len as usize),
+        }
+    }
+}
+
+/// The database file, read through the page cache and uploaded whole on sync.
+pub struct DbFile(Mutex<DbInner>);
+
+struct DbInner {
+    store: Box<dyn ObjectStore>,
+    key: String,
+    /// Size of the object in the store.
+    remote_size: u64,
+    /// Size including local writes that have not been synced yet.
+    size: u64,
+    cache: PageCache,
+    /// Blocks written since the last sync.
+    dirty: HashMap<u64, Vec<u8>>,
+    /// Whether the file was truncated since the last sync.
+    truncated: bool,
+}
+
+impl DbFile {
+    pub fn open(
+        store: Box<dyn ObjectStore>,
+        key: &str,
+        cache: impl FnOnce(&str) -> PageCache,
+    ) -> ExtResult<Self> {
+        let meta = store.head(key)?.unwrap_or(ObjectMeta {
+            size: 0,
+            version: String::new(),
+        });
+        Ok(Self(Mutex::new(DbInner {
+            store,
+            key: key.to_string(),
+            remote_size: meta.size,
+            size: meta.size,
+            cache: cache(&meta.version),
+            dirty: HashMap::new(),
+            truncated: false,
+        })))
+    }
+
+    fn read(&mut self, buf: &mut [u8], offset: u64) -> ExtResult<i32> {
+        let inner = self.0.get_mut().map_err(|_| ResultCode::Internal)?;
+        let end = (offset + buf.len() as u64).min(inner.size);
+        if offset >= end {
+            return Ok(0);
+        }
+        let first = offset / BLOCK_SIZE;
+        let blocks = inner.load(first, (end - 1) / BLOCK_SIZE)?;
+        let mut pos = offset;
+        for (i, block) in blocks.iter().enumerate() {
+            let block_start = (first + i as u64) * BLOCK_SIZE;
+            let block_end = (block_start + BLOCK_SIZE).min(end);
+            let dst = &mut buf[(pos - offset) as usize..(block_end - offset) as usize];
+            let src_start = (pos - block_start) as usize;
+            let available = block.len().saturating_sub(src_start).min(dst.len());
+            dst[..available].copy_from_slice(&block[src_start..src_start + available]);
+            // Parts of the file that were never written read as zeroes.
+            dst[available..].fill(0);
+            pos = block_end;
+        }
+        Ok((end - offset) as i32)
+    }
+
+    fn write(&mut self, buf: &[u8], offset: u64) -> ExtResult<i32> {
+        let inner = self.0.get_mut().map_err(|_| ResultCode::Internal)?;
+        let end = offset + buf.len() as u64;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let first = offset / BLOCK_SIZE;
+        let last = (end - 1) / BLOCK_SIZE;
+        for index in first..=last {
+            let block_start = index * BLOCK_SIZE;
+            let mut block = match inner.dirty.remove(&index) {
+                Some(block) => block,
+                None => inner.load(index, index)?.pop().unwrap_or_default(),
+            };
+            block.resize(BLOCK_SIZE as usize, 0);
+            let start = offset.max(block_start);
+            let stop = end.min(block_start + BLOCK_SIZE);
+            block[(start - block_start) as usize..(stop - block_start) as usize]
+                .copy_from_slice(&buf[(start - offset) as usize..(stop - offset) as usize]);
+            inner.dirty.insert(index, block);
+        }
+        inner.size = inner.size.max(end);
+        Ok(buf.len() as i32)
+    }
+
+    fn sync(&self) -> ExtResult<()> {
+        let mut inner = self.0.lock().map_err(|_| ResultCode::Internal)?;
+        if inner.dirty.is_empty() && !inner.truncated {
+            return Ok(());
+        }
+        let size = inner.size;
+        let mut data = Vec::with_capacity(size as usize);
+        if size > 0 {
+            for (i, block) in inner.load(0, (size - 1) / BLOCK_SIZE)?.iter().enumerate() {
+                data.extend_from_slice(block);
+                data.resize((i + 1) * BLOCK_SIZE as usize, 0);
+            }
+        }
+        data.truncate(size as usize);
+        let version = inner.store.put(&inner.key, &data)?;
+
+        let inner = &mut *inner;
+        inner.cache.invalidate();
+        for (index, block) in inner.dirty.drain() {
+            let start = (index * BLOCK_SIZE) as usize;
+            let end = (start + BLOCK_SIZE as usize).min(data.len());
+            inner.cache.insert(index, &block[..end - start]);
+        }
+        inner.cache.set_version(&version);
+        inner.remote_size = size;
+        inner.truncated = false;
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.0.lock().map(|inner| inner.size).unwrap_or(0)
+    }
+
+    fn truncate(&mut self, len: u64) -> ExtResult<()> {
+        let inner = self.0.get_mut().map_err(|_| ResultCode::Internal)?;
+        if len < inner.size && len % BLOCK_SIZE != 0 {
+            // Keep the start of the last block, zeroing the rest so that extending
+            // the file again reads back zeroes.
+            let index = len / BLOCK_SIZE;
+            let mut block = match inner.dirty.remove(&index) {
+                Some(block) => block,
+                None => inner.load(index, index)?.pop().unwrap_or_default(),
+            };
+            block.resize(BLOCK_SIZE as usize, 0);
+            block[(len % BLOCK_SIZE) as usize..].fill(0);
+            inner.dirty.insert(index, block);
+        }
+        inner.dirty.retain(|index, _| index * BLOCK_SIZE < len);
+        // The store keeps the old object until the next sync, but nothing past
+        // the new end may be read back from it.
+        inner.remote_size = inner.remote_size.min(len);
+        inner.size = len;
+        inner.truncated = true;
+        Ok(())
+    }
+}
+
+impl DbInner {
+    /// Returns blocks `first..=last`, fetching the ones that are neither dirty nor
+    /// cached with as few requests as possible. Blocks past the end of the object
+    /// that were never written are returned empty.
+    fn load(&mut self, first: u64, last: u64) -> ExtResult<Vec<Vec<u8>>> {
+        let remote_blocks = self.remote_size.div_ceil(BLOCK_SIZE);
+        let mut blocks = Vec::with_capacity((last - first + 1) as usize);
+        let mut index = first;
+        while index <= last {
+            if let Some(block) = self.dirty.get(&index) {
+                blocks.push(block.clone());
+                index += 1;
+                continue;
+            }
+            if index >= remote_blocks {
+                blocks.push(Vec::new());
+                index += 1;
+                continue;
+            }
+            if let Some(block) = self.cache.get(index) {
+                blocks.push(block);
+                index += 1;
+                continue;
+            }
+            let mut run_end = index;
+            while run_end < last.min(remote_blocks - 1)
+                && !self.dirty.contains_key(&(run_end + 1))
+                && !self.cache.contains(run_end + 1)
+            {
+                run_end += 1;
+            }
+            let start = index * BLOCK_SIZE;
+            let end = ((run_end + 1) * BLOCK_SIZE).min(self.remote_size);
+            let data = self.store.get_range(&self.key, start, end)?;
+            if data.len() as u64 != end - start {
+                log::error!("object vfs: short read from {}", self.key);
+                return Err(ResultCode::Error);
+            }
+            for chunk in data.chunks(BLOCK_SIZE as usize) {
+                self.cache.insert(index, chunk);
+                blocks.push(chunk.to_vec());
+                index += 1;
+            }
+        }
+        Ok(blocks)
+    }
+}
+
+/// The WAL, kept in memory and written back to the store as segments.
+pub struct WalFile(Mutex<WalInner>);
+
+struct WalInner {
+    store: Box<dyn ObjectStore>,
+    /// Prefix of the segment keys.
+    prefix: String,
+    data: Vec<u8>,
+    /// Range written since the last sync.
+    dirty: Option<(usize, usize)>,
+    /// Offsets of the uploaded segments, in ascending order.
+    segments: Vec<usize>,
+}
+
+impl WalFile {
+    /// Opens the WAL stored under `key`, replaying its segments in order of their
+    /// offsets so that later writes win.
+    pub fn open(store: Box<dyn ObjectStore>, key: &str) -> ExtResult<Self> {
+        let prefix = format!("{}/", key);
+        let mut segments: Vec<usize> = store
+            .list(&prefix)?
+            .iter()
+            .filter_map(|key| key[prefix.len()..].parse().ok())
+            .collect();
+        segments.sort_unstable();
+        let mut data = Vec::new();
+        for &offset in &segments {
+            let segment = store.get(&segment_key(&prefix, offset))?;
+            if data.len() < offset + segment.len() {
+                data.resize(offset + segment.len(), 0);
+            }
+            data[offset..offset + segment.len()].copy_from_slice(&segment);
+        }
+        Ok(Self(Mutex::new(WalInner {
+            store,
+            prefix,
+            data,
+            dirty: None,
+            segments,
+        })))
+    }
+
+    fn read(&mut self, buf: &mut [u8], offset: u64) -> ExtResult<i32> {
+        let inner = self.0.get_mut().map_err(|_| ResultCode::Internal)?;
+        let start = (offset as usize).min(inner.data.len());
+        let end = (start + buf.len()).min(inner.data.len());
+        buf[..end - start].copy_from_slice(&inner.data[start..end]);
+        Ok((end - start) as i32)
+    }
+
+    fn write(&mut self, buf: &[u8], offset: u64) -> ExtResult<i32> {
+        let inner = self.0.get_mut().map_err(|_| ResultCode::Internal)?;
+        let start = offset as usize;
+        let end = start + buf.len();
+        if inner.data.len() < end {
+            inner.data.resize(end, 0);
+        }
+        inner.data[start..end].copy_from_slice(buf);
+        inner.dirty = Some(match inner.dirty {
+            Some((lo, hi)) => (lo.min(start), hi.max(end)),
+            None => (start, end),
+        });
+        Ok(buf.len() as i32)
+    }
+
+    fn sync(&self) -> ExtResult<()> {
+        let mut inner = self.0.lock().map_err(|_| ResultCode::Internal)?;
+        let Some((start, end)) = inner.dirty else {
+            return Ok(());
+        };
+        if inner.segments.len() >= MAX_WAL_SEGMENTS {
+            // Replace all segments with a single one holding the whole WAL. The old
+            // segments hold the same data, so they can go once it is uploaded.
+            inner
+                .store
+                .put(&segment_key(&inner.prefix, 0), &inner.data)?;
+            for offset in inner.segments.iter().filter(|&&offset| offset > 0) {
+                inner.store.delete(&segment_key(&inner.prefix, *offset))?;
+            }
+            inner.segments = vec![0];
+            inner.dirty = None;
+            return Ok(());
+        }
+        // Segments are replayed in order of their offsets, so the ones after this
+        // segment would overwrite it with stale data. After a checkpoint the WAL is
+        // rewritten from the start, which drops every old segment.
+        for offset in inner.segments.iter().filter(|&&offset| offset > start) {
+            inner.store.delete(&segment_key(&inner.prefix, *offset))?;
+        }
+        inner
+            .store
+            .put(&segment_key(&inner.prefix, start), &inner.data[start..end])?;
+        inner.segments.retain(|&offset| offset < start);
+        inner.segments.push(start);
+        inner.dirty = None;
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.0
+            .lock()
+            .map(|inner| inner.data.len() as u64)
+            .unwrap_or(0)
+    }
+
+    fn truncate(&mut self, len: usize) -> ExtResult<()> {
+        let inner = self.0.get_mut().map_err(|_| ResultCode::Internal)?;
+        inner.data.resize(len, 0);
+        // Earlier segments may reach past the new end, so the next sync uploads
+        // the whole WAL as the first segment, dropping all others.
+        inner.dirty = Some((0, len));
+        Ok(())
+    }
+}
+
+fn segment_key(prefix: &str, offset: usize) -> String {
+    format!("{}{:020}", prefix, offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    /// An in-memory store that counts the reads it serves.
+    #[derive(Clone, Default)]
+    struct MemoryStore {
+        objects: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+        reads: Arc<AtomicUsize>,
+        puts: Arc<AtomicUsize>,
+    }
+
+    impl ObjectStore for MemoryStore {
+        fn head(&self, key: &str) -> ExtResult<Option<ObjectMeta>> {
+            Ok(self
+                .objects
+                .lock()
+                .unwrap()
+                .get(key)
+                .map(|data| ObjectMeta {
+                    size: data.len() as u64,
+                    version: format!("{}", self.puts.load(Ordering::SeqCst)),
+                }))
+        }
+
+        fn get_range(&self, key: &str, start: u64, end: u64) -> ExtResult<Vec<u8>> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            let objects = self.objects.lock().unwrap();
+            let data = objects.get(key).ok_or(ResultCode::NotFound)?;
+            Ok(data[start as usize..end as usize].to_vec())
+        }
+
+        fn get(&self, key: &str) -> ExtResult<Vec<u8>> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            let objects = self.objects.lock().unwrap();
+            objects.get(key).cloned().ok_or(ResultCode::NotFound)
+        }
+
+        fn put(&self, key: &str, data: &[u8]) -> ExtResult<String> {
+            let version = self.puts.fetch_add(1, Ordering::SeqCst) + 1;
+            self.objects
+                .lock()
+                .unwrap()
+                .insert(key.to_string(), data.to_vec());
+            Ok(format!("{}", version))
+        }
+
+        fn delete(&self, key: &str) -> ExtResult<()> {
+            self.objects.lock().unwrap().remove(key);
+            Ok(())
+        }
+
+        fn list(&self, prefix: &str) -> ExtResult<Vec<String>> {
+            let objects = self.objects.lock().unwrap();
+            Ok(objects
+                .keys()
+                .filter(|key| key.starts_with(prefix))
+                .cloned()
+                .collect())
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("limbo-objectvfs-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn read_all(file: &mut DbFile) -> Vec<u8> {
+        let mut buf = vec![0; file.size() as usize];
+        assert_eq!(file.read(&mut buf, 0).unwrap() as usize, buf.len());
+        buf
+    }
+
+    #[test]
+    fn test_database_is_uploaded_on_sync() {
+        let store = MemoryStore::default();
+        let mut file = DbFile::open(Box::new(store.clone()), "data.db", |_| {
+            PageCache::disabled()
+        })
+        .unwrap();
+        assert_eq!(file.size(), 0);
+
+        let page: Vec<u8> = (0..4096).map(|i| (i % 251) as u8).collect();
+        file.write(&page, 0).unwrap();
+        file.write(&page, 3 * BLOCK_SIZE - 100).unwrap();
+        assert!(store.head("data.db").unwrap().is_none());
+        file.sync().unwrap();
+
+        let remote = store.get("data.db").unwrap();
+        assert_eq!(remote.len() as u64, 3 * BLOCK_SIZE - 100 + 4096);
+        assert_eq!(&remote[..4096], &page[..]);
+        assert!(remote[4096..(3 * BLOCK_SIZE - 100) as usize]
+            .iter()
+            .all(|&b| b == 0));
+        assert_eq!(&remote[(3 * BLOCK_SIZE - 100) as usize..], &page[..]);
+
+        // Overwriting part of a block keeps the rest of it.
+        file.write(&[0xff; 10], 5).unwrap();
+        file.sync().unwrap();
+        let mut reopened = DbFile::open(Box::new(store.clone()), "data.db", |_| {
+            PageCache::disabled()
+        })
+        .unwrap();
+        let mut expected = remote.clone();
+        expected[5..15].fill(0xff);
+        assert_eq!(read_all(&mut reopened), expected);
+        assert_eq!(read_all(&mut file), expected);
+    }
+
+    #[test]
+    fn test_page_cache() {
+        let dir = temp_dir("cache");
+        let store = MemoryStore::default();
+        let data: Vec<u8> = (0..5 * BLOCK_SIZE).map(|i| (i % 253) as u8).collect();
+        store.put("data.db", &data).unwrap();
+
+        let open = |capacity| {
+            DbFile::open(Box::new(store.clone()), "data.db", |version| {
+                PageCache::open(dir.clone(), version, capacity)
+            })
+            .unwrap()
+        };
+        let mut file = open(16);
+        assert_eq!(read_all(&mut file), data);
+        let reads = store.reads.load(Ordering::SeqCst);
+        assert_eq!(reads, 1);
+
+        // A new file starts out with the blocks cached on disk.
+        let mut file = open(16);
+        assert_eq!(read_all(&mut file), data);
+        assert_eq!(store.reads.load(Ordering::SeqCst), reads);
+
+        // Synced writes are cached under the new version of the object.
+        file.write(&[7; 100], BLOCK_SIZE).unwrap();
+        file.sync().unwrap();
+        let mut expected = data.clone();
+        expected[BLOCK_SIZE as usize..BLOCK_SIZE as usize + 100].fill(7);
+        let mut file = open(16);
+        assert_eq!(read_all(&mut file), expected);
+        assert_eq!(store.reads.load(Ordering::SeqCst), reads);
+
+        // The cache is emptied when the object changes behind its back.
+        store.put("data.db", &data).unwrap();
+        let mut file = open(2);
+        assert_eq!(read_all(&mut file), data);
+        assert_eq!(store.reads.load(Ordering::SeqCst), reads + 1);
+        let cached = std::fs::read_dir(&dir).unwrap().count() - 1;
+        assert_eq!(cached, 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_wal_segments() {
+        let store = MemoryStore::default();
+        let open = || WalFile::open(Box::new(store.clone()), "data.db-wal").unwrap();
+        let read_all = |file: &mut WalFile| {
+            let mut buf = vec![0; file.size() as usize];
+            file.read(&mut buf, 0).unwrap();
+            buf
+        };
+
+        let mut wal = open();
+        wal.write(&[1; 32], 0).unwrap();
+        wal.write(&[2; 100], 32).unwrap();
+        wal.sync().unwrap();
+        wal.write(&[3; 100], 132).unwrap();
+        wal.sync().unwrap();
+        assert_eq!(store.list("data.db-wal/").unwrap().len(), 2);
+        let expected = [vec![1; 32], vec![2; 100], vec![3; 100]].concat();
+        assert_eq!(read_all(&mut open()), expected);
+
+        // Restarting the WAL from the beginning replaces every segment.
+        wal.write(&[4; 32], 0).unwrap();
+        wal.sync().unwrap();
+        assert_eq!(
+            store.list("data.db-wal/").unwrap(),
+            ["data.db-wal/00000000000000000000"]
+        );
+        assert_eq!(read_all(&mut open()), [4; 32]);
+
+        // Many small segments are merged into one.
+        for i in 0..MAX_WAL_SEGMENTS {
+            wal.write(&[5], (32 + i) as u64).unwrap();
+            wal.sync().unwrap();
+        }
+        assert!(store.list("data.db-wal/").unwrap().len() < MAX_WAL_SEGMENTS);
+        assert_eq!(read_all(&mut open()), read_all(&mut wal));
+
+        // Truncating drops everything past the new end.
+        wal.truncate(16).unwrap();
+        wal.sync().unwrap();
+        assert_eq!(
+            store.list("data.db-wal/").unwrap(),
+            ["data.db-wal/00000000000000000000"]
+        );
+        assert_eq!(read_all(&mut open()), [4; 16]);
+    }
+}
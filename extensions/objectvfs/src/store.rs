@@ -0,0 +1,136 @@
+use limbo_ext::{ExtResult, ResultCode};
+use std::{
+    fs,
+    io::{ErrorKind, Read, Seek, SeekFrom},
+    path::PathBuf,
+    time::UNIX_EPOCH,
+};
+
+/// Size and version of a stored object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectMeta {
+    pub size: u64,
+    /// Changes whenever the object is rewritten, e.g. the ETag.
+    pub version: String,
+}
+
+/// The operations the VFS needs from an object store. Implement this to add a
+/// new backend.
+pub trait ObjectStore: Send + Sync {
+    /// Returns the size and version of `key`, or `None` if it does not exist.
+    fn head(&self, key: &str) -> ExtResult<Option<ObjectMeta>>;
+    /// Returns bytes `start..end` of `key`.
+    fn get_range(&self, key: &str, start: u64, end: u64) -> ExtResult<Vec<u8>>;
+    /// Returns the whole contents of `key`.
+    fn get(&self, key: &str) -> ExtResult<Vec<u8>>;
+    /// Creates or replaces `key`, returning the version of the new object.
+    fn put(&self, key: &str, data: &[u8]) -> ExtResult<String>;
+    fn delete(&self, key: &str) -> ExtResult<()>;
+    /// Returns the keys that start with `prefix`.
+    fn list(&self, prefix: &str) -> ExtResult<Vec<String>>;
+}
+
+/// Stores objects as files below a local directory, mainly useful for testing
+/// and for mounting buckets through a file system.
+pub struct LocalStore {
+    root: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+fn io_error(key: &str, e: std::io::Error) -> ResultCode {
+    log::error!("object vfs: {}: {}", key, e);
+    match e.kind() {
+        ErrorKind::NotFound => ResultCode::NotFound,
+        ErrorKind::PermissionDenied => ResultCode::PermissionDenied,
+        _ => ResultCode::Error,
+    }
+}
+
+impl ObjectStore for LocalStore {
+    fn head(&self, key: &str) -> ExtResult<Option<ObjectMeta>> {
+        let metadata = match fs::metadata(self.path(key)) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(io_error(key, e)),
+        };
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        Ok(Some(ObjectMeta {
+            size: metadata.len(),
+            version: format!("{}-{}", modified.as_nanos(), metadata.len()),
+        }))
+    }
+
+    fn get_range(&self, key: &str, start: u64, end: u64) -> ExtResult<Vec<u8>> {
+        let mut file = fs::File::open(self.path(key)).map_err(|e| io_error(key, e))?;
+        file.seek(SeekFrom::Start(start))
+            .map_err(|e| io_error(key, e))?;
+        let mut data = Vec::with_capacity((end - start) as usize);
+        file.take(end - start)
+            .read_to_end(&mut data)
+            .map_err(|e| io_error(key, e))?;
+        Ok(data)
+    }
+
+    fn get(&self, key: &str) -> ExtResult<Vec<u8>> {
+        fs::read(self.path(key)).map_err(|e| io_error(key, e))
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> ExtResult<String> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| io_error(key, e))?;
+        }
+        // Write to a temporary file first so that readers never see a partial object.
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, data).map_err(|e| io_error(key, e))?;
+        fs::rename(&tmp, &path).map_err(|e| io_error(key, e))?;
+        self.head(key)?
+            .map(|meta| meta.version)
+            .ok_or(ResultCode::Error)
+    }
+
+    fn delete(&self, key: &str) -> ExtResult<()> {
+        match fs::remove_file(self.path(key)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(io_error(key, e)),
+            _ => Ok(()),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> ExtResult<Vec<String>> {
+        // Keys are paths, so only the directory holding the prefix needs scanning.
+        let (dir, _) = prefix.rsplit_once('/').unwrap_or(("", prefix));
+        let entries = match fs::read_dir(self.path(dir)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(io_error(prefix, e)),
+        };
+        let mut keys = Vec::new();
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let key = if dir.is_empty() {
+                name
+            } else {
+                format!("{}/{}", dir, name)
+            };
+            if key.starts_with(prefix) && entry.path().is_file() {
+                keys.push(key);
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+}
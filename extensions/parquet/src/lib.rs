@@ -0,0 +1,673 @@
+//! A virtual table that reads a [Parquet](https://parquet.apache.org) file:
+//!
+//! ```sql
+//! CREATE VIRTUAL TABLE trips USING parquet('trips.parquet');
+//! SELECT * FROM trips WHERE fare > 20;
+//! ```
+//!
+//! Each top-level field of the file becomes a column, typed INTEGER, REAL, TEXT or BLOB after
+//! its physical and logical types. Comparisons of a column with a value in the WHERE clause
+//! skip the row groups whose statistics show that none of their rows can match.
+use chrono::DateTime;
+use limbo_ext::{
+    register_extension, ConstraintInfo, ConstraintOp, ConstraintUsage, IndexInfo, ResultCode,
+    VTabCursor, VTabKind, VTabModule, VTabModuleDerive, Value, ValueType,
+};
+use parquet::{
+    basic::{LogicalType, Type as PhysicalType},
+    file::{
+        metadata::RowGroupMetaData,
+        reader::FileReader,
+        serialized_reader::{ReadOptionsBuilder, SerializedFileReader},
+        statistics::Statistics,
+    },
+    record::{reader::RowIter, Field, Row},
+    schema::types::{SchemaDescriptor, Type},
+};
+use std::{cmp::Ordering, fs::File};
+
+register_extension! {
+    vtabs: { ParquetVTab }
+}
+
+#[derive(Debug, VTabModuleDerive, Default)]
+struct ParquetVTab;
+
+impl VTabModule for ParquetVTab {
+    type VCursor = ParquetCursor;
+    type Error = String;
+    const NAME: &'static str = "parquet";
+    const VTAB_KIND: VTabKind = VTabKind::VirtualTable;
+
+    fn create_schema(args: &[Value]) -> String {
+        // An invalid schema makes the creation fail.
+        let Some(path) = path_arg(args) else {
+            return String::new();
+        };
+        let Ok(reader) = File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| SerializedFileReader::new(file).map_err(|e| e.to_string()))
+        else {
+            return String::new();
+        };
+        let columns = columns(reader.metadata().file_metadata().schema_descr())
+            .iter()
+            .map(|column| {
+                format!(
+                    "\"{}\" {}",
+                    column.name.replace('"', "\"\""),
+                    column.affinity.as_str()
+                )
+            })
+            .collect::<Vec<_>>();
+        format!("CREATE TABLE x ({})", columns.join(", "))
+    }
+
+    fn open(&self) -> Result<Self::VCursor, Self::Error> {
+        Ok(ParquetCursor {
+            rows: None,
+            row: None,
+            rowid: 0,
+        })
+    }
+
+    /// `args` are the path of the file, followed by the values of the constraints described
+    /// by `idx_str`.
+    fn filter(
+        cursor: &mut Self::VCursor,
+        args: &[Value],
+        idx_info: Option<(&str, i32)>,
+    ) -> ResultCode {
+        let Some(path) = path_arg(args) else {
+            return ResultCode::InvalidArgs;
+        };
+        let Some(constraints) = idx_info.map_or(Some(vec![]), |(idx_str, _)| {
+            parse_constraints(idx_str, args)
+        }) else {
+            return ResultCode::InvalidArgs;
+        };
+        let Ok(file) = File::open(path) else {
+            return ResultCode::Error;
+        };
+        let options = ReadOptionsBuilder::new()
+            .with_predicate(Box::new(move |row_group, _| {
+                row_group_may_match(row_group, &constraints)
+            }))
+            .build();
+        let Ok(reader) = SerializedFileReader::new_with_options(file, options) else {
+            return ResultCode::Error;
+        };
+        let mut rows = RowIter::from_file_into(Box::new(reader));
+        cursor.row = match rows.next() {
+            Some(Ok(row)) => Some(row),
+            Some(Err(_)) => return ResultCode::Error,
+            None => None,
+        };
+        cursor.rows = Some(rows);
+        cursor.rowid = 1;
+        if cursor.row.is_none() {
+            return ResultCode::EOF;
+        }
+        ResultCode::OK
+    }
+
+    /// Uses every constraint to skip row groups. They are checked again on each row.
+    fn best_index(constraints: &[ConstraintInfo]) -> IndexInfo {
+        let idx_str = constraints
+            .iter()
+            .map(|constraint| {
+                let op = match constraint.op {
+                    ConstraintOp::Eq => "eq",
+                    ConstraintOp::Lt => "lt",
+                    ConstraintOp::Le => "le",
+                    ConstraintOp::Gt => "gt",
+                    ConstraintOp::Ge => "ge",
+                };
+                format!("{}:{}", constraint.column_index, op)
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        IndexInfo {
+            constraint_usages: (1..=constraints.len() as u32)
+                .map(|argv_index| ConstraintUsage {
+                    argv_index: Some(argv_index),
+                })
+                .collect(),
+            idx_num: 0,
+            idx_str: Some(idx_str),
+        }
+    }
+
+    fn column(cursor: &Self::VCursor, idx: u32) -> Result<Value, Self::Error> {
+        cursor.column(idx)
+    }
+
+    fn next(cursor: &mut Self::VCursor) -> ResultCode {
+        cursor.next()
+    }
+
+    fn eof(cursor: &Self::VCursor) -> bool {
+        cursor.eof()
+    }
+}
+
+struct ParquetCursor {
+    rows: Option<RowIter<'static>>,
+    row: Option<Row>,
+    rowid: i64,
+}
+
+impl VTabCursor for ParquetCursor {
+    type Error = String;
+
+    fn rowid(&self) -> i64 {
+        self.rowid
+    }
+
+    fn column(&self, idx: u32) -> Result<Value, Self::Error> {
+        let row = self.row.as_ref().ok_or("cursor out of range")?;
+        let (_, field) = row
+            .get_column_iter()
+            .nth(idx as usize)
+            .ok_or("invalid column")?;
+        Ok(field_to_value(field))
+    }
+
+    fn eof(&self) -> bool {
+        self.row.is_none()
+    }
+
+    fn next(&mut self) -> ResultCode {
+        let Some(rows) = self.rows.as_mut() else {
+            return ResultCode::EOF;
+        };
+        match rows.next() {
+            Some(Ok(row)) => {
+                self.row = Some(row);
+                self.rowid += 1;
+                ResultCode::OK
+            }
+            Some(Err(_)) => {
+                self.row = None;
+                ResultCode::Error
+            }
+            None => {
+                self.row = None;
+                ResultCode::EOF
+            }
+        }
+    }
+}
+
+/// The path of the file, the first argument of the table, which may be quoted.
+fn path_arg(args: &[Value]) -> Option<String> {
+    let arg = args.first()?.to_text()?.trim();
+    for quote in ['\'', '"'] {
+        if let Some(unquoted) = arg
+            .strip_prefix(quote)
+            .and_then(|arg| arg.strip_suffix(quote))
+        {
+            return Some(unquoted.replace(&format!("{quote}{quote}"), &quote.to_string()));
+        }
+    }
+    Some(arg.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Affinity {
+    Integer,
+    Real,
+    Text,
+    Blob,
+}
+
+impl Affinity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Affinity::Integer => "INTEGER",
+            Affinity::Real => "REAL",
+            Affinity::Text => "TEXT",
+            Affinity::Blob => "BLOB",
+        }
+    }
+}
+
+/// A column of the table, for a top-level field of the file.
+#[derive(Debug)]
+struct Column {
+    name: String,
+    affinity: Affinity,
+    /// The leaf column of the file holding the statistics of the field, if it is a primitive.
+    leaf: Option<usize>,
+    /// Whether the minimum and maximum of the statistics compare like the values of the column.
+    ordered_stats: bool,
+}
+
+fn columns(schema: &SchemaDescriptor) -> Vec<Column> {
+    schema
+        .root_schema()
+        .get_fields()
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let (affinity, ordered_stats) = field_affinity(field);
+            let leaf = if field.is_primitive() {
+                (0..schema.num_columns()).find(|&leaf| schema.get_column_root_idx(leaf) == i)
+            } else {
+                None
+            };
+            Column {
+                name: field.name().to_string(),
+                affinity,
+                leaf,
+                ordered_stats,
+            }
+        })
+        .collect()
+}
+
+/// The affinity of the column for a field, and whether the field's statistics compare like the
+/// values of the column.
+fn field_affinity(field: &Type) -> (Affinity, bool) {
+    if field.is_group() {
+        // Nested fields are read as their textual representation.
+        return (Affinity::Text, false);
+    }
+    let logical_type = field.get_basic_info().logical_type();
+    match (field.get_physical_type(), logical_type) {
+        (_, Some(LogicalType::Decimal { .. })) => (Affinity::Real, false),
+        (_, Some(LogicalType::Date | LogicalType::Time { .. } | LogicalType::Timestamp { .. })) => {
+            (Affinity::Text, false)
+        }
+        (PhysicalType::BOOLEAN, _) => (Affinity::Integer, false),
+        (PhysicalType::INT32 | PhysicalType::INT64, None) => (Affinity::Integer, true),
+        (
+            PhysicalType::INT32 | PhysicalType::INT64,
+            Some(LogicalType::Integer { is_signed, .. }),
+        ) => (Affinity::Integer, is_signed),
+        (PhysicalType::INT32 | PhysicalType::INT64, _) => (Affinity::Integer, false),
+        (PhysicalType::INT96, _) => (Affinity::Text, false),
+        (PhysicalType::FLOAT | PhysicalType::DOUBLE, _) => (Affinity::Real, true),
+        (PhysicalType::BYTE_ARRAY, Some(LogicalType::String)) => (Affinity::Text, true),
+        (PhysicalType::BYTE_ARRAY, Some(LogicalType::Enum | LogicalType::Json)) => {
+            (Affinity::Text, false)
+        }
+        (PhysicalType::BYTE_ARRAY, None)
+            if field.get_basic_info().converted_type() == parquet::basic::ConvertedType::UTF8 =>
+        {
+            (Affinity::Text, true)
+        }
+        (PhysicalType::FIXED_LEN_BYTE_ARRAY, Some(LogicalType::Float16)) => (Affinity::Real, false),
+        _ => (Affinity::Blob, false),
+    }
+}
+
+fn field_to_value(field: &Field) -> Value {
+    match field {
+        Field::Null => Value::null(),
+        Field::Bool(v) => Value::from_integer(*v as i64),
+        Field::Byte(v) => Value::from_integer(*v as i64),
+        Field::Short(v) => Value::from_integer(*v as i64),
+        Field::Int(v) => Value::from_integer(*v as i64),
+        Field::Long(v) => Value::from_integer(*v),
+        Field::UByte(v) => Value::from_integer(*v as i64),
+        Field::UShort(v) => Value::from_integer(*v as i64),
+        Field::UInt(v) => Value::from_integer(*v as i64),
+        Field::ULong(v) => {
+            i64::try_from(*v).map_or_else(|_| Value::from_float(*v as f64), Value::from_integer)
+        }
+        Field::Float16(v) => Value::from_float(v.to_f64()),
+        Field::Float(v) => Value::from_float(*v as f64),
+        Field::Double(v) => Value::from_float(*v),
+        Field::Decimal(_) => field
+            .to_string()
+            .parse::<f64>()
+            .map_or_else(|_| Value::null(), Value::from_float),
+        Field::Str(v) => Value::from_text(v.clone()),
+        Field::Bytes(v) => Value::from_blob(v.data().to_vec()),
+        Field::Date(days) => DateTime::from_timestamp(*days as i64 * 86_400, 0)
+            .map_or_else(Value::null, |dt| {
+                Value::from_text(dt.format("%Y-%m-%d").to_string())
+            }),
+        Field::TimestampMillis(millis) => DateTime::from_timestamp_millis(*millis)
+            .map_or_else(Value::null, |dt| {
+                Value::from_text(dt.format("%Y-%m-%d %H:%M:%S%.3f").to_string())
+            }),
+        Field::TimestampMicros(micros) => DateTime::from_timestamp_micros(*micros)
+            .map_or_else(Value::null, |dt| {
+                Value::from_text(dt.format("%Y-%m-%d %H:%M:%S%.6f").to_string())
+            }),
+        other => Value::from_text(other.to_string()),
+    }
+}
+
+/// A constraint of the WHERE clause, as passed to [ParquetVTab::filter].
+#[derive(Debug, Clone, PartialEq)]
+struct Constraint {
+    column: usize,
+    op: ConstraintOp,
+    value: ConstraintValue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ConstraintValue {
+    Number(f64),
+    Text(String),
+    /// Values the statistics can't be compared with, like NULL or blobs.
+    Other,
+}
+
+/// Parses the `idx_str` of [ParquetVTab::best_index], whose constraint values are the last
+/// of `args`.
+fn parse_constraints(idx_str: &str, args: &[Value]) -> Option<Vec<Constraint>> {
+    let specs = idx_str
+        .split(',')
+        .filter(|spec| !spec.is_empty())
+        .collect::<Vec<_>>();
+    let values = args.get(args.len().checked_sub(specs.len())?..)?;
+    specs
+        .iter()
+        .zip(values)
+        .map(|(spec, value)| {
+            let (column, op) = spec.split_once(':')?;
+            let op = match op {
+                "eq" => ConstraintOp::Eq,
+                "lt" => ConstraintOp::Lt,
+                "le" => ConstraintOp::Le,
+                "gt" => ConstraintOp::Gt,
+                "ge" => ConstraintOp::Ge,
+                _ => return None,
+            };
+            let value = match value.value_type() {
+                ValueType::Integer => value.to_integer().map_or(ConstraintValue::Other, |v| {
+                    ConstraintValue::Number(v as f64)
+                }),
+                ValueType::Float => value
+                    .to_float()
+                    .map_or(ConstraintValue::Other, ConstraintValue::Number),
+                ValueType::Text => value.to_text().map_or(ConstraintValue::Other, |v| {
+                    ConstraintValue::Text(v.to_string())
+                }),
+                _ => ConstraintValue::Other,
+            };
+            Some(Constraint {
+                column: column.parse().ok()?,
+                op,
+                value,
+            })
+        })
+        .collect()
+}
+
+/// Whether some rows of the row group may satisfy all the constraints, after its statistics.
+fn row_group_may_match(row_group: &RowGroupMetaData, constraints: &[Constraint]) -> bool {
+    if constraints.is_empty() {
+        return true;
+    }
+    let columns = columns(row_group.schema_descr());
+    constraints.iter().all(|constraint| {
+        let Some(column) = columns.get(constraint.column) else {
+            return true;
+        };
+        let Some(stats) = column
+            .leaf
+            .and_then(|leaf| row_group.column(leaf).statistics())
+        else {
+            return true;
+        };
+        // A comparison with NULL is never true.
+        if stats.null_count_opt() == Some(row_group.num_rows() as u64) {
+            return false;
+        }
+        if !column.ordered_stats {
+            return true;
+        }
+        let Some((min, max)) = stats_range(stats, &constraint.value) else {
+            return true;
+        };
+        match constraint.op {
+            ConstraintOp::Eq => min != Ordering::Greater && max != Ordering::Less,
+            ConstraintOp::Lt => min == Ordering::Less,
+            ConstraintOp::Le => min != Ordering::Greater,
+            ConstraintOp::Gt => max == Ordering::Greater,
+            ConstraintOp::Ge => max != Ordering::Less,
+        }
+    })
+}
+
+/// How the minimum and maximum of the statistics compare with the value, if they can be
+/// compared with it.
+fn stats_range(stats: &Statistics, value: &ConstraintValue) -> Option<(Ordering, Ordering)> {
+    fn compare<T: PartialOrd + ?Sized>(
+        min: Option<&T>,
+        max: Option<&T>,
+        value: &T,
+    ) -> Option<(Ordering, Ordering)> {
+        Some((min?.partial_cmp(value)?, max?.partial_cmp(value)?))
+    }
+    match (stats, value) {
+        (Statistics::Int32(s), ConstraintValue::Number(v)) => compare(
+            s.min_opt().map(|min| *min as f64).as_ref(),
+            s.max_opt().map(|max| *max as f64).as_ref(),
+            v,
+        ),
+        (Statistics::Int64(s), ConstraintValue::Number(v)) => compare(
+            s.min_opt().map(|min| *min as f64).as_ref(),
+            s.max_opt().map(|max| *max as f64).as_ref(),
+            v,
+        ),
+        (Statistics::Float(s), ConstraintValue::Number(v)) => compare(
+            s.min_opt().map(|min| *min as f64).as_ref(),
+            s.max_opt().map(|max| *max as f64).as_ref(),
+            v,
+        ),
+        (Statistics::Double(s), ConstraintValue::Number(v)) => compare(s.min_opt(), s.max_opt(), v),
+        // Old writers ordered the deprecated statistics of byte arrays as signed bytes.
+        (Statistics::ByteArray(s), ConstraintValue::Text(v)) if !stats.is_min_max_deprecated() => {
+            compare(
+                s.min_opt().map(|min| min.data()),
+                s.max_opt().map(|max| max.data()),
+                v.as_bytes(),
+            )
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::{
+        data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type},
+        file::{properties::WriterProperties, writer::SerializedFileWriter},
+        schema::parser::parse_message_type,
+    };
+    use std::{path::Path, sync::Arc};
+
+    /// Writes a file with a row group for each id range of `groups`.
+    fn write_file(path: &Path, groups: &[std::ops::Range<i64>]) {
+        let schema = Arc::new(
+            parse_message_type(
+                "message test {
+                    REQUIRED INT64 id;
+                    OPTIONAL DOUBLE score;
+                    OPTIONAL BYTE_ARRAY name (UTF8);
+                    OPTIONAL BYTE_ARRAY data;
+                }",
+            )
+            .unwrap(),
+        );
+        let props = Arc::new(WriterProperties::builder().build());
+        let mut writer =
+            SerializedFileWriter::new(File::create(path).unwrap(), schema, props).unwrap();
+        for ids in groups {
+            let mut row_group = writer.next_row_group().unwrap();
+            let ids = ids.clone().collect::<Vec<_>>();
+            let mut column = row_group.next_column().unwrap().unwrap();
+            column
+                .typed::<Int64Type>()
+                .write_batch(&ids, None, None)
+                .unwrap();
+            column.close().unwrap();
+            let mut column = row_group.next_column().unwrap().unwrap();
+            let scores = ids.iter().map(|id| *id as f64 / 2.0).collect::<Vec<_>>();
+            let def_levels = vec![1; ids.len()];
+            column
+                .typed::<DoubleType>()
+                .write_batch(&scores, Some(&def_levels), None)
+                .unwrap();
+            column.close().unwrap();
+            let mut column = row_group.next_column().unwrap().unwrap();
+            let names = ids
+                .iter()
+                .map(|id| ByteArray::from(format!("name{id:03}").as_str()))
+                .collect::<Vec<_>>();
+            column
+                .typed::<ByteArrayType>()
+                .write_batch(&names, Some(&def_levels), None)
+                .unwrap();
+            column.close().unwrap();
+            // All NULL.
+            let mut column = row_group.next_column().unwrap().unwrap();
+            column
+                .typed::<ByteArrayType>()
+                .write_batch(&[], Some(&vec![0; ids.len()]), None)
+                .unwrap();
+            column.close().unwrap();
+            row_group.close().unwrap();
+        }
+        writer.close().unwrap();
+    }
+
+    fn scan(args: &[Value], idx_str: Option<&str>) -> Vec<Vec<Value>> {
+        let mut cursor = ParquetVTab.open().unwrap();
+        let mut rows = vec![];
+        let mut rc = ParquetVTab::filter(&mut cursor, args, idx_str.map(|s| (s, 0)));
+        while rc == ResultCode::OK {
+            rows.push(
+                (0..4)
+                    .map(|i| ParquetVTab::column(&cursor, i).unwrap())
+                    .collect(),
+            );
+            rc = ParquetVTab::next(&mut cursor);
+        }
+        assert_eq!(rc, ResultCode::EOF);
+        rows
+    }
+
+    fn path_value(path: &Path) -> Value {
+        Value::from_text(format!("'{}'", path.display()))
+    }
+
+    #[test]
+    fn test_create_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("t.parquet");
+        write_file(&path, &[0..2, 2..3]);
+        assert_eq!(
+            ParquetVTab::create_schema(&[path_value(&path)]),
+            "CREATE TABLE x (\"id\" INTEGER, \"score\" REAL, \"name\" TEXT, \"data\" BLOB)"
+        );
+        assert_eq!(
+            ParquetVTab::create_schema(&[Value::from_text("'missing.parquet'".into())]),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("t.parquet");
+        write_file(&path, &[0..2, 2..3]);
+        let rows = scan(&[path_value(&path)], None);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[2][0].to_integer(), Some(2));
+        assert_eq!(rows[2][1].to_float(), Some(1.0));
+        assert_eq!(rows[2][2].to_text(), Some("name002"));
+        assert_eq!(rows[2][3].value_type(), ValueType::Null);
+    }
+
+    #[test]
+    fn test_row_groups_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("t.parquet");
+        write_file(&path, &[0..10, 10..20, 20..30]);
+        let ids = |idx_str: &str, values: Vec<Value>| {
+            let mut args = vec![path_value(&path)];
+            args.extend(values);
+            scan(&args, Some(idx_str))
+                .iter()
+                .map(|row| row[0].to_integer().unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids("0:eq", vec![Value::from_integer(15)]),
+            (10..20).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            ids("0:ge", vec![Value::from_integer(20)]),
+            (20..30).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            ids("0:lt", vec![Value::from_integer(10)]),
+            (0..10).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            ids(
+                "1:gt,0:le",
+                vec![Value::from_float(5.0), Value::from_integer(25)]
+            ),
+            (10..30).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            ids("2:eq", vec![Value::from_text("name025".into())]),
+            (20..30).collect::<Vec<_>>()
+        );
+        assert_eq!(ids("0:gt", vec![Value::from_integer(100)]), vec![]);
+        // Only NULLs.
+        assert_eq!(ids("3:eq", vec![Value::from_blob(vec![1])]), vec![]);
+        // Not comparable with the statistics.
+        assert_eq!(ids("0:eq", vec![Value::from_text("15".into())]).len(), 30);
+        assert_eq!(ids("0:eq", vec![Value::null()]).len(), 30);
+    }
+
+    #[test]
+    fn test_best_index() {
+        let info = ParquetVTab::best_index(&[
+            ConstraintInfo {
+                column_index: 2,
+                op: ConstraintOp::Ge,
+            },
+            ConstraintInfo {
+                column_index: 0,
+                op: ConstraintOp::Eq,
+            },
+        ]);
+        assert_eq!(info.idx_str.as_deref(), Some("2:ge,0:eq"));
+        assert_eq!(
+            info.constraint_usages,
+            vec![
+                ConstraintUsage {
+                    argv_index: Some(1)
+                },
+                ConstraintUsage {
+                    argv_index: Some(2)
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_path_arg() {
+        for arg in ["'a b.parquet'", "\"a b.parquet\"", "a b.parquet"] {
+            assert_eq!(
+                path_arg(&[Value::from_text(arg.into())]).as_deref(),
+                Some("a b.parquet")
+            );
+        }
+        assert_eq!(
+            path_arg(&[Value::from_text("'it''s.parquet'".into())]).as_deref(),
+            Some("it's.parquet")
+        );
+    }
+}
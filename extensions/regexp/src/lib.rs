@@ -1,8 +1,33 @@
 use limbo_ext::{register_extension, scalar, Value, ValueType};
 use regex::Regex;
+use std::cell::RefCell;
+use std::collections::HashMap;
 
 register_extension! {
-    scalars: { regexp, regexp_like, regexp_substr, regexp_replace }
+    scalars: { regexp, regexp_like, regexp_substr, regexp_capture, regexp_replace }
+}
+
+/// How many compiled patterns each thread keeps.
+const PATTERN_CACHE_SIZE: usize = 64;
+
+thread_local! {
+    static PATTERNS: RefCell<HashMap<String, Regex>> = RefCell::new(HashMap::new());
+}
+
+/// Compiles `pattern`, reusing the result for the next rows that use the same one. The cache
+/// is emptied when it is full, as queries rarely use more than a few patterns.
+fn compile(pattern: &str) -> Option<Regex> {
+    PATTERNS.with_borrow_mut(|patterns| {
+        if let Some(re) = patterns.get(pattern) {
+            return Some(re.clone());
+        }
+        let re = Regex::new(pattern).ok()?;
+        if patterns.len() >= PATTERN_CACHE_SIZE {
+            patterns.clear();
+        }
+        patterns.insert(pattern.to_string(), re.clone());
+        Some(re)
+    })
 }
 
 #[scalar(name = "regexp")]
@@ -19,9 +44,8 @@ fn regex(pattern: &Value, haystack: &Value) -> Value {
             let Some(haystack) = haystack.to_text() else {
                 return Value::null();
             };
-            let re = match Regex::new(pattern) {
-                Ok(re) => re,
-                Err(_) => return Value::null(),
+            let Some(re) = compile(pattern) else {
+                return Value::null();
             };
             Value::from_integer(re.is_match(haystack) as i64)
         }
@@ -44,9 +68,8 @@ fn regexp_substr(&self, args: &[Value]) -> Value {
             let Some(pattern) = &args[1].to_text() else {
                 return Value::null();
             };
-            let re = match Regex::new(pattern) {
-                Ok(re) => re,
-                Err(_) => return Value::null(),
+            let Some(re) = compile(pattern) else {
+                return Value::null();
             };
             match re.find(haystack) {
                 Some(mat) => Value::from_text(mat.as_str().to_string()),
@@ -57,6 +80,37 @@ fn regexp_substr(&self, args: &[Value]) -> Value {
     }
 }
 
+/// The text matched by capture group `n` of the first match, the whole match if `n` is
+/// omitted or 0. NULL if nothing matches or the group didn't take part in the match.
+#[scalar(name = "regexp_capture", alias = "regexp_extract")]
+fn regexp_capture(args: &[Value]) -> Value {
+    let (Some(haystack), Some(pattern)) = (
+        args.first().and_then(Value::to_text),
+        args.get(1).and_then(Value::to_text),
+    ) else {
+        return Value::null();
+    };
+    let group = match args.get(2) {
+        Some(group) => match group.to_integer() {
+            Some(group) if group >= 0 => group as usize,
+            _ => return Value::null(),
+        },
+        None => 0,
+    };
+    let Some(re) = compile(pattern) else {
+        return Value::null();
+    };
+    match re
+        .captures(haystack)
+        .and_then(|captures| captures.get(group))
+    {
+        Some(capture) => Value::from_text(capture.as_str().to_string()),
+        None => Value::null(),
+    }
+}
+
+/// Replaces every match of the pattern. `$1` or `${name}` in the replacement stand for
+/// capture groups.
 #[scalar(name = "regexp_replace")]
 fn regexp_replace(&self, args: &[Value]) -> Value {
     let replacement = match args.get(2) {
@@ -73,11 +127,10 @@ fn regexp_replace(&self, args: &[Value]) -> Value {
                 return Value::from_text("".to_string()); // Return an empty string if pattern is not valid
             };
 
-            let re = match Regex::new(&pattern_text) {
-                Ok(re) => re,
-                Err(_) => return Value::from_text("".to_string()), // Return an empty string if regex compilation fails
+            let Some(re) = compile(pattern_text) else {
+                return Value::from_text("".to_string()); // Return an empty string if regex compilation fails
             };
-            Value::from_text(re.replace(&haystack_text, replacement).to_string())
+            Value::from_text(re.replace_all(haystack_text, replacement).to_string())
         }
         _ => Value::from_text("".to_string()), // Return an empty string for invalid value types
     }
@@ -45,7 +45,11 @@ impl VTabModule for GenerateSeriesVTab {
         })
     }
 
-    fn filter(cursor: &mut Self::VCursor, args: &[Value]) -> ResultCode {
+    fn filter(
+        cursor: &mut Self::VCursor,
+        args: &[Value],
+        _idx_info: Option<(&str, i32)>,
+    ) -> ResultCode {
         // args are the start, stop, and step
         if args.is_empty() || args.len() > 3 {
             return ResultCode::InvalidArgs;
@@ -240,7 +244,7 @@ mod tests {
         ];
 
         // Initialize cursor through filter
-        match GenerateSeriesVTab::filter(&mut cursor, &args) {
+        match GenerateSeriesVTab::filter(&mut cursor, &args, None) {
             ResultCode::OK => (),
             ResultCode::EOF => return Ok(vec![]),
             err => return Err(err),
@@ -556,7 +560,7 @@ mod tests {
         ];
 
         // Initialize cursor through filter
-        GenerateSeriesVTab::filter(&mut cursor, &args);
+        GenerateSeriesVTab::filter(&mut cursor, &args, None);
 
         let mut rowids = vec![];
         while !GenerateSeriesVTab::eof(&cursor) {
@@ -0,0 +1,103 @@
+//! Scalar functions used by the [SQLite Archive](https://sqlite.org/sqlar.html) format.
+//!
+//! File contents stored in the `sqlar` table are zlib-compressed only when that
+//! makes them smaller, so readers tell the two cases apart by comparing the stored
+//! blob length with the original size kept in the `sz` column.
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use limbo_ext::{register_extension, scalar, ResultCode, Value, ValueType};
+use std::io::{Read, Write};
+
+register_extension! {
+    scalars: { sqlar_compress, sqlar_uncompress },
+}
+
+/// How many times the length of a compressed blob is preallocated at most for its contents.
+const MAX_PREALLOC_RATIO: usize = 16;
+
+/// Compresses a text or blob value, returning it unchanged if compression would not
+/// make it smaller.
+#[scalar(name = "sqlar_compress")]
+fn sqlar_compress(args: &[Value]) -> Value {
+    if args.len() != 1 {
+        return Value::error(ResultCode::InvalidArgs);
+    }
+    let data = match args[0].value_type() {
+        ValueType::Blob | ValueType::Text => args[0].to_blob().unwrap_or_default(),
+        _ => return copy_value(&args[0]),
+    };
+    match compress(&data) {
+        Some(compressed) if compressed.len() < data.len() => Value::from_blob(compressed),
+        _ => copy_value(&args[0]),
+    }
+}
+
+/// Decompresses a blob produced by `sqlar_compress`, given the original size. Blobs
+/// whose length already equals the original size were stored uncompressed. Fails if the
+/// blob doesn't decompress to exactly that size.
+#[scalar(name = "sqlar_uncompress")]
+fn sqlar_uncompress(args: &[Value]) -> Value {
+    if args.len() != 2 {
+        return Value::error(ResultCode::InvalidArgs);
+    }
+    let Some(sz) = args[1].to_integer() else {
+        return copy_value(&args[0]);
+    };
+    if args[0].value_type() != ValueType::Blob {
+        return copy_value(&args[0]);
+    }
+    let data = args[0].to_blob().unwrap_or_default();
+    if data.len() as i64 == sz {
+        return Value::from_blob(data);
+    }
+    match usize::try_from(sz)
+        .ok()
+        .and_then(|sz| uncompress(&data, sz))
+    {
+        Some(uncompressed) => Value::from_blob(uncompressed),
+        None => Value::error(ResultCode::Corrupt),
+    }
+}
+
+/// Decompresses `data`, which has to decompress to exactly `sz` bytes. `sz` comes from the
+/// archive, so the buffer is also bounded by the length of `data`, and decompressing stops one
+/// byte past `sz` instead of inflating whatever `data` holds.
+fn uncompress(data: &[u8], sz: usize) -> Option<Vec<u8>> {
+    let mut uncompressed = Vec::with_capacity(sz.min(data.len() * MAX_PREALLOC_RATIO));
+    ZlibDecoder::new(data)
+        .take((sz as u64).saturating_add(1))
+        .read_to_end(&mut uncompressed)
+        .ok()?;
+    (uncompressed.len() == sz).then_some(uncompressed)
+}
+
+fn compress(data: &[u8]) -> Option<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).ok()?;
+    encoder.finish().ok()
+}
+
+fn copy_value(value: &Value) -> Value {
+    match value.value_type() {
+        ValueType::Integer => Value::from_integer(value.to_integer().unwrap_or_default()),
+        ValueType::Float => Value::from_float(value.to_float().unwrap_or_default()),
+        ValueType::Text => Value::from_text(value.to_text().unwrap_or_default().to_string()),
+        ValueType::Blob => Value::from_blob(value.to_blob().unwrap_or_default()),
+        ValueType::Null | ValueType::Error => Value::null(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uncompress_checks_size() {
+        let data = b"limbo ".repeat(100);
+        let compressed = compress(&data).unwrap();
+        assert_eq!(uncompress(&compressed, 600), Some(data));
+        assert_eq!(uncompress(&compressed, 599), None);
+        assert_eq!(uncompress(&compressed, 601), None);
+        assert_eq!(uncompress(&compressed, usize::MAX), None);
+        assert_eq!(uncompress(b"limbo", 5000), None);
+    }
+}
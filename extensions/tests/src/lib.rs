@@ -46,7 +46,11 @@ impl VTabModule for KVStoreVTab {
         })
     }
 
-    fn filter(cursor: &mut Self::VCursor, _args: &[Value]) -> ResultCode {
+    fn filter(
+        cursor: &mut Self::VCursor,
+        _args: &[Value],
+        _idx_info: Option<(&str, i32)>,
+    ) -> ResultCode {
         let store = GLOBAL_STORE.lock().unwrap();
         cursor.rows = store
             .iter()
@@ -220,4 +224,8 @@ impl VfsFile for TestFile {
     fn size(&self) -> i64 {
         self.file.metadata().map(|m| m.len() as i64).unwrap_or(-1)
     }
+
+    fn truncate(&mut self, len: i64) -> ExtResult<()> {
+        self.file.set_len(len as u64).map_err(|_| ResultCode::Error)
+    }
 }
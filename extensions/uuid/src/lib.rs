@@ -1,15 +1,25 @@
 use limbo_ext::{register_extension, scalar, ResultCode, Value, ValueType};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 register_extension! {
-    scalars: {uuid4_str, uuid4_blob, uuid7_str, uuid7, uuid7_ts, uuid_str, uuid_blob },
+    scalars: {uuid4_str, uuid_text, uuid4_blob, uuid7_str, uuid7, uuid7_ts, uuid_str, uuid_blob, ulid },
 }
 
+/// The digits of Crockford's base32, which ULIDs are written in.
+const CROCKFORD_BASE32: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
 #[scalar(name = "uuid4_str", alias = "gen_random_uuid")]
 fn uuid4_str(_args: &[Value]) -> Value {
     let uuid = uuid::Uuid::new_v4().to_string();
     Value::from_text(uuid)
 }
 
+/// A random UUID as text, like `uuid()` of SQLite's uuid extension.
+#[scalar(name = "uuid")]
+fn uuid_text(_args: &[Value]) -> Value {
+    Value::from_text(uuid::Uuid::new_v4().to_string())
+}
+
 #[scalar(name = "uuid4")]
 fn uuid4_blob(_args: &[Value]) -> Value {
     let uuid = uuid::Uuid::new_v4();
@@ -99,31 +109,55 @@ fn uuid7_ts(args: &[Value]) -> Value {
     }
 }
 
+/// Formats a UUID given as a blob or as text in any of the forms [uuid::Uuid::parse_str]
+/// accepts.
 #[scalar(name = "uuid_str")]
 fn uuid_str(args: &[Value]) -> Value {
-    let Some(blob) = args[0].to_blob() else {
-        return Value::null();
-    };
-    let parsed = uuid::Uuid::from_slice(blob.as_slice())
-        .ok()
-        .map(|u| u.to_string());
-    match parsed {
-        Some(s) => Value::from_text(s),
+    match parse_uuid(&args[0]) {
+        Some(uuid) => Value::from_text(uuid.to_string()),
         None => Value::null(),
     }
 }
 
 #[scalar(name = "uuid_blob")]
 fn uuid_blob(&self, args: &[Value]) -> Value {
-    let Some(text) = args[0].to_text() else {
-        return Value::null();
-    };
-    match uuid::Uuid::parse_str(text) {
-        Ok(uuid) => Value::from_blob(uuid.as_bytes().to_vec()),
-        Err(_) => Value::null(),
+    match parse_uuid(&args[0]) {
+        Some(uuid) => Value::from_blob(uuid.as_bytes().to_vec()),
+        None => Value::null(),
+    }
+}
+
+fn parse_uuid(value: &Value) -> Option<uuid::Uuid> {
+    match value.value_type() {
+        ValueType::Blob => uuid::Uuid::from_slice(value.to_blob()?.as_slice()).ok(),
+        ValueType::Text => uuid::Uuid::parse_str(value.to_text()?).ok(),
+        _ => None,
     }
 }
 
+/// A [ULID](https://github.com/ulid/spec): 48 bits of milliseconds since the epoch followed
+/// by 80 random bits, as 26 characters of Crockford's base32, so that ULIDs sort by the
+/// time they were made.
+#[scalar(name = "ulid")]
+fn ulid(_args: &[Value]) -> Value {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64);
+    let mut random = [0u8; 16];
+    if getrandom::getrandom(&mut random[6..]).is_err() {
+        return Value::error(ResultCode::Error);
+    }
+    random[..6].copy_from_slice(&millis.to_be_bytes()[2..]);
+    Value::from_text(encode_ulid(u128::from_be_bytes(random)))
+}
+
+fn encode_ulid(value: u128) -> String {
+    (0..26)
+        .rev()
+        .map(|i| CROCKFORD_BASE32[(value >> (i * 5)) as usize & 31] as char)
+        .collect()
+}
+
 #[inline(always)]
 fn uuid_to_unix(uuid: &[u8; 16]) -> u64 {
     ((uuid[0] as u64) << 40)
@@ -133,3 +167,17 @@ fn uuid_to_unix(uuid: &[u8; 16]) -> u64 {
         | ((uuid[4] as u64) << 8)
         | (uuid[5] as u64)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_ulid() {
+        assert_eq!(encode_ulid(0), "00000000000000000000000000");
+        assert_eq!(encode_ulid(u128::MAX), "7ZZZZZZZZZZZZZZZZZZZZZZZZZ");
+        // The example of the ULID spec, whose timestamp is 1469918176385.
+        let ulid = (1469918176385u128 << 80) | 0x6F4_B1C9_5C8A_59F2_D6A2;
+        assert_eq!(&encode_ulid(ulid)[..10], "01ARYZ6S41");
+    }
+}
@@ -0,0 +1,41 @@
+#![no_main]
+use std::{error::Error, sync::Arc};
+
+use libfuzzer_sys::fuzz_target;
+use limbo_core::{Database, LimboError, PlatformIO};
+
+/// Opens the input as an untrusted database file and reads every table in it. Errors are
+/// fine, panics are not.
+fn do_fuzz(data: &[u8]) -> Result<(), Box<dyn Error>> {
+    let path = std::env::temp_dir().join(format!("limbo-fuzz-untrusted-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(path.with_extension("db-wal"));
+    std::fs::write(&path, data)?;
+
+    let io = Arc::new(PlatformIO::new()?);
+    let db = Database::open_untrusted(io, path.to_str().unwrap())?;
+    let conn = db.connect()?;
+
+    let mut tables = vec!["sqlite_schema".to_string()];
+    {
+        let mut stmt = conn.prepare("SELECT name FROM sqlite_schema WHERE type = 'table'")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            tables.push(row.get::<String>(0)?);
+        }
+    }
+    for table in tables {
+        let sql = format!("SELECT * FROM \"{}\"", table.replace('"', "\"\""));
+        let mut stmt = match conn.prepare(sql) {
+            Ok(stmt) => stmt,
+            Err(LimboError::ParseError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let mut rows = stmt.query([])?;
+        while rows.next()?.is_some() {}
+    }
+    Ok(())
+}
+
+fuzz_target!(|data: &[u8]| {
+    let _ = do_fuzz(data);
+});
@@ -371,7 +371,7 @@ pub fn derive_agg_func(input: TokenStream) -> TokenStream {
 ///       CsvCursor { rows, index: 0 }
 ///   }
 ///   /// Filter the virtual table based on arguments (omitted here for simplicity)
-///   fn filter(_cursor: &mut Self::VCursor, _arg_count: i32, _args: &[Value]) -> ResultCode {
+///   fn filter(_cursor: &mut Self::VCursor, _args: &[Value], _idx_info: Option<(&str, i32)>) -> ResultCode {
 ///       ResultCode::OK
 ///   }
 ///   /// Return the value for a given column index
@@ -450,6 +450,7 @@ pub fn derive_vtab_module(input: TokenStream) -> TokenStream {
     let eof_fn_name = format_ident!("eof_{}", struct_name);
     let update_fn_name = format_ident!("update_{}", struct_name);
     let rowid_fn_name = format_ident!("rowid_{}", struct_name);
+    let best_index_fn_name = format_ident!("best_index_{}", struct_name);
 
     let expanded = quote! {
         impl #struct_name {
@@ -485,13 +486,41 @@ pub fn derive_vtab_module(input: TokenStream) -> TokenStream {
                 cursor: *const ::std::ffi::c_void,
                 argc: i32,
                 argv: *const ::limbo_ext::Value,
+                idx_str: *const ::std::ffi::c_char,
+                idx_num: i32,
             ) -> ::limbo_ext::ResultCode {
                 if cursor.is_null() {
                     return ::limbo_ext::ResultCode::Error;
                 }
                 let cursor = unsafe { &mut *(cursor as *mut <#struct_name as ::limbo_ext::VTabModule>::VCursor) };
-                let args = ::std::slice::from_raw_parts(argv, argc as usize);
-                <#struct_name as ::limbo_ext::VTabModule>::filter(cursor, args)
+                let args = if argv.is_null() {
+                    &[]
+                } else {
+                    ::std::slice::from_raw_parts(argv, argc as usize)
+                };
+                let idx_str = if idx_str.is_null() {
+                    None
+                } else {
+                    ::std::ffi::CStr::from_ptr(idx_str).to_str().ok()
+                };
+                <#struct_name as ::limbo_ext::VTabModule>::filter(
+                    cursor,
+                    args,
+                    idx_str.map(|idx_str| (idx_str, idx_num)),
+                )
+            }
+
+            #[no_mangle]
+            unsafe extern "C" fn #best_index_fn_name(
+                constraints: *const ::limbo_ext::ConstraintInfo,
+                n_constraints: i32,
+            ) -> ::limbo_ext::ExtIndexInfo {
+                let constraints = if constraints.is_null() {
+                    &[]
+                } else {
+                    ::std::slice::from_raw_parts(constraints, n_constraints as usize)
+                };
+                <#struct_name as ::limbo_ext::VTabModule>::best_index(constraints).to_ffi()
             }
 
             #[no_mangle]
@@ -614,6 +643,7 @@ pub fn derive_vtab_module(input: TokenStream) -> TokenStream {
                     eof: Self::#eof_fn_name,
                     update: Self::#update_fn_name,
                     rowid: Self::#rowid_fn_name,
+                    best_index: Self::#best_index_fn_name,
                 };
                 (api.register_vtab_module)(api.ctx, name_c, module, <#struct_name as ::limbo_ext::VTabModule>::VTAB_KIND)
             }
@@ -637,6 +667,7 @@ pub fn derive_vfs_module(input: TokenStream) -> TokenStream {
     let unlock_fn_name = format_ident!("{}_unlock", struct_name);
     let sync_fn_name = format_ident!("{}_sync", struct_name);
     let size_fn_name = format_ident!("{}_size", struct_name);
+    let truncate_fn_name = format_ident!("{}_truncate", struct_name);
     let run_once_fn_name = format_ident!("{}_run_once", struct_name);
     let generate_random_number_fn_name = format_ident!("{}_generate_random_number", struct_name);
     let get_current_time_fn_name = format_ident!("{}_get_current_time", struct_name);
@@ -658,6 +689,7 @@ pub fn derive_vfs_module(input: TokenStream) -> TokenStream {
                 unlock: #unlock_fn_name,
                 sync: #sync_fn_name,
                 size: #size_fn_name,
+                truncate: #truncate_fn_name,
                 run_once: #run_once_fn_name,
                 gen_random_number: #generate_random_number_fn_name,
                 current_time: #get_current_time_fn_name,
@@ -681,6 +713,7 @@ pub fn derive_vfs_module(input: TokenStream) -> TokenStream {
                 unlock: #unlock_fn_name,
                 sync: #sync_fn_name,
                 size: #size_fn_name,
+                truncate: #truncate_fn_name,
                 run_once: #run_once_fn_name,
                 gen_random_number: #generate_random_number_fn_name,
                 current_time: #get_current_time_fn_name,
@@ -821,6 +854,20 @@ pub fn derive_vfs_module(input: TokenStream) -> TokenStream {
             <#struct_name as ::limbo_ext::VfsExtension>::File::size(file)
         }
 
+        #[no_mangle]
+        pub unsafe extern "C" fn #truncate_fn_name(file_ptr: *const ::std::ffi::c_void, len: i64) -> ::limbo_ext::ResultCode {
+            if file_ptr.is_null() {
+                return ::limbo_ext::ResultCode::Error;
+            }
+            let vfs_file: &mut ::limbo_ext::VfsFileImpl = &mut *(file_ptr as *mut ::limbo_ext::VfsFileImpl);
+            let file: &mut <#struct_name as ::limbo_ext::VfsExtension>::File =
+                &mut *(vfs_file.file as *mut <#struct_name as ::limbo_ext::VfsExtension>::File);
+            if let Err(e) = <#struct_name as ::limbo_ext::VfsExtension>::File::truncate(file, len) {
+                return e;
+            }
+            ::limbo_ext::ResultCode::OK
+        }
+
         #[no_mangle]
         pub unsafe extern "C" fn #generate_random_number_fn_name() -> i64 {
             let obj = #struct_name::default();
@@ -939,6 +986,15 @@ pub fn register_extension(input: TokenStream) -> TokenStream {
             }
         }
     });
+    let capabilities = [
+        (!scalars.is_empty(), quote! { SCALARS }),
+        (!aggregates.is_empty(), quote! { AGGREGATES }),
+        (!vtabs.is_empty(), quote! { VTABS }),
+        (!vfs.is_empty(), quote! { VFS }),
+    ]
+    .into_iter()
+    .filter(|(used, _)| *used)
+    .map(|(_, capability)| capability);
     let static_aggregates = aggregate_calls.clone();
     let static_scalars = scalar_calls.clone();
     let static_vtabs = vtab_calls.clone();
@@ -963,9 +1019,23 @@ pub fn register_extension(input: TokenStream) -> TokenStream {
                 ::limbo_ext::ResultCode::OK
               }
 
+            #[cfg(not(feature = "static"))]
+            #[no_mangle]
+            pub unsafe extern "C" fn extension_info() -> ::limbo_ext::ExtensionInfo {
+                ::limbo_ext::ExtensionInfo {
+                    api_version: ::limbo_ext::EXTENSION_API_VERSION,
+                    capabilities: ::limbo_ext::Capabilities::NONE
+                        #(.union(::limbo_ext::Capabilities::#capabilities))*,
+                }
+            }
+
             #[cfg(not(feature = "static"))]
             #[no_mangle]
             pub unsafe extern "C" fn register_extension(api: &::limbo_ext::ExtensionApi) -> ::limbo_ext::ResultCode {
+                if api.version != ::limbo_ext::EXTENSION_API_VERSION {
+                    return ::limbo_ext::ResultCode::Unimplemented;
+                }
+
                 #(#scalar_calls)*
 
                 #(#aggregate_calls)*
@@ -980,3 +1050,79 @@ pub fn register_extension(input: TokenStream) -> TokenStream {
 
     TokenStream::from(expanded)
 }
+
+/// Derives `limbo_core::FromRow` for a struct with named fields. Each field is read from the
+/// result column with the same name, or the one given with `#[column(name = "...")]`, and its
+/// type must implement `limbo_core::FromValueRow`.
+///
+/// ```ignore
+/// #[derive(FromRow)]
+/// struct User {
+///     id: i64,
+///     #[column(name = "user_name")]
+///     name: String,
+///     email: Option<String>,
+/// }
+///
+/// let mut stmt = conn.prepare("SELECT id, user_name, email FROM users")?;
+/// for user in stmt.query_as::<User>()? {
+///     println!("{}", user?.name);
+/// }
+/// ```
+#[proc_macro_derive(FromRow, attributes(column))]
+pub fn derive_from_row(input: TokenStream) -> TokenStream {
+    let derive_input = parse_macro_input!(input as DeriveInput);
+    let struct_name = &derive_input.ident;
+    let fields = match &derive_input.data {
+        syn::Data::Struct(syn::DataStruct {
+            fields: syn::Fields::Named(fields),
+            ..
+        }) => &fields.named,
+        _ => {
+            return syn::Error::new_spanned(
+                struct_name,
+                "FromRow can only be derived for structs with named fields",
+            )
+            .to_compile_error()
+            .into();
+        }
+    };
+
+    let mut columns = Vec::new();
+    let mut field_values = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let ident = field.ident.as_ref().unwrap();
+        let mut column = ident.to_string().trim_start_matches("r#").to_string();
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("column")) {
+            let parsed = attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("name") {
+                    column = meta.value()?.parse::<syn::LitStr>()?.value();
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `name = \"...\"`"))
+                }
+            });
+            if let Err(err) = parsed {
+                return err.to_compile_error().into();
+            }
+        }
+        columns.push(column);
+        let ty = &field.ty;
+        field_values.push(quote! { #ident: row.get::<#ty>(columns[#i])? });
+    }
+
+    let (impl_generics, ty_generics, where_clause) = derive_input.generics.split_for_impl();
+    let expanded = quote! {
+        impl #impl_generics ::limbo_core::FromRow for #struct_name #ty_generics #where_clause {
+            const COLUMNS: &'static [&'static str] = &[#(#columns),*];
+
+            fn from_row(row: &::limbo_core::Row, columns: &[usize]) -> ::limbo_core::Result<Self> {
+                Ok(Self {
+                    #(#field_values),*
+                })
+            }
+        }
+    };
+
+    TokenStream::from(expanded)
+}
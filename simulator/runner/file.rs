@@ -112,6 +112,19 @@ impl File for SimulatorFile {
     fn size(&self) -> Result<u64> {
         self.inner.size()
     }
+
+    fn truncate(&self, len: usize) -> Result<()> {
+        if *self.fault.borrow() {
+            return Err(limbo_core::LimboError::InternalError(
+                "Injected fault".into(),
+            ));
+        }
+        self.inner.truncate(len)
+    }
+
+    fn device_characteristics(&self) -> limbo_core::DeviceCharacteristics {
+        self.inner.device_characteristics()
+    }
 }
 
 impl Drop for SimulatorFile {
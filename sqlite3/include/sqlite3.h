@@ -17,8 +17,12 @@
 
 #define SQLITE_CANTOPEN 14
 
+#define SQLITE_CONSTRAINT 19
+
 #define SQLITE_MISUSE 21
 
+#define SQLITE_RANGE 25
+
 #define SQLITE_ROW 100
 
 #define SQLITE_DONE 101
@@ -37,6 +41,18 @@
 #define SQLITE_CHECKPOINT_RESTART 2
 #define SQLITE_CHECKPOINT_TRUNCATE 3
 
+/* Fundamental datatypes */
+#define SQLITE_INTEGER 1
+#define SQLITE_FLOAT 2
+#define SQLITE_TEXT 3
+#define SQLITE_BLOB 4
+#define SQLITE_NULL 5
+
+/* Destructors for bound text and blobs */
+typedef void (*sqlite3_destructor_type)(void *);
+#define SQLITE_STATIC ((sqlite3_destructor_type)0)
+#define SQLITE_TRANSIENT ((sqlite3_destructor_type)-1)
+
 typedef struct sqlite3 sqlite3;
 
 typedef struct sqlite3_stmt sqlite3_stmt;
@@ -70,13 +86,17 @@ int sqlite3_set_authorizer(sqlite3 *_db, int (*_callback)(void), void *_context)
 
 void *sqlite3_context_db_handle(void *_context);
 
-int sqlite3_prepare_v2(sqlite3 *db, const char *sql, int _len, sqlite3_stmt **out_stmt, const char **_tail);
+int sqlite3_prepare_v2(sqlite3 *db, const char *sql, int len, sqlite3_stmt **out_stmt, const char **tail);
 
 int sqlite3_finalize(sqlite3_stmt *stmt);
 
 int sqlite3_step(sqlite3_stmt *stmt);
 
-int sqlite3_exec(sqlite3 *db, const char *sql, int (*_callback)(void), void *_context, char **_err);
+int sqlite3_exec(sqlite3 *db,
+                 const char *sql,
+                 int (*callback)(void *context, int n_column, char **argv, char **colv),
+                 void *context,
+                 char **err);
 
 int sqlite3_reset(sqlite3_stmt *stmt);
 
@@ -106,9 +126,9 @@ void sqlite3_sleep(int _ms);
 
 int sqlite3_limit(sqlite3 *_db, int _id, int _new_value);
 
-void *sqlite3_malloc64(int _n);
+void *sqlite3_malloc64(uint64_t n);
 
-void sqlite3_free(void *_ptr);
+void sqlite3_free(void *ptr);
 
 int sqlite3_errcode(sqlite3 *_db);
 
@@ -130,35 +150,39 @@ char *sqlite3_expanded_sql(sqlite3_stmt *_stmt);
 
 int sqlite3_data_count(sqlite3_stmt *stmt);
 
-int sqlite3_bind_parameter_count(sqlite3_stmt *_stmt);
+int sqlite3_bind_parameter_count(sqlite3_stmt *stmt);
 
-const char *sqlite3_bind_parameter_name(sqlite3_stmt *_stmt, int _idx);
+const char *sqlite3_bind_parameter_name(sqlite3_stmt *stmt, int idx);
 
-int sqlite3_bind_null(sqlite3_stmt *_stmt, int _idx);
+int sqlite3_bind_null(sqlite3_stmt *stmt, int idx);
 
-int sqlite3_bind_int64(sqlite3_stmt *_stmt, int _idx, int64_t _val);
+int sqlite3_bind_int(sqlite3_stmt *stmt, int idx, int val);
 
-int sqlite3_bind_double(sqlite3_stmt *_stmt, int _idx, double _val);
+int sqlite3_bind_int64(sqlite3_stmt *stmt, int idx, int64_t val);
 
-int sqlite3_bind_text(sqlite3_stmt *_stmt, int _idx, const char *_text, int _len, void *_destroy);
+int sqlite3_bind_double(sqlite3_stmt *stmt, int idx, double val);
 
-int sqlite3_bind_blob(sqlite3_stmt *_stmt, int _idx, const void *_blob, int _len, void *_destroy);
+int sqlite3_bind_text(sqlite3_stmt *stmt, int idx, const char *text, int len, void *destroy);
 
-int sqlite3_column_type(sqlite3_stmt *_stmt, int _idx);
+int sqlite3_bind_blob(sqlite3_stmt *stmt, int idx, const void *blob, int len, void *destroy);
 
-int sqlite3_column_count(sqlite3_stmt *_stmt);
+int sqlite3_column_type(sqlite3_stmt *stmt, int idx);
+
+int sqlite3_column_count(sqlite3_stmt *stmt);
 
 const char *sqlite3_column_decltype(sqlite3_stmt *_stmt, int _idx);
 
-const char *sqlite3_column_name(sqlite3_stmt *_stmt, int _idx);
+const char *sqlite3_column_name(sqlite3_stmt *stmt, int idx);
+
+int sqlite3_column_int(sqlite3_stmt *stmt, int idx);
 
-int64_t sqlite3_column_int64(sqlite3_stmt *_stmt, int _idx);
+int64_t sqlite3_column_int64(sqlite3_stmt *stmt, int idx);
 
-double sqlite3_column_double(sqlite3_stmt *_stmt, int _idx);
+double sqlite3_column_double(sqlite3_stmt *stmt, int idx);
 
-const void *sqlite3_column_blob(sqlite3_stmt *_stmt, int _idx);
+const void *sqlite3_column_blob(sqlite3_stmt *stmt, int idx);
 
-int sqlite3_column_bytes(sqlite3_stmt *_stmt, int _idx);
+int sqlite3_column_bytes(sqlite3_stmt *stmt, int idx);
 
 int sqlite3_value_type(void *value);
 
@@ -242,6 +266,8 @@ const char *sqlite3_errmsg(sqlite3 *_db);
 
 int sqlite3_extended_errcode(sqlite3 *_db);
 
+int sqlite3_extended_result_codes(sqlite3 *db, int onoff);
+
 int sqlite3_complete(const char *_sql);
 
 int sqlite3_threadsafe(void);
@@ -1,9 +1,10 @@
 #![allow(clippy::missing_safety_doc)]
 #![allow(non_camel_case_types)]
 
-use limbo_core::OwnedValue;
+use limbo_core::{CheckpointMode, OwnedValue};
 use log::trace;
 use std::ffi::{self, CStr, CString};
+use std::num::NonZero;
 
 use std::rc::Rc;
 use std::sync::Arc;
@@ -22,7 +23,9 @@ pub const SQLITE_NOMEM: ffi::c_int = 7;
 pub const SQLITE_INTERRUPT: ffi::c_int = 9;
 pub const SQLITE_NOTFOUND: ffi::c_int = 12;
 pub const SQLITE_CANTOPEN: ffi::c_int = 14;
+pub const SQLITE_CONSTRAINT: ffi::c_int = 19;
 pub const SQLITE_MISUSE: ffi::c_int = 21;
+pub const SQLITE_RANGE: ffi::c_int = 25;
 pub const SQLITE_ROW: ffi::c_int = 100;
 pub const SQLITE_DONE: ffi::c_int = 101;
 pub const SQLITE_ABORT_ROLLBACK: ffi::c_int = SQLITE_ABORT | (2 << 8);
@@ -35,6 +38,12 @@ pub const SQLITE_CHECKPOINT_FULL: ffi::c_int = 1;
 pub const SQLITE_CHECKPOINT_RESTART: ffi::c_int = 2;
 pub const SQLITE_CHECKPOINT_TRUNCATE: ffi::c_int = 3;
 
+pub const SQLITE_INTEGER: ffi::c_int = 1;
+pub const SQLITE_FLOAT: ffi::c_int = 2;
+pub const SQLITE_TEXT: ffi::c_int = 3;
+pub const SQLITE_BLOB: ffi::c_int = 4;
+pub const SQLITE_NULL: ffi::c_int = 5;
+
 pub mod util;
 
 use util::sqlite3_safety_check_sick_or_ok;
@@ -46,7 +55,7 @@ pub struct sqlite3 {
     pub(crate) err_mask: ffi::c_int,
     pub(crate) malloc_failed: bool,
     pub(crate) e_open_state: u8,
-    pub(crate) p_err: *mut ffi::c_void,
+    pub(crate) err_msg: Option<CString>,
 }
 
 impl sqlite3 {
@@ -55,21 +64,77 @@ impl sqlite3 {
             _db: db,
             conn,
             err_code: SQLITE_OK,
-            err_mask: 0xFFFFFFFFu32 as i32,
+            err_mask: 0xFF,
             malloc_failed: false,
             e_open_state: SQLITE_STATE_OPEN,
-            p_err: std::ptr::null_mut(),
+            err_msg: None,
         }
     }
+
+    /// Records the error for sqlite3_errcode() and sqlite3_errmsg(), returning the result code
+    /// the failed call returns.
+    fn set_error(&mut self, err: &limbo_core::LimboError) -> ffi::c_int {
+        let err = limbo_core::SqliteError::from(err);
+        self.err_code = err.extended_code as ffi::c_int;
+        self.err_msg = CString::new(err.message).ok();
+        self.err_code & self.err_mask
+    }
 }
 
 pub struct sqlite3_stmt {
+    pub(crate) db: *mut sqlite3,
     pub(crate) stmt: limbo_core::Statement,
+    /// NUL-terminated strings handed out to the caller, which stay valid until the statement is
+    /// finalized (names) or stepped again (column text).
+    pub(crate) column_names: Vec<Option<CString>>,
+    pub(crate) parameter_names: Vec<Option<CString>>,
+    pub(crate) column_text: Vec<Option<Vec<u8>>>,
 }
 
 impl sqlite3_stmt {
-    pub fn new(stmt: limbo_core::Statement) -> Self {
-        Self { stmt }
+    pub fn new(db: *mut sqlite3, stmt: limbo_core::Statement) -> Self {
+        Self {
+            db,
+            stmt,
+            column_names: Vec::new(),
+            parameter_names: Vec::new(),
+            column_text: Vec::new(),
+        }
+    }
+
+    fn column_value(&self, idx: ffi::c_int) -> Option<&OwnedValue> {
+        let row = self.stmt.row()?;
+        let idx = usize::try_from(idx).ok()?;
+        if idx >= row.len() {
+            return None;
+        }
+        Some(row.get_value(idx))
+    }
+
+    /// Converts the column to text the way sqlite3_column_text() does and returns a
+    /// NUL-terminated copy that lives until the next step.
+    fn column_text(&mut self, idx: ffi::c_int) -> Option<&[u8]> {
+        let mut text = match self.column_value(idx)? {
+            OwnedValue::Null => return None,
+            OwnedValue::Text(text) => text.as_str().as_bytes().to_vec(),
+            OwnedValue::Blob(blob) => blob.clone(),
+            value => value.to_string().into_bytes(),
+        };
+        text.push(0);
+        let idx = idx as usize;
+        if self.column_text.len() <= idx {
+            self.column_text.resize(idx + 1, None);
+        }
+        Some(self.column_text[idx].insert(text))
+    }
+
+    fn bind(&mut self, idx: ffi::c_int, value: OwnedValue) -> ffi::c_int {
+        let index = match usize::try_from(idx).ok().and_then(NonZero::new) {
+            Some(index) if index.get() <= self.stmt.parameters_count() => index,
+            _ => return SQLITE_RANGE,
+        };
+        self.stmt.bind_at(index, value);
+        SQLITE_OK
     }
 }
 
@@ -89,7 +154,6 @@ pub unsafe extern "C" fn sqlite3_shutdown() -> ffi::c_int {
 }
 
 #[no_mangle]
-#[allow(clippy::arc_with_non_send_sync)]
 pub unsafe extern "C" fn sqlite3_open(
     filename: *const ffi::c_char,
     db_out: *mut *mut sqlite3,
@@ -110,19 +174,13 @@ pub unsafe extern "C" fn sqlite3_open(
         Ok(s) => s,
         Err(_) => return SQLITE_MISUSE,
     };
-    let io: Arc<dyn limbo_core::IO> = match filename {
-        ":memory:" => Arc::new(limbo_core::MemoryIO::new()),
-        _ => match limbo_core::PlatformIO::new() {
-            Ok(io) => Arc::new(io),
-            Err(_) => return SQLITE_CANTOPEN,
-        },
-    };
-    match limbo_core::Database::open_file(io, filename, false) {
-        Ok(db) => {
+    match limbo_core::Database::open_uri(filename, false) {
+        Ok((_io, db)) => {
             let conn = db.connect().unwrap();
             *db_out = Box::leak(Box::new(sqlite3::new(db, conn)));
             SQLITE_OK
         }
+        Err(limbo_core::LimboError::Busy) => SQLITE_BUSY,
         Err(e) => {
             log::error!("error opening database {:?}", e);
             SQLITE_CANTOPEN
@@ -202,25 +260,41 @@ pub unsafe extern "C" fn sqlite3_context_db_handle(_context: *mut ffi::c_void) -
 pub unsafe extern "C" fn sqlite3_prepare_v2(
     db: *mut sqlite3,
     sql: *const ffi::c_char,
-    _len: ffi::c_int,
+    len: ffi::c_int,
     out_stmt: *mut *mut sqlite3_stmt,
-    _tail: *mut *const ffi::c_char,
+    tail: *mut *const ffi::c_char,
 ) -> ffi::c_int {
     if db.is_null() || sql.is_null() || out_stmt.is_null() {
         return SQLITE_MISUSE;
     }
-    let db: &mut sqlite3 = &mut *db;
-    let sql = CStr::from_ptr(sql);
-    let sql = match sql.to_str() {
-        Ok(s) => s,
-        Err(_) => return SQLITE_MISUSE,
+    *out_stmt = std::ptr::null_mut();
+    // A negative length means the SQL is NUL-terminated; otherwise it ends at `len` bytes or
+    // the first NUL, whichever comes first.
+    let bytes = if len < 0 {
+        CStr::from_ptr(sql).to_bytes()
+    } else {
+        let bytes = std::slice::from_raw_parts(sql as *const u8, len as usize);
+        match bytes.iter().position(|b| *b == 0) {
+            Some(end) => &bytes[..end],
+            None => bytes,
+        }
     };
-    let stmt = match db.conn.prepare(sql) {
-        Ok(stmt) => stmt,
-        Err(_) => return SQLITE_ERROR,
+    let Ok(sql_str) = std::str::from_utf8(bytes) else {
+        return SQLITE_MISUSE;
     };
-    *out_stmt = Box::leak(Box::new(sqlite3_stmt::new(stmt)));
-    SQLITE_OK
+    match (*db).conn.prepare_with_tail(sql_str) {
+        Ok((stmt, consumed)) => {
+            if !tail.is_null() {
+                *tail = sql.add(consumed);
+            }
+            // Comments and whitespace prepare to no statement at all.
+            if let Some(stmt) = stmt {
+                *out_stmt = Box::leak(Box::new(sqlite3_stmt::new(db, stmt)));
+            }
+            SQLITE_OK
+        }
+        Err(err) => (*db).set_error(&err),
+    }
 }
 
 #[no_mangle]
@@ -234,17 +308,25 @@ pub unsafe extern "C" fn sqlite3_finalize(stmt: *mut sqlite3_stmt) -> ffi::c_int
 
 #[no_mangle]
 pub unsafe extern "C" fn sqlite3_step(stmt: *mut sqlite3_stmt) -> ffi::c_int {
+    if stmt.is_null() {
+        return SQLITE_MISUSE;
+    }
     let stmt = &mut *stmt;
-    if let Ok(result) = stmt.stmt.step() {
-        match result {
-            limbo_core::StepResult::IO => SQLITE_BUSY,
-            limbo_core::StepResult::Done => SQLITE_DONE,
-            limbo_core::StepResult::Interrupt => SQLITE_INTERRUPT,
-            limbo_core::StepResult::Row => SQLITE_ROW,
-            limbo_core::StepResult::Busy => SQLITE_BUSY,
+    stmt.column_text.clear();
+    loop {
+        match stmt.stmt.step() {
+            // sqlite3_step() blocks, so wait for the I/O here rather than returning early.
+            Ok(limbo_core::StepResult::IO) => {
+                if let Err(err) = stmt.stmt.run_once() {
+                    return (*stmt.db).set_error(&err);
+                }
+            }
+            Ok(limbo_core::StepResult::Done) => return SQLITE_DONE,
+            Ok(limbo_core::StepResult::Interrupt) => return SQLITE_INTERRUPT,
+            Ok(limbo_core::StepResult::Row) => return SQLITE_ROW,
+            Ok(limbo_core::StepResult::Busy) => return SQLITE_BUSY,
+            Err(err) => return (*stmt.db).set_error(&err),
         }
-    } else {
-        SQLITE_ERROR
     }
 }
 
@@ -261,30 +343,84 @@ type exec_callback = Option<
 pub unsafe extern "C" fn sqlite3_exec(
     db: *mut sqlite3,
     sql: *const ffi::c_char,
-    _callback: exec_callback,
-    _context: *mut ffi::c_void,
-    _err: *mut *mut ffi::c_char,
+    callback: exec_callback,
+    context: *mut ffi::c_void,
+    err: *mut *mut ffi::c_char,
 ) -> ffi::c_int {
     if db.is_null() || sql.is_null() {
         return SQLITE_MISUSE;
     }
-    let db: &mut sqlite3 = &mut *db;
+    if !err.is_null() {
+        *err = std::ptr::null_mut();
+    }
     let sql = CStr::from_ptr(sql);
     let sql = match sql.to_str() {
         Ok(s) => s,
         Err(_) => return SQLITE_MISUSE,
     };
     trace!("sqlite3_exec(sql={})", sql);
-    match db.conn.execute(sql) {
-        Ok(_) => SQLITE_OK,
-        Err(_) => SQLITE_ERROR,
+    let rc = exec(db, sql, callback, context);
+    if rc != SQLITE_OK && !err.is_null() {
+        *err = copy_errmsg(db);
     }
+    rc
+}
+
+unsafe fn exec(
+    db: *mut sqlite3,
+    sql: &str,
+    callback: exec_callback,
+    context: *mut ffi::c_void,
+) -> ffi::c_int {
+    let conn = (*db).conn.clone();
+    for stmt in conn.query_runner(sql.as_bytes()) {
+        let stmt = match stmt {
+            Ok(Some(stmt)) => stmt,
+            Ok(None) => continue,
+            Err(err) => return (*db).set_error(&err),
+        };
+        let mut stmt = sqlite3_stmt::new(db, stmt);
+        loop {
+            match sqlite3_step(&mut stmt) {
+                SQLITE_ROW => {}
+                SQLITE_DONE => break,
+                rc => return rc,
+            }
+            let Some(callback) = callback else {
+                continue;
+            };
+            let n_column = sqlite3_column_count(&mut stmt);
+            let mut colv = (0..n_column)
+                .map(|idx| sqlite3_column_name(&mut stmt, idx) as *mut ffi::c_char)
+                .collect::<Vec<_>>();
+            let mut argv = (0..n_column)
+                .map(|idx| sqlite3_column_text(&mut stmt, idx) as *mut ffi::c_char)
+                .collect::<Vec<_>>();
+            if callback(context, n_column, argv.as_mut_ptr(), colv.as_mut_ptr()) != 0 {
+                (*db).err_code = SQLITE_ABORT;
+                (*db).err_msg = Some(c"query aborted".to_owned());
+                return SQLITE_ABORT;
+            }
+        }
+    }
+    SQLITE_OK
+}
+
+/// Copies the current error message into memory the caller releases with sqlite3_free().
+unsafe fn copy_errmsg(db: *mut sqlite3) -> *mut ffi::c_char {
+    let msg = CStr::from_ptr(sqlite3_errmsg(db)).to_bytes_with_nul();
+    let buf = sqlite3_malloc64(msg.len() as u64) as *mut ffi::c_char;
+    if !buf.is_null() {
+        std::ptr::copy_nonoverlapping(msg.as_ptr() as *const ffi::c_char, buf, msg.len());
+    }
+    buf
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn sqlite3_reset(stmt: *mut sqlite3_stmt) -> ffi::c_int {
     let stmt = &mut *stmt;
     stmt.stmt.reset();
+    stmt.column_text.clear();
     SQLITE_OK
 }
 
@@ -370,13 +506,16 @@ pub unsafe extern "C" fn sqlite3_limit(
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn sqlite3_malloc64(_n: ffi::c_int) -> *mut ffi::c_void {
-    stub!();
+pub unsafe extern "C" fn sqlite3_malloc64(n: u64) -> *mut ffi::c_void {
+    if n == 0 {
+        return std::ptr::null_mut();
+    }
+    libc::malloc(n as usize)
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn sqlite3_free(_ptr: *mut ffi::c_void) {
-    stub!();
+pub unsafe extern "C" fn sqlite3_free(ptr: *mut ffi::c_void) {
+    libc::free(ptr);
 }
 
 #[no_mangle]
@@ -448,77 +587,169 @@ pub unsafe extern "C" fn sqlite3_data_count(stmt: *mut sqlite3_stmt) -> ffi::c_i
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn sqlite3_bind_parameter_count(_stmt: *mut sqlite3_stmt) -> ffi::c_int {
-    stub!();
+pub unsafe extern "C" fn sqlite3_bind_parameter_count(stmt: *mut sqlite3_stmt) -> ffi::c_int {
+    if stmt.is_null() {
+        return 0;
+    }
+    (*stmt).stmt.parameters_count() as ffi::c_int
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn sqlite3_bind_parameter_name(
-    _stmt: *mut sqlite3_stmt,
-    _idx: ffi::c_int,
+    stmt: *mut sqlite3_stmt,
+    idx: ffi::c_int,
 ) -> *const ffi::c_char {
-    stub!();
+    if stmt.is_null() {
+        return std::ptr::null();
+    }
+    let stmt = &mut *stmt;
+    let Some(index) = usize::try_from(idx).ok().and_then(NonZero::new) else {
+        return std::ptr::null();
+    };
+    if stmt.parameter_names.is_empty() {
+        let parameters = stmt.stmt.parameters();
+        stmt.parameter_names = (1..=stmt.stmt.parameters_count())
+            .map(|i| {
+                // Anonymous parameters have no name.
+                parameters
+                    .name(NonZero::new(i).unwrap())
+                    .filter(|name| name != "?")
+                    .and_then(|name| CString::new(name).ok())
+            })
+            .collect();
+    }
+    match stmt.parameter_names.get(index.get() - 1) {
+        Some(Some(name)) => name.as_ptr(),
+        _ => std::ptr::null(),
+    }
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn sqlite3_bind_null(
-    _stmt: *mut sqlite3_stmt,
-    _idx: ffi::c_int,
+pub unsafe extern "C" fn sqlite3_bind_null(stmt: *mut sqlite3_stmt, idx: ffi::c_int) -> ffi::c_int {
+    if stmt.is_null() {
+        return SQLITE_MISUSE;
+    }
+    (*stmt).bind(idx, OwnedValue::Null)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sqlite3_bind_int(
+    stmt: *mut sqlite3_stmt,
+    idx: ffi::c_int,
+    val: ffi::c_int,
 ) -> ffi::c_int {
-    stub!();
+    sqlite3_bind_int64(stmt, idx, val as i64)
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn sqlite3_bind_int64(
-    _stmt: *mut sqlite3_stmt,
-    _idx: ffi::c_int,
-    _val: i64,
+    stmt: *mut sqlite3_stmt,
+    idx: ffi::c_int,
+    val: i64,
 ) -> ffi::c_int {
-    stub!();
+    if stmt.is_null() {
+        return SQLITE_MISUSE;
+    }
+    (*stmt).bind(idx, OwnedValue::Integer(val))
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn sqlite3_bind_double(
-    _stmt: *mut sqlite3_stmt,
-    _idx: ffi::c_int,
-    _val: f64,
+    stmt: *mut sqlite3_stmt,
+    idx: ffi::c_int,
+    val: f64,
 ) -> ffi::c_int {
-    stub!();
+    if stmt.is_null() {
+        return SQLITE_MISUSE;
+    }
+    (*stmt).bind(idx, OwnedValue::Float(val))
+}
+
+/// Releases bound data with the caller's destructor. SQLITE_STATIC (0) and SQLITE_TRANSIENT (-1)
+/// are sentinels rather than functions; the data is copied when bound, so neither needs
+/// anything done.
+unsafe fn destroy_bound_data(destroy: *mut ffi::c_void, data: *const ffi::c_void) {
+    if destroy.is_null() || destroy as isize == -1 {
+        return;
+    }
+    let destroy: unsafe extern "C" fn(*mut ffi::c_void) = std::mem::transmute(destroy);
+    destroy(data as *mut ffi::c_void);
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn sqlite3_bind_text(
-    _stmt: *mut sqlite3_stmt,
-    _idx: ffi::c_int,
-    _text: *const ffi::c_char,
-    _len: ffi::c_int,
-    _destroy: *mut ffi::c_void,
+    stmt: *mut sqlite3_stmt,
+    idx: ffi::c_int,
+    text: *const ffi::c_char,
+    len: ffi::c_int,
+    destroy: *mut ffi::c_void,
 ) -> ffi::c_int {
-    stub!();
+    if stmt.is_null() {
+        return SQLITE_MISUSE;
+    }
+    let value = if text.is_null() {
+        OwnedValue::Null
+    } else {
+        let bytes = if len < 0 {
+            CStr::from_ptr(text).to_bytes()
+        } else {
+            std::slice::from_raw_parts(text as *const u8, len as usize)
+        };
+        OwnedValue::build_text(&String::from_utf8_lossy(bytes))
+    };
+    let rc = (*stmt).bind(idx, value);
+    if !text.is_null() {
+        destroy_bound_data(destroy, text as *const ffi::c_void);
+    }
+    rc
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn sqlite3_bind_blob(
-    _stmt: *mut sqlite3_stmt,
-    _idx: ffi::c_int,
-    _blob: *const ffi::c_void,
-    _len: ffi::c_int,
-    _destroy: *mut ffi::c_void,
+    stmt: *mut sqlite3_stmt,
+    idx: ffi::c_int,
+    blob: *const ffi::c_void,
+    len: ffi::c_int,
+    destroy: *mut ffi::c_void,
 ) -> ffi::c_int {
-    stub!();
+    if stmt.is_null() || len < 0 {
+        return SQLITE_MISUSE;
+    }
+    let value = if blob.is_null() {
+        OwnedValue::Null
+    } else {
+        OwnedValue::from_blob(std::slice::from_raw_parts(blob as *const u8, len as usize).to_vec())
+    };
+    let rc = (*stmt).bind(idx, value);
+    if !blob.is_null() {
+        destroy_bound_data(destroy, blob);
+    }
+    rc
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn sqlite3_column_type(
-    _stmt: *mut sqlite3_stmt,
-    _idx: ffi::c_int,
+    stmt: *mut sqlite3_stmt,
+    idx: ffi::c_int,
 ) -> ffi::c_int {
-    stub!();
+    if stmt.is_null() {
+        return SQLITE_NULL;
+    }
+    match (*stmt).column_value(idx) {
+        Some(OwnedValue::Integer(_)) => SQLITE_INTEGER,
+        Some(OwnedValue::Float(_)) => SQLITE_FLOAT,
+        Some(OwnedValue::Text(_)) => SQLITE_TEXT,
+        Some(OwnedValue::Blob(_)) => SQLITE_BLOB,
+        Some(OwnedValue::Null) | None => SQLITE_NULL,
+    }
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn sqlite3_column_count(_stmt: *mut sqlite3_stmt) -> ffi::c_int {
-    stub!();
+pub unsafe extern "C" fn sqlite3_column_count(stmt: *mut sqlite3_stmt) -> ffi::c_int {
+    if stmt.is_null() {
+        return 0;
+    }
+    (*stmt).stmt.num_columns() as ffi::c_int
 }
 
 #[no_mangle]
@@ -526,41 +757,142 @@ pub unsafe extern "C" fn sqlite3_column_decltype(
     _stmt: *mut sqlite3_stmt,
     _idx: ffi::c_int,
 ) -> *const ffi::c_char {
-    stub!();
+    // Result columns don't carry their declared type yet, which SQLite also reports as NULL for
+    // expressions.
+    std::ptr::null()
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn sqlite3_column_name(
-    _stmt: *mut sqlite3_stmt,
-    _idx: ffi::c_int,
+    stmt: *mut sqlite3_stmt,
+    idx: ffi::c_int,
 ) -> *const ffi::c_char {
-    stub!();
+    if stmt.is_null() {
+        return std::ptr::null();
+    }
+    let stmt = &mut *stmt;
+    if stmt.column_names.is_empty() {
+        stmt.column_names = (0..stmt.stmt.num_columns())
+            .map(|i| CString::new(stmt.stmt.get_column_name(i).as_str()).ok())
+            .collect();
+    }
+    match usize::try_from(idx)
+        .ok()
+        .and_then(|idx| stmt.column_names.get(idx))
+    {
+        Some(Some(name)) => name.as_ptr(),
+        _ => std::ptr::null(),
+    }
+}
+
+/// Parses the numeric prefix of a text value like SQLite's integer and real conversions do,
+/// yielding 0 when there is none.
+fn text_to_f64(text: &str) -> f64 {
+    let text = text.trim_start();
+    let mut end = 0;
+    let mut seen_dot = false;
+    let mut seen_exp = false;
+    let bytes = text.as_bytes();
+    while end < bytes.len() {
+        match bytes[end] {
+            b'0'..=b'9' => {}
+            b'+' | b'-' if end == 0 || matches!(bytes[end - 1], b'e' | b'E') => {}
+            b'.' if !seen_dot && !seen_exp => seen_dot = true,
+            b'e' | b'E' if !seen_exp && end > 0 => seen_exp = true,
+            _ => break,
+        }
+        end += 1;
+    }
+    // Back off an exponent or sign that isn't followed by digits.
+    while end > 0 && !text[..end].ends_with(|c: char| c.is_ascii_digit() || c == '.') {
+        end -= 1;
+    }
+    text[..end].parse().unwrap_or(0.0)
+}
+
+fn text_to_i64(text: &str) -> i64 {
+    let trimmed = text.trim_start();
+    let digits = trimmed
+        .char_indices()
+        .take_while(|(i, c)| c.is_ascii_digit() || (*i == 0 && (*c == '+' || *c == '-')))
+        .count();
+    match trimmed[..digits].parse() {
+        Ok(value) if !matches!(trimmed[digits..].chars().next(), Some('.' | 'e' | 'E')) => value,
+        _ => text_to_f64(text) as i64,
+    }
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn sqlite3_column_int64(_stmt: *mut sqlite3_stmt, _idx: ffi::c_int) -> i64 {
-    stub!();
+pub unsafe extern "C" fn sqlite3_column_int(
+    stmt: *mut sqlite3_stmt,
+    idx: ffi::c_int,
+) -> ffi::c_int {
+    sqlite3_column_int64(stmt, idx) as ffi::c_int
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn sqlite3_column_double(_stmt: *mut sqlite3_stmt, _idx: ffi::c_int) -> f64 {
-    stub!();
+pub unsafe extern "C" fn sqlite3_column_int64(stmt: *mut sqlite3_stmt, idx: ffi::c_int) -> i64 {
+    if stmt.is_null() {
+        return 0;
+    }
+    match (*stmt).column_value(idx) {
+        Some(OwnedValue::Integer(i)) => *i,
+        Some(OwnedValue::Float(f)) => *f as i64,
+        Some(OwnedValue::Text(text)) => text_to_i64(text.as_str()),
+        Some(OwnedValue::Blob(blob)) => text_to_i64(&String::from_utf8_lossy(blob)),
+        Some(OwnedValue::Null) | None => 0,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sqlite3_column_double(stmt: *mut sqlite3_stmt, idx: ffi::c_int) -> f64 {
+    if stmt.is_null() {
+        return 0.0;
+    }
+    match (*stmt).column_value(idx) {
+        Some(OwnedValue::Integer(i)) => *i as f64,
+        Some(OwnedValue::Float(f)) => *f,
+        Some(OwnedValue::Text(text)) => text_to_f64(text.as_str()),
+        Some(OwnedValue::Blob(blob)) => text_to_f64(&String::from_utf8_lossy(blob)),
+        Some(OwnedValue::Null) | None => 0.0,
+    }
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn sqlite3_column_blob(
-    _stmt: *mut sqlite3_stmt,
-    _idx: ffi::c_int,
+    stmt: *mut sqlite3_stmt,
+    idx: ffi::c_int,
 ) -> *const ffi::c_void {
-    stub!();
+    if stmt.is_null() {
+        return std::ptr::null();
+    }
+    let stmt = &mut *stmt;
+    match stmt.column_value(idx) {
+        Some(OwnedValue::Blob(blob)) if blob.is_empty() => std::ptr::null(),
+        Some(OwnedValue::Blob(blob)) => blob.as_ptr() as *const ffi::c_void,
+        _ => match stmt.column_text(idx) {
+            Some(text) if text.len() > 1 => text.as_ptr() as *const ffi::c_void,
+            _ => std::ptr::null(),
+        },
+    }
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn sqlite3_column_bytes(
-    _stmt: *mut sqlite3_stmt,
-    _idx: ffi::c_int,
+    stmt: *mut sqlite3_stmt,
+    idx: ffi::c_int,
 ) -> ffi::c_int {
-    stub!();
+    if stmt.is_null() {
+        return 0;
+    }
+    let stmt = &mut *stmt;
+    match stmt.column_value(idx) {
+        Some(OwnedValue::Blob(blob)) => blob.len() as ffi::c_int,
+        // The text conversion is NUL-terminated, which isn't counted.
+        _ => stmt
+            .column_text(idx)
+            .map_or(0, |text| (text.len() - 1) as ffi::c_int),
+    }
 }
 
 #[no_mangle]
@@ -568,11 +900,11 @@ pub unsafe extern "C" fn sqlite3_value_type(value: *mut ffi::c_void) -> ffi::c_i
     let value = value as *mut limbo_core::OwnedValue;
     let value = &*value;
     match value {
-        limbo_core::OwnedValue::Null => 0,
-        limbo_core::OwnedValue::Integer(_) => 1,
-        limbo_core::OwnedValue::Float(_) => 2,
-        limbo_core::OwnedValue::Text(_) => 3,
-        limbo_core::OwnedValue::Blob(_) => 4,
+        limbo_core::OwnedValue::Null => SQLITE_NULL,
+        limbo_core::OwnedValue::Integer(_) => SQLITE_INTEGER,
+        limbo_core::OwnedValue::Float(_) => SQLITE_FLOAT,
+        limbo_core::OwnedValue::Text(_) => SQLITE_TEXT,
+        limbo_core::OwnedValue::Blob(_) => SQLITE_BLOB,
     }
 }
 
@@ -631,15 +963,12 @@ pub unsafe extern "C" fn sqlite3_column_text(
     stmt: *mut sqlite3_stmt,
     idx: ffi::c_int,
 ) -> *const ffi::c_uchar {
-    let stmt = &mut *stmt;
-    let row = stmt.stmt.row();
-    let row = match row.as_ref() {
-        Some(row) => row,
-        None => return std::ptr::null(),
-    };
-    match row.get::<&OwnedValue>(idx as usize) {
-        Ok(limbo_core::OwnedValue::Text(text)) => text.as_str().as_ptr(),
-        _ => std::ptr::null(),
+    if stmt.is_null() {
+        return std::ptr::null();
+    }
+    match (*stmt).column_text(idx) {
+        Some(text) => text.as_ptr(),
+        None => std::ptr::null(),
     }
 }
 
@@ -666,7 +995,7 @@ impl TabResult {
         for &ptr in &self.az_result {
             if !ptr.is_null() {
                 unsafe {
-                    sqlite3_free(ptr as *mut _);
+                    drop(CString::from_raw(ptr));
                 }
             }
         }
@@ -942,14 +1271,9 @@ pub unsafe extern "C" fn sqlite3_errmsg(_db: *mut sqlite3) -> *const ffi::c_char
         return sqlite3_errstr(SQLITE_NOMEM);
     }
 
-    let err_msg = if (*_db).err_code != SQLITE_OK {
-        if !(*_db).p_err.is_null() {
-            (*_db).p_err as *const ffi::c_char
-        } else {
-            std::ptr::null()
-        }
-    } else {
-        std::ptr::null()
+    let err_msg = match &(*_db).err_msg {
+        Some(err_msg) if (*_db).err_code != SQLITE_OK => err_msg.as_ptr(),
+        _ => std::ptr::null(),
     };
 
     if err_msg.is_null() {
@@ -969,7 +1293,23 @@ pub unsafe extern "C" fn sqlite3_extended_errcode(_db: *mut sqlite3) -> ffi::c_i
         return SQLITE_NOMEM;
     }
 
-    (*_db).err_code & (*_db).err_mask
+    (*_db).err_code
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sqlite3_extended_result_codes(
+    db: *mut sqlite3,
+    onoff: ffi::c_int,
+) -> ffi::c_int {
+    if db.is_null() {
+        return SQLITE_MISUSE;
+    }
+    (*db).err_mask = if onoff != 0 {
+        0xFFFFFFFFu32 as i32
+    } else {
+        0xFF
+    };
+    SQLITE_OK
 }
 
 #[no_mangle]
@@ -1069,7 +1409,7 @@ pub unsafe extern "C" fn sqlite3_wal_checkpoint(
 pub unsafe extern "C" fn sqlite3_wal_checkpoint_v2(
     db: *mut sqlite3,
     _db_name: *const ffi::c_char,
-    _mode: ffi::c_int,
+    mode: ffi::c_int,
     _log_size: *mut ffi::c_int,
     _checkpoint_count: *mut ffi::c_int,
 ) -> ffi::c_int {
@@ -1077,8 +1417,15 @@ pub unsafe extern "C" fn sqlite3_wal_checkpoint_v2(
         return SQLITE_MISUSE;
     }
     let db: &mut sqlite3 = &mut *db;
-    // TODO: Checkpointing modes and reporting back log size and checkpoint count to caller.
-    if db.conn.checkpoint().is_err() {
+    let mode = match mode {
+        SQLITE_CHECKPOINT_PASSIVE => CheckpointMode::Passive,
+        SQLITE_CHECKPOINT_FULL => CheckpointMode::Full,
+        SQLITE_CHECKPOINT_RESTART => CheckpointMode::Restart,
+        SQLITE_CHECKPOINT_TRUNCATE => CheckpointMode::Truncate,
+        _ => return SQLITE_MISUSE,
+    };
+    // TODO: Reporting back log size and checkpoint count to caller.
+    if db.conn.checkpoint_with_mode(mode).is_err() {
         return SQLITE_ERROR;
     }
     SQLITE_OK
@@ -1137,6 +1484,292 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_constraint_error_codes() {
+        unsafe {
+            let mut db = ptr::null_mut();
+            assert_eq!(sqlite3_open(c":memory:".as_ptr(), &mut db), SQLITE_OK);
+            assert_eq!(
+                sqlite3_exec(
+                    db,
+                    c"CREATE TABLE t (id INTEGER PRIMARY KEY)".as_ptr(),
+                    None,
+                    ptr::null_mut(),
+                    ptr::null_mut()
+                ),
+                SQLITE_OK
+            );
+            let insert = c"INSERT INTO t VALUES (1)".as_ptr();
+            let mut stmt = ptr::null_mut();
+            assert_eq!(
+                sqlite3_prepare_v2(db, insert, -1, &mut stmt, ptr::null_mut()),
+                SQLITE_OK
+            );
+            assert_eq!(sqlite3_step(stmt), SQLITE_DONE);
+            assert_eq!(sqlite3_finalize(stmt), SQLITE_OK);
+
+            let mut stmt = ptr::null_mut();
+            assert_eq!(
+                sqlite3_prepare_v2(db, insert, -1, &mut stmt, ptr::null_mut()),
+                SQLITE_OK
+            );
+            assert_eq!(sqlite3_step(stmt), SQLITE_CONSTRAINT);
+            assert_eq!(sqlite3_errcode(db), SQLITE_CONSTRAINT);
+            // SQLITE_CONSTRAINT_PRIMARYKEY
+            assert_eq!(sqlite3_extended_errcode(db), SQLITE_CONSTRAINT | (6 << 8));
+            assert_eq!(
+                CStr::from_ptr(sqlite3_errmsg(db)),
+                c"UNIQUE constraint failed: t.id"
+            );
+            assert_eq!(sqlite3_finalize(stmt), SQLITE_OK);
+            assert_eq!(sqlite3_close(db), SQLITE_OK);
+        }
+    }
+
+    unsafe fn open_memory() -> *mut sqlite3 {
+        let mut db = ptr::null_mut();
+        assert_eq!(sqlite3_open(c":memory:".as_ptr(), &mut db), SQLITE_OK);
+        db
+    }
+
+    #[test]
+    fn test_prepare_tail() {
+        unsafe {
+            let db = open_memory();
+            let sql = c"SELECT 1; SELECT 2;  ";
+            let mut stmt = ptr::null_mut();
+            let mut tail = ptr::null();
+            assert_eq!(
+                sqlite3_prepare_v2(db, sql.as_ptr(), -1, &mut stmt, &mut tail),
+                SQLITE_OK
+            );
+            assert_eq!(CStr::from_ptr(tail), c" SELECT 2;  ");
+            assert_eq!(sqlite3_finalize(stmt), SQLITE_OK);
+
+            let rest = tail;
+            assert_eq!(
+                sqlite3_prepare_v2(db, rest, -1, &mut stmt, &mut tail),
+                SQLITE_OK
+            );
+            assert_eq!(sqlite3_step(stmt), SQLITE_ROW);
+            assert_eq!(sqlite3_column_int64(stmt, 0), 2);
+            assert_eq!(sqlite3_finalize(stmt), SQLITE_OK);
+
+            // Only whitespace is left, which prepares to no statement.
+            assert_eq!(
+                sqlite3_prepare_v2(db, tail, -1, &mut stmt, &mut tail),
+                SQLITE_OK
+            );
+            assert!(stmt.is_null());
+
+            // The length limits how much of the SQL is read.
+            assert_eq!(
+                sqlite3_prepare_v2(db, sql.as_ptr(), 8, &mut stmt, &mut tail),
+                SQLITE_OK
+            );
+            assert_eq!(tail, sql.as_ptr().add(8));
+            assert_eq!(sqlite3_step(stmt), SQLITE_ROW);
+            assert_eq!(sqlite3_column_int64(stmt, 0), 1);
+            assert_eq!(sqlite3_finalize(stmt), SQLITE_OK);
+            assert_eq!(sqlite3_close(db), SQLITE_OK);
+        }
+    }
+
+    #[test]
+    fn test_bind_and_column() {
+        unsafe {
+            let db = open_memory();
+            let mut stmt = ptr::null_mut();
+            assert_eq!(
+                sqlite3_prepare_v2(
+                    db,
+                    c"SELECT ?, :name, ?3, ?4, ?5".as_ptr(),
+                    -1,
+                    &mut stmt,
+                    ptr::null_mut()
+                ),
+                SQLITE_OK
+            );
+            assert_eq!(sqlite3_bind_parameter_count(stmt), 5);
+            assert!(sqlite3_bind_parameter_name(stmt, 1).is_null());
+            assert_eq!(
+                CStr::from_ptr(sqlite3_bind_parameter_name(stmt, 2)),
+                c":name"
+            );
+            assert_eq!(CStr::from_ptr(sqlite3_bind_parameter_name(stmt, 3)), c"?3");
+
+            assert_eq!(sqlite3_bind_int64(stmt, 1, 42), SQLITE_OK);
+            assert_eq!(sqlite3_bind_double(stmt, 2, 2.5), SQLITE_OK);
+            assert_eq!(
+                sqlite3_bind_text(stmt, 3, c"hello world".as_ptr(), 5, ptr::null_mut()),
+                SQLITE_OK
+            );
+            let blob = [1u8, 0, 2];
+            assert_eq!(
+                sqlite3_bind_blob(stmt, 4, blob.as_ptr() as *const _, 3, ptr::null_mut()),
+                SQLITE_OK
+            );
+            assert_eq!(sqlite3_bind_null(stmt, 5), SQLITE_OK);
+            assert_eq!(sqlite3_bind_null(stmt, 6), SQLITE_RANGE);
+            assert_eq!(sqlite3_bind_null(stmt, 0), SQLITE_RANGE);
+
+            assert_eq!(sqlite3_step(stmt), SQLITE_ROW);
+            assert_eq!(sqlite3_column_count(stmt), 5);
+            assert_eq!(sqlite3_column_type(stmt, 0), SQLITE_INTEGER);
+            assert_eq!(sqlite3_column_type(stmt, 1), SQLITE_FLOAT);
+            assert_eq!(sqlite3_column_type(stmt, 2), SQLITE_TEXT);
+            assert_eq!(sqlite3_column_type(stmt, 3), SQLITE_BLOB);
+            assert_eq!(sqlite3_column_type(stmt, 4), SQLITE_NULL);
+
+            assert_eq!(sqlite3_column_int64(stmt, 0), 42);
+            assert_eq!(sqlite3_column_double(stmt, 0), 42.0);
+            assert_eq!(CStr::from_ptr(sqlite3_column_text(stmt, 0) as _), c"42");
+            assert_eq!(sqlite3_column_double(stmt, 1), 2.5);
+            assert_eq!(sqlite3_column_int64(stmt, 1), 2);
+            assert_eq!(CStr::from_ptr(sqlite3_column_text(stmt, 1) as _), c"2.5");
+            assert_eq!(CStr::from_ptr(sqlite3_column_text(stmt, 2) as _), c"hello");
+            assert_eq!(sqlite3_column_bytes(stmt, 2), 5);
+            assert_eq!(sqlite3_column_bytes(stmt, 3), 3);
+            let column_blob = sqlite3_column_blob(stmt, 3) as *const u8;
+            assert_eq!(std::slice::from_raw_parts(column_blob, 3), &blob);
+            assert!(sqlite3_column_text(stmt, 4).is_null());
+            assert!(sqlite3_column_blob(stmt, 4).is_null());
+            assert_eq!(sqlite3_column_bytes(stmt, 4), 0);
+            assert_eq!(sqlite3_column_type(stmt, 5), SQLITE_NULL);
+            assert_eq!(sqlite3_step(stmt), SQLITE_DONE);
+
+            assert_eq!(sqlite3_reset(stmt), SQLITE_OK);
+            for idx in 1..=5 {
+                assert_eq!(
+                    sqlite3_bind_text(stmt, idx, c"12abc".as_ptr(), -1, ptr::null_mut()),
+                    SQLITE_OK
+                );
+            }
+            assert_eq!(sqlite3_step(stmt), SQLITE_ROW);
+            assert_eq!(sqlite3_column_int64(stmt, 0), 12);
+            assert_eq!(sqlite3_column_double(stmt, 0), 12.0);
+            assert_eq!(sqlite3_finalize(stmt), SQLITE_OK);
+            assert_eq!(sqlite3_close(db), SQLITE_OK);
+        }
+    }
+
+    #[test]
+    fn test_column_name() {
+        unsafe {
+            let db = open_memory();
+            let mut stmt = ptr::null_mut();
+            assert_eq!(
+                sqlite3_prepare_v2(
+                    db,
+                    c"SELECT 1 AS one, 2".as_ptr(),
+                    -1,
+                    &mut stmt,
+                    ptr::null_mut()
+                ),
+                SQLITE_OK
+            );
+            assert_eq!(CStr::from_ptr(sqlite3_column_name(stmt, 0)), c"one");
+            assert_eq!(CStr::from_ptr(sqlite3_column_name(stmt, 1)), c"2");
+            assert!(sqlite3_column_name(stmt, 2).is_null());
+            assert!(sqlite3_column_decltype(stmt, 0).is_null());
+            assert_eq!(sqlite3_finalize(stmt), SQLITE_OK);
+            assert_eq!(sqlite3_close(db), SQLITE_OK);
+        }
+    }
+
+    unsafe extern "C" fn collect_rows(
+        context: *mut ffi::c_void,
+        n_column: ffi::c_int,
+        argv: *mut *mut ffi::c_char,
+        colv: *mut *mut ffi::c_char,
+    ) -> ffi::c_int {
+        let rows = &mut *(context as *mut Vec<String>);
+        let row = (0..n_column as usize)
+            .map(|i| {
+                let name = CStr::from_ptr(*colv.add(i)).to_str().unwrap();
+                let value = *argv.add(i);
+                let value = if value.is_null() {
+                    "NULL"
+                } else {
+                    CStr::from_ptr(value).to_str().unwrap()
+                };
+                format!("{name}={value}")
+            })
+            .collect::<Vec<_>>();
+        rows.push(row.join(","));
+        // Stop once two rows have been seen.
+        (rows.len() >= 2) as ffi::c_int
+    }
+
+    #[test]
+    fn test_exec() {
+        unsafe {
+            let db = open_memory();
+            let mut rows = Vec::<String>::new();
+            let mut err = ptr::null_mut();
+            assert_eq!(
+                sqlite3_exec(
+                    db,
+                    c"CREATE TABLE t (a, b); INSERT INTO t VALUES (1, 'x'); SELECT a, b FROM t;"
+                        .as_ptr(),
+                    Some(collect_rows),
+                    &mut rows as *mut _ as *mut _,
+                    &mut err
+                ),
+                SQLITE_OK
+            );
+            assert!(err.is_null());
+            assert_eq!(rows, vec!["a=1,b=x"]);
+
+            // A non-zero return from the callback aborts the rest of the SQL.
+            rows.clear();
+            assert_eq!(
+                sqlite3_exec(
+                    db,
+                    c"INSERT INTO t VALUES (2, NULL); SELECT * FROM t; INSERT INTO t VALUES (3, 3);"
+                        .as_ptr(),
+                    Some(collect_rows),
+                    &mut rows as *mut _ as *mut _,
+                    &mut err
+                ),
+                SQLITE_ABORT
+            );
+            assert_eq!(rows, vec!["a=1,b=x", "a=2,b=NULL"]);
+            assert_eq!(CStr::from_ptr(err), c"query aborted");
+            sqlite3_free(err as *mut _);
+
+            assert_eq!(
+                sqlite3_exec(
+                    db,
+                    c"SELECT * FROM missing".as_ptr(),
+                    None,
+                    ptr::null_mut(),
+                    &mut err
+                ),
+                SQLITE_ERROR
+            );
+            assert!(!err.is_null());
+            assert_eq!(CStr::from_ptr(err), CStr::from_ptr(sqlite3_errmsg(db)));
+            sqlite3_free(err as *mut _);
+
+            let mut stmt = ptr::null_mut();
+            assert_eq!(
+                sqlite3_prepare_v2(
+                    db,
+                    c"SELECT count(*) FROM t".as_ptr(),
+                    -1,
+                    &mut stmt,
+                    ptr::null_mut()
+                ),
+                SQLITE_OK
+            );
+            assert_eq!(sqlite3_step(stmt), SQLITE_ROW);
+            assert_eq!(sqlite3_column_int64(stmt, 0), 2);
+            assert_eq!(sqlite3_finalize(stmt), SQLITE_OK);
+            assert_eq!(sqlite3_close(db), SQLITE_OK);
+        }
+    }
+
     #[test]
     fn test_close() {
         unsafe {
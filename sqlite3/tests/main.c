@@ -5,6 +5,7 @@ extern void test_open_not_found();
 extern void test_open_existing();
 extern void test_close();
 extern void test_prepare_misuse();
+extern void test_bind_step_column();
 extern void test_wal_checkpoint();
 extern void test_wal_checkpoint_v2();
 
@@ -17,6 +18,7 @@ int main(int argc, char *argv[])
 	test_open_existing();
 	test_close();
 	test_prepare_misuse();
+	test_bind_step_column();
 	test_wal_checkpoint();
 	test_wal_checkpoint_v2();
 
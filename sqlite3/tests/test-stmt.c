@@ -0,0 +1,61 @@
+#include "check.h"
+
+#include <sqlite3.h>
+#include <stddef.h>
+#include <stdlib.h>
+#include <stdio.h>
+#include <string.h>
+
+static int count_rows(void *context, int n_column, char **argv, char **colv)
+{
+	int *rows = context;
+
+	CHECK_EQUAL(2, n_column);
+	CHECK_EQUAL(0, strcmp(colv[0], "a"));
+	CHECK_EQUAL(0, strcmp(colv[1], "b"));
+	(*rows)++;
+	return 0;
+}
+
+void test_bind_step_column(void)
+{
+	sqlite3 *db;
+	sqlite3_stmt *stmt;
+	const char *tail;
+	char *err = NULL;
+	int rows = 0;
+
+	CHECK_EQUAL(SQLITE_OK, sqlite3_open(":memory:", &db));
+	CHECK_EQUAL(SQLITE_OK, sqlite3_exec(db, "CREATE TABLE t (a, b); INSERT INTO t VALUES (1, 'one');", NULL, NULL, &err));
+	CHECK_EQUAL(1, err == NULL);
+
+	CHECK_EQUAL(SQLITE_OK, sqlite3_prepare_v2(db, "INSERT INTO t VALUES (?, ?); SELECT 1", -1, &stmt, &tail));
+	CHECK_EQUAL(0, strcmp(tail, " SELECT 1"));
+	CHECK_EQUAL(2, sqlite3_bind_parameter_count(stmt));
+	CHECK_EQUAL(SQLITE_OK, sqlite3_bind_int64(stmt, 1, 2));
+	CHECK_EQUAL(SQLITE_OK, sqlite3_bind_text(stmt, 2, "two", -1, SQLITE_TRANSIENT));
+	CHECK_EQUAL(SQLITE_RANGE, sqlite3_bind_null(stmt, 3));
+	CHECK_EQUAL(SQLITE_DONE, sqlite3_step(stmt));
+	CHECK_EQUAL(SQLITE_OK, sqlite3_finalize(stmt));
+
+	CHECK_EQUAL(SQLITE_OK, sqlite3_prepare_v2(db, "SELECT a, b FROM t WHERE a = 2", -1, &stmt, NULL));
+	CHECK_EQUAL(2, sqlite3_column_count(stmt));
+	CHECK_EQUAL(0, strcmp(sqlite3_column_name(stmt, 1), "b"));
+	CHECK_EQUAL(SQLITE_ROW, sqlite3_step(stmt));
+	CHECK_EQUAL(SQLITE_INTEGER, sqlite3_column_type(stmt, 0));
+	CHECK_EQUAL(2, (int)sqlite3_column_int64(stmt, 0));
+	CHECK_EQUAL(SQLITE_TEXT, sqlite3_column_type(stmt, 1));
+	CHECK_EQUAL(0, strcmp((const char *)sqlite3_column_text(stmt, 1), "two"));
+	CHECK_EQUAL(3, sqlite3_column_bytes(stmt, 1));
+	CHECK_EQUAL(SQLITE_DONE, sqlite3_step(stmt));
+	CHECK_EQUAL(SQLITE_OK, sqlite3_finalize(stmt));
+
+	CHECK_EQUAL(SQLITE_OK, sqlite3_exec(db, "SELECT a, b FROM t", count_rows, &rows, NULL));
+	CHECK_EQUAL(2, rows);
+
+	CHECK_EQUAL(SQLITE_ERROR, sqlite3_exec(db, "SELECT * FROM missing", NULL, NULL, &err));
+	CHECK_EQUAL(1, err != NULL);
+	sqlite3_free(err);
+
+	CHECK_EQUAL(SQLITE_OK, sqlite3_close(db));
+}
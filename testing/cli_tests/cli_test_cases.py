@@ -3,6 +3,8 @@ from test_limbo_cli import TestLimboShell
 from pathlib import Path
 import time
 import os
+import shutil
+import sqlite3
 
 
 def test_basic_queries():
@@ -236,6 +238,119 @@ def test_import_csv_skip():
     shell.quit()
 
 
+def test_export():
+    shell = TestLimboShell("CREATE TABLE t (a INT, b TEXT);")
+    shell.execute_dot("INSERT INTO t VALUES (1, 'x'), (2, 'a, \"b\"'), (3, NULL);")
+    out_dir = shell.config.test_dir / shell.config.py_folder
+    csv_file = out_dir / "export.csv"
+    jsonl_file = out_dir / "export.jsonl"
+    shell.run_test(
+        "export-csv-verbose",
+        f".export --csv -v t {csv_file}",
+        f"Exported 3 rows to {csv_file}",
+    )
+    shell.run_test("export-jsonl", f".export --jsonl t {jsonl_file}", "")
+    shell.quit()
+
+    with open(csv_file, "rb") as f:
+        assert f.read() == b'a,b\r\n1,x\r\n2,"a, ""b"""\r\n3,\r\n'
+    with open(jsonl_file, "r") as f:
+        assert f.read() == (
+            '{"a":1,"b":"x"}\n{"a":2,"b":"a, \\"b\\""}\n{"a":3,"b":null}\n'
+        )
+
+    os.remove(csv_file)
+    os.remove(jsonl_file)
+
+
+def test_dump():
+    out_dir = Path("testing") / "cli_tests"
+    db_file = out_dir / "dump.db"
+    if db_file.exists():
+        os.remove(db_file)
+    with sqlite3.connect(db_file) as conn:
+        conn.executescript("""
+            PRAGMA journal_mode=WAL;
+            PRAGMA user_version=7;
+            PRAGMA application_id=1234;
+            CREATE TABLE t (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT, b, r REAL);
+            INSERT INTO t (name, b, r) VALUES ('it''s', x'00ff', 0.1), (NULL, 'x', NULL);
+            DELETE FROM t WHERE id = 2;
+            CREATE TABLE "we'ird" (a, "b""q" REAL);
+            INSERT INTO "we'ird" VALUES (1, 1e16), (2, NULL), (3, 0.30000000000000004);
+            DELETE FROM "we'ird" WHERE a = 2;
+        """)
+    conn.close()
+    dump = "\n".join(
+        [
+            "PRAGMA foreign_keys=OFF;",
+            "BEGIN TRANSACTION;",
+            "PRAGMA user_version=7;",
+            "PRAGMA application_id=1234;",
+            "CREATE TABLE t (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT, b, r REAL);",
+            "INSERT INTO \"t\" VALUES(1,'it''s',X'00ff',0.1);",
+            "CREATE TABLE \"we'ird\" (a, \"b\"\"q\" REAL);",
+            "INSERT INTO \"we'ird\"(rowid,\"a\",\"b\"\"q\") VALUES(1,1,1e16);",
+            "INSERT INTO \"we'ird\"(rowid,\"a\",\"b\"\"q\") VALUES(3,3,0.30000000000000004);",
+            "DELETE FROM sqlite_sequence;",
+            "INSERT INTO \"sqlite_sequence\"(rowid,\"name\",\"seq\") VALUES(1,'t',2);",
+            "COMMIT;",
+        ]
+    )
+    shell = TestLimboShell(init_commands="", flags=f"-q {db_file}")
+    shell.run_test("dump-sequence-and-pragmas", ".dump", dump)
+    shell.quit()
+
+    # Restoring the dump gives back the rows, the sequence and the header fields.
+    restored = sqlite3.connect(":memory:")
+    restored.executescript(dump)
+    assert restored.execute("SELECT * FROM t").fetchall() == [
+        (1, "it's", b"\x00\xff", 0.1)
+    ]
+    # Rowids of tables without an INTEGER PRIMARY KEY are kept too, and floats read back
+    # exactly.
+    assert restored.execute('SELECT rowid, * FROM "we\'ird"').fetchall() == [
+        (1, 1, 1e16),
+        (3, 3, 0.30000000000000004),
+    ]
+    assert restored.execute("SELECT * FROM sqlite_sequence").fetchall() == [("t", 2)]
+    assert restored.execute("PRAGMA user_version").fetchone() == (7,)
+    assert restored.execute("PRAGMA application_id").fetchone() == (1234,)
+    restored.close()
+
+    for suffix in ["", "-wal", "-shm"]:
+        if os.path.exists(f"{db_file}{suffix}"):
+            os.remove(f"{db_file}{suffix}")
+
+
+def test_json_modes():
+    shell = TestLimboShell("CREATE TABLE t (a INT, b TEXT, c BLOB, d REAL);")
+    shell.execute_dot(
+        "INSERT INTO t VALUES (1, 'x\"y', x'00ff10', 1.5), (2, NULL, x'', -0.5);"
+    )
+    out_file = shell.config.test_dir / shell.config.py_folder / "modes.json"
+    # The shell's end-of-result marker would be JSON too, so the output goes to a file.
+    shell.execute_dot(f".output {out_file}")
+    shell.execute_dot(".mode json")
+    shell.execute_dot("SELECT * FROM t;")
+    shell.execute_dot("SELECT * FROM t WHERE a > 2;")
+    shell.execute_dot(".mode ndjson")
+    shell.execute_dot("SELECT * FROM t;")
+    shell.execute_dot(".output stdout")
+    shell.execute_dot(".mode list")
+    shell.run_test("json-modes-done", "SELECT 1;", "1")
+    shell.quit()
+
+    with open(out_file, "r") as f:
+        assert f.read() == (
+            '[{"a":1,"b":"x\\"y","c":"AP8Q","d":1.5},\n'
+            '{"a":2,"b":null,"c":"","d":-0.5}]\n'
+            '{"a":1,"b":"x\\"y","c":"AP8Q","d":1.5}\n'
+            '{"a":2,"b":null,"c":"","d":-0.5}\n'
+        )
+    os.remove(out_file)
+
+
 def test_table_patterns():
     shell = TestLimboShell()
     shell.run_test("tables-pattern", ".tables us%", "users")
@@ -300,6 +415,59 @@ def test_insert_default_values():
     limbo.quit()
 
 
+def test_trace():
+    shell = TestLimboShell()
+    trace_filename = "limbo_trace.txt"
+    trace_file = shell.config.test_dir / shell.config.py_folder / trace_filename
+    shell.execute_dot(f".cd {shell.config.test_dir}/{shell.config.py_folder}")
+    shell.execute_dot(f".trace {trace_filename}")
+    shell.run_test("trace-query", "SELECT 'traced';", "traced")
+    shell.execute_dot(".trace off")
+    shell.run_test("untraced-query", "SELECT 'untraced';", "untraced")
+    shell.quit()
+
+    with open(trace_file, "r") as f:
+        contents = f.read()
+    assert "SELECT 'traced';" in contents, "Traced statement missing"
+    assert "untraced" not in contents, "Statement traced after .trace off"
+
+    os.remove(trace_file)
+
+
+def test_changes():
+    shell = TestLimboShell("CREATE TABLE t (a);")
+    shell.execute_dot(".changes on")
+    shell.run_test(
+        "changes-insert",
+        "INSERT INTO t VALUES (1), (2), (3);",
+        "changes: 3   total_changes: 3",
+    )
+    shell.run_test("changes-select", "SELECT count(*) FROM t;", "3")
+    shell.execute_dot(".changes off")
+    shell.run_test("changes-off", "INSERT INTO t VALUES (4);", "")
+    shell.quit()
+
+
+def test_archive():
+    shell = TestLimboShell()
+    shell.execute_dot(".archive --create testing/test_files/test.csv")
+    shell.run_test("archive-list", ".archive --list", "testing/test_files/test.csv")
+    shell.run_test(
+        "archive-uncompress",
+        "SELECT sz = length(sqlar_uncompress(data, sz)) FROM sqlar;",
+        "1",
+    )
+    out_dir = shell.config.test_dir / shell.config.py_folder / "archive_out"
+    shell.execute_dot(f".archive --extract -C {out_dir}")
+    shell.quit()
+
+    extracted = out_dir / "testing" / "test_files" / "test.csv"
+    with open(extracted, "rb") as f, open("testing/test_files/test.csv", "rb") as g:
+        assert f.read() == g.read(), "Extracted file differs from the original"
+
+    shutil.rmtree(out_dir)
+
+
 if __name__ == "__main__":
     print("Running all Limbo CLI tests...")
     test_basic_queries()
@@ -316,7 +484,13 @@ if __name__ == "__main__":
     test_import_csv()
     test_import_csv_verbose()
     test_import_csv_skip()
+    test_export()
+    test_dump()
+    test_json_modes()
     test_table_patterns()
     test_update_with_limit()
     test_update_with_limit_and_offset()
+    test_trace()
+    test_changes()
+    test_archive()
     print("All tests have passed")
@@ -57,6 +57,17 @@ def test_uuid():
         validate_string_uuid,
         "scalar alias's are registered properly",
     )
+    limbo.run_test_fn("SELECT uuid();", validate_string_uuid)
+    limbo.run_test_fn(
+        "SELECT uuid_str('{A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11}');",
+        lambda res: res == "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11",
+        "uuid_str normalizes text uuids",
+    )
+    limbo.run_test_fn(
+        "SELECT ulid();",
+        lambda res: len(res) == 26 and res[0] == "0",
+        "ulid returns 26 characters starting with the timestamp",
+    )
     limbo.quit()
 
 
@@ -105,6 +116,17 @@ def test_regexp():
         "select regexp_replace('the year is 2021', '([0-9]+)', '$1 or 2050') = 'the year is 2021 or 2050';",
         true,
     )
+    limbo.run_test_fn(
+        "select regexp_replace('a1b22c333', '[0-9]+', '#') = 'a#b#c#';",
+        true,
+    )
+    limbo.run_test_fn(
+        "select regexp_capture('2024-05-06', '(\\d+)-(\\d+)', 2) = '05';", true
+    )
+    limbo.run_test_fn("select regexp_capture('abc', 'x');", null)
+    limbo.run_test_fn("select regexp_extract('key=val', '\\w+') = 'key';", true)
+    limbo.run_test_fn("select 'the year is 2021' regexp '[0-9]+';", true)
+    limbo.run_test_fn("select 'the year is 2021' not regexp '[0-9]+';", false)
     limbo.quit()
 
 
@@ -254,6 +276,34 @@ def test_crypto():
         == "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
         "sha512 should encrypt correctly",
     )
+    limbo.run_test_fn(
+        "SELECT crypto_encode(crypto_sha3('abc'), 'hex');",
+        lambda a: a
+        == "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532",
+        "sha3 should default to 256 bits",
+    )
+    limbo.run_test_fn(
+        "SELECT crypto_encode(crypto_sha3('abc', 224), 'hex');",
+        lambda a: a == "e642824c3f8cf24ad09234ee7d3c766fc9a3a5168d0c94ad73b46fdf",
+        "sha3 should hash with the given size",
+    )
+    limbo.run_test_fn(
+        "SELECT hex(crypto_sha3('abc', 512));",
+        lambda a: a
+        == "B751850B1A57168A5693CD924B6B096E08F621827444F70D884F5D0240D2712E10E116E9192AF3C91A7EC57647E3934057340B4CF408D5A56592F8274EEC53F0",
+        "hex should encode hash blobs",
+    )
+    limbo.run_test_fn(
+        "SELECT crypto_encode(crypto_hmac('The quick brown fox jumps over the lazy dog', 'key'), 'hex');",
+        lambda a: a
+        == "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8",
+        "hmac should default to sha256",
+    )
+    limbo.run_test_fn(
+        "SELECT crypto_encode(crypto_hmac('The quick brown fox jumps over the lazy dog', 'key', 'sha1'), 'hex');",
+        lambda a: a == "de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9",
+        "hmac should use the given algorithm",
+    )
 
     # Encoding and Decoding
     limbo.run_test_fn(
@@ -402,6 +452,21 @@ def test_kv():
     limbo.run_test_fn(
         "select count(*) from t;", lambda res: "4" == res, "four rows remain"
     )
+    limbo.run_test_fn(
+        "insert into t values ('a', '1'), ('b', '2'), ('c', '3');",
+        null,
+        "can insert several rows at once",
+    )
+    limbo.run_test_fn(
+        "select count(*) from t;", lambda res: "7" == res, "all rows are inserted"
+    )
+    rows = ", ".join(f"('k{i}', '{i}')" for i in range(500))
+    limbo.run_test_fn(
+        f"insert into t values {rows};", null, "can insert many rows at once"
+    )
+    limbo.run_test_fn(
+        "select count(*) from t;", lambda res: "507" == res, "all of the many rows are inserted"
+    )
     limbo.quit()
 
 
@@ -473,6 +538,122 @@ def test_ipaddr():
     limbo.quit()
 
 
+def test_geopoly():
+    limbo = TestLimboShell()
+    ext_path = "./target/debug/liblimbo_geopoly"
+    square = "'[[0,0],[2,0],[2,2],[0,2],[0,0]]'"
+    limbo.run_test_fn(
+        f"SELECT geopoly_area({square});",
+        lambda res: "error: no such function: " in res,
+        "geopoly functions are missing when ext not loaded",
+    )
+    limbo.execute_dot(f".load {ext_path}")
+    limbo.run_test_fn(
+        f"SELECT geopoly_area({square});",
+        lambda res: "4.0" == res,
+        "geopoly_area returns the area of a polygon",
+    )
+    limbo.run_test_fn(
+        "SELECT geopoly_json(geopoly_ccw('[[0,0],[0,1],[1,1],[0,0]]'));",
+        lambda res: "[[0.0,0.0],[1.0,1.0],[0.0,1.0],[0.0,0.0]]" == res,
+        "geopoly_ccw makes vertices counter-clockwise",
+    )
+    limbo.run_test_fn(
+        f"SELECT geopoly_contains_point({square}, 1, 1), geopoly_contains_point({square}, 2, 1), geopoly_contains_point({square}, 3, 1);",
+        lambda res: "2|1|0" == res,
+        "geopoly_contains_point tells inside, boundary and outside apart",
+    )
+    limbo.run_test_fn(
+        f"SELECT geopoly_overlap({square}, '[[1,1],[3,1],[3,3],[1,1]]'), geopoly_within('[[1,1],[2,1],[2,2],[1,1]]', {square});",
+        lambda res: "1|1" == res,
+        "geopoly_overlap and geopoly_within compare polygons",
+    )
+    limbo.execute_dot("CREATE TABLE shapes (shape);")
+    limbo.execute_dot(
+        "INSERT INTO shapes VALUES ('[[0,0],[1,0],[1,1],[0,0]]'), ('[[-3,2],[1,5],[0,9],[-3,2]]');"
+    )
+    limbo.run_test_fn(
+        "SELECT geopoly_json(geopoly_group_bbox(shape)) FROM shapes;",
+        lambda res: "[[-3.0,0.0],[1.0,0.0],[1.0,9.0],[-3.0,9.0],[-3.0,0.0]]" == res,
+        "geopoly_group_bbox returns the bounding box of a group",
+    )
+    limbo.quit()
+
+
+def test_decimal():
+    limbo = TestLimboShell()
+    ext_path = "./target/debug/liblimbo_decimal"
+    limbo.run_test_fn(
+        "SELECT decimal_add('0.1', '0.2');",
+        lambda res: "error: no such function: " in res,
+        "decimal functions are missing when ext not loaded",
+    )
+    limbo.execute_dot(f".load {ext_path}")
+    limbo.run_test_fn(
+        "SELECT decimal_add('0.1', '0.2'), decimal_sub('1', '0.999'), decimal_mul('1.25', '-4.4');",
+        lambda res: "0.3|0.001|-5.5" == res,
+        "decimal arithmetic is exact",
+    )
+    limbo.run_test_fn(
+        "SELECT decimal_cmp('1.10', '1.1'), decimal_cmp('10', '9.99'), decimal_exp('123.45');",
+        lambda res: "0|1|+1.2345e+02" == res,
+        "decimal_cmp compares values and decimal_exp formats them",
+    )
+    limbo.run_test_fn(
+        "SELECT decimal(0.1);",
+        lambda res: "0.1000000000000000055511151231257827021181583404541015625" == res,
+        "decimal shows the exact value of a float",
+    )
+    limbo.execute_dot("CREATE TABLE prices (amount TEXT);")
+    limbo.execute_dot("INSERT INTO prices VALUES ('19.99'), ('0.01'), (NULL), ('-5.50');")
+    limbo.run_test_fn(
+        "SELECT decimal_sum(amount) FROM prices;",
+        lambda res: "14.50" == res,
+        "decimal_sum adds up a group exactly",
+    )
+    limbo.quit()
+
+
+def test_compress():
+    limbo = TestLimboShell()
+    ext_path = "./target/debug/liblimbo_compress"
+    limbo.run_test_fn(
+        "SELECT compress('hello');",
+        lambda res: "error: no such function: " in res,
+        "compress functions are missing when ext not loaded",
+    )
+    limbo.execute_dot(f".load {ext_path}")
+    limbo.run_test_fn(
+        "SELECT hex(compress('hello')) LIKE '28B52FFD%', hex(compress('hello', 'gzip')) LIKE '1F8B%';",
+        lambda res: "1|1" == res,
+        "compress writes zstd by default and gzip when asked",
+    )
+    limbo.run_test_fn(
+        "SELECT CAST(uncompress(compress('hello')) AS TEXT), CAST(uncompress(compress('hello', 'gzip', 9)) AS TEXT);",
+        lambda res: "hello|hello" == res,
+        "uncompress detects the algorithm",
+    )
+    limbo.run_test_fn(
+        "SELECT length(compress(hex(zeroblob(1000)))) < 100;",
+        lambda res: "1" == res,
+        "compress makes repetitive values smaller",
+    )
+    limbo.run_test_fn(
+        "SELECT uncompress(x'0102') IS NULL, uncompress(NULL) IS NULL;",
+        lambda res: "1|1" == res,
+        "uncompress returns NULL for data it can't read",
+    )
+    limbo.execute_dot("CREATE TABLE payloads (data BLOB);")
+    limbo.execute_dot(
+        "INSERT INTO payloads VALUES (compress('{\"a\": 1}')), (compress('{\"b\": 2}', 'gzip'));"
+    )
+    limbo.run_test_fn(
+        "SELECT CAST(uncompress(data) AS TEXT) FROM payloads;",
+        lambda res: '{"a": 1}\n{"b": 2}' == res,
+        "compressed payloads round trip through a table",
+    )
+    limbo.quit()
+
 def test_vfs():
     limbo = TestLimboShell()
     ext_path = "target/debug/liblimbo_ext_tests"
@@ -498,6 +679,34 @@ def test_vfs():
         "Tested large write to testfs",
     )
     print("Tested large write to testfs")
+    limbo.execute_dot(".open --vfs testvfs testing/vfs.db")
+    limbo.run_test_fn(
+        "SELECT count(*) FROM vfs;",
+        lambda res: res == "50",
+        "Reopened db file with .open --vfs",
+    )
+    limbo.execute_dot(".open file:testing/vfs.db?vfs=testvfs")
+    limbo.run_test_fn(
+        "SELECT count(*) FROM test;",
+        lambda res: res == "50",
+        "Reopened db file with a vfs URI parameter",
+    )
+    limbo.run_test_fn(
+        ".open --vfs nosuchvfs testing/vfs.db",
+        lambda res: "no such VFS: nosuchvfs" in res,
+        "Opening with an unknown vfs fails",
+    )
+    limbo.quit()
+
+
+def test_load_non_extension():
+    limbo = TestLimboShell()
+    limbo.run_test_fn(
+        ".load target/debug/liblimbo_sqlite3",
+        lambda res: "not a Limbo extension" in res,
+        "Loading a library that isn't an extension fails",
+    )
+    limbo.run_test_fn("SELECT 1;", lambda res: res == "1", "Shell still works")
     limbo.quit()
 
 
@@ -545,8 +754,12 @@ if __name__ == "__main__":
         test_crypto()
         test_series()
         test_ipaddr()
+        test_geopoly()
+        test_decimal()
+        test_compress()
         test_vfs()
         test_sqlite_vfs_compat()
+        test_load_non_extension()
         test_kv()
     except Exception as e:
         print(f"Test FAILED: {e}")
@@ -0,0 +1,207 @@
+#!/usr/bin/env python3
+import json
+import os
+import re
+import socket
+import subprocess
+import threading
+import time
+import urllib.error
+import urllib.request
+
+limbo_exec = os.getenv("LIMBO_EXEC", "./target/debug/limbo")
+
+
+class LimboServer:
+    def __init__(self, *args):
+        self.proc = subprocess.Popen(
+            [limbo_exec, "serve", "--listen", "127.0.0.1:0", *args],
+            stderr=subprocess.PIPE,
+            text=True,
+        )
+        line = self.proc.stderr.readline()
+        match = re.search(r"http://(\S+)", line)
+        assert match, f"Unexpected startup message: {line!r}"
+        self.url = f"http://{match.group(1)}"
+
+    def post(self, path, body):
+        request = urllib.request.Request(
+            self.url + path,
+            data=json.dumps(body).encode(),
+            headers={"Content-Type": "application/json"},
+        )
+        with urllib.request.urlopen(request, timeout=10) as response:
+            return response.read().decode()
+
+    def pipeline(self, requests, baton=None):
+        return json.loads(
+            self.post("/v2/pipeline", {"baton": baton, "requests": requests})
+        )
+
+    def stop(self):
+        self.proc.terminate()
+        self.proc.wait()
+
+
+def execute(sql, args=None):
+    return {"type": "execute", "stmt": {"sql": sql, "args": args or []}}
+
+
+def integer(value):
+    return {"type": "integer", "value": str(value)}
+
+
+def test_pipeline(server):
+    resp = server.pipeline(
+        [
+            execute("CREATE TABLE t (a, b)"),
+            execute(
+                "INSERT INTO t VALUES (?, ?)",
+                [integer(1), {"type": "text", "value": "one"}],
+            ),
+            execute("SELECT a, b FROM t"),
+            {"type": "close"},
+        ]
+    )
+    assert resp["baton"] is None, "Closed stream returned a baton"
+    results = [r["response"]["result"] for r in resp["results"][:3]]
+    assert results[1]["affected_row_count"] == 1
+    assert [c["name"] for c in results[2]["cols"]] == ["a", "b"]
+    assert results[2]["rows"] == [[integer(1), {"type": "text", "value": "one"}]]
+    print("test_pipeline passed")
+
+
+def test_stream_transaction(server):
+    resp = server.pipeline(
+        [execute("BEGIN"), execute("INSERT INTO t VALUES (2, 'two')")]
+    )
+    baton = resp["baton"]
+    assert baton, "Open stream did not return a baton"
+    resp = server.pipeline(
+        [{"type": "get_autocommit"}, execute("COMMIT"), {"type": "close"}], baton
+    )
+    assert resp["results"][0]["response"]["is_autocommit"] is False
+    resp = server.pipeline([execute("SELECT count(*) FROM t"), {"type": "close"}])
+    assert resp["results"][0]["response"]["result"]["rows"] == [[integer(2)]]
+    print("test_stream_transaction passed")
+
+
+def test_errors(server):
+    resp = server.pipeline([execute("SELECT * FROM missing"), {"type": "close"}])
+    assert resp["results"][0]["type"] == "error"
+    assert resp["results"][1]["type"] == "ok"
+    try:
+        server.pipeline([], baton="bogus")
+        assert False, "Invalid baton was accepted"
+    except urllib.error.HTTPError as e:
+        assert e.code == 400
+    print("test_errors passed")
+
+
+def test_cursor(server):
+    body = server.post(
+        "/v3/cursor",
+        {
+            "baton": None,
+            "batch": {
+                "steps": [
+                    {"stmt": {"sql": "SELECT a FROM t"}},
+                    {
+                        "condition": {"type": "ok", "step": 0},
+                        "stmt": {"sql": "SELECT 'done'"},
+                    },
+                ]
+            },
+        },
+    )
+    entries = [json.loads(line) for line in body.splitlines()]
+    assert entries[0]["baton"], "Cursor did not return a baton"
+    types = [e["type"] for e in entries[1:]]
+    assert types == [
+        "step_begin",
+        "row",
+        "row",
+        "step_end",
+        "step_begin",
+        "row",
+        "step_end",
+    ], types
+    print("test_cursor passed")
+
+
+def test_expired_transaction(server):
+    server.pipeline([execute("CREATE TABLE u (x)"), {"type": "close"}])
+    resp = server.pipeline([execute("BEGIN"), execute("INSERT INTO u VALUES (1)")])
+    assert resp["baton"], "Open stream did not return a baton"
+    # The stream expires and its transaction is rolled back, leaving nothing behind for the
+    # next stream to see or commit.
+    time.sleep(2)
+    resp = server.pipeline(
+        [
+            execute("INSERT INTO u VALUES (2)"),
+            execute("SELECT count(*) FROM u"),
+            {"type": "close"},
+        ]
+    )
+    assert resp["results"][1]["response"]["result"]["rows"] == [[integer(1)]], resp
+    print("test_expired_transaction passed")
+
+
+def test_stalled_client(server):
+    host, port = server.url.removeprefix("http://").rsplit(":", 1)
+    # A client that never finishes its request is disconnected instead of holding up others.
+    stalled = socket.create_connection((host, int(port)))
+    stalled.sendall(b"GET /health HTTP/1.1\r\n")
+    start = time.monotonic()
+    resp = server.pipeline([execute("SELECT 1"), {"type": "close"}])
+    assert resp["results"][0]["type"] == "ok"
+    assert time.monotonic() - start < 5
+    stalled.close()
+
+    # So is one that keeps sending its request a byte at a time.
+    trickling = socket.create_connection((host, int(port)))
+    trickling.sendall(b"G")
+
+    def trickle():
+        for byte in b"ET /health HTTP/1.1\r\nX-Filler: " + b"x" * 40:
+            time.sleep(0.25)
+            try:
+                trickling.sendall(bytes([byte]))
+            except OSError:
+                return
+
+    trickler = threading.Thread(target=trickle)
+    trickler.start()
+    start = time.monotonic()
+    resp = server.pipeline([execute("SELECT 1"), {"type": "close"}])
+    assert resp["results"][0]["type"] == "ok"
+    assert time.monotonic() - start < 5
+    trickler.join()
+    trickling.close()
+
+    # Oversized headers are rejected.
+    client = socket.create_connection((host, int(port)))
+    client.sendall(b"GET /health HTTP/1.1\r\nX-Filler: " + b"x" * 100_000 + b"\r\n\r\n")
+    data = b""
+    while chunk := client.recv(1024):
+        data += chunk
+    assert data.startswith(b"HTTP/1.1 400")
+    client.close()
+    print("test_stalled_client passed")
+
+
+if __name__ == "__main__":
+    server = LimboServer("--stream-timeout", "1", "--request-timeout", "1")
+    try:
+        test_pipeline(server)
+        test_stream_transaction(server)
+        test_errors(server)
+        test_cursor(server)
+        test_expired_transaction(server)
+        test_stalled_client(server)
+    except Exception as e:
+        print(f"Test FAILED: {e}")
+        server.stop()
+        exit(1)
+    server.stop()
+    print("All tests passed successfully.")
@@ -1,5 +1,15 @@
 use crate::common::TempDatabase;
-use limbo_core::{OwnedValue, StepResult};
+use limbo_core::{
+    CArray, ColumnInfo, ExecutionLimit, ExecutionLimits, ExportFormat, FallibleStreamingIterator,
+    ForeignKey, ForeignKeyAction, FromRow, IndexColumnInfo, LimboError, MemoryPressure, OwnedValue,
+    PagingOptions, RowFilter, StepResult, TableStats, TraceEvent,
+};
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 #[test]
 fn test_statement_reset_bind() -> anyhow::Result<()> {
@@ -99,3 +109,1216 @@ fn test_statement_bind() -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+#[test]
+fn test_statement_bind_carray() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db =
+        TempDatabase::new_with_rusqlite("create table users (id integer primary key, name text);");
+    let conn = tmp_db.connect_limbo();
+    conn.execute("insert into users values (1, 'alice'), (2, 'bob'), (3, 'carol'), (4, 'dave')")?;
+
+    let mut stmt =
+        conn.prepare("select id from users where id in carray(?1) or name in carray(?2)")?;
+    let mut query = |ids: CArray, names: CArray| -> anyhow::Result<Vec<OwnedValue>> {
+        stmt.reset();
+        stmt.bind_at(1.try_into()?, ids.into());
+        stmt.bind_at(2.try_into()?, names.into());
+        let mut rows = Vec::new();
+        loop {
+            match stmt.step()? {
+                StepResult::Row => rows.push(stmt.row().unwrap().get::<&OwnedValue>(0)?.clone()),
+                StepResult::IO => tmp_db.io.run_once()?,
+                _ => break,
+            }
+        }
+        Ok(rows)
+    };
+
+    assert_eq!(
+        query(
+            CArray::from_integers(&[4, 2, 9]),
+            CArray::from_texts(&["carol"])
+        )?,
+        vec![
+            OwnedValue::Integer(2),
+            OwnedValue::Integer(3),
+            OwnedValue::Integer(4)
+        ]
+    );
+    let no_names: &[&str] = &[];
+    assert_eq!(
+        query(CArray::from_integers(&[]), CArray::from_texts(no_names))?,
+        vec![]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_statement_trace() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("create table test (i integer);");
+    let conn = tmp_db.connect_limbo();
+
+    let traced = Rc::new(RefCell::new(Vec::new()));
+    {
+        let traced = traced.clone();
+        conn.trace(
+            false,
+            Some(Box::new(move |event| {
+                if let TraceEvent::Stmt(sql) = event {
+                    traced.borrow_mut().push(sql.to_string());
+                }
+            })),
+        );
+    }
+
+    let mut stmt = conn.prepare("select ?, :name, ?5, ?")?;
+    stmt.bind_at(1.try_into()?, OwnedValue::build_text("it's"));
+    stmt.bind_at(2.try_into()?, OwnedValue::Integer(42));
+    stmt.bind_at(5.try_into()?, OwnedValue::from_blob(vec![0xab, 0x01]));
+    stmt.bind_at(6.try_into()?, OwnedValue::Null);
+    loop {
+        match stmt.step()? {
+            StepResult::IO => tmp_db.io.run_once()?,
+            StepResult::Row => {}
+            _ => break,
+        }
+    }
+
+    conn.trace(false, None);
+    conn.execute("select 1")?;
+
+    assert_eq!(
+        *traced.borrow(),
+        vec!["select 'it''s', 42, X'AB01', NULL".to_string()]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_statement_expanded_and_normalized_sql() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("create table test (i integer, t text);");
+    let conn = tmp_db.connect_limbo();
+
+    let mut stmt = conn.prepare(
+        "select *  from test -- by value
+         where i = ?1 and t = :t and i > -5 and t <> 'x' and ?1 < 1.5",
+    )?;
+    assert_eq!(
+        stmt.normalized_sql(),
+        "select * from test where i = ? and t = ? and i > -? and t <> ? and ? < ?"
+    );
+    assert_eq!(
+        stmt.expanded_sql(),
+        stmt.sql(),
+        "unbound parameters are left as written"
+    );
+
+    stmt.bind_at(1.try_into()?, OwnedValue::Integer(7));
+    stmt.bind_at(2.try_into()?, OwnedValue::build_text("it's"));
+    assert_eq!(
+        stmt.expanded_sql(),
+        "select *  from test -- by value
+         where i = 7 and t = 'it''s' and i > -5 and t <> 'x' and 7 < 1.5"
+    );
+
+    let other = conn.prepare("SELECT * FROM test WHERE i = 1 AND t = 'a'")?;
+    let same = conn.prepare("SELECT * FROM test WHERE i = 2 AND t = 'b'")?;
+    assert_eq!(other.normalized_sql(), same.normalized_sql());
+    Ok(())
+}
+
+#[test]
+fn test_explain_comments_and_vdbe_trace() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("create table test (i integer);");
+    let conn = tmp_db.connect_limbo();
+
+    let explain = conn.prepare("select i from test")?.explain();
+    assert!(explain.contains("table=test, root=2"), "{explain}");
+    assert!(explain.contains("=test.i"), "{explain}");
+    assert!(explain.contains("if test has next goto"), "{explain}");
+
+    let traced = Rc::new(RefCell::new(Vec::new()));
+    {
+        let traced = traced.clone();
+        conn.trace(
+            false,
+            Some(Box::new(move |event| {
+                if let TraceEvent::Registers(registers) = event {
+                    traced.borrow_mut().push(registers.to_string());
+                }
+            })),
+        );
+    }
+    conn.execute("select 'a' || 'b', 40 + 2")?;
+    assert!(traced.borrow().is_empty());
+
+    conn.execute("PRAGMA vdbe_trace = ON")?;
+    let mut stmt = conn.prepare("select 'a' || 'b', 40 + 2")?;
+    loop {
+        match stmt.step()? {
+            StepResult::IO => tmp_db.io.run_once()?,
+            StepResult::Row => {}
+            _ => break,
+        }
+    }
+    let traced = traced.borrow().join(" ");
+    assert!(traced.contains("='ab'"), "{traced}");
+    assert!(traced.contains("=42"), "{traced}");
+    Ok(())
+}
+
+#[test]
+fn test_statement_profile() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("create table test (i integer);");
+    let conn = tmp_db.connect_limbo();
+    conn.execute("insert into test values (1), (2), (3)")?;
+
+    let mut stmt = conn.prepare("select i from test")?;
+    assert!(stmt.profile().is_empty());
+    stmt.set_profiling(true);
+    let mut rows = 0;
+    loop {
+        match stmt.step()? {
+            StepResult::IO => tmp_db.io.run_once()?,
+            StepResult::Row => rows += 1,
+            _ => break,
+        }
+    }
+    assert_eq!(rows, 3);
+    let profile = stmt.profile();
+    let result_row = profile
+        .iter()
+        .find(|insn| insn.insn.contains("ResultRow"))
+        .unwrap();
+    assert_eq!(result_row.executions, 3);
+    let init = profile.iter().find(|insn| insn.addr == 0).unwrap();
+    assert!(init.insn.contains("Init"), "{}", init.insn);
+    assert_eq!(init.executions, 1);
+
+    stmt.reset();
+    assert!(stmt.profile().iter().all(|insn| insn.executions == 0));
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, FromRow)]
+struct Product {
+    id: i64,
+    #[column(name = "product_name")]
+    name: String,
+    price: f64,
+    note: Option<String>,
+}
+
+#[test]
+fn test_statement_query_as() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite(
+        "create table products (id integer primary key, product_name text, price real, note text);",
+    );
+    let conn = tmp_db.connect_limbo();
+    conn.execute("insert into products values (1, 'hat', 9.5, null), (2, 'cap', 3, 'blue')")?;
+
+    // Columns are matched by name, not position.
+    let mut stmt = conn.prepare("select note, price, product_name, id from products")?;
+    let products = stmt
+        .query_as::<Product>()?
+        .collect::<limbo_core::Result<Vec<_>>>()?;
+    assert_eq!(
+        products,
+        vec![
+            Product {
+                id: 1,
+                name: "hat".to_string(),
+                price: 9.5,
+                note: None,
+            },
+            Product {
+                id: 2,
+                name: "cap".to_string(),
+                price: 3.0,
+                note: Some("blue".to_string()),
+            },
+        ]
+    );
+
+    let mut stmt = conn.prepare("select id, product_name, price from products")?;
+    let Err(err) = stmt.query_as::<Product>() else {
+        panic!("query without a note column should fail");
+    };
+    assert_eq!(
+        err.to_string(),
+        "Invalid argument supplied: query has no column named note"
+    );
+
+    // A value of the wrong type fails the row rather than panicking.
+    let mut stmt =
+        conn.prepare("select product_name as id, product_name, price, note from products")?;
+    let products = stmt
+        .query_as::<Product>()?
+        .collect::<limbo_core::Result<Vec<_>>>();
+    assert!(products.is_err());
+    Ok(())
+}
+
+#[test]
+fn test_statement_row_serde() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("create table t (a, b, c, d);");
+    let conn = tmp_db.connect_limbo();
+    conn.execute("insert into t values (1, 2.5, 'x', x'0aff'), (null, -1, '', null)")?;
+
+    let mut stmt = conn.prepare("select a, b, c as name, d from t")?;
+    let mut objects = Vec::new();
+    let mut arrays = Vec::new();
+    loop {
+        match stmt.step()? {
+            StepResult::Row => {
+                objects.push(serde_json::to_string(&stmt.row_map().unwrap())?);
+                arrays.push(serde_json::to_string(stmt.row().unwrap())?);
+            }
+            StepResult::IO => tmp_db.io.run_once()?,
+            _ => break,
+        }
+    }
+    assert_eq!(
+        objects,
+        vec![
+            r#"{"a":1,"b":2.5,"name":"x","d":[10,255]}"#,
+            r#"{"a":null,"b":-1,"name":"","d":null}"#,
+        ]
+    );
+    assert_eq!(
+        arrays,
+        vec![r#"[1,2.5,"x",[10,255]]"#, r#"[null,-1,"",null]"#]
+    );
+
+    let values: Vec<OwnedValue> = serde_json::from_str(r#"[null, 7, 1.5, "s", [1, 2], true]"#)?;
+    assert_eq!(
+        values,
+        vec![
+            OwnedValue::Null,
+            OwnedValue::Integer(7),
+            OwnedValue::Float(1.5),
+            OwnedValue::build_text("s"),
+            OwnedValue::from_blob(vec![1, 2]),
+            OwnedValue::Integer(1),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_statement_query_rows() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("create table t (x integer, y text);");
+    let conn = tmp_db.connect_limbo();
+    conn.execute("insert into t values (1, 'a'), (2, 'b'), (3, 'c')")?;
+
+    let mut stmt = conn.prepare("select x, y from t where x >= ? order by x")?;
+    let mut seen = Vec::new();
+    for row in stmt.query([OwnedValue::Integer(2)])? {
+        seen.push(row?);
+    }
+    assert_eq!(
+        seen,
+        vec![
+            vec![OwnedValue::Integer(2), OwnedValue::build_text("b")],
+            vec![OwnedValue::Integer(3), OwnedValue::build_text("c")],
+        ]
+    );
+
+    // The statement can be queried again with other parameters, streaming the rows in place.
+    let mut rows = stmt.query([OwnedValue::Integer(1)])?;
+    let mut xs = Vec::new();
+    while let Some(row) = rows.next()? {
+        xs.push(row.get::<i64>(0)?);
+    }
+    assert_eq!(xs, vec![1, 2, 3]);
+    assert!(rows.next()?.is_none());
+
+    let mut stmt = conn.prepare("select count(*) from t")?;
+    assert_eq!(stmt.query([])?.count()?, 1);
+    assert!(stmt
+        .query([OwnedValue::Integer(1), OwnedValue::Integer(2)])
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn test_statement_limits() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("create table t (x integer);");
+    let conn = tmp_db.connect_limbo();
+    let values: Vec<String> = (1..=1000).map(|i| format!("({i})")).collect();
+    conn.execute(format!("insert into t values {}", values.join(", ")))?;
+
+    let mut stmt = conn.prepare("select x from t order by x")?;
+    stmt.set_limits(ExecutionLimits {
+        max_rows: Some(5),
+        ..Default::default()
+    });
+    let mut rows = stmt.query([])?;
+    assert_eq!(rows.next_chunk(2)?.len(), 2);
+    assert_eq!(
+        rows.next_chunk(3)?,
+        vec![
+            vec![OwnedValue::Integer(3)],
+            vec![OwnedValue::Integer(4)],
+            vec![OwnedValue::Integer(5)],
+        ]
+    );
+    assert!(matches!(
+        rows.next_chunk(1),
+        Err(LimboError::LimitExceeded(ExecutionLimit::Rows))
+    ));
+
+    // The budget is per execution.
+    let mut rows = stmt.query([])?;
+    assert_eq!(rows.next_chunk(5)?.len(), 5);
+    stmt.set_limits(ExecutionLimits::default());
+    assert_eq!(stmt.query([])?.next_chunk(2000)?.len(), 1000);
+
+    let mut stmt = conn.prepare("select count(*) from t where x > 0")?;
+    stmt.set_limits(ExecutionLimits {
+        max_steps: Some(100),
+        ..Default::default()
+    });
+    assert!(matches!(
+        stmt.query([])?.next(),
+        Err(LimboError::LimitExceeded(ExecutionLimit::Steps))
+    ));
+
+    let mut stmt = conn.prepare("select count(*) from t a, t b")?;
+    stmt.set_limits(ExecutionLimits {
+        max_duration: Some(Duration::from_millis(1)),
+        ..Default::default()
+    });
+    assert!(matches!(
+        stmt.query([])?.next(),
+        Err(LimboError::LimitExceeded(ExecutionLimit::Duration))
+    ));
+
+    // The connection is usable after an aborted statement.
+    let mut stmt = conn.prepare("select count(*) from t")?;
+    let mut rows = stmt.query([])?;
+    assert_eq!(rows.next()?.unwrap().get::<i64>(0)?, 1000);
+    Ok(())
+}
+
+#[test]
+fn test_table_stats() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db =
+        TempDatabase::new_with_rusqlite("create table t (x integer primary key, y integer);");
+    let conn = tmp_db.connect_limbo();
+    conn.execute("create index t_y on t (y)")?;
+    let stats = |conn: &Rc<limbo_core::Connection>| {
+        conn.table_stats()
+            .into_iter()
+            .find(|(name, _)| name == "t")
+            .map(|(_, stats)| stats)
+            .unwrap_or_default()
+    };
+    let run = |sql: &str| -> anyhow::Result<()> {
+        let mut stmt = conn.prepare(sql)?;
+        let mut rows = stmt.query([])?;
+        while rows.next()?.is_some() {}
+        Ok(())
+    };
+
+    // Building the index scanned the empty table, and inserting with a rowid looks it up
+    // first to check it isn't taken.
+    run("insert into t values (1, 10), (2, 20), (3, 30)")?;
+    assert_eq!(
+        stats(&conn),
+        TableStats {
+            rows_written: 3,
+            seeks: 3,
+            full_scans: 1,
+            ..Default::default()
+        }
+    );
+
+    run("select * from t")?;
+    assert_eq!(stats(&conn).full_scans, 2);
+    assert_eq!(stats(&conn).rows_read, 3);
+
+    run("select * from t where x = 2")?;
+    run("select x from t where y = 30")?;
+    let after_seeks = stats(&conn);
+    assert_eq!(after_seeks.seeks, 5);
+    assert_eq!(after_seeks.full_scans, 2);
+
+    let mut stmt = conn.prepare("pragma limbo_table_stats(t)")?;
+    let mut rows = stmt.query([])?;
+    let row = rows.next()?.unwrap();
+    let values: Vec<OwnedValue> = row.get_values().cloned().collect();
+    assert_eq!(
+        values,
+        vec![
+            OwnedValue::build_text("t"),
+            OwnedValue::Integer(after_seeks.rows_read as i64),
+            OwnedValue::Integer(3),
+            OwnedValue::Integer(5),
+            OwnedValue::Integer(2),
+        ]
+    );
+    assert!(rows.next()?.is_none());
+    Ok(())
+}
+
+#[test]
+fn test_deferred_seek() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite(
+        "create table t (x integer primary key, y integer, z text);",
+    );
+    let conn = tmp_db.connect_limbo();
+    conn.execute("insert into t values (1, 10, 'a'), (2, 20, 'b'), (3, 30, 'c'), (4, 40, 'd')")?;
+    conn.execute("create index t_y on t (y)")?;
+    let seeks = |conn: &Rc<limbo_core::Connection>| {
+        conn.table_stats()
+            .into_iter()
+            .find(|(name, _)| name == "t")
+            .map(|(_, stats)| stats.seeks)
+            .unwrap_or_default()
+    };
+    let query = |sql: &str| -> anyhow::Result<Vec<Vec<OwnedValue>>> {
+        let mut stmt = conn.prepare(sql)?;
+        let mut rows = stmt.query([])?;
+        let mut values = Vec::new();
+        while let Some(row) = rows.next()? {
+            values.push(row.get_values().cloned().collect());
+        }
+        Ok(values)
+    };
+
+    // The rowid and y are in the index, so the table is not looked up at all.
+    let before = seeks(&conn);
+    assert_eq!(
+        query("select x, y from t where y > 15 and y % 20 = 0")?,
+        vec![
+            vec![OwnedValue::Integer(2), OwnedValue::Integer(20)],
+            vec![OwnedValue::Integer(4), OwnedValue::Integer(40)],
+        ]
+    );
+    assert_eq!(seeks(&conn) - before, 1);
+
+    // z is not in the index, so the table is looked up, but only for the rows that pass the
+    // condition on y.
+    let before = seeks(&conn);
+    assert_eq!(
+        query("select z from t where y > 15 and y % 20 = 0")?,
+        vec![
+            vec![OwnedValue::build_text("b")],
+            vec![OwnedValue::build_text("d")],
+        ]
+    );
+    assert_eq!(seeks(&conn) - before, 1 + 2);
+    Ok(())
+}
+
+#[test]
+fn test_row_filter() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite(
+        "create table docs (id integer primary key, tenant_id integer, title text);",
+    );
+    let conn = tmp_db.connect_limbo();
+    conn.execute("create table tags (doc_id integer, tenant_id integer, tag text)")?;
+    conn.execute("insert into docs values (1, 1, 'a'), (2, 2, 'b'), (3, 1, 'c')")?;
+    conn.execute("insert into tags values (1, 1, 'x'), (2, 2, 'y'), (3, 2, 'z')")?;
+    let query = |sql: &str| -> anyhow::Result<Vec<Vec<OwnedValue>>> {
+        let mut stmt = conn.prepare(sql)?;
+        let rows = stmt.query([])?.into_iter().collect::<Result<_, _>>()?;
+        Ok(rows)
+    };
+
+    let tenant = Rc::new(Cell::new(1));
+    {
+        let tenant = tenant.clone();
+        conn.set_row_filter(Some(Box::new(move |table| {
+            (table == "docs" || table == "tags").then(|| {
+                RowFilter::new("tenant_id = ?", &[OwnedValue::Integer(tenant.get())]).unwrap()
+            })
+        })));
+    }
+    let ids = "select id from docs order by id";
+    assert_eq!(
+        query(ids)?,
+        vec![vec![OwnedValue::Integer(1)], vec![OwnedValue::Integer(3)]]
+    );
+    // Hidden rows of the right side of an outer join are completed with NULLs.
+    assert_eq!(
+        query("select d.id, t.tag from docs d left join tags t on t.doc_id = d.id order by d.id")?,
+        vec![
+            vec![OwnedValue::Integer(1), OwnedValue::build_text("x")],
+            vec![OwnedValue::Integer(3), OwnedValue::Null],
+        ]
+    );
+    assert_eq!(
+        query("select count(*) from (select * from tags)")?,
+        vec![vec![OwnedValue::Integer(1)]]
+    );
+
+    // The hook runs on every prepare, so cached programs don't leak rows across tenants.
+    tenant.set(2);
+    assert_eq!(query(ids)?, vec![vec![OwnedValue::Integer(2)]]);
+    conn.execute("update docs set title = 'new'")?;
+    tenant.set(1);
+    conn.execute("delete from docs where id = 2")?;
+
+    conn.set_row_filter(None);
+    assert_eq!(
+        query("select id, title from docs order by id")?,
+        vec![
+            vec![OwnedValue::Integer(1), OwnedValue::build_text("a")],
+            vec![OwnedValue::Integer(2), OwnedValue::build_text("new")],
+            vec![OwnedValue::Integer(3), OwnedValue::build_text("c")],
+        ]
+    );
+
+    assert!(RowFilter::new("tenant_id = ?", &[]).is_err());
+    assert!(RowFilter::new("tenant_id = 1) or (1", &[]).is_err());
+    assert!(RowFilter::new("1; drop table docs", &[]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_salvage_mode() -> anyhow::Result<()> {
+    const PAGE_SIZE: usize = 4096;
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE t (x)");
+    let root_page: usize = {
+        let conn = rusqlite::Connection::open(&tmp_db.path)?;
+        for i in 1..=1000 {
+            conn.execute("INSERT INTO t VALUES (?)", [format!("{i:0100}")])?;
+        }
+        conn.query_row(
+            "SELECT rootpage FROM sqlite_schema WHERE name = 't'",
+            [],
+            |row| row.get(0),
+        )?
+    };
+
+    // The root is an interior page whose cells point to the leaves, each cell keyed by the
+    // largest rowid of its leaf.
+    let mut file = std::fs::read(&tmp_db.path)?;
+    let root = &file[(root_page - 1) * PAGE_SIZE..root_page * PAGE_SIZE];
+    assert_eq!(root[0], 5);
+    let leaf = |idx: usize| -> (usize, i64) {
+        let cell = u16::from_be_bytes([root[12 + idx * 2], root[13 + idx * 2]]) as usize;
+        let child = u32::from_be_bytes(root[cell..cell + 4].try_into().unwrap()) as usize;
+        let mut key = 0i64;
+        for byte in &root[cell + 4..] {
+            key = (key << 7) | (byte & 0x7f) as i64;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        (child, key)
+    };
+    let (first_leaf, first_key) = leaf(0);
+    let (_, second_key) = leaf(1);
+    let (third_leaf, third_key) = leaf(2);
+    // One leaf gets an invalid page type, another a cell pointer past the end of the page.
+    file[(first_leaf - 1) * PAGE_SIZE] = 0x42;
+    file[(third_leaf - 1) * PAGE_SIZE + 8..(third_leaf - 1) * PAGE_SIZE + 10]
+        .copy_from_slice(&[0xff, 0xff]);
+    std::fs::write(&tmp_db.path, file)?;
+
+    let conn = tmp_db.connect_limbo();
+    conn.set_salvage_mode(true);
+    let mut stmt = conn.prepare("SELECT rowid FROM t")?;
+    let mut rowids = Vec::new();
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        rowids.push(row.get::<i64>(0)?);
+    }
+    let expected: Vec<i64> = (first_key + 1..=second_key)
+        .chain(third_key + 1..=1000)
+        .collect();
+    assert_eq!(rowids, expected);
+    let skipped: Vec<usize> = stmt.corrupt_pages().iter().map(|p| p.page_id).collect();
+    assert_eq!(skipped, vec![first_leaf, third_leaf]);
+    assert_eq!(
+        stmt.corrupt_pages()[0].reason,
+        format!("invalid page type {}", 0x42)
+    );
+
+    stmt.reset();
+    assert!(stmt.corrupt_pages().is_empty());
+    Ok(())
+}
+
+/// Opens the database as untrusted and reads every row of every table, returning how many there were.
+fn scan_untrusted(tmp_db: &TempDatabase) -> limbo_core::Result<usize> {
+    let db =
+        limbo_core::Database::open_untrusted(tmp_db.io.clone(), tmp_db.path.to_str().unwrap())?;
+    let conn = db.connect()?;
+    let mut tables = Vec::new();
+    {
+        let mut stmt = conn.prepare("SELECT name FROM sqlite_schema WHERE type = 'table'")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            tables.push(row.get::<String>(0)?);
+        }
+    }
+    let mut count = 0;
+    for table in tables {
+        let mut stmt = conn.prepare(format!("SELECT * FROM {table}"))?;
+        let mut rows = stmt.query([])?;
+        while rows.next()?.is_some() {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+#[test]
+fn test_open_untrusted() -> anyhow::Result<()> {
+    const PAGE_SIZE: usize = 4096;
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE t (x)");
+    {
+        let conn = rusqlite::Connection::open(&tmp_db.path)?;
+        for i in 1..=200 {
+            conn.execute("INSERT INTO t VALUES (?)", [format!("{i:0100}")])?;
+        }
+        conn.execute("INSERT INTO t VALUES (?)", ["x".repeat(10000)])?;
+    }
+    let file = std::fs::read(&tmp_db.path)?;
+    assert_eq!(scan_untrusted(&tmp_db)?, 201);
+
+    // An invalid page type in the root page of the table.
+    let mut corrupted = file.clone();
+    corrupted[PAGE_SIZE * 2] = 0x42;
+    std::fs::write(&tmp_db.path, &corrupted)?;
+    assert!(matches!(
+        scan_untrusted(&tmp_db),
+        Err(limbo_core::LimboError::Corrupt(_))
+    ));
+
+    // No single damaged byte may bring the reader down.
+    for offset in (0..file.len()).step_by(17) {
+        let mut corrupted = file.clone();
+        corrupted[offset] ^= 0xff;
+        std::fs::write(&tmp_db.path, &corrupted)?;
+        let result =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| scan_untrusted(&tmp_db)));
+        assert!(result.is_ok(), "panicked with byte {offset} flipped");
+    }
+    Ok(())
+}
+
+/// Runs an integrity check pragma on a fresh connection and returns the lines it reported.
+fn integrity_check(tmp_db: &TempDatabase, pragma: &str) -> anyhow::Result<Vec<String>> {
+    let conn = tmp_db.connect_limbo();
+    let mut stmt = conn.prepare(pragma)?;
+    let mut rows = stmt.query([])?;
+    let mut lines = Vec::new();
+    while let Some(row) = rows.next()? {
+        lines.extend(row.get::<String>(0)?.lines().map(str::to_string));
+    }
+    Ok(lines)
+}
+
+#[test]
+fn test_integrity_check() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE t (id INTEGER PRIMARY KEY, a, b)");
+    {
+        let conn = rusqlite::Connection::open(&tmp_db.path)?;
+        conn.execute("CREATE INDEX ta ON t (a)", ())?;
+        conn.execute("CREATE UNIQUE INDEX tb ON t (b)", ())?;
+        for i in 1..=300 {
+            conn.execute(
+                "INSERT INTO t VALUES (?, ?, ?)",
+                (
+                    i,
+                    format!("{i:050}"),
+                    format!("{i}{}", "x".repeat(i % 7 * 1000)),
+                ),
+            )?;
+        }
+    }
+    assert_eq!(integrity_check(&tmp_db, "PRAGMA integrity_check")?, ["ok"]);
+    assert_eq!(integrity_check(&tmp_db, "PRAGMA quick_check")?, ["ok"]);
+    assert_eq!(
+        integrity_check(&tmp_db, "PRAGMA integrity_check(t)")?,
+        ["ok"]
+    );
+    assert!(integrity_check(&tmp_db, "PRAGMA integrity_check(nope)").is_err());
+    assert_eq!(integrity_check(&tmp_db, "PRAGMA index_check")?, ["ok"]);
+    assert_eq!(integrity_check(&tmp_db, "PRAGMA index_check(tb)")?, ["ok"]);
+
+    // Point the index at another column, so that none of its entries match the rows.
+    {
+        let conn = rusqlite::Connection::open(&tmp_db.path)?;
+        conn.execute_batch(
+            "PRAGMA writable_schema = ON;
+             UPDATE sqlite_schema SET sql = 'CREATE INDEX ta ON t (id)' WHERE name = 'ta';",
+        )?;
+    }
+    assert_eq!(
+        integrity_check(&tmp_db, "PRAGMA integrity_check(2)")?,
+        ["row 1 missing from index ta", "row 2 missing from index ta"]
+    );
+    assert_eq!(integrity_check(&tmp_db, "PRAGMA quick_check")?, ["ok"]);
+    assert_eq!(integrity_check(&tmp_db, "PRAGMA index_check(tb)")?, ["ok"]);
+
+    // Point it at a column whose values all sort after the ones it has, so that its entries
+    // come up as stray ones first.
+    {
+        let conn = rusqlite::Connection::open(&tmp_db.path)?;
+        conn.execute_batch(
+            "PRAGMA writable_schema = ON;
+             UPDATE sqlite_schema SET sql = 'CREATE INDEX ta ON t (b)' WHERE name = 'ta';",
+        )?;
+    }
+    let errors = integrity_check(&tmp_db, "PRAGMA index_check(ta)")?;
+    assert_eq!(
+        errors[..2],
+        [
+            "stray entry for row 1 in index ta",
+            "stray entry for row 2 in index ta"
+        ]
+    );
+    assert_eq!(errors.len(), 100);
+
+    // Claim a freelist page that doesn't exist.
+    let mut file = std::fs::read(&tmp_db.path)?;
+    file[36..40].copy_from_slice(&1u32.to_be_bytes());
+    std::fs::write(&tmp_db.path, &file)?;
+    assert_eq!(
+        integrity_check(&tmp_db, "PRAGMA quick_check")?,
+        ["Main freelist: size is 0 but should be 1"]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_statement_allocation_stats() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db =
+        TempDatabase::new_with_rusqlite("create table t (id integer primary key, x text);");
+    let conn = tmp_db.connect_limbo();
+    for i in 0..40 {
+        conn.execute(format!("insert into t values ({i}, 'row {i}')"))?;
+    }
+
+    let run = |stmt: &mut limbo_core::Statement| -> anyhow::Result<usize> {
+        let mut rows = 0;
+        loop {
+            match stmt.step()? {
+                StepResult::Row => rows += 1,
+                StepResult::IO => tmp_db.io.run_once()?,
+                _ => break,
+            }
+        }
+        stmt.reset();
+        Ok(rows)
+    };
+    // A sort, and a join that builds an automatic index on `b.x`. The sorter that fills the
+    // index holds all rows at once, so there are few enough for the arena to keep all buffers.
+    let queries = [
+        ("select id, x from t order by x desc limit 10", 10),
+        ("select a.id, b.id from t a join t b on a.x = b.x", 40),
+    ];
+    for (sql, expected) in queries {
+        let mut stmt = conn.prepare(sql)?;
+        assert_eq!(run(&mut stmt)?, expected);
+        let warm = stmt.allocation_stats();
+        assert!(warm.allocations > 0);
+        // The records and cursors of the next execution reuse the buffers of the first one.
+        assert_eq!(run(&mut stmt)?, expected);
+        let stats = stmt.allocation_stats();
+        assert_eq!(stats.allocations, warm.allocations, "{sql}: {stats:?}");
+        assert!(stats.reuses > warm.reuses);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_temp_store_spill() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db =
+        TempDatabase::new_with_rusqlite("create table t (id integer primary key, x text);");
+    let conn = tmp_db.connect_limbo();
+    // About 3MB of rows, well beyond the default cache size of 2000KiB.
+    for batch in 0..30 {
+        conn.execute("BEGIN")?;
+        for i in batch * 100..(batch + 1) * 100 {
+            conn.execute(format!("insert into t values ({i}, '{i:0900}')"))?;
+        }
+        conn.execute("COMMIT")?;
+    }
+
+    // A materialized subquery and an automatic index, both bigger than the cache.
+    let queries = [
+        "with big as materialized (select id, x from t) \
+         select count(*), sum(length(x)), sum(id) from big",
+        "select count(*), sum(length(b.x)), sum(a.id) from t a join t b on a.x = b.x",
+    ];
+    let mut results = Vec::new();
+    for temp_store in ["memory", "file"] {
+        conn.execute(format!("PRAGMA temp_store = {temp_store}"))?;
+        for query in queries {
+            let mut stmt = conn.prepare(query)?;
+            loop {
+                match stmt.step()? {
+                    StepResult::Row => {
+                        let row = stmt.row().unwrap();
+                        results.push((row.get::<i64>(0)?, row.get::<i64>(1)?, row.get::<i64>(2)?));
+                    }
+                    StepResult::IO => tmp_db.io.run_once()?,
+                    _ => break,
+                }
+            }
+        }
+    }
+    assert_eq!(results, vec![(3000, 3000 * 900, 2999 * 3000 / 2); 4]);
+
+    let mut stmt = conn.prepare("PRAGMA temp_store")?;
+    loop {
+        match stmt.step()? {
+            StepResult::Row => assert_eq!(stmt.row().unwrap().get::<i64>(0)?, 1),
+            StepResult::IO => tmp_db.io.run_once()?,
+            _ => break,
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_schema_introspection() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite(
+        "CREATE TABLE parent (id INTEGER PRIMARY KEY, name TEXT NOT NULL DEFAULT 'none');",
+    );
+    let conn = tmp_db.connect_limbo();
+    conn.execute(
+        "CREATE TABLE child (a INT, b TEXT REFERENCES parent ON DELETE CASCADE, c, \
+         PRIMARY KEY (c, a), FOREIGN KEY (a, c) REFERENCES other (x, y) ON UPDATE SET NULL)",
+    )?;
+    conn.execute("CREATE INDEX child_b ON child (b DESC, a)")?;
+
+    let tables = conn.tables()?;
+    let names: Vec<&str> = tables.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, vec!["child", "parent", "sqlite_schema"]);
+    assert!(tables.iter().all(|t| t.has_rowid && !t.is_virtual));
+
+    let columns = conn.columns("parent")?;
+    assert_eq!(
+        columns,
+        vec![
+            ColumnInfo {
+                name: "id".to_string(),
+                declared_type: "INTEGER".to_string(),
+                not_null: false,
+                default_value: None,
+                primary_key: 1,
+                is_rowid_alias: true,
+            },
+            ColumnInfo {
+                name: "name".to_string(),
+                declared_type: "TEXT".to_string(),
+                not_null: true,
+                default_value: Some("'none'".to_string()),
+                primary_key: 0,
+                is_rowid_alias: false,
+            },
+        ]
+    );
+    let primary_key: Vec<(String, usize)> = conn
+        .columns("CHILD")?
+        .into_iter()
+        .map(|c| (c.name, c.primary_key))
+        .collect();
+    assert_eq!(
+        primary_key,
+        vec![
+            ("a".to_string(), 2),
+            ("b".to_string(), 0),
+            ("c".to_string(), 1)
+        ]
+    );
+
+    let indexes = conn.indexes("child")?;
+    let index_names: Vec<(&str, bool, bool)> = indexes
+        .iter()
+        .map(|i| (i.name.as_str(), i.unique, i.automatic))
+        .collect();
+    assert_eq!(
+        index_names,
+        vec![
+            ("child_b", false, false),
+            ("sqlite_autoindex_child_1", true, true)
+        ]
+    );
+    assert_eq!(
+        indexes[0].columns,
+        vec![
+            IndexColumnInfo {
+                name: "b".to_string(),
+                descending: true
+            },
+            IndexColumnInfo {
+                name: "a".to_string(),
+                descending: false
+            },
+        ]
+    );
+    assert!(conn.indexes("parent")?.is_empty());
+
+    assert_eq!(
+        conn.foreign_keys("child")?,
+        vec![
+            ForeignKey {
+                columns: vec!["b".to_string()],
+                parent_table: "parent".to_string(),
+                parent_columns: vec![],
+                on_delete: ForeignKeyAction::Cascade,
+                on_update: ForeignKeyAction::NoAction,
+            },
+            ForeignKey {
+                columns: vec!["a".to_string(), "c".to_string()],
+                parent_table: "other".to_string(),
+                parent_columns: vec!["x".to_string(), "y".to_string()],
+                on_delete: ForeignKeyAction::NoAction,
+                on_update: ForeignKeyAction::SetNull,
+            },
+        ]
+    );
+    assert!(conn.foreign_keys("parent")?.is_empty());
+    assert!(conn.columns("missing").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_pragma_function_list() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("create table test (i integer);");
+    let conn = tmp_db.connect_limbo();
+
+    let mut stmt = conn.prepare("PRAGMA function_list")?;
+    let mut functions = Vec::new();
+    loop {
+        match stmt.step()? {
+            StepResult::Row => {
+                let row = stmt.row().unwrap();
+                functions.push((
+                    row.get::<&str>(0)?.to_string(),
+                    row.get::<i64>(1)?,
+                    row.get::<&str>(2)?.to_string(),
+                    row.get::<i64>(4)?,
+                ));
+            }
+            StepResult::IO => tmp_db.io.run_once()?,
+            _ => break,
+        }
+    }
+    let find = |name: &str| {
+        functions
+            .iter()
+            .filter(|(n, ..)| n == name)
+            .map(|(_, builtin, ty, narg)| (*builtin, ty.as_str(), *narg))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(find("substr"), vec![(1, "s", 2), (1, "s", 3)]);
+    assert_eq!(find("count"), vec![(1, "a", 0), (1, "a", 1)]);
+    assert_eq!(find("max"), vec![(1, "s", -1), (1, "a", 1)]);
+    assert!(find("no_such_function").is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_memory_limit_spills_sorter() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db =
+        TempDatabase::new_with_rusqlite("create table t (id integer primary key, x text);");
+    {
+        let conn = rusqlite::Connection::open(&tmp_db.path)?;
+        conn.execute_batch(
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 5000)
+             INSERT INTO t SELECT i, printf('%08d', (i * 7919) % 5000) || zeroblob(100) FROM n;",
+        )?;
+    }
+    let db = tmp_db.limbo_database();
+    let pressure = Arc::new(Mutex::new(Vec::new()));
+    {
+        let pressure = pressure.clone();
+        db.on_memory_pressure(Some(Box::new(move |event| {
+            pressure.lock().unwrap().push(event)
+        })));
+    }
+    assert_eq!(db.set_memory_limit(64 * 1024), 0);
+    assert_eq!(db.memory_limit(), 64 * 1024);
+    let conn = db.connect()?;
+
+    let mut stmt = conn.prepare("SELECT id, x FROM t ORDER BY x DESC")?;
+    let mut ids = Vec::new();
+    let mut previous: Option<String> = None;
+    loop {
+        match stmt.step()? {
+            StepResult::Row => {
+                let row = stmt.row().unwrap();
+                let x = row.get::<&str>(1)?.to_string();
+                assert!(previous.as_ref().is_none_or(|previous| *previous >= x));
+                ids.push(row.get::<i64>(0)?);
+                previous = Some(x);
+            }
+            StepResult::IO => tmp_db.io.run_once()?,
+            _ => break,
+        }
+    }
+    drop(stmt);
+    ids.sort();
+    assert_eq!(ids, (1..=5000).collect::<Vec<_>>());
+
+    let pressure = pressure.lock().unwrap();
+    assert!(pressure
+        .iter()
+        .any(|event| matches!(event, MemoryPressure::Sorter { .. })));
+    assert!(pressure
+        .iter()
+        .any(|event| matches!(event, MemoryPressure::PageCache { .. })));
+    assert!(db.memory_used() <= 64 * 1024);
+    Ok(())
+}
+
+#[test]
+fn test_statement_prefetch() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("create table t (x integer primary key, y text);");
+    let conn = tmp_db.connect_limbo();
+    // Enough rows for the table to have interior pages.
+    conn.execute("begin")?;
+    for i in 1..=2000 {
+        conn.execute(format!("insert into t values ({i}, '{}')", "y".repeat(200)))?;
+    }
+    conn.execute("commit")?;
+
+    let mut stmt = conn.prepare("select length(y) from t where x = ?")?;
+    let keys = [1, 7, 7, 999, 2000, 5000, -3];
+    stmt.prefetch(&keys)?;
+    for key in keys {
+        let mut rows = stmt.query([OwnedValue::Integer(key)])?;
+        let row = rows.next()?.map(|row| row.get::<i64>(0)).transpose()?;
+        assert_eq!(row, (1..=2000).contains(&key).then_some(200), "key {}", key);
+    }
+
+    // Statements that don't look rows up by rowid have nothing to prefetch.
+    let stmt = conn.prepare("select count(*) from t")?;
+    stmt.prefetch(&keys)?;
+
+    // Within a transaction, the pages come from its snapshot.
+    conn.execute("begin")?;
+    conn.execute("delete from t where x = 999")?;
+    let mut stmt = conn.prepare("select y from t where x = ?")?;
+    stmt.prefetch(&[999, 1000])?;
+    assert!(stmt.query([OwnedValue::Integer(999)])?.next()?.is_none());
+    assert!(stmt.query([OwnedValue::Integer(1000)])?.next()?.is_some());
+    conn.execute("commit")?;
+    Ok(())
+}
+
+#[test]
+fn test_export_table() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db =
+        TempDatabase::new_with_rusqlite("create table t (a integer, b text, c real, d blob);");
+    let conn = tmp_db.connect_limbo();
+    conn.execute(
+        "insert into t values (1, 'plain', 1.5, null), (2, 'a, \"b\"', null, x'00ff'), (3, 'x\ny', -2.0, x'41')",
+    )?;
+
+    let mut csv = vec![];
+    assert_eq!(conn.export_table("t", ExportFormat::Csv, &mut csv)?, 3);
+    assert_eq!(
+        csv,
+        b"a,b,c,d\r\n1,plain,1.5,\r\n2,\"a, \"\"b\"\"\",,\x00\xff\r\n3,\"x\ny\",-2.0,A\r\n"
+    );
+
+    let mut jsonl = vec![];
+    assert_eq!(conn.export_table("t", ExportFormat::Jsonl, &mut jsonl)?, 3);
+    let jsonl = String::from_utf8(jsonl)?;
+    let rows = jsonl
+        .lines()
+        .map(serde_json::from_str::<serde_json::Value>)
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        rows,
+        vec![
+            serde_json::json!({"a": 1, "b": "plain", "c": 1.5, "d": null}),
+            serde_json::json!({"a": 2, "b": "a, \"b\"", "c": null, "d": "00ff"}),
+            serde_json::json!({"a": 3, "b": "x\ny", "c": -2.0, "d": "41"}),
+        ]
+    );
+
+    assert!(conn
+        .export_table("missing", ExportFormat::Csv, &mut vec![])
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn test_query_page() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("create table t (x integer primary key, y text);");
+    let conn = tmp_db.connect_limbo();
+    conn.execute("insert into t values (1, 'a'), (2, 'b'), (3, 'c'), (4, 'd'), (5, 'e')")?;
+
+    let options = PagingOptions {
+        page_size: 2,
+        ..Default::default()
+    };
+    let sql = "select x, y from t where x > ? order by x";
+    let mut page = conn.query_page(sql, [OwnedValue::Integer(1)], &options)?;
+    assert_eq!(page.columns, vec!["x", "y"]);
+    let mut xs = vec![];
+    loop {
+        xs.extend(page.rows.iter().map(|row| row[0].clone()));
+        let Some(token) = page.next else {
+            break;
+        };
+        page = conn.next_page(&token, &options)?;
+    }
+    assert_eq!(
+        xs,
+        (2..=5).map(OwnedValue::Integer).collect::<Vec<_>>(),
+        "a full last page has no next page"
+    );
+
+    // A token is tied to the database that issued it and can't be edited.
+    let first = conn.query_page(sql, [OwnedValue::Integer(0)], &options)?;
+    let token = first.next.unwrap();
+    let mut tampered = token.clone().into_bytes();
+    tampered[4] = if tampered[4] == b'0' { b'1' } else { b'0' };
+    assert!(conn
+        .next_page(std::str::from_utf8(&tampered)?, &options)
+        .is_err());
+    let other = TempDatabase::new_with_rusqlite("create table t (x integer primary key, y text);");
+    assert!(other.connect_limbo().next_page(&token, &options).is_err());
+
+    let expiring = PagingOptions {
+        page_size: 2,
+        expiry: Duration::ZERO,
+    };
+    let token = conn
+        .query_page(sql, [OwnedValue::Integer(0)], &expiring)?
+        .next
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(2));
+    assert!(matches!(
+        conn.next_page(&token, &expiring),
+        Err(LimboError::InvalidArgument(_))
+    ));
+
+    assert!(conn
+        .query_page("insert into t values (6, 'f')", [], &options)
+        .is_err());
+    Ok(())
+}
@@ -1,8 +1,10 @@
 use crate::common::{self, maybe_setup_tracing};
 use crate::common::{compare_string, do_flush, TempDatabase};
-use limbo_core::{Connection, OwnedValue, StepResult};
+use limbo_core::cdc::{ChangeOp, CommitToken, ConflictPolicy, RowChange};
+use limbo_core::{Connection, FallibleStreamingIterator, LimboError, OwnedValue, StepResult};
 use log::debug;
 use std::rc::Rc;
+use std::time::Duration;
 
 #[test]
 #[ignore]
@@ -461,3 +463,1042 @@ fn test_insert_after_big_blob() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_shared_memory_database() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    fn count(conn: &Rc<Connection>) -> anyhow::Result<i64> {
+        let mut stmt = conn.prepare("SELECT count(*) FROM test")?;
+        loop {
+            match stmt.step()? {
+                StepResult::Row => return Ok(stmt.row().unwrap().get::<i64>(0)?),
+                StepResult::IO => stmt.run_once()?,
+                _ => anyhow::bail!("expected a row"),
+            }
+        }
+    }
+
+    let (_, db) =
+        limbo_core::Database::open_uri("file:shared_test?mode=memory&cache=shared", false)?;
+    let conn = db.connect()?;
+    conn.execute("CREATE TABLE test (x INTEGER)")?;
+    conn.execute("INSERT INTO test VALUES (1)")?;
+
+    let (_, other) =
+        limbo_core::Database::open_uri("file:shared_test?mode=memory&cache=shared", false)?;
+    assert_eq!(count(&other.connect()?)?, 1);
+
+    let (_, private) = limbo_core::Database::open_uri("file:shared_test?mode=memory", false)?;
+    assert!(private.connect()?.prepare("SELECT * FROM test").is_err());
+
+    drop((conn, db, other));
+    let (_, reopened) =
+        limbo_core::Database::open_uri("file:shared_test?mode=memory&cache=shared", false)?;
+    assert!(
+        reopened.connect()?.prepare("SELECT * FROM test").is_err(),
+        "shared database outlived its last handle"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_analyze_and_optimize() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    fn stats(conn: &Rc<Connection>) -> anyhow::Result<Vec<String>> {
+        let mut stmt = conn.prepare("SELECT tbl, idx, stat FROM sqlite_stat1")?;
+        let mut rows = Vec::new();
+        loop {
+            match stmt.step()? {
+                StepResult::Row => {
+                    let row = stmt.row().unwrap();
+                    let values = row.get_values().map(|v| v.to_string());
+                    rows.push(values.collect::<Vec<_>>().join("|"));
+                }
+                StepResult::IO => stmt.run_once()?,
+                StepResult::Done => break,
+                _ => anyhow::bail!("unexpected step result"),
+            }
+        }
+        rows.sort();
+        Ok(rows)
+    }
+
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE t (a, b)");
+    let conn = tmp_db.connect_limbo();
+    conn.execute("CREATE TABLE u (x)")?;
+    conn.execute("INSERT INTO t VALUES (1, 1), (1, 2), (2, NULL), (NULL, NULL), (NULL, NULL)")?;
+    conn.execute("CREATE INDEX ta ON t (a, b)")?;
+    conn.execute("INSERT INTO u VALUES (1), (2)")?;
+
+    // Nothing was analyzed yet, t has an index and was written to.
+    conn.execute("PRAGMA optimize")?;
+    assert_eq!(stats(&conn)?, vec!["t|ta|5 2 2"]);
+
+    conn.execute("ANALYZE")?;
+    assert_eq!(stats(&conn)?, vec!["t|ta|5 2 2", "u||2"]);
+
+    conn.execute("INSERT INTO u VALUES (3)")?;
+    // u has no index, so its statistics don't matter to the planner.
+    conn.execute("PRAGMA optimize")?;
+    assert_eq!(stats(&conn)?, vec!["t|ta|5 2 2", "u||2"]);
+    conn.execute("ANALYZE u")?;
+    assert_eq!(stats(&conn)?, vec!["t|ta|5 2 2", "u||3"]);
+    Ok(())
+}
+
+#[test]
+fn test_plan_cache() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    fn run(conn: &Rc<Connection>, sql: &str) -> anyhow::Result<usize> {
+        let mut stmt = conn.prepare(sql)?;
+        let mut rows = 0;
+        loop {
+            match stmt.step()? {
+                StepResult::Row => rows += 1,
+                StepResult::IO => stmt.run_once()?,
+                StepResult::Done => break,
+                _ => anyhow::bail!("unexpected step result"),
+            }
+        }
+        Ok(rows)
+    }
+
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE t (a, b)");
+    let db = tmp_db.limbo_database();
+    let conn = db.connect()?;
+    run(&conn, "INSERT INTO t VALUES (1, 2)")?;
+    // The cached program counts the changes of each execution on its own.
+    run(&conn, "insert into t values (1, 2)")?;
+    assert_eq!(conn.changes(), 1);
+    let stats = conn.plan_cache_stats();
+    assert_eq!((stats.hits, stats.misses), (1, 1));
+
+    // Whitespace and keyword case don't matter.
+    assert_eq!(run(&conn, "SELECT a FROM t WHERE b = 2")?, 2);
+    assert_eq!(run(&conn, "select a  from t\n where b = 2")?, 2);
+    assert_eq!(conn.plan_cache_stats().hits, 2);
+
+    // DDL on another connection and ANALYZE invalidate the plan.
+    let other = db.connect()?;
+    other.execute("CREATE INDEX tb ON t (b)")?;
+    assert_eq!(run(&conn, "SELECT a FROM t WHERE b = 2")?, 2);
+    assert_eq!(conn.plan_cache_stats().hits, 2);
+    conn.execute("ANALYZE")?;
+    assert_eq!(run(&conn, "SELECT a FROM t WHERE b = 2")?, 2);
+    assert_eq!(conn.plan_cache_stats().hits, 2);
+    assert_eq!(run(&conn, "SELECT a FROM t WHERE b = 2")?, 2);
+    assert_eq!(conn.plan_cache_stats().hits, 3);
+
+    conn.execute("PRAGMA automatic_index = off")?;
+    assert_eq!(conn.plan_cache_stats().entries, 0);
+
+    conn.set_plan_cache_capacity(0);
+    run(&conn, "SELECT a FROM t WHERE b = 2")?;
+    run(&conn, "SELECT a FROM t WHERE b = 2")?;
+    assert_eq!(conn.plan_cache_stats().hits, 3);
+    Ok(())
+}
+
+#[test]
+fn test_statement_rollback() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    fn rows(conn: &Rc<Connection>) -> anyhow::Result<Vec<(i64, i64)>> {
+        let mut stmt = conn.prepare("SELECT id, x FROM t")?;
+        let mut rows = Vec::new();
+        loop {
+            match stmt.step()? {
+                StepResult::Row => {
+                    let row = stmt.row().unwrap();
+                    rows.push((row.get::<i64>(0)?, row.get::<i64>(1)?));
+                }
+                StepResult::IO => stmt.run_once()?,
+                StepResult::Done => break,
+                _ => anyhow::bail!("unexpected step result"),
+            }
+        }
+        Ok(rows)
+    }
+
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE t (id INTEGER PRIMARY KEY, x)");
+    let conn = tmp_db.connect_limbo();
+    conn.execute("INSERT INTO t VALUES (1, 1), (2, 2), (3, -9223372036854775808), (4, 4)")?;
+    let original = rows(&conn)?;
+
+    // The update fails on the third row, after changing the first two.
+    assert!(conn.execute("UPDATE t SET x = abs(x) + 1").is_err());
+    assert_eq!(rows(&conn)?, original);
+    // The implicit transaction of the failed statement is over.
+    conn.execute("INSERT INTO t VALUES (5, 5)")?;
+
+    conn.execute("BEGIN")?;
+    conn.execute("INSERT INTO t VALUES (6, 6)")?;
+    // The failed statement is undone, the transaction and its earlier statements are not.
+    assert!(conn
+        .execute("INSERT INTO t VALUES (7, 7), (8, 8), (1, 1)")
+        .is_err());
+    conn.execute("INSERT INTO t VALUES (9, 9)")?;
+    conn.execute("COMMIT")?;
+    let expected = [
+        (1, 1),
+        (2, 2),
+        (3, i64::MIN),
+        (4, 4),
+        (5, 5),
+        (6, 6),
+        (9, 9),
+    ];
+    assert_eq!(rows(&conn)?, expected);
+    do_flush(&conn, &tmp_db)?;
+
+    let conn = tmp_db.connect_limbo();
+    assert_eq!(rows(&conn)?, expected);
+    Ok(())
+}
+
+#[test]
+fn test_change_data_capture() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE t (id INTEGER PRIMARY KEY, x)");
+    let db = tmp_db.limbo_database();
+    let changes = db.subscribe_changes();
+    let conn = db.connect()?;
+    let change =
+        |op, rowid, old: Option<Vec<OwnedValue>>, new: Option<Vec<OwnedValue>>| RowChange {
+            table: "t".to_string(),
+            op,
+            rowid,
+            old,
+            new,
+        };
+    let row = |id: i64, x: &str| Some(vec![OwnedValue::Integer(id), OwnedValue::build_text(x)]);
+
+    conn.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b')")?;
+    assert_eq!(
+        changes.try_recv()?.changes,
+        vec![
+            change(ChangeOp::Insert, 1, None, row(1, "a")),
+            change(ChangeOp::Insert, 2, None, row(2, "b")),
+        ]
+    );
+
+    conn.execute("UPDATE t SET x = 'c' WHERE id = 2")?;
+    assert_eq!(
+        changes.try_recv()?.changes,
+        vec![change(ChangeOp::Update, 2, row(2, "b"), row(2, "c"))]
+    );
+
+    // An explicit transaction is published as a whole on commit, without its failed statements.
+    conn.execute("BEGIN")?;
+    conn.execute("DELETE FROM t WHERE id = 1")?;
+    assert!(conn
+        .execute("INSERT INTO t VALUES (3, 'd'), (2, 'e')")
+        .is_err());
+    conn.execute("INSERT INTO t VALUES (4, 'f')")?;
+    assert!(changes.try_recv().is_err());
+    conn.execute("COMMIT")?;
+    assert_eq!(
+        changes.try_recv()?.changes,
+        vec![
+            change(ChangeOp::Delete, 1, row(1, "a"), None),
+            change(ChangeOp::Insert, 4, None, row(4, "f")),
+        ]
+    );
+
+    // Statements that fail or write nothing publish nothing.
+    assert!(conn.execute("INSERT INTO t VALUES (4, 'g')").is_err());
+    conn.execute("DELETE FROM t WHERE id = 100")?;
+    assert!(changes.try_recv().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_apply_changes() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    fn rows(conn: &Rc<Connection>) -> anyhow::Result<Vec<(i64, String)>> {
+        let mut stmt = conn.prepare("SELECT id, x FROM t")?;
+        let mut rows = stmt.query([])?;
+        let mut result = Vec::new();
+        while let Some(row) = rows.next()? {
+            result.push((row.get::<i64>(0)?, row.get::<String>(1)?));
+        }
+        Ok(result)
+    }
+
+    let primary_db = TempDatabase::new_with_rusqlite("CREATE TABLE t (id INTEGER PRIMARY KEY, x)");
+    let primary_db = primary_db.limbo_database();
+    let changes = primary_db.subscribe_changes();
+    let primary = primary_db.connect()?;
+    let replica_db = TempDatabase::new_with_rusqlite("CREATE TABLE t (id INTEGER PRIMARY KEY, x)");
+    let replica = replica_db.connect_limbo();
+    let replicate = |policy| -> anyhow::Result<()> {
+        while let Ok(change_set) = changes.try_recv() {
+            replica.apply_changes(&change_set, policy)?;
+        }
+        Ok(())
+    };
+
+    primary.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b'), (3, 'c')")?;
+    primary.execute("UPDATE t SET x = 'd' WHERE id = 2")?;
+    primary.execute("DELETE FROM t WHERE id = 3")?;
+    replicate(ConflictPolicy::Abort)?;
+    assert_eq!(rows(&replica)?, rows(&primary)?);
+
+    // The replica diverges on row 1 and gets row 4 of its own.
+    replica.execute("UPDATE t SET x = 'e' WHERE id = 1")?;
+    replica.execute("INSERT INTO t VALUES (4, 'f')")?;
+    primary.execute("BEGIN")?;
+    primary.execute("INSERT INTO t VALUES (5, 'g')")?;
+    primary.execute("INSERT INTO t VALUES (4, 'h')")?;
+    primary.execute("UPDATE t SET x = 'i' WHERE id = 1")?;
+    primary.execute("COMMIT")?;
+    let change_set = changes.try_recv()?;
+
+    // Nothing of a batch that conflicts is applied.
+    assert!(replica
+        .apply_changes(&change_set, ConflictPolicy::Abort)
+        .is_err());
+    let diverged = vec![
+        (1, "e".to_string()),
+        (2, "d".to_string()),
+        (4, "f".to_string()),
+    ];
+    assert_eq!(rows(&replica)?, diverged);
+    replica.execute("INSERT INTO t VALUES (6, 'j')")?;
+    replica.execute("DELETE FROM t WHERE id = 6")?;
+
+    replica.apply_changes(&change_set, ConflictPolicy::Ignore)?;
+    let mut expected = diverged.clone();
+    expected.push((5, "g".to_string()));
+    assert_eq!(rows(&replica)?, expected);
+
+    replica.apply_changes(&change_set, ConflictPolicy::Replace)?;
+    assert_eq!(rows(&replica)?, rows(&primary)?);
+    do_flush(&replica, &replica_db)?;
+    assert_eq!(rows(&replica_db.connect_limbo())?, rows(&primary)?);
+
+    // Rows are identified by the INTEGER PRIMARY KEY, which other tables don't have.
+    primary.execute("CREATE TABLE u (x)")?;
+    replica.execute("CREATE TABLE u (x)")?;
+    primary.execute("INSERT INTO u VALUES (1)")?;
+    assert!(replicate(ConflictPolicy::Abort).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_wait_for_commit() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let primary_db = TempDatabase::new_with_rusqlite("CREATE TABLE t (id INTEGER PRIMARY KEY, x)");
+    let primary_db = primary_db.limbo_database();
+    let changes = primary_db.subscribe_changes();
+    let primary = primary_db.connect()?;
+    let replica_tmp = TempDatabase::new_with_rusqlite("CREATE TABLE t (id INTEGER PRIMARY KEY, x)");
+    let replica_db = replica_tmp.limbo_database();
+    let replica = replica_db.connect()?;
+
+    assert_eq!(primary.last_commit_token(), None);
+    primary.execute("INSERT INTO t VALUES (1, 'a')")?;
+    let first = primary.last_commit_token().unwrap();
+    primary.execute("INSERT INTO t VALUES (2, 'b')")?;
+    let second = primary.last_commit_token().unwrap();
+    assert!(second > first);
+    primary.wait_for_commit(second, Duration::ZERO)?;
+    assert!(matches!(
+        replica.wait_for_commit(first, Duration::from_millis(10)),
+        Err(LimboError::Busy)
+    ));
+
+    // A reader of the replica waits until the second change set is applied.
+    let reader = {
+        let replica_db = replica_db.clone();
+        std::thread::spawn(move || -> anyhow::Result<i64> {
+            let conn = replica_db.connect()?;
+            conn.wait_for_commit(second, Duration::from_secs(30))?;
+            let mut stmt = conn.prepare("SELECT count(*) FROM t")?;
+            let mut rows = stmt.query([])?;
+            Ok(rows.next()?.unwrap().get::<i64>(0)?)
+        })
+    };
+    let replica_changes = replica_db.subscribe_changes();
+    for _ in 0..2 {
+        std::thread::sleep(Duration::from_millis(10));
+        let change_set = changes.try_recv()?;
+        replica.apply_changes(&change_set, ConflictPolicy::Abort)?;
+    }
+    assert_eq!(reader.join().unwrap()?, 2);
+    // The replica passes the changes on under the tokens of the primary.
+    assert_eq!(replica_changes.try_recv()?.token, first);
+    assert_eq!(replica_changes.try_recv()?.token, second);
+    assert_eq!(replica.last_commit_token(), Some(second));
+
+    // Once reopened, the replica still has the commits it applied.
+    do_flush(&replica, &replica_tmp)?;
+    let reopened = replica_tmp.connect_limbo();
+    reopened.wait_for_commit(second, Duration::ZERO)?;
+    assert!(reopened
+        .wait_for_commit(CommitToken(second.0 + 1), Duration::ZERO)
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_backup() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    fn count(conn: &Rc<Connection>) -> anyhow::Result<i64> {
+        let mut stmt = conn.prepare("SELECT count(*) FROM t")?;
+        let mut rows = stmt.query([])?;
+        Ok(rows.next()?.unwrap().get::<i64>(0)?)
+    }
+
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE t (x)");
+    let db = tmp_db.limbo_database();
+    let conn = db.connect()?;
+    for _ in 0..20 {
+        conn.execute("INSERT INTO t VALUES (randomblob(1000))")?;
+    }
+
+    let snapshot = db.snapshot()?;
+    assert!(snapshot.wal_frame() > 0);
+    let backup = TempDatabase::new("backup.db");
+    std::fs::copy(&tmp_db.path, &backup.path)?;
+    for _ in 0..20 {
+        conn.execute("INSERT INTO t VALUES (randomblob(1000))")?;
+    }
+    // The checkpoint leaves alone what comes after the snapshot, writes continue meanwhile.
+    let result = conn.checkpoint()?;
+    assert_eq!(result.num_checkpointed_frames, snapshot.wal_frame());
+    conn.execute("DELETE FROM t")?;
+    assert_eq!(count(snapshot.connection())?, 20);
+
+    // The database file copied before the checkpoint lacks what the WAL copied after has.
+    let wal = std::fs::read(format!("{}-wal", tmp_db.path.display()))?;
+    std::fs::write(
+        format!("{}-wal", backup.path.display()),
+        &wal[..snapshot.wal_size() as usize],
+    )?;
+    assert_eq!(count(&backup.connect_limbo())?, 20);
+
+    drop(snapshot);
+    let result = conn.checkpoint()?;
+    assert_eq!(result.num_checkpointed_frames, result.num_wal_frames);
+    assert_eq!(count(&conn)?, 0);
+    Ok(())
+}
+
+#[test]
+fn test_checkpoint_into() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    fn count(conn: &Rc<Connection>) -> anyhow::Result<i64> {
+        let mut stmt = conn.prepare("SELECT count(*) FROM t")?;
+        let mut rows = stmt.query([])?;
+        Ok(rows.next()?.unwrap().get::<i64>(0)?)
+    }
+
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE t (x)");
+    let db = tmp_db.limbo_database();
+    let conn = db.connect()?;
+    for _ in 0..300 {
+        conn.execute("INSERT INTO t VALUES (randomblob(1000))")?;
+    }
+    let wal_path = format!("{}-wal", tmp_db.path.display());
+    let wal = std::fs::read(&wal_path)?;
+
+    // The copy has the rows that are only in the WAL; the database and its WAL are untouched.
+    let copy = TempDatabase::new("copy.db");
+    db.checkpoint_into(copy.path.to_str().unwrap())?;
+    assert_eq!(std::fs::read(&wal_path)?, wal);
+    conn.execute("DELETE FROM t")?;
+    assert!(!std::path::Path::new(&format!("{}-wal", copy.path.display())).exists());
+    assert_eq!(count(&copy.connect_limbo())?, 300);
+    let sqlite = rusqlite::Connection::open(&copy.path)?;
+    let check: String = sqlite.query_row("PRAGMA integrity_check", (), |row| row.get(0))?;
+    assert_eq!(check, "ok");
+
+    assert!(db.checkpoint_into(copy.path.to_str().unwrap()).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_alter_table_rebuild() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    fn query(conn: &Rc<Connection>, sql: &str) -> anyhow::Result<Vec<Vec<OwnedValue>>> {
+        let mut stmt = conn.prepare(sql)?;
+        let mut rows = stmt.query([])?;
+        let mut result = Vec::new();
+        while let Some(row) = rows.next()? {
+            result.push(row.get_values().cloned().collect());
+        }
+        Ok(result)
+    }
+
+    let tmp_db = TempDatabase::new_with_rusqlite(
+        "CREATE TABLE t (id INTEGER PRIMARY KEY, x TEXT, y INTEGER);",
+    );
+    let conn = tmp_db.connect_limbo();
+    conn.execute("INSERT INTO t VALUES (1, 'a', 10), (2, 'b', 20), (5, 'c', 30)")?;
+    conn.execute("CREATE INDEX t_x ON t (x)")?;
+    conn.execute("CREATE INDEX t_y ON t (y)")?;
+
+    // Drops y along with its index and adds z.
+    conn.alter_table_rebuild(
+        "t",
+        "CREATE TABLE t (id INTEGER PRIMARY KEY, x TEXT, z INTEGER DEFAULT 7)",
+    )?;
+    let schema = query(&conn, "SELECT type, name, sql FROM sqlite_schema")?;
+    assert_eq!(
+        schema,
+        vec![
+            vec![
+                OwnedValue::build_text("table"),
+                OwnedValue::build_text("t"),
+                OwnedValue::build_text(
+                    "CREATE TABLE t (id INTEGER PRIMARY KEY, x TEXT, z INTEGER DEFAULT 7)"
+                ),
+            ],
+            vec![
+                OwnedValue::build_text("index"),
+                OwnedValue::build_text("t_x"),
+                OwnedValue::build_text("CREATE INDEX t_x ON t (x)"),
+            ],
+        ]
+    );
+    let rows = query(&conn, "SELECT id, x, z FROM t")?;
+    assert_eq!(
+        rows,
+        [(1, "a"), (2, "b"), (5, "c")]
+            .into_iter()
+            .map(|(id, x)| vec![
+                OwnedValue::Integer(id),
+                OwnedValue::build_text(x),
+                OwnedValue::Integer(7),
+            ])
+            .collect::<Vec<_>>()
+    );
+
+    // Rows that don't fit the new definition, here by sharing the z that would be their rowid,
+    // leave the table as it was.
+    assert!(conn
+        .alter_table_rebuild("t", "CREATE TABLE t (z INTEGER PRIMARY KEY, x TEXT)")
+        .is_err());
+    assert_eq!(
+        query(&conn, "SELECT count(*) FROM t")?,
+        vec![vec![OwnedValue::Integer(3)]]
+    );
+    assert_eq!(
+        query(&conn, "SELECT type, name, sql FROM sqlite_schema")?,
+        schema
+    );
+    assert!(conn
+        .alter_table_rebuild("t", "CREATE TABLE u (id INTEGER PRIMARY KEY)")
+        .is_err());
+    assert!(conn
+        .alter_table_rebuild("u", "CREATE TABLE u (id INTEGER PRIMARY KEY)")
+        .is_err());
+    do_flush(&conn, &tmp_db)?;
+
+    let conn = rusqlite::Connection::open(&tmp_db.path)?;
+    let check: String = conn.query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+    assert_eq!(check, "ok");
+    Ok(())
+}
+
+#[test]
+fn test_kv_cursor() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("create table t (x integer primary key, y);");
+    let conn = tmp_db.connect_limbo();
+    conn.execute("insert into t values (1, 'one'), (3, 'three')")?;
+    let root_page = {
+        let mut stmt = conn.prepare("select rootpage from sqlite_schema where name = 't'")?;
+        let mut rows = stmt.query([])?;
+        rows.next()?.unwrap().get::<i64>(0)? as usize
+    };
+    let text = |s: &str| OwnedValue::build_text(s);
+
+    // Rows of tables are records with NULL in place of the rowid alias.
+    let tx = conn.begin_kv_write()?;
+    let mut cursor = tx.cursor(root_page);
+    assert_eq!(cursor.get(3)?, Some(vec![OwnedValue::Null, text("three")]));
+    assert_eq!(cursor.get(2)?, None);
+    cursor.put(2, &[OwnedValue::Null, text("two")])?;
+    cursor.put(3, &[OwnedValue::Null, text("THREE")])?;
+    assert!(cursor.delete(1)?);
+    assert!(!cursor.delete(1)?);
+    drop(cursor);
+    tx.commit()?;
+    let mut stmt = conn.prepare("select x, y from t")?;
+    let mut rows = stmt.query([])?;
+    let mut seen = Vec::new();
+    while let Some(row) = rows.next()? {
+        seen.push((row.get::<i64>(0)?, row.get::<String>(1)?));
+    }
+    assert_eq!(seen, vec![(2, "two".to_string()), (3, "THREE".to_string())]);
+
+    // Uncommitted writes, including new b-trees, are rolled back.
+    let tx = conn.begin_kv_write()?;
+    let new_root = tx.create_btree()?;
+    let mut cursor = tx.cursor(new_root);
+    for key in (0..500).rev() {
+        cursor.put(key, &[OwnedValue::Integer(key * 10)])?;
+    }
+    cursor.seek(495)?;
+    let mut keys = Vec::new();
+    while let Some((key, value)) = cursor.next_entry()? {
+        assert_eq!(value, vec![OwnedValue::Integer(key * 10)]);
+        keys.push(key);
+    }
+    assert_eq!(keys, vec![495, 496, 497, 498, 499]);
+    tx.cursor(root_page)
+        .put(4, &[OwnedValue::Null, text("four")])?;
+    drop(cursor);
+    drop(tx);
+
+    // Read transactions see the committed b-trees and can't write.
+    let tx = conn.begin_kv_read()?;
+    let mut cursor = tx.cursor(root_page);
+    cursor.rewind()?;
+    assert_eq!(cursor.next_entry()?.map(|(key, _)| key), Some(2));
+    assert_eq!(cursor.next_entry()?.map(|(key, _)| key), Some(3));
+    assert_eq!(cursor.next_entry()?, None);
+    assert!(cursor.put(5, &[]).is_err());
+    drop(cursor);
+    tx.commit()?;
+    conn.execute("insert into t values (5, 'five')")?;
+    Ok(())
+}
+
+#[test]
+fn test_execute_batch() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("create table migrations (version integer);");
+    let conn = tmp_db.connect_limbo();
+    let count = |table: &str| -> anyhow::Result<i64> {
+        let mut stmt = conn.prepare(format!("select count(*) from {table}"))?;
+        let mut rows = stmt.query([])?;
+        Ok(rows.next()?.unwrap().get::<i64>(0)?)
+    };
+
+    conn.execute_batch(
+        "create table t (x integer primary key, y text);
+        create index t_y on t (y);
+        -- rows of queries are discarded
+        select * from migrations;
+        begin;
+        insert into t values (1, 'a');
+        insert into t values (2, 'b');
+        commit;
+        insert into migrations values (1);",
+    )?;
+    assert_eq!(count("t")?, 2);
+    assert_eq!(count("migrations")?, 1);
+
+    // A failing statement rolls back the transaction the script began.
+    let err = conn.execute_batch(
+        "begin;
+        insert into t values (3, 'c');
+        insert into t values (1, 'duplicate');
+        insert into migrations values (2);
+        commit;",
+    );
+    assert!(err.is_err());
+    assert_eq!(count("t")?, 2);
+    assert_eq!(count("migrations")?, 1);
+    assert!(conn.get_auto_commit());
+
+    // Statements of the script that were committed stay.
+    let err = conn.execute_batch(
+        "insert into t values (3, 'c');
+        insert into no_such_table values (1);",
+    );
+    assert!(err.is_err());
+    assert_eq!(count("t")?, 3);
+    conn.execute_batch("insert into migrations values (2)")?;
+    assert_eq!(count("migrations")?, 2);
+
+    // Tables created by the rolled back transaction are gone, and the pages they used are
+    // handed out again.
+    let err = conn.execute_batch(
+        "begin;
+        create table zz (a);
+        insert into zz values (1);
+        insert into no_such_table values (1);",
+    );
+    assert!(err.is_err());
+    assert!(conn.get_auto_commit());
+    assert!(conn.execute("select * from zz").is_err());
+    conn.execute_batch(
+        "create table zz (a);
+        insert into zz values (2);
+        insert into t values (4, 'd');",
+    )?;
+    assert_eq!(count("zz")?, 1);
+    assert_eq!(count("t")?, 4);
+    let mut stmt = conn.prepare("pragma integrity_check(zz)")?;
+    let mut check = stmt.query([])?;
+    assert_eq!(check.next()?.unwrap().get::<String>(0)?, "ok");
+    Ok(())
+}
+
+#[test]
+fn test_transaction_guard() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("create table t (x integer primary key, y);");
+    let conn = tmp_db.connect_limbo();
+    let rows = |conn: &Rc<Connection>| -> anyhow::Result<Vec<i64>> {
+        let mut stmt = conn.prepare("select x from t")?;
+        let mut rows = stmt.query([])?;
+        let mut xs = Vec::new();
+        while let Some(row) = rows.next()? {
+            xs.push(row.get::<i64>(0)?);
+        }
+        Ok(xs)
+    };
+
+    let tx = conn.transaction()?;
+    tx.execute("insert into t values (1, 'a')")?;
+    tx.commit()?;
+    assert_eq!(rows(&conn)?, vec![1]);
+
+    // Dropping a transaction rolls it back.
+    {
+        let tx = conn.transaction()?;
+        tx.execute("insert into t values (2, 'b')")?;
+        tx.execute("delete from t where x = 1")?;
+        assert_eq!(rows(&tx)?, vec![2]);
+    }
+    assert!(conn.get_auto_commit());
+    assert_eq!(rows(&conn)?, vec![1]);
+
+    let mut tx = conn.transaction()?;
+    tx.execute("insert into t values (2, 'b')")?;
+    {
+        let mut sp = tx.savepoint()?;
+        sp.execute("insert into t values (3, 'c')")?;
+        let inner = sp.savepoint()?;
+        inner.execute("insert into t values (4, 'd')")?;
+        inner.execute("delete from t where x = 1")?;
+        assert_eq!(rows(&inner)?, vec![2, 3, 4]);
+        inner.rollback()?;
+        assert_eq!(rows(&sp)?, vec![1, 2, 3]);
+        sp.commit()?;
+    }
+    {
+        // Enough rows to split pages, and a table that is gone again once rolled back.
+        let sp = tx.savepoint()?;
+        for x in 10..500 {
+            sp.execute(format!("insert into t values ({x}, '{}')", "y".repeat(100)))?;
+        }
+        sp.execute("create table u (z)")?;
+        sp.execute("insert into u values (1)")?;
+    }
+    assert!(tx.execute("select * from u").is_err());
+    assert_eq!(rows(&tx)?, vec![1, 2, 3]);
+    tx.execute("insert into t values (5, 'e')")?;
+    tx.commit()?;
+    assert_eq!(rows(&conn)?, vec![1, 2, 3, 5]);
+
+    // The database is intact after the rollbacks.
+    let mut stmt = conn.prepare("pragma integrity_check")?;
+    let mut check = stmt.query([])?;
+    assert_eq!(check.next()?.unwrap().get::<String>(0)?, "ok");
+    Ok(())
+}
+
+#[test]
+fn test_missing_table_error_code() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("create table t (x);");
+    let conn = tmp_db.connect_limbo();
+    for sql in [
+        "insert into nosuch values (1)",
+        "delete from nosuch",
+        "update nosuch set x = 1",
+    ] {
+        let err = conn.execute(sql).unwrap_err();
+        assert_eq!(
+            err.extended_code(),
+            limbo_core::error::SQLITE_ERROR,
+            "{sql}"
+        );
+        assert!(err.to_string().contains("no such table: nosuch"), "{err}");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_connection_rollback() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("create table t (x integer primary key, y);");
+    let conn = tmp_db.connect_limbo();
+    let rows = |conn: &Rc<Connection>| -> anyhow::Result<Vec<i64>> {
+        let mut stmt = conn.prepare("select x from t")?;
+        let mut rows = stmt.query([])?;
+        let mut xs = Vec::new();
+        while let Some(row) = rows.next()? {
+            xs.push(row.get::<i64>(0)?);
+        }
+        Ok(xs)
+    };
+    conn.execute("insert into t values (1, 'a')")?;
+
+    // Outside of a transaction there is nothing to roll back.
+    conn.rollback()?;
+    assert_eq!(rows(&conn)?, vec![1]);
+
+    conn.execute("begin")?;
+    conn.execute("select * from t")?;
+    conn.execute("create table zz (a)")?;
+    conn.execute("insert into zz values (1)")?;
+    for x in 10..300 {
+        conn.execute(format!("insert into t values ({x}, '{}')", "y".repeat(100)))?;
+    }
+    conn.rollback()?;
+    assert!(conn.get_auto_commit());
+    assert!(conn.execute("select * from zz").is_err());
+    assert_eq!(rows(&conn)?, vec![1]);
+
+    conn.execute("begin immediate")?;
+    conn.execute("insert into t values (2, 'b')")?;
+    conn.execute("create table zz (a)")?;
+    conn.rollback()?;
+    assert_eq!(rows(&conn)?, vec![1]);
+
+    // The rolled back table can be created again, and its pages are reused.
+    conn.execute("create table zz (a)")?;
+    conn.execute("insert into zz values (2)")?;
+    conn.execute("insert into t values (3, 'c')")?;
+    assert_eq!(rows(&conn)?, vec![1, 3]);
+    let mut stmt = conn.prepare("pragma integrity_check")?;
+    let mut check = stmt.query([])?;
+    assert_eq!(check.next()?.unwrap().get::<String>(0)?, "ok");
+    Ok(())
+}
+
+#[test]
+fn test_update_delete_order_by_limit() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db =
+        TempDatabase::new_with_rusqlite("create table t (id integer primary key, x integer, y);");
+    let conn = tmp_db.connect_limbo();
+    let rows = || -> anyhow::Result<Vec<(i64, i64, String)>> {
+        let mut stmt = conn.prepare("select id, x, y from t")?;
+        let mut rows = stmt.query([])?;
+        let mut result = Vec::new();
+        while let Some(row) = rows.next()? {
+            result.push((row.get(0)?, row.get(1)?, row.get::<String>(2)?));
+        }
+        Ok(result)
+    };
+    conn.execute("begin")?;
+    for (id, x) in [(1, 5), (2, 3), (3, 9), (4, 1), (5, 7), (6, 2)] {
+        conn.execute(format!("insert into t values ({id}, {x}, 'a')"))?;
+    }
+    conn.execute("commit")?;
+
+    // The rows are changed in the order of ORDER BY, from OFFSET on and up to LIMIT of them.
+    conn.execute("delete from t order by x desc limit 2")?;
+    conn.execute("delete from t where id > 1 order by x limit 1 offset 1")?;
+    conn.execute("update t set y = 'b' order by x desc limit 2 offset 1")?;
+    conn.execute("update t set x = x + 10 where x < 5 order by y desc, id limit 1")?;
+    assert_eq!(
+        rows()?,
+        vec![
+            (1, 5, "a".to_string()),
+            (2, 13, "b".to_string()),
+            (4, 1, "b".to_string())
+        ]
+    );
+
+    // Like in SQLite, ORDER BY has to come with a LIMIT.
+    assert!(conn.execute("delete from t order by x").is_err());
+    assert!(conn.execute("update t set y = 'c' order by x").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_materialized_views() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite(
+        "CREATE TABLE t (id INTEGER PRIMARY KEY, cat TEXT, x INTEGER)",
+    );
+    let conn = tmp_db.connect_limbo();
+    fn rows(conn: &Rc<Connection>, sql: &str) -> anyhow::Result<Vec<Vec<OwnedValue>>> {
+        let mut stmt = conn.prepare(sql)?;
+        let mut rows = stmt.query([])?;
+        let mut result = Vec::new();
+        while let Some(row) = rows.next()? {
+            result.push(row.get_values().cloned().collect());
+        }
+        Ok(result)
+    }
+    // Each view holds what its query returns right now.
+    let queries = [
+        ("big", "SELECT rowid, id, x FROM t WHERE x > 15 ORDER BY 1"),
+        (
+            "sums",
+            "SELECT cat, count(*), sum(x) FROM t GROUP BY cat ORDER BY 1",
+        ),
+        ("total", "SELECT count(*), max(x) FROM t"),
+    ];
+    let check = |conn: &Rc<Connection>| -> anyhow::Result<()> {
+        for (view, query) in queries {
+            let ordered = if view == "big" { "rowid, *" } else { "*" };
+            assert_eq!(
+                rows(conn, &format!("SELECT {ordered} FROM {view} ORDER BY 1"))?,
+                rows(conn, query)?,
+                "{view}"
+            );
+        }
+        Ok(())
+    };
+
+    conn.execute("INSERT INTO t VALUES (1, 'a', 10), (2, 'b', 20), (3, 'a', 30)")?;
+    conn.execute("CREATE MATERIALIZED VIEW big AS SELECT id, x FROM t WHERE x > 15")?;
+    conn.execute(
+        "CREATE MATERIALIZED VIEW sums AS SELECT cat, count(*) AS n, sum(x) FROM t GROUP BY cat",
+    )?;
+    conn.execute("CREATE MATERIALIZED VIEW total(n, m) AS SELECT count(*), max(x) FROM t")?;
+    conn.execute("CREATE MATERIALIZED VIEW top AS SELECT cat, x FROM t ORDER BY x DESC LIMIT 2")?;
+    check(&conn)?;
+
+    conn.execute("INSERT INTO t VALUES (4, NULL, 5), (5, 'b', 40)")?;
+    conn.execute("UPDATE t SET x = 1 WHERE id = 2")?;
+    conn.execute("UPDATE t SET cat = 'c' WHERE id = 1")?;
+    conn.execute("DELETE FROM t WHERE id = 3")?;
+    conn.execute("BEGIN")?;
+    conn.execute("INSERT INTO t VALUES (6, NULL, 50)")?;
+    // Views are created outside of transactions, and failing to doesn't undo the transaction.
+    assert!(conn
+        .execute("CREATE MATERIALIZED VIEW v AS SELECT x FROM t")
+        .is_err());
+    conn.execute("UPDATE t SET x = 60 WHERE id = 6")?;
+    conn.execute("COMMIT")?;
+    check(&conn)?;
+
+    // Views that aren't maintained are brought up to date by refreshing them.
+    let top = |conn: &Rc<Connection>| rows(conn, "SELECT cat, x FROM top ORDER BY x DESC");
+    let row = |cat: OwnedValue, x: i64| vec![cat, OwnedValue::Integer(x)];
+    let a = || OwnedValue::build_text("a");
+    let b = || OwnedValue::build_text("b");
+    assert_eq!(top(&conn)?, vec![row(a(), 30), row(b(), 20)]);
+    conn.execute("REFRESH MATERIALIZED VIEW top")?;
+    assert_eq!(top(&conn)?, vec![row(OwnedValue::Null, 60), row(b(), 40)]);
+
+    // Only the views' own maintenance writes them.
+    assert!(conn.execute("INSERT INTO big VALUES (9, 9)").is_err());
+    assert!(conn.execute("DELETE FROM sums").is_err());
+    assert!(conn.execute("DROP TABLE total").is_err());
+    assert!(conn.execute("DROP TABLE t").is_err());
+
+    conn.execute("DROP MATERIALIZED VIEW top")?;
+    conn.execute("DROP MATERIALIZED VIEW IF EXISTS top")?;
+    assert!(conn.execute("DROP MATERIALIZED VIEW top").is_err());
+    assert!(conn.execute("REFRESH MATERIALIZED VIEW top").is_err());
+    do_flush(&conn, &tmp_db)?;
+
+    // The views are found again when the database is opened, and SQLite reads them as tables.
+    let conn = tmp_db.connect_limbo();
+    conn.execute("INSERT INTO t VALUES (7, 'a', 70)")?;
+    check(&conn)?;
+    do_flush(&conn, &tmp_db)?;
+    let sqlite = rusqlite::Connection::open(&tmp_db.path)?;
+    let max: i64 = sqlite.query_row("SELECT m FROM total", (), |row| row.get(0))?;
+    assert_eq!(max, 70);
+    Ok(())
+}
+
+#[test]
+fn test_materialized_view_maintenance_failure() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE t (id INTEGER PRIMARY KEY, j TEXT)");
+    let conn = tmp_db.connect_limbo();
+    let count = |conn: &Rc<Connection>, table: &str| -> anyhow::Result<i64> {
+        let mut stmt = conn.prepare(format!("SELECT count(*) FROM {table}"))?;
+        let mut rows = stmt.query([])?;
+        let row = rows.next()?.unwrap();
+        Ok(row.get::<i64>(0)?)
+    };
+    conn.execute("CREATE MATERIALIZED VIEW v AS SELECT id, json_extract(j, '$.a') FROM t")?;
+    conn.execute(r#"INSERT INTO t VALUES (1, '{"a": 1}')"#)?;
+
+    // The view can't be computed for the malformed JSON, so neither the row nor the view commit.
+    assert!(conn.execute("INSERT INTO t VALUES (2, '{')").is_err());
+    assert_eq!(count(&conn, "t")?, 1);
+    assert_eq!(count(&conn, "v")?, 1);
+    conn.execute("BEGIN")?;
+    conn.execute(r#"INSERT INTO t VALUES (3, '{"a": 3}')"#)?;
+    conn.execute("INSERT INTO t VALUES (4, '{')")?;
+    assert!(conn.execute("COMMIT").is_err());
+    assert_eq!(count(&conn, "t")?, 1);
+    assert_eq!(count(&conn, "v")?, 1);
+
+    conn.execute(r#"INSERT INTO t VALUES (5, '{"a": 5}')"#)?;
+    assert_eq!(count(&conn, "t")?, 2);
+    assert_eq!(count(&conn, "v")?, 2);
+    Ok(())
+}
+
+#[test]
+fn test_vector_index() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db =
+        TempDatabase::new_with_rusqlite("CREATE TABLE t (id INTEGER PRIMARY KEY, e F32_BLOB(2))");
+    let conn = tmp_db.connect_limbo();
+    let nearest = |conn: &Rc<Connection>, query: &str, k: usize| -> anyhow::Result<Vec<i64>> {
+        let mut stmt = conn.prepare(format!(
+            "SELECT id FROM t WHERE e MATCH vector('{query}') LIMIT {k}"
+        ))?;
+        let mut rows = stmt.query([])?;
+        let mut result = Vec::new();
+        while let Some(row) = rows.next()? {
+            result.push(row.get(0)?);
+        }
+        Ok(result)
+    };
+
+    conn.execute("BEGIN")?;
+    for id in 0..200 {
+        let angle = id as f64 / 200.0 * std::f64::consts::TAU;
+        conn.execute(format!(
+            "INSERT INTO t VALUES ({id}, vector('[{},{}]'))",
+            angle.cos(),
+            angle.sin()
+        ))?;
+    }
+    conn.execute("COMMIT")?;
+    conn.execute("CREATE INDEX t_e ON t(libsql_vector_idx(e, 'lists=8', 'probes=3'))")?;
+    // The vectors around the query direction are the nearest by cosine distance.
+    assert_eq!(nearest(&conn, "[1,0.01]", 3)?, vec![0, 1, 199]);
+    assert_eq!(nearest(&conn, "[0,2]", 1)?, vec![50]);
+
+    conn.execute("INSERT INTO t VALUES (200, vector('[0.01,1]'))")?;
+    conn.execute("DELETE FROM t WHERE id = 50")?;
+    assert_eq!(nearest(&conn, "[0,2]", 2)?, vec![200, 49]);
+    // The index commits along with the table, so a transaction failing to commit doesn't reach
+    // it: the view can't measure the distance to a vector of other dimensions.
+    conn.execute(
+        "CREATE MATERIALIZED VIEW d AS SELECT id, vector_distance_l2(e, vector('[1,0]')) FROM t",
+    )?;
+    conn.execute("BEGIN")?;
+    conn.execute("INSERT INTO t VALUES (202, vector('[0.02,1]'))")?;
+    conn.execute("INSERT INTO t VALUES (203, vector('[0,1,0]'))")?;
+    assert!(conn.execute("COMMIT").is_err());
+    assert_eq!(nearest(&conn, "[0,2]", 2)?, vec![200, 49]);
+    conn.execute("DROP MATERIALIZED VIEW d")?;
+    // Vectors of other dimensions are left out of the index but can't be searched for.
+    conn.execute("INSERT INTO t VALUES (201, vector('[0,1,0]'))")?;
+    assert!(nearest(&conn, "[0,1,0]", 1).is_err());
+    // The index stores itself and is dropped with DROP INDEX only.
+    assert!(conn.execute("DELETE FROM t_e_shadow").is_err());
+    assert!(conn.execute("DROP TABLE t").is_err());
+    do_flush(&conn, &tmp_db)?;
+
+    // The index is found again when the database is opened.
+    let conn = tmp_db.connect_limbo();
+    assert_eq!(nearest(&conn, "[0,2]", 2)?, vec![200, 49]);
+    conn.execute("UPDATE t SET e = vector('[0,1]') WHERE id = 100")?;
+    assert_eq!(nearest(&conn, "[0,2]", 1)?, vec![100]);
+    conn.execute("DROP INDEX t_e")?;
+    assert!(nearest(&conn, "[0,2]", 1).is_err());
+    conn.execute("DROP TABLE t")?;
+    Ok(())
+}
@@ -1,9 +1,13 @@
 use crate::common::{do_flush, maybe_setup_tracing, TempDatabase};
-use limbo_core::{Connection, LimboError, Result, StepResult};
+use limbo_core::{
+    CheckpointStatus, Connection, LimboError, OwnedValue, Result, Statement, StepResult,
+    WalBackpressure,
+};
 use std::cell::RefCell;
 use std::ops::Deref;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 #[allow(clippy::arc_with_non_send_sync)]
 #[test]
@@ -32,6 +36,542 @@ fn test_wal_checkpoint_result() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_wal_journal_size_limit() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = TempDatabase::new("test_wal.db");
+    let wal_path = format!("{}-wal", tmp_db.path.display());
+    let wal_size = || std::fs::metadata(&wal_path).unwrap().len();
+    let wal_checkpoint_seq = || {
+        // Let the write of the new header complete.
+        tmp_db.io.run_once().unwrap();
+        let header = std::fs::read(&wal_path).unwrap();
+        u32::from_be_bytes(header[12..16].try_into().unwrap())
+    };
+    let conn = tmp_db.connect_limbo();
+    conn.execute("CREATE TABLE t (x TEXT);")?;
+    // The row spills into overflow pages, keeping only a few hundred bytes in the table's leaf
+    // page, so the inserts never need to balance the tree.
+    let insert_row = |conn: &Rc<Connection>| -> Result<()> {
+        conn.execute(format!("INSERT INTO t VALUES ('{}');", "x".repeat(8181)))?;
+        do_flush(conn, &tmp_db).unwrap();
+        Ok(())
+    };
+
+    let res = execute_and_get_ints(&tmp_db, &conn, "pragma journal_size_limit;")?;
+    assert_eq!(res, vec![-1]);
+    insert_row(&conn)?;
+    let grown = wal_size();
+    assert!(grown > 8192);
+
+    // Without a limit the WAL restarts from the beginning but keeps its size.
+    execute_and_get_ints(&tmp_db, &conn, "pragma wal_checkpoint;")?;
+    assert_eq!(wal_size(), grown);
+    assert_eq!(wal_checkpoint_seq(), 1);
+
+    let res = execute_and_get_ints(&tmp_db, &conn, "pragma journal_size_limit = 8192;")?;
+    assert_eq!(res, vec![8192]);
+    insert_row(&conn)?;
+    execute_and_get_ints(&tmp_db, &conn, "pragma wal_checkpoint;")?;
+    assert_eq!(wal_size(), 8192);
+    assert_eq!(wal_checkpoint_seq(), 2);
+
+    insert_row(&conn)?;
+    let res = execute_and_get_ints(&tmp_db, &conn, "pragma wal_checkpoint(TRUNCATE);")?;
+    assert_eq!(res[0], 0);
+    assert_eq!(wal_size(), 32);
+    assert_eq!(wal_checkpoint_seq(), 3);
+
+    // Frames written after the restart are found again.
+    insert_row(&conn)?;
+    let res = execute_and_get_ints(&tmp_db, &conn, "SELECT count(*) FROM t;")?;
+    assert_eq!(res, vec![4]);
+    conn.close()?;
+    let conn = tmp_db.connect_limbo();
+    let res = execute_and_get_ints(&tmp_db, &conn, "SELECT count(*) FROM t;")?;
+    assert_eq!(res, vec![4]);
+
+    Ok(())
+}
+
+#[test]
+fn test_wal_locking_mode() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = TempDatabase::new("test_wal.db");
+    let db = tmp_db.limbo_database();
+    let conn1 = db.connect()?;
+    conn1.execute("CREATE TABLE t (x INTEGER);")?;
+    // The locks kept by the connection that was alone are released for the new one.
+    let conn2 = db.connect()?;
+    conn2.execute("INSERT INTO t VALUES (1);")?;
+
+    let res = execute_and_get_strings(&tmp_db, &conn1, "pragma locking_mode;")?;
+    assert_eq!(res, vec!["normal"]);
+    let res = execute_and_get_strings(&tmp_db, &conn1, "pragma locking_mode = EXCLUSIVE;")?;
+    assert_eq!(res, vec!["exclusive"]);
+    conn1.execute("INSERT INTO t VALUES (2);")?;
+    let res = execute_and_get_ints(&tmp_db, &conn1, "SELECT count(*) FROM t;")?;
+    assert_eq!(res, vec![2]);
+
+    // The other connection is locked out until the first one goes back to normal mode.
+    let mut stmt = conn2.prepare("SELECT count(*) FROM t;")?;
+    assert!(matches!(stmt.step()?, StepResult::Busy));
+    let res = execute_and_get_strings(&tmp_db, &conn1, "pragma locking_mode = normal;")?;
+    assert_eq!(res, vec!["normal"]);
+    loop {
+        match stmt.step()? {
+            StepResult::Row => {
+                assert_eq!(
+                    stmt.row().unwrap().get_value(0),
+                    &limbo_core::OwnedValue::Integer(2)
+                );
+            }
+            StepResult::IO => tmp_db.io.run_once()?,
+            StepResult::Done => break,
+            step => panic!("unexpected step result {step:?}"),
+        }
+    }
+    drop(stmt);
+
+    // Closing the connection also gives up exclusive mode.
+    execute_and_get_strings(&tmp_db, &conn1, "pragma locking_mode = exclusive;")?;
+    conn1.execute("INSERT INTO t VALUES (3);")?;
+    drop(conn1);
+    let res = execute_and_get_ints(&tmp_db, &conn2, "SELECT count(*) FROM t;")?;
+    assert_eq!(res, vec![3]);
+
+    Ok(())
+}
+
+#[test]
+fn test_wal_header_pragmas() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = TempDatabase::new("test_wal.db");
+    {
+        let db = tmp_db.limbo_database();
+        let conn1 = db.connect()?;
+        let conn2 = db.connect()?;
+        conn1.execute("CREATE TABLE t (x INTEGER);")?;
+        let data_version = |conn: &Rc<Connection>| -> Result<i64> {
+            Ok(execute_and_get_ints(&tmp_db, conn, "pragma data_version;")?[0])
+        };
+        let (version1, version2) = (data_version(&conn1)?, data_version(&conn2)?);
+
+        conn1.execute("pragma user_version = 7;")?;
+        conn1.execute("pragma application_id = -1;")?;
+        // Only the other connection sees its data version change.
+        assert_eq!(data_version(&conn1)?, version1);
+        assert_ne!(data_version(&conn2)?, version2);
+        let res = execute_and_get_ints(&tmp_db, &conn2, "pragma user_version;")?;
+        assert_eq!(res, vec![7]);
+        conn1.close()?;
+        conn2.close()?;
+    }
+
+    // The header is written with the transaction, so it is found again in the WAL.
+    let conn = tmp_db.connect_limbo();
+    let res = execute_and_get_ints(&tmp_db, &conn, "pragma user_version;")?;
+    assert_eq!(res, vec![7]);
+    let res = execute_and_get_ints(&tmp_db, &conn, "pragma application_id;")?;
+    assert_eq!(res, vec![-1]);
+    assert!(conn.execute("pragma schema_version = 1;").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_wal_synchronous_normal_group_commit() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = TempDatabase::new("test_wal.db");
+    {
+        let db = tmp_db.limbo_database();
+        let conn1 = db.connect()?;
+        let conn2 = db.connect()?;
+        conn1.execute("CREATE TABLE t (x INTEGER);")?;
+
+        let res = execute_and_get_ints(&tmp_db, &conn1, "pragma synchronous;")?;
+        assert_eq!(res, vec![2]);
+        conn1.execute("pragma synchronous = NORMAL;")?;
+        conn2.execute("pragma synchronous = 1;")?;
+        let res = execute_and_get_ints(&tmp_db, &conn2, "pragma synchronous;")?;
+        assert_eq!(res, vec![1]);
+        assert!(conn1.execute("pragma synchronous = fastest;").is_err());
+
+        // Small commits from both connections share WAL syncs but are visible right away.
+        for i in 0..50 {
+            let conn = if i % 2 == 0 { &conn1 } else { &conn2 };
+            conn.execute(format!("INSERT INTO t VALUES ({i});"))?;
+            do_flush(conn, &tmp_db).unwrap();
+        }
+        let res = execute_and_get_ints(&tmp_db, &conn2, "SELECT count(*) FROM t;")?;
+        assert_eq!(res, vec![50]);
+
+        // The checkpoint syncs the frames whose commits skipped it before backfilling them.
+        let res = execute_and_get_ints(&tmp_db, &conn1, "pragma wal_checkpoint;")?;
+        assert_eq!(res[0], 0);
+        assert_eq!(res[1], res[2]);
+        conn1.close()?;
+        conn2.close()?;
+    }
+
+    let conn = tmp_db.connect_limbo();
+    let res = execute_and_get_ints(&tmp_db, &conn, "SELECT count(*), sum(x) FROM t;")?;
+    assert_eq!(res, vec![50, 1225]);
+
+    Ok(())
+}
+
+#[test]
+fn test_wal_recovery() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = TempDatabase::new("test_wal.db");
+    {
+        let conn = tmp_db.connect_limbo();
+        conn.execute("CREATE TABLE t (x TEXT);")?;
+        for i in 0..20 {
+            conn.execute(format!(
+                "INSERT INTO t VALUES ('{}');",
+                i.to_string().repeat(500)
+            ))?;
+            do_flush(&conn, &tmp_db).unwrap();
+        }
+        conn.execute("pragma user_version = 3;")?;
+        do_flush(&conn, &tmp_db).unwrap();
+        // Dropping the connection without closing it leaves the commits in the WAL only.
+    }
+
+    let conn = tmp_db.connect_limbo();
+    let res = execute_and_get_ints(&tmp_db, &conn, "SELECT count(*) FROM t;")?;
+    assert_eq!(res, vec![20]);
+    let res = execute_and_get_ints(&tmp_db, &conn, "pragma user_version;")?;
+    assert_eq!(res, vec![3]);
+    // New frames continue the recovered ones.
+    conn.execute("INSERT INTO t VALUES ('after recovery');")?;
+    do_flush(&conn, &tmp_db).unwrap();
+    let res = execute_and_get_ints(&tmp_db, &conn, "pragma wal_checkpoint;")?;
+    assert_eq!(res[0], 0);
+    assert_eq!(res[1], res[2]);
+    conn.close()?;
+
+    let conn = tmp_db.connect_limbo();
+    let res = execute_and_get_ints(&tmp_db, &conn, "SELECT count(*) FROM t;")?;
+    assert_eq!(res, vec![21]);
+
+    Ok(())
+}
+
+#[test]
+fn test_wal_flush_without_changes() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = TempDatabase::new("test_wal.db");
+    {
+        let conn = tmp_db.connect_limbo();
+        conn.execute("CREATE TABLE t (x INTEGER);")?;
+        conn.execute("INSERT INTO t VALUES (1);")?;
+        do_flush(&conn, &tmp_db).unwrap();
+        // Closing backfills every frame, so the next writer restarts the log.
+        conn.close()?;
+    }
+
+    let conn = tmp_db.connect_limbo();
+    for i in 2..=4 {
+        // A flush with nothing to write is done right away, leaving nothing behind that
+        // would hold up the frames of the next commit.
+        assert!(matches!(conn.cacheflush()?, CheckpointStatus::Done(_)));
+        conn.execute(format!("INSERT INTO t VALUES ({i});"))?;
+        let res = execute_and_get_ints(&tmp_db, &conn, "SELECT count(*), sum(x) FROM t;")?;
+        assert_eq!(res, vec![i, i * (i + 1) / 2]);
+    }
+    conn.close()?;
+
+    let conn = tmp_db.connect_limbo();
+    let res = execute_and_get_ints(&tmp_db, &conn, "SELECT count(*) FROM t;")?;
+    assert_eq!(res, vec![4]);
+
+    Ok(())
+}
+
+#[test]
+fn test_wal_flush_inside_transaction() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = TempDatabase::new("test_wal.db");
+    let conn = tmp_db.connect_limbo();
+    conn.execute("CREATE TABLE t (x INTEGER);")?;
+    do_flush(&conn, &tmp_db).unwrap();
+
+    conn.execute("BEGIN;")?;
+    conn.execute("CREATE TABLE u (y INTEGER);")?;
+    // The frames appended here are past the snapshot the transaction started with, the rest
+    // of the transaction must still read them.
+    do_flush(&conn, &tmp_db).unwrap();
+    let res = execute_and_get_strings(&tmp_db, &conn, "SELECT name FROM sqlite_schema;")?;
+    assert_eq!(res, vec!["t", "u"]);
+    conn.execute("INSERT INTO t VALUES (1);")?;
+    do_flush(&conn, &tmp_db).unwrap();
+    conn.execute("INSERT INTO u VALUES (2);")?;
+    conn.execute("COMMIT;")?;
+    do_flush(&conn, &tmp_db).unwrap();
+    conn.close()?;
+
+    let conn = tmp_db.connect_limbo();
+    let res = execute_and_get_ints(&tmp_db, &conn, "SELECT x, y FROM t, u;")?;
+    assert_eq!(res, vec![1, 2]);
+
+    Ok(())
+}
+
+#[test]
+fn test_wal_checkpoint_with_reader() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = TempDatabase::new("test_wal.db");
+    let db = tmp_db.limbo_database();
+    let writer = db.connect()?;
+    let reader = db.connect()?;
+    writer.execute("CREATE TABLE t (x INTEGER);")?;
+    for i in 0..10 {
+        writer.execute(format!("INSERT INTO t VALUES ({i});"))?;
+        do_flush(&writer, &tmp_db).unwrap();
+    }
+
+    // Keep a read transaction open on the snapshot with 10 rows.
+    let mut stmt = reader.prepare("SELECT x FROM t;")?;
+    step_until_row(&tmp_db, &mut stmt)?;
+    writer.execute("INSERT INTO t VALUES (10);")?;
+    do_flush(&writer, &tmp_db).unwrap();
+    // Only the frames of the reader's snapshot can be backfilled.
+    let res = execute_and_get_ints(&tmp_db, &writer, "pragma wal_checkpoint(RESTART);")?;
+    assert_eq!(res[0], 1);
+    assert!(0 < res[2] && res[2] < res[1]);
+    assert_eq!(count_remaining_rows(&tmp_db, &mut stmt)?, 10);
+    drop(stmt);
+
+    // A reader of the latest snapshot lets everything be backfilled, but the log can't restart
+    // while it uses it.
+    let mut stmt = reader.prepare("SELECT x FROM t;")?;
+    step_until_row(&tmp_db, &mut stmt)?;
+    let res = execute_and_get_ints(&tmp_db, &writer, "pragma wal_checkpoint(RESTART);")?;
+    assert_eq!(res[0], 1);
+    assert_eq!(res[1], res[2]);
+    let log_frames = res[1];
+    assert_eq!(count_remaining_rows(&tmp_db, &mut stmt)?, 11);
+    drop(stmt);
+
+    // The next write restarts the log now that the reader is done.
+    writer.execute("INSERT INTO t VALUES (11);")?;
+    do_flush(&writer, &tmp_db).unwrap();
+    let res = execute_and_get_ints(&tmp_db, &writer, "pragma wal_checkpoint;")?;
+    assert_eq!(res[0], 0);
+    assert!(res[1] < log_frames);
+    let res = execute_and_get_ints(&tmp_db, &reader, "SELECT count(*) FROM t;")?;
+    assert_eq!(res, vec![12]);
+
+    Ok(())
+}
+
+#[test]
+fn test_wal_write_on_stale_snapshot() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = TempDatabase::new("test_wal.db");
+    let db = tmp_db.limbo_database();
+    let conn1 = db.connect()?;
+    let conn2 = db.connect()?;
+    conn1.execute("CREATE TABLE t (x INTEGER);")?;
+
+    conn1.execute("BEGIN;")?;
+    let res = execute_and_get_ints(&tmp_db, &conn1, "SELECT count(*) FROM t;")?;
+    assert_eq!(res, vec![0]);
+    conn2.execute("INSERT INTO t VALUES (1);")?;
+    do_flush(&conn2, &tmp_db).unwrap();
+    // Writing on top of the snapshot without the row of conn2 would lose it.
+    let err = conn1.execute("INSERT INTO t VALUES (2);").unwrap_err();
+    assert_eq!(err.extended_code(), limbo_core::error::SQLITE_BUSY_SNAPSHOT);
+    assert_eq!(err.code(), limbo_core::error::SQLITE_BUSY);
+
+    Ok(())
+}
+
+#[test]
+fn test_wal_backpressure() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = TempDatabase::new("test_wal.db");
+    let db = tmp_db.limbo_database();
+    let writer = db.connect()?;
+    let reader = db.connect()?;
+    writer.execute("CREATE TABLE t (x INTEGER);")?;
+    writer.execute("INSERT INTO t VALUES (0);")?;
+    do_flush(&writer, &tmp_db).unwrap();
+    let backpressure = WalBackpressure {
+        max_frames: 4,
+        max_delay: Duration::from_millis(1),
+    };
+    db.set_wal_backpressure(backpressure);
+    assert_eq!(db.wal_backpressure(), backpressure);
+
+    // A reader of an old snapshot keeps the log from restarting, so once it is long enough the
+    // writes wait for it until they give up.
+    let mut stmt = reader.prepare("SELECT x FROM t;")?;
+    step_until_row(&tmp_db, &mut stmt)?;
+    for i in 1..10 {
+        writer.execute(format!("INSERT INTO t VALUES ({i});"))?;
+        do_flush(&writer, &tmp_db).unwrap();
+    }
+    let stats = db.wal_backpressure_stats();
+    assert!(stats.delayed_writes > 0);
+    assert_eq!(stats.timed_out_writes, stats.delayed_writes);
+    assert!(stats.total_delay >= backpressure.max_delay * stats.delayed_writes as u32);
+    assert_eq!(count_remaining_rows(&tmp_db, &mut stmt)?, 1);
+    drop(stmt);
+
+    // Without the reader, the next write checkpoints and restarts the log before going ahead.
+    writer.execute("INSERT INTO t VALUES (10);")?;
+    do_flush(&writer, &tmp_db).unwrap();
+    let after = db.wal_backpressure_stats();
+    assert_eq!(after.delayed_writes, stats.delayed_writes + 1);
+    assert_eq!(after.timed_out_writes, stats.timed_out_writes);
+    let res = execute_and_get_ints(&tmp_db, &writer, "pragma wal_checkpoint;")?;
+    assert!(res[1] < backpressure.max_frames as i64);
+    let res = execute_and_get_ints(&tmp_db, &reader, "SELECT count(*) FROM t;")?;
+    assert_eq!(res, vec![11]);
+
+    Ok(())
+}
+
+#[test]
+fn test_wal_time_travel() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = TempDatabase::new("test_wal.db");
+    let db = tmp_db.limbo_database();
+    db.set_retain_history(true);
+    let writer = db.connect()?;
+    let reader = db.connect()?;
+    writer.execute("CREATE TABLE u (x INTEGER);")?;
+    do_flush(&writer, &tmp_db).unwrap();
+    let before_t = db.history().last().unwrap().frame;
+    writer.execute("CREATE TABLE t (x INTEGER);")?;
+    do_flush(&writer, &tmp_db).unwrap();
+    let created = db.history().last().unwrap().frame;
+    let mut markers = Vec::new();
+    for i in 1..=3 {
+        writer.execute(format!("INSERT INTO t VALUES ({i});"))?;
+        do_flush(&writer, &tmp_db).unwrap();
+        markers.push(db.history().last().unwrap().frame);
+    }
+
+    // Each commit reads as it was, from any connection, with the marker in a parameter too.
+    for (i, marker) in markers.iter().enumerate() {
+        let res = execute_and_get_ints(
+            &tmp_db,
+            &reader,
+            &format!("SELECT sum(x), count(*) FROM t AS OF {marker};"),
+        )?;
+        let rows = i as i64 + 1;
+        assert_eq!(res, vec![rows * (rows + 1) / 2, rows]);
+    }
+    let res = execute_and_get_ints(
+        &tmp_db,
+        &reader,
+        &format!("SELECT x FROM t AS OF {created};"),
+    )?;
+    assert!(res.is_empty());
+    let mut stmt = reader.prepare("SELECT max(x) FROM t AS OF ?;")?;
+    stmt.bind_at(
+        1.try_into().unwrap(),
+        OwnedValue::Integer(markers[1] as i64),
+    );
+    step_until_row(&tmp_db, &mut stmt)?;
+    assert_eq!(
+        stmt.row().unwrap().get::<&OwnedValue>(0)?,
+        &OwnedValue::Integer(2)
+    );
+    assert_eq!(count_remaining_rows(&tmp_db, &mut stmt)?, 1);
+    let res = execute_and_get_ints(&tmp_db, &reader, "SELECT count(*) FROM t;")?;
+    assert_eq!(res, vec![3]);
+
+    // PRAGMA limbo_history lists the markers with the time of their commit, which reads as of
+    // that commit too.
+    let history = execute_and_get_strings(&tmp_db, &reader, "PRAGMA limbo_history;")?;
+    assert_eq!(history.len(), 2 * db.history().len());
+    let position = history
+        .iter()
+        .position(|marker| *marker == markers[0].to_string())
+        .unwrap();
+    let res = execute_and_get_ints(
+        &tmp_db,
+        &reader,
+        &format!("SELECT count(*) FROM t AS OF '{}';", history[position + 1]),
+    )?;
+    assert!(!res.is_empty() && res[0] >= 1);
+    let res = execute_and_get_ints(
+        &tmp_db,
+        &reader,
+        "SELECT count(*) FROM t AS OF datetime('now', '+1 day');",
+    )?;
+    assert_eq!(res, vec![3]);
+
+    let err = |sql: &str| -> String {
+        let mut stmt = reader.prepare(sql).unwrap();
+        loop {
+            match stmt.step() {
+                Ok(StepResult::IO) => tmp_db.io.run_once().unwrap(),
+                Ok(step) => panic!("{sql}: {step:?}"),
+                Err(err) => return err.to_string(),
+            }
+        }
+    };
+    assert!(err("SELECT * FROM t AS OF 1000;").contains("commit 1000 is not in the WAL"));
+    assert!(err("SELECT * FROM t AS OF 0;").contains("commit 0 is not in the WAL"));
+    // Statements are planned with the current schema, which can't read older ones.
+    let schema_changed = format!("the schema changed since commit {before_t}");
+    assert!(err(&format!("SELECT * FROM t AS OF {before_t};")).contains(&schema_changed));
+    assert!(err("SELECT * FROM t AS OF '2000-01-01';").contains("no commit at or before"));
+    assert!(err("SELECT * FROM t AS OF 1.5;").contains("commit marker or a timestamp"));
+    reader.execute("BEGIN;")?;
+    assert!(err("SELECT * FROM t AS OF 1;").contains("inside a transaction"));
+    reader.execute("COMMIT;")?;
+
+    // Commits no longer checkpoint at the threshold while history is retained, but an explicit
+    // checkpoint backfills the history, after which it can't be read anymore.
+    for i in 4..=1100 {
+        writer.execute(format!("INSERT INTO t VALUES ({i});"))?;
+        do_flush(&writer, &tmp_db).unwrap();
+    }
+    assert!(db.history().len() > 1000);
+    let res = execute_and_get_ints(
+        &tmp_db,
+        &reader,
+        &format!("SELECT count(*) FROM t AS OF {};", markers[0]),
+    )?;
+    assert_eq!(res, vec![1]);
+    execute_and_get_ints(&tmp_db, &writer, "PRAGMA wal_checkpoint;")?;
+    assert!(db.history().len() <= 1);
+    assert!(err(&format!("SELECT * FROM t AS OF {};", markers[0])).contains("not in the WAL"));
+
+    Ok(())
+}
+
+fn step_until_row(tmp_db: &TempDatabase, stmt: &mut Statement) -> Result<()> {
+    loop {
+        match stmt.step()? {
+            StepResult::Row => return Ok(()),
+            StepResult::IO => tmp_db.io.run_once()?,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Counts the current row and the ones left in `stmt`.
+fn count_remaining_rows(tmp_db: &TempDatabase, stmt: &mut Statement) -> Result<usize> {
+    let mut rows = 1;
+    loop {
+        match stmt.step()? {
+            StepResult::Row => rows += 1,
+            StepResult::IO => tmp_db.io.run_once()?,
+            StepResult::Done => return Ok(rows),
+            _ => unreachable!(),
+        }
+    }
+}
+
 #[test]
 #[ignore = "ignored for now because it's flaky"]
 fn test_wal_1_writer_1_reader() -> Result<()> {
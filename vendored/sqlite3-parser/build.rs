@@ -164,6 +164,7 @@ fn main() -> Result<()> {
             .entry(UncasedStr::new("RANGE"), "TokenType::TK_RANGE")
             .entry(UncasedStr::new("RECURSIVE"), "TokenType::TK_RECURSIVE")
             .entry(UncasedStr::new("REFERENCES"), "TokenType::TK_REFERENCES")
+            .entry(UncasedStr::new("REFRESH"), "TokenType::TK_REFRESH")
             .entry(UncasedStr::new("REGEXP"), "TokenType::TK_LIKE_KW")
             .entry(UncasedStr::new("REINDEX"), "TokenType::TK_REINDEX")
             .entry(UncasedStr::new("RELEASE"), "TokenType::TK_RELEASE")
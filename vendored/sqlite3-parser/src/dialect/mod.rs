@@ -94,6 +94,7 @@ impl TokenType {
             TK_ALWAYS => Some("ALWAYS"),
             TK_AND => Some("AND"),
             TK_AS => Some("AS"),
+            TK_ASOF => Some("AS"),
             TK_ASC => Some("ASC"),
             TK_ATTACH => Some("ATTACH"),
             TK_AUTOINCR => Some("AUTOINCREMENT"),
@@ -184,6 +185,7 @@ impl TokenType {
             TK_RANGE => Some("RANGE"),
             TK_RECURSIVE => Some("RECURSIVE"),
             TK_REFERENCES => Some("REFERENCES"),
+            TK_REFRESH => Some("REFRESH"),
             TK_REINDEX => Some("REINDEX"),
             TK_RELEASE => Some("RELEASE"),
             TK_RENAME => Some("RENAME"),
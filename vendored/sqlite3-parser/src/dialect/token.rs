@@ -108,74 +108,76 @@ pub enum TokenType {
     TK_GENERATED = 96,
     TK_ALWAYS = 97,
     TK_MATERIALIZED = 98,
-    TK_REINDEX = 99,
-    TK_RENAME = 100,
-    TK_CTIME_KW = 101,
-    TK_ANY = 102,
-    TK_BITAND = 103,
-    TK_BITOR = 104,
-    TK_LSHIFT = 105,
-    TK_RSHIFT = 106,
-    TK_PLUS = 107,
-    TK_MINUS = 108,
-    TK_STAR = 109,
-    TK_SLASH = 110,
-    TK_REM = 111,
-    TK_CONCAT = 112,
-    TK_PTR = 113,
-    TK_COLLATE = 114,
-    TK_BITNOT = 115,
-    TK_ON = 116,
-    TK_INDEXED = 117,
-    TK_STRING = 118,
-    TK_JOIN_KW = 119,
-    TK_CONSTRAINT = 120,
-    TK_DEFAULT = 121,
-    TK_NULL = 122,
-    TK_PRIMARY = 123,
-    TK_UNIQUE = 124,
-    TK_CHECK = 125,
-    TK_REFERENCES = 126,
-    TK_AUTOINCR = 127,
-    TK_INSERT = 128,
-    TK_DELETE = 129,
-    TK_UPDATE = 130,
-    TK_SET = 131,
-    TK_DEFERRABLE = 132,
-    TK_FOREIGN = 133,
-    TK_DROP = 134,
-    TK_UNION = 135,
-    TK_ALL = 136,
-    TK_EXCEPT = 137,
-    TK_INTERSECT = 138,
-    TK_SELECT = 139,
-    TK_VALUES = 140,
-    TK_DISTINCT = 141,
-    TK_DOT = 142,
-    TK_FROM = 143,
-    TK_JOIN = 144,
-    TK_USING = 145,
-    TK_ORDER = 146,
-    TK_GROUP = 147,
-    TK_HAVING = 148,
-    TK_LIMIT = 149,
-    TK_WHERE = 150,
-    TK_RETURNING = 151,
-    TK_INTO = 152,
-    TK_NOTHING = 153,
-    TK_BLOB = 154,
-    TK_FLOAT = 155,
-    TK_INTEGER = 156,
-    TK_VARIABLE = 157,
-    TK_CASE = 158,
-    TK_WHEN = 159,
-    TK_THEN = 160,
-    TK_ELSE = 161,
-    TK_INDEX = 162,
-    TK_ALTER = 163,
-    TK_ADD = 164,
-    TK_WINDOW = 165,
-    TK_OVER = 166,
-    TK_FILTER = 167,
+    TK_REFRESH = 99,
+    TK_REINDEX = 100,
+    TK_RENAME = 101,
+    TK_CTIME_KW = 102,
+    TK_ANY = 103,
+    TK_BITAND = 104,
+    TK_BITOR = 105,
+    TK_LSHIFT = 106,
+    TK_RSHIFT = 107,
+    TK_PLUS = 108,
+    TK_MINUS = 109,
+    TK_STAR = 110,
+    TK_SLASH = 111,
+    TK_REM = 112,
+    TK_CONCAT = 113,
+    TK_PTR = 114,
+    TK_COLLATE = 115,
+    TK_BITNOT = 116,
+    TK_ON = 117,
+    TK_INDEXED = 118,
+    TK_STRING = 119,
+    TK_JOIN_KW = 120,
+    TK_CONSTRAINT = 121,
+    TK_DEFAULT = 122,
+    TK_NULL = 123,
+    TK_PRIMARY = 124,
+    TK_UNIQUE = 125,
+    TK_CHECK = 126,
+    TK_REFERENCES = 127,
+    TK_AUTOINCR = 128,
+    TK_INSERT = 129,
+    TK_DELETE = 130,
+    TK_UPDATE = 131,
+    TK_SET = 132,
+    TK_DEFERRABLE = 133,
+    TK_FOREIGN = 134,
+    TK_DROP = 135,
+    TK_ASOF = 136,
+    TK_UNION = 137,
+    TK_ALL = 138,
+    TK_EXCEPT = 139,
+    TK_INTERSECT = 140,
+    TK_SELECT = 141,
+    TK_VALUES = 142,
+    TK_DISTINCT = 143,
+    TK_DOT = 144,
+    TK_FROM = 145,
+    TK_JOIN = 146,
+    TK_USING = 147,
+    TK_ORDER = 148,
+    TK_GROUP = 149,
+    TK_HAVING = 150,
+    TK_LIMIT = 151,
+    TK_WHERE = 152,
+    TK_RETURNING = 153,
+    TK_INTO = 154,
+    TK_NOTHING = 155,
+    TK_BLOB = 156,
+    TK_FLOAT = 157,
+    TK_INTEGER = 158,
+    TK_VARIABLE = 159,
+    TK_CASE = 160,
+    TK_WHEN = 161,
+    TK_THEN = 162,
+    TK_ELSE = 163,
+    TK_INDEX = 164,
+    TK_ALTER = 165,
+    TK_ADD = 166,
+    TK_WINDOW = 167,
+    TK_OVER = 168,
+    TK_FILTER = 169,
     TK_ILLEGAL = 185,
 }
@@ -142,6 +142,23 @@ fn analyze_window_keyword(
     };
     Ok(TK_WINDOW)
 }
+/// AS followed by OF and what can start an expression introduces the commit a SELECT reads
+/// the database as of, instead of naming something "of".
+fn analyze_as_keyword(scanner: &mut Scanner<Tokenizer>, input: &[u8]) -> Result<TokenType, Error> {
+    let mut next = || -> Result<Option<TokenType>, Error> {
+        Ok(scanner.scan(input)?.1.map(|(_, token_type)| token_type))
+    };
+    if next()? != Some(TK_OF) {
+        return Ok(TK_AS);
+    }
+    match next()? {
+        Some(
+            TK_INTEGER | TK_FLOAT | TK_STRING | TK_VARIABLE | TK_ID | TK_CTIME_KW | TK_LP
+            | TK_MINUS | TK_PLUS,
+        ) => Ok(TK_ASOF),
+        _ => Ok(TK_AS),
+    }
+}
 fn analyze_over_keyword(
     scanner: &mut Scanner<Tokenizer>,
     input: &[u8],
@@ -211,7 +228,12 @@ impl FallibleIterator for Parser<'_> {
                 ));
             }
 
-            let token = if token_type >= TK_WINDOW {
+            let token = if token_type == TK_AS {
+                self.scanner.mark();
+                token_type = analyze_as_keyword(&mut self.scanner, self.input)?;
+                self.scanner.reset_to_mark();
+                token_type.to_token(start, value, end)
+            } else if token_type >= TK_WINDOW {
                 debug_assert!(
                     token_type == TK_OVER || token_type == TK_FILTER || token_type == TK_WINDOW
                 );
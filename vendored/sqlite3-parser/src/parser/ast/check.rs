@@ -72,6 +72,7 @@ impl Stmt {
             }
             Self::Pragma(..) => ColumnCount::Dynamic,
             Self::Select(s) => s.column_count(),
+            Self::SelectAsOf { select, .. } => select.column_count(),
             Self::Update(update) => {
                 let Update { returning, .. } = &**update;
                 match returning {
@@ -96,6 +97,7 @@ impl Stmt {
             Self::Rollback { .. } => true,
             Self::Savepoint(..) => true,
             Self::Select(..) => true,
+            Self::SelectAsOf { .. } => true,
             _ => false,
         }
     }
@@ -148,6 +150,12 @@ impl Stmt {
                 columns: Some(columns),
                 select,
                 ..
+            }
+            | Self::CreateMaterializedView {
+                view_name,
+                columns: Some(columns),
+                select,
+                ..
             } => {
                 // SQLite3 engine renames duplicates:
                 for (i, c) in columns.iter().enumerate() {
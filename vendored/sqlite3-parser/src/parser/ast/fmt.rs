@@ -283,6 +283,29 @@ impl ToTokens for Stmt {
                 s.append(TK_AS, None)?;
                 select.to_tokens(s)
             }
+            Self::CreateMaterializedView {
+                if_not_exists,
+                view_name,
+                columns,
+                select,
+            } => {
+                s.append(TK_CREATE, None)?;
+                s.append(TK_MATERIALIZED, None)?;
+                s.append(TK_VIEW, None)?;
+                if *if_not_exists {
+                    s.append(TK_IF, None)?;
+                    s.append(TK_NOT, None)?;
+                    s.append(TK_EXISTS, None)?;
+                }
+                view_name.to_tokens(s)?;
+                if let Some(columns) = columns {
+                    s.append(TK_LP, None)?;
+                    comma(columns, s)?;
+                    s.append(TK_RP, None)?;
+                }
+                s.append(TK_AS, None)?;
+                select.to_tokens(s)
+            }
             Self::CreateVirtualTable(create_virtual_table) => {
                 let CreateVirtualTable {
                     if_not_exists,
@@ -396,6 +419,19 @@ impl ToTokens for Stmt {
                 }
                 view_name.to_tokens(s)
             }
+            Self::DropMaterializedView {
+                if_exists,
+                view_name,
+            } => {
+                s.append(TK_DROP, None)?;
+                s.append(TK_MATERIALIZED, None)?;
+                s.append(TK_VIEW, None)?;
+                if *if_exists {
+                    s.append(TK_IF, None)?;
+                    s.append(TK_EXISTS, None)?;
+                }
+                view_name.to_tokens(s)
+            }
             Self::Insert(insert) => {
                 let Insert {
                     with,
@@ -439,6 +475,12 @@ impl ToTokens for Stmt {
                 }
                 Ok(())
             }
+            Self::RefreshMaterializedView { view_name } => {
+                s.append(TK_REFRESH, None)?;
+                s.append(TK_MATERIALIZED, None)?;
+                s.append(TK_VIEW, None)?;
+                view_name.to_tokens(s)
+            }
             Self::Reindex { obj_name } => {
                 s.append(TK_REINDEX, None)?;
                 if let Some(obj_name) = obj_name {
@@ -470,6 +512,12 @@ impl ToTokens for Stmt {
                 name.to_tokens(s)
             }
             Self::Select(select) => select.to_tokens(s),
+            Self::SelectAsOf { select, as_of } => {
+                select.to_tokens(s)?;
+                s.append(TK_AS, None)?;
+                s.append(TK_OF, None)?;
+                as_of.to_tokens(s)
+            }
             Self::Update(update) => {
                 let Update {
                     with,
@@ -129,6 +129,17 @@ pub enum Stmt {
         /// query
         select: Box<Select>,
     },
+    /// `CREATE MATERIALIZED VIEW`
+    CreateMaterializedView {
+        /// `IF NOT EXISTS`
+        if_not_exists: bool,
+        /// view name
+        view_name: QualifiedName,
+        /// columns
+        columns: Option<Vec<IndexedColumn>>,
+        /// query
+        select: Box<Select>,
+    },
     /// `CREATE VIRTUAL TABLE`
     CreateVirtualTable(Box<CreateVirtualTable>),
     /// `DELETE`
@@ -163,10 +174,22 @@ pub enum Stmt {
         /// view name
         view_name: QualifiedName,
     },
+    /// `DROP MATERIALIZED VIEW`
+    DropMaterializedView {
+        /// `IF EXISTS`
+        if_exists: bool,
+        /// view name
+        view_name: QualifiedName,
+    },
     /// `INSERT`
     Insert(Box<Insert>),
     /// `PRAGMA`: pragma name, body
     Pragma(Box<QualifiedName>, Option<Box<PragmaBody>>),
+    /// `REFRESH MATERIALIZED VIEW`
+    RefreshMaterializedView {
+        /// view name
+        view_name: QualifiedName,
+    },
     /// `REINDEX`
     Reindex {
         /// collation or index or table name
@@ -185,6 +208,13 @@ pub enum Stmt {
     Savepoint(Name),
     /// `SELECT`
     Select(Box<Select>),
+    /// `SELECT ... AS OF`: reads the database as of a commit marker or timestamp
+    SelectAsOf {
+        /// select
+        select: Box<Select>,
+        /// commit marker or timestamp
+        as_of: Box<Expr>,
+    },
     /// `UPDATE`
     Update(Box<Update>),
     /// `VACUUM`: database name, into expr
@@ -1614,18 +1644,60 @@ pub type PragmaValue = Expr; // TODO
 #[derive(Clone, Debug, PartialEq, Eq, EnumIter, EnumString, strum::Display)]
 #[strum(serialize_all = "snake_case")]
 pub enum PragmaName {
+    /// set the application ID stored in the database header
+    ApplicationId,
+    /// let the query planner build transient indexes for joins
+    AutomaticIndex,
     /// `cache_size` pragma
     CacheSize,
+    /// returns a number that changes when another connection commits
+    DataVersion,
+    /// lists the SQL functions available to the connection
+    FunctionList,
+    /// checks that indexes have an entry for each row of their tables and no others
+    IndexCheck,
+    /// returns information about the columns of an index
+    IndexInfo,
+    /// lists the indexes of a table
+    IndexList,
+    /// returns information about the columns of an index, including the ones it doesn't sort by
+    IndexXinfo,
+    /// checks the database for corruption
+    IntegrityCheck,
     /// `journal_mode` pragma
     JournalMode,
+    /// limit the size of the WAL file left behind after checkpoints
+    JournalSizeLimit,
     /// Noop as per SQLite docs
     LegacyFileFormat,
+    /// lists the commits that `SELECT ... AS OF` can read the database as of
+    LimboHistory,
+    /// reports how the connection read and wrote each table
+    LimboTableStats,
+    /// hold the database locks across transactions
+    LockingMode,
+    /// run ANALYZE on tables whose statistics are out of date
+    Optimize,
     /// Return the total number of pages in the database file.
     PageCount,
+    /// lists the pragmas the connection understands
+    PragmaList,
+    /// checks the database for corruption, without comparing indexes to their tables
+    QuickCheck,
+    /// returns the schema cookie of the database header
+    SchemaVersion,
+    /// whether commits wait for the WAL to be synced
+    Synchronous,
     /// returns information about the columns of a table
     TableInfo,
+    /// lists the tables and virtual tables of the schema
+    TableList,
+    /// whether transient tables and indexes may spill to temp files
+    TempStore,
     /// Returns the user version of the database file.
     UserVersion,
+    /// Reports the registers each VDBE instruction changes, for debugging code generation.
+    VdbeTrace,
     /// trigger a checkpoint to run on database(s) if WAL is enabled
     WalCheckpoint,
 }
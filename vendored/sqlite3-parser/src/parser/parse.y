@@ -201,7 +201,7 @@ columnname(A) ::= nm(X) typetoken(Y). {A = (X, Y);}
 %ifndef SQLITE_OMIT_GENERATED_COLUMNS
   GENERATED ALWAYS
 %endif
-  MATERIALIZED
+  MATERIALIZED REFRESH
   REINDEX RENAME CTIME_KW IF
   .
 %wildcard ANY.
@@ -483,11 +483,29 @@ cmd ::= DROP VIEW ifexists(E) fullname(X). {
 }
 %endif  SQLITE_OMIT_VIEW
 
+///////////////////// The MATERIALIZED VIEW statements //////////////////////
+//
+cmd ::= createkw MATERIALIZED VIEW ifnotexists(E) fullname(Y) eidlist_opt(C)
+          AS select(S). {
+  self.ctx.stmt = Some(Stmt::CreateMaterializedView{ if_not_exists: E, view_name: Y, columns: C,
+                                                     select: Box::new(S) });
+}
+cmd ::= REFRESH MATERIALIZED VIEW fullname(X). {
+  self.ctx.stmt = Some(Stmt::RefreshMaterializedView{ view_name: X });
+}
+cmd ::= DROP MATERIALIZED VIEW ifexists(E) fullname(X). {
+  self.ctx.stmt = Some(Stmt::DropMaterializedView{ if_exists: E, view_name: X });
+}
+
 //////////////////////// The SELECT statement /////////////////////////////////
 //
 cmd ::= select(X).  {
   self.ctx.stmt = Some(Stmt::Select(Box::new(X)));
 }
+// The tokenizer turns AS into ASOF when OF and the start of an expression follow it.
+cmd ::= select(X) ASOF OF expr(E). {
+  self.ctx.stmt = Some(Stmt::SelectAsOf{ select: Box::new(X), as_of: Box::new(E) });
+}
 
 %type select {Select}
 %type selectnowith {SelectBody}